@@ -282,6 +282,45 @@ where
     }
 }
 
+/// An ADC with a gated clock
+///
+/// Get a `Disabled` ADC from [`ADC::disable()`](struct.ADC.html#method.disable). Use
+/// `enable()` to clock the ADC again, then build it.
+pub struct Disabled<ADCx> {
+    _module: PhantomData<ADCx>,
+    reg: ral::adc::Instance,
+}
+
+macro_rules! disable_impl {
+    ($adc:ty, $gate:ty) => {
+        impl ADC<$adc> {
+            /// Gate the ADC's clock, and return the disabled ADC
+            ///
+            /// The other ADC keeps its clock.
+            pub fn disable(self, handle: &mut ccm::Handle) -> Disabled<$adc> {
+                handle.gate::<$gate>(ccm::GateSetting::Off);
+                Disabled {
+                    _module: PhantomData,
+                    reg: self.reg,
+                }
+            }
+        }
+
+        impl Disabled<$adc> {
+            /// Ungate the ADC's clock, and return a builder
+            ///
+            /// The builder calibrates the ADC again.
+            pub fn enable(self, handle: &mut ccm::Handle) -> Builder<$adc> {
+                handle.gate::<$gate>(ccm::GateSetting::RunAndWait);
+                Builder::new(self.reg)
+            }
+        }
+    };
+}
+
+disable_impl!(ADC1, ccm::clock_gate::Adc1);
+disable_impl!(ADC2, ccm::clock_gate::Adc2);
+
 /// Unclocked ADC modules
 ///
 /// The `Unclocked` struct represents both unconfigured ADC peripherals.
@@ -1,16 +1,36 @@
 //! Clock Configuration Module (CCM)
 
 mod arm_clock;
-use arm_clock::set_arm_clock;
+pub mod clock_gate;
+mod clock_tree;
+pub mod flexspi;
+pub mod listener;
+pub mod low_power;
+pub(crate) mod math;
+pub mod observability;
+pub mod osc;
+pub mod pll4;
+pub mod pll6;
+pub mod step_clock;
+pub mod trace;
+use arm_clock::{set_arm_clock, set_operating_point};
+pub use arm_clock::{OperatingPoint, OperatingPointError};
+pub use clock_gate::{ClockGate, GateSetting};
+pub use clock_tree::Frequencies;
+pub use listener::{ClockListener, ClockListenerMut, ClocksChanged};
+use math::dividers;
+pub use step_clock::{with_step_clock, StepClock};
 
 use core::time::Duration;
 use imxrt_ral as ral;
 
-use ral::modify_reg;
+use ral::{modify_reg, read_reg};
 
 pub struct Handle {
     pub(crate) base: ral::ccm::Instance,
     pub(crate) analog: ral::ccm_analog::Instance,
+    listeners: listener::Listeners<'static>,
+    step_clock: StepClock,
 }
 
 impl Handle {
@@ -27,7 +47,11 @@ pub struct CCM {
     /// The 480 MHz PFD
     pub pll2: pll2::PFD,
     /// The 528 MHz PFD
+    ///
+    /// Also powers and enables PLL3, which feeds the PFDs.
     pub pll3: pll3::PFD,
+    /// The audio PLL
+    pub audio_pll: pll4::PLL4,
 }
 
 /// Sets the low power clock mode
@@ -51,6 +75,67 @@ pub struct ArmFrequency(Frequency);
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct IPGFrequency(pub(crate) Frequency);
 
+impl ArmFrequency {
+    /// Returns the frequency, in Hz
+    pub fn hz(self) -> u32 {
+        (self.0).0
+    }
+}
+
+impl IPGFrequency {
+    /// Returns the frequency, in Hz
+    pub fn hz(self) -> u32 {
+        (self.0).0
+    }
+}
+
+/// The ARM clock could not be set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmClockError {
+    /// The frequency is below [`PLL1::MIN_HZ`](struct.PLL1.html#associatedconstant.MIN_HZ)
+    TooSlow,
+    /// The frequency is above [`PLL1::MAX_HZ`](struct.PLL1.html#associatedconstant.MAX_HZ)
+    TooFast,
+    /// PLL1 didn't lock before the timeout
+    ///
+    /// The core keeps running from the [step clock](step_clock/index.html).
+    PllLockTimeout,
+}
+
+impl From<PllLockTimeout> for ArmClockError {
+    fn from(_: PllLockTimeout) -> Self {
+        ArmClockError::PllLockTimeout
+    }
+}
+
+/// A PLL didn't lock before the timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PllLockTimeout(());
+
+/// The number of times we check for a PLL lock before giving up
+///
+/// The datasheet specifies a lock time of 11250 reference clock cycles, about 470us
+/// with the 24MHz oscillator. A poll takes at least one core cycle; at 600MHz, that's
+/// at most 281250 polls. This allows more than three times that. If the core runs
+/// from the 24MHz oscillator, the longest wait is about 40ms.
+const PLL_LOCK_SPINS: u32 = 1_000_000;
+
+/// Call `locked` until it returns `true`, at most `spins + 1` times
+///
+/// Returns an error if `locked` never returned `true`.
+pub(crate) fn wait_for_lock(
+    spins: u32,
+    mut locked: impl FnMut() -> bool,
+) -> Result<(), PllLockTimeout> {
+    let mut spins = spins;
+    while !locked() {
+        spins = spins.checked_sub(1).ok_or(PllLockTimeout(()))?;
+        #[allow(deprecated)]
+        core::sync::atomic::spin_loop_hint();
+    }
+    Ok(())
+}
+
 pub struct PLL1(());
 impl PLL1 {
     fn new() -> Self {
@@ -58,33 +143,204 @@ impl PLL1 {
     }
 
     pub const ARM_HZ: u32 = 600_000_000;
+    /// The fastest supported ARM clock frequency, which is the rated maximum
+    pub const MAX_HZ: u32 = math::MAX_ARM_HZ;
+    /// The slowest ARM clock frequency that the PLL and dividers can produce
+    pub const MIN_HZ: u32 = math::MIN_ARM_HZ;
 
     /// Set the clock speed for the ARM core. This represents the base processor frequency.
     /// Consider using the 600MHz recommended frequency `PLL1::ARM_HZ`.
+    ///
+    /// `hz` may be any frequency from `PLL1::MIN_HZ` to `PLL1::MAX_HZ`. `set_arm_clock()`
+    /// selects the PLL and divider settings that get closest to `hz` without exceeding it,
+    /// and returns the frequencies that it achieved. It runs the core from the
+    /// [step clock](step_clock/index.html) while it reconfigures the PLL. The IPG clock is the fastest division of the core clock
+    /// that doesn't exceed 150MHz.
+    ///
+    /// Returns an error, without touching any clocks, if `hz` is out of range. Returns
+    /// [`ArmClockError::PllLockTimeout`](enum.ArmClockError.html#variant.PllLockTimeout)
+    /// if PLL1 doesn't lock.
+    ///
+    /// ```no_run
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let (arm, ipg) = peripherals
+    ///     .ccm
+    ///     .pll1
+    ///     .set_arm_clock(396_000_000, &mut peripherals.ccm.handle, &mut peripherals.dcdc)
+    ///     .unwrap();
+    /// assert_eq!(arm.hz(), 396_000_000);
+    /// assert_eq!(ipg.hz(), 132_000_000);
+    /// ```
     pub fn set_arm_clock(
         &mut self,
         hz: u32,
         handle: &mut Handle,
         dcdc: &mut crate::dcdc::DCDC,
-    ) -> (ArmFrequency, IPGFrequency) {
+    ) -> Result<(ArmFrequency, IPGFrequency), ArmClockError> {
+        let dividers = dividers(hz).ok_or(if hz < Self::MIN_HZ {
+            ArmClockError::TooSlow
+        } else {
+            ArmClockError::TooFast
+        })?;
+        let old = handle.frequencies();
+        let step = handle.step_clock;
+        let (ccm, ccm_analog) = handle.raw();
+        let dcdc = dcdc.raw();
+        let result = set_arm_clock(dividers, step, ccm, ccm_analog, dcdc);
+        handle.notify_clocks_changed(old);
+        let (arm_freq, ipg_freq) = result?;
+        Ok((
+            ArmFrequency(Frequency(arm_freq)),
+            IPGFrequency(Frequency(ipg_freq)),
+        ))
+    }
+
+    /// Set the core clock, and the VDD_SOC voltage, to an operating point
+    ///
+    /// When the voltage rises, `set_operating_point()` raises the voltage, and waits
+    /// for the DCDC to settle, before it speeds up the clock. When the voltage falls,
+    /// it slows down the clock before it lowers the voltage. The core clock must be
+    /// exactly reachable by the PLL and dividers; see
+    /// [`set_arm_clock()`](struct.PLL1.html#method.set_arm_clock).
+    ///
+    /// Returns an error, without touching any clocks, if the operating point isn't
+    /// supported. A core clock above 528MHz needs at least 1.25V, and a core clock
+    /// above 24MHz needs at least 1.15V.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::ccm::OperatingPoint;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let (arm, _) = peripherals
+    ///     .ccm
+    ///     .pll1
+    ///     .set_operating_point(
+    ///         OperatingPoint::LOW_POWER_RUN,
+    ///         &mut peripherals.ccm.handle,
+    ///         &mut peripherals.dcdc,
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(arm.hz(), 24_000_000);
+    /// ```
+    pub fn set_operating_point(
+        &mut self,
+        point: OperatingPoint,
+        handle: &mut Handle,
+        dcdc: &mut crate::dcdc::DCDC,
+    ) -> Result<(ArmFrequency, IPGFrequency), OperatingPointError> {
+        let dividers = point.dividers()?;
+        let old = handle.frequencies();
+        let step = handle.step_clock;
         let (ccm, ccm_analog) = handle.raw();
         let dcdc = dcdc.raw();
-        let (arm_freq, ipg_freq) = set_arm_clock(hz, ccm, ccm_analog, dcdc);
-        (
+        let result = set_operating_point(point, dividers, step, ccm, ccm_analog, dcdc);
+        handle.notify_clocks_changed(old);
+        let (arm_freq, ipg_freq) = result?;
+        Ok((
             ArmFrequency(Frequency(arm_freq)),
             IPGFrequency(Frequency(ipg_freq)),
-        )
+        ))
+    }
+}
+
+/// USB1 PLL (PLL3), running at 480MHz
+///
+/// PLL3, its PFDs, and its fixed dividers can clock the UART, SPI, and I2C peripherals.
+/// Enable PLL3 with [`CCM::pll3`](struct.CCM.html#structfield.pll3) before you select
+/// one of those clocks. The USB peripheral also relies on PLL3; the HAL never disables it.
+pub struct PLL3(());
+impl PLL3 {
+    /// The PLL3 output frequency
+    pub const HZ: u32 = 480_000_000;
+}
+
+impl pll3::PFD {
+    /// Power and enable PLL3 at 480MHz, and wait for it to lock
+    ///
+    /// If PLL3 is already running at 480MHz, `enable()` leaves it alone. Returns an
+    /// error if the PLL doesn't lock in time. PLL3 is bypassed until it locks, so it
+    /// outputs the 24MHz oscillator clock if it times out.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::ccm;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let frequency = peripherals
+    ///     .ccm
+    ///     .pll3
+    ///     .enable(&mut peripherals.ccm.handle)
+    ///     .unwrap();
+    /// assert_eq!(frequency.hz(), ccm::PLL3::HZ);
+    /// ```
+    pub fn enable(&mut self, handle: &mut Handle) -> Result<Frequency, PllLockTimeout> {
+        if self.frequency(handle) == Some(Frequency(PLL3::HZ)) {
+            return Ok(Frequency(PLL3::HZ));
+        }
+        let (_, analog) = handle.raw();
+        modify_reg!(ral::ccm_analog, analog, PLL_USB1, BYPASS: 1);
+        modify_reg!(
+            ral::ccm_analog,
+            analog,
+            PLL_USB1,
+            POWER: 1,
+            ENABLE: 1,
+            EN_USB_CLKS: 1,
+            DIV_SELECT: 0
+        );
+        wait_for_lock(PLL_LOCK_SPINS, || {
+            read_reg!(ral::ccm_analog, analog, PLL_USB1, LOCK) != 0
+        })?;
+        modify_reg!(ral::ccm_analog, analog, PLL_USB1, BYPASS: 0);
+        Ok(Frequency(PLL3::HZ))
+    }
+
+    /// Returns `true` if PLL3 is locked
+    pub fn is_locked(&self, handle: &Handle) -> bool {
+        read_reg!(ral::ccm_analog, handle.analog, PLL_USB1, LOCK) != 0
+    }
+
+    /// Returns the PLL3 output frequency, or `None` if PLL3 is off, or not yet locked
+    ///
+    /// A bypassed PLL3 outputs the 24MHz oscillator clock.
+    pub fn frequency(&self, handle: &Handle) -> Option<Frequency> {
+        let (power, enable, bypass, div_select, lock) = read_reg!(
+            ral::ccm_analog,
+            handle.analog,
+            PLL_USB1,
+            POWER,
+            ENABLE,
+            BYPASS,
+            DIV_SELECT,
+            LOCK
+        );
+        if power == 0 || enable == 0 {
+            None
+        } else if bypass != 0 {
+            Some(OSCILLATOR_FREQUENCY)
+        } else if lock == 0 {
+            None
+        } else if div_select == 0 {
+            Some(Frequency(PLL3::HZ))
+        } else {
+            Some(Frequency(528_000_000))
+        }
     }
 }
 
 impl CCM {
     pub(crate) fn new(base: ral::ccm::Instance, analog: ral::ccm_analog::Instance) -> Self {
         CCM {
-            handle: Handle { base, analog },
+            handle: Handle {
+                base,
+                analog,
+                listeners: listener::Listeners::new(),
+                step_clock: StepClock::default(),
+            },
             perclk: perclk::Multiplexer::new(),
             pll1: PLL1::new(),
             pll2: pll2::PFD::new(),
             pll3: pll3::PFD::new(),
+            audio_pll: pll4::PLL4::new(),
         }
     }
 
@@ -99,7 +355,7 @@ impl CCM {
 }
 
 pub mod perclk {
-    use super::{ral, Divider, Frequency, Handle, OSCILLATOR_FREQUENCY};
+    use super::{math, ral, Divider, Frequency, GateSetting, Handle, OSCILLATOR_FREQUENCY};
 
     use ral::{ccm::CSCMR1::PERCLK_CLK_SEL, modify_reg};
 
@@ -263,7 +519,7 @@ pub mod perclk {
 
     impl From<PODF> for Divider {
         fn from(podf: PODF) -> Self {
-            Divider((podf as u32) + 1)
+            Divider(math::divisor(podf as u32))
         }
     }
 
@@ -288,6 +544,116 @@ pub mod perclk {
         }
     }
 
+    /// A PERCLK change
+    ///
+    /// Pass the change to the timers that run from PERCLK, like
+    /// [`PIT::reclock()`](../../pit/struct.PIT.html#method.reclock) and
+    /// [`GPT::reclock()`](../../gpt/struct.GPT.html#method.reclock), so that they
+    /// use the new frequency.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PerclkChanged {
+        old_hz: u32,
+        new_hz: u32,
+        clksel: CLKSEL,
+    }
+
+    impl PerclkChanged {
+        /// The PERCLK frequency before the change, in Hz
+        pub fn old_hz(&self) -> u32 {
+            self.old_hz
+        }
+
+        /// The PERCLK frequency after the change, in Hz
+        pub fn new_hz(&self) -> u32 {
+            self.new_hz
+        }
+
+        /// The clock selection after the change
+        pub fn clock_selection(&self) -> CLKSEL {
+            self.clksel
+        }
+
+        /// Scale `ticks` of the old frequency into ticks of the new frequency
+        ///
+        /// Rounds to the nearest tick, and saturates at `u32::max_value()`.
+        pub(crate) fn rescale(&self, ticks: u32) -> u32 {
+            rescale(ticks, self.old_hz, self.new_hz)
+        }
+    }
+
+    /// Change the PERCLK divider, and clock selection, while timers are running
+    ///
+    /// Use this when the IPG clock changes, or to select a different PERCLK frequency
+    /// after you've clocked the PIT and GPTs. `reconfigure()` pauses the PIT and GPT
+    /// clocks while it switches the clock, then resumes them. Pass the returned change
+    /// to each timer's `reclock()` method, so that they keep their timeouts. `reconfigure()`
+    /// also notifies the handle's [listeners](../listener/index.html).
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::ccm::perclk::{CLKSEL, PODF};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let (_, ipg_hz) = peripherals.ccm.pll1.set_arm_clock(
+    ///     imxrt1060_hal::ccm::PLL1::ARM_HZ,
+    ///     &mut peripherals.ccm.handle,
+    ///     &mut peripherals.dcdc,
+    /// ).unwrap();
+    ///
+    /// let mut cfg = peripherals.ccm.perclk.configure(
+    ///     &mut peripherals.ccm.handle,
+    ///     PODF::DIVIDE_3,
+    ///     CLKSEL::IPG(ipg_hz),
+    /// );
+    /// let (mut pit, _, _, _) = peripherals.pit.clock(&mut cfg);
+    /// let mut gpt1 = peripherals.gpt1.clock(&mut cfg);
+    ///
+    /// // Later, run the timers from the oscillator
+    /// let changed = cfg.reconfigure(PODF::DIVIDE_1, CLKSEL::OSC);
+    /// pit.reclock(&changed);
+    /// gpt1.reclock(&changed);
+    /// ```
+    pub fn reconfigure(handle: &mut Handle, podf: PODF, clksel: CLKSEL) -> PerclkChanged {
+        use super::clock_gate::{Gpt1, Gpt2, Pit};
+        let old = handle.frequencies();
+
+        let gates = (
+            handle.gate_setting::<Pit>(),
+            handle.gate_setting::<Gpt1>(),
+            handle.gate_setting::<Gpt2>(),
+        );
+        handle.gate::<Pit>(GateSetting::Off);
+        handle.gate::<Gpt1>(GateSetting::Off);
+        handle.gate::<Gpt2>(GateSetting::Off);
+
+        modify_reg!(
+            ral::ccm,
+            handle.base,
+            CSCMR1,
+            PERCLK_CLK_SEL: clksel.to_perclk_clk_sel(),
+            PERCLK_PODF: (podf as u32)
+        );
+
+        handle.gate::<Pit>(gates.0);
+        handle.gate::<Gpt1>(gates.1);
+        handle.gate::<Gpt2>(gates.2);
+        handle.notify_clocks_changed(old);
+
+        PerclkChanged {
+            old_hz: old.perclk.hz(),
+            new_hz: (Frequency::from(clksel) / Divider::from(podf)).0,
+            clksel,
+        }
+    }
+
+    /// Scale `ticks` from an `old_hz` clock to a `new_hz` clock
+    pub(crate) fn rescale(ticks: u32, old_hz: u32, new_hz: u32) -> u32 {
+        if old_hz == 0 {
+            return ticks;
+        }
+        let scaled = (ticks as u64 * new_hz as u64 + old_hz as u64 / 2) / old_hz as u64;
+        scaled.min(u32::max_value() as u64) as u32
+    }
+
     impl<'a> Configured<'a> {
         pub(crate) fn enable_pit_clock_gates(&mut self) -> (Frequency, Divider) {
             modify_reg!(ral::ccm, self.handle.base, CCGR1, CG6: 0x3);
@@ -307,6 +673,16 @@ pub mod perclk {
         pub(crate) fn clock_selection(&self) -> CLKSEL {
             self.clksel
         }
+
+        /// Change the PERCLK divider, and clock selection
+        ///
+        /// See [`reconfigure()`](fn.reconfigure.html) for more information.
+        pub fn reconfigure(&mut self, podf: PODF, clksel: CLKSEL) -> PerclkChanged {
+            let changed = reconfigure(self.handle, podf, clksel);
+            self.podf = podf;
+            self.clksel = clksel;
+            changed
+        }
     }
 
     impl From<CLKSEL> for Frequency {
@@ -318,6 +694,29 @@ pub mod perclk {
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::rescale;
+
+        #[test]
+        fn rescale_ticks() {
+            // 75MHz to 24MHz
+            assert_eq!(rescale(75_000, 75_000_000, 24_000_000), 24_000);
+            // 24MHz to 150MHz
+            assert_eq!(rescale(24_000, 24_000_000, 150_000_000), 150_000);
+            // Rounds to the nearest tick
+            assert_eq!(rescale(10, 3, 2), 7);
+            assert_eq!(rescale(1, 3, 1), 0);
+            // Saturates
+            assert_eq!(
+                rescale(u32::max_value(), 24_000_000, 150_000_000),
+                u32::max_value()
+            );
+            // Unknown old frequency
+            assert_eq!(rescale(123, 0, 24_000_000), 123);
+        }
+    }
 }
 
 macro_rules! pfd {
@@ -376,7 +775,14 @@ pub mod pll3 {
 
     pfd!(PFD_480_SET, PFD_480);
 
-    pub struct Frequency(u8);
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Frequency(pub(crate) u8);
+
+    impl From<Frequency> for super::Frequency {
+        fn from(pfd: Frequency) -> super::Frequency {
+            super::Frequency(super::math::pfd_hz(super::PLL3::HZ, pfd.0 as u32))
+        }
+    }
 
     pub const MHZ_720: Frequency = Frequency(12);
     pub const MHZ_664: Frequency = Frequency(13);
@@ -408,6 +814,9 @@ pub mod pll3 {
 pub mod pll2 {
     pfd!(PFD_528_SET, PFD_528);
 
+    mod pfd;
+    pub use pfd::{Pfd, PfdClock, PfdError};
+
     pub struct Frequency(u8);
 
     pub const MHZ_792: Frequency = Frequency(12);
@@ -481,6 +890,13 @@ pub fn ticks<R: TicksRepr>(dur: Duration, freq: u32, div: u32) -> Result<R, Tick
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Frequency(pub(crate) u32);
 
+impl Frequency {
+    /// Returns the frequency, in Hz
+    pub fn hz(self) -> u32 {
+        self.0
+    }
+}
+
 impl From<Frequency> for core::time::Duration {
     fn from(hz: Frequency) -> core::time::Duration {
         core::time::Duration::from_nanos((1_000_000_000u32 / hz.0).into())
@@ -498,7 +914,7 @@ impl Default for Divider {
 }
 
 /// High speed oscillator frequency
-const OSCILLATOR_FREQUENCY: Frequency = Frequency(24_000_000 /* 24MHz */);
+pub(crate) const OSCILLATOR_FREQUENCY: Frequency = Frequency(24_000_000 /* 24MHz */);
 
 impl core::ops::Div<Divider> for Frequency {
     type Output = Frequency;
@@ -566,7 +982,7 @@ pub mod pwm {
 
 /// Timing configurations for I2C peripherals
 pub mod i2c {
-    use super::{Divider, Frequency, OSCILLATOR_FREQUENCY};
+    use super::{math, Divider, Frequency, OSCILLATOR_FREQUENCY, PLL3};
     use crate::ral;
 
     /// Clock selection for all I2C peripherals
@@ -576,6 +992,11 @@ pub mod i2c {
     pub enum ClockSelect {
         /// Derive clock from oscillator
         OSC = ral::ccm::CSCDR2::LPI2C_CLK_SEL::RW::LPI2C_CLK_SEL_1,
+        /// PLL3 divided by 8 (60MHz)
+        ///
+        /// Enable PLL3 with [`pll3::PFD::enable()`](../pll3/struct.PFD.html#method.enable) before
+        /// selecting this clock.
+        PLL3 = ral::ccm::CSCDR2::LPI2C_CLK_SEL::RW::LPI2C_CLK_SEL_0,
     }
 
     /// Prescalar selection for all I2C input clocks
@@ -653,20 +1074,22 @@ pub mod i2c {
         fn from(clock_select: ClockSelect) -> Self {
             match clock_select {
                 ClockSelect::OSC => OSCILLATOR_FREQUENCY,
+                ClockSelect::PLL3 => Frequency(PLL3::HZ / math::LPI2C_PLL3_DIVIDER),
             }
         }
     }
 
     impl From<PrescalarSelect> for Divider {
         fn from(prescalar_select: PrescalarSelect) -> Self {
-            Divider((prescalar_select as u32) + 1)
+            Divider(math::divisor(prescalar_select as u32))
         }
     }
 }
 
 pub mod uart {
-    use super::{Divider, Frequency, OSCILLATOR_FREQUENCY};
+    use super::{math, Divider, Frequency, Handle, OSCILLATOR_FREQUENCY, PLL3};
     use crate::ral;
+    use core::sync::atomic::{AtomicUsize, Ordering};
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     #[repr(u32)]
@@ -674,6 +1097,11 @@ pub mod uart {
     pub enum ClockSelect {
         /// Oscillator clock
         OSC = ral::ccm::CSCDR1::UART_CLK_SEL::RW::UART_CLK_SEL_1,
+        /// PLL3 divided by 6 (80MHz)
+        ///
+        /// Enable PLL3 with [`pll3::PFD::enable()`](../pll3/struct.PFD.html#method.enable) before
+        /// selecting this clock.
+        PLL3 = ral::ccm::CSCDR1::UART_CLK_SEL::RW::UART_CLK_SEL_0,
     }
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -812,7 +1240,7 @@ pub mod uart {
 
     impl From<PrescalarSelect> for Divider {
         fn from(prescale: PrescalarSelect) -> Divider {
-            Divider((prescale as u32) + 1)
+            Divider(math::divisor(prescale as u32))
         }
     }
 
@@ -820,10 +1248,288 @@ pub mod uart {
         fn from(clock_select: ClockSelect) -> Self {
             match clock_select {
                 ClockSelect::OSC => OSCILLATOR_FREQUENCY,
+                ClockSelect::PLL3 => Frequency(PLL3::HZ / math::UART_PLL3_DIVIDER),
+            }
+        }
+    }
+
+    /// The UART root clock, shared by all UART peripherals
+    ///
+    /// [`Unclocked::clock()`](../../uart/struct.Unclocked.html#method.clock) returns the
+    /// first `UartClock` in [`UARTs`](../../uart/struct.UARTs.html). Each `Uninit` and `UART`
+    /// holds a clone, and computes its dividers from the clone's frequency. Since all UARTs
+    /// share one root clock, the clock only changes through
+    /// [`reconfigure()`](#method.reconfigure), once no UART holds a clone. The first
+    /// `UartClock` and its clones share one count of live clones.
+    ///
+    /// # Maximum baud rates
+    ///
+    /// The UART oversamples each bit 4 to 32 times. The fastest rate samples each bit
+    /// four times, on both clock edges, so the maximum baud rate is a quarter of the
+    /// root clock. A UART samples 16 times at a sixteenth of the root clock, which
+    /// tolerates more noise.
+    ///
+    /// | Root clock              | [`max_baud()`](#method.max_baud) | 16x oversampling |
+    /// | ----------------------- | -------------------------------- | ---------------- |
+    /// | `OSC`, 24MHz            | 6Mbaud                           | 1.5Mbaud         |
+    /// | `PLL3`, 80MHz           | 20Mbaud                          | 5Mbaud           |
+    ///
+    /// The maximum isn't always the best choice, since the dividers are integers. 6Mbaud
+    /// divides the 24MHz oscillator exactly, but it's 2.6% off from the 80MHz PLL3 clock.
+    #[derive(Debug)]
+    pub struct UartClock {
+        pub(crate) frequency: Frequency,
+        clock_select: ClockSelect,
+        prescalar: PrescalarSelect,
+        /// The number of live clones, or `None` for a clock that doesn't hold the
+        /// root clock
+        users: Option<&'static AtomicUsize>,
+    }
+
+    impl UartClock {
+        /// A token for the root clock that's counted in `users`
+        fn new(
+            users: &'static AtomicUsize,
+            clock_select: ClockSelect,
+            prescalar: PrescalarSelect,
+        ) -> Self {
+            users.fetch_add(1, Ordering::Relaxed);
+            UartClock {
+                frequency: Frequency::from(clock_select) / Divider::from(prescalar),
+                clock_select,
+                prescalar,
+                users: Some(users),
+            }
+        }
+
+        /// A token for a driver that only aliases a UART, and never computes dividers
+        pub(crate) const fn detached() -> Self {
+            UartClock {
+                frequency: Frequency(0),
+                clock_select: ClockSelect::OSC,
+                prescalar: PrescalarSelect::DIVIDE_1,
+                users: None,
+            }
+        }
+
+        /// Select the UART root clock, and ungate all UARTs
+        ///
+        /// `users` counts the returned clock, and its clones.
+        pub(crate) fn configure(
+            handle: &mut Handle,
+            users: &'static AtomicUsize,
+            clock_select: ClockSelect,
+            prescalar: PrescalarSelect,
+        ) -> Self {
+            select(handle, clock_select, prescalar);
+            UartClock::new(users, clock_select, prescalar)
+        }
+
+        /// Returns the UART root clock frequency
+        pub fn frequency(&self) -> Frequency {
+            self.frequency
+        }
+
+        /// Returns the root clock's source
+        pub fn clock_select(&self) -> ClockSelect {
+            self.clock_select
+        }
+
+        /// Returns the root clock's divider
+        pub fn prescalar(&self) -> PrescalarSelect {
+            self.prescalar
+        }
+
+        /// Returns the fastest baud rate, a quarter of the root clock
+        ///
+        /// A UART refuses faster rates with
+        /// [`TimingsError::OutOfRange`](enum.TimingsError.html#variant.OutOfRange).
+        pub fn max_baud(&self) -> u32 {
+            self.frequency.0 / 4
+        }
+
+        /// Returns `true` if another `UartClock` shares the root clock
+        fn is_shared(&self) -> bool {
+            match self.users {
+                Some(users) => users.load(Ordering::Acquire) > 1,
+                None => true,
+            }
+        }
+
+        /// Change the UART root clock
+        ///
+        /// Returns the new root clock frequency, or an error if an `Uninit` or `UART`
+        /// still holds a clone of this clock. Drop the UARTs that you don't use before
+        /// you reconfigure the clock.
+        ///
+        /// # Example
+        ///
+        /// Speed up the root clock for 6Mbaud, then initialize a UART.
+        ///
+        /// ```no_run
+        /// use imxrt1060_hal::ccm::uart::{ClockSelect, PrescalarSelect};
+        ///
+        /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+        /// let (uart2, mut clock) = {
+        ///     let mut uarts = peripherals.uart.clock(
+        ///         &mut peripherals.ccm.handle,
+        ///         ClockSelect::OSC,
+        ///         PrescalarSelect::DIVIDE_4,
+        ///     );
+        ///     let clock = uarts.take_clock().unwrap();
+        ///     // The other Uninit UARTs drop at the end of the block
+        ///     (uarts.uart2, clock)
+        /// };
+        ///
+        /// // Fails; uart2 holds the clock
+        /// assert!(clock
+        ///     .reconfigure(&mut peripherals.ccm.handle, ClockSelect::OSC, PrescalarSelect::DIVIDE_1)
+        ///     .is_err());
+        ///
+        /// drop(uart2);
+        /// clock
+        ///     .reconfigure(&mut peripherals.ccm.handle, ClockSelect::OSC, PrescalarSelect::DIVIDE_1)
+        ///     .unwrap();
+        /// assert_eq!(clock.max_baud(), 6_000_000);
+        /// ```
+        pub fn reconfigure(
+            &mut self,
+            handle: &mut Handle,
+            clock_select: ClockSelect,
+            prescalar: PrescalarSelect,
+        ) -> Result<Frequency, ClockInUse> {
+            if self.is_shared() {
+                return Err(ClockInUse(()));
             }
+            let old = handle.frequencies();
+            select(handle, clock_select, prescalar);
+            self.frequency = Frequency::from(clock_select) / Divider::from(prescalar);
+            self.clock_select = clock_select;
+            self.prescalar = prescalar;
+            handle.notify_clocks_changed(old);
+            Ok(self.frequency)
         }
     }
 
+    /// Shares the root clock with another UART
+    impl Clone for UartClock {
+        fn clone(&self) -> Self {
+            if let Some(users) = self.users {
+                users.fetch_add(1, Ordering::Relaxed);
+            }
+            UartClock {
+                frequency: self.frequency,
+                clock_select: self.clock_select,
+                prescalar: self.prescalar,
+                users: self.users,
+            }
+        }
+    }
+
+    impl Drop for UartClock {
+        fn drop(&mut self) {
+            if let Some(users) = self.users {
+                users.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    /// A UART still holds the root clock, so it can't change
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ClockInUse(());
+
+    /// Gate all UARTs, select the root clock, then ungate all UARTs
+    ///
+    /// See table 13-4 for clock gating registers.
+    fn select(handle: &mut Handle, clock_select: ClockSelect, prescalar: PrescalarSelect) {
+        let (ccm, _) = handle.raw();
+
+        // -----------------------------------------
+        // Disable clocks before modifying selection
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR5,
+            CG12: 0,    // UART1
+            CG13: 0     // UART7
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR0,
+            CG14: 0,    // UART2
+            CG6: 0      // UART3
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR1,
+            CG12: 0     // UART4
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR3,
+            CG1: 0,     // UART5
+            CG3: 0      // UART6
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR6,
+            CG7: 0      // UART8
+        );
+        // -----------------------------------------
+
+        // -------------------------
+        // Select clocks & prescalar
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CSCDR1,
+            UART_CLK_SEL: (clock_select as u32),
+            UART_CLK_PODF: (prescalar as u32)
+        );
+        // -------------------------
+
+        // -------------
+        // Enable clocks
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR5,
+            CG12: 0b11,    // UART1
+            CG13: 0b11     // UART7
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR0,
+            CG14: 0b11,    // UART2
+            CG6: 0b11      // UART3
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR1,
+            CG12: 0b11     // UART4
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR3,
+            CG1: 0b11,     // UART5
+            CG3: 0b11      // UART6
+        );
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CCGR6,
+            CG7: 0b11      // UART8
+        );
+        // -------------
+    }
+
     /// An opaque type that describes timing configurations
     pub struct Timings {
         /// OSR register value. Accounts for the -1. May be written
@@ -833,72 +1539,182 @@ pub mod uart {
         pub(crate) both_edge: bool,
         /// SBR value;
         pub(crate) sbr: u16,
+        /// The baud rate that the OSR and SBR produce
+        pub(crate) achieved: u32,
+        /// The error of `achieved`, in parts per million of the requested baud
+        pub(crate) error_ppm: i32,
     }
 
     #[derive(Clone, Copy, Debug)]
     pub enum TimingsError {
         DivideByZero,
+        /// The baud rate is faster than a quarter of the clock, or it
+        /// needs an SBR larger than 8191
         OutOfRange,
     }
 
+    /// The largest SBR value
+    const SBR_MAX: u64 = 8191;
+
     /// Compute timings for a UART peripheral. Returns the timings,
     /// or a string describing an error.
     pub(crate) fn timings(effective_clock: Frequency, baud: u32) -> Result<Timings, TimingsError> {
-        let effective_clock = effective_clock.0;
+        let effective_clock = u64::from(effective_clock.0);
 
         //        effective_clock
         // baud = ---------------
         //         (OSR+1)(SBR)
         //
-        // Solve for SBR:
+        // Solve for SBR, rounding to the nearest integer:
         //
         //       effective_clock
         // SBR = ---------------
         //        (OSR+1)(baud)
         //
         // After selecting SBR, calculate effective baud.
-        // Minimize the error over all OSRs.
-
-        let base_clock: u32 = effective_clock
-            .checked_div(baud)
-            .ok_or(TimingsError::DivideByZero)?;
-        let mut error = u32::max_value();
-        let mut best_osr = 16;
-        let mut best_sbr = 1;
+        // Minimize the error over all OSRs. Prefer the largest OSR
+        // of equal errors, since it samples each bit the most.
+
+        let baud = u64::from(baud);
+        if baud == 0 || effective_clock == 0 {
+            return Err(TimingsError::DivideByZero);
+        } else if effective_clock < 4 * baud {
+            return Err(TimingsError::OutOfRange);
+        }
+        let mut best: Option<(u64, u64, u64)> = None;
 
         for osr in 4..=32 {
-            let sbr = base_clock
-                .checked_div(osr)
-                .ok_or(TimingsError::DivideByZero)?;
-            let sbr = sbr.max(1).min(8191);
-            let effective_baud = effective_clock
-                .checked_div(osr * sbr)
-                .ok_or(TimingsError::DivideByZero)?;
-            let err = effective_baud.max(baud) - effective_baud.min(baud);
-            if err < error {
-                best_osr = osr;
-                best_sbr = sbr;
-                error = err
+            let divisor = osr * baud;
+            let sbr = (effective_clock + divisor / 2) / divisor;
+            if !(1..=SBR_MAX).contains(&sbr) {
+                continue;
+            }
+            // Error in thousandths of a baud
+            let effective_baud = effective_clock * 1000 / (osr * sbr);
+            let err = effective_baud.max(baud * 1000) - effective_baud.min(baud * 1000);
+            if best.map_or(true, |(_, _, error)| err <= error) {
+                best = Some((osr, sbr, err));
             }
         }
 
-        use core::convert::TryFrom;
+        let (osr, sbr, _) = best.ok_or(TimingsError::OutOfRange)?;
+        let achieved = effective_clock / (osr * sbr);
+        let exact = effective_clock as i64 * 1_000_000 / (osr * sbr) as i64;
+        let error_ppm = (exact - baud as i64 * 1_000_000) / baud as i64;
         Ok(Timings {
-            osr: u8::try_from(best_osr - 1).map_err(|_| TimingsError::OutOfRange)?,
-            sbr: u16::try_from(best_sbr).map_err(|_| TimingsError::OutOfRange)?,
-            both_edge: best_osr < 8,
+            osr: (osr - 1) as u8,
+            sbr: sbr as u16,
+            both_edge: osr < 8,
+            achieved: achieved as u32,
+            error_ppm: error_ppm as i32,
         })
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{timings, ClockSelect, Frequency, PrescalarSelect, UartClock};
+        use core::sync::atomic::AtomicUsize;
+
+        /// Returns (OSR, SBR, BOTHEDGE, achieved baud, error ppm)
+        fn search(clock: u32, baud: u32) -> Option<(u8, u16, bool, u32, i32)> {
+            timings(Frequency(clock), baud)
+                .ok()
+                .map(|t| (t.osr + 1, t.sbr, t.both_edge, t.achieved, t.error_ppm))
+        }
+
+        #[test]
+        fn baud_search() {
+            const OSC: u32 = 24_000_000;
+            const PLL3: u32 = 80_000_000;
+            // (root clock, requested baud, (OSR, SBR, BOTHEDGE, achieved baud, error ppm))
+            let table = [
+                (OSC, 115_200, Some((26, 8, false, 115_384, 1_602))),
+                (OSC, 9_600, Some((25, 100, false, 9_600, 0))),
+                (OSC, 1_500_000, Some((16, 1, false, 1_500_000, 0))),
+                (OSC, 6_000_000, Some((4, 1, true, 6_000_000, 0))),
+                (PLL3, 4_000_000, Some((20, 1, false, 4_000_000, 0))),
+                (PLL3, 1_500_000, Some((27, 2, false, 1_481_481, -12_345))),
+                (PLL3, 921_600, Some((29, 3, false, 919_540, -2_234))),
+                (PLL3, 115_200, Some((5, 139, true, 115_107, -799))),
+                // 2.6% off; the oscillator divides 6Mbaud exactly
+                (PLL3, 6_000_000, Some((13, 1, false, 6_153_846, 25_641))),
+                // PLL3 divided by 4
+                (PLL3 / 4, 115_200, Some((29, 6, false, 114_942, -2_234))),
+                (PLL3 / 4, 1_000_000, Some((20, 1, false, 1_000_000, 0))),
+                (PLL3 / 4, 5_000_000, Some((4, 1, true, 5_000_000, 0))),
+                // Needs less than 4x oversampling
+                (OSC, 10_000_000, None),
+                // Needs an SBR larger than 8191
+                (OSC, 50, None),
+            ];
+            for &(clock, baud, expected) in &table {
+                assert_eq!(search(clock, baud), expected, "{} / {}", clock, baud);
+            }
+            assert!(timings(Frequency(OSC), 0).is_err());
+        }
+
+        #[test]
+        fn uart_clock() {
+            for &(clock_select, prescalar) in &[
+                (ClockSelect::OSC, PrescalarSelect::DIVIDE_1),
+                (ClockSelect::PLL3, PrescalarSelect::DIVIDE_1),
+                (ClockSelect::PLL3, PrescalarSelect::DIVIDE_3),
+            ] {
+                static USERS: AtomicUsize = AtomicUsize::new(0);
+                let clock = UartClock::new(&USERS, clock_select, prescalar);
+                let max = clock.max_baud();
+                let t = timings(clock.frequency(), max).unwrap();
+                assert_eq!((t.osr + 1, t.both_edge, t.achieved), (4, true, max));
+                assert!(timings(clock.frequency(), max + 1).is_err());
+            }
+
+            static USERS: AtomicUsize = AtomicUsize::new(0);
+            let clock = UartClock::new(&USERS, ClockSelect::OSC, PrescalarSelect::DIVIDE_1);
+            assert_eq!(clock.max_baud(), 6_000_000);
+            assert!(!clock.is_shared());
+            let uart = clock.clone();
+            assert!(clock.is_shared());
+            // Another root clock's clones don't count
+            static OTHER_USERS: AtomicUsize = AtomicUsize::new(0);
+            let other = UartClock::new(&OTHER_USERS, ClockSelect::OSC, PrescalarSelect::DIVIDE_1);
+            let _other_uart = other.clone();
+            drop(uart);
+            assert!(!clock.is_shared());
+            assert!(other.is_shared());
+            // A detached clock never changes the root clock
+            assert!(UartClock::detached().is_shared());
+        }
+    }
 }
 
 /// Timing configurations for SPI peripherals
 pub mod spi {
-    use super::{ral::ccm, Divider, Frequency};
+    use super::{
+        clock_gate::{Lpspi1, Lpspi2, Lpspi3, Lpspi4},
+        math, pll2, pll3,
+        ral::{self, ccm},
+        Divider, Frequency, GateSetting, Handle, PLL3,
+    };
+    use crate::iomuxc::consts::U2;
 
     #[derive(Clone, Copy)]
     #[non_exhaustive] // Not all variants added
     pub enum ClockSelect {
         Pll2,
+        /// PLL3 PFD0, configured to the provided frequency
+        ///
+        /// Clocking the SPI peripherals configures, and ungates, the PFD. Enable PLL3 with
+        /// [`pll3::PFD::enable()`](../pll3/struct.PFD.html#method.enable) before selecting this clock.
+        Pll3Pfd0(pll3::Frequency),
+        /// PLL3 PFD1, configured to the provided frequency
+        ///
+        /// See [`Pll3Pfd0`](#variant.Pll3Pfd0) for more information.
+        Pll3Pfd1(pll3::Frequency),
+        /// PLL2 PFD2
+        ///
+        /// Acquire the clock from an ungated PFD with
+        /// [`Pfd::into_clock()`](../pll2/struct.Pfd.html#method.into_clock).
+        Pll2Pfd2(pll2::PfdClock<U2>),
     }
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -927,13 +1743,129 @@ pub mod spi {
         fn from(clock_select: ClockSelect) -> Self {
             match clock_select {
                 ClockSelect::Pll2 => Frequency(528_000_000),
+                ClockSelect::Pll3Pfd0(pfd) | ClockSelect::Pll3Pfd1(pfd) => pfd.into(),
+                ClockSelect::Pll2Pfd2(pfd) => pfd.frequency(),
             }
         }
     }
 
     impl From<PrescalarSelect> for Divider {
         fn from(prescalar_select: PrescalarSelect) -> Self {
-            Divider((prescalar_select as u32) + 1)
+            Divider(math::divisor(prescalar_select as u32))
         }
     }
+
+    /// A configured LPSPI root clock
+    ///
+    /// Acquire the clock with [`lpspi_clock()`](fn.lpspi_clock.html), then use it to
+    /// [`clock()`](../../spi/struct.Unclocked.html#method.clock) the SPI peripherals.
+    /// The SPI drivers compute their clock dividers from its frequency.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct LpspiClock(Frequency);
+
+    impl LpspiClock {
+        /// Returns the LPSPI root clock frequency
+        pub fn frequency(&self) -> Frequency {
+            self.0
+        }
+    }
+
+    /// Select, and divide, the LPSPI root clock
+    ///
+    /// The LPSPI root clock is the selection divided by `podf`. `lpspi_clock()` gates the
+    /// SPI peripherals while it switches the clock, then restores their gates. SPI
+    /// peripherals that you already clocked don't learn the new frequency, so change the
+    /// root clock before you clock the SPI peripherals.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::ccm::spi::{self, ClockSelect, PrescalarSelect};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let clock = spi::lpspi_clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     ClockSelect::Pll2,
+    ///     PrescalarSelect::LPSPI_PODF_5,
+    /// );
+    /// assert_eq!(clock.frequency().hz(), 88_000_000);
+    /// ```
+    pub fn lpspi_clock(
+        handle: &mut Handle,
+        selection: ClockSelect,
+        podf: PrescalarSelect,
+    ) -> LpspiClock {
+        let gates = [
+            handle.gate_setting::<Lpspi1>(),
+            handle.gate_setting::<Lpspi2>(),
+            handle.gate_setting::<Lpspi3>(),
+            handle.gate_setting::<Lpspi4>(),
+        ];
+        handle.gate::<Lpspi1>(GateSetting::Off);
+        handle.gate::<Lpspi2>(GateSetting::Off);
+        handle.gate::<Lpspi3>(GateSetting::Off);
+        handle.gate::<Lpspi4>(GateSetting::Off);
+
+        let (ccm, ccm_analog) = handle.raw();
+        use ral::ccm::CBCMR::LPSPI_CLK_SEL;
+        let clk_sel = match selection {
+            ClockSelect::Pll2 => LPSPI_CLK_SEL::RW::LPSPI_CLK_SEL_2,
+            ClockSelect::Pll3Pfd0(pfd) => {
+                ral::modify_reg!(ral::ccm_analog, ccm_analog, PFD_480, PFD0_FRAC: pfd.0 as u32, PFD0_CLKGATE: 0);
+                LPSPI_CLK_SEL::RW::LPSPI_CLK_SEL_1
+            }
+            ClockSelect::Pll3Pfd1(pfd) => {
+                ral::modify_reg!(ral::ccm_analog, ccm_analog, PFD_480, PFD1_FRAC: pfd.0 as u32, PFD1_CLKGATE: 0);
+                LPSPI_CLK_SEL::RW::LPSPI_CLK_SEL_0
+            }
+            ClockSelect::Pll2Pfd2(_) => LPSPI_CLK_SEL::RW::LPSPI_CLK_SEL_3,
+        };
+        ral::modify_reg!(
+            ral::ccm,
+            ccm,
+            CBCMR,
+            LPSPI_PODF: (podf as u32),
+            LPSPI_CLK_SEL: clk_sel
+        );
+
+        handle.gate::<Lpspi1>(gates[0]);
+        handle.gate::<Lpspi2>(gates[1]);
+        handle.gate::<Lpspi3>(gates[2]);
+        handle.gate::<Lpspi4>(gates[3]);
+
+        LpspiClock(Frequency::from(selection) / Divider::from(podf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wait_for_lock;
+
+    #[test]
+    fn lock_wait_is_bounded() {
+        let mut polls = 0;
+        assert!(wait_for_lock(100, || {
+            polls += 1;
+            false
+        })
+        .is_err());
+        assert_eq!(polls, 101);
+    }
+
+    #[test]
+    fn lock_wait_returns_on_lock() {
+        let mut polls = 0;
+        assert!(wait_for_lock(100, || {
+            polls += 1;
+            polls == 42
+        })
+        .is_ok());
+        assert_eq!(polls, 42);
+
+        polls = 0;
+        assert!(wait_for_lock(0, || {
+            polls += 1;
+            true
+        })
+        .is_ok());
+        assert_eq!(polls, 1);
+    }
 }
@@ -5,72 +5,214 @@
 //!
 //! [`set_arm_clock` routine]: https://github.com/PaulStoffregen/cores/blob/master/teensy4/clockspeed.c
 
+use super::{
+    math::{dividers, vdd_soc_trg, Dividers, MAX_ARM_HZ},
+    step_clock::{self, CoreMux, StepClock},
+    wait_for_lock, PllLockTimeout, PLL_LOCK_SPINS,
+};
 use imxrt_ral as ral;
 use ral::{modify_reg, read_reg, write_reg};
 
-/// Note that while the value is limited to u8 the return
-/// is u32 to easily compare against register read values which are always u32
-#[inline(always)]
-fn reg3_trg(mv: u32) -> u32 {
-    (((mv - 800) / 25) as u8) as u32
+/// Smallest and largest VDD_SOC setpoints, in millivolts
+const VDD_SOC_MV: core::ops::RangeInclusive<u32> = 925..=1300;
+/// VDD_SOC setpoint step, in millivolts
+const VDD_SOC_STEP_MV: u32 = 25;
+
+/// A core clock frequency, and the VDD_SOC voltage that supports it
+///
+/// Use one of the presets, which come from the operating ranges in the datasheet, or
+/// describe your own. Set an operating point with
+/// [`PLL1::set_operating_point()`](struct.PLL1.html#method.set_operating_point).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatingPoint {
+    /// Core clock frequency, in Hz
+    pub core_hz: u32,
+    /// VDD_SOC voltage, in millivolts
+    pub vdd_soc_mv: u32,
 }
 
-/// Sets the main system clock to as close to `hz` as possible.
-/// Returns the `(ARM, IPG)` clock frequencies based on the input frequency
-/// and selected prescalars.
-pub fn set_arm_clock(
-    mut hz: u32,
+impl OperatingPoint {
+    /// Overdrive run mode: 600MHz at 1.25V
+    pub const OVERDRIVE: Self = OperatingPoint {
+        core_hz: 600_000_000,
+        vdd_soc_mv: 1250,
+    };
+    /// Full-speed run mode: 528MHz at 1.15V
+    pub const FULL_SPEED: Self = OperatingPoint {
+        core_hz: 528_000_000,
+        vdd_soc_mv: 1150,
+    };
+    /// Low-power run mode: 24MHz at 0.95V
+    pub const LOW_POWER_RUN: Self = OperatingPoint {
+        core_hz: 24_000_000,
+        vdd_soc_mv: 950,
+    };
+
+    /// Returns the operating point that `set_arm_clock()` selects for `hz`
+    fn for_frequency(hz: u32) -> Self {
+        let vdd_soc_mv = if hz > 528_000_000 {
+            1250 // 1.25V
+        } else if hz <= 24_000_000 {
+            950 // 0.95V
+        } else {
+            1150 // 1.15V, default
+        };
+        OperatingPoint {
+            core_hz: hz,
+            vdd_soc_mv,
+        }
+    }
+
+    /// Computes the dividers for this operating point, or returns an error if the
+    /// operating point isn't supported
+    ///
+    /// The dividers must reach `core_hz` exactly.
+    pub(super) fn dividers(&self) -> Result<Dividers, OperatingPointError> {
+        if !VDD_SOC_MV.contains(&self.vdd_soc_mv) || self.vdd_soc_mv % VDD_SOC_STEP_MV != 0 {
+            return Err(OperatingPointError::InvalidVoltage);
+        }
+        let dividers = dividers(self.core_hz).ok_or(OperatingPointError::Unsupported)?;
+        if dividers.arm_hz() != self.core_hz || self.core_hz > max_arm_hz(self.vdd_soc_mv) {
+            return Err(OperatingPointError::Unsupported);
+        }
+        Ok(dividers)
+    }
+}
+
+/// The operating point isn't supported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatingPointError {
+    /// The voltage isn't a 25mV step from 0.925V to 1.3V
+    InvalidVoltage,
+    /// The core clock is out of range, or too fast for the voltage
+    Unsupported,
+    /// PLL1 didn't lock before the timeout
+    ///
+    /// VDD_SOC is at least as high as it was, and the core runs from the step clock.
+    PllLockTimeout,
+}
+
+impl From<PllLockTimeout> for OperatingPointError {
+    fn from(_: PllLockTimeout) -> Self {
+        OperatingPointError::PllLockTimeout
+    }
+}
+
+/// Returns the fastest core clock that `vdd_soc_mv` supports
+fn max_arm_hz(vdd_soc_mv: u32) -> u32 {
+    if vdd_soc_mv >= 1250 {
+        MAX_ARM_HZ
+    } else if vdd_soc_mv >= 1150 {
+        528_000_000
+    } else {
+        24_000_000
+    }
+}
+
+/// A step in an operating point transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Set VDD_SOC, and wait for the DCDC to settle
+    Voltage,
+    /// Set the core clock
+    Frequency,
+}
+
+/// Returns the order of steps that moves VDD_SOC from `current_mv` to `target_mv`
+///
+/// Raise the voltage before speeding up the clock, and lower the voltage after
+/// slowing down the clock.
+fn sequence(current_mv: u32, target_mv: u32) -> [Step; 2] {
+    if target_mv > current_mv {
+        [Step::Voltage, Step::Frequency]
+    } else {
+        [Step::Frequency, Step::Voltage]
+    }
+}
+
+/// Sets the main system clock to `point`, using the `dividers` from
+/// `point.dividers()`. Returns the `(ARM, IPG)` clock frequencies.
+///
+/// If PLL1 doesn't lock, this returns an error, and leaves the core running
+/// from the step clock.
+pub fn set_operating_point(
+    point: OperatingPoint,
+    dividers: Dividers,
+    source: StepClock,
     ccm: &ral::ccm::Instance,
     ccm_analog: &ral::ccm_analog::Instance,
     dcdc: &ral::dcdc::Instance,
-) -> (u32, u32) {
-    let millivolts: u32 = if hz > 528_000_000 {
-        1250 // 1.25V
-    } else if hz <= 24_000_000 {
-        950 // 0.95V
-    } else {
-        1150 // 1.15V, default
-    };
-
+) -> Result<(u32, u32), PllLockTimeout> {
     // Enable clocks to the DCDC module
     // Safety: CG3 field is two bits
     modify_reg!(ral::ccm, ccm, CCGR6, CG3: 0x3);
 
-    // Set VDD_SOC, voltage for the chip
-    let reg3_trg_mv = reg3_trg(millivolts);
-    if read_reg!(ral::dcdc, dcdc, REG3, TRG) < reg3_trg_mv {
-        log::debug!("Increasing voltage to {}mv", millivolts);
+    let current_mv = 800 + read_reg!(ral::dcdc, dcdc, REG3, TRG) * VDD_SOC_STEP_MV;
+    for step in &sequence(current_mv, point.vdd_soc_mv) {
+        match step {
+            Step::Voltage => set_voltage(point.vdd_soc_mv, dcdc),
+            Step::Frequency => set_frequency(dividers, source, ccm, ccm_analog)?,
+        }
+    }
+    Ok((dividers.arm_hz(), dividers.ipg_hz()))
+}
+
+/// Sets the main system clock using the `dividers` from [`dividers()`], and the
+/// voltage that the frequency requires. Returns the `(ARM, IPG)` clock frequencies.
+///
+/// If PLL1 doesn't lock, this returns an error, and leaves the core running
+/// from the step clock.
+pub fn set_arm_clock(
+    dividers: Dividers,
+    source: StepClock,
+    ccm: &ral::ccm::Instance,
+    ccm_analog: &ral::ccm_analog::Instance,
+    dcdc: &ral::dcdc::Instance,
+) -> Result<(u32, u32), PllLockTimeout> {
+    let point = OperatingPoint::for_frequency(dividers.arm_hz());
+    set_operating_point(point, dividers, source, ccm, ccm_analog, dcdc)
+}
+
+/// Sets VDD_SOC, the voltage for the chip, and waits for the DCDC to settle
+fn set_voltage(millivolts: u32, dcdc: &ral::dcdc::Instance) {
+    let reg3_trg_mv = vdd_soc_trg(millivolts);
+    if read_reg!(ral::dcdc, dcdc, REG3, TRG) != reg3_trg_mv {
+        log::debug!("Setting voltage to {}mv", millivolts);
         // Safety: the possible values of millivolts after going through
-        // reg3_trg fits in 5 bits.
+        // vdd_soc_trg fits in 5 bits.
         modify_reg!(ral::dcdc, dcdc, REG3, TRG: reg3_trg_mv);
         while read_reg!(ral::dcdc, dcdc, REG0, STS_DC_OK) == 0 {
             #[allow(deprecated)]
             core::sync::atomic::spin_loop_hint();
         }
     }
+}
 
-    select_alt_clock(ccm, ccm_analog);
+/// Runs the core at the frequency described by `dividers`, using the step clock
+/// `source` while PLL1 relocks
+fn set_frequency(
+    dividers: Dividers,
+    source: StepClock,
+    ccm: &ral::ccm::Instance,
+    ccm_analog: &ral::ccm_analog::Instance,
+) -> Result<(), PllLockTimeout> {
+    let Dividers {
+        mult,
+        div_arm,
+        div_ahb,
+        div_ipg,
+    } = dividers;
+    let hz = dividers.arm_hz();
+
+    let (saved, step_hz) = step_clock::enter(ccm, ccm_analog, source);
+    log::debug!("Step clock = {}Hz", step_hz.hz());
 
-    let (mut div_arm, mut div_ahb) = (1, 1);
-    while hz * div_arm * div_ahb < 648_000_000 {
-        if div_arm < 8 {
-            div_arm += 1;
-        } else if div_ahb < 5 {
-            div_ahb += 1;
-            div_arm = 1;
-        } else {
-            break;
-        }
-    }
-    let mult = (hz * div_arm * div_ahb + 6_000_000) / 12_000_000;
-    let mult = mult.min(108).max(54);
     log::debug!(
         "Frequency 12MHz * {mult} / {div_arm} / {div_ahb}",
         mult = mult,
         div_arm = div_arm,
         div_ahb = div_ahb
     );
-    hz = mult * 12_000_000 / div_arm / div_ahb;
 
     let pll_arm = read_reg!(ral::ccm_analog, ccm_analog, PLL_ARM);
     log::debug!("ARM PLL = 0x{:x}", pll_arm);
@@ -93,9 +235,11 @@ pub fn set_arm_clock(
                PLL_SEL: 0,
                LOCK: 0
     );
-    while read_reg!(ral::ccm_analog, ccm_analog, PLL_ARM, LOCK) == 0 {
-        #[allow(deprecated)]
-        core::sync::atomic::spin_loop_hint();
+    if let Err(err) = wait_for_lock(PLL_LOCK_SPINS, || {
+        read_reg!(ral::ccm_analog, ccm_analog, PLL_ARM, LOCK) != 0
+    }) {
+        log::error!("ARM PLL didn't lock");
+        return Err(err);
     }
     log::debug!(
         "ARM PLL = 0x{:x}",
@@ -113,78 +257,85 @@ pub fn set_arm_clock(
         core::sync::atomic::spin_loop_hint();
     }
 
-    let div_ipg = (hz + 149_999_999) / 150_000_000;
-    let div_ipg = div_ipg.min(4);
     modify_reg!(ral::ccm, ccm, CBCDR, IPG_PODF: (div_ipg - 1));
-    modify_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK_SEL: 0);
-    while read_reg!(ral::ccm, ccm, CDHIPR, PERIPH_CLK_SEL_BUSY) > 0 {
-        #[allow(deprecated)]
-        core::sync::atomic::spin_loop_hint();
-    }
+    step_clock::leave(
+        ccm,
+        CoreMux {
+            periph_clk_sel: step_clock::PRE_PERIPH,
+            pre_periph_clk_sel: step_clock::ARM_PLL,
+            ..saved
+        },
+    );
 
     log::debug!("ARM={}, IPG={}", hz, hz / div_ipg);
-    let reg3_trg_mv = reg3_trg(millivolts);
-    if read_reg!(ral::dcdc, dcdc, REG3, TRG) > reg3_trg_mv {
-        log::debug!("Decreasing voltage to {}mv", millivolts);
-        modify_reg!(ral::dcdc, dcdc, REG3, TRG: reg3_trg_mv);
-        while read_reg!(ral::dcdc, dcdc, REG0, STS_DC_OK) == 0 {
-            #[allow(deprecated)]
-            core::sync::atomic::spin_loop_hint();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_arm_hz, sequence, OperatingPoint, OperatingPointError, Step};
+
+    #[test]
+    fn preset_operating_points() {
+        for point in &[
+            OperatingPoint::OVERDRIVE,
+            OperatingPoint::FULL_SPEED,
+            OperatingPoint::LOW_POWER_RUN,
+        ] {
+            let dividers = point.dividers().unwrap();
+            assert_eq!(dividers.arm_hz(), point.core_hz, "{:?}", point);
         }
     }
 
-    (hz, hz / div_ipg)
-}
-
-/// Selects an alternative clock so that we can modify the main
-/// system clock.
-#[inline(always)]
-fn select_alt_clock(ccm: &ral::ccm::Instance, ccm_analog: &ral::ccm_analog::Instance) {
-    use ral::ccm::CBCDR::PERIPH_CLK_SEL;
-    let periph_clk_sel = read_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK_SEL);
-    if PERIPH_CLK_SEL::RW::PERIPH_CLK_SEL_0 == periph_clk_sel {
-        log::debug!("Choosing alternative clock before reconfiguring ARM PLL...");
-        use ral::ccm::{CBCDR::PERIPH_CLK2_PODF, CBCMR::PERIPH_CLK2_SEL};
-
-        let (usb_pll_enable, usb_pll_en_usb_clks, usb_pll_power, usb_pll_lock) = read_reg!(
-            ral::ccm_analog,
-            ccm_analog,
-            PLL_USB1,
-            ENABLE,
-            EN_USB_CLKS,
-            POWER,
-            LOCK
-        );
-        let (sel, div) = if usb_pll_enable > 0
-            && usb_pll_en_usb_clks > 0
-            && usb_pll_power > 0
-            && usb_pll_lock > 0
-        {
-            log::debug!("Using USB PLL, divided down to 120MHz");
-            (
-                PERIPH_CLK2_SEL::RW::PERIPH_CLK2_SEL_0,
-                PERIPH_CLK2_PODF::RW::PERIPH_CLK2_PODF_3,
-            )
-        } else {
-            log::debug!("USB PLL is off; using 24MHz oscillator");
-            (
-                PERIPH_CLK2_SEL::RW::PERIPH_CLK2_SEL_1,
-                PERIPH_CLK2_PODF::RW::PERIPH_CLK2_PODF_0,
-            )
+    #[test]
+    fn unsupported_operating_points() {
+        let point = |core_hz, vdd_soc_mv| OperatingPoint {
+            core_hz,
+            vdd_soc_mv,
         };
-        modify_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK2_PODF: div);
-        modify_reg!(ral::ccm, ccm, CBCMR, PERIPH_CLK2_SEL: sel);
-        while read_reg!(ral::ccm, ccm, CDHIPR, PERIPH2_CLK_SEL_BUSY) > 0 {
-            #[allow(deprecated)]
-            core::sync::atomic::spin_loop_hint();
-        }
+        assert_eq!(
+            point(600_000_000, 1150).dividers(),
+            Err(OperatingPointError::Unsupported)
+        );
+        assert_eq!(
+            point(132_000_000, 1000).dividers(),
+            Err(OperatingPointError::Unsupported)
+        );
+        assert_eq!(
+            point(816_000_000, 1300).dividers(),
+            Err(OperatingPointError::Unsupported)
+        );
+        assert_eq!(
+            point(24_000_000, 900).dividers(),
+            Err(OperatingPointError::InvalidVoltage)
+        );
+        assert_eq!(
+            point(24_000_000, 1010).dividers(),
+            Err(OperatingPointError::InvalidVoltage)
+        );
+        assert_eq!(
+            point(600_000_000, 1325).dividers(),
+            Err(OperatingPointError::InvalidVoltage)
+        );
+        // No dividers reach 599MHz
+        assert_eq!(
+            point(599_000_000, 1250).dividers(),
+            Err(OperatingPointError::Unsupported)
+        );
+        assert!(point(132_000_000, 1150).dividers().is_ok());
+        assert!(point(600_000_000, 1300).dividers().is_ok());
+        assert_eq!(max_arm_hz(925), 24_000_000);
+    }
 
-        modify_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK_SEL: 1);
-        while read_reg!(ral::ccm, ccm, CDHIPR, PERIPH_CLK_SEL_BUSY) > 0 {
-            #[allow(deprecated)]
-            core::sync::atomic::spin_loop_hint();
-        }
-    } else {
-        log::debug!("Already running from PERIPH2_CLK2");
+    #[test]
+    fn transition_order() {
+        // Speeding up: raise the voltage first
+        assert_eq!(sequence(1150, 1250), [Step::Voltage, Step::Frequency]);
+        assert_eq!(sequence(950, 1150), [Step::Voltage, Step::Frequency]);
+        // Slowing down: lower the voltage last
+        assert_eq!(sequence(1250, 1150), [Step::Frequency, Step::Voltage]);
+        assert_eq!(sequence(1150, 950), [Step::Frequency, Step::Voltage]);
+        // Same voltage
+        assert_eq!(sequence(1150, 1150), [Step::Frequency, Step::Voltage]);
     }
 }
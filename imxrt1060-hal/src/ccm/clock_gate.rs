@@ -0,0 +1,528 @@
+//! Peripheral clock gates
+//!
+//! Each type in this module names a peripheral's clock gates. Use the type with
+//! [`Handle::gate()`](../struct.Handle.html#method.gate) to gate, or ungate, the peripheral's
+//! clocks, and with [`Handle::gate_setting()`](../struct.Handle.html#method.gate_setting) to
+//! query the gate.
+//!
+//! A peripheral's `clock()` method ungates its clocks. Gate a peripheral that you're
+//! no longer using to save power. Don't gate a peripheral that's still in use; accessing
+//! a gated peripheral's registers faults.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::{clock_gate::Lpuart2, GateSetting};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! peripherals.ccm.handle.gate::<Lpuart2>(GateSetting::Off);
+//! assert_eq!(peripherals.ccm.handle.gate_setting::<Lpuart2>(), GateSetting::Off);
+//! assert!(!peripherals.ccm.handle.is_enabled::<Lpuart2>());
+//! ```
+//!
+//! To set many gates at once, like before you enter a low-power mode, build a
+//! [`Profile`](struct.Profile.html), and apply it with
+//! [`Handle::apply_low_power_profile()`](../struct.Handle.html#method.apply_low_power_profile).
+//!
+//! Libraries that drive a peripheral through the RAL can check
+//! [`Handle::is_enabled()`](../struct.Handle.html#method.is_enabled) before they
+//! touch the peripheral's registers.
+
+use super::Handle;
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg};
+
+/// A clock gate setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum GateSetting {
+    /// The clock is off in all modes
+    Off = 0b00,
+    /// The clock is on in run mode, and off in WAIT and STOP modes
+    Run = 0b01,
+    /// The clock is on in run and WAIT modes, and off in STOP mode
+    RunAndWait = 0b11,
+}
+
+impl GateSetting {
+    fn from_field(field: u32) -> Self {
+        match field {
+            0b00 => GateSetting::Off,
+            0b01 => GateSetting::Run,
+            // 0b10 is reserved
+            _ => GateSetting::RunAndWait,
+        }
+    }
+}
+
+/// A peripheral with clock gates
+///
+/// This trait is sealed; the types in the [`clock_gate`](index.html) module implement it.
+pub trait ClockGate: private::Sealed {}
+
+mod private {
+    pub trait Sealed {
+        /// `(CCGR register, CG field)` for each of the peripheral's gates
+        const GATES: &'static [(u8, u8)];
+    }
+}
+
+macro_rules! clock_gates {
+    ($($(#[$attr:meta])* $name:ident => [$(($ccgr:literal, $cg:literal)),+]),+ $(,)?) => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug)]
+            pub enum $name {}
+            impl private::Sealed for $name {
+                const GATES: &'static [(u8, u8)] = &[$(($ccgr, $cg)),+];
+            }
+            impl ClockGate for $name {}
+        )+
+
+        /// Every gate in this module
+        const ALL_GATES: &[(u8, u8)] = &[$($(($ccgr, $cg),)+)+];
+    };
+}
+
+// The CCGR register and CG field of every clock gate. See the clock gating
+// table in the CCM chapter of the reference manual.
+clock_gates! {
+    /// DMA controller and DMA multiplexer
+    Dma => [(5, 3)],
+    /// GPT1 bus and serial clocks
+    Gpt1 => [(1, 10), (1, 11)],
+    /// GPT2 bus and serial clocks
+    Gpt2 => [(0, 12), (0, 13)],
+    /// Periodic interrupt timer
+    Pit => [(1, 6)],
+    /// ADC1
+    Adc1 => [(1, 8)],
+    /// ADC2
+    Adc2 => [(1, 4)],
+    /// LPI2C1
+    Lpi2c1 => [(2, 3)],
+    /// LPI2C2
+    Lpi2c2 => [(2, 4)],
+    /// LPI2C3
+    Lpi2c3 => [(2, 5)],
+    /// LPI2C4
+    Lpi2c4 => [(6, 12)],
+    /// LPSPI1
+    Lpspi1 => [(1, 0)],
+    /// LPSPI2
+    Lpspi2 => [(1, 1)],
+    /// LPSPI3
+    Lpspi3 => [(1, 2)],
+    /// LPSPI4
+    Lpspi4 => [(1, 3)],
+    /// LPUART1
+    Lpuart1 => [(5, 12)],
+    /// LPUART2
+    Lpuart2 => [(0, 14)],
+    /// LPUART3
+    Lpuart3 => [(0, 6)],
+    /// LPUART4
+    Lpuart4 => [(1, 12)],
+    /// LPUART5
+    Lpuart5 => [(3, 1)],
+    /// LPUART6
+    Lpuart6 => [(3, 3)],
+    /// LPUART7
+    Lpuart7 => [(5, 13)],
+    /// LPUART8
+    Lpuart8 => [(6, 7)],
+    /// FlexPWM1
+    Pwm1 => [(4, 8)],
+    /// FlexPWM2
+    Pwm2 => [(4, 9)],
+    /// FlexPWM3
+    Pwm3 => [(4, 10)],
+    /// FlexPWM4
+    Pwm4 => [(4, 11)],
+    /// SAI1
+    Sai1 => [(5, 9)],
+    /// Secure real-time clock, in the SNVS low-power domain
+    Srtc => [(5, 15)],
+    /// True random number generator
+    Trng => [(6, 6)],
+    /// Trace root clock
+    Trace => [(0, 11)],
+    /// ENET1 MAC
+    Enet => [(1, 5)],
+}
+
+/// The gates of each LPUART, for drivers that know their instance by number
+pub(crate) const LPUART: [&[(u8, u8)]; 8] = [
+    <Lpuart1 as private::Sealed>::GATES,
+    <Lpuart2 as private::Sealed>::GATES,
+    <Lpuart3 as private::Sealed>::GATES,
+    <Lpuart4 as private::Sealed>::GATES,
+    <Lpuart5 as private::Sealed>::GATES,
+    <Lpuart6 as private::Sealed>::GATES,
+    <Lpuart7 as private::Sealed>::GATES,
+    <Lpuart8 as private::Sealed>::GATES,
+];
+/// The gates of each LPSPI
+pub(crate) const LPSPI: [&[(u8, u8)]; 4] = [
+    <Lpspi1 as private::Sealed>::GATES,
+    <Lpspi2 as private::Sealed>::GATES,
+    <Lpspi3 as private::Sealed>::GATES,
+    <Lpspi4 as private::Sealed>::GATES,
+];
+/// The gates of each LPI2C
+pub(crate) const LPI2C: [&[(u8, u8)]; 4] = [
+    <Lpi2c1 as private::Sealed>::GATES,
+    <Lpi2c2 as private::Sealed>::GATES,
+    <Lpi2c3 as private::Sealed>::GATES,
+    <Lpi2c4 as private::Sealed>::GATES,
+];
+/// The gates of each FlexPWM
+pub(crate) const PWM: [&[(u8, u8)]; 4] = [
+    <Pwm1 as private::Sealed>::GATES,
+    <Pwm2 as private::Sealed>::GATES,
+    <Pwm3 as private::Sealed>::GATES,
+    <Pwm4 as private::Sealed>::GATES,
+];
+
+/// Returns `true` if every gate in `gates` is on
+///
+/// `read_ccgr` reads a CCGR register.
+fn gates_on(gates: &[(u8, u8)], read_ccgr: impl Fn(u8) -> u32) -> bool {
+    gates
+        .iter()
+        .all(|&(ccgr, cg)| (read_ccgr(ccgr) >> (2 * cg)) & 0b11 != GateSetting::Off as u32)
+}
+
+/// Returns `true` if every gate in `gates` is on
+///
+/// Unlike [`Handle::is_enabled()`](../struct.Handle.html#method.is_enabled), this doesn't
+/// need the handle, so that drivers can check their clocks when they're constructed.
+pub(crate) fn gates_clocked(gates: &[(u8, u8)]) -> bool {
+    // Safety: the CCM block is always valid, and reading a CCGR has no side effects
+    let ccm = unsafe { &*ral::ccm::CCM };
+    gates_on(gates, |ccgr| read_ccgr(ccm, ccgr))
+}
+
+/// Returns `true` if peripheral `P`'s clocks are on
+pub(crate) fn is_clocked<P: ClockGate>() -> bool {
+    gates_clocked(P::GATES)
+}
+
+/// Debug-asserts that a peripheral's clocks are on
+///
+/// Give the peripheral's gate type, like `Dma`, or an instance table and index, like
+/// `LPUART[M::USIZE - 1]`. Drivers use this in their constructors, so that a missing
+/// `clock()` call shows up as an assertion, instead of a fault on the first register
+/// access.
+macro_rules! debug_assert_clocked {
+    ($table:ident[$idx:expr]) => {
+        debug_assert!(
+            $crate::ccm::clock_gate::gates_clocked($crate::ccm::clock_gate::$table[$idx]),
+            "The {}{} clock gate is off",
+            stringify!($table),
+            $idx + 1
+        )
+    };
+    ($gate:ty) => {
+        debug_assert!(
+            $crate::ccm::clock_gate::is_clocked::<$gate>(),
+            concat!("The ", stringify!($gate), " clock gate is off")
+        )
+    };
+}
+pub(crate) use debug_assert_clocked;
+
+/// The number of CCGR registers
+const CCGR_COUNT: usize = 7;
+
+/// Clock gate settings for many peripherals
+///
+/// A profile only changes the gates that you select. Gates that aren't in the profile,
+/// including the gates for memories and the boot flash, keep their settings. Build a
+/// profile, then apply it with
+/// [`Handle::apply_low_power_profile()`](../struct.Handle.html#method.apply_low_power_profile).
+///
+/// Keep the DMA and LPUART2 clocks on in WAIT, and turn off the other peripherals' clocks
+/// in WAIT:
+///
+/// ```no_run
+/// use imxrt1060_hal::ccm::{clock_gate::{Dma, Lpuart2, Profile}, GateSetting};
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let profile = Profile::peripherals(GateSetting::Run)
+///     .gate::<Dma>(GateSetting::RunAndWait)
+///     .gate::<Lpuart2>(GateSetting::RunAndWait);
+/// let previous = peripherals.ccm.handle.apply_low_power_profile(profile);
+///
+/// // Enter WAIT...
+///
+/// peripherals.ccm.handle.apply_low_power_profile(previous);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// The CG fields that the profile changes, per CCGR register
+    mask: [u32; CCGR_COUNT],
+    /// The CG field values, per CCGR register
+    ccgr: [u32; CCGR_COUNT],
+}
+
+impl Profile {
+    /// A profile that doesn't change any gates
+    pub const UNCHANGED: Profile = Profile {
+        mask: [0; CCGR_COUNT],
+        ccgr: [0; CCGR_COUNT],
+    };
+
+    /// A profile that sets the gates of every peripheral in this module to `setting`
+    pub const fn peripherals(setting: GateSetting) -> Self {
+        Profile::UNCHANGED.with_gates(ALL_GATES, setting)
+    }
+
+    /// Set peripheral `P`'s gates to `setting`
+    pub const fn gate<P: ClockGate>(self, setting: GateSetting) -> Self {
+        self.with_gates(P::GATES, setting)
+    }
+
+    const fn with_gates(mut self, gates: &[(u8, u8)], setting: GateSetting) -> Self {
+        let mut idx = 0;
+        while idx < gates.len() {
+            let (ccgr, cg) = gates[idx];
+            let shift = 2 * cg as u32;
+            self.mask[ccgr as usize] |= 0b11 << shift;
+            self.ccgr[ccgr as usize] =
+                (self.ccgr[ccgr as usize] & !(0b11 << shift)) | ((setting as u32) << shift);
+            idx += 1;
+        }
+        self
+    }
+
+    /// Returns the new value of CCGR register `ccgr`, given its current value
+    fn apply(&self, ccgr: usize, current: u32) -> u32 {
+        (current & !self.mask[ccgr]) | self.ccgr[ccgr]
+    }
+}
+
+/// Apply `f` to CCGR register `ccgr`
+fn modify_ccgr(ccm: &ral::ccm::Instance, ccgr: u8, f: impl FnOnce(u32) -> u32) {
+    match ccgr {
+        0 => modify_reg!(ral::ccm, ccm, CCGR0, f),
+        1 => modify_reg!(ral::ccm, ccm, CCGR1, f),
+        2 => modify_reg!(ral::ccm, ccm, CCGR2, f),
+        3 => modify_reg!(ral::ccm, ccm, CCGR3, f),
+        4 => modify_reg!(ral::ccm, ccm, CCGR4, f),
+        5 => modify_reg!(ral::ccm, ccm, CCGR5, f),
+        6 => modify_reg!(ral::ccm, ccm, CCGR6, f),
+        _ => unreachable!("CCGR{} does not exist", ccgr),
+    }
+}
+
+/// Read CCGR register `ccgr`
+fn read_ccgr(ccm: &ral::ccm::RegisterBlock, ccgr: u8) -> u32 {
+    match ccgr {
+        0 => read_reg!(ral::ccm, ccm, CCGR0),
+        1 => read_reg!(ral::ccm, ccm, CCGR1),
+        2 => read_reg!(ral::ccm, ccm, CCGR2),
+        3 => read_reg!(ral::ccm, ccm, CCGR3),
+        4 => read_reg!(ral::ccm, ccm, CCGR4),
+        5 => read_reg!(ral::ccm, ccm, CCGR5),
+        6 => read_reg!(ral::ccm, ccm, CCGR6),
+        _ => unreachable!("CCGR{} does not exist", ccgr),
+    }
+}
+
+impl Handle {
+    /// Set the clock gates for peripheral `P`
+    ///
+    /// See the [`clock_gate`](clock_gate/index.html) module for the peripherals.
+    pub fn gate<P: ClockGate>(&mut self, setting: GateSetting) {
+        self.set_gates(P::GATES, setting);
+    }
+
+    /// Set the clock gates `gates`, one of the instance tables in this module
+    pub(crate) fn set_gates(&mut self, gates: &[(u8, u8)], setting: GateSetting) {
+        for &(ccgr, cg) in gates {
+            let shift = 2 * cg as u32;
+            modify_ccgr(&self.base, ccgr, |r| {
+                (r & !(0b11 << shift)) | ((setting as u32) << shift)
+            });
+        }
+    }
+
+    /// Returns the clock gate setting for peripheral `P`
+    ///
+    /// If the peripheral has more than one gate, this returns the setting of the
+    /// first gate.
+    pub fn gate_setting<P: ClockGate>(&self) -> GateSetting {
+        let (ccgr, cg) = P::GATES[0];
+        GateSetting::from_field((read_ccgr(&self.base, ccgr) >> (2 * cg)) & 0b11)
+    }
+
+    /// Write all clock gates from a profile, and return the previous gates
+    ///
+    /// The returned profile holds every CCGR register, so applying it restores all gates
+    /// to their settings before this call. Gate a peripheral only when you're not using
+    /// it. See [`Profile`](clock_gate/struct.Profile.html) for more information.
+    pub fn apply_low_power_profile(&mut self, profile: Profile) -> Profile {
+        let mut previous = Profile {
+            mask: [u32::max_value(); CCGR_COUNT],
+            ccgr: [0; CCGR_COUNT],
+        };
+        for ccgr in 0..CCGR_COUNT {
+            previous.ccgr[ccgr] = read_ccgr(&self.base, ccgr as u8);
+            modify_ccgr(&self.base, ccgr as u8, |r| profile.apply(ccgr, r));
+        }
+        previous
+    }
+
+    /// Returns `true` if peripheral `P`'s clocks are on
+    ///
+    /// A peripheral with more than one gate is on if all of its gates are on.
+    pub fn is_enabled<P: ClockGate>(&self) -> bool {
+        gates_on(P::GATES, |ccgr| read_ccgr(&self.base, ccgr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::private::Sealed;
+    use super::*;
+
+    #[test]
+    fn gate_table() {
+        fn gates<P: ClockGate>() -> &'static [(u8, u8)] {
+            P::GATES
+        }
+        assert_eq!(gates::<Dma>(), &[(5, 3)]);
+        assert_eq!(gates::<Gpt1>(), &[(1, 10), (1, 11)]);
+        assert_eq!(gates::<Gpt2>(), &[(0, 12), (0, 13)]);
+        assert_eq!(gates::<Pit>(), &[(1, 6)]);
+        assert_eq!(gates::<Adc1>(), &[(1, 8)]);
+        assert_eq!(gates::<Adc2>(), &[(1, 4)]);
+        assert_eq!(gates::<Lpi2c1>(), &[(2, 3)]);
+        assert_eq!(gates::<Lpi2c2>(), &[(2, 4)]);
+        assert_eq!(gates::<Lpi2c3>(), &[(2, 5)]);
+        assert_eq!(gates::<Lpi2c4>(), &[(6, 12)]);
+        assert_eq!(gates::<Lpspi1>(), &[(1, 0)]);
+        assert_eq!(gates::<Lpspi2>(), &[(1, 1)]);
+        assert_eq!(gates::<Lpspi3>(), &[(1, 2)]);
+        assert_eq!(gates::<Lpspi4>(), &[(1, 3)]);
+        assert_eq!(gates::<Lpuart1>(), &[(5, 12)]);
+        assert_eq!(gates::<Lpuart2>(), &[(0, 14)]);
+        assert_eq!(gates::<Lpuart3>(), &[(0, 6)]);
+        assert_eq!(gates::<Lpuart4>(), &[(1, 12)]);
+        assert_eq!(gates::<Lpuart5>(), &[(3, 1)]);
+        assert_eq!(gates::<Lpuart6>(), &[(3, 3)]);
+        assert_eq!(gates::<Lpuart7>(), &[(5, 13)]);
+        assert_eq!(gates::<Lpuart8>(), &[(6, 7)]);
+        assert_eq!(gates::<Pwm1>(), &[(4, 8)]);
+        assert_eq!(gates::<Pwm2>(), &[(4, 9)]);
+        assert_eq!(gates::<Pwm3>(), &[(4, 10)]);
+        assert_eq!(gates::<Pwm4>(), &[(4, 11)]);
+        assert_eq!(gates::<Sai1>(), &[(5, 9)]);
+        assert_eq!(gates::<Srtc>(), &[(5, 15)]);
+        assert_eq!(gates::<Trng>(), &[(6, 6)]);
+        assert_eq!(gates::<Trace>(), &[(0, 11)]);
+        assert_eq!(gates::<Enet>(), &[(1, 5)]);
+    }
+
+    #[test]
+    fn instance_gates() {
+        // The reference manual's CCGR register and CG field of each instance
+        let lpuart = [
+            (5, 12),
+            (0, 14),
+            (0, 6),
+            (1, 12),
+            (3, 1),
+            (3, 3),
+            (5, 13),
+            (6, 7),
+        ];
+        let lpspi = [(1, 0), (1, 1), (1, 2), (1, 3)];
+        let lpi2c = [(2, 3), (2, 4), (2, 5), (6, 12)];
+        let pwm = [(4, 8), (4, 9), (4, 10), (4, 11)];
+        let tables: [(&[&[(u8, u8)]], &[(u8, u8)]); 4] = [
+            (&LPUART, &lpuart),
+            (&LPSPI, &lpspi),
+            (&LPI2C, &lpi2c),
+            (&PWM, &pwm),
+        ];
+
+        // Gating an instance clears its CG field, and nothing else
+        for (gates, positions) in tables.iter() {
+            assert_eq!(gates.len(), positions.len());
+            for (gates, &(ccgr, cg)) in gates.iter().zip(positions.iter()) {
+                let off = Profile::UNCHANGED.with_gates(gates, GateSetting::Off);
+                for reg in 0..CCGR_COUNT {
+                    let expected = if reg == usize::from(ccgr) {
+                        !(0b11 << (2 * cg))
+                    } else {
+                        u32::max_value()
+                    };
+                    assert_eq!(off.apply(reg, u32::max_value()), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn profiles() {
+        let profile = Profile::UNCHANGED
+            .gate::<Gpt1>(GateSetting::Run)
+            .gate::<Dma>(GateSetting::RunAndWait)
+            .gate::<Lpuart2>(GateSetting::Off);
+        // GPT1 is CCGR1 CG10 and CG11
+        assert_eq!(profile.apply(1, 0), 0b0101 << 20);
+        assert_eq!(profile.apply(1, u32::max_value()), !(0b1010 << 20));
+        // DMA is CCGR5 CG3
+        assert_eq!(profile.apply(5, 0), 0b11 << 6);
+        // LPUART2 is CCGR0 CG14
+        assert_eq!(profile.apply(0, u32::max_value()), !(0b11 << 28));
+        // Other registers don't change
+        assert_eq!(profile.apply(6, 0x1234_5678), 0x1234_5678);
+        // A later setting replaces an earlier setting
+        assert_eq!(profile.gate::<Dma>(GateSetting::Run).apply(5, 0), 0b01 << 6);
+    }
+
+    #[test]
+    fn peripheral_profile() {
+        let profile = Profile::peripherals(GateSetting::Off);
+        for &(ccgr, cg) in ALL_GATES {
+            let field = 0b11 << (2 * cg);
+            assert_eq!(profile.apply(ccgr as usize, u32::max_value()) & field, 0);
+        }
+        // OCRAM (CCGR3 CG14) and FlexSPI (CCGR6 CG5) stay on
+        assert_ne!(profile.apply(3, u32::max_value()) & (0b11 << 28), 0);
+        assert_ne!(profile.apply(6, u32::max_value()) & (0b11 << 10), 0);
+    }
+
+    #[test]
+    fn const_profile() {
+        const WAIT: Profile =
+            Profile::peripherals(GateSetting::Run).gate::<Dma>(GateSetting::RunAndWait);
+        let profile = Profile::peripherals(GateSetting::Run).gate::<Dma>(GateSetting::RunAndWait);
+        assert_eq!(WAIT, profile);
+        // DMA is CCGR5 CG3
+        assert_eq!(WAIT.apply(5, 0) & (0b11 << 6), 0b11 << 6);
+    }
+
+    #[test]
+    fn gates_on_off() {
+        // CCGR1 with GPT1 bus clock (CG10) on, and serial clock (CG11) off
+        let ccgr = |ccgr| if ccgr == 1 { 0b11 << 20 } else { 0 };
+        assert!(!gates_on(<Gpt1 as Sealed>::GATES, ccgr));
+        let ccgr = |ccgr| if ccgr == 1 { 0b0111 << 20 } else { 0 };
+        assert!(gates_on(<Gpt1 as Sealed>::GATES, ccgr));
+        assert!(!gates_on(<Gpt2 as Sealed>::GATES, ccgr));
+        let ccgr = |_| u32::max_value();
+        assert!(gates_on(<Dma as Sealed>::GATES, ccgr));
+    }
+
+    #[test]
+    fn gate_settings() {
+        assert_eq!(GateSetting::from_field(0), GateSetting::Off);
+        assert_eq!(GateSetting::from_field(1), GateSetting::Run);
+        assert_eq!(GateSetting::from_field(3), GateSetting::RunAndWait);
+        assert_eq!(GateSetting::Off as u32, 0b00);
+        assert_eq!(GateSetting::Run as u32, 0b01);
+        assert_eq!(GateSetting::RunAndWait as u32, 0b11);
+    }
+}
@@ -0,0 +1,410 @@
+//! Computes root clock frequencies from the live CCM configuration
+//!
+//! The computation is separate from the register reads, so that we can test
+//! it on the host.
+
+use super::{math, osc, ArmFrequency, Frequency, Handle, IPGFrequency, OSCILLATOR_FREQUENCY};
+use imxrt_ral as ral;
+use ral::read_reg;
+
+/// A snapshot of the clock tree's root frequencies
+///
+/// Acquire a snapshot with [`Handle::frequencies()`](struct.Handle.html#method.frequencies).
+/// A clock that's gated, powered down, or fed by a gated source reads as 0Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frequencies {
+    /// The ARM core clock, which is the same as the AHB clock
+    pub arm: ArmFrequency,
+    /// The AHB clock
+    pub ahb: Frequency,
+    /// The IPG clock, derived from the AHB clock
+    pub ipg: IPGFrequency,
+    /// The periodic clock, which feeds the PIT and GPT timers
+    pub perclk: Frequency,
+    /// The root clock for all UART peripherals
+    pub uart: Frequency,
+    /// The root clock for all SPI peripherals
+    pub lpspi: Frequency,
+    /// The root clock for all I2C peripherals
+    pub lpi2c: Frequency,
+    /// The source of the 24MHz reference clock
+    ///
+    /// The frequencies assume a 24MHz reference clock. When the RC oscillator drives
+    /// the reference clock, they're only approximations. See the
+    /// [`osc`](osc/index.html) module for more information.
+    pub oscillator: osc::OscSource,
+}
+
+impl Handle {
+    /// Read the clock configuration, and compute the root clock frequencies
+    ///
+    /// The snapshot reflects the registers, no matter who configured them. That
+    /// includes a bootloader that ran before the HAL.
+    ///
+    /// ```no_run
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let frequencies = peripherals.ccm.handle.frequencies();
+    /// log::info!("IPG clock: {}Hz", frequencies.ipg.hz());
+    /// ```
+    pub fn frequencies(&self) -> Frequencies {
+        Settings::read(self).frequencies()
+    }
+}
+
+/// A phase-locked loop's configuration
+#[derive(Debug, Clone, Copy, Default)]
+struct Pll {
+    /// The loop divider that multiplies the 24MHz reference
+    mult: u32,
+    /// Fractional multiplier numerator
+    num: u32,
+    /// Fractional multiplier denominator; 0 if there's no fractional part
+    denom: u32,
+    /// The PLL outputs its reference clock
+    bypass: bool,
+    /// The PLL is powered down, or its output is disabled
+    off: bool,
+}
+
+impl Pll {
+    fn hz(&self) -> u32 {
+        let osc = OSCILLATOR_FREQUENCY.0;
+        if self.off {
+            0
+        } else if self.bypass {
+            osc
+        } else {
+            math::pll_hz(osc, self.mult, self.num, self.denom)
+        }
+    }
+}
+
+/// PFD output frequency, given the PLL frequency, and the PFD's FRAC value
+///
+/// Returns 0 if the PFD is gated.
+fn pfd_hz(pll_hz: u32, frac: u32, gated: bool) -> u32 {
+    if gated {
+        0
+    } else {
+        math::pfd_hz(pll_hz, frac)
+    }
+}
+
+/// Decode the FRAC and CLKGATE fields of a PFD register
+fn pfds(pll_hz: u32, reg: u32) -> [u32; 4] {
+    let mut pfds = [0; 4];
+    for (idx, pfd) in pfds.iter_mut().enumerate() {
+        let field = reg >> (8 * idx);
+        *pfd = pfd_hz(pll_hz, field & 0x3F, field & 0x80 != 0);
+    }
+    pfds
+}
+
+/// The clock multiplexer and divider settings that affect the root clocks
+///
+/// Dividers are the raw register values, which are one less than the divisor.
+#[derive(Debug, Clone, Copy, Default)]
+struct Settings {
+    /// ARM PLL
+    pll1: Pll,
+    /// System PLL
+    pll2: Pll,
+    /// USB1 PLL
+    pll3: Pll,
+    /// PFD_528 register
+    pll2_pfds: u32,
+    /// PFD_480 register
+    pll3_pfds: u32,
+    arm_podf: u32,
+    pre_periph_clk_sel: u32,
+    periph_clk_sel: u32,
+    periph_clk2_sel: u32,
+    periph_clk2_podf: u32,
+    ahb_podf: u32,
+    ipg_podf: u32,
+    perclk_clk_sel: u32,
+    perclk_podf: u32,
+    uart_clk_sel: u32,
+    uart_clk_podf: u32,
+    lpspi_clk_sel: u32,
+    lpspi_podf: u32,
+    lpi2c_clk_sel: u32,
+    lpi2c_clk_podf: u32,
+    oscillator: osc::OscSource,
+}
+
+impl Settings {
+    fn read(handle: &Handle) -> Self {
+        let (ccm, analog) = (&handle.base, &handle.analog);
+
+        let (mult, powerdown, enable, bypass) = read_reg!(
+            ral::ccm_analog,
+            analog,
+            PLL_ARM,
+            DIV_SELECT,
+            POWERDOWN,
+            ENABLE,
+            BYPASS
+        );
+        // Fout = Fin * DIV_SELECT / 2; the halving is a fractional part of the multiplier
+        let pll1 = Pll {
+            mult: mult / 2,
+            num: mult % 2,
+            denom: 2,
+            bypass: bypass != 0,
+            off: powerdown != 0 || enable == 0,
+        };
+
+        let (div_select, powerdown, enable, bypass) = read_reg!(
+            ral::ccm_analog,
+            analog,
+            PLL_SYS,
+            DIV_SELECT,
+            POWERDOWN,
+            ENABLE,
+            BYPASS
+        );
+        let pll2 = Pll {
+            mult: if div_select != 0 { 22 } else { 20 },
+            num: read_reg!(ral::ccm_analog, analog, PLL_SYS_NUM, A),
+            denom: read_reg!(ral::ccm_analog, analog, PLL_SYS_DENOM, B),
+            bypass: bypass != 0,
+            off: powerdown != 0 || enable == 0,
+        };
+
+        let (div_select, power, enable, bypass) = read_reg!(
+            ral::ccm_analog,
+            analog,
+            PLL_USB1,
+            DIV_SELECT,
+            POWER,
+            ENABLE,
+            BYPASS
+        );
+        let pll3 = Pll {
+            mult: if div_select != 0 { 22 } else { 20 },
+            num: 0,
+            denom: 0,
+            bypass: bypass != 0,
+            off: power == 0 || enable == 0,
+        };
+
+        let (periph_clk2_podf, periph_clk_sel, ahb_podf, ipg_podf) = read_reg!(
+            ral::ccm,
+            ccm,
+            CBCDR,
+            PERIPH_CLK2_PODF,
+            PERIPH_CLK_SEL,
+            AHB_PODF,
+            IPG_PODF
+        );
+        let (lpspi_podf, pre_periph_clk_sel, periph_clk2_sel, lpspi_clk_sel) = read_reg!(
+            ral::ccm,
+            ccm,
+            CBCMR,
+            LPSPI_PODF,
+            PRE_PERIPH_CLK_SEL,
+            PERIPH_CLK2_SEL,
+            LPSPI_CLK_SEL
+        );
+        let (perclk_clk_sel, perclk_podf) =
+            read_reg!(ral::ccm, ccm, CSCMR1, PERCLK_CLK_SEL, PERCLK_PODF);
+        let (uart_clk_sel, uart_clk_podf) =
+            read_reg!(ral::ccm, ccm, CSCDR1, UART_CLK_SEL, UART_CLK_PODF);
+        let (lpi2c_clk_sel, lpi2c_clk_podf) =
+            read_reg!(ral::ccm, ccm, CSCDR2, LPI2C_CLK_SEL, LPI2C_CLK_PODF);
+
+        Settings {
+            pll1,
+            pll2,
+            pll3,
+            pll2_pfds: read_reg!(ral::ccm_analog, analog, PFD_528),
+            pll3_pfds: read_reg!(ral::ccm_analog, analog, PFD_480),
+            arm_podf: read_reg!(ral::ccm, ccm, CACRR, ARM_PODF),
+            pre_periph_clk_sel,
+            periph_clk_sel,
+            periph_clk2_sel,
+            periph_clk2_podf,
+            ahb_podf,
+            ipg_podf,
+            perclk_clk_sel,
+            perclk_podf,
+            uart_clk_sel,
+            uart_clk_podf,
+            lpspi_clk_sel,
+            lpspi_podf,
+            lpi2c_clk_sel,
+            lpi2c_clk_podf,
+            oscillator: osc::read_osc(),
+        }
+    }
+
+    fn frequencies(&self) -> Frequencies {
+        let osc = OSCILLATOR_FREQUENCY.0;
+        let pll1 = self.pll1.hz();
+        let pll2 = self.pll2.hz();
+        let pll3 = self.pll3.hz();
+        let pll2_pfds = pfds(pll2, self.pll2_pfds);
+        let pll3_pfds = pfds(pll3, self.pll3_pfds);
+
+        let periph = if self.periph_clk_sel == 0 {
+            match self.pre_periph_clk_sel {
+                0 => pll2,
+                1 => pll2_pfds[2],
+                2 => pll2_pfds[0],
+                _ => math::divide(pll1, self.arm_podf),
+            }
+        } else {
+            let periph_clk2 = match self.periph_clk2_sel {
+                0 => pll3,
+                1 => osc,
+                // PLL2 bypass clock, which is the oscillator on this board design
+                _ => osc,
+            };
+            math::divide(periph_clk2, self.periph_clk2_podf)
+        };
+        let ahb = math::divide(periph, self.ahb_podf);
+        let ipg = math::divide(ahb, self.ipg_podf);
+
+        let perclk = math::perclk_hz(ipg, osc, self.perclk_clk_sel, self.perclk_podf);
+        let uart = math::uart_hz(pll3, osc, self.uart_clk_sel, self.uart_clk_podf);
+        let lpspi = math::divide(
+            match self.lpspi_clk_sel {
+                0 => pll3_pfds[1],
+                1 => pll3_pfds[0],
+                2 => pll2,
+                _ => pll2_pfds[2],
+            },
+            self.lpspi_podf,
+        );
+        let lpi2c = math::lpi2c_hz(pll3, osc, self.lpi2c_clk_sel, self.lpi2c_clk_podf);
+
+        Frequencies {
+            arm: ArmFrequency(Frequency(ahb)),
+            ahb: Frequency(ahb),
+            ipg: IPGFrequency(Frequency(ipg)),
+            perclk: Frequency(perclk),
+            uart: Frequency(uart),
+            lpspi: Frequency(lpspi),
+            lpi2c: Frequency(lpi2c),
+            oscillator: self.oscillator,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{osc, pfds, Pll, Settings};
+
+    fn pll(mult: u32) -> Pll {
+        Pll {
+            mult,
+            ..Pll::default()
+        }
+    }
+
+    #[test]
+    fn pll_frequencies() {
+        assert_eq!(pll(22).hz(), 528_000_000);
+        assert_eq!(pll(20).hz(), 480_000_000);
+        // ARM PLL, DIV_SELECT = 100
+        let pll1 = Pll {
+            mult: 50,
+            num: 0,
+            denom: 2,
+            ..Pll::default()
+        };
+        assert_eq!(pll1.hz(), 1_200_000_000);
+        let pll1 = Pll {
+            mult: 54,
+            num: 1,
+            denom: 2,
+            ..Pll::default()
+        };
+        assert_eq!(pll1.hz(), 1_308_000_000);
+        assert_eq!(
+            Pll {
+                bypass: true,
+                ..pll(22)
+            }
+            .hz(),
+            24_000_000
+        );
+        assert_eq!(
+            Pll {
+                off: true,
+                ..pll(22)
+            }
+            .hz(),
+            0
+        );
+    }
+
+    #[test]
+    fn pfd_frequencies() {
+        // PFD0 = 18, PFD1 = 24, PFD2 = 16 (gated), PFD3 = 35
+        let reg = 18 | (24 << 8) | ((16 | 0x80) << 16) | (35 << 24);
+        assert_eq!(
+            pfds(528_000_000, reg),
+            [528_000_000, 396_000_000, 0, 271_542_857]
+        );
+    }
+
+    /// Core at 600MHz from PLL1, IPG at 150MHz, PERCLK from IPG / 3,
+    /// UART and I2C from the oscillator, SPI from PLL2 / 8
+    #[test]
+    fn configured_tree() {
+        let settings = Settings {
+            pll1: Pll {
+                mult: 50,
+                num: 0,
+                denom: 2,
+                ..Pll::default()
+            },
+            pll2: pll(22),
+            pll3: pll(20),
+            arm_podf: 1,
+            pre_periph_clk_sel: 3,
+            periph_clk_sel: 0,
+            ahb_podf: 0,
+            ipg_podf: 3,
+            perclk_clk_sel: 0,
+            perclk_podf: 2,
+            uart_clk_sel: 1,
+            lpspi_clk_sel: 2,
+            lpspi_podf: 7,
+            lpi2c_clk_sel: 1,
+            lpi2c_clk_podf: 2,
+            ..Settings::default()
+        };
+        let frequencies = settings.frequencies();
+        assert_eq!(frequencies.arm.hz(), 600_000_000);
+        assert_eq!(frequencies.ahb.hz(), 600_000_000);
+        assert_eq!(frequencies.ipg.hz(), 150_000_000);
+        assert_eq!(frequencies.perclk.hz(), 50_000_000);
+        assert_eq!(frequencies.uart.hz(), 24_000_000);
+        assert_eq!(frequencies.lpspi.hz(), 66_000_000);
+        assert_eq!(frequencies.lpi2c.hz(), 8_000_000);
+    }
+
+    /// Running from the alternate clock while PLL1 is reconfigured
+    #[test]
+    fn alternate_clock() {
+        let settings = Settings {
+            pll3: pll(20),
+            periph_clk_sel: 1,
+            periph_clk2_sel: 0,
+            periph_clk2_podf: 3,
+            uart_clk_sel: 0,
+            lpi2c_clk_sel: 0,
+            ..Settings::default()
+        };
+        let frequencies = settings.frequencies();
+        assert_eq!(frequencies.arm.hz(), 120_000_000);
+        assert_eq!(frequencies.ipg.hz(), 120_000_000);
+        assert_eq!(frequencies.perclk.hz(), 120_000_000);
+        assert_eq!(frequencies.uart.hz(), 80_000_000);
+        assert_eq!(frequencies.lpi2c.hz(), 60_000_000);
+        assert_eq!(frequencies.oscillator, osc::OscSource::Xtal);
+    }
+}
@@ -0,0 +1,274 @@
+//! FlexSPI root clock
+//!
+//! The FlexSPI root clock drives FlexSPI1, which usually serves the external boot flash.
+//! If the processor executes code, or reads data, from the flash while the clock changes,
+//! it fetches garbage, and the system faults. [`flexspi_clock()`](fn.flexspi_clock.html)
+//! changes the clock from a function that runs from RAM, with interrupts disabled.
+//!
+//! The switching function lives in the `.fastrun` link section. Your linker script must
+//! place `.fastrun` in ITCM or OCRAM. Since a linker script mistake would put the function
+//! back into flash, `flexspi_clock()` checks the function's address before it calls the
+//! function, and returns an error if the function is in the FlexSPI address space.
+//!
+//! The switching function can't call functions from flash. It accesses registers with
+//! inline assembly, and has no other calls, so it stays in RAM at every `opt-level`,
+//! including the `opt-level = 0` of debug builds.
+//!
+//! # Example
+//!
+//! Raise the FlexSPI root clock to 480MHz / 4 = 120MHz.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::{flexspi::{self, FlexspiClockSelect}, pll3};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let hz = flexspi::flexspi_clock(
+//!     &mut peripherals.ccm.handle,
+//!     FlexspiClockSelect::Pll3Pfd0(pll3::MHZ_480),
+//!     4,
+//! )
+//! .unwrap();
+//! assert_eq!(hz.hz(), 120_000_000);
+//! ```
+
+use super::{pll2::PfdClock, pll3, Frequency, Handle, PLL3};
+use crate::iomuxc::consts::U2;
+use imxrt_ral as ral;
+use ral::read_reg;
+
+/// Largest FLEXSPI_PODF divider
+pub const MAX_FLEXSPI_PODF: u8 = 8;
+
+/// FlexSPI1 and FlexSPI2 AMBA address space
+const FLEXSPI_ADDRESSES: core::ops::Range<usize> = 0x6000_0000..0x7F80_0000;
+
+/// CCM_CSCMR1
+const CSCMR1: *mut u32 = 0x400F_C01C as *mut u32;
+/// CCM_CCGR6
+const CCGR6: *mut u32 = 0x400F_C080 as *mut u32;
+/// CCM_ANALOG_PFD_480
+const PFD_480: *mut u32 = 0x400D_80F0 as *mut u32;
+/// FLEXSPI_MCR0
+const FLEXSPI_MCR0: *mut u32 = 0x402A_8000 as *mut u32;
+/// FLEXSPI_STS0
+const FLEXSPI_STS0: *const u32 = 0x402A_80E0 as *const u32;
+
+// CSCMR1 fields
+const FLEXSPI_PODF_SHIFT: u32 = 23;
+const FLEXSPI_CLK_SEL_SHIFT: u32 = 29;
+const FLEXSPI_MASK: u32 = (0b111 << FLEXSPI_PODF_SHIFT) | (0b11 << FLEXSPI_CLK_SEL_SHIFT);
+/// CCGR6 CG5, the FlexSPI clock gate
+const FLEXSPI_GATE: u32 = 0b11 << 10;
+// PFD_480 fields
+const PFD0_FRAC: u32 = 0x3F;
+const PFD0_CLKGATE: u32 = 1 << 7;
+// MCR0 bits
+const SWRESET: u32 = 1 << 0;
+const MDIS: u32 = 1 << 1;
+/// STS0 SEQIDLE and ARBIDLE
+const IDLE: u32 = 0b11;
+
+/// FLEXSPI_CLK_SEL for PLL3, the alternate clock during the switch
+const PLL3_SW_CLK: u32 = 0b01;
+/// CSCMR1 fields for the alternate clock, PLL3 / 8
+const ALTERNATE: u32 = (PLL3_SW_CLK << FLEXSPI_CLK_SEL_SHIFT) | (0b111 << FLEXSPI_PODF_SHIFT);
+
+/// FlexSPI root clock sources
+///
+/// The FlexSPI root clock can also derive from the SEMC root clock, before its divider.
+/// The HAL doesn't support that source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FlexspiClockSelect {
+    /// USB1 PLL (PLL3)
+    Pll3,
+    /// PLL2 PFD2
+    ///
+    /// Acquire the clock from an ungated PFD with
+    /// [`Pfd::into_clock()`](../pll2/struct.Pfd.html#method.into_clock).
+    Pll2Pfd2(PfdClock<U2>),
+    /// PLL3 PFD0, configured to the provided frequency
+    ///
+    /// `flexspi_clock()` configures, and ungates, the PFD. Other root clocks that use PLL3
+    /// PFD0 change frequency, too.
+    Pll3Pfd0(pll3::Frequency),
+}
+
+impl FlexspiClockSelect {
+    fn clk_sel(self) -> u32 {
+        match self {
+            FlexspiClockSelect::Pll3 => PLL3_SW_CLK,
+            FlexspiClockSelect::Pll2Pfd2(_) => 0b10,
+            FlexspiClockSelect::Pll3Pfd0(_) => 0b11,
+        }
+    }
+}
+
+impl From<FlexspiClockSelect> for Frequency {
+    fn from(selection: FlexspiClockSelect) -> Self {
+        match selection {
+            FlexspiClockSelect::Pll3 => Frequency(PLL3::HZ),
+            FlexspiClockSelect::Pll2Pfd2(pfd) => pfd.frequency(),
+            FlexspiClockSelect::Pll3Pfd0(pfd) => pfd.into(),
+        }
+    }
+}
+
+/// Errors when changing the FlexSPI root clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlexspiClockError {
+    /// The divider is outside of 1 and [`MAX_FLEXSPI_PODF`](constant.MAX_FLEXSPI_PODF.html)
+    Divider,
+    /// PLL3, the alternate clock during the switch, isn't locked
+    ///
+    /// Enable PLL3 with [`pll3::PFD::enable()`](../pll3/struct.PFD.html#method.enable).
+    Pll3Off,
+    /// The switching function isn't in RAM
+    ///
+    /// Check that your linker script places the `.fastrun` section in ITCM or OCRAM.
+    NotInRam,
+}
+
+/// Select, and divide, the FlexSPI root clock
+///
+/// The FlexSPI root clock is `selection / podf`. With interrupts disabled,
+/// `flexspi_clock()` waits for FlexSPI1 to idle, and disables it. Then, it gates the clock,
+/// switches to the PLL3 clock, reprograms the PFD, switches to the new clock, and
+/// ungates the clock. Finally, it resets FlexSPI1, which clears the prefetch buffers.
+/// Returns the new FlexSPI root clock frequency.
+///
+/// Returns an error, without touching any clocks, if `podf` is out of range, if PLL3 is
+/// off, or if the switching function isn't in RAM.
+pub fn flexspi_clock(
+    handle: &mut Handle,
+    selection: FlexspiClockSelect,
+    podf: u8,
+) -> Result<Frequency, FlexspiClockError> {
+    if !(1..=MAX_FLEXSPI_PODF).contains(&podf) {
+        return Err(FlexspiClockError::Divider);
+    }
+    // PLL3 is the alternate clock, so it must run for every selection
+    let (_, analog) = handle.raw();
+    if read_reg!(ral::ccm_analog, analog, PLL_USB1, LOCK) == 0 {
+        return Err(FlexspiClockError::Pll3Off);
+    }
+    let switch: unsafe extern "C" fn(u32, u32) = switch_flexspi_clock;
+    if in_flexspi(switch as usize) {
+        return Err(FlexspiClockError::NotInRam);
+    }
+
+    let frac = match selection {
+        FlexspiClockSelect::Pll3Pfd0(pfd) => pfd.0 as u32,
+        _ => 0,
+    };
+    let cscmr1 =
+        (selection.clk_sel() << FLEXSPI_CLK_SEL_SHIFT) | ((podf as u32 - 1) << FLEXSPI_PODF_SHIFT);
+    cortex_m::interrupt::free(|_| {
+        // Safety: the handle gives us exclusive access to the CCM, and we're in a
+        // critical section. We checked that the function isn't in flash.
+        unsafe { switch(cscmr1, frac) };
+    });
+
+    let Frequency(hz) = Frequency::from(selection);
+    Ok(Frequency(hz / podf as u32))
+}
+
+/// Returns `true` if `address` is in the FlexSPI address space
+fn in_flexspi(address: usize) -> bool {
+    FLEXSPI_ADDRESSES.contains(&address)
+}
+
+/// Read the register at `address`
+///
+/// On the target, this is a single `ldr` that's always inlined, so it doesn't call
+/// `read_volatile()` from flash.
+#[inline(always)]
+unsafe fn read(address: *const u32) -> u32 {
+    #[cfg(target_arch = "arm")]
+    {
+        let value: u32;
+        core::arch::asm!(
+            "ldr {}, [{}]",
+            out(reg) value,
+            in(reg) address,
+            options(nostack, preserves_flags),
+        );
+        value
+    }
+    #[cfg(not(target_arch = "arm"))]
+    {
+        core::ptr::read_volatile(address)
+    }
+}
+
+/// Write `value` to the register at `address`
+///
+/// See [`read()`](fn.read.html).
+#[inline(always)]
+unsafe fn write(address: *mut u32, value: u32) {
+    #[cfg(target_arch = "arm")]
+    {
+        core::arch::asm!(
+            "str {}, [{}]",
+            in(reg) value,
+            in(reg) address,
+            options(nostack, preserves_flags),
+        );
+    }
+    #[cfg(not(target_arch = "arm"))]
+    {
+        core::ptr::write_volatile(address, value)
+    }
+}
+
+/// Switch the FlexSPI root clock
+///
+/// `cscmr1` has the new FLEXSPI_CLK_SEL and FLEXSPI_PODF fields. If `frac` is non-zero,
+/// it's the new PLL3 PFD0 fraction.
+///
+/// Keep this function free of calls, and of anything that could panic; those would
+/// land in flash. The register accesses are always inlined.
+///
+/// # Safety
+///
+/// Call from a critical section, from RAM. Everything this function touches must be
+/// outside of flash.
+#[inline(never)]
+#[link_section = ".fastrun"]
+unsafe extern "C" fn switch_flexspi_clock(cscmr1: u32, frac: u32) {
+    while read(FLEXSPI_STS0) & IDLE != IDLE {}
+    write(FLEXSPI_MCR0, read(FLEXSPI_MCR0) | MDIS);
+    write(CCGR6, read(CCGR6) & !FLEXSPI_GATE);
+
+    write(CSCMR1, (read(CSCMR1) & !FLEXSPI_MASK) | ALTERNATE);
+    if frac != 0 {
+        write(
+            PFD_480,
+            (read(PFD_480) & !(PFD0_FRAC | PFD0_CLKGATE)) | frac,
+        );
+    }
+    write(CSCMR1, (read(CSCMR1) & !FLEXSPI_MASK) | cscmr1);
+
+    write(CCGR6, read(CCGR6) | FLEXSPI_GATE);
+    write(FLEXSPI_MCR0, read(FLEXSPI_MCR0) & !MDIS);
+    write(FLEXSPI_MCR0, read(FLEXSPI_MCR0) | SWRESET);
+    while read(FLEXSPI_MCR0) & SWRESET != 0 {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::in_flexspi;
+
+    #[test]
+    fn flexspi_addresses() {
+        // ITCM, DTCM, and OCRAM
+        assert!(!in_flexspi(0x0000_1000));
+        assert!(!in_flexspi(0x2000_0000));
+        assert!(!in_flexspi(0x2020_0000));
+        // FlexSPI1, and FlexSPI2
+        assert!(in_flexspi(0x6000_0000));
+        assert!(in_flexspi(0x6000_2000));
+        assert!(in_flexspi(0x7000_0000));
+        assert!(!in_flexspi(0x7F80_0000));
+    }
+}
@@ -0,0 +1,228 @@
+//! Clock change notifications
+//!
+//! Drivers compute their dividers from a clock frequency when you construct them. If
+//! you later change the clock tree, the dividers are wrong. Register a
+//! [`ClockListener`](trait.ClockListener.html) with the CCM handle, and the HAL tells the
+//! listener when these functions change a root clock:
+//!
+//! - [`PLL1::set_arm_clock()`](../struct.PLL1.html#method.set_arm_clock)
+//! - [`PLL1::set_operating_point()`](../struct.PLL1.html#method.set_operating_point)
+//! - [`perclk::reconfigure()`](../perclk/fn.reconfigure.html)
+//!
+//! The HAL notifies listeners after the clocks change. It passes the frequencies before,
+//! and after, the change.
+//!
+//! The handle holds up to [`MAX_LISTENERS`](constant.MAX_LISTENERS.html) listeners, without
+//! allocating. Listeners live forever, so that the handle can't notify a dropped
+//! driver. To register a driver that you still want to use, keep it in a
+//! `Mutex<RefCell<Option<_>>>`, the same way you'd share it with an interrupt handler.
+//! The UART, I2C, and PIT drivers implement
+//! [`ClockListenerMut`](trait.ClockListenerMut.html), so their mutexes are listeners.
+//! The HAL borrows the driver in a critical section, so don't change the clocks while
+//! you're borrowing a registered driver.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use core::cell::RefCell;
+//! use cortex_m::interrupt::Mutex;
+//! use imxrt1060_hal::{ccm::perclk, pit::{channel, PIT}};
+//!
+//! static TIMER: Mutex<RefCell<Option<PIT<channel::_3>>>> = Mutex::new(RefCell::new(None));
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let (_, ipg_hz) = peripherals
+//!     .ccm
+//!     .pll1
+//!     .set_arm_clock(600_000_000, &mut peripherals.ccm.handle, &mut peripherals.dcdc)
+//!     .unwrap();
+//! let mut cfg = peripherals.ccm.perclk.configure(
+//!     &mut peripherals.ccm.handle,
+//!     perclk::PODF::DIVIDE_3,
+//!     perclk::CLKSEL::IPG(ipg_hz),
+//! );
+//! let (_, _, _, timer) = peripherals.pit.clock(&mut cfg);
+//! cortex_m::interrupt::free(|cs| *TIMER.borrow(cs).borrow_mut() = Some(timer));
+//! peripherals.ccm.handle.register_listener(&TIMER).unwrap();
+//!
+//! // The PIT keeps its period, even though PERCLK follows the IPG clock
+//! peripherals
+//!     .ccm
+//!     .pll1
+//!     .set_arm_clock(396_000_000, &mut peripherals.ccm.handle, &mut peripherals.dcdc)
+//!     .unwrap();
+//! ```
+
+use super::{Frequencies, Handle};
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+
+/// The most listeners that a handle holds
+pub const MAX_LISTENERS: usize = 8;
+
+/// A clock tree change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClocksChanged {
+    /// The frequencies before the change
+    pub old: Frequencies,
+    /// The frequencies after the change
+    pub new: Frequencies,
+}
+
+/// Learns about clock tree changes
+///
+/// Listeners are `Sync`, since an interrupt handler may change the clocks. See the
+/// [module documentation](index.html) for more information.
+pub trait ClockListener: Sync {
+    /// The clock tree changed
+    ///
+    /// `clocks_changed()` runs after the change, and before the function that changed the
+    /// clocks returns.
+    fn clocks_changed(&self, change: &ClocksChanged);
+}
+
+/// A driver that adapts to clock tree changes
+///
+/// A `Mutex<RefCell<Option<_>>>` that holds a `ClockListenerMut` is a
+/// [`ClockListener`](trait.ClockListener.html).
+pub trait ClockListenerMut {
+    /// The clock tree changed
+    ///
+    /// See [`ClockListener::clocks_changed()`](trait.ClockListener.html#tymethod.clocks_changed).
+    fn clocks_changed(&mut self, change: &ClocksChanged);
+}
+
+impl<T: ClockListenerMut + Send> ClockListener for Mutex<RefCell<Option<T>>> {
+    /// Borrows the driver in a critical section, and passes it the change
+    ///
+    /// Does nothing if the mutex doesn't hold a driver. Panics if the driver is
+    /// already borrowed.
+    fn clocks_changed(&self, change: &ClocksChanged) {
+        interrupt::free(|cs| {
+            if let Some(driver) = self.borrow(cs).borrow_mut().as_mut() {
+                driver.clocks_changed(change);
+            }
+        })
+    }
+}
+
+/// The handle already holds [`MAX_LISTENERS`](constant.MAX_LISTENERS.html) listeners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerError(());
+
+/// A fixed-size collection of listeners
+pub(super) struct Listeners<'a>([Option<&'a dyn ClockListener>; MAX_LISTENERS]);
+
+impl<'a> Listeners<'a> {
+    pub(super) fn new() -> Self {
+        Listeners([None; MAX_LISTENERS])
+    }
+
+    fn register(&mut self, listener: &'a dyn ClockListener) -> Result<(), ListenerError> {
+        let slot = self
+            .0
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ListenerError(()))?;
+        *slot = Some(listener);
+        Ok(())
+    }
+
+    /// Notify all listeners, in the order that they registered
+    fn notify(&self, change: &ClocksChanged) {
+        for listener in self.0.iter().flatten() {
+            listener.clocks_changed(change);
+        }
+    }
+}
+
+impl Handle {
+    /// Register a listener, which learns about clock tree changes
+    ///
+    /// Returns an error if the handle already holds
+    /// [`MAX_LISTENERS`](listener/constant.MAX_LISTENERS.html) listeners. See the
+    /// [`listener`](listener/index.html) module for more information.
+    pub fn register_listener(
+        &mut self,
+        listener: &'static dyn ClockListener,
+    ) -> Result<(), ListenerError> {
+        self.listeners.register(listener)
+    }
+
+    /// Notify the listeners if the frequencies changed since `old`
+    pub(super) fn notify_clocks_changed(&self, old: Frequencies) {
+        let new = self.frequencies();
+        if new != old {
+            self.listeners.notify(&ClocksChanged { old, new });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClockListener, ClocksChanged, ListenerError, Listeners, MAX_LISTENERS};
+    use crate::ccm::{osc::OscSource, ArmFrequency, Frequencies, Frequency, IPGFrequency};
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    /// Remembers the IPG frequencies from the last change
+    #[derive(Default)]
+    struct Fake {
+        old: AtomicU32,
+        new: AtomicU32,
+    }
+
+    impl Fake {
+        fn ipg(&self) -> (u32, u32) {
+            (
+                self.old.load(Ordering::Relaxed),
+                self.new.load(Ordering::Relaxed),
+            )
+        }
+    }
+
+    impl ClockListener for Fake {
+        fn clocks_changed(&self, change: &ClocksChanged) {
+            self.old.store(change.old.ipg.hz(), Ordering::Relaxed);
+            self.new.store(change.new.ipg.hz(), Ordering::Relaxed);
+        }
+    }
+
+    fn frequencies(arm: u32) -> Frequencies {
+        Frequencies {
+            arm: ArmFrequency(Frequency(arm)),
+            ahb: Frequency(arm),
+            ipg: IPGFrequency(Frequency(arm / 4)),
+            perclk: Frequency(arm / 4),
+            uart: Frequency(24_000_000),
+            lpspi: Frequency(0),
+            lpi2c: Frequency(0),
+            oscillator: OscSource::Xtal,
+        }
+    }
+
+    #[test]
+    fn listeners_see_old_and_new() {
+        let fakes: [Fake; 2] = Default::default();
+        let mut listeners = Listeners::new();
+        for fake in &fakes {
+            listeners.register(fake).unwrap();
+        }
+        listeners.notify(&ClocksChanged {
+            old: frequencies(600_000_000),
+            new: frequencies(396_000_000),
+        });
+        for fake in &fakes {
+            assert_eq!(fake.ipg(), (150_000_000, 99_000_000));
+        }
+    }
+
+    #[test]
+    fn listeners_are_bounded() {
+        let fake = Fake::default();
+        let mut listeners = Listeners::new();
+        for _ in 0..MAX_LISTENERS {
+            listeners.register(&fake).unwrap();
+        }
+        assert_eq!(listeners.register(&fake), Err(ListenerError(())));
+    }
+}
@@ -0,0 +1,367 @@
+//! Low-power WAIT and STOP modes
+//!
+//! [`enter_wait()`](fn.enter_wait.html) and [`enter_stop()`](fn.enter_stop.html) configure the
+//! CCM for the low-power mode, execute `WFI`, and restore the run mode when an interrupt
+//! wakes the processor. They implement the workaround for erratum ERR050143 (formerly
+//! ERR007265), which otherwise lets the SoC enter the low-power mode before the core
+//! executes `WFI`.
+//!
+//! In WAIT mode, the core clock stops, and peripherals keep running. Any enabled interrupt
+//! wakes the processor.
+//!
+//! In STOP mode, the CCM also stops the peripheral clocks, and the 24MHz oscillator
+//! powers down unless you keep it. Only the interrupts that you select in the
+//! [`StopConfig`](struct.StopConfig.html) wake the processor. The 32KHz domain, including the
+//! SRTC, keeps running. Peripherals that can run from the 32KHz clock, like GPIO edge
+//! detection and the GPT, can wake the processor.
+//!
+//! Call the functions in a critical section. The processor still wakes when an interrupt
+//! is pending, and the function reports the [`WakeReason`](enum.WakeReason.html) before
+//! the interrupt handler runs.
+//!
+//! # Example
+//!
+//! Use GPT1, running from the 32KHz clock, to wake from STOP after a second.
+//!
+//! ```no_run
+//! use imxrt1060_hal::{ccm::low_power, gpt, ral::interrupt};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+//! let mut core = cortex_m::Peripherals::take().unwrap();
+//!
+//! let mut gpt1 = peripherals.gpt1.clock_low_frequency(&mut peripherals.ccm.handle);
+//! gpt1.set_stop_mode_enable(true);
+//! gpt1.set_wait_mode_enable(true);
+//! gpt1.set_output_interrupt_on_compare(gpt::OutputCompareRegister::One, true);
+//! gpt1.set_output_compare_duration(
+//!     gpt::OutputCompareRegister::One,
+//!     core::time::Duration::from_secs(1),
+//! );
+//! gpt1.set_enable(true);
+//! unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::GPT1) };
+//!
+//! let config = low_power::StopConfig::new().wake_on(interrupt::GPT1);
+//! let reason = cortex_m::interrupt::free(|_| {
+//!     low_power::enter_stop(&mut peripherals.ccm.handle, &mut gpr, &mut core.SCB, config)
+//! });
+//! assert_eq!(reason, low_power::WakeReason::Interrupt(interrupt::GPT1 as u8));
+//! ```
+
+use super::Handle;
+use crate::iomuxc::GPR;
+use cortex_m::{
+    asm,
+    interrupt::Nr,
+    peripheral::{NVIC, SCB},
+};
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg, write_reg};
+
+/// The number of GPC interrupt mask (and status) registers
+const GPC_REGISTERS: usize = 5;
+
+/// GPC_IMR1 through GPC_IMR5, for IRQs 32 through 191
+const GPC_IMR: [*mut u32; GPC_REGISTERS] = [
+    0x400F_4008 as *mut u32,
+    0x400F_400C as *mut u32,
+    0x400F_4010 as *mut u32,
+    0x400F_4014 as *mut u32,
+    0x400F_4034 as *mut u32,
+];
+
+/// GPC_ISR1 through GPC_ISR5, for IRQs 32 through 191
+const GPC_ISR: [*const u32; GPC_REGISTERS] = [
+    0x400F_4018 as *const u32,
+    0x400F_401C as *const u32,
+    0x400F_4020 as *const u32,
+    0x400F_4024 as *const u32,
+    0x400F_4038 as *const u32,
+];
+
+/// GPR1 bit that asserts GPR_IRQ
+const GPR1_GINT: u32 = 1 << 12;
+/// GPR_IRQ, used for the ERR050143 workaround
+const GPR_IRQ: u8 = ral::interrupt::GPR_IRQ as u8;
+/// The first IRQ that the GPC can mask
+const GPC_FIRST_IRQ: u8 = 32;
+
+// CLPCR bits
+const LPM_MASK: u32 = 0b11;
+const LPM_WAIT: u32 = 0b01;
+const LPM_STOP: u32 = 0b10;
+const ARM_CLK_DIS_ON_LPM: u32 = 1 << 5;
+const SBYOS: u32 = 1 << 6;
+const VSTBY: u32 = 1 << 8;
+const STBY_COUNT: u32 = 0b11 << 9;
+const BYPASS_LPM_HS1: u32 = 1 << 19;
+const BYPASS_LPM_HS0: u32 = 1 << 21;
+const MASK_SCU_IDLE: u32 = 1 << 26;
+const MASK_L2CC_IDLE: u32 = 1 << 27;
+
+/// Selects the interrupts that wake the processor from STOP mode
+///
+/// By default, no interrupts wake the processor, and the 24MHz oscillator powers
+/// down in STOP mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopConfig {
+    /// One bit per IRQ, starting at IRQ 32; set if the IRQ wakes the processor
+    wakeups: [u32; GPC_REGISTERS],
+    keep_oscillator: bool,
+}
+
+impl StopConfig {
+    /// A configuration without any wakeup interrupts
+    pub const fn new() -> Self {
+        StopConfig {
+            wakeups: [0; GPC_REGISTERS],
+            keep_oscillator: false,
+        }
+    }
+
+    /// Wake the processor when `irq` is pending
+    ///
+    /// You must also enable the interrupt in the NVIC. Interrupts 0 through 31 always
+    /// wake the processor, so this call has no effect for those interrupts.
+    pub fn wake_on<I: Nr>(mut self, irq: I) -> Self {
+        if let Some((idx, bit)) = gpc_bit(irq.nr()) {
+            self.wakeups[idx] |= bit;
+        }
+        self
+    }
+
+    /// Keep the 24MHz oscillator powered in STOP mode
+    ///
+    /// Keeping the oscillator lets the processor resume faster, and lets oscillator-clocked
+    /// peripherals keep running, at the cost of more current.
+    pub fn keep_oscillator(mut self, keep: bool) -> Self {
+        self.keep_oscillator = keep;
+        self
+    }
+}
+
+impl Default for StopConfig {
+    fn default() -> Self {
+        StopConfig::new()
+    }
+}
+
+/// The reason that the processor left a low-power mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeReason {
+    /// This interrupt was pending when the processor woke
+    ///
+    /// If more than one interrupt was pending, this is the lowest interrupt number.
+    Interrupt(u8),
+    /// The interrupt was already handled, or it's one that the GPC doesn't track
+    Unknown,
+}
+
+/// Enter WAIT mode, and return when an interrupt wakes the processor
+///
+/// The erratum workaround uses the `gpr`.
+pub fn enter_wait(handle: &mut Handle, gpr: &mut GPR, scb: &mut SCB) -> WakeReason {
+    scb.clear_sleepdeep();
+    enter(handle, gpr, LowPowerMode::Wait, None)
+}
+
+/// Enter STOP mode, and return when a wakeup interrupt wakes the processor
+///
+/// `config` selects the interrupts that wake the processor. If `config` doesn't select
+/// any interrupts, only interrupts 0 through 31 can wake the processor. The erratum
+/// workaround uses the `gpr`.
+pub fn enter_stop(
+    handle: &mut Handle,
+    gpr: &mut GPR,
+    scb: &mut SCB,
+    config: StopConfig,
+) -> WakeReason {
+    scb.set_sleepdeep();
+    let reason = enter(
+        handle,
+        gpr,
+        LowPowerMode::Stop {
+            keep_oscillator: config.keep_oscillator,
+        },
+        Some(config.wakeups),
+    );
+    scb.clear_sleepdeep();
+    reason
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LowPowerMode {
+    Wait,
+    Stop { keep_oscillator: bool },
+}
+
+/// Enter the low-power mode, and restore the CCM and GPC when we wake
+///
+/// If `wakeups` is `None`, the GPC masks don't change.
+fn enter(
+    handle: &mut Handle,
+    gpr: &mut GPR,
+    mode: LowPowerMode,
+    wakeups: Option<[u32; GPC_REGISTERS]>,
+) -> WakeReason {
+    let (ccm, _) = handle.raw();
+    let run_clpcr = read_reg!(ral::ccm, ccm, CLPCR);
+
+    let gint = read_reg!(ral::iomuxc_gpr, gpr.0, GPR1) & GPR1_GINT;
+    // Safety: the GPC registers are always valid to access. The HAL doesn't otherwise
+    // use them, and we restore their values before returning.
+    unsafe {
+        let mut imrs = [0; GPC_REGISTERS];
+        for (imr, reg) in imrs.iter_mut().zip(GPC_IMR.iter()) {
+            *imr = reg.read_volatile();
+        }
+        let masks = wakeups.map(|wakeups| {
+            let mut masks = [0; GPC_REGISTERS];
+            for (mask, wakeup) in masks.iter_mut().zip(wakeups.iter()) {
+                *mask = !wakeup;
+            }
+            masks
+        });
+        if let Some(masks) = masks {
+            for (reg, mask) in GPC_IMR.iter().zip(masks.iter()) {
+                reg.write_volatile(*mask);
+            }
+        }
+
+        // ERR050143: keep GPR_IRQ pending, and unmask it while we set the low-power mode.
+        // Then, mask it, so that it doesn't wake the processor.
+        let (idx, bit) = gpc_bit(GPR_IRQ).unwrap();
+        modify_reg!(ral::iomuxc_gpr, gpr.0, GPR1, |gpr1| gpr1 | GPR1_GINT);
+        let imr = GPC_IMR[idx].read_volatile();
+        GPC_IMR[idx].write_volatile(imr & !bit);
+        write_reg!(ral::ccm, ccm, CLPCR, low_power_clpcr(run_clpcr, mode));
+        GPC_IMR[idx].write_volatile(imr | bit);
+
+        asm::dsb();
+        asm::wfi();
+        asm::isb();
+
+        write_reg!(ral::ccm, ccm, CLPCR, run_clpcr);
+
+        // An interrupt that could wake us is pending, and unmasked
+        let effective = masks.unwrap_or(imrs);
+        let mut pending = [0; GPC_REGISTERS];
+        for ((status, reg), mask) in pending.iter_mut().zip(GPC_ISR.iter()).zip(effective.iter()) {
+            *status = reg.read_volatile() & !mask;
+        }
+        pending[idx] &= !bit;
+
+        for (reg, imr) in GPC_IMR.iter().zip(imrs.iter()) {
+            reg.write_volatile(*imr);
+        }
+        // Only GINT changed. If it wasn't asserted before, GPR_IRQ wasn't pending.
+        modify_reg!(ral::iomuxc_gpr, gpr.0, GPR1, |gpr1| (gpr1 & !GPR1_GINT)
+            | gint);
+        if gint == 0 {
+            NVIC::unpend(ral::interrupt::GPR_IRQ);
+        }
+
+        wake_reason(&pending)
+    }
+}
+
+/// Returns the GPC register index, and bit, for `irq`
+///
+/// Returns `None` if the GPC can't mask the IRQ.
+fn gpc_bit(irq: u8) -> Option<(usize, u32)> {
+    let offset = irq.checked_sub(GPC_FIRST_IRQ)? as usize;
+    if offset < GPC_REGISTERS * 32 {
+        Some((offset / 32, 1 << (offset % 32)))
+    } else {
+        None
+    }
+}
+
+/// Returns the CLPCR value for `mode`, given the run-mode CLPCR value
+fn low_power_clpcr(run_clpcr: u32, mode: LowPowerMode) -> u32 {
+    let clpcr = (run_clpcr & !(LPM_MASK | ARM_CLK_DIS_ON_LPM | SBYOS))
+        | ARM_CLK_DIS_ON_LPM
+        | STBY_COUNT
+        | MASK_SCU_IDLE
+        | MASK_L2CC_IDLE
+        | BYPASS_LPM_HS0
+        | BYPASS_LPM_HS1;
+    match mode {
+        LowPowerMode::Wait => clpcr | LPM_WAIT,
+        LowPowerMode::Stop { keep_oscillator } => {
+            let clpcr = clpcr | LPM_STOP | VSTBY;
+            if keep_oscillator {
+                clpcr
+            } else {
+                clpcr | SBYOS
+            }
+        }
+    }
+}
+
+/// Returns the lowest pending IRQ
+fn wake_reason(pending: &[u32; GPC_REGISTERS]) -> WakeReason {
+    pending
+        .iter()
+        .enumerate()
+        .find(|(_, status)| **status != 0)
+        .map(|(idx, status)| {
+            WakeReason::Interrupt(GPC_FIRST_IRQ + (idx as u8 * 32) + status.trailing_zeros() as u8)
+        })
+        .unwrap_or(WakeReason::Unknown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gpc_bit, low_power_clpcr, wake_reason, LowPowerMode, WakeReason};
+
+    #[test]
+    fn gpc_bits() {
+        assert_eq!(gpc_bit(0), None);
+        assert_eq!(gpc_bit(31), None);
+        assert_eq!(gpc_bit(32), Some((0, 1)));
+        assert_eq!(gpc_bit(41), Some((0, 1 << 9)));
+        // GPT1
+        assert_eq!(gpc_bit(100), Some((2, 1 << 4)));
+        assert_eq!(gpc_bit(191), Some((4, 1 << 31)));
+        assert_eq!(gpc_bit(192), None);
+    }
+
+    #[test]
+    fn clpcr_values() {
+        // CLPCR reset value, in run mode
+        const RUN: u32 = 0x0000_0079;
+        assert_eq!(low_power_clpcr(RUN, LowPowerMode::Wait), 0x0C28_0639);
+        assert_eq!(
+            low_power_clpcr(
+                RUN,
+                LowPowerMode::Stop {
+                    keep_oscillator: false
+                }
+            ),
+            0x0C28_077A
+        );
+        assert_eq!(
+            low_power_clpcr(
+                RUN,
+                LowPowerMode::Stop {
+                    keep_oscillator: true
+                }
+            ),
+            0x0C28_073A
+        );
+    }
+
+    #[test]
+    fn wake_reasons() {
+        assert_eq!(wake_reason(&[0; 5]), WakeReason::Unknown);
+        assert_eq!(
+            wake_reason(&[0, 0, 1 << 4 | 1 << 5, 0, 0]),
+            WakeReason::Interrupt(100)
+        );
+        assert_eq!(
+            wake_reason(&[0, 0, 0, 0, 1 << 31]),
+            WakeReason::Interrupt(191)
+        );
+    }
+}
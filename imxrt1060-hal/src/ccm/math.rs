@@ -0,0 +1,292 @@
+//! Frequency and divider math for the clock tree
+//!
+//! These functions take and return plain integers, usually raw register field values.
+//! They don't touch the RAL, so the register code calls them, and the tests run on the
+//! host. Divider fields (`*_PODF`, `*_PRED`) hold one less than the divisor.
+
+/// PLL1 output frequency for each DIV_SELECT step
+const PLL1_STEP_HZ: u64 = 12_000_000;
+/// Smallest and largest PLL1 DIV_SELECT values
+const PLL1_MULT: core::ops::RangeInclusive<u32> = 54..=108;
+/// Largest ARM_PODF and AHB_PODF divider
+const MAX_PODF: u32 = 8;
+/// Fastest IPG clock
+const MAX_IPG_HZ: u32 = 150_000_000;
+/// Largest IPG_PODF divider
+const MAX_IPG_PODF: u32 = 4;
+
+/// The rated maximum core frequency
+pub const MAX_ARM_HZ: u32 = 600_000_000;
+/// The slowest core frequency that the dividers can reach
+pub const MIN_ARM_HZ: u32 = (PLL1_STEP_HZ * 54 / (MAX_PODF * MAX_PODF) as u64) as u32;
+
+/// PLL3 divider in front of the UART root clock multiplexer
+pub const UART_PLL3_DIVIDER: u32 = 6;
+/// PLL3 divider in front of the LPI2C root clock multiplexer
+pub const LPI2C_PLL3_DIVIDER: u32 = 8;
+
+/// PLL1 and divider settings for a core frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dividers {
+    /// PLL1 DIV_SELECT
+    pub(super) mult: u32,
+    pub(super) div_arm: u32,
+    pub(super) div_ahb: u32,
+    pub(super) div_ipg: u32,
+}
+
+impl Dividers {
+    /// Returns the core (AHB) frequency
+    pub const fn arm_hz(&self) -> u32 {
+        (PLL1_STEP_HZ * self.mult as u64 / (self.div_arm * self.div_ahb) as u64) as u32
+    }
+
+    /// Returns the IPG frequency
+    pub const fn ipg_hz(&self) -> u32 {
+        self.arm_hz() / self.div_ipg
+    }
+}
+
+/// Computes the settings that get the core frequency closest to `hz`, without
+/// exceeding `hz`
+///
+/// Returns `None` if `hz` is out of range.
+pub fn dividers(hz: u32) -> Option<Dividers> {
+    if hz < MIN_ARM_HZ || hz > MAX_ARM_HZ {
+        return None;
+    }
+    let mut best: Option<Dividers> = None;
+    for div_ahb in 1..=MAX_PODF {
+        for div_arm in 1..=MAX_PODF {
+            let div = (div_arm * div_ahb) as u64;
+            let mult = (hz as u64 * div / PLL1_STEP_HZ) as u32;
+            let mult = mult.min(*PLL1_MULT.end());
+            if !PLL1_MULT.contains(&mult) {
+                continue;
+            }
+            let candidate = Dividers {
+                mult,
+                div_arm,
+                div_ahb,
+                div_ipg: 1,
+            };
+            // Strictly greater, so that we prefer the smallest dividers
+            if best.map_or(true, |best| candidate.arm_hz() > best.arm_hz()) {
+                best = Some(candidate);
+            }
+        }
+    }
+    best.map(|mut dividers| {
+        dividers.div_ipg = ipg_divider(dividers.arm_hz());
+        dividers
+    })
+}
+
+/// Returns the smallest IPG divider that keeps the IPG clock at or below 150MHz
+pub const fn ipg_divider(arm_hz: u32) -> u32 {
+    let div = (arm_hz + MAX_IPG_HZ - 1) / MAX_IPG_HZ;
+    if div > MAX_IPG_PODF {
+        MAX_IPG_PODF
+    } else {
+        div
+    }
+}
+
+/// Returns the divisor for a raw divider field
+pub const fn divisor(podf: u32) -> u32 {
+    podf + 1
+}
+
+/// Divides `hz` by a raw divider field
+pub const fn divide(hz: u32, podf: u32) -> u32 {
+    hz / divisor(podf)
+}
+
+/// Returns the output of a PLL that multiplies `ref_hz` by `mult + num / denom`
+///
+/// A `denom` of 0 means that there's no fractional part.
+pub const fn pll_hz(ref_hz: u32, mult: u32, num: u32, denom: u32) -> u32 {
+    let ref_hz = ref_hz as u64;
+    let frac = if denom != 0 {
+        ref_hz * num as u64 / denom as u64
+    } else {
+        0
+    };
+    (ref_hz * mult as u64 + frac) as u32
+}
+
+/// Returns the output of a PFD with fraction `frac`, driven by a `pll_hz` PLL
+///
+/// Returns 0 if `frac` is 0.
+pub const fn pfd_hz(pll_hz: u32, frac: u32) -> u32 {
+    if frac == 0 {
+        0
+    } else {
+        (pll_hz as u64 * 18 / frac as u64) as u32
+    }
+}
+
+/// Returns the PERCLK frequency, given PERCLK_CLK_SEL and PERCLK_PODF
+pub const fn perclk_hz(ipg_hz: u32, osc_hz: u32, clk_sel: u32, podf: u32) -> u32 {
+    divide(if clk_sel == 0 { ipg_hz } else { osc_hz }, podf)
+}
+
+/// Returns the UART root clock frequency, given UART_CLK_SEL and UART_CLK_PODF
+pub const fn uart_hz(pll3_hz: u32, osc_hz: u32, clk_sel: u32, podf: u32) -> u32 {
+    let source = if clk_sel == 0 {
+        pll3_hz / UART_PLL3_DIVIDER
+    } else {
+        osc_hz
+    };
+    divide(source, podf)
+}
+
+/// Returns the LPI2C root clock frequency, given LPI2C_CLK_SEL and LPI2C_CLK_PODF
+pub const fn lpi2c_hz(pll3_hz: u32, osc_hz: u32, clk_sel: u32, podf: u32) -> u32 {
+    let source = if clk_sel == 0 {
+        pll3_hz / LPI2C_PLL3_DIVIDER
+    } else {
+        osc_hz
+    };
+    divide(source, podf)
+}
+
+/// Returns the DCDC REG3[TRG] value for a VDD_SOC voltage
+///
+/// Note that while the value is limited to u8 the return
+/// is u32 to easily compare against register read values which are always u32
+pub const fn vdd_soc_trg(mv: u32) -> u32 {
+    (((mv - 800) / 25) as u8) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OSC: u32 = 24_000_000;
+    const PLL2: u32 = 528_000_000;
+    const PLL3: u32 = 480_000_000;
+
+    #[test]
+    fn exact_targets() {
+        for &(target, ipg) in &[
+            (600_000_000, 150_000_000),
+            (528_000_000, 132_000_000),
+            (396_000_000, 132_000_000),
+            (132_000_000, 132_000_000),
+            (24_000_000, 24_000_000),
+        ] {
+            let dividers = dividers(target).unwrap();
+            assert_eq!(dividers.arm_hz(), target, "{:?}", dividers);
+            assert_eq!(dividers.ipg_hz(), ipg, "{:?}", dividers);
+        }
+    }
+
+    #[test]
+    fn never_exceed_target() {
+        for target in (MIN_ARM_HZ..=MAX_ARM_HZ).step_by(999_983) {
+            let dividers = dividers(target).unwrap();
+            assert!(dividers.arm_hz() <= target, "{} {:?}", target, dividers);
+            assert!(
+                dividers.ipg_hz() <= 150_000_000,
+                "{} {:?}",
+                target,
+                dividers
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(dividers(MAX_ARM_HZ + 1).is_none());
+        assert!(dividers(MIN_ARM_HZ - 1).is_none());
+        assert!(dividers(MIN_ARM_HZ).is_some());
+        assert_eq!(MIN_ARM_HZ, 10_125_000);
+    }
+
+    #[test]
+    fn arm_pll_settings() {
+        // 600MHz: DIV_SELECT = 100, ARM_PODF = 2
+        let dividers = dividers(600_000_000).unwrap();
+        assert_eq!(
+            dividers,
+            Dividers {
+                mult: 100,
+                div_arm: 2,
+                div_ahb: 1,
+                div_ipg: 4
+            }
+        );
+    }
+
+    #[test]
+    fn ipg_dividers() {
+        for &(arm_hz, div) in &[
+            (600_000_000, 4),
+            (528_000_000, 4),
+            (450_000_000, 3),
+            (396_000_000, 3),
+            (300_000_000, 2),
+            (150_000_000, 1),
+            (24_000_000, 1),
+            // Can't reach 150MHz
+            (816_000_000, 4),
+        ] {
+            assert_eq!(ipg_divider(arm_hz), div, "{}", arm_hz);
+        }
+    }
+
+    #[test]
+    fn plls() {
+        assert_eq!(pll_hz(OSC, 22, 0, 0), PLL2);
+        assert_eq!(pll_hz(OSC, 20, 0, 0), PLL3);
+        // ARM PLL, DIV_SELECT = 100
+        assert_eq!(pll_hz(OSC, 50, 0, 2), 1_200_000_000);
+        assert_eq!(pll_hz(OSC, 54, 1, 2), 1_308_000_000);
+        // Audio PLL, for 44.1KHz
+        assert_eq!(pll_hz(OSC, 30, 1056, 10_000), 722_534_400);
+    }
+
+    #[test]
+    fn pfds() {
+        for &(pll, frac, hz) in &[
+            (PLL2, 18, PLL2),
+            (PLL2, 24, 396_000_000),
+            (PLL2, 16, 594_000_000),
+            (PLL2, 35, 271_542_857),
+            (PLL3, 12, 720_000_000),
+            (PLL3, 18, PLL3),
+            (PLL3, 35, 246_857_142),
+            (PLL3, 0, 0),
+        ] {
+            assert_eq!(pfd_hz(pll, frac), hz, "{} / {}", pll, frac);
+        }
+    }
+
+    #[test]
+    fn root_dividers() {
+        // PERCLK: IPG / 2, or the oscillator / 24
+        assert_eq!(perclk_hz(150_000_000, OSC, 0, 1), 75_000_000);
+        assert_eq!(perclk_hz(150_000_000, OSC, 1, 23), 1_000_000);
+        // UART: PLL3 / 6, or the oscillator
+        assert_eq!(uart_hz(PLL3, OSC, 0, 0), 80_000_000);
+        assert_eq!(uart_hz(PLL3, OSC, 1, 0), OSC);
+        assert_eq!(uart_hz(PLL3, OSC, 0, 63), 1_250_000);
+        // LPI2C: PLL3 / 8, or the oscillator
+        assert_eq!(lpi2c_hz(PLL3, OSC, 0, 0), 60_000_000);
+        assert_eq!(lpi2c_hz(PLL3, OSC, 1, 2), 8_000_000);
+        // LPSPI: PLL3 PFD0, or PLL2
+        assert_eq!(divide(pfd_hz(PLL3, 12), 5), 120_000_000);
+        assert_eq!(divide(PLL2, 7), 66_000_000);
+        assert_eq!(divisor(0), 1);
+    }
+
+    #[test]
+    fn vdd_soc_steps() {
+        assert_eq!(vdd_soc_trg(800), 0);
+        assert_eq!(vdd_soc_trg(950), 6);
+        assert_eq!(vdd_soc_trg(1150), 14);
+        assert_eq!(vdd_soc_trg(1250), 18);
+        assert_eq!(vdd_soc_trg(1575), 31);
+    }
+}
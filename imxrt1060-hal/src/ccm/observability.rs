@@ -0,0 +1,281 @@
+//! Route internal clocks to the CCM_CLKO1 and CCM_CLKO2 pads
+//!
+//! Use the clock outputs to measure internal clocks with a scope, or a frequency counter.
+//! [`clko1()`](fn.clko1.html) and [`clko2()`](fn.clko2.html) divide a clock source, and drive
+//! the pad with the result. Disable the output to return the pad to the function that it had
+//! before you enabled the output.
+//!
+//! CCM_CLKO1 is pad SD_B0_04, and CCM_CLKO2 is pad SD_B0_05. On a Teensy 4.1, those are
+//! DAT2 and DAT3 of the microSD socket.
+//!
+//! # Example
+//!
+//! Output the AHB clock, divided by 8, on CCM_CLKO1.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::observability::{self, Clko1Source};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let clko1 = observability::clko1(
+//!     &mut peripherals.ccm.handle,
+//!     Clko1Source::Ahb,
+//!     8,
+//!     peripherals.iomuxc.sd_b0.p04,
+//! )
+//! .unwrap();
+//!
+//! // Measure...
+//!
+//! let pad = clko1.disable(&mut peripherals.ccm.handle).release();
+//! ```
+
+use super::Handle;
+use crate::iomuxc::{self, sd_b0::SD_B0_04, sd_b0::SD_B0_05};
+use imxrt_ral as ral;
+use ral::{read_reg, write_reg};
+
+/// The CCM_CLKO1 and CCM_CLKO2 alternate function on their pads
+const CLKO_ALT: u32 = 6;
+
+/// Largest clock output divider
+pub const MAX_DIVIDER: u8 = 8;
+
+// CCOSR fields
+const CLKO1_SEL_SHIFT: u32 = 0;
+const CLKO1_DIV_SHIFT: u32 = 4;
+const CLKO1_EN: u32 = 1 << 7;
+const CLK_OUT_SEL: u32 = 1 << 8;
+const CLKO1_MASK: u32 = 0xFF | CLK_OUT_SEL;
+const CLKO2_SEL_SHIFT: u32 = 16;
+const CLKO2_DIV_SHIFT: u32 = 21;
+const CLKO2_EN: u32 = 1 << 24;
+const CLKO2_MASK: u32 = 0x1FF << 16;
+
+/// Clock sources for CCM_CLKO1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clko1Source {
+    /// USB1 PLL (PLL3), divided by 2
+    Pll3Div2 = 0b0000,
+    /// System PLL (PLL2), divided by 2
+    Pll2Div2 = 0b0001,
+    /// Video PLL (PLL5), divided by 2
+    Pll5Div2 = 0b0011,
+    /// SEMC root clock
+    Semc = 0b0101,
+    /// LCDIF pixel root clock
+    LcdifPix = 0b1010,
+    /// AHB root clock
+    Ahb = 0b1011,
+    /// IPG root clock
+    Ipg = 0b1100,
+    /// PERCLK root clock
+    Perclk = 0b1101,
+    /// 32KHz low-frequency clock
+    Ckil = 0b1110,
+    /// Audio PLL (PLL4)
+    Pll4 = 0b1111,
+}
+
+/// Clock sources for CCM_CLKO2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clko2Source {
+    /// USDHC1 root clock
+    Usdhc1 = 0b00011,
+    /// LPI2C root clock
+    Lpi2c = 0b00101,
+    /// CSI root clock
+    Csi = 0b01011,
+    /// 24MHz oscillator
+    Osc = 0b01110,
+    /// USDHC2 root clock
+    Usdhc2 = 0b10001,
+    /// SAI1 root clock
+    Sai1 = 0b10010,
+    /// SAI2 root clock
+    Sai2 = 0b10011,
+    /// SAI3 root clock
+    Sai3 = 0b10100,
+    /// CAN root clock
+    Can = 0b10111,
+    /// FlexSPI root clock
+    Flexspi = 0b11011,
+    /// UART root clock
+    Uart = 0b11100,
+    /// SPDIF root clock
+    Spdif = 0b11101,
+}
+
+/// The clock output divider is outside of 1 and [`MAX_DIVIDER`](constant.MAX_DIVIDER.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DividerError(());
+
+/// The CCM_CLKO1 pad
+pub struct Clko1Pad(SD_B0_04);
+
+impl From<SD_B0_04> for Clko1Pad {
+    fn from(pad: SD_B0_04) -> Self {
+        Clko1Pad(pad)
+    }
+}
+
+impl Clko1Pad {
+    /// Release the pad
+    pub fn release(self) -> SD_B0_04 {
+        self.0
+    }
+}
+
+/// The CCM_CLKO2 pad
+pub struct Clko2Pad(SD_B0_05);
+
+impl From<SD_B0_05> for Clko2Pad {
+    fn from(pad: SD_B0_05) -> Self {
+        Clko2Pad(pad)
+    }
+}
+
+impl Clko2Pad {
+    /// Release the pad
+    pub fn release(self) -> SD_B0_05 {
+        self.0
+    }
+}
+
+/// An enabled CCM_CLKO1 output
+pub struct Clko1 {
+    pad: Clko1Pad,
+    /// The pad's mux register before we enabled the output
+    mux: u32,
+}
+
+/// An enabled CCM_CLKO2 output
+pub struct Clko2 {
+    pad: Clko2Pad,
+    /// The pad's mux register before we enabled the output
+    mux: u32,
+}
+
+/// Output `source`, divided by `divider`, on CCM_CLKO1
+///
+/// `divider` must be from 1 to [`MAX_DIVIDER`](constant.MAX_DIVIDER.html). Otherwise, this
+/// returns the pad with an error.
+pub fn clko1(
+    handle: &mut Handle,
+    source: Clko1Source,
+    divider: u8,
+    pad: impl Into<Clko1Pad>,
+) -> Result<Clko1, (Clko1Pad, DividerError)> {
+    let mut pad = pad.into();
+    if !(1..=MAX_DIVIDER).contains(&divider) {
+        return Err((pad, DividerError(())));
+    }
+    let (ccm, _) = handle.raw();
+    let ccosr = read_reg!(ral::ccm, ccm, CCOSR);
+    write_reg!(ral::ccm, ccm, CCOSR, clko1_ccosr(ccosr, source, divider));
+    let mux = take_pad(&mut pad.0);
+    Ok(Clko1 { pad, mux })
+}
+
+/// Output `source`, divided by `divider`, on CCM_CLKO2
+///
+/// `divider` must be from 1 to [`MAX_DIVIDER`](constant.MAX_DIVIDER.html). Otherwise, this
+/// returns the pad with an error.
+pub fn clko2(
+    handle: &mut Handle,
+    source: Clko2Source,
+    divider: u8,
+    pad: impl Into<Clko2Pad>,
+) -> Result<Clko2, (Clko2Pad, DividerError)> {
+    let mut pad = pad.into();
+    if !(1..=MAX_DIVIDER).contains(&divider) {
+        return Err((pad, DividerError(())));
+    }
+    let (ccm, _) = handle.raw();
+    let ccosr = read_reg!(ral::ccm, ccm, CCOSR);
+    write_reg!(ral::ccm, ccm, CCOSR, clko2_ccosr(ccosr, source, divider));
+    let mux = take_pad(&mut pad.0);
+    Ok(Clko2 { pad, mux })
+}
+
+impl Clko1 {
+    /// Disable the output, and restore the pad's previous function
+    pub fn disable(mut self, handle: &mut Handle) -> Clko1Pad {
+        let (ccm, _) = handle.raw();
+        ral::modify_reg!(ral::ccm, ccm, CCOSR, CLKO1_EN: 0);
+        restore_pad(&mut self.pad.0, self.mux);
+        self.pad
+    }
+}
+
+impl Clko2 {
+    /// Disable the output, and restore the pad's previous function
+    pub fn disable(mut self, handle: &mut Handle) -> Clko2Pad {
+        let (ccm, _) = handle.raw();
+        ral::modify_reg!(ral::ccm, ccm, CCOSR, CLKO2_EN: 0);
+        restore_pad(&mut self.pad.0, self.mux);
+        self.pad
+    }
+}
+
+/// Select the clock output function, and return the previous mux register value
+fn take_pad<P: iomuxc::Iomuxc>(pad: &mut P) -> u32 {
+    // Safety: we own the pad, so we may access its mux register
+    let mux = unsafe { pad.mux().read_volatile() };
+    iomuxc::alternate(pad, CLKO_ALT);
+    mux
+}
+
+/// Restore a mux register value that we saved in `take_pad()`
+fn restore_pad<P: iomuxc::Iomuxc>(pad: &mut P, mux: u32) {
+    // Safety: we own the pad, so we may access its mux register
+    unsafe { pad.mux().write_volatile(mux) };
+}
+
+/// Returns the CCOSR value that enables CCM_CLKO1
+///
+/// The CCM_CLKO2 fields don't change. `divider` is from 1 to 8.
+fn clko1_ccosr(ccosr: u32, source: Clko1Source, divider: u8) -> u32 {
+    (ccosr & !CLKO1_MASK)
+        | ((source as u32) << CLKO1_SEL_SHIFT)
+        | ((divider as u32 - 1) << CLKO1_DIV_SHIFT)
+        | CLKO1_EN
+}
+
+/// Returns the CCOSR value that enables CCM_CLKO2
+///
+/// The CCM_CLKO1 fields don't change. `divider` is from 1 to 8.
+fn clko2_ccosr(ccosr: u32, source: Clko2Source, divider: u8) -> u32 {
+    (ccosr & !CLKO2_MASK)
+        | ((source as u32) << CLKO2_SEL_SHIFT)
+        | ((divider as u32 - 1) << CLKO2_DIV_SHIFT)
+        | CLKO2_EN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CCOSR reset value
+    const RESET: u32 = 0x000A_0001;
+
+    #[test]
+    fn clko1_values() {
+        assert_eq!(clko1_ccosr(RESET, Clko1Source::Ahb, 8), 0x000A_00FB);
+        assert_eq!(clko1_ccosr(RESET, Clko1Source::Pll3Div2, 1), 0x000A_0080);
+        // CLK_OUT_SEL routes CCM_CLKO1 to its own pad
+        assert_eq!(
+            clko1_ccosr(RESET | CLK_OUT_SEL, Clko1Source::Ipg, 2),
+            0x000A_009C
+        );
+    }
+
+    #[test]
+    fn clko2_values() {
+        assert_eq!(clko2_ccosr(RESET, Clko2Source::Osc, 1), 0x010E_0001);
+        assert_eq!(clko2_ccosr(RESET, Clko2Source::Uart, 4), 0x017C_0001);
+        // CCM_CLKO1 settings don't change
+        assert_eq!(clko2_ccosr(0x0000_00FB, Clko2Source::Lpi2c, 8), 0x01E5_00FB);
+    }
+}
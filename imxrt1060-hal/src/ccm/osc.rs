@@ -0,0 +1,273 @@
+//! 24MHz crystal oscillator, and the RC oscillator fallback
+//!
+//! The 24MHz reference clock comes from the crystal oscillator, or from an internal RC
+//! oscillator. All PLLs, and every clock that the HAL describes as the "oscillator,"
+//! derive from the reference clock. The HAL assumes that the crystal oscillator drives
+//! the reference clock, since it's the only accurate source.
+//!
+//! If the crystal fails, you can switch the reference clock to the RC oscillator, and
+//! keep running in a degraded mode. The RC oscillator is nominally 24MHz, but it's much
+//! less accurate than the crystal. Expect timers and baud rates to drift. Check
+//! [`Frequencies::oscillator`](../struct.Frequencies.html#structfield.oscillator) to learn
+//! which oscillator a frequency snapshot assumes.
+//!
+//! Switching the reference clock would glitch a running PLL, so the switching functions
+//! reject the switch while any PLL is running. Run the core from the oscillator, and
+//! power down or bypass the PLLs, before you switch.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::osc::{self, OscSource};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let handle = &mut peripherals.ccm.handle;
+//! if !osc::xtal_ready(handle) {
+//!     // Bypass or power down the PLLs, then...
+//!     osc::switch_periph_to_rc(handle).unwrap();
+//!     assert_eq!(osc::current_osc(handle), OscSource::Rc);
+//! }
+//! ```
+
+use super::Handle;
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg};
+
+/// XTALOSC24M_LOWPWR_CTRL
+const LOWPWR_CTRL: *const u32 = 0x400D_8270 as *const u32;
+/// XTALOSC24M_LOWPWR_CTRL_SET
+const LOWPWR_CTRL_SET: *mut u32 = 0x400D_8274 as *mut u32;
+/// XTALOSC24M_LOWPWR_CTRL_CLR
+const LOWPWR_CTRL_CLR: *mut u32 = 0x400D_8278 as *mut u32;
+
+// LOWPWR_CTRL bits
+const RC_OSC_EN: u32 = 1 << 0;
+const OSC_SEL: u32 = 1 << 4;
+
+/// The number of times we spin while the RC oscillator starts
+///
+/// The RC oscillator starts in a few microseconds. This is a multiple of that at 600MHz.
+const RC_OSC_STARTUP_SPINS: u32 = 10_000;
+
+/// The source of the 24MHz reference clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OscSource {
+    /// The 24MHz crystal oscillator
+    Xtal,
+    /// The internal RC oscillator
+    Rc,
+}
+
+impl Default for OscSource {
+    fn default() -> Self {
+        OscSource::Xtal
+    }
+}
+
+impl OscSource {
+    fn from_lowpwr_ctrl(lowpwr_ctrl: u32) -> Self {
+        if lowpwr_ctrl & OSC_SEL != 0 {
+            OscSource::Rc
+        } else {
+            OscSource::Xtal
+        }
+    }
+}
+
+/// Errors when switching the reference clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OscError {
+    /// A PLL is running from the reference clock
+    PllsInUse,
+    /// The crystal oscillator isn't stable
+    XtalNotReady,
+}
+
+/// Returns `true` if the crystal oscillator is powered and stable
+///
+/// The first call enables the crystal oscillator's stability detector. The detector needs
+/// a few milliseconds before it reports a stable crystal.
+pub fn xtal_ready(handle: &mut Handle) -> bool {
+    let (_, analog) = handle.raw();
+    if read_reg!(ral::ccm_analog, analog, MISC0, OSC_XTALOK_EN) == 0 {
+        modify_reg!(ral::ccm_analog, analog, MISC0, OSC_XTALOK_EN: 1);
+    }
+    let (powered_down, ok) = read_reg!(ral::ccm_analog, analog, MISC0, XTAL_24M_PWD, OSC_XTALOK);
+    powered_down == 0 && ok != 0
+}
+
+/// Enable the RC oscillator, and wait for it to start
+///
+/// Enabling the RC oscillator doesn't change the reference clock.
+pub fn enable_rc_osc(_: &mut Handle) {
+    // Safety: the handle gives us exclusive access to the oscillator registers.
+    // Atomic write to the SET register.
+    unsafe {
+        if LOWPWR_CTRL.read_volatile() & RC_OSC_EN != 0 {
+            return;
+        }
+        LOWPWR_CTRL_SET.write_volatile(RC_OSC_EN);
+    }
+    for _ in 0..RC_OSC_STARTUP_SPINS {
+        #[allow(deprecated)]
+        core::sync::atomic::spin_loop_hint();
+    }
+}
+
+/// Switch the reference clock to the RC oscillator
+///
+/// `switch_periph_to_rc()` enables the RC oscillator, waits for it to start, then
+/// selects it as the reference clock. Returns an error, without touching any clocks,
+/// if a PLL is running.
+pub fn switch_periph_to_rc(handle: &mut Handle) -> Result<(), OscError> {
+    if plls_in_use(handle) {
+        return Err(OscError::PllsInUse);
+    }
+    enable_rc_osc(handle);
+    // Safety: see enable_rc_osc(). Atomic write to the SET register.
+    unsafe { LOWPWR_CTRL_SET.write_volatile(OSC_SEL) };
+    Ok(())
+}
+
+/// Switch the reference clock back to the crystal oscillator
+///
+/// The RC oscillator keeps running. Returns an error, without touching any clocks,
+/// if the crystal isn't stable, or if a PLL is running.
+pub fn switch_periph_to_xtal(handle: &mut Handle) -> Result<(), OscError> {
+    if !xtal_ready(handle) {
+        return Err(OscError::XtalNotReady);
+    }
+    if plls_in_use(handle) {
+        return Err(OscError::PllsInUse);
+    }
+    // Safety: see enable_rc_osc(). Atomic write to the CLR register.
+    unsafe { LOWPWR_CTRL_CLR.write_volatile(OSC_SEL) };
+    Ok(())
+}
+
+/// Returns the source of the 24MHz reference clock
+pub fn current_osc(_: &Handle) -> OscSource {
+    read_osc()
+}
+
+/// Reads the reference clock source
+pub(super) fn read_osc() -> OscSource {
+    // Safety: atomic read of a register without side effects
+    OscSource::from_lowpwr_ctrl(unsafe { LOWPWR_CTRL.read_volatile() })
+}
+
+// Analog PLL bits. Every PLL has them at the same positions.
+/// POWERDOWN, or POWER for the USB PLLs
+const POWER: u32 = 1 << 12;
+const ENABLE: u32 = 1 << 13;
+const BYPASS: u32 = 1 << 16;
+/// PLL_ENET's other reference clock enables, ENET2_REF_EN and ENET_25M_REF_EN
+const ENET_REF_ENABLES: u32 = (1 << 20) | (1 << 21);
+
+/// How a PLL's power bit reads when the PLL is powered
+#[derive(Clone, Copy)]
+enum Power {
+    /// POWERDOWN is clear
+    PowerdownClear,
+    /// POWER is set
+    PowerSet,
+}
+
+/// Returns `true` if the PLL register value `pll` describes a running PLL
+///
+/// A running PLL is powered, not bypassed, and has one of the output `enables`.
+fn running(pll: u32, power: Power, enables: u32) -> bool {
+    let powered = match power {
+        Power::PowerdownClear => pll & POWER == 0,
+        Power::PowerSet => pll & POWER != 0,
+    };
+    powered && pll & enables != 0 && pll & BYPASS == 0
+}
+
+/// Returns `true` if any PLL is running from the reference clock
+///
+/// A bypassed PLL passes the reference clock through, so it doesn't glitch.
+fn plls_in_use(handle: &Handle) -> bool {
+    let analog = &handle.analog;
+    let plls = [
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_ARM),
+            Power::PowerdownClear,
+            ENABLE,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_SYS),
+            Power::PowerdownClear,
+            ENABLE,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_USB1),
+            Power::PowerSet,
+            ENABLE,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_AUDIO),
+            Power::PowerdownClear,
+            ENABLE,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_VIDEO),
+            Power::PowerdownClear,
+            ENABLE,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_ENET),
+            Power::PowerdownClear,
+            ENABLE | ENET_REF_ENABLES,
+        ),
+        (
+            read_reg!(ral::ccm_analog, analog, PLL_USB2),
+            Power::PowerSet,
+            ENABLE,
+        ),
+    ];
+    plls.iter()
+        .any(|&(pll, power, enables)| running(pll, power, enables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        running, OscSource, Power, BYPASS, ENABLE, ENET_REF_ENABLES, OSC_SEL, POWER, RC_OSC_EN,
+    };
+
+    #[test]
+    fn osc_source() {
+        assert_eq!(OscSource::from_lowpwr_ctrl(0x0007_4F00), OscSource::Xtal);
+        assert_eq!(OscSource::from_lowpwr_ctrl(RC_OSC_EN), OscSource::Xtal);
+        assert_eq!(
+            OscSource::from_lowpwr_ctrl(RC_OSC_EN | OSC_SEL),
+            OscSource::Rc
+        );
+    }
+
+    #[test]
+    fn running_plls() {
+        // PLL1, PLL2, PLL4 and PLL5, and the reset value of PLL_ARM
+        assert!(running(ENABLE, Power::PowerdownClear, ENABLE));
+        assert!(!running(0x0001_3063, Power::PowerdownClear, ENABLE));
+        assert!(!running(POWER | ENABLE, Power::PowerdownClear, ENABLE));
+        assert!(!running(ENABLE | BYPASS, Power::PowerdownClear, ENABLE));
+        assert!(!running(0, Power::PowerdownClear, ENABLE));
+
+        // PLL3 and PLL7
+        assert!(running(POWER | ENABLE, Power::PowerSet, ENABLE));
+        assert!(!running(ENABLE, Power::PowerSet, ENABLE));
+        assert!(!running(POWER | ENABLE | BYPASS, Power::PowerSet, ENABLE));
+        assert!(!running(POWER, Power::PowerSet, ENABLE));
+
+        // PLL6 runs while any reference clock is enabled
+        let enet = ENABLE | ENET_REF_ENABLES;
+        assert!(running(ENABLE, Power::PowerdownClear, enet));
+        assert!(running(1 << 20, Power::PowerdownClear, enet));
+        assert!(running(1 << 21, Power::PowerdownClear, enet));
+        assert!(!running(1 << 21 | BYPASS, Power::PowerdownClear, enet));
+        assert!(!running(POWER | 1 << 20, Power::PowerdownClear, enet));
+        assert!(!running(0, Power::PowerdownClear, enet));
+    }
+}
@@ -0,0 +1,308 @@
+//! Individual PLL2 PFD handles
+
+use super::super::{math, Frequency, Handle};
+use crate::iomuxc::consts::{Unsigned, U0, U1, U2, U3};
+use core::marker::PhantomData;
+use imxrt_ral::{self as ral, modify_reg, read_reg, write_reg};
+
+/// The PLL2 output frequency
+const PLL2_HZ: u32 = 528_000_000;
+
+/// Errors when configuring a PLL2 PFD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PfdError {
+    /// The fractional divider is outside of
+    /// [`MIN_FRAC`](struct.Pfd.html#associatedconstant.MIN_FRAC) and
+    /// [`MAX_FRAC`](struct.Pfd.html#associatedconstant.MAX_FRAC)
+    FracOutOfRange,
+    /// The PFD is gated
+    Gated,
+    /// The PFD clocks the core, SEMC, or FlexSPI, and changing it could glitch
+    /// the running system
+    InUse,
+}
+
+impl super::PFD {
+    /// Split the PLL2 PFDs into individual handles
+    ///
+    /// Once split, you configure each PFD with a [`Pfd`](struct.Pfd.html) handle,
+    /// instead of using [`set()`](#method.set).
+    pub fn split(self) -> (Pfd<U0>, Pfd<U1>, Pfd<U2>, Pfd<U3>) {
+        (Pfd::new(), Pfd::new(), Pfd::new(), Pfd::new())
+    }
+}
+
+/// A handle to PLL2 PFD `N`
+///
+/// Acquire the handles with [`PFD::split()`](struct.PFD.html#method.split). A PFD's output
+/// frequency is `528MHz * 18 / frac`, where `frac` is from 12 to 35.
+///
+/// # Runtime safety
+///
+/// The boot ROM, or your startup code, may use PLL2 PFDs for clocks that the
+/// system relies on:
+///
+/// - PFD0, or PFD2, may clock the core and AHB bus, through the pre-peripheral clock.
+/// - PFD2 may clock the SEMC, which could be running your external SDRAM.
+/// - PFD2 may clock the FlexSPI, which could be running your code from flash.
+///
+/// Changing, or gating, a PFD that drives one of those clocks glitches the running
+/// system. `set_frac()` and `gate()` check the clock configuration, and return
+/// [`PfdError::InUse`](enum.PfdError.html#variant.InUse) instead of modifying such a PFD.
+/// PFD1 and PFD3 never drive those clocks, so they're always safe to modify.
+///
+/// Modifying a PFD changes the frequency of every peripheral root that it drives. Stop,
+/// or gate, those peripherals before you modify the PFD.
+///
+/// # Root clock selection
+///
+/// A peripheral root clock that selects a PFD can't use a gated PFD. Consume an ungated
+/// PFD with `into_clock()`, and provide the resulting [`PfdClock`](struct.PfdClock.html)
+/// to a clock selection, like [`spi::ClockSelect::Pll2Pfd2`](../spi/enum.ClockSelect.html#variant.Pll2Pfd2).
+/// Since you no longer have the handle, the PFD stays configured.
+///
+/// ```no_run
+/// use imxrt1060_hal::ccm;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let (_, _, mut pfd2, _) = peripherals.ccm.pll2.split();
+///
+/// let hz = pfd2.set_frac(&mut peripherals.ccm.handle, 24).unwrap();
+/// assert_eq!(hz.hz(), 396_000_000);
+/// pfd2.ungate(&mut peripherals.ccm.handle);
+/// let pfd2 = pfd2.into_clock(&peripherals.ccm.handle).unwrap();
+///
+/// let lpspi_clock = ccm::spi::lpspi_clock(
+///     &mut peripherals.ccm.handle,
+///     ccm::spi::ClockSelect::Pll2Pfd2(pfd2),
+///     ccm::spi::PrescalarSelect::LPSPI_PODF_5,
+/// );
+/// let spi_builders = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
+/// ```
+pub struct Pfd<N> {
+    _pfd: PhantomData<N>,
+}
+
+impl<N: Unsigned> Pfd<N> {
+    fn new() -> Self {
+        Pfd { _pfd: PhantomData }
+    }
+
+    /// The smallest fractional divider, and the fastest frequency
+    pub const MIN_FRAC: u8 = 12;
+    /// The largest fractional divider, and the slowest frequency
+    pub const MAX_FRAC: u8 = 35;
+
+    /// Offset of this PFD's fields in the PFD_528 register
+    fn shift() -> u32 {
+        8 * N::U32
+    }
+
+    /// Set the fractional divider, and return the PFD frequency
+    ///
+    /// The PFD's gate doesn't change. If the PFD is ungated, its new frequency
+    /// takes effect immediately.
+    pub fn set_frac(&mut self, handle: &mut Handle, frac: u8) -> Result<Frequency, PfdError> {
+        let hz = frac_hz(frac).ok_or(PfdError::FracOutOfRange)?;
+        if self.is_in_use(handle) {
+            return Err(PfdError::InUse);
+        }
+        let shift = Self::shift();
+        let (_, analog) = handle.raw();
+        modify_reg!(ral::ccm_analog, analog, PFD_528, |r| (r & !(0x3F << shift))
+            | ((frac as u32) << shift));
+        Ok(Frequency(hz))
+    }
+
+    /// Returns the fractional divider
+    pub fn frac(&self, handle: &Handle) -> u8 {
+        ((read_reg!(ral::ccm_analog, handle.analog, PFD_528) >> Self::shift()) & 0x3F) as u8
+    }
+
+    /// Returns `true` if the PFD is gated
+    pub fn is_gated(&self, handle: &Handle) -> bool {
+        read_reg!(ral::ccm_analog, handle.analog, PFD_528) & (1 << (Self::shift() + 7)) != 0
+    }
+
+    /// Returns the PFD frequency, or `None` if the PFD is gated
+    pub fn frequency(&self, handle: &Handle) -> Option<Frequency> {
+        if self.is_gated(handle) {
+            None
+        } else {
+            frac_hz(self.frac(handle)).map(Frequency)
+        }
+    }
+
+    /// Gate the PFD, stopping its output
+    pub fn gate(&mut self, handle: &mut Handle) -> Result<(), PfdError> {
+        if self.is_in_use(handle) {
+            return Err(PfdError::InUse);
+        }
+        let gate = 1 << (Self::shift() + 7);
+        write_reg!(ral::ccm_analog, handle.analog, PFD_528_SET, gate);
+        Ok(())
+    }
+
+    /// Ungate the PFD, and return its frequency
+    pub fn ungate(&mut self, handle: &mut Handle) -> Frequency {
+        let gate = 1 << (Self::shift() + 7);
+        write_reg!(ral::ccm_analog, handle.analog, PFD_528_CLR, gate);
+        // The FRAC field resets to a valid value, and we only write valid values
+        Frequency(frac_hz(self.frac(handle)).unwrap_or(0))
+    }
+
+    /// Consume the ungated PFD, and return a clock for root clock selection
+    ///
+    /// Returns the PFD, and [`PfdError::Gated`](enum.PfdError.html#variant.Gated), if
+    /// the PFD is gated.
+    pub fn into_clock(self, handle: &Handle) -> Result<PfdClock<N>, (Self, PfdError)> {
+        match self.frequency(handle) {
+            Some(hz) => Ok(PfdClock {
+                hz,
+                _pfd: PhantomData,
+            }),
+            None => Err((self, PfdError::Gated)),
+        }
+    }
+
+    /// Returns `true` if this PFD drives the core, SEMC, or FlexSPI clocks
+    fn is_in_use(&self, handle: &Handle) -> bool {
+        let (periph_clk_sel, semc_clk_sel, semc_alt_clk_sel) = read_reg!(
+            ral::ccm,
+            handle.base,
+            CBCDR,
+            PERIPH_CLK_SEL,
+            SEMC_CLK_SEL,
+            SEMC_ALT_CLK_SEL
+        );
+        let muxes = Muxes {
+            periph_clk_sel,
+            pre_periph_clk_sel: read_reg!(ral::ccm, handle.base, CBCMR, PRE_PERIPH_CLK_SEL),
+            semc_clk_sel,
+            semc_alt_clk_sel,
+            flexspi_clk_sel: read_reg!(ral::ccm, handle.base, CSCMR1, FLEXSPI_CLK_SEL),
+        };
+        muxes.uses(N::U32)
+    }
+}
+
+/// An ungated PLL2 PFD, for root clock selection
+///
+/// Acquire a `PfdClock` with [`Pfd::into_clock()`](struct.Pfd.html#method.into_clock).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PfdClock<N> {
+    hz: Frequency,
+    _pfd: PhantomData<N>,
+}
+
+impl<N> PfdClock<N> {
+    /// Returns the PFD frequency
+    pub fn frequency(self) -> Frequency {
+        self.hz
+    }
+}
+
+/// Returns the PFD frequency for `frac`, or `None` if `frac` is out of range
+fn frac_hz(frac: u8) -> Option<u32> {
+    if (Pfd::<U0>::MIN_FRAC..=Pfd::<U0>::MAX_FRAC).contains(&frac) {
+        Some(math::pfd_hz(PLL2_HZ, frac as u32))
+    } else {
+        None
+    }
+}
+
+/// Clock multiplexer selections that may use a PLL2 PFD
+struct Muxes {
+    periph_clk_sel: u32,
+    pre_periph_clk_sel: u32,
+    semc_clk_sel: u32,
+    semc_alt_clk_sel: u32,
+    flexspi_clk_sel: u32,
+}
+
+impl Muxes {
+    /// Returns `true` if PFD `pfd` drives the core, SEMC, or FlexSPI clocks
+    fn uses(&self, pfd: u32) -> bool {
+        let core = if self.periph_clk_sel == 0 {
+            match self.pre_periph_clk_sel {
+                1 => Some(2),
+                2 => Some(0),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let semc = if self.semc_clk_sel == 1 && self.semc_alt_clk_sel == 0 {
+            Some(2)
+        } else {
+            None
+        };
+        let flexspi = if self.flexspi_clk_sel == 2 {
+            Some(2)
+        } else {
+            None
+        };
+        [core, semc, flexspi].contains(&Some(pfd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frac_hz, Muxes};
+
+    #[test]
+    fn frac_frequencies() {
+        assert_eq!(frac_hz(11), None);
+        assert_eq!(frac_hz(12), Some(792_000_000));
+        assert_eq!(frac_hz(18), Some(528_000_000));
+        assert_eq!(frac_hz(24), Some(396_000_000));
+        assert_eq!(frac_hz(27), Some(352_000_000));
+        assert_eq!(frac_hz(35), Some(271_542_857));
+        assert_eq!(frac_hz(36), None);
+        assert_eq!(frac_hz(0), None);
+    }
+
+    #[test]
+    fn pfds_in_use() {
+        let idle = Muxes {
+            periph_clk_sel: 0,
+            pre_periph_clk_sel: 3,
+            semc_clk_sel: 0,
+            semc_alt_clk_sel: 0,
+            flexspi_clk_sel: 3,
+        };
+        assert!((0..4).all(|pfd| !idle.uses(pfd)));
+
+        let core = Muxes {
+            pre_periph_clk_sel: 2,
+            ..idle
+        };
+        assert!(core.uses(0));
+        assert!(!core.uses(2));
+        // Core runs from the alternate clock
+        assert!(!Muxes {
+            periph_clk_sel: 1,
+            ..core
+        }
+        .uses(0));
+
+        let semc = Muxes {
+            semc_clk_sel: 1,
+            ..idle
+        };
+        assert!(semc.uses(2));
+        assert!(!Muxes {
+            semc_alt_clk_sel: 1,
+            ..semc
+        }
+        .uses(2));
+
+        let flexspi = Muxes {
+            flexspi_clk_sel: 2,
+            ..idle
+        };
+        assert!(flexspi.uses(2));
+        assert!(!flexspi.uses(1));
+        assert!(!flexspi.uses(3));
+    }
+}
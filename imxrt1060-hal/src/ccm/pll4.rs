@@ -0,0 +1,394 @@
+//! Audio PLL (PLL4), and the SAI1 root clock
+//!
+//! PLL4 multiplies the 24MHz oscillator by a fractional loop divider, then divides the
+//! result by a post divider:
+//!
+//! ```text
+//! PLL4 = 24MHz * (loop_divider + num / denom) / post_divider
+//! ```
+//!
+//! The fractional loop divider reaches the frequencies that audio sample rates need,
+//! like multiples of 44.1KHz. Route PLL4 to the SAI1 root clock with
+//! [`sai1_clock()`](fn.sai1_clock.html).
+//!
+//! The HAL doesn't have a SAI driver. Once you've configured the SAI1 root clock, use the
+//! RAL to drive SAI1.
+//!
+//! # Example
+//!
+//! Run PLL4 at 722.5344MHz, then divide it down to 11.2896MHz, which is 256 times 44.1KHz.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::pll4::{self, AudioPllConfig, SaiClockSelect};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let pll4 = peripherals
+//!     .ccm
+//!     .audio_pll
+//!     .configure(
+//!         &mut peripherals.ccm.handle,
+//!         AudioPllConfig {
+//!             loop_divider: 30,
+//!             num: 1056,
+//!             denom: 10_000,
+//!             post_divider: 1,
+//!         },
+//!     )
+//!     .unwrap();
+//! assert_eq!(pll4.hz(), 722_534_400);
+//!
+//! let sai1 = pll4::sai1_clock(&mut peripherals.ccm.handle, SaiClockSelect::Pll4, 4, 16).unwrap();
+//! assert_eq!(sai1.map(|sai1| sai1.hz()), Some(11_289_600));
+//! ```
+
+use super::{
+    clock_gate::Sai1, math, wait_for_lock, Frequency, GateSetting, Handle, PllLockTimeout,
+    OSCILLATOR_FREQUENCY, PLL_LOCK_SPINS,
+};
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg, write_reg};
+
+/// Smallest and largest PLL4 loop dividers
+const LOOP_DIVIDER: core::ops::RangeInclusive<u8> = 27..=54;
+/// Largest numerator and denominator; the fields are 30 bits
+const MAX_FRACTION: u32 = (1 << 30) - 1;
+/// Largest SAI1_CLK_PRED divider
+pub const MAX_SAI_PRED: u8 = 8;
+/// Largest SAI1_CLK_PODF divider
+pub const MAX_SAI_PODF: u8 = 64;
+
+/// PLL4 settings
+///
+/// See the [module documentation](index.html) for the PLL4 frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPllConfig {
+    /// Integer part of the loop divider, from 27 to 54
+    pub loop_divider: u8,
+    /// Numerator of the loop divider's fractional part
+    ///
+    /// Must be less than `denom`.
+    pub num: u32,
+    /// Denominator of the loop divider's fractional part
+    ///
+    /// Must be non-zero, and less than 2^30.
+    pub denom: u32,
+    /// Post divider: 1, 2, 4, 8, or 16
+    pub post_divider: u8,
+}
+
+/// PLL4 errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PllError {
+    /// The loop divider is out of range
+    LoopDivider,
+    /// The fraction's denominator is zero or too large, or the numerator isn't less
+    /// than the denominator
+    Fraction,
+    /// The post divider isn't 1, 2, 4, 8, or 16
+    PostDivider,
+    /// PLL4 didn't lock before the timeout
+    ///
+    /// PLL4 stays bypassed, and outputs the 24MHz oscillator clock.
+    LockTimeout,
+}
+
+impl From<PllLockTimeout> for PllError {
+    fn from(_: PllLockTimeout) -> Self {
+        PllError::LockTimeout
+    }
+}
+
+/// The post divider fields
+///
+/// PLL4 has a post divider in PLL_AUDIO, and another in MISC2. Together, they divide
+/// by up to 16.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PostDivider {
+    /// PLL_AUDIO[POST_DIV_SELECT]
+    post_div_select: u32,
+    /// MISC2[AUDIO_DIV_MSB]
+    msb: u32,
+    /// MISC2[AUDIO_DIV_LSB]
+    lsb: u32,
+}
+
+impl PostDivider {
+    fn new(post_divider: u8) -> Option<Self> {
+        let (post_div_select, msb, lsb) = match post_divider {
+            1 => (0b10, 0, 0),
+            2 => (0b01, 0, 0),
+            4 => (0b00, 0, 0),
+            8 => (0b00, 0, 1),
+            16 => (0b00, 1, 1),
+            _ => return None,
+        };
+        Some(PostDivider {
+            post_div_select,
+            msb,
+            lsb,
+        })
+    }
+
+    fn divider(self) -> u32 {
+        let post_div = match self.post_div_select {
+            0b00 => 4,
+            0b01 => 2,
+            _ => 1,
+        };
+        let misc2_div = match (self.msb, self.lsb) {
+            (0, 0) | (1, 0) => 1,
+            (0, _) => 2,
+            _ => 4,
+        };
+        post_div * misc2_div
+    }
+}
+
+impl AudioPllConfig {
+    /// Returns the PLL4 frequency for this configuration, or an error if the
+    /// configuration is invalid
+    pub fn hz(&self) -> Result<u32, PllError> {
+        self.post().map(|post| {
+            audio_pll_hz(
+                self.loop_divider as u32,
+                self.num,
+                self.denom,
+                post.divider(),
+            )
+        })
+    }
+
+    /// Validates the configuration, and returns the post divider fields
+    fn post(&self) -> Result<PostDivider, PllError> {
+        if !LOOP_DIVIDER.contains(&self.loop_divider) {
+            return Err(PllError::LoopDivider);
+        }
+        if self.denom == 0 || self.denom > MAX_FRACTION || self.num >= self.denom {
+            return Err(PllError::Fraction);
+        }
+        PostDivider::new(self.post_divider).ok_or(PllError::PostDivider)
+    }
+}
+
+/// Computes the PLL4 frequency from the register values
+fn audio_pll_hz(loop_divider: u32, num: u32, denom: u32, post_divider: u32) -> u32 {
+    math::pll_hz(OSCILLATOR_FREQUENCY.0, loop_divider, num, denom) / post_divider
+}
+
+/// Audio PLL (PLL4)
+///
+/// Available as `CCM::audio_pll`. See the [module documentation](index.html) for more
+/// information.
+pub struct PLL4(());
+
+impl PLL4 {
+    pub(super) fn new() -> Self {
+        PLL4(())
+    }
+
+    /// Program PLL4, and wait for it to lock
+    ///
+    /// Returns the PLL4 frequency. Returns an error, without touching PLL4, if `config`
+    /// is invalid. PLL4 is bypassed while it's reprogrammed, so peripherals that use
+    /// PLL4 see the 24MHz oscillator until PLL4 locks.
+    pub fn configure(
+        &mut self,
+        handle: &mut Handle,
+        config: AudioPllConfig,
+    ) -> Result<Frequency, PllError> {
+        let post = config.post()?;
+        let (_, analog) = handle.raw();
+
+        modify_reg!(ral::ccm_analog, analog, PLL_AUDIO, BYPASS_CLK_SRC: 0, BYPASS: 1);
+        write_reg!(ral::ccm_analog, analog, PLL_AUDIO_NUM, A: config.num);
+        write_reg!(ral::ccm_analog, analog, PLL_AUDIO_DENOM, B: config.denom);
+        modify_reg!(
+            ral::ccm_analog,
+            analog,
+            PLL_AUDIO,
+            POWERDOWN: 0,
+            ENABLE: 1,
+            DIV_SELECT: config.loop_divider as u32,
+            POST_DIV_SELECT: post.post_div_select
+        );
+        modify_reg!(
+            ral::ccm_analog,
+            analog,
+            MISC2,
+            AUDIO_DIV_MSB: post.msb,
+            AUDIO_DIV_LSB: post.lsb
+        );
+        wait_for_lock(PLL_LOCK_SPINS, || {
+            read_reg!(ral::ccm_analog, analog, PLL_AUDIO, LOCK) != 0
+        })?;
+        modify_reg!(ral::ccm_analog, analog, PLL_AUDIO, BYPASS: 0);
+
+        Ok(Frequency(audio_pll_hz(
+            config.loop_divider as u32,
+            config.num,
+            config.denom,
+            post.divider(),
+        )))
+    }
+
+    /// Returns the PLL4 frequency, or `None` if PLL4 is off, or not yet locked
+    ///
+    /// A bypassed PLL4 outputs the 24MHz oscillator clock.
+    pub fn frequency(&self, handle: &Handle) -> Option<Frequency> {
+        frequency(&handle.analog)
+    }
+}
+
+/// Reads the PLL4 frequency
+fn frequency(analog: &ral::ccm_analog::Instance) -> Option<Frequency> {
+    let (powerdown, enable, bypass, lock, div_select, post_div_select) = read_reg!(
+        ral::ccm_analog,
+        analog,
+        PLL_AUDIO,
+        POWERDOWN,
+        ENABLE,
+        BYPASS,
+        LOCK,
+        DIV_SELECT,
+        POST_DIV_SELECT
+    );
+    if bypass != 0 {
+        return Some(OSCILLATOR_FREQUENCY);
+    }
+    if powerdown != 0 || enable == 0 || lock == 0 {
+        return None;
+    }
+    let num = read_reg!(ral::ccm_analog, analog, PLL_AUDIO_NUM, A);
+    let denom = read_reg!(ral::ccm_analog, analog, PLL_AUDIO_DENOM, B);
+    let (msb, lsb) = read_reg!(ral::ccm_analog, analog, MISC2, AUDIO_DIV_MSB, AUDIO_DIV_LSB);
+    let post = PostDivider {
+        post_div_select,
+        msb,
+        lsb,
+    };
+    Some(Frequency(audio_pll_hz(
+        div_select,
+        num,
+        denom.max(1),
+        post.divider(),
+    )))
+}
+
+/// SAI1 root clock sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaiClockSelect {
+    /// PLL3 PFD2
+    ///
+    /// Configure PLL3 PFD2 before selecting this clock.
+    Pll3Pfd2,
+    /// Audio PLL (PLL4)
+    ///
+    /// Configure PLL4 with [`PLL4::configure()`](struct.PLL4.html#method.configure)
+    /// before selecting this clock.
+    Pll4,
+}
+
+/// The SAI1 root clock dividers are out of range
+///
+/// The pre divider must be from 1 to [`MAX_SAI_PRED`](constant.MAX_SAI_PRED.html), and
+/// the post divider must be from 1 to [`MAX_SAI_PODF`](constant.MAX_SAI_PODF.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaiDividerError(());
+
+/// Select, and divide, the SAI1 root clock
+///
+/// The SAI1 root clock is `selection / pred / podf`. Returns the SAI1 root clock
+/// frequency, or `None` if the selected source is off. `sai1_clock()` gates SAI1
+/// while it switches the clock, then restores the gate.
+///
+/// Returns an error, without touching any clocks, if a divider is out of range.
+pub fn sai1_clock(
+    handle: &mut Handle,
+    selection: SaiClockSelect,
+    pred: u8,
+    podf: u8,
+) -> Result<Option<Frequency>, SaiDividerError> {
+    if !(1..=MAX_SAI_PRED).contains(&pred) || !(1..=MAX_SAI_PODF).contains(&podf) {
+        return Err(SaiDividerError(()));
+    }
+
+    let gate = handle.gate_setting::<Sai1>();
+    handle.gate::<Sai1>(GateSetting::Off);
+    let (ccm, analog) = handle.raw();
+    let sel = match selection {
+        SaiClockSelect::Pll3Pfd2 => 0b00,
+        SaiClockSelect::Pll4 => 0b10,
+    };
+    modify_reg!(ral::ccm, ccm, CSCMR1, SAI1_CLK_SEL: sel);
+    modify_reg!(
+        ral::ccm,
+        ccm,
+        CS1CDR,
+        SAI1_CLK_PRED: (pred - 1) as u32,
+        SAI1_CLK_PODF: (podf - 1) as u32
+    );
+    let source = match selection {
+        SaiClockSelect::Pll3Pfd2 => pll3_pfd2(analog),
+        SaiClockSelect::Pll4 => frequency(analog),
+    };
+    handle.gate::<Sai1>(gate);
+
+    Ok(source.map(|Frequency(hz)| Frequency(hz / pred as u32 / podf as u32)))
+}
+
+/// Reads the PLL3 PFD2 frequency
+fn pll3_pfd2(analog: &ral::ccm_analog::Instance) -> Option<Frequency> {
+    let (power, lock) = read_reg!(ral::ccm_analog, analog, PLL_USB1, POWER, LOCK);
+    let (frac, gated) = read_reg!(ral::ccm_analog, analog, PFD_480, PFD2_FRAC, PFD2_CLKGATE);
+    if power == 0 || lock == 0 || gated != 0 || frac == 0 {
+        None
+    } else {
+        Some(Frequency(math::pfd_hz(super::PLL3::HZ, frac)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioPllConfig, PllError, PostDivider};
+
+    fn config(loop_divider: u8, num: u32, denom: u32, post_divider: u8) -> AudioPllConfig {
+        AudioPllConfig {
+            loop_divider,
+            num,
+            denom,
+            post_divider,
+        }
+    }
+
+    #[test]
+    fn audio_pll_frequencies() {
+        // 44.1KHz family: 256 * 44.1KHz * 64
+        assert_eq!(config(30, 1056, 10_000, 1).hz(), Ok(722_534_400));
+        // 48KHz family: 256 * 48KHz * 64
+        assert_eq!(config(32, 768, 1000, 1).hz(), Ok(786_432_000));
+        assert_eq!(config(32, 768, 1000, 4).hz(), Ok(196_608_000));
+        assert_eq!(config(32, 768, 1000, 16).hz(), Ok(49_152_000));
+        // Integer loop dividers
+        assert_eq!(config(27, 0, 1, 1).hz(), Ok(648_000_000));
+        assert_eq!(config(54, 0, 1, 2).hz(), Ok(648_000_000));
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        assert_eq!(config(26, 0, 1, 1).hz(), Err(PllError::LoopDivider));
+        assert_eq!(config(55, 0, 1, 1).hz(), Err(PllError::LoopDivider));
+        assert_eq!(config(30, 0, 0, 1).hz(), Err(PllError::Fraction));
+        assert_eq!(config(30, 10, 10, 1).hz(), Err(PllError::Fraction));
+        assert_eq!(config(30, 0, 1 << 30, 1).hz(), Err(PllError::Fraction));
+        assert_eq!(config(30, 0, 1, 3).hz(), Err(PllError::PostDivider));
+        assert_eq!(config(30, 0, 1, 32).hz(), Err(PllError::PostDivider));
+    }
+
+    #[test]
+    fn post_dividers() {
+        for &post_divider in &[1, 2, 4, 8, 16] {
+            let post = PostDivider::new(post_divider).unwrap();
+            assert_eq!(post.divider(), post_divider as u32, "{:?}", post);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! ENET PLL (PLL6), and the ENET reference clocks
+//!
+//! PLL6 runs at 500MHz. It divides that frequency down to three Ethernet reference
+//! clocks:
+//!
+//! - `ref_enetpll0`, for ENET1, at 25MHz, 50MHz, 100MHz, or 125MHz
+//! - `ref_enetpll1`, for ENET2, at the same frequencies
+//! - a fixed 25MHz reference, which can clock an external PHY
+//!
+//! An RMII PHY needs a 50MHz reference clock. Either the i.MX RT drives the clock to the
+//! PHY, from `ref_enetpll0`, or the PHY drives the clock to the i.MX RT. Select the
+//! direction with [`enet_ref_clock()`](fn.enet_ref_clock.html).
+//!
+//! The HAL doesn't have an ENET driver. Once you've configured the clocks, and ungated
+//! [`Enet`](../clock_gate/struct.Enet.html), use the RAL to drive ENET1.
+//!
+//! # Example
+//!
+//! Drive a 50MHz RMII reference clock to the PHY.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::{
+//!     clock_gate::Enet,
+//!     pll6::{self, EnetDiv, EnetPllConfig, Mac, RefClock},
+//!     GateSetting,
+//! };
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+//! let clocks = pll6::enable(
+//!     &mut peripherals.ccm.handle,
+//!     EnetPllConfig {
+//!         enet0_div: EnetDiv::MHZ_50,
+//!         enet1_div: None,
+//!         enable_25m_ref: false,
+//!     },
+//! )
+//! .unwrap();
+//! assert_eq!(clocks.enet0.hz(), 50_000_000);
+//!
+//! pll6::enet_ref_clock(&mut gpr, Mac::Enet1, RefClock::Output);
+//! peripherals.ccm.handle.gate::<Enet>(GateSetting::RunAndWait);
+//! ```
+
+use super::{
+    wait_for_lock, Frequency, Handle, PllLockTimeout, OSCILLATOR_FREQUENCY, PLL_LOCK_SPINS,
+};
+use crate::iomuxc::GPR;
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg, write_reg};
+
+// PLL_ENET fields
+const DIV_SELECT_SHIFT: u32 = 0;
+const ENET2_DIV_SELECT_SHIFT: u32 = 2;
+const POWERDOWN: u32 = 1 << 12;
+/// Enables `ref_enetpll0`
+const ENABLE: u32 = 1 << 13;
+const BYPASS: u32 = 1 << 16;
+/// Enables `ref_enetpll1`
+const ENET2_REF_EN: u32 = 1 << 20;
+const ENET_25M_REF_EN: u32 = 1 << 21;
+const LOCK: u32 = 1 << 31;
+
+/// The fixed reference clock
+const ENET_25M_HZ: u32 = 25_000_000;
+
+/// `ref_enetpll0` and `ref_enetpll1` frequencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+#[allow(non_camel_case_types)] // Easier mapping if the names are consistent
+pub enum EnetDiv {
+    /// 0b00: 25MHz
+    MHZ_25 = 0b00,
+    /// 0b01: 50MHz, for RMII
+    MHZ_50 = 0b01,
+    /// 0b10: 100MHz
+    MHZ_100 = 0b10,
+    /// 0b11: 125MHz, for RGMII
+    MHZ_125 = 0b11,
+}
+
+impl EnetDiv {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0b00 => EnetDiv::MHZ_25,
+            0b01 => EnetDiv::MHZ_50,
+            0b10 => EnetDiv::MHZ_100,
+            _ => EnetDiv::MHZ_125,
+        }
+    }
+
+    fn hz(self) -> u32 {
+        match self {
+            EnetDiv::MHZ_25 => 25_000_000,
+            EnetDiv::MHZ_50 => 50_000_000,
+            EnetDiv::MHZ_100 => 100_000_000,
+            EnetDiv::MHZ_125 => 125_000_000,
+        }
+    }
+}
+
+/// PLL6 settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnetPllConfig {
+    /// The `ref_enetpll0` frequency, for ENET1
+    pub enet0_div: EnetDiv,
+    /// The `ref_enetpll1` frequency, for ENET2, or `None` to leave `ref_enetpll1` off
+    pub enet1_div: Option<EnetDiv>,
+    /// Enable the fixed 25MHz reference clock
+    pub enable_25m_ref: bool,
+}
+
+impl EnetPllConfig {
+    /// Returns the PLL_ENET value for this configuration
+    ///
+    /// PLL6 is powered, and not bypassed.
+    fn pll_enet(&self) -> u32 {
+        let mut pll_enet = ((self.enet0_div as u32) << DIV_SELECT_SHIFT) | ENABLE;
+        if let Some(enet1_div) = self.enet1_div {
+            pll_enet |= ((enet1_div as u32) << ENET2_DIV_SELECT_SHIFT) | ENET2_REF_EN;
+        }
+        if self.enable_25m_ref {
+            pll_enet |= ENET_25M_REF_EN;
+        }
+        pll_enet
+    }
+}
+
+/// The PLL6 reference clock frequencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnetClocks {
+    /// `ref_enetpll0`
+    pub enet0: Frequency,
+    /// `ref_enetpll1`, or `None` if it's off
+    pub enet1: Option<Frequency>,
+    /// The fixed 25MHz reference, or `None` if it's off
+    pub enet_25m: Option<Frequency>,
+}
+
+/// Computes the reference clock frequencies from a PLL_ENET value
+///
+/// Returns `None` if PLL6 is off, or not yet locked. A bypassed PLL6 outputs the 24MHz
+/// oscillator clock.
+fn clocks(pll_enet: u32) -> Option<EnetClocks> {
+    let bypass = pll_enet & BYPASS != 0;
+    if !bypass && (pll_enet & POWERDOWN != 0 || pll_enet & LOCK == 0) {
+        return None;
+    }
+    if pll_enet & ENABLE == 0 {
+        return None;
+    }
+    let hz = |div: EnetDiv| {
+        if bypass {
+            OSCILLATOR_FREQUENCY
+        } else {
+            Frequency(div.hz())
+        }
+    };
+    let enet1 = if pll_enet & ENET2_REF_EN != 0 {
+        Some(hz(EnetDiv::from_bits(pll_enet >> ENET2_DIV_SELECT_SHIFT)))
+    } else {
+        None
+    };
+    let enet_25m = if pll_enet & ENET_25M_REF_EN != 0 {
+        Some(if bypass {
+            OSCILLATOR_FREQUENCY
+        } else {
+            Frequency(ENET_25M_HZ)
+        })
+    } else {
+        None
+    };
+    Some(EnetClocks {
+        enet0: hz(EnetDiv::from_bits(pll_enet >> DIV_SELECT_SHIFT)),
+        enet1,
+        enet_25m,
+    })
+}
+
+/// Power PLL6, enable its reference clocks, and wait for it to lock
+///
+/// Returns the reference clock frequencies. PLL6 is bypassed until it locks, so
+/// peripherals that use PLL6 see the 24MHz oscillator until then. If PLL6 doesn't lock,
+/// `enable()` returns an error, and PLL6 stays bypassed.
+pub fn enable(handle: &mut Handle, config: EnetPllConfig) -> Result<EnetClocks, PllLockTimeout> {
+    let (_, analog) = handle.raw();
+    let pll_enet = config.pll_enet();
+    write_reg!(
+        ral::ccm_analog,
+        analog,
+        PLL_ENET,
+        pll_enet | BYPASS | POWERDOWN
+    );
+    write_reg!(ral::ccm_analog, analog, PLL_ENET, pll_enet | BYPASS);
+    wait_for_lock(PLL_LOCK_SPINS, || {
+        read_reg!(ral::ccm_analog, analog, PLL_ENET) & LOCK != 0
+    })?;
+    write_reg!(ral::ccm_analog, analog, PLL_ENET, pll_enet);
+    // Unwrap OK: PLL6 is powered, locked, and ref_enetpll0 is enabled
+    Ok(clocks(read_reg!(ral::ccm_analog, analog, PLL_ENET)).unwrap())
+}
+
+/// Returns the PLL6 reference clock frequencies, or `None` if PLL6 or `ref_enetpll0` is
+/// off, or PLL6 isn't yet locked
+pub fn frequency(handle: &Handle) -> Option<EnetClocks> {
+    clocks(read_reg!(ral::ccm_analog, handle.analog, PLL_ENET))
+}
+
+/// An ENET MAC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mac {
+    /// ENET1, using `ref_enetpll0`
+    Enet1,
+    /// ENET2, using `ref_enetpll1`
+    Enet2,
+}
+
+/// The direction of the ENET reference clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefClock {
+    /// PLL6 clocks the MAC, and drives the clock out of the ENET_TX_CLK pad, to the PHY
+    Output,
+    /// The PHY drives the clock into the ENET_TX_CLK pad, and the pad clocks the MAC
+    Input,
+}
+
+/// Returns the GPR1 `(mask, value)` for an ENET reference clock direction
+fn gpr1(mac: Mac, direction: RefClock) -> (u32, u32) {
+    // ENETn_CLK_SEL, and ENETn_TX_CLK_DIR
+    let (clk_sel, tx_clk_dir) = match mac {
+        Mac::Enet1 => (1 << 13, 1 << 17),
+        Mac::Enet2 => (1 << 14, 1 << 18),
+    };
+    let value = match direction {
+        RefClock::Output => tx_clk_dir,
+        RefClock::Input => clk_sel,
+    };
+    (clk_sel | tx_clk_dir, value)
+}
+
+/// Select the direction of a MAC's reference clock
+///
+/// `enet_ref_clock()` only changes IOMUXC_GPR_GPR1. You still need to configure the
+/// ENET_TX_CLK pad for its ENET_REF_CLK alternate. Set the pad's software input on (SION)
+/// bit, so that the MAC sees the clock on the pad.
+pub fn enet_ref_clock(gpr: &mut GPR, mac: Mac, direction: RefClock) {
+    let (mask, value) = gpr1(mac, direction);
+    modify_reg!(ral::iomuxc_gpr, gpr.0, GPR1, |gpr1| (gpr1 & !mask) | value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clocks, gpr1, EnetClocks, EnetDiv, EnetPllConfig, Mac, RefClock, BYPASS, LOCK, POWERDOWN,
+    };
+    use crate::ccm::Frequency;
+
+    const RMII: EnetPllConfig = EnetPllConfig {
+        enet0_div: EnetDiv::MHZ_50,
+        enet1_div: None,
+        enable_25m_ref: false,
+    };
+
+    #[test]
+    fn pll_enet_values() {
+        assert_eq!(RMII.pll_enet(), 0x2001);
+        let all = EnetPllConfig {
+            enet0_div: EnetDiv::MHZ_125,
+            enet1_div: Some(EnetDiv::MHZ_25),
+            enable_25m_ref: true,
+        };
+        assert_eq!(all.pll_enet(), 0x0030_2003);
+        let enet2 = EnetPllConfig {
+            enet1_div: Some(EnetDiv::MHZ_100),
+            ..RMII
+        };
+        assert_eq!(enet2.pll_enet(), 0x0010_2009);
+    }
+
+    #[test]
+    fn reference_clocks() {
+        assert_eq!(clocks(RMII.pll_enet()), None, "Not locked");
+        assert_eq!(clocks(RMII.pll_enet() | LOCK | POWERDOWN), None);
+        assert_eq!(
+            clocks(RMII.pll_enet() | LOCK),
+            Some(EnetClocks {
+                enet0: Frequency(50_000_000),
+                enet1: None,
+                enet_25m: None,
+            })
+        );
+        let all = EnetPllConfig {
+            enet0_div: EnetDiv::MHZ_25,
+            enet1_div: Some(EnetDiv::MHZ_125),
+            enable_25m_ref: true,
+        };
+        assert_eq!(
+            clocks(all.pll_enet() | LOCK),
+            Some(EnetClocks {
+                enet0: Frequency(25_000_000),
+                enet1: Some(Frequency(125_000_000)),
+                enet_25m: Some(Frequency(25_000_000)),
+            })
+        );
+        assert_eq!(
+            clocks(all.pll_enet() | BYPASS),
+            Some(EnetClocks {
+                enet0: Frequency(24_000_000),
+                enet1: Some(Frequency(24_000_000)),
+                enet_25m: Some(Frequency(24_000_000)),
+            })
+        );
+    }
+
+    #[test]
+    fn gpr1_bits() {
+        assert_eq!(
+            gpr1(Mac::Enet1, RefClock::Output),
+            (0x0002_2000, 0x0002_0000)
+        );
+        assert_eq!(gpr1(Mac::Enet1, RefClock::Input), (0x0002_2000, 0x2000));
+        assert_eq!(
+            gpr1(Mac::Enet2, RefClock::Output),
+            (0x0004_4000, 0x0004_0000)
+        );
+        assert_eq!(gpr1(Mac::Enet2, RefClock::Input), (0x0004_4000, 0x4000));
+    }
+}
@@ -0,0 +1,474 @@
+//! Step clock, which runs the core while PLL1 relocks
+//!
+//! The core clock comes from one of two branches. The PRE_PERIPH branch selects PLL1,
+//! PLL2, or a PLL2 PFD. The PERIPH_CLK2 branch selects PLL3, or the 24MHz oscillator.
+//! Only the multiplexer between the two branches switches without glitches, so the HAL
+//! only changes a branch while the core runs from the other branch.
+//!
+//! [`with_step_clock()`](fn.with_step_clock.html) moves the core to the step clock, runs
+//! your closure, and moves the core back to its previous clock.
+//! [`PLL1::set_arm_clock()`](../struct.PLL1.html#method.set_arm_clock) and
+//! [`PLL1::set_operating_point()`](../struct.PLL1.html#method.set_operating_point) use the
+//! same step clock while PLL1 relocks. Select the step clock with
+//! [`Handle::set_step_clock()`](../struct.Handle.html#method.set_step_clock).
+//!
+//! # Interrupt latency
+//!
+//! The HAL doesn't disable interrupts while the core runs from the step clock. An
+//! interrupt that runs in that window runs at the step clock frequency, so anything that
+//! counts core cycles—SysTick delays, DWT cycle counts, bit-banged protocols—runs slower
+//! than expected. PLL1 takes about 470us to lock. To keep those interrupts out of the
+//! window, call `set_arm_clock()` in a critical section; interrupts then wait up to the
+//! lock time, plus the time to switch the clocks. To shrink the slowdown instead, select a
+//! fast step clock, like a PLL2 PFD at 396MHz.
+//!
+//! The AHB and IPG dividers still apply to the step clock. The step clock must not run
+//! the core faster than VDD_SOC allows. Clock listeners don't learn about the step clock.
+//!
+//! # Example
+//!
+//! Run the core from PLL2 PFD2 while PLL1 relocks.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::{self, StepClock};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let (_, _, mut pfd2, _) = peripherals.ccm.pll2.split();
+//! pfd2.set_frac(&mut peripherals.ccm.handle, 24).unwrap();
+//! pfd2.ungate(&mut peripherals.ccm.handle);
+//! let pfd2 = pfd2.into_clock(&peripherals.ccm.handle).unwrap();
+//! peripherals.ccm.handle.set_step_clock(StepClock::Pll2Pfd2(pfd2));
+//! ccm::with_step_clock(&mut peripherals.ccm.handle, |hz| {
+//!     // The core runs from PFD2, divided by the AHB divider
+//!     assert!(hz.hz() <= pfd2.frequency().hz());
+//! });
+//! ```
+
+use super::{math, pll2::PfdClock, Frequency, Handle, OSCILLATOR_FREQUENCY, PLL3};
+use crate::iomuxc::consts::{U0, U2};
+use imxrt_ral as ral;
+use ral::{modify_reg, read_reg};
+
+/// Step clock sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepClock {
+    /// The 24MHz oscillator
+    Oscillator,
+    /// PLL3, divided down to 120MHz
+    ///
+    /// If PLL3 isn't running, the HAL uses the oscillator. This is the default.
+    Pll3,
+    /// PLL2 PFD0
+    ///
+    /// Acquire the clock from an ungated PFD with
+    /// [`Pfd::into_clock()`](../pll2/struct.Pfd.html#method.into_clock).
+    Pll2Pfd0(PfdClock<U0>),
+    /// PLL2 PFD2
+    ///
+    /// See [`Pll2Pfd0`](#variant.Pll2Pfd0) for more information.
+    Pll2Pfd2(PfdClock<U2>),
+}
+
+impl Default for StepClock {
+    fn default() -> Self {
+        StepClock::Pll3
+    }
+}
+
+/// PERIPH_CLK_SEL, selecting the PRE_PERIPH branch
+pub(super) const PRE_PERIPH: u32 = 0;
+/// PERIPH_CLK_SEL, selecting the PERIPH_CLK2 branch
+pub(super) const PERIPH_CLK2: u32 = 1;
+/// PRE_PERIPH_CLK_SEL for PLL1, divided by ARM_PODF
+pub(super) const ARM_PLL: u32 = 3;
+
+/// PERIPH_CLK2_SEL and PERIPH_CLK2_PODF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PeriphClk2 {
+    sel: u32,
+    podf: u32,
+}
+
+/// The oscillator, undivided. It always runs, so it's safe to pass through.
+const OSC: PeriphClk2 = PeriphClk2 { sel: 1, podf: 0 };
+/// PLL3, divided by 4
+const PLL3_120MHZ: PeriphClk2 = PeriphClk2 { sel: 0, podf: 3 };
+
+impl PeriphClk2 {
+    fn hz(self) -> u32 {
+        let hz = if self.sel == 0 {
+            PLL3::HZ
+        } else {
+            OSCILLATOR_FREQUENCY.0
+        };
+        math::divide(hz, self.podf)
+    }
+}
+
+/// The core clock multiplexers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CoreMux {
+    pub(super) periph_clk_sel: u32,
+    pub(super) pre_periph_clk_sel: u32,
+    periph_clk2: PeriphClk2,
+}
+
+impl CoreMux {
+    fn read(ccm: &ral::ccm::Instance) -> Self {
+        let (periph_clk_sel, podf) =
+            read_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK_SEL, PERIPH_CLK2_PODF);
+        let (pre_periph_clk_sel, sel) =
+            read_reg!(ral::ccm, ccm, CBCMR, PRE_PERIPH_CLK_SEL, PERIPH_CLK2_SEL);
+        CoreMux {
+            periph_clk_sel,
+            pre_periph_clk_sel,
+            periph_clk2: PeriphClk2 { sel, podf },
+        }
+    }
+
+    /// Returns the multiplexers that run the core from `source`, and the step clock
+    /// frequency, before the AHB divider
+    ///
+    /// If the core already runs from the PERIPH_CLK2 branch, and `source` is on that branch,
+    /// the core stays on its current clock.
+    fn step(self, source: StepClock) -> (Self, u32) {
+        let pfd = |pre_periph_clk_sel, hz: Frequency| {
+            let mux = CoreMux {
+                periph_clk_sel: PRE_PERIPH,
+                pre_periph_clk_sel,
+                ..self
+            };
+            (mux, hz.0)
+        };
+        let periph_clk2 = match source {
+            StepClock::Pll2Pfd0(clock) => return pfd(2, clock.frequency()),
+            StepClock::Pll2Pfd2(clock) => return pfd(1, clock.frequency()),
+            _ if self.periph_clk_sel == PERIPH_CLK2 => self.periph_clk2,
+            StepClock::Pll3 => PLL3_120MHZ,
+            StepClock::Oscillator => OSC,
+        };
+        let mux = CoreMux {
+            periph_clk_sel: PERIPH_CLK2,
+            periph_clk2,
+            ..self
+        };
+        (mux, periph_clk2.hz())
+    }
+}
+
+/// A multiplexer write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MuxWrite {
+    PrePeriph(u32),
+    PeriphClk2(PeriphClk2),
+    Select(u32),
+}
+
+/// The most writes in a transition
+const MAX_WRITES: usize = 5;
+
+/// The writes that move the multiplexers from one state to another
+///
+/// Each write only changes a branch that doesn't drive the core.
+struct Transition {
+    state: CoreMux,
+    writes: [Option<MuxWrite>; MAX_WRITES],
+    len: usize,
+}
+
+impl Transition {
+    /// Plan the writes from `from` to `to`
+    ///
+    /// If the branch that drives the core has to change, the core passes through the
+    /// other branch. It passes through the oscillator, or through `to`'s PRE_PERIPH
+    /// selection, so `to` must only select running clocks.
+    fn new(from: CoreMux, to: CoreMux) -> Self {
+        let mut plan = Transition {
+            state: from,
+            writes: [None; MAX_WRITES],
+            len: 0,
+        };
+        if plan.selected_differs(to) {
+            if plan.state.periph_clk_sel == PRE_PERIPH {
+                let bypass = if to.periph_clk_sel == PERIPH_CLK2 {
+                    to.periph_clk2
+                } else {
+                    OSC
+                };
+                plan.periph_clk2(bypass);
+                plan.push(MuxWrite::Select(PERIPH_CLK2));
+            } else {
+                plan.pre_periph(to.pre_periph_clk_sel);
+                plan.push(MuxWrite::Select(PRE_PERIPH));
+            }
+        }
+        plan.unselected(to);
+        if plan.state.periph_clk_sel != to.periph_clk_sel {
+            plan.push(MuxWrite::Select(to.periph_clk_sel));
+        }
+        plan.unselected(to);
+        plan
+    }
+
+    /// Returns `true` if the branch that drives the core isn't what `to` wants
+    fn selected_differs(&self, to: CoreMux) -> bool {
+        if self.state.periph_clk_sel == PRE_PERIPH {
+            self.state.pre_periph_clk_sel != to.pre_periph_clk_sel
+        } else {
+            self.state.periph_clk2 != to.periph_clk2
+        }
+    }
+
+    /// Set the branch that doesn't drive the core to its value in `to`
+    fn unselected(&mut self, to: CoreMux) {
+        if self.state.periph_clk_sel == PRE_PERIPH {
+            self.periph_clk2(to.periph_clk2);
+        } else {
+            self.pre_periph(to.pre_periph_clk_sel);
+        }
+    }
+
+    fn periph_clk2(&mut self, periph_clk2: PeriphClk2) {
+        if self.state.periph_clk2 != periph_clk2 {
+            self.push(MuxWrite::PeriphClk2(periph_clk2));
+        }
+    }
+
+    fn pre_periph(&mut self, pre_periph_clk_sel: u32) {
+        if self.state.pre_periph_clk_sel != pre_periph_clk_sel {
+            self.push(MuxWrite::PrePeriph(pre_periph_clk_sel));
+        }
+    }
+
+    fn push(&mut self, write: MuxWrite) {
+        match write {
+            MuxWrite::PrePeriph(sel) => {
+                debug_assert_eq!(self.state.periph_clk_sel, PERIPH_CLK2);
+                self.state.pre_periph_clk_sel = sel;
+            }
+            MuxWrite::PeriphClk2(periph_clk2) => {
+                debug_assert_eq!(self.state.periph_clk_sel, PRE_PERIPH);
+                self.state.periph_clk2 = periph_clk2;
+            }
+            MuxWrite::Select(sel) => self.state.periph_clk_sel = sel,
+        }
+        self.writes[self.len] = Some(write);
+        self.len += 1;
+    }
+
+    fn writes(&self) -> impl Iterator<Item = MuxWrite> + '_ {
+        self.writes.iter().flatten().copied()
+    }
+}
+
+/// Move the multiplexers from `from` to `to`
+fn switch(ccm: &ral::ccm::Instance, from: CoreMux, to: CoreMux) {
+    for write in Transition::new(from, to).writes() {
+        match write {
+            MuxWrite::PrePeriph(sel) => {
+                modify_reg!(ral::ccm, ccm, CBCMR, PRE_PERIPH_CLK_SEL: sel);
+            }
+            MuxWrite::PeriphClk2(PeriphClk2 { sel, podf }) => {
+                modify_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK2_PODF: podf);
+                modify_reg!(ral::ccm, ccm, CBCMR, PERIPH_CLK2_SEL: sel);
+                while read_reg!(ral::ccm, ccm, CDHIPR, PERIPH2_CLK_SEL_BUSY) > 0 {
+                    #[allow(deprecated)]
+                    core::sync::atomic::spin_loop_hint();
+                }
+            }
+            MuxWrite::Select(sel) => {
+                modify_reg!(ral::ccm, ccm, CBCDR, PERIPH_CLK_SEL: sel);
+                while read_reg!(ral::ccm, ccm, CDHIPR, PERIPH_CLK_SEL_BUSY) > 0 {
+                    #[allow(deprecated)]
+                    core::sync::atomic::spin_loop_hint();
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if PLL3 runs, and drives its PERIPH_CLK2 output
+fn pll3_running(ccm_analog: &ral::ccm_analog::Instance) -> bool {
+    let (enable, en_usb_clks, power, lock) = read_reg!(
+        ral::ccm_analog,
+        ccm_analog,
+        PLL_USB1,
+        ENABLE,
+        EN_USB_CLKS,
+        POWER,
+        LOCK
+    );
+    enable > 0 && en_usb_clks > 0 && power > 0 && lock > 0
+}
+
+/// Run the core from the step clock
+///
+/// Returns the multiplexers from before the switch, and the core frequency.
+pub(super) fn enter(
+    ccm: &ral::ccm::Instance,
+    ccm_analog: &ral::ccm_analog::Instance,
+    source: StepClock,
+) -> (CoreMux, Frequency) {
+    let source = match source {
+        StepClock::Pll3 if !pll3_running(ccm_analog) => StepClock::Oscillator,
+        source => source,
+    };
+    let saved = CoreMux::read(ccm);
+    let (step, hz) = saved.step(source);
+    log::debug!("Running the core from the {:?} step clock", source);
+    switch(ccm, saved, step);
+    let ahb_podf = read_reg!(ral::ccm, ccm, CBCDR, AHB_PODF);
+    (saved, Frequency(math::divide(hz, ahb_podf)))
+}
+
+/// Move the core from the step clock to `to`
+pub(super) fn leave(ccm: &ral::ccm::Instance, to: CoreMux) {
+    switch(ccm, CoreMux::read(ccm), to);
+}
+
+/// Run `f` while the core runs from the step clock
+///
+/// `with_step_clock()` passes the core frequency to `f`. Once `f` returns, the core
+/// returns to its previous clock. See the [module documentation](index.html)
+/// for the interrupt latency implications.
+pub fn with_step_clock<R>(handle: &mut Handle, f: impl FnOnce(Frequency) -> R) -> R {
+    let source = handle.step_clock;
+    let (ccm, ccm_analog) = handle.raw();
+    let (saved, hz) = enter(ccm, ccm_analog, source);
+    let result = f(hz);
+    leave(ccm, saved);
+    result
+}
+
+impl Handle {
+    /// Select the step clock
+    ///
+    /// [`with_step_clock()`](step_clock/fn.with_step_clock.html), and the PLL1 functions,
+    /// use the step clock while PLL1 relocks. The default step clock is
+    /// [`StepClock::Pll3`](step_clock/enum.StepClock.html#variant.Pll3).
+    pub fn set_step_clock(&mut self, source: StepClock) {
+        self.step_clock = source;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CoreMux, MuxWrite, PeriphClk2, Transition, ARM_PLL, OSC, PERIPH_CLK2, PLL3_120MHZ,
+        PRE_PERIPH,
+    };
+
+    /// The core runs from PLL1; PERIPH_CLK2 has its reset value
+    const PLL1: CoreMux = CoreMux {
+        periph_clk_sel: PRE_PERIPH,
+        pre_periph_clk_sel: ARM_PLL,
+        periph_clk2: PeriphClk2 { sel: 0, podf: 0 },
+    };
+
+    /// Asserts that the transition from `from` to `to` makes `expected` writes
+    fn assert_writes(from: CoreMux, to: CoreMux, expected: &[MuxWrite]) {
+        let plan = Transition::new(from, to);
+        assert_eq!(plan.state, to);
+        assert!(plan.writes().eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn periph_clk2_and_back() {
+        let step = CoreMux {
+            periph_clk_sel: PERIPH_CLK2,
+            periph_clk2: PLL3_120MHZ,
+            ..PLL1
+        };
+        assert_writes(
+            PLL1,
+            step,
+            &[
+                MuxWrite::PeriphClk2(PLL3_120MHZ),
+                MuxWrite::Select(PERIPH_CLK2),
+            ],
+        );
+        assert_writes(
+            step,
+            PLL1,
+            &[
+                MuxWrite::Select(PRE_PERIPH),
+                MuxWrite::PeriphClk2(PLL1.periph_clk2),
+            ],
+        );
+    }
+
+    #[test]
+    fn pfd_passes_through_the_oscillator() {
+        let step = CoreMux {
+            pre_periph_clk_sel: 1,
+            ..PLL1
+        };
+        let through_osc = |pre_periph_clk_sel| {
+            [
+                MuxWrite::PeriphClk2(OSC),
+                MuxWrite::Select(PERIPH_CLK2),
+                MuxWrite::PrePeriph(pre_periph_clk_sel),
+                MuxWrite::Select(PRE_PERIPH),
+                MuxWrite::PeriphClk2(PLL1.periph_clk2),
+            ]
+        };
+        assert_writes(PLL1, step, &through_osc(1));
+        assert_writes(step, PLL1, &through_osc(ARM_PLL));
+    }
+
+    #[test]
+    fn already_on_periph_clk2() {
+        let alternate = CoreMux {
+            periph_clk_sel: PERIPH_CLK2,
+            periph_clk2: OSC,
+            ..PLL1
+        };
+        // PERIPH_CLK2 sources keep the current clock
+        for source in &[super::StepClock::Oscillator, super::StepClock::Pll3] {
+            let (step, hz) = alternate.step(*source);
+            assert_eq!(step, alternate);
+            assert_eq!(hz, 24_000_000);
+            assert_writes(alternate, step, &[]);
+        }
+        // Relocked PLL1, after a lock timeout
+        let relocked = CoreMux {
+            periph_clk_sel: PRE_PERIPH,
+            ..alternate
+        };
+        assert_writes(alternate, relocked, &[MuxWrite::Select(PRE_PERIPH)]);
+    }
+
+    #[test]
+    fn periph_clk2_sources() {
+        let (step, hz) = PLL1.step(super::StepClock::Pll3);
+        assert_eq!(step.periph_clk2, PLL3_120MHZ);
+        assert_eq!(hz, 120_000_000);
+        let (step, hz) = PLL1.step(super::StepClock::Oscillator);
+        assert_eq!(step.periph_clk2, OSC);
+        assert_eq!(hz, 24_000_000);
+    }
+
+    #[test]
+    fn every_transition_is_glitch_free() {
+        const BRANCHES: [PeriphClk2; 3] = [PeriphClk2 { sel: 0, podf: 0 }, PLL3_120MHZ, OSC];
+        let states = || {
+            [PRE_PERIPH, PERIPH_CLK2]
+                .iter()
+                .flat_map(move |&periph_clk_sel| {
+                    (0..4).flat_map(move |pre_periph_clk_sel| {
+                        BRANCHES.iter().map(move |&periph_clk2| CoreMux {
+                            periph_clk_sel,
+                            pre_periph_clk_sel,
+                            periph_clk2,
+                        })
+                    })
+                })
+        };
+        // Transition::push asserts that it only changes unselected branches
+        for from in states() {
+            for to in states() {
+                assert_eq!(Transition::new(from, to).state, to);
+            }
+        }
+    }
+}
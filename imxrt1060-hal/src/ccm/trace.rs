@@ -0,0 +1,94 @@
+//! Trace root clock, for SWO and ETM
+//!
+//! The trace root clock drives the TPIU, which serializes ITM and ETM data. A debugger
+//! computes the SWO baud rate from the TPIU clock, so configure the trace clock before you
+//! configure the [`itm`](../../itm/index.html) prescaler.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm::trace::{self, TraceClockSelect};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let hz = trace::trace_clock(&mut peripherals.ccm.handle, TraceClockSelect::Pll2, 4).unwrap();
+//! assert_eq!(hz.hz(), 132_000_000);
+//! ```
+
+use super::{clock_gate::Trace, pll2::PfdClock, Frequency, GateSetting, Handle};
+use crate::iomuxc::consts::{U0, U1, U2};
+use imxrt_ral as ral;
+use ral::modify_reg;
+
+/// Largest TRACE_PODF divider
+pub const MAX_TRACE_PODF: u8 = 4;
+
+/// Trace root clock sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceClockSelect {
+    /// System PLL (PLL2)
+    Pll2,
+    /// PLL2 PFD0
+    ///
+    /// Acquire the clock from an ungated PFD with
+    /// [`Pfd::into_clock()`](../pll2/struct.Pfd.html#method.into_clock).
+    Pll2Pfd0(PfdClock<U0>),
+    /// PLL2 PFD1
+    ///
+    /// See [`Pll2Pfd0`](#variant.Pll2Pfd0) for more information.
+    Pll2Pfd1(PfdClock<U1>),
+    /// PLL2 PFD2
+    ///
+    /// See [`Pll2Pfd0`](#variant.Pll2Pfd0) for more information.
+    Pll2Pfd2(PfdClock<U2>),
+}
+
+impl From<TraceClockSelect> for Frequency {
+    fn from(selection: TraceClockSelect) -> Self {
+        match selection {
+            TraceClockSelect::Pll2 => Frequency(528_000_000),
+            TraceClockSelect::Pll2Pfd0(pfd) => pfd.frequency(),
+            TraceClockSelect::Pll2Pfd1(pfd) => pfd.frequency(),
+            TraceClockSelect::Pll2Pfd2(pfd) => pfd.frequency(),
+        }
+    }
+}
+
+/// The trace root clock divider is outside of 1 and
+/// [`MAX_TRACE_PODF`](constant.MAX_TRACE_PODF.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceDividerError(());
+
+/// Select, and divide, the trace root clock
+///
+/// The trace root clock is `selection / podf`. `trace_clock()` gates the trace clock while
+/// it switches the clock, then restores the trace clock gate's previous setting. If the
+/// trace clock was gated, ungate it with [`Handle::gate()`](../struct.Handle.html#method.gate)
+/// and [`clock_gate::Trace`](../clock_gate/enum.Trace.html) before you use the ITM.
+/// Returns the trace root clock frequency.
+///
+/// Returns an error, without touching any clocks, if `podf` is out of range.
+pub fn trace_clock(
+    handle: &mut Handle,
+    selection: TraceClockSelect,
+    podf: u8,
+) -> Result<Frequency, TraceDividerError> {
+    if !(1..=MAX_TRACE_PODF).contains(&podf) {
+        return Err(TraceDividerError(()));
+    }
+
+    let setting = handle.gate_setting::<Trace>();
+    handle.gate::<Trace>(GateSetting::Off);
+    let (ccm, _) = handle.raw();
+    let sel = match selection {
+        TraceClockSelect::Pll2 => 0b00,
+        TraceClockSelect::Pll2Pfd2(_) => 0b01,
+        TraceClockSelect::Pll2Pfd0(_) => 0b10,
+        TraceClockSelect::Pll2Pfd1(_) => 0b11,
+    };
+    modify_reg!(ral::ccm, ccm, CBCMR, TRACE_CLK_SEL: sel);
+    modify_reg!(ral::ccm, ccm, CSCDR1, TRACE_PODF: (podf - 1) as u32);
+    handle.gate::<Trace>(setting);
+
+    let Frequency(hz) = Frequency::from(selection);
+    Ok(Frequency(hz / podf as u32))
+}
@@ -50,11 +50,12 @@
 //! // SPI setup...
 //! //
 //!
-//! let (_, _, _, spi4_builder) = peripherals.spi.clock(
+//! let lpspi_clock = imxrt1060_hal::ccm::spi::lpspi_clock(
 //!     &mut peripherals.ccm.handle,
 //!     imxrt1060_hal::ccm::spi::ClockSelect::Pll2,
 //!     imxrt1060_hal::ccm::spi::PrescalarSelect::LPSPI_PODF_5,
 //! );
+//! let (_, _, _, spi4_builder) = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
 //!
 //! let mut spi4 = spi4_builder.build(
 //!     peripherals.iomuxc.b0.p02,
@@ -96,7 +97,7 @@
 //! // These adapters will 'own' the statically-allocated memory. See the
 //! // Linear and Circular docs for more information.
 //! let mut tx_buffer = Circular::new(&TX_BUFFER.0).unwrap();
-//! let mut rx_buffer = Linear::new(&RX_BUFFER).unwrap();
+//! let mut rx_buffer = Linear::try_new(&RX_BUFFER).unwrap();
 //!
 //! // Send 6 elements, and expect to receive 6 elements
 //! for v in 1..=6 {
@@ -137,29 +138,107 @@
 //!
 //! See the [`Memcpy`](struct.Memcpy.html#example) documentation for an example of DMA-powered memcpy.
 //!
+//! # Interrupts
+//!
+//! Each DMA interrupt serves two channels: `DMA[n]_DMA[n+16]` handles channels n and n + 16.
+//! [`on_dma_interrupt()`](fn.on_dma_interrupt.html) tells you which of the two channels
+//! signaled, and clears their interrupt flags. [`dispatch()`](fn.dispatch.html) does the
+//! same, then calls any handler that you registered with [`set_handler()`](fn.set_handler.html).
+//!
+//! Neither function depends on an interrupt framework. Call them from a `cortex-m-rt`
+//! `#[interrupt]` handler, or from an RTIC task that's bound to the DMA interrupt.
+//!
+//! ```no_run
+//! use imxrt1060_hal::{dma, ral::interrupt};
+//!
+//! // Channels 7 and 23 share the DMA7_DMA23 interrupt
+//! fn uart_rx_complete() { /* ... */ }
+//! fn spi_tx_complete() { /* ... */ }
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn DMA7_DMA23() {
+//!     let fired = dma::on_dma_interrupt(7);
+//!     if fired.contains(7) {
+//!         uart_rx_complete();
+//!     }
+//!     if fired.contains(23) {
+//!         spi_tx_complete();
+//!     }
+//! }
+//!
+//! # unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::DMA7_DMA23) };
+//! ```
+//!
+//! With registered handlers, the interrupt handler only dispatches:
+//!
+//! ```no_run
+//! use imxrt1060_hal::{dma, ral::interrupt};
+//!
+//! fn uart_rx_complete() { /* ... */ }
+//! fn spi_tx_complete() { /* ... */ }
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn DMA7_DMA23() {
+//!     dma::dispatch(7);
+//! }
+//!
+//! dma::set_handler(7, Some(uart_rx_complete));
+//! dma::set_handler(23, Some(spi_tx_complete));
+//! unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::DMA7_DMA23) };
+//! ```
+//!
+//! Since these functions clear the channels' interrupt flags, you don't need to clear them
+//! through the `Memcpy` or `Peripheral` that owns the channel. They don't check for, or clear,
+//! channel errors.
+//!
 //! # Notes on Data Cache
 //!
-//! If your i.MX RT system is using a data cache (DCache), you're responsible for issuing memory barriers,
-//! and flushing any cached buffers, for the DMA controller. More generally, you're responsible for placing
-//! your DMA buffers into memory regions that the DMA controller can use.
+//! If your i.MX RT system is using a data cache (DCache), enable the `"dcache"` feature. With the feature,
+//! [`Linear`](struct.Linear.html) and [`Circular`](struct.Circular.html) buffers clean source memory before
+//! a transfer, and invalidate destination memory after a transfer. Buffers in tightly coupled memory (TCM)
+//! are not cached, so the HAL skips maintenance for DTCM and ITCM buffers.
+//!
+//! Without the `"dcache"` feature, you're responsible for issuing memory barriers, and flushing any cached
+//! buffers, for the DMA controller. More generally, you're responsible for placing
+//! your DMA buffers into memory regions that the DMA controller can use. See the [`Buffer`](struct.Buffer.html#placement)
+//! documentation for placement and alignment guarantees.
 //!
 //! ## TODO
 //!
 //! - Channel arbitration modes
-//! - Channel grouping
 //! - Channel priority, and channel priority swapping
 //! - Channel chaining
 
-mod buffer;
+pub(crate) mod buffer;
+pub(crate) mod cache;
+pub(crate) mod channel;
+mod interrupts;
 mod memcpy;
 pub(crate) mod peripheral;
+mod scope;
+mod statics;
 
 use imxrt_dma::Transfer;
 pub use imxrt_dma::{Channel, Element, ErrorStatus};
 
-pub use buffer::{Buffer, Circular, CircularError, Drain, Linear, ReadHalf, WriteHalf};
-pub use memcpy::Memcpy;
-pub use peripheral::{helpers::*, Peripheral};
+pub use buffer::{
+    Buffer, Circular, CircularError, Drain, Linear, LinearError, ReadHalf, ReadOnly, WriteHalf,
+};
+pub use cache::CACHE_LINE_SIZE;
+pub use channel::{
+    Attributes, ChannelExt, ChannelState, ControlFlags, ControlStatus, PeriodicTriggerError,
+    TcdSnapshot,
+};
+pub use interrupts::{
+    dispatch, on_dma_interrupt, set_handler, vector_for, ChannelInterrupts, VECTOR_COUNT,
+};
+pub use memcpy::{Memcpy, Progress};
+pub use peripheral::{helpers::*, Peripheral, ReceiveTimeout};
+pub use scope::{scope, Scope, ScopedTransfer};
+
+pub use crate::buffers;
+#[doc(hidden)]
+pub use statics as __statics;
 
 use crate::{ccm, ral};
 
@@ -176,6 +255,18 @@ pub enum Error {
     ScheduledTransfer,
     /// Error setting up the DMA transfer
     Setup(ErrorStatus),
+    /// The channel already had an error before the transfer was scheduled
+    ///
+    /// The error status describes the earlier error. Clear the error, and try again.
+    PreexistingError(ErrorStatus),
+    /// The source and destination of the transfer disagree on the element size
+    IncompatibleElements,
+    /// The chunk size does not evenly divide the buffer, or it's too large
+    InvalidChunkSize,
+    /// Every buffer filled before the user took one, so there was nowhere to put new data
+    Overrun,
+    /// The transfer has more elements than the DMA's 32,767 major loop iterations
+    TooManyElements,
 }
 
 /// Helper symbol to support DMA channel initialization
@@ -198,17 +289,25 @@ const DMA_CHANNEL_INIT: [Option<Channel>; 32] = [
 /// let channel_27 = dma_channels[27].take().unwrap();
 /// let channel_0 = dma_channels[0].take().unwrap();
 /// ```
-pub struct Unclocked([Option<Channel>; CHANNEL_COUNT]);
+///
+/// Use [`clock_with_controller()`](struct.Unclocked.html#method.clock_with_controller) to also
+/// acquire the DMA [`Controller`](struct.Controller.html), which manages controller-wide settings.
+pub struct Unclocked {
+    channels: [Option<Channel>; CHANNEL_COUNT],
+    dma: ral::dma0::Instance,
+}
 impl Unclocked {
     pub(crate) fn new(dma: ral::dma0::Instance, mux: ral::dmamux::Instance) -> Self {
-        // Explicitly dropping instances
+        // Explicitly dropping instance
         //
-        // Users should see these as "taken" by the HAL's DMA module, although they're not
+        // Users should see this as "taken" by the HAL's DMA module, although it's not
         // used in the implementation.
-        drop(dma);
         drop(mux);
 
-        Unclocked(DMA_CHANNEL_INIT)
+        Unclocked {
+            channels: DMA_CHANNEL_INIT,
+            dma,
+        }
     }
     /// Enable the clocks for the DMA peripheral
     ///
@@ -216,16 +315,160 @@ impl Unclocked {
     /// are initialized to `Some(channel)`. The rest are `None`.**
     ///
     /// Users may take channels as needed. The index in the array maps to the DMA channel number.
-    pub fn clock(mut self, ccm: &mut ccm::Handle) -> [Option<Channel>; 32] {
+    pub fn clock(self, ccm: &mut ccm::Handle) -> [Option<Channel>; 32] {
+        let (channels, _) = self.clock_with_controller(ccm);
+        channels
+    }
+    /// Enable the clocks for the DMA peripheral, and acquire the DMA controller
+    ///
+    /// The channels are the same as the channels returned from [`clock()`](struct.Unclocked.html#method.clock).
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{GroupArbitration, GroupPriority};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    ///
+    /// let (mut dma_channels, mut controller) =
+    ///     peripherals.dma.clock_with_controller(&mut peripherals.ccm.handle);
+    ///
+    /// // Channels 16 through 31 win over channels 0 through 15
+    /// controller.set_group_arbitration(GroupArbitration::Fixed);
+    /// controller
+    ///     .set_group_priorities(GroupPriority::Low, GroupPriority::High)
+    ///     .unwrap();
+    /// ```
+    pub fn clock_with_controller(
+        mut self,
+        ccm: &mut ccm::Handle,
+    ) -> ([Option<Channel>; 32], Controller) {
         let (ccm, _) = ccm.raw();
         ral::modify_reg!(ral::ccm, ccm, CCGR5, CG3: 0x03);
-        for (idx, channel) in self.0.iter_mut().take(CHANNEL_COUNT).enumerate() {
+        for (idx, channel) in self.channels.iter_mut().take(CHANNEL_COUNT).enumerate() {
             // Safety: because we have the DMA instance, we assume that we own the DMA
             // peripheral. That means we own all the DMA channels.
             let mut chan = unsafe { Channel::new(idx) };
             chan.reset();
             *channel = Some(chan);
         }
-        self.0
+        (self.channels, Controller { dma: self.dma })
+    }
+}
+
+/// DMA controller settings that affect all channels
+///
+/// Acquire a `Controller` from [`Unclocked::clock_with_controller()`](struct.Unclocked.html#method.clock_with_controller).
+///
+/// # Arbitration
+///
+/// The DMA controller divides its 32 channels into two groups. Group 0 contains channels 0 through 15,
+/// and group 1 contains channels 16 through 31. When there are active requests in both groups, the
+/// controller uses the *group* arbitration to select a group. Then, it uses each channel's priority
+/// (the DCHPRI registers) to select a channel within that group. A channel's priority only competes with
+/// other channels in the same group.
+///
+/// Change controller settings while no DMA transfers are active.
+pub struct Controller {
+    dma: ral::dma0::Instance,
+}
+
+/// Arbitration between the two DMA channel groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupArbitration {
+    /// The group with the higher [`GroupPriority`](enum.GroupPriority.html) always wins
+    Fixed,
+    /// The groups take turns, ignoring their priorities
+    RoundRobin,
+}
+
+/// The priority of a DMA channel group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupPriority {
+    /// Lowest priority
+    Low,
+    /// Highest priority
+    High,
+}
+
+impl GroupPriority {
+    fn from_bit(bit: u32) -> Self {
+        if bit == 0 {
+            GroupPriority::Low
+        } else {
+            GroupPriority::High
+        }
+    }
+    fn bit(self) -> u32 {
+        match self {
+            GroupPriority::Low => 0,
+            GroupPriority::High => 1,
+        }
+    }
+}
+
+/// Indicates that the two group priorities are the same
+///
+/// Each group must have a unique priority.
+#[derive(Debug)]
+pub struct PriorityError(());
+
+/// Ensure that the group priorities are unique
+fn validate_group_priorities(
+    grp0: GroupPriority,
+    grp1: GroupPriority,
+) -> Result<(), PriorityError> {
+    if grp0 == grp1 {
+        Err(PriorityError(()))
+    } else {
+        Ok(())
+    }
+}
+
+impl Controller {
+    /// Set the arbitration between the two channel groups
+    pub fn set_group_arbitration(&mut self, arbitration: GroupArbitration) {
+        let erga = (arbitration == GroupArbitration::RoundRobin) as u32;
+        ral::modify_reg!(ral::dma0, self.dma, CR, ERGA: erga);
+    }
+
+    /// Returns the arbitration between the two channel groups
+    pub fn group_arbitration(&self) -> GroupArbitration {
+        if ral::read_reg!(ral::dma0, self.dma, CR, ERGA == 1) {
+            GroupArbitration::RoundRobin
+        } else {
+            GroupArbitration::Fixed
+        }
+    }
+
+    /// Set the priorities of group 0 and group 1
+    ///
+    /// Returns an error if the two priorities are the same. Priorities only take
+    /// effect when using [`GroupArbitration::Fixed`](enum.GroupArbitration.html#variant.Fixed).
+    pub fn set_group_priorities(
+        &mut self,
+        grp0: GroupPriority,
+        grp1: GroupPriority,
+    ) -> Result<(), PriorityError> {
+        validate_group_priorities(grp0, grp1)?;
+        ral::modify_reg!(ral::dma0, self.dma, CR, GRP0PRI: grp0.bit(), GRP1PRI: grp1.bit());
+        Ok(())
+    }
+
+    /// Returns the priorities of group 0 and group 1
+    pub fn group_priorities(&self) -> (GroupPriority, GroupPriority) {
+        let (grp0, grp1) = ral::read_reg!(ral::dma0, self.dma, CR, GRP0PRI, GRP1PRI);
+        (GroupPriority::from_bit(grp0), GroupPriority::from_bit(grp1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_group_priorities, GroupPriority};
+
+    #[test]
+    fn group_priorities_must_differ() {
+        assert!(validate_group_priorities(GroupPriority::Low, GroupPriority::Low).is_err());
+        assert!(validate_group_priorities(GroupPriority::High, GroupPriority::High).is_err());
+        assert!(validate_group_priorities(GroupPriority::Low, GroupPriority::High).is_ok());
+        assert!(validate_group_priorities(GroupPriority::High, GroupPriority::Low).is_ok());
     }
 }
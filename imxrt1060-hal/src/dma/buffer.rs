@@ -5,12 +5,14 @@
 //! - A normal, statically-allocated array, which we call [`Linear`](struct.Linear.html)
 //! - A circular buffer, called [`Circular`](struct.Circular.html)
 
-use super::{Element, Transfer};
+use super::{cache, Element, Transfer};
 
 use as_slice::{AsMutSlice, AsSlice};
 use core::{
     cell::UnsafeCell,
-    mem, ptr,
+    mem,
+    ops::Range,
+    ptr,
     sync::atomic::{AtomicBool, Ordering},
 };
 
@@ -27,13 +29,50 @@ use core::{
 ///
 /// DMA memory adapters may enforce additional size or alignment requirements on the
 /// statically-allocated buffers. See the adapter's documentation for details.
-#[repr(C)] // Need guaranteed layout for checking memory aligmnent, required by circular buffer
+///
+/// # Placement
+///
+/// `Buffer`s are always aligned to 32 bytes, the size of a Cortex-M7 data cache line.
+/// The buffer's ownership flag sits in a cache line of its own, after the memory. So the
+/// memory never shares a cache line with other data, and cache maintenance on the memory
+/// will not clobber unrelated memory.
+///
+/// Since [`new()`](struct.Buffer.html#method.new) is a `const fn`, you may place a `Buffer`
+/// in a specific memory region with a link section attribute. The section name depends on
+/// your linker script.
+///
+/// ```no_run
+/// use imxrt1060_hal::dma;
+///
+/// #[link_section = ".ocram"]
+/// static OCRAM_BUFFER: dma::Buffer<[u8; 256]> = dma::Buffer::new([0; 256]);
+///
+/// // Check the placement in your own tests
+/// let range = OCRAM_BUFFER.addr_range();
+/// assert!(range.start >= 0x2020_0000 && range.end <= 0x2030_0000);
+/// ```
+///
+/// When running on the MCU, [`Linear`](struct.Linear.html) and [`Circular`](struct.Circular.html)
+/// reject buffers that don't reside in memory that the DMA controller can access. The accessible
+/// regions are ITCM, DTCM, OCRAM, and external SEMC memory.
+#[repr(C, align(32))] // Need guaranteed layout for checking memory aligmnent, required by circular buffer
 pub struct Buffer<B> {
     /// A mutable array that will be used by both the hardware DMA channel
     /// and the user.
     memory: UnsafeCell<B>,
     /// `true` if this buffer has been taken, else `false`
-    taken: AtomicBool,
+    taken: CacheLine<AtomicBool>,
+}
+
+/// A value in a cache line of its own
+#[repr(C, align(32))]
+struct CacheLine<T>(T);
+
+impl<T> core::ops::Deref for CacheLine<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
 }
 
 // Its safe to allocate `Buffer` as an immutable static. The two `Buffer` adapters
@@ -53,9 +92,56 @@ impl<B> Buffer<B> {
     pub const fn new(memory: B) -> Self {
         Buffer {
             memory: UnsafeCell::new(memory),
-            taken: AtomicBool::new(false),
+            taken: CacheLine(AtomicBool::new(false)),
         }
     }
+
+    /// Returns the range of addresses occupied by the buffer's memory
+    ///
+    /// Use `addr_range()` to assert the placement of your buffers.
+    ///
+    /// ```
+    /// use imxrt1060_hal::dma;
+    /// static BUFFER: dma::Buffer<[u32; 16]> = dma::Buffer::new([0; 16]);
+    ///
+    /// let range = BUFFER.addr_range();
+    /// assert_eq!(range.end - range.start, 64);
+    /// assert_eq!(range.start % 32, 0);
+    /// ```
+    pub fn addr_range(&self) -> Range<usize> {
+        let start = self.memory.get() as usize;
+        start..(start + mem::size_of::<B>())
+    }
+}
+
+/// Memory regions that the DMA controller can access
+///
+/// In order: ITCM, DTCM, OCRAM (including FlexRAM OCRAM), FlexSPI, FlexSPI2,
+/// and SEMC. FlexSPI memory may be flash, or external RAM like the Teensy 4.1
+/// PSRAM on FlexSPI2.
+const DMA_ACCESSIBLE: [Range<usize>; 6] = [
+    0x0000_0000..0x0008_0000,
+    0x2000_0000..0x2008_0000,
+    0x2020_0000..0x2030_0000,
+    0x6000_0000..0x7000_0000,
+    0x7000_0000..0x7F00_0000,
+    0x8000_0000..0xE000_0000,
+];
+
+/// Returns `true` if the whole address `range` is in one DMA-accessible memory region
+fn is_dma_accessible(range: &Range<usize>) -> bool {
+    DMA_ACCESSIBLE
+        .iter()
+        .any(|region| region.start <= range.start && range.end <= region.end)
+}
+
+/// Returns `true` if the DMA controller can access the buffer
+///
+/// Placement only matters on the MCU. Tests and documentation examples that
+/// run on a host always pass this check.
+fn buffer_is_accessible<B>(buffer: &Buffer<B>) -> bool {
+    cfg!(not(all(target_arch = "arm", target_os = "none")))
+        || is_dma_accessible(&buffer.addr_range())
 }
 
 /// A linear DMA buffer
@@ -79,10 +165,10 @@ impl<B> Buffer<B> {
 ///
 /// static DMA1_BUFFER: dma::Buffer<[u8; 256]> = dma::Buffer::new([0; 256]);
 ///
-/// let mut linear = dma::Linear::new(&DMA1_BUFFER).unwrap();
+/// let mut linear = dma::Linear::try_new(&DMA1_BUFFER).unwrap();
 /// // DMA1_BUFFER is owned by linear. If we try to use it again,
-/// // it returns None.
-/// assert!(dma::Linear::new(&DMA1_BUFFER).is_none());
+/// // it returns an error.
+/// assert_eq!(dma::Linear::try_new(&DMA1_BUFFER).unwrap_err(), dma::LinearError::BufferTaken);
 ///
 /// // Fill the first 6 elements, and mark them for transfer
 /// linear.as_mut_elements()[..6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
@@ -111,16 +197,34 @@ where
     /// by `buffer`
     ///
     /// If the constructor has exclusive ownership of `buffer`, returns `Some(Linear)`.
-    /// Returns `None` if the `buffer` is already owned.
+    /// Returns `None` if the `buffer` is already owned, or if the DMA controller
+    /// cannot access the buffer's memory.
+    #[deprecated(since = "0.5.0", note = "use `Linear::try_new()`")]
     pub fn new<B>(buffer: &'static Buffer<B>) -> Option<Self>
+    where
+        B: AsMutSlice<Element = E>,
+    {
+        Self::try_new(buffer).ok()
+    }
+
+    /// Create a new `Linear` DMA buffer that takes ownership of the memory wrapped
+    /// by `buffer`
+    ///
+    /// If the constructor has exclusive ownership of `buffer`, returns `Ok(Linear)`.
+    /// Returns an error if the `buffer` is already owned, or if the DMA controller
+    /// cannot access the buffer's memory.
+    pub fn try_new<B>(buffer: &'static Buffer<B>) -> Result<Self, LinearError>
     where
         B: AsMutSlice<Element = E>,
     {
         let taken = buffer.taken.swap(true, Ordering::SeqCst);
         if taken {
-            None
+            Err(LinearError::BufferTaken)
+        } else if !buffer_is_accessible(buffer) {
+            buffer.taken.store(false, Ordering::SeqCst);
+            Err(LinearError::InaccessibleMemory)
         } else {
-            unsafe { Some(Self::new_unchecked(buffer)) }
+            unsafe { Ok(Self::new_unchecked(buffer)) }
         }
     }
 
@@ -223,10 +327,133 @@ impl<E: Element> AsMutSlice for Linear<E> {
     }
 }
 
+/// Possible errors when creating a linear buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinearError {
+    /// The buffer is taken, likely used in another DMA buffer
+    BufferTaken,
+    /// The buffer is placed in memory that the DMA controller cannot access
+    InaccessibleMemory,
+}
+
 // OK to send; the pointer is assumed to be static. A `Linear` object is the
 // only (safe) owner of the memory.
 unsafe impl<E: Element> Send for Linear<E> {}
 
+/// A read-only DMA source
+///
+/// `ReadOnly` sends `'static` data, like a string constant, without copying it
+/// into a [`Buffer`](struct.Buffer.html). The data may be in flash. The DMA
+/// controller only reads the memory, so many `ReadOnly` sources may send the same
+/// data. Once the transfer completes, [`into_elements()`](#method.into_elements)
+/// returns the data.
+///
+/// ```
+/// use imxrt1060_hal::dma;
+///
+/// static GREETING: &str = "hello, world\n";
+///
+/// let source = dma::ReadOnly::new(GREETING.as_bytes()).unwrap();
+/// assert_eq!(source.as_elements(), b"hello, world\n");
+/// ```
+#[derive(Debug)]
+pub struct ReadOnly<E> {
+    ptr: *const E,
+    len: usize,
+    /// The ownership flag of the staging buffer that holds the data, if
+    /// the data is staged in a `Buffer`
+    staged: Option<&'static AtomicBool>,
+}
+
+impl<E> ReadOnly<E>
+where
+    E: Element,
+{
+    /// Create a read-only source that sends `data`
+    ///
+    /// Returns [`LinearError::InaccessibleMemory`](enum.LinearError.html#variant.InaccessibleMemory)
+    /// if the DMA controller cannot read `data`.
+    pub fn new(data: &'static [E]) -> Result<Self, LinearError> {
+        if slice_is_readable(data) {
+            Ok(ReadOnly {
+                ptr: data.as_ptr(),
+                len: data.len(),
+                staged: None,
+            })
+        } else {
+            Err(LinearError::InaccessibleMemory)
+        }
+    }
+
+    /// Stage data in `buffer`, and create a source that sends it
+    ///
+    /// `fill` writes the data into the buffer, and returns the number of elements
+    /// to send. The source owns `buffer` until the transfer completes, or until the
+    /// source drops.
+    pub(crate) fn stage<B, F>(buffer: &'static Buffer<B>, fill: F) -> Result<Self, LinearError>
+    where
+        B: AsMutSlice<Element = E>,
+        F: FnOnce(&mut [E]) -> usize,
+    {
+        let mut linear = Linear::try_new(buffer)?;
+        let elements = linear.as_mut_elements();
+        let len = fill(elements).min(elements.len());
+        Ok(ReadOnly {
+            ptr: elements.as_ptr(),
+            len,
+            staged: Some(&*buffer.taken),
+        })
+    }
+
+    /// Returns the elements that this source sends
+    pub fn as_elements(&self) -> &[E] {
+        // Safety: the data is static, or we own the staging buffer
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Returns the data that this source sends
+    ///
+    /// Staged data isn't static. If the data is staged, this releases the staging
+    /// buffer, and returns an empty slice.
+    pub fn into_elements(mut self) -> &'static [E] {
+        if self.staged.is_some() {
+            self.release();
+            &[]
+        } else {
+            // Safety: the data isn't staged, so it's static
+            unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+
+    /// Release the staging buffer, if there is one
+    fn release(&mut self) {
+        if let Some(taken) = self.staged.take() {
+            self.len = 0;
+            taken.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<E> Drop for ReadOnly<E> {
+    fn drop(&mut self) {
+        if let Some(taken) = self.staged.take() {
+            taken.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Returns `true` if the DMA controller can read `data`
+fn slice_is_readable<E>(data: &[E]) -> bool {
+    let start = data.as_ptr() as usize;
+    let range = start..(start + mem::size_of_val(data));
+    cfg!(not(all(target_arch = "arm", target_os = "none")))
+        || range.start == range.end
+        || is_dma_accessible(&range)
+}
+
+// OK to send; the data is static, or the source owns its staging buffer
+unsafe impl<E: Element> Send for ReadOnly<E> {}
+
 /// A circular DMA buffer
 ///
 /// `Circular` provides a [`push()`](struct.Circular.html#method.push) and [`pop()`](struct.Circular.html#method.pop)
@@ -369,6 +596,8 @@ pub enum CircularError {
     /// The alignment of the buffer must be a multiple of the buffer's
     /// size, which includes both element type, and the length of the buffer.
     IncorrectAlignment,
+    /// The buffer is placed in memory that the DMA controller cannot access
+    InaccessibleMemory,
 }
 
 impl<E: Element> Circular<E> {
@@ -380,6 +609,9 @@ impl<E: Element> Circular<E> {
         let taken = buffer.taken.swap(true, Ordering::SeqCst);
         if taken {
             Err(CircularError::BufferTaken)
+        } else if !buffer_is_accessible(buffer) {
+            buffer.taken.store(false, Ordering::SeqCst);
+            Err(CircularError::InaccessibleMemory)
         } else {
             // Safety: it's not taken
             unsafe { Self::new_unchecked(buffer) }.map_err(|err| {
@@ -588,6 +820,76 @@ impl<E: Element> Circular<E> {
         self.reserved = reservation.min(self.capacity());
     }
 
+    /// Returns the number of elements in the backing buffer
+    pub(crate) fn size(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns a pointer to the start of the backing buffer
+    pub(crate) fn as_ptr(&self) -> *const E {
+        self.ptr
+    }
+
+    /// Discard all elements, and prepare the whole backing buffer to be the destination
+    /// of a continuous DMA transfer
+    ///
+    /// The read and write positions move to the start of the backing buffer.
+    pub(crate) fn prepare_continuous_destination(&mut self) {
+        self.read = 0;
+        self.write = 0;
+        self.reserved = 0;
+        cache::clean_invalidate(self.ptr as usize, self.cap * mem::size_of::<E>());
+    }
+
+    /// Returns the index of the element at `addr`, an address within the backing buffer
+    pub(crate) fn index_of(&self, addr: usize) -> usize {
+        (addr.wrapping_sub(self.ptr as usize) / mem::size_of::<E>()) & (self.cap - 1)
+    }
+
+    /// Returns the number of readable elements if the write position were `write`
+    pub(crate) fn readable_until(&self, write: usize) -> usize {
+        write.wrapping_sub(self.read) & (self.cap - 1)
+    }
+
+    /// Mark all elements up to the position `write` as written, usually as reported
+    /// by the DMA controller
+    pub(crate) fn mark_written_until(&mut self, write: usize) {
+        let written = write.wrapping_sub(self.write) & (self.cap - 1);
+        for (addr, len) in self.segments(self.write, written).iter() {
+            cache::invalidate(*addr, *len);
+        }
+        self.mark_written(written);
+    }
+
+    /// Returns `len` readable elements, and marks them as read
+    ///
+    /// Caller must ensure that the elements don't wrap around the end of the
+    /// backing buffer, and that `len` elements are readable.
+    pub(super) fn take_contiguous(&mut self, len: usize) -> &[E] {
+        debug_assert!(self.read + len <= self.cap && len <= self.len());
+        let ptr = self.read_ptr();
+        self.mark_read(len);
+        // Safety: pointer is valid for len elements, per the caller's guarantees.
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    }
+
+    /// Returns the readable elements, without marking them as read
+    ///
+    /// The first slice runs up to the end of the backing buffer, and the second slice
+    /// holds the elements that wrap around to its start.
+    pub(crate) fn as_slices(&self) -> (&[E], &[E]) {
+        let len = self.len();
+        let first = len.min(self.cap - self.read);
+        // Safety: both slices are within the backing buffer, and they cover
+        // readable elements.
+        unsafe {
+            (
+                core::slice::from_raw_parts(self.read_ptr(), first),
+                core::slice::from_raw_parts(self.ptr, len - first),
+            )
+        }
+    }
+
     /// Returns the pointer to the start of the readable queue memory
     fn read_ptr(&self) -> *const E {
         unsafe { self.ptr.add(self.read) }
@@ -598,11 +900,24 @@ impl<E: Element> Circular<E> {
         unsafe { self.ptr.add(self.write) }
     }
 
+    /// Returns the memory covered by `count` elements, starting at element `start`,
+    /// as `(address, bytes)` pairs
+    ///
+    /// The second pair is non-empty when the elements wrap around the end of the buffer.
+    fn segments(&self, start: usize, count: usize) -> [(usize, usize); 2] {
+        let size = mem::size_of::<E>();
+        let first = count.min(self.cap - start);
+        [
+            (self.ptr as usize + start * size, first * size),
+            (self.ptr as usize, (count - first) * size),
+        ]
+    }
+
     /// Mark `size` elements as read
     ///
     /// Equivalent to calling `pop()` `size` times, and dropping
     /// the elements.
-    fn mark_read(&mut self, size: usize) {
+    pub(crate) fn mark_read(&mut self, size: usize) {
         self.read = (self.read + size) & (self.cap - 1);
     }
 
@@ -726,14 +1041,17 @@ pub trait Destination<E: Element>: private::Sealed {
     /// Use this to perform any final state transformations before hand-off to
     /// the user.
     fn complete_destination(&mut self);
+    /// Invoked when the DMA transfer stopped early, after writing `elements`
+    fn complete_destination_partial(&mut self, elements: usize);
 }
 
 mod private {
     pub trait Sealed {}
 
-    use super::{Circular, Linear};
+    use super::{Circular, Linear, ReadOnly};
     impl<E> Sealed for Linear<E> {}
     impl<E> Sealed for Circular<E> {}
+    impl<E> Sealed for ReadOnly<E> {}
 }
 
 //
@@ -749,7 +1067,9 @@ impl<E: Element> Source<E> for Linear<E> {
     fn source_len(&self) -> usize {
         self.usable
     }
-    fn prepare_source(&mut self) {}
+    fn prepare_source(&mut self) {
+        cache::clean(self.ptr as usize, self.usable * mem::size_of::<E>());
+    }
     fn complete_source(&mut self) {}
 }
 
@@ -762,8 +1082,38 @@ impl<E: Element> Destination<E> for Linear<E> {
     fn destination_len(&self) -> usize {
         self.usable
     }
-    fn prepare_destination(&mut self) {}
-    fn complete_destination(&mut self) {}
+    fn prepare_destination(&mut self) {
+        cache::clean_invalidate(self.ptr as usize, self.usable * mem::size_of::<E>());
+    }
+    fn complete_destination(&mut self) {
+        cache::invalidate(self.ptr as usize, self.usable * mem::size_of::<E>());
+    }
+    fn complete_destination_partial(&mut self, elements: usize) {
+        cache::invalidate(self.ptr as usize, elements * mem::size_of::<E>());
+    }
+}
+
+//
+// Read-only Sources
+//
+
+impl<E: Element> Source<E> for ReadOnly<E> {
+    fn source(&self) -> Transfer<E> {
+        // Safety: the DMA controller only reads the elements, which are
+        // static, or owned by this source. The pointer is never written.
+        unsafe { Transfer::buffer_linear(self.ptr as *mut E, self.len) }
+    }
+    fn source_len(&self) -> usize {
+        self.len
+    }
+    fn prepare_source(&mut self) {
+        // Static data may be freshly written, like a leaked singleton
+        cache::clean(self.ptr as usize, self.len * mem::size_of::<E>());
+    }
+    fn complete_source(&mut self) {
+        // The staging buffer is free for the next message
+        self.release();
+    }
 }
 
 //
@@ -783,6 +1133,9 @@ impl<E: Element> Source<E> for Circular<E> {
     }
     fn prepare_source(&mut self) {
         self.reserved = self.len();
+        for (addr, len) in self.segments(self.read, self.reserved).iter() {
+            cache::clean(*addr, *len);
+        }
     }
     fn complete_source(&mut self) {
         self.mark_read(self.reserved);
@@ -800,10 +1153,24 @@ impl<E: Element> Destination<E> for Circular<E> {
     fn destination_len(&self) -> usize {
         self.reserved
     }
-    fn prepare_destination(&mut self) {}
+    fn prepare_destination(&mut self) {
+        for (addr, len) in self.segments(self.write, self.reserved).iter() {
+            cache::clean_invalidate(*addr, *len);
+        }
+    }
     fn complete_destination(&mut self) {
+        for (addr, len) in self.segments(self.write, self.reserved).iter() {
+            cache::invalidate(*addr, *len);
+        }
         self.mark_written(self.reserved);
     }
+    fn complete_destination_partial(&mut self, elements: usize) {
+        let elements = elements.min(self.reserved);
+        for (addr, len) in self.segments(self.write, elements).iter() {
+            cache::invalidate(*addr, *len);
+        }
+        self.mark_written(elements);
+    }
 }
 
 #[cfg(test)]
@@ -912,4 +1279,126 @@ mod tests {
         }
         assert_eq!(calls, 23);
     }
+
+    #[test]
+    fn circular_segments() {
+        let mut memory = [0u16; 8];
+        let circular: Circular<u16> = unsafe { from_raw_unaligned(&mut memory) };
+        let base = circular.ptr as usize;
+        assert_eq!(circular.segments(2, 4), [(base + 4, 8), (base, 0)]);
+        assert_eq!(circular.segments(6, 4), [(base + 12, 4), (base, 4)]);
+    }
+
+    #[test]
+    fn circular_simulate_chunks() {
+        let mut memory: [u8; 16] = [0; 16];
+        for (dst, src) in memory.iter_mut().zip(0..) {
+            *dst = src;
+        }
+        let mut circular: Circular<u8> = unsafe { from_raw_unaligned(&mut memory) };
+        circular.prepare_continuous_destination();
+        let base = circular.ptr as usize;
+
+        // DMA controller has received one and a half chunks of 4 elements
+        let write = circular.index_of(base + 6);
+        assert_eq!(circular.readable_until(write) / 4, 1);
+        circular.mark_written_until(write);
+        assert_eq!(circular.take_contiguous(4), &[0, 1, 2, 3]);
+
+        // DMA controller wraps around, and received three more chunks
+        let write = circular.index_of(base + 18);
+        assert_eq!(circular.readable_until(write), 14);
+        circular.mark_written_until(write);
+        assert_eq!(circular.take_contiguous(4), &[4, 5, 6, 7]);
+        assert_eq!(circular.take_contiguous(4), &[8, 9, 10, 11]);
+        assert_eq!(circular.take_contiguous(4), &[12, 13, 14, 15]);
+
+        // Partial trailing chunk remains readable
+        assert_eq!(circular.len(), 2);
+        assert_eq!(circular.pop(), Some(0));
+    }
+
+    #[test]
+    fn circular_slices() {
+        let mut memory: [u8; 8] = [0; 8];
+        for (dst, src) in memory.iter_mut().zip(0..) {
+            *dst = src;
+        }
+        let mut circular: Circular<u8> = unsafe { from_raw_unaligned(&mut memory) };
+        assert_eq!(circular.as_slices(), (&[][..], &[][..]));
+
+        circular.mark_written(5);
+        circular.mark_read(2);
+        assert_eq!(circular.as_slices(), (&[2, 3, 4][..], &[][..]));
+
+        // The readable elements wrap around the end of the buffer
+        circular.mark_written(4);
+        assert_eq!(circular.as_slices(), (&[2, 3, 4, 5, 6, 7][..], &[0][..]));
+        circular.mark_read(6);
+        assert_eq!(circular.as_slices(), (&[0][..], &[][..]));
+    }
+
+    #[test]
+    fn dma_accessible_regions() {
+        // DTCM and OCRAM
+        assert!(is_dma_accessible(&(0x2000_0000..0x2000_0100)));
+        assert!(is_dma_accessible(&(0x2020_0000..0x2030_0000)));
+        // FlexSPI flash, and FlexSPI2 external RAM
+        assert!(is_dma_accessible(&(0x6000_1000..0x6000_2000)));
+        assert!(is_dma_accessible(&(0x7000_0000..0x7080_0000)));
+        // Peripheral and boot ROM addresses
+        assert!(!is_dma_accessible(&(0x4018_4000..0x4018_4100)));
+        assert!(!is_dma_accessible(&(0x0020_0000..0x0020_0010)));
+        // Spans the end of DTCM
+        assert!(!is_dma_accessible(&(0x2007_FFF0..0x2008_0010)));
+    }
+
+    #[test]
+    fn buffer_alignment() {
+        static BUFFER: Buffer<[u8; 3]> = Buffer::new([0; 3]);
+        assert_eq!(mem::align_of::<Buffer<[u8; 3]>>(), 32);
+        assert_eq!(BUFFER.addr_range().start % 32, 0);
+        assert_eq!(BUFFER.addr_range().len(), 3);
+
+        // The flag starts the next cache line
+        let taken = &*BUFFER.taken as *const AtomicBool as usize;
+        assert_eq!(taken - BUFFER.addr_range().start, 32);
+        assert_eq!(mem::size_of::<Buffer<[u8; 3]>>(), 64);
+        static LINE: Buffer<[u32; 8]> = Buffer::new([0; 8]);
+        let taken = &*LINE.taken as *const AtomicBool as usize;
+        assert_eq!(taken, LINE.addr_range().end);
+    }
+
+    #[test]
+    fn read_only_staging() {
+        static STAGING: Buffer<[u8; 8]> = Buffer::new([0; 8]);
+        let mut staged = ReadOnly::stage(&STAGING, |buffer| {
+            buffer[..3].copy_from_slice(b"abc");
+            3
+        })
+        .unwrap();
+        assert_eq!(staged.as_elements(), b"abc");
+        assert_eq!(staged.source_len(), 3);
+        // Owned until the transfer completes
+        assert_eq!(
+            ReadOnly::stage(&STAGING, |_| 0).unwrap_err(),
+            LinearError::BufferTaken
+        );
+        staged.complete_source();
+        assert_eq!(staged.source_len(), 0);
+        assert_eq!(staged.into_elements(), &[]);
+
+        // A cancelled transfer releases the buffer when it drops
+        let staged = ReadOnly::stage(&STAGING, |_| 100).unwrap();
+        assert_eq!(staged.source_len(), 8);
+        drop(staged);
+        let staged = ReadOnly::stage(&STAGING, |_| 0).unwrap();
+        assert_eq!(staged.into_elements(), &[]);
+        assert!(!STAGING.taken.load(Ordering::SeqCst));
+
+        static GREETING: &str = "hi";
+        let mut source = ReadOnly::new(GREETING.as_bytes()).unwrap();
+        source.complete_source();
+        assert_eq!(source.into_elements(), b"hi");
+    }
 }
@@ -0,0 +1,204 @@
+//! Data cache maintenance for DMA buffers
+//!
+//! When the `"dcache"` feature is enabled, the DMA buffers clean source memory
+//! before a transfer, and invalidate destination memory after a transfer. Without
+//! the feature, all operations are no-ops.
+//!
+//! Maintenance operates on 32-byte cache lines. Invalidating a line that's only
+//! partially covered by a buffer would discard unrelated CPU writes in the rest
+//! of the line. So, partially-covered lines at the edges of a range are cleaned
+//! *and* invalidated. [`Buffer`s](struct.Buffer.html) are always line-aligned, so
+//! this never happens for buffers allocated with `Buffer::new()`.
+
+use core::ops::Range;
+
+/// Size of a Cortex-M7 data cache line, in bytes
+pub const CACHE_LINE_SIZE: usize = 32;
+
+/// Tightly coupled memory is never cached; ITCM, then DTCM
+const TCM: [Range<usize>; 2] = [0x0000_0000..0x0008_0000, 0x2000_0000..0x2008_0000];
+
+/// A cache maintenance operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Clean,
+    CleanInvalidate,
+    Invalidate,
+}
+
+/// The cache lines touched by a memory range
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(
+    not(all(feature = "dcache", target_arch = "arm", target_os = "none")),
+    allow(dead_code)
+)]
+struct Lines {
+    /// Address of the first line
+    first: usize,
+    /// Address one past the last line
+    end: usize,
+    /// `true` if the first line is only partially covered by the range
+    partial_first: bool,
+    /// `true` if the last line is only partially covered by the range
+    partial_last: bool,
+}
+
+#[cfg_attr(
+    not(all(feature = "dcache", target_arch = "arm", target_os = "none")),
+    allow(dead_code)
+)]
+impl Lines {
+    /// Returns the operation that's safe to perform on `line`, when the caller
+    /// asked for `requested`
+    ///
+    /// Invalidating a partial line is promoted to a clean and invalidate.
+    fn operation(&self, line: usize, requested: Operation) -> Operation {
+        let partial = (self.partial_first && line == self.first)
+            || (self.partial_last && line + CACHE_LINE_SIZE == self.end);
+        match requested {
+            Operation::Invalidate if partial => Operation::CleanInvalidate,
+            op => op,
+        }
+    }
+}
+
+/// Compute the cache lines for `len` bytes starting at `addr`
+///
+/// Returns `None` if there's nothing to maintain.
+fn lines(addr: usize, len: usize) -> Option<Lines> {
+    if len == 0 || TCM.iter().any(|tcm| tcm.contains(&addr)) {
+        return None;
+    }
+    let end = addr + len;
+    let first = addr & !(CACHE_LINE_SIZE - 1);
+    let last_end = (end + CACHE_LINE_SIZE - 1) & !(CACHE_LINE_SIZE - 1);
+    Some(Lines {
+        first,
+        end: last_end,
+        partial_first: first != addr,
+        partial_last: last_end != end,
+    })
+}
+
+#[cfg(all(feature = "dcache", target_arch = "arm", target_os = "none"))]
+fn apply(lines: &Lines, requested: Operation) {
+    /// D-cache clean by address to the point of coherency
+    const DCCMVAC: *mut u32 = 0xE000_EF68 as *mut u32;
+    /// D-cache invalidate by address to the point of coherency
+    const DCIMVAC: *mut u32 = 0xE000_EF5C as *mut u32;
+    /// D-cache clean and invalidate by address to the point of coherency
+    const DCCIMVAC: *mut u32 = 0xE000_EF70 as *mut u32;
+
+    cortex_m::asm::dsb();
+    for line in (lines.first..lines.end).step_by(CACHE_LINE_SIZE) {
+        let register = match lines.operation(line, requested) {
+            Operation::Clean => DCCMVAC,
+            Operation::CleanInvalidate => DCCIMVAC,
+            Operation::Invalidate => DCIMVAC,
+        };
+        // Safety: cache maintenance registers are write-only, and always valid to
+        // write. `operation()` makes sure we never discard data outside of the range.
+        unsafe { core::ptr::write_volatile(register, line as u32) };
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+#[cfg(not(all(feature = "dcache", target_arch = "arm", target_os = "none")))]
+fn apply(_: &Lines, _: Operation) {}
+
+fn maintain(addr: usize, len: usize, operation: Operation) {
+    if let Some(lines) = lines(addr, len) {
+        apply(&lines, operation);
+    }
+}
+
+/// Clean `len` bytes starting at `addr`, writing any cached data back to memory
+///
+/// Use this before the DMA controller reads from memory.
+pub(crate) fn clean(addr: usize, len: usize) {
+    maintain(addr, len, Operation::Clean);
+}
+
+/// Clean and invalidate `len` bytes starting at `addr`
+///
+/// Use this before the DMA controller writes to memory, so that dirty lines
+/// are not evicted on top of the DMA controller's data.
+pub(crate) fn clean_invalidate(addr: usize, len: usize) {
+    maintain(addr, len, Operation::CleanInvalidate);
+}
+
+/// Invalidate `len` bytes starting at `addr`, so that the CPU reads the DMA controller's data
+///
+/// Lines that are only partially covered by the range are cleaned and invalidated.
+pub(crate) fn invalidate(addr: usize, len: usize) {
+    maintain(addr, len, Operation::Invalidate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lines, Lines, Operation};
+
+    #[test]
+    fn aligned_range() {
+        assert_eq!(
+            lines(0x2020_0000, 64),
+            Some(Lines {
+                first: 0x2020_0000,
+                end: 0x2020_0040,
+                partial_first: false,
+                partial_last: false,
+            })
+        );
+    }
+
+    #[test]
+    fn partial_lines() {
+        assert_eq!(
+            lines(0x2020_0004, 60),
+            Some(Lines {
+                first: 0x2020_0000,
+                end: 0x2020_0040,
+                partial_first: true,
+                partial_last: false,
+            })
+        );
+        assert_eq!(
+            lines(0x2020_0004, 30),
+            Some(Lines {
+                first: 0x2020_0000,
+                end: 0x2020_0040,
+                partial_first: true,
+                partial_last: true,
+            })
+        );
+    }
+
+    #[test]
+    fn never_invalidate_partial_lines() {
+        let lines = lines(0x2020_0004, 64).unwrap();
+        assert_eq!(
+            lines.operation(0x2020_0000, Operation::Invalidate),
+            Operation::CleanInvalidate
+        );
+        assert_eq!(
+            lines.operation(0x2020_0020, Operation::Invalidate),
+            Operation::Invalidate
+        );
+        assert_eq!(
+            lines.operation(0x2020_0040, Operation::Invalidate),
+            Operation::CleanInvalidate
+        );
+        assert_eq!(
+            lines.operation(0x2020_0000, Operation::Clean),
+            Operation::Clean
+        );
+    }
+
+    #[test]
+    fn skip_tcm_and_empty() {
+        assert_eq!(lines(0x2000_0100, 64), None);
+        assert_eq!(lines(0x0000_0100, 64), None);
+        assert_eq!(lines(0x2020_0000, 0), None);
+    }
+}
@@ -0,0 +1,777 @@
+//! DMA channel extensions
+//!
+//! The [`Channel`](struct.Channel.html) type is defined outside of the HAL. The
+//! [`ChannelExt`](trait.ChannelExt.html) trait adds channel functions that are specific
+//! to the i.MX RT 1060 DMA controller.
+
+use super::Channel;
+use core::{fmt, ptr};
+
+/// DMA controller register block
+const DMA_BASE: usize = 0x400E_8000;
+/// Start of the transfer control descriptors (TCD), one per channel
+const TCD_BASE: usize = DMA_BASE + 0x1000;
+/// Size of a single TCD, in bytes
+const TCD_SIZE: usize = 32;
+/// DMAMUX channel configuration registers, one per channel
+const DMAMUX_BASE: usize = 0x400E_C000;
+
+/// Number of DMA channels, starting from 0, that PIT channels can trigger
+const PERIODIC_TRIGGER_CHANNELS: usize = 4;
+
+/// Clear enable error interrupt
+const CEEI: usize = DMA_BASE + 0x18;
+/// Clear enable request
+const CERQ: usize = DMA_BASE + 0x1A;
+
+/// Offset of the ATTR field within a TCD
+const TCD_ATTR: usize = 0x06;
+/// Offset of the DADDR field within a TCD
+const TCD_DADDR: usize = 0x10;
+/// Offset of the CITER field within a TCD
+const TCD_CITER: usize = 0x16;
+/// Offset of the CSR field within a TCD
+const TCD_CSR: usize = 0x1C;
+/// Offset of the BITER field within a TCD
+const TCD_BITER: usize = 0x1E;
+/// Iteration count bits of CITER and BITER, when minor loop channel linking is disabled
+const ITER_MASK: u16 = 0x7FFF;
+
+/// Returns `len` minor loops as a major loop iteration count
+///
+/// Returns an error if a major loop can't iterate `len` times.
+pub(crate) fn major_iterations(len: usize) -> Result<u16, super::Error> {
+    if len <= ITER_MASK as usize {
+        Ok(len as u16)
+    } else {
+        Err(super::Error::TooManyElements)
+    }
+}
+
+/// Returns a pointer to the start of `channel`'s TCD
+pub(crate) fn tcd(channel: &Channel) -> *mut u32 {
+    (TCD_BASE + TCD_SIZE * channel.channel()) as *mut u32
+}
+
+/// Write all of `tcd` into `channel`'s TCD
+///
+/// # Safety
+///
+/// The channel must be disabled, and not active. The TCD's addresses must be valid for
+/// every transfer that the channel will perform.
+pub(crate) unsafe fn write_tcd(channel: &mut Channel, tcd: &TcdSnapshot) {
+    write_words(self::tcd(channel), &tcd.to_words());
+}
+
+/// Write `words` into the TCD at `dst`
+///
+/// # Safety
+///
+/// `dst` must point to a TCD, or to memory that's as large as a TCD.
+unsafe fn write_words(dst: *mut u32, words: &[u32; TCD_SIZE / 4]) {
+    for (idx, word) in words.iter().enumerate() {
+        ptr::write_volatile(dst.add(idx), *word);
+    }
+}
+
+/// Zero all fields of the TCD at `tcd`
+///
+/// # Safety
+///
+/// See `write_words()`.
+unsafe fn clear_tcd(tcd: *mut u32) {
+    write_words(tcd, &[0; TCD_SIZE / 4]);
+}
+
+/// Returns `true` if the channel will interrupt when its major loop completes
+pub(crate) fn is_interrupt_on_completion(channel: &Channel) -> bool {
+    // Safety: the TCD is always valid to read. We own the channel.
+    let csr = unsafe { ptr::read_volatile((tcd(channel) as usize + TCD_CSR) as *const u16) };
+    ControlFlags::from_bits_truncate(csr).contains(ControlFlags::INTMAJOR)
+}
+
+/// Returns the channel's current destination address
+pub(crate) fn destination_address(channel: &Channel) -> usize {
+    // Safety: the TCD is always valid to read. We own the channel.
+    unsafe { ptr::read_volatile((tcd(channel) as usize + TCD_DADDR) as *const u32) as usize }
+}
+
+/// Stop the channel with index `idx`, and wait until it's no longer moving data
+///
+/// Only the TCD's CSR and the channel's enable request are touched, so the caller does
+/// not need a reference to the `Channel`.
+///
+/// # Safety
+///
+/// Caller must own channel `idx`, and no one else may be using the
+/// channel's CSR.
+pub(crate) unsafe fn cancel(idx: usize) {
+    let csr = (TCD_BASE + TCD_SIZE * idx + TCD_CSR) as *mut u16;
+    ptr::write_volatile(CERQ as *mut u8, idx as u8);
+    let flags = ptr::read_volatile(csr);
+    ptr::write_volatile(csr, flags & !ControlFlags::START.bits());
+    while ptr::read_volatile(csr) & ControlFlags::ACTIVE.bits() != 0 {
+        #[allow(deprecated)]
+        core::sync::atomic::spin_loop_hint();
+    }
+}
+
+/// Returns the number of major loop iterations that the channel has completed
+///
+/// Call this after the channel is disabled. `complete` is the channel's done flag;
+/// once the major loop completes, the hardware reloads CITER from BITER.
+pub(crate) fn completed_iterations(channel: &Channel, complete: bool) -> usize {
+    // Safety: the TCD is always valid to read. We own the channel.
+    let (citer, biter) = unsafe {
+        let tcd = tcd(channel) as usize;
+        (
+            ptr::read_volatile((tcd + TCD_CITER) as *const u16),
+            ptr::read_volatile((tcd + TCD_BITER) as *const u16),
+        )
+    };
+    iterations_from(complete, citer, biter)
+}
+
+fn iterations_from(complete: bool, citer: u16, biter: u16) -> usize {
+    let biter = (biter & ITER_MASK) as usize;
+    if complete {
+        biter
+    } else {
+        biter.saturating_sub((citer & ITER_MASK) as usize)
+    }
+}
+
+/// Returns the channel's transfer attributes, TCD_ATTR
+pub(crate) fn attributes(channel: &Channel) -> u16 {
+    // Safety: the TCD is always valid to read. We own the channel.
+    unsafe { ptr::read_volatile((tcd(channel) as usize + TCD_ATTR) as *const u16) }
+}
+
+/// Returns `true` if the SSIZE and DSIZE fields of `attr` both describe `size` bytes
+pub(crate) fn attr_matches_size(attr: u16, size: usize) -> bool {
+    let attr = Attributes::from_raw(attr);
+    let size = Some(size as u8);
+    attr.ssize == size && attr.dsize == size
+}
+
+/// Extensions for DMA channels
+pub trait ChannelExt: private::Sealed {
+    /// Restore the channel's transfer control descriptor (TCD), and its triggering configuration,
+    /// to the reset state
+    ///
+    /// `reset_tcd()` disables the channel, zeros all TCD fields, clears the channel's done, error,
+    /// and interrupt flags, and disables the channel's DMAMUX source, trigger, and always-on
+    /// configuration. Use `reset_tcd()` before reusing a channel for a different kind of transfer,
+    /// so that stale fields, like minor loop offsets or last address adjustments, don't affect
+    /// the next transfer.
+    ///
+    /// The channel should not be active when you call `reset_tcd()`.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::ChannelExt;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let mut channel = dma_channels[3].take().unwrap();
+    /// channel.reset_tcd();
+    /// ```
+    fn reset_tcd(&mut self);
+
+    /// Returns a snapshot of the channel's transfer control descriptor (TCD)
+    ///
+    /// Use the snapshot to debug your DMA configuration. The snapshot's `Debug`
+    /// implementation decodes the TCD fields.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::ChannelExt;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let channel = dma_channels[3].take().unwrap();
+    /// log::info!("{:#?}", channel.tcd_snapshot());
+    /// ```
+    fn tcd_snapshot(&self) -> TcdSnapshot;
+
+    /// Trigger the channel periodically from the PIT channel of the same number
+    ///
+    /// The channel is always enabled in the DMA multiplexer, and the PIT channel paces the requests.
+    /// Call this after any other trigger configuration, like `set_trigger_from_hardware()` or
+    /// `set_always_on()`, since those reset the periodic trigger.
+    ///
+    /// Only DMA channels 0 through 3 support periodic triggers. Returns an error for any other channel.
+    fn set_periodic_trigger(&mut self) -> Result<(), PeriodicTriggerError>;
+
+    /// Returns the channel's state, decoded from its request enable, error, and TCD status bits
+    ///
+    /// See [`ChannelState`](enum.ChannelState.html) for how to interpret the state.
+    fn state(&self) -> ChannelState;
+}
+
+/// The state of a DMA channel
+///
+/// Get the state with [`ChannelExt::state()`](trait.ChannelExt.html#tymethod.state). The state
+/// is a snapshot; the DMA controller may change it as soon as you read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    /// The channel is not enabled, and it has no pending work
+    Idle,
+    /// The channel is enabled, or started, but it hasn't moved any data yet
+    ///
+    /// The channel is waiting for a request, or for the arbiter to select it.
+    Armed,
+    /// The channel is executing a minor loop
+    Active,
+    /// The channel has moved some data, but it's not executing
+    ///
+    /// The channel is between minor loops. It's waiting for its next request, or it was
+    /// preempted by, or lost arbitration to, a higher priority channel.
+    Suspended,
+    /// The major loop is complete
+    Done,
+    /// The channel has an error
+    Error,
+}
+
+/// Decode a channel's state from its status bits
+fn state_from(error: bool, enabled: bool, csr: u16, citer: u16, biter: u16) -> ChannelState {
+    let flags = ControlFlags::from_bits_truncate(csr);
+    if error {
+        ChannelState::Error
+    } else if flags.contains(ControlFlags::DONE) {
+        ChannelState::Done
+    } else if flags.contains(ControlFlags::ACTIVE) {
+        ChannelState::Active
+    } else if enabled || flags.contains(ControlFlags::START) {
+        if (citer & ITER_MASK) == (biter & ITER_MASK) {
+            ChannelState::Armed
+        } else {
+            ChannelState::Suspended
+        }
+    } else {
+        ChannelState::Idle
+    }
+}
+
+/// Indicates that the DMA channel does not support periodic triggers
+///
+/// Only DMA channels 0 through 3 support periodic triggers.
+#[derive(Debug)]
+pub struct PeriodicTriggerError(());
+
+impl ChannelExt for Channel {
+    fn reset_tcd(&mut self) {
+        self.disable();
+        let idx = self.channel();
+        let tcd = tcd(self);
+        // Safety: we own the channel, so we own its TCD, and its DMAMUX configuration
+        // register. The CEEI register is write-only, and it only affects the channel
+        // that we're writing.
+        unsafe {
+            clear_tcd(tcd);
+            ptr::write_volatile(CEEI as *mut u8, idx as u8);
+            ptr::write_volatile((DMAMUX_BASE + 4 * idx) as *mut u32, 0);
+        }
+        self.clear_complete();
+        self.clear_error();
+        self.clear_interrupt();
+    }
+
+    fn tcd_snapshot(&self) -> TcdSnapshot {
+        let tcd = tcd(self);
+        let mut words = [0; TCD_SIZE / 4];
+        for (idx, word) in words.iter_mut().enumerate() {
+            // Safety: the TCD is always valid to read.
+            *word = unsafe { ptr::read_volatile(tcd.add(idx)) };
+        }
+        TcdSnapshot::from_words(words)
+    }
+
+    fn set_periodic_trigger(&mut self) -> Result<(), PeriodicTriggerError> {
+        const ENBL: u32 = 1 << 31;
+        const TRIG: u32 = 1 << 30;
+        const A_ON: u32 = 1 << 29;
+        let idx = self.channel();
+        if idx >= PERIODIC_TRIGGER_CHANNELS {
+            return Err(PeriodicTriggerError(()));
+        }
+        // Safety: we own the channel, so we own its DMAMUX configuration register.
+        // The configuration must be disabled before it's changed.
+        unsafe {
+            let chcfg = (DMAMUX_BASE + 4 * idx) as *mut u32;
+            ptr::write_volatile(chcfg, 0);
+            ptr::write_volatile(chcfg, ENBL | TRIG | A_ON);
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> ChannelState {
+        // Safety: the TCD is always valid to read. We own the channel.
+        let (csr, citer, biter) = unsafe {
+            let tcd = tcd(self) as usize;
+            (
+                ptr::read_volatile((tcd + TCD_CSR) as *const u16),
+                ptr::read_volatile((tcd + TCD_CITER) as *const u16),
+                ptr::read_volatile((tcd + TCD_BITER) as *const u16),
+            )
+        };
+        state_from(self.is_error(), self.is_enabled(), csr, citer, biter)
+    }
+}
+
+/// Returns the channel's current major iteration count, the number of minor loops
+/// that are left in the major loop
+pub(crate) fn remaining_iterations(channel: &Channel) -> u16 {
+    // Safety: the TCD is always valid to read. We own the channel.
+    let citer = unsafe { ptr::read_volatile((tcd(channel) as usize + TCD_CITER) as *const u16) };
+    citer & ITER_MASK
+}
+
+/// Returns the number of minor loops in the channel's major loop
+pub(crate) fn transfer_iterations(channel: &Channel) -> u16 {
+    // Safety: the TCD is always valid to read. We own the channel.
+    let biter = unsafe { ptr::read_volatile((tcd(channel) as usize + TCD_BITER) as *const u16) };
+    biter & ITER_MASK
+}
+
+/// A snapshot of a DMA channel's transfer control descriptor (TCD)
+///
+/// Acquire a snapshot with [`tcd_snapshot()`](trait.ChannelExt.html#tymethod.tcd_snapshot).
+/// The `Debug` representation shows addresses in hex.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TcdSnapshot {
+    /// Source address
+    pub saddr: u32,
+    /// Signed source address offset, applied after each read
+    pub soff: i16,
+    /// Transfer attributes
+    pub attr: Attributes,
+    /// Minor loop byte count, including any minor loop offset bits
+    pub nbytes: u32,
+    /// Last source address adjustment
+    pub slast: i32,
+    /// Destination address
+    pub daddr: u32,
+    /// Signed destination address offset, applied after each write
+    pub doff: i16,
+    /// Current major iteration count, including the channel link bits
+    pub citer: u16,
+    /// Last destination address adjustment, or scatter / gather address
+    pub dlast_sga: i32,
+    /// Control and status
+    pub csr: ControlStatus,
+    /// Beginning major iteration count, including the channel link bits
+    pub biter: u16,
+}
+
+impl TcdSnapshot {
+    /// Decode the eight words of a TCD
+    fn from_words(words: [u32; TCD_SIZE / 4]) -> Self {
+        TcdSnapshot {
+            saddr: words[0],
+            soff: words[1] as u16 as i16,
+            attr: Attributes::from_raw((words[1] >> 16) as u16),
+            nbytes: words[2],
+            slast: words[3] as i32,
+            daddr: words[4],
+            doff: words[5] as u16 as i16,
+            citer: (words[5] >> 16) as u16,
+            dlast_sga: words[6] as i32,
+            csr: ControlStatus::from_raw(words[7] as u16),
+            biter: (words[7] >> 16) as u16,
+        }
+    }
+
+    /// Encode the eight words of a TCD
+    fn to_words(&self) -> [u32; TCD_SIZE / 4] {
+        [
+            self.saddr,
+            u32::from(self.soff as u16) | u32::from(self.attr.to_raw()) << 16,
+            self.nbytes,
+            self.slast as u32,
+            self.daddr,
+            u32::from(self.doff as u16) | u32::from(self.citer) << 16,
+            self.dlast_sga as u32,
+            u32::from(self.csr.to_raw()) | u32::from(self.biter) << 16,
+        ]
+    }
+}
+
+impl fmt::Debug for TcdSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcdSnapshot")
+            .field("saddr", &format_args!("{:#010X}", self.saddr))
+            .field("soff", &self.soff)
+            .field("attr", &self.attr)
+            .field("nbytes", &self.nbytes)
+            .field("slast", &self.slast)
+            .field("daddr", &format_args!("{:#010X}", self.daddr))
+            .field("doff", &self.doff)
+            .field("citer", &self.citer)
+            .field("dlast_sga", &format_args!("{:#010X}", self.dlast_sga))
+            .field("csr", &self.csr)
+            .field("biter", &self.biter)
+            .finish()
+    }
+}
+
+/// Decoded TCD transfer attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    /// Source transfer size, in bytes
+    ///
+    /// `None` if the TCD has a reserved size encoding.
+    pub ssize: Option<u8>,
+    /// Destination transfer size, in bytes
+    ///
+    /// `None` if the TCD has a reserved size encoding.
+    pub dsize: Option<u8>,
+    /// Source address modulo
+    pub smod: u8,
+    /// Destination address modulo
+    pub dmod: u8,
+}
+
+impl Attributes {
+    fn from_raw(attr: u16) -> Self {
+        fn size(encoding: u16) -> Option<u8> {
+            match encoding {
+                0 => Some(1),
+                1 => Some(2),
+                2 => Some(4),
+                3 => Some(8),
+                5 => Some(32),
+                _ => None,
+            }
+        }
+        Attributes {
+            smod: (attr >> 11) as u8 & 0x1F,
+            ssize: size((attr >> 8) & 0x7),
+            dmod: (attr >> 3) as u8 & 0x1F,
+            dsize: size(attr & 0x7),
+        }
+    }
+
+    /// Encode the attributes; a `None` size uses a reserved encoding
+    fn to_raw(self) -> u16 {
+        fn encoding(size: Option<u8>) -> u16 {
+            match size {
+                Some(1) => 0,
+                Some(2) => 1,
+                Some(4) => 2,
+                Some(8) => 3,
+                Some(32) => 5,
+                _ => 7,
+            }
+        }
+        u16::from(self.smod & 0x1F) << 11
+            | encoding(self.ssize) << 8
+            | u16::from(self.dmod & 0x1F) << 3
+            | encoding(self.dsize)
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags in the TCD control and status field
+    pub struct ControlFlags : u16 {
+        /// Channel done
+        const DONE = 1 << 7;
+        /// Channel active
+        const ACTIVE = 1 << 6;
+        /// Link to another channel on major loop completion
+        const MAJORELINK = 1 << 5;
+        /// Enable scatter / gather
+        const ESG = 1 << 4;
+        /// Disable the hardware request on completion
+        const DREQ = 1 << 3;
+        /// Interrupt when the major loop is half complete
+        const INTHALF = 1 << 2;
+        /// Interrupt when the major loop is complete
+        const INTMAJOR = 1 << 1;
+        /// Channel start
+        const START = 1 << 0;
+    }
+}
+
+/// Decoded TCD control and status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlStatus {
+    /// Bandwidth control
+    pub bwc: u8,
+    /// The channel to link on major loop completion
+    pub major_link_channel: u8,
+    /// Control and status flags
+    pub flags: ControlFlags,
+}
+
+impl ControlStatus {
+    fn from_raw(csr: u16) -> Self {
+        ControlStatus {
+            bwc: (csr >> 14) as u8,
+            major_link_channel: (csr >> 8) as u8 & 0x1F,
+            flags: ControlFlags::from_bits_truncate(csr),
+        }
+    }
+
+    fn to_raw(self) -> u16 {
+        u16::from(self.bwc & 0x3) << 14
+            | u16::from(self.major_link_channel & 0x1F) << 8
+            | self.flags.bits()
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for super::Channel {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        attr_matches_size, clear_tcd, iterations_from, major_iterations, state_from, write_words,
+        ChannelState, ControlFlags, TcdSnapshot, TCD_SIZE,
+    };
+    use crate::dma::Error;
+
+    /// A channel's TCD in host memory, and an eDMA that runs the TCD's major loop over
+    /// `memory`, where an address is an index into `memory`
+    ///
+    /// The fake moves one byte for each read and write, and it doesn't model scatter
+    /// / gather or channel linking.
+    struct FakeChannel {
+        tcd: [u32; TCD_SIZE / 4],
+        memory: [u8; 64],
+    }
+
+    impl FakeChannel {
+        fn new() -> Self {
+            let mut memory = [0; 64];
+            for (idx, byte) in memory.iter_mut().enumerate() {
+                *byte = idx as u8;
+            }
+            FakeChannel {
+                tcd: [0; TCD_SIZE / 4],
+                memory,
+            }
+        }
+
+        fn write(&mut self, tcd: &TcdSnapshot) {
+            // Safety: the fake TCD is as large as a TCD
+            unsafe { write_words(self.tcd.as_mut_ptr(), &tcd.to_words()) };
+        }
+
+        /// Reset the fake TCD the way that `reset_tcd()` resets the channel's TCD
+        fn reset(&mut self) {
+            // Safety: the fake TCD is as large as a TCD
+            unsafe { clear_tcd(self.tcd.as_mut_ptr()) };
+        }
+
+        /// Write the fields that `Memcpy::transfer()` writes for a copy of `len` bytes
+        ///
+        /// The other fields keep whatever the TCD held, just like the hardware.
+        fn memcpy(&mut self, saddr: u32, daddr: u32, len: u16) {
+            let mut tcd = TcdSnapshot::from_words(self.tcd);
+            tcd.saddr = saddr;
+            tcd.soff = 1;
+            tcd.slast = 0;
+            tcd.daddr = daddr;
+            tcd.doff = 1;
+            tcd.dlast_sga = 0;
+            tcd.attr.ssize = Some(1);
+            tcd.attr.dsize = Some(1);
+            tcd.nbytes = 1;
+            tcd.citer = len;
+            tcd.biter = len;
+            self.write(&tcd);
+        }
+
+        /// Run the TCD's major loop
+        fn run(&mut self) {
+            fn modulo(base: u32, addr: u32, bits: u8) -> u32 {
+                if bits == 0 {
+                    addr
+                } else {
+                    let mask = (1 << bits) - 1;
+                    (base & !mask) | (addr & mask)
+                }
+            }
+            let tcd = TcdSnapshot::from_words(self.tcd);
+            let (mut saddr, mut daddr) = (tcd.saddr, tcd.daddr);
+            for _ in 0..tcd.citer {
+                for _ in 0..tcd.nbytes {
+                    self.memory[daddr as usize] = self.memory[saddr as usize];
+                    saddr = modulo(
+                        tcd.saddr,
+                        saddr.wrapping_add(tcd.soff as u32),
+                        tcd.attr.smod,
+                    );
+                    daddr = modulo(
+                        tcd.daddr,
+                        daddr.wrapping_add(tcd.doff as u32),
+                        tcd.attr.dmod,
+                    );
+                }
+            }
+        }
+    }
+
+    /// A transfer that gathers a column of a 4x4 block into a 4 byte circular buffer
+    const COLUMN: TcdSnapshot = TcdSnapshot {
+        saddr: 1,
+        soff: 4,
+        attr: super::Attributes {
+            ssize: Some(1),
+            dsize: Some(1),
+            smod: 0,
+            dmod: 2,
+        },
+        nbytes: 4,
+        slast: -16,
+        daddr: 16,
+        doff: 1,
+        citer: 1,
+        dlast_sga: 0,
+        csr: super::ControlStatus {
+            bwc: 0,
+            major_link_channel: 0,
+            flags: ControlFlags::empty(),
+        },
+        biter: 1,
+    };
+
+    #[test]
+    fn reset_before_memcpy() {
+        let mut channel = FakeChannel::new();
+        channel.write(&COLUMN);
+        channel.run();
+        assert_eq!(channel.memory[16..20], [1, 5, 9, 13]);
+
+        // A memcpy on the same channel, after a reset, is byte exact
+        channel.reset();
+        assert_eq!(
+            TcdSnapshot::from_words(channel.tcd),
+            TcdSnapshot::from_words([0; 8])
+        );
+        channel.memcpy(32, 48, 8);
+        channel.run();
+        for (idx, byte) in channel.memory[48..56].iter().enumerate() {
+            assert_eq!(*byte, 32 + idx as u8);
+        }
+        assert_eq!(channel.memory[56], 56);
+
+        // Without the reset, the stale destination modulo wraps the copy around
+        // four bytes
+        let mut channel = FakeChannel::new();
+        channel.write(&COLUMN);
+        channel.run();
+        channel.memcpy(32, 48, 8);
+        channel.run();
+        assert_eq!(channel.memory[48..56], [36, 37, 38, 39, 52, 53, 54, 55]);
+    }
+
+    #[test]
+    fn major_iteration_limit() {
+        assert_eq!(major_iterations(0).unwrap(), 0);
+        assert_eq!(major_iterations(0x7FFF).unwrap(), 0x7FFF);
+        assert!(matches!(
+            major_iterations(0x8000),
+            Err(Error::TooManyElements)
+        ));
+        assert!(matches!(
+            major_iterations(100_000),
+            Err(Error::TooManyElements)
+        ));
+    }
+
+    #[test]
+    fn channel_state() {
+        let done = ControlFlags::DONE.bits();
+        let active = ControlFlags::ACTIVE.bits();
+        let start = ControlFlags::START.bits();
+        assert_eq!(state_from(false, false, 0, 16, 16), ChannelState::Idle);
+        assert_eq!(state_from(false, true, 0, 16, 16), ChannelState::Armed);
+        assert_eq!(state_from(false, false, start, 16, 16), ChannelState::Armed);
+        assert_eq!(
+            state_from(false, true, active, 15, 16),
+            ChannelState::Active
+        );
+        assert_eq!(state_from(false, true, 0, 8, 16), ChannelState::Suspended);
+        assert_eq!(state_from(false, false, done, 16, 16), ChannelState::Done);
+        assert_eq!(state_from(true, true, active, 8, 16), ChannelState::Error);
+    }
+
+    #[test]
+    fn completed_iterations() {
+        // Nothing transferred
+        assert_eq!(iterations_from(false, 16, 16), 0);
+        // Partial transfer
+        assert_eq!(iterations_from(false, 6, 16), 10);
+        // Completion raced with the cancel; CITER was reloaded from BITER
+        assert_eq!(iterations_from(true, 16, 16), 16);
+        // ELINK bits are ignored
+        assert_eq!(iterations_from(false, 0x8006, 0x8010), 10);
+    }
+
+    #[test]
+    fn decode_tcd() {
+        let tcd = TcdSnapshot::from_words([
+            0x2020_0000,
+            0x0200_0002 | 0x0800_0000,
+            4,
+            (-8i32) as u32,
+            0x4018_4038,
+            0x0008_0000,
+            0,
+            0x0008_0000 | 0x050A,
+        ]);
+        assert_eq!(tcd.saddr, 0x2020_0000);
+        assert_eq!(tcd.soff, 2);
+        assert_eq!(tcd.attr.ssize, Some(4));
+        assert_eq!(tcd.attr.smod, 1);
+        assert_eq!(tcd.attr.dsize, Some(1));
+        assert_eq!(tcd.attr.dmod, 0);
+        assert_eq!(tcd.nbytes, 4);
+        assert_eq!(tcd.slast, -8);
+        assert_eq!(tcd.daddr, 0x4018_4038);
+        assert_eq!(tcd.doff, 0);
+        assert_eq!(tcd.citer, 8);
+        assert_eq!(tcd.biter, 8);
+        assert_eq!(tcd.csr.major_link_channel, 5);
+        assert_eq!(tcd.csr.flags, ControlFlags::DREQ | ControlFlags::INTMAJOR);
+    }
+
+    #[test]
+    fn encode_tcd() {
+        let words = [
+            0x2020_0000,
+            0x0200_0004,
+            60,
+            (-60i32) as u32,
+            0x4039_4060,
+            0x0001_0004,
+            (-4i32) as u32,
+            0x0001_0000,
+        ];
+        let mut tcd = TcdSnapshot::from_words(words);
+        assert_eq!(tcd.to_words(), words);
+
+        tcd.attr.dmod = 3;
+        tcd.csr.major_link_channel = 5;
+        tcd.csr.flags = ControlFlags::INTMAJOR;
+        let words = tcd.to_words();
+        assert_eq!(words[1], 0x0200_0004 | 3 << 19);
+        assert_eq!(words[7], 0x0001_0502);
+        assert_eq!(TcdSnapshot::from_words(words), tcd);
+    }
+
+    #[test]
+    fn element_sizes() {
+        // SSIZE = DSIZE = 16 bit
+        assert!(attr_matches_size(0x0101, 2));
+        assert!(!attr_matches_size(0x0101, 1));
+        // SSIZE = 8 bit, DSIZE = 32 bit
+        assert!(!attr_matches_size(0x0002, 1));
+        assert!(!attr_matches_size(0x0002, 4));
+        // Modulo fields don't matter
+        assert!(attr_matches_size(0x5A12, 4));
+    }
+}
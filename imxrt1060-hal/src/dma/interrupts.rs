@@ -0,0 +1,138 @@
+//! DMA interrupt dispatch
+//!
+//! See the [module-level docs](index.html#interrupts) for an example.
+
+use super::CHANNEL_COUNT;
+use core::{cell::Cell, ptr};
+use cortex_m::interrupt::{self, Mutex};
+
+/// The number of DMA interrupts; each serves two channels
+pub const VECTOR_COUNT: usize = CHANNEL_COUNT / 2;
+
+/// Interrupt request register
+const INT: *mut u32 = (0x400E_8000 + 0x24) as *mut u32;
+
+/// Returns the DMA interrupt that serves `channel`
+///
+/// ```
+/// use imxrt1060_hal::dma;
+/// assert_eq!(dma::vector_for(7), 7);
+/// assert_eq!(dma::vector_for(23), 7);
+/// ```
+pub const fn vector_for(channel: usize) -> usize {
+    channel % VECTOR_COUNT
+}
+
+/// Returns the INT bits for the two channels served by `vector`
+///
+/// Returns 0 if `vector` is out of range.
+const fn vector_mask(vector: usize) -> u32 {
+    if vector < VECTOR_COUNT {
+        (1 << vector) | (1 << (vector + VECTOR_COUNT))
+    } else {
+        0
+    }
+}
+
+/// The channels that signaled an interrupt
+///
+/// Acquire this from [`on_dma_interrupt()`](fn.on_dma_interrupt.html), or
+/// [`dispatch()`](fn.dispatch.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelInterrupts(u32);
+
+impl ChannelInterrupts {
+    /// Returns `true` if `channel` signaled an interrupt
+    pub fn contains(self, channel: usize) -> bool {
+        channel < CHANNEL_COUNT && self.0 & (1 << channel) != 0
+    }
+
+    /// Returns `true` if no channel signaled an interrupt
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the channels that signaled an interrupt, lowest channel first
+    pub fn channels(self) -> impl Iterator<Item = usize> {
+        (0..CHANNEL_COUNT).filter(move |&channel| self.contains(channel))
+    }
+}
+
+/// Returns the channels served by interrupt `vector_index` that signaled, and clears their
+/// interrupt flags
+///
+/// Call this in the `DMA[n]_DMA[n+16]` interrupt handler, passing `n`. The result may include
+/// one, both, or neither of the channels. If `vector_index` is 16 or more, the result is empty,
+/// and no flags are cleared.
+pub fn on_dma_interrupt(vector_index: usize) -> ChannelInterrupts {
+    let mask = vector_mask(vector_index);
+    // Safety: INT is always valid to read. Writing a one clears that channel's flag, and
+    // zeros have no effect, so we only affect the channels that are served by this interrupt.
+    let fired = unsafe {
+        let fired = ptr::read_volatile(INT) & mask;
+        ptr::write_volatile(INT, fired);
+        fired
+    };
+    ChannelInterrupts(fired)
+}
+
+/// A channel's interrupt handler
+type Handler = Option<fn()>;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: Mutex<Cell<Handler>> = Mutex::new(Cell::new(None));
+static HANDLERS: [Mutex<Cell<Handler>>; CHANNEL_COUNT] = [NO_HANDLER; CHANNEL_COUNT];
+
+/// Register `handler` for `channel`, replacing any previous handler
+///
+/// [`dispatch()`](fn.dispatch.html) calls the handler when the channel signals an interrupt.
+/// Use `None` to remove the handler. Does nothing if `channel` is out of range.
+pub fn set_handler(channel: usize, handler: Option<fn()>) {
+    if let Some(slot) = HANDLERS.get(channel) {
+        interrupt::free(|cs| slot.borrow(cs).set(handler));
+    }
+}
+
+/// Clear the interrupt flags for the channels served by `vector_index`, and call their handlers
+///
+/// Handlers run outside of a critical section, lowest channel first. Returns the channels
+/// that signaled, including channels that don't have a handler.
+pub fn dispatch(vector_index: usize) -> ChannelInterrupts {
+    let fired = on_dma_interrupt(vector_index);
+    for channel in fired.channels() {
+        if let Some(handler) = interrupt::free(|cs| HANDLERS[channel].borrow(cs).get()) {
+            handler();
+        }
+    }
+    fired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{vector_for, vector_mask, ChannelInterrupts};
+
+    #[test]
+    fn vector_masks() {
+        assert_eq!(vector_mask(0), 0x0001_0001);
+        assert_eq!(vector_mask(7), 0x0080_0080);
+        assert_eq!(vector_mask(15), 0x8000_8000);
+        assert_eq!(vector_mask(16), 0);
+        for channel in 0..32 {
+            assert_ne!(vector_mask(vector_for(channel)) & (1 << channel), 0);
+        }
+    }
+
+    #[test]
+    fn channel_interrupts() {
+        let fired = ChannelInterrupts(0x0080_0080);
+        assert!(fired.contains(7));
+        assert!(fired.contains(23));
+        assert!(!fired.contains(8));
+        assert!(!fired.contains(40));
+        let mut channels = fired.channels();
+        assert_eq!(channels.next(), Some(7));
+        assert_eq!(channels.next(), Some(23));
+        assert_eq!(channels.next(), None);
+        assert!(ChannelInterrupts(0).is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 //! DMA-powered memory copy
 
-use super::{buffer, Channel, Element, Error};
+use super::{buffer, channel, Channel, ChannelExt, ChannelState, Element, Error, TcdSnapshot};
 use core::{
     marker::PhantomData,
     sync::atomic::{compiler_fence, Ordering},
@@ -15,6 +15,15 @@ use core::{
 /// A `Memcpy` accepts either a [`Linear`](struct.Linear.html)
 /// or a [`Circular`](struct.Circular.html) buffer.
 ///
+/// # Channel configuration
+///
+/// `Memcpy` [resets the channel's TCD](trait.ChannelExt.html#tymethod.reset_tcd) when it's
+/// created, and when the channel is taken back. The only setting that `Memcpy` keeps from
+/// your channel configuration is the interrupt on completion. `Memcpy` then uses an
+/// always-on channel that disables itself on completion, with a minor loop of one
+/// element. Each transfer sets the source and destination addresses, offsets, and
+/// last address adjustments, along with the number of iterations.
+///
 /// # Example
 ///
 /// ```no_run
@@ -30,8 +39,8 @@ use core::{
 ///
 /// let mut memcpy = dma::Memcpy::new(dma_channel);
 ///
-/// let mut source = dma::Linear::new(&SOURCE).unwrap();
-/// let mut destination = dma::Linear::new(&DESTINATION).unwrap();
+/// let mut source = dma::Linear::try_new(&SOURCE).unwrap();
+/// let mut destination = dma::Linear::try_new(&DESTINATION).unwrap();
 ///
 /// source.as_mut_elements()[..14].copy_from_slice(&[8; 14]);
 /// source.set_transfer_len(14);
@@ -48,12 +57,109 @@ use core::{
 /// // Don't forget to clear the complete signal.
 /// let (source, destination) = memcpy.complete().unwrap().unwrap();
 /// ```
-pub struct Memcpy<E, S, D> {
+///
+/// # Element types
+///
+/// The source and destination buffers must use the `Memcpy`'s element type. To fix the element
+/// type when you create the `Memcpy`, name it explicitly:
+///
+/// ```no_run
+/// use imxrt1060_hal::dma;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+/// let mut memcpy = dma::Memcpy::<u16, dma::Linear<_>, dma::Linear<_>>::new(
+///     dma_channels[7].take().unwrap()
+/// );
+/// ```
+///
+/// Or let the first transfer's buffers supply the type:
+///
+/// ```no_run
+/// use imxrt1060_hal::dma;
+///
+/// static SOURCE: dma::Buffer<[u16; 32]> = dma::Buffer::new([0; 32]);
+/// static DESTINATION: dma::Buffer<[u16; 32]> = dma::Buffer::new([0; 32]);
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+/// let mut memcpy = dma::Memcpy::new(dma_channels[7].take().unwrap());
+///
+/// let source = dma::Linear::try_new(&SOURCE).unwrap();
+/// let destination = dma::Linear::try_new(&DESTINATION).unwrap();
+/// memcpy.transfer(source, destination).unwrap();
+/// ```
+///
+/// A `Memcpy` with buffers of a different element type cannot be constructed:
+///
+/// ```compile_fail
+/// use imxrt1060_hal::dma;
+///
+/// fn mismatch(memcpy: dma::Memcpy<u16, dma::Linear<u8>, dma::Linear<u8>>) {}
+/// ```
+///
+/// # Preemption and starvation
+///
+/// A `Memcpy` channel is always requesting service, so it competes with every other channel
+/// in its group. With fixed-priority arbitration, the DMA controller always services the
+/// highest-priority requesting channel. If a higher-priority peripheral channel is busy, or
+/// if it can preempt the `Memcpy` channel, the `Memcpy` stops making progress until the
+/// other channel is idle. A long `Memcpy` on a low-priority channel may never finish while
+/// a busy, high-priority stream is running.
+///
+/// Use [`progress()`](struct.Memcpy.html#method.progress) to tell a stalled transfer from
+/// one that's moving data. If the transfer is stalled, you can wait, or give it more bandwidth
+/// by moving it to a higher-priority channel or group; see [`Controller`](struct.Controller.html).
+///
+/// ```no_run
+/// use imxrt1060_hal::dma::{self, Progress};
+/// # fn wait(memcpy: &mut dma::Memcpy<u8, dma::Linear<u8>, dma::Linear<u8>>) {
+///
+/// loop {
+///     match memcpy.progress() {
+///         Progress::Complete => break,
+///         Progress::Moving { .. } => {}
+///         Progress::Stalled { remaining } => {
+///             // Another channel is starving this transfer
+///         }
+///     }
+/// }
+/// # }
+/// ```
+pub struct Memcpy<E, S, D>
+where
+    E: Element,
+    S: buffer::Source<E>,
+    D: buffer::Destination<E>,
+{
     channel: Channel,
     buffers: Option<(S, D)>,
+    /// Remaining iterations when `progress()` was last called
+    last_remaining: Option<u16>,
     _element: PhantomData<E>,
 }
 
+/// The progress of a `Memcpy` transfer
+///
+/// See [`Memcpy::progress()`](struct.Memcpy.html#method.progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// The transfer is complete, or there's no transfer
+    Complete,
+    /// The transfer moved data since the last check
+    Moving {
+        /// The number of elements that are left to transfer
+        remaining: usize,
+    },
+    /// The transfer didn't move any data since the last check
+    ///
+    /// The channel is waiting for arbitration, or it was preempted.
+    Stalled {
+        /// The number of elements that are left to transfer
+        remaining: usize,
+    },
+}
+
 impl<E: Element, S, D> Memcpy<E, S, D>
 where
     S: buffer::Source<E>,
@@ -61,17 +167,25 @@ where
 {
     /// Create a type that can perform memory-to-memory DMA transfers
     pub fn new(mut channel: Channel) -> Self {
+        crate::ccm::clock_gate::debug_assert_clocked!(crate::ccm::clock_gate::Dma);
+        let interrupt_on_completion = channel::is_interrupt_on_completion(&channel);
+        channel.reset_tcd();
+        channel.set_interrupt_on_completion(interrupt_on_completion);
         channel.set_always_on();
         channel.set_disable_on_completion(true);
         Memcpy {
             channel,
             buffers: None,
+            last_remaining: None,
             _element: PhantomData,
         }
     }
 
     /// Take the underlying DMA channel, and destroy the `Memcpy`
-    pub fn take(self) -> Channel {
+    ///
+    /// The channel's TCD is reset, so it's ready to use for any other kind of transfer.
+    pub fn take(mut self) -> Channel {
+        self.channel.reset_tcd();
         self.channel
     }
 
@@ -82,9 +196,29 @@ where
     ///
     /// The number of elements transferred is the minimum size of the two
     /// buffers.
+    ///
+    /// Before enabling the channel, `transfer()` checks that the channel's source and
+    /// destination transfer sizes are both the size of `E`. If they aren't, `transfer()`
+    /// returns [`Error::IncompatibleElements`](enum.Error.html#variant.IncompatibleElements).
+    ///
+    /// If the channel still holds an error from an earlier transfer, `transfer()` returns
+    /// [`Error::PreexistingError`](enum.Error.html#variant.PreexistingError) without touching
+    /// the channel. Use [`clear_error()`](struct.Memcpy.html#method.clear_error) before trying again.
     pub fn transfer(&mut self, mut source: S, mut destination: D) -> Result<(), (S, D, Error)> {
-        if self.buffers.is_some() || self.channel.is_enabled() {
-            return Err((source, destination, Error::ScheduledTransfer));
+        let scheduled = self.buffers.is_some() || self.channel.is_enabled();
+        let error = if self.channel.is_error() {
+            Some(self.channel.error_status())
+        } else {
+            None
+        };
+        match check_ready(scheduled, error) {
+            Err(NotReady::Scheduled) => {
+                return Err((source, destination, Error::ScheduledTransfer))
+            }
+            Err(NotReady::Errored(es)) => {
+                return Err((source, destination, Error::PreexistingError(es)))
+            }
+            Ok(()) => {}
         }
 
         let src = source.source();
@@ -95,6 +229,13 @@ where
             self.channel.set_destination_transfer(&dst);
         }
 
+        if let Err(err) = check_elements(
+            channel::attributes(&self.channel),
+            core::mem::size_of::<E>(),
+        ) {
+            return Err((source, destination, err));
+        }
+
         source.prepare_source();
         destination.prepare_destination();
 
@@ -114,6 +255,7 @@ where
             Err((source, destination, Error::Setup(es)))
         } else {
             self.buffers = Some((source, destination));
+            self.last_remaining = None;
             Ok(())
         }
     }
@@ -127,6 +269,77 @@ where
         self.channel.is_complete()
     }
 
+    /// Returns the channel's state
+    ///
+    /// See [`ChannelExt::state()`](trait.ChannelExt.html#tymethod.state) for details.
+    pub fn state(&self) -> ChannelState {
+        self.channel.state()
+    }
+
+    /// Returns `true` if the DMA controller is executing the transfer right now
+    ///
+    /// If the transfer is not complete, but it's not active, it may have been preempted
+    /// by another transfer. Use [`progress()`](struct.Memcpy.html#method.progress) to
+    /// learn if the transfer is still moving data.
+    pub fn is_active(&self) -> bool {
+        self.channel.is_active()
+    }
+
+    /// Check if the transfer moved data since the last call to `progress()`
+    ///
+    /// `progress()` compares the channel's remaining iterations against the value from the previous
+    /// call. The first call after a transfer starts compares against the full transfer. Poll at an
+    /// interval that's longer than the time to move one element, or `progress()` may report a stall
+    /// for a transfer that's only slow.
+    pub fn progress(&mut self) -> Progress {
+        if self.buffers.is_none() || self.channel.is_complete() {
+            return Progress::Complete;
+        }
+        let remaining = channel::remaining_iterations(&self.channel);
+        let previous = self
+            .last_remaining
+            .replace(remaining)
+            .unwrap_or_else(|| channel::transfer_iterations(&self.channel));
+        if remaining < previous {
+            Progress::Moving {
+                remaining: remaining as usize,
+            }
+        } else {
+            Progress::Stalled {
+                remaining: remaining as usize,
+            }
+        }
+    }
+
+    /// Returns a snapshot of the channel's TCD
+    ///
+    /// See [`ChannelExt::tcd_snapshot()`](trait.ChannelExt.html#tymethod.tcd_snapshot) for details.
+    pub fn dump_tcd(&self) -> TcdSnapshot {
+        self.channel.tcd_snapshot()
+    }
+
+    /// Clears the channel's error state
+    ///
+    /// [`complete()`](struct.Memcpy.html#method.complete) clears the error state for you. Use
+    /// `clear_error()` if a [`transfer()`](struct.Memcpy.html#method.transfer) reports an
+    /// [`Error::PreexistingError`](enum.Error.html#variant.PreexistingError).
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{Error, Linear, Memcpy};
+    /// # fn schedule(memcpy: &mut Memcpy<u8, Linear<u8>, Linear<u8>>, src: Linear<u8>, dst: Linear<u8>) {
+    /// match memcpy.transfer(src, dst) {
+    ///     Err((src, dst, Error::PreexistingError(_))) => {
+    ///         memcpy.clear_error();
+    ///         memcpy.transfer(src, dst).unwrap();
+    ///     }
+    ///     result => result.unwrap(),
+    /// }
+    /// # }
+    /// ```
+    pub fn clear_error(&mut self) {
+        self.channel.clear_error();
+    }
+
     /// Returns `true` if this transfer has generated an interrupt
     pub fn is_interrupt(&self) -> bool {
         self.channel.is_interrupt()
@@ -151,8 +364,12 @@ where
     /// - `None` indicates that there's no scheduled transfer; we have no buffers
     /// - `Some(Ok(..))` indicates that the transfer was complete when `complete()` was called
     /// - `Some(Err(..))` indicates that the transfer was in progress, but was cancelled
+    ///
+    /// In either case, `complete()` clears any channel error, so that the
+    /// channel is ready for the next transfer.
     pub fn complete(&mut self) -> Option<Result<(S, D), (S, D)>> {
         self.buffers.take().map(|(mut source, mut destination)| {
+            self.channel.clear_error();
             if self.is_complete() {
                 self.channel.clear_complete();
                 source.complete_source();
@@ -166,3 +383,74 @@ where
         })
     }
 }
+
+/// Why a channel can't start a transfer
+#[derive(Debug, PartialEq, Eq)]
+enum NotReady<ES> {
+    /// The channel has a transfer
+    Scheduled,
+    /// The channel holds an error status from an earlier transfer
+    Errored(ES),
+}
+
+/// Check that a channel can start a transfer
+///
+/// `scheduled` is `true` if the channel has a transfer, and `error` is the channel's
+/// error status, if it has one. A scheduled transfer takes precedence, since the error
+/// might belong to that transfer.
+fn check_ready<ES>(scheduled: bool, error: Option<ES>) -> Result<(), NotReady<ES>> {
+    if scheduled {
+        Err(NotReady::Scheduled)
+    } else if let Some(es) = error {
+        Err(NotReady::Errored(es))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that the transfer attributes `attr` move elements of `size` bytes
+fn check_elements(attr: u16, size: usize) -> Result<(), Error> {
+    if channel::attr_matches_size(attr, size) {
+        Ok(())
+    } else {
+        Err(Error::IncompatibleElements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_elements, check_ready, Error, NotReady};
+
+    #[test]
+    fn preexisting_error() {
+        // An earlier transfer left an error, and it's still scheduled
+        let es = 0x8000_0001u32;
+        assert_eq!(check_ready(true, Some(es)), Err(NotReady::Scheduled));
+        // complete() takes the buffers, but the user didn't clear the error
+        assert_eq!(check_ready(false, Some(es)), Err(NotReady::Errored(es)));
+        // After clear_error(), the next transfer starts
+        assert_eq!(check_ready::<u32>(false, None), Ok(()));
+        assert_eq!(check_ready::<u32>(true, None), Err(NotReady::Scheduled));
+    }
+
+    #[test]
+    fn element_size_mismatch() {
+        // SSIZE and DSIZE of u16 buffers
+        assert!(check_elements(0x0101, 2).is_ok());
+        // u16 elements, but u8 transfer sizes
+        assert!(matches!(
+            check_elements(0x0000, 2),
+            Err(Error::IncompatibleElements)
+        ));
+        // u32 elements, but u16 transfer sizes
+        assert!(matches!(
+            check_elements(0x0101, 4),
+            Err(Error::IncompatibleElements)
+        ));
+        // A source and destination that disagree
+        assert!(matches!(
+            check_elements(0x0102, 2),
+            Err(Error::IncompatibleElements)
+        ));
+    }
+}
@@ -15,7 +15,10 @@
 //! See the [Rust API guidelines on future-proofing](https://rust-lang.github.io/api-guidelines/future-proofing.html#sealed-traits-protect-against-downstream-implementations-c-sealed)
 //! to learn about the 'Sealed' pattern. Use the UART peripheral as an example.
 
-use super::{buffer, Channel, Circular, Element, Error, ReadHalf, Transfer, WriteHalf};
+use super::{
+    buffer, channel, Channel, ChannelExt, Circular, Element, Error, PeriodicTriggerError, ReadHalf,
+    TcdSnapshot, Transfer, WriteHalf,
+};
 use core::sync::atomic::{compiler_fence, Ordering};
 pub use imxrt_dma::{Destination, Source};
 
@@ -30,6 +33,12 @@ pub use imxrt_dma::{Destination, Source};
 /// interrupt, and for clearing the interrupt. The `Peripheral` has methods for clearing interrupts
 /// due to transfer and receive DMA channels.
 ///
+/// A `Peripheral` configures the channel's hardware trigger, the peripheral side of the transfer,
+/// and disables the channel on completion. Each transfer sets the buffer side of the transfer,
+/// the minor loop, and the number of iterations. Any other TCD field is used as-is. If you're
+/// reusing a channel from another kind of transfer, call [`reset_tcd()`](trait.ChannelExt.html#tymethod.reset_tcd)
+/// before configuring the channel.
+///
 /// See the [module-level docs](index.html#example-full-duplex-spi-peripheral)
 /// for an example of how to create and use a peripheral.
 pub struct Peripheral<P, E, S, D = S> {
@@ -45,10 +54,112 @@ pub struct Peripheral<P, E, S, D = S> {
     source_buffer: Option<S>,
     /// The buffer that's used to receive data in a DMA transfer
     destination_buffer: Option<D>,
+    /// The progress of a chunked receive
+    chunks: Option<Chunks>,
+    /// `true` if the source buffer is streaming data to the peripheral
+    streaming: bool,
+    /// Spare and filled buffers, when receiving into two buffers
+    double_buffer: Option<DoubleBuffer<D>>,
+}
+
+/// The progress of a chunked receive
+///
+/// The write position of a circular receive can't tell a full buffer from an empty
+/// one. The channel's interrupt flag is set at the end of every pass through the
+/// buffer. A flag without a write position that crossed the end of the buffer means
+/// that the DMA controller wrapped around the whole buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Chunks {
+    /// The number of elements in each chunk
+    elements: usize,
+    /// The number of elements in the backing buffer, a power of two
+    capacity: usize,
+    /// The address of the backing buffer
+    base: usize,
+    /// The size of each element, in bytes
+    element_size: usize,
+    /// The write position when the receive was last observed
+    write: usize,
+    /// Received elements that the user hasn't taken
+    unread: usize,
+    /// `true` if the last observation saw the write position cross the end of the
+    /// buffer before it saw the interrupt flag, so the next flag was already counted
+    owed: bool,
+    /// `true` once the DMA controller overwrote elements that weren't taken
+    overrun: bool,
+}
+
+impl Chunks {
+    fn new(elements: usize, capacity: usize, base: usize, element_size: usize) -> Self {
+        Chunks {
+            elements,
+            capacity,
+            base,
+            element_size,
+            write: 0,
+            unread: 0,
+            owed: false,
+            overrun: false,
+        }
+    }
+
+    /// Returns the index of the element at `addr`, an address within the backing buffer
+    fn index_of(&self, addr: usize) -> usize {
+        (addr.wrapping_sub(self.base) / self.element_size) & (self.capacity - 1)
+    }
+
+    /// Record an observation of the receive
+    ///
+    /// `interrupted` is `true` if the interrupt flag was set, and `write` is the write
+    /// position that's read after checking the flag.
+    fn observe(&mut self, interrupted: bool, write: usize) {
+        let received = write.wrapping_sub(self.write) & (self.capacity - 1);
+        let wrapped = write < self.write;
+        let mut interrupted = interrupted;
+        if self.owed && interrupted {
+            interrupted = false;
+            self.owed = false;
+        }
+        if wrapped && !interrupted {
+            self.owed = true;
+        }
+        self.unread += received;
+        if (interrupted && !wrapped) || self.unread >= self.capacity {
+            self.overrun = true;
+        }
+        self.write = write;
+    }
+
+    /// Returns the number of chunks that are ready to take, or an overrun
+    fn available(&self) -> Result<usize, Error> {
+        if self.overrun {
+            Err(Error::Overrun)
+        } else {
+            Ok(self.unread / self.elements)
+        }
+    }
+
+    /// Record that the user took a chunk
+    fn take(&mut self) {
+        self.unread -= self.elements;
+    }
+}
+
+/// Buffers that aren't being filled by a double-buffered receive
+///
+/// While the receive is running, exactly one of `next` and `full` holds a buffer.
+struct DoubleBuffer<D> {
+    /// The buffer to fill once the active buffer is full
+    next: Option<D>,
+    /// The filled buffer, waiting for the user
+    full: Option<D>,
+    /// A second filled buffer. If this holds a buffer, the receive has stopped.
+    overrun: Option<D>,
 }
 
 impl<P, E, S, D> Peripheral<P, E, S, D> {
     fn new(peripheral: P) -> Self {
+        crate::ccm::clock_gate::debug_assert_clocked!(crate::ccm::clock_gate::Dma);
         Peripheral {
             peripheral,
             rx_channel: None,
@@ -56,8 +167,24 @@ impl<P, E, S, D> Peripheral<P, E, S, D> {
             _element: core::marker::PhantomData,
             source_buffer: None,
             destination_buffer: None,
+            chunks: None,
+            streaming: false,
+            double_buffer: None,
         }
     }
+
+    /// Returns the wrapped peripheral
+    ///
+    /// For drivers that keep state alongside the peripheral's registers. Don't
+    /// use it to reconfigure the peripheral's DMA requests.
+    pub(crate) fn peripheral_mut(&mut self) -> &mut P {
+        &mut self.peripheral
+    }
+
+    /// Returns the wrapped peripheral
+    pub(crate) fn peripheral(&self) -> &P {
+        &self.peripheral
+    }
 }
 
 impl<P, E, S, D> Peripheral<P, E, S, D>
@@ -147,8 +274,29 @@ where
     /// Users are **required** to clear the interrupt flag, or the hardware
     /// may continue to generate interrupts for the channel. This must be called
     /// for completion interrupts and half-transfer interrupts.
+    ///
+    /// While [receiving chunks](struct.Peripheral.html#method.receive_chunks), clearing the
+    /// flag also counts the received chunks.
     pub fn receive_clear_interrupt(&mut self) {
-        self.rx_channel.as_mut().unwrap().clear_interrupt()
+        if self.chunks.is_some() {
+            self.observe_chunks();
+        } else {
+            self.rx_channel.as_mut().unwrap().clear_interrupt()
+        }
+    }
+
+    /// Count the chunks that arrived since the last observation, and clear the
+    /// interrupt flag
+    fn observe_chunks(&mut self) {
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        if let Some(chunks) = self.chunks.as_mut() {
+            let interrupted = rx_channel.is_interrupt();
+            if interrupted {
+                rx_channel.clear_interrupt();
+            }
+            let write = chunks.index_of(channel::destination_address(rx_channel));
+            chunks.observe(interrupted, write);
+        }
     }
 
     /// Cancel a receive transfer
@@ -164,6 +312,245 @@ where
         self.destination_buffer.take()
     }
 
+    /// Wait for the receive to complete, or for `expired` to return `true`
+    ///
+    /// `receive_with_timeout()` polls the receive. If the receive completes first, the result
+    /// is the same as [`receive_complete()`](struct.Peripheral.html#method.receive_complete). Otherwise,
+    /// `receive_with_timeout()` disables the peripheral's DMA request, waits for any in-flight element
+    /// to land in the buffer, and disables the channel. It returns the buffer, and the number of
+    /// elements received. If the receive completes while it's being cancelled, the result is a complete
+    /// receive.
+    ///
+    /// `expired` is your deadline check; it could compare a timer's count against a deadline.
+    /// Returns `None` if there's no receive in progress.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, Linear, ReceiveTimeout};
+    /// # use imxrt1060_hal::{gpt::GPT, iomuxc::consts::U2, uart::UART};
+    /// # fn read(rx: &mut dma::Peripheral<UART<U2>, u8, Linear<u8>>, gpt: &GPT) {
+    /// let start = gpt.count();
+    /// match rx.receive_with_timeout(|| gpt.count().wrapping_sub(start) > 10_000) {
+    ///     Some(ReceiveTimeout::Complete(buffer)) => { /* All elements received */ }
+    ///     Some(ReceiveTimeout::Expired { buffer, received }) => {
+    ///         let data = &buffer.as_elements()[..received];
+    ///     }
+    ///     None => { /* No receive was scheduled */ }
+    /// }
+    /// # }
+    /// ```
+    pub fn receive_with_timeout<F>(&mut self, mut expired: F) -> Option<ReceiveTimeout<D>>
+    where
+        F: FnMut() -> bool,
+    {
+        self.destination_buffer.as_ref()?;
+        loop {
+            if self.is_receive_complete() {
+                return self.receive_complete().map(ReceiveTimeout::Complete);
+            }
+            if expired() {
+                break;
+            }
+        }
+        self.receive_stop()
+    }
+
+    /// Stop the receive early, keeping the elements received so far
+    ///
+    /// Disables the peripheral's DMA request, waits for any in-flight element to land in the
+    /// buffer, and disables the channel. Returns the buffer, and the number of elements received,
+    /// as [`ReceiveTimeout::Expired`](enum.ReceiveTimeout.html). If the receive completes while
+    /// it's being stopped, the result is a complete receive. Returns `None` if there's no receive
+    /// in progress.
+    pub fn receive_stop(&mut self) -> Option<ReceiveTimeout<D>> {
+        self.destination_buffer.as_ref()?;
+        self.peripheral.disable_source();
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        while rx_channel.is_hardware_signaling() || rx_channel.is_active() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        rx_channel.disable();
+        compiler_fence(Ordering::Acquire);
+
+        if rx_channel.is_complete() {
+            return self.receive_complete().map(ReceiveTimeout::Complete);
+        }
+        let received = channel::completed_iterations(rx_channel, false);
+        self.destination_buffer.take().map(|mut buffer| {
+            buffer.complete_destination_partial(received);
+            ReceiveTimeout::Expired { buffer, received }
+        })
+    }
+
+    /// Returns `true` if there's a receive in progress, or a complete receive
+    /// that holds its buffer
+    pub(crate) fn is_receive_scheduled(&self) -> bool {
+        self.destination_buffer.is_some()
+    }
+
+    /// Returns the number of elements received into the buffer so far
+    ///
+    /// Returns 0 if there's no receive in progress, or if the receive is complete.
+    pub fn receive_progress(&self) -> usize {
+        if self.destination_buffer.is_none() {
+            return 0;
+        }
+        channel::completed_iterations(self.rx_channel.as_ref().unwrap(), false)
+    }
+
+    /// Start receiving into two buffers, `first` then `second`
+    ///
+    /// When one buffer fills, the DMA controller starts filling the other. Hand the filled buffer
+    /// to a consumer with [`swap_completed()`](struct.Peripheral.html#method.swap_completed), which
+    /// exchanges it for a fresh buffer. The peripheral's DMA request stays enabled for the whole
+    /// receive.
+    ///
+    /// The switch happens in software. `start_double_buffered()` enables the receive channel's
+    /// interrupt on completion; call [`poll_double_buffered()`](struct.Peripheral.html#method.poll_double_buffered)
+    /// in the DMA interrupt handler. The time between a buffer filling and the next element arriving is your
+    /// budget for handling the interrupt. Make sure the peripheral can hold elements in a FIFO while the
+    /// interrupt runs.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, Linear};
+    /// # use imxrt1060_hal::{iomuxc::consts::U2, uart::UART};
+    /// # fn capture(rx: &mut dma::Peripheral<UART<U2>, u8, Linear<u8>>, a: Linear<u8>, b: Linear<u8>, mut fresh: Linear<u8>) {
+    ///
+    /// rx.start_double_buffered(a, b).unwrap();
+    ///
+    /// // In the DMA interrupt handler...
+    /// rx.receive_clear_interrupt();
+    /// match rx.poll_double_buffered() {
+    ///     Ok(true) => { /* A buffer is ready for swap_completed() */ }
+    ///     Ok(false) => {}
+    ///     Err(dma::Error::Overrun) => { /* Receive stopped */ }
+    ///     Err(_) => { /* Receive stopped */ }
+    /// }
+    ///
+    /// // In the consumer...
+    /// if let Ok(full) = rx.swap_completed(fresh) {
+    ///     // Write full.as_elements() to the SD card, then reuse it as the next fresh buffer
+    /// }
+    /// # }
+    /// ```
+    pub fn start_double_buffered(&mut self, first: D, second: D) -> Result<(), (D, D, Error)> {
+        if self.double_buffer.is_some() || self.destination_buffer.is_some() {
+            return Err((first, second, Error::ScheduledTransfer));
+        }
+        self.rx_channel
+            .as_mut()
+            .unwrap()
+            .set_interrupt_on_completion(true);
+        self.start_receive(first)
+            .map_err(|(first, err)| (first, second, err))?;
+        self.double_buffer = Some(DoubleBuffer {
+            next: Some(second),
+            full: None,
+            overrun: None,
+        });
+        Ok(())
+    }
+
+    /// Switch to the next buffer if the active buffer is full
+    ///
+    /// Returns `Ok(true)` if a buffer filled, and is ready for [`swap_completed()`](struct.Peripheral.html#method.swap_completed).
+    /// Returns `Ok(false)` if the receive isn't double buffered, or the active buffer isn't full.
+    ///
+    /// If both buffers fill before you swap the first, there's nowhere to put the next element.
+    /// `poll_double_buffered()` disables the peripheral's DMA request, and returns [`Error::Overrun`](enum.Error.html#variant.Overrun).
+    /// Both filled buffers are kept intact; use `swap_completed()` to take them, then
+    /// [`restart_double_buffered()`](struct.Peripheral.html#method.restart_double_buffered).
+    pub fn poll_double_buffered(&mut self) -> Result<bool, Error> {
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        let double_buffer = match self.double_buffer.as_mut() {
+            Some(double_buffer) if rx_channel.is_complete() => double_buffer,
+            _ => return Ok(false),
+        };
+        rx_channel.clear_complete();
+        // Unwrap OK: a complete, double-buffered receive always has an active buffer
+        let mut completed = self.destination_buffer.take().unwrap();
+
+        match double_buffer.next.take() {
+            Some(next) => {
+                // Re-arm before anything else, to keep the gap short
+                let result = self.start_receive(next).map_err(|(next, err)| {
+                    self.peripheral.disable_source();
+                    self.double_buffer.as_mut().unwrap().next = Some(next);
+                    err
+                });
+                completed.complete_destination();
+                self.double_buffer.as_mut().unwrap().full = Some(completed);
+                result.map(|_| true)
+            }
+            None => {
+                self.peripheral.disable_source();
+                completed.complete_destination();
+                double_buffer.overrun = Some(completed);
+                Err(Error::Overrun)
+            }
+        }
+    }
+
+    /// Exchange the filled buffer for `fresh`
+    ///
+    /// The receive fills `fresh` after the active buffer. Returns `Err(fresh)` if there's no
+    /// filled buffer.
+    ///
+    /// After an overrun, the receive is stopped. `swap_completed()` returns the oldest filled buffer.
+    /// To take the other filled buffer, call [`restart_double_buffered()`](struct.Peripheral.html#method.restart_double_buffered),
+    /// then swap again.
+    pub fn swap_completed(&mut self, fresh: D) -> Result<D, D> {
+        let double_buffer = match self.double_buffer.as_mut() {
+            Some(double_buffer) => double_buffer,
+            None => return Err(fresh),
+        };
+        if double_buffer.full.is_none() || double_buffer.next.is_some() {
+            return Err(fresh);
+        }
+        let full = double_buffer.full.take().unwrap();
+        double_buffer.full = double_buffer.overrun.take();
+        double_buffer.next = Some(fresh);
+        Ok(full)
+    }
+
+    /// Restart a double-buffered receive that stopped after an overrun
+    ///
+    /// Call this after you've swapped the oldest filled buffer with [`swap_completed()`](struct.Peripheral.html#method.swap_completed).
+    /// The receive starts filling the fresh buffer, and the other filled buffer is ready for the next swap.
+    /// Returns [`Error::Overrun`](enum.Error.html#variant.Overrun) if you haven't supplied a fresh buffer.
+    pub fn restart_double_buffered(&mut self) -> Result<(), Error> {
+        let double_buffer = match self.double_buffer.as_mut() {
+            Some(double_buffer) if self.destination_buffer.is_none() => double_buffer,
+            _ => return Err(Error::ScheduledTransfer),
+        };
+        match double_buffer.next.take() {
+            Some(next) => self.start_receive(next).map_err(|(next, err)| {
+                self.double_buffer.as_mut().unwrap().next = Some(next);
+                err
+            }),
+            None => Err(Error::Overrun),
+        }
+    }
+
+    /// Stop a double-buffered receive, and return its buffers
+    ///
+    /// The peripheral holds at most two buffers. The contents of the buffer that was being
+    /// filled are unspecified. Returns `None` if the receive isn't double buffered.
+    pub fn stop_double_buffered(&mut self) -> Option<(Option<D>, Option<D>)> {
+        let mut double_buffer = self.double_buffer.take()?;
+        let active = self.receive_cancel();
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        rx_channel.clear_complete();
+        rx_channel.set_interrupt_on_completion(false);
+
+        let mut buffers = active
+            .into_iter()
+            .chain(double_buffer.full.take())
+            .chain(double_buffer.next.take())
+            .chain(double_buffer.overrun.take());
+        Some((buffers.next(), buffers.next()))
+    }
+
     /// Release the peripheral and the channel
     ///
     /// Users should ensure that any started transfer has completed. If the
@@ -183,6 +570,13 @@ where
     pub fn is_receive_active(&self) -> bool {
         self.rx_channel.as_ref().unwrap().is_active()
     }
+
+    /// Returns a snapshot of the receive channel's TCD
+    ///
+    /// See [`ChannelExt::tcd_snapshot()`](trait.ChannelExt.html#tymethod.tcd_snapshot) for details.
+    pub fn receive_dump_tcd(&self) -> TcdSnapshot {
+        self.rx_channel.as_ref().unwrap().tcd_snapshot()
+    }
 }
 
 impl<P, E, S> Peripheral<P, E, S, Circular<E>>
@@ -198,6 +592,175 @@ where
     pub fn read_half(&mut self) -> Option<ReadHalf<E>> {
         self.destination_buffer.as_mut().map(ReadHalf::new)
     }
+
+    /// Start a continuous DMA receive that delivers data in chunks of `chunk_elements`
+    ///
+    /// The DMA controller receives into `buffer` until you call [`receive_chunks_stop()`](struct.Peripheral.html#method.receive_chunks_stop).
+    /// Each minor loop receives one chunk of `chunk_elements`, and the major loop iterates
+    /// `buffer_len / chunk_elements` times, where `buffer_len` is the size of `buffer`'s backing
+    /// memory. The channel doesn't disable itself when the major loop completes. Instead, the
+    /// `Circular` destination's address modulo wraps the next pass back to the start of `buffer`.
+    /// Use [`chunks_available()`](struct.Peripheral.html#method.chunks_available) and
+    /// [`take_chunk()`](struct.Peripheral.html#method.take_chunk) to read the received chunks.
+    ///
+    /// Every peripheral request moves a whole minor loop, so the peripheral must request
+    /// only once it holds `chunk_elements`. Set the peripheral's receive watermark to match.
+    ///
+    /// `chunk_elements` must evenly divide `buffer_len`, so there is never a partial trailing
+    /// chunk at the end of the buffer. Since `buffer_len` is a power of two, `chunk_elements` is
+    /// also a power of two. Otherwise, or if the major loop can't iterate `buffer_len / chunk_elements`
+    /// times, `receive_chunks()` returns [`Error::InvalidChunkSize`](enum.Error.html#variant.InvalidChunkSize).
+    /// `receive_chunks()` discards any elements in `buffer`.
+    ///
+    /// The receive channel interrupts once the major loop completes, at the end of every pass
+    /// through the buffer, so you should register a DMA interrupt handler. Clear the interrupt
+    /// flag with [`receive_clear_interrupt()`](struct.Peripheral.html#method.receive_clear_interrupt),
+    /// which counts the chunks. Between interrupts, `chunks_available()` and `take_chunk()` count
+    /// the chunks that arrived since the last observation.
+    ///
+    /// Take chunks before the DMA controller fills the whole buffer. Otherwise, the
+    /// DMA controller overwrites data that you have not read, and `chunks_available()`
+    /// and `take_chunk()` return [`Error::Overrun`](enum.Error.html#variant.Overrun) until
+    /// you stop the receive.
+    ///
+    /// While receiving chunks, `is_receive_complete()` and `receive_complete()` do not apply.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, receive_u8, Buffer, Circular};
+    ///
+    /// #[repr(align(256))]
+    /// struct Align(Buffer<[u8; 256]>);
+    /// static RX_BUFFER: Align = Align(Buffer::new([0; 256]));
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    /// // Request DMA once the RX FIFO holds a whole chunk of 4 bytes
+    /// uart.set_rx_fifo(true);
+    /// uart.set_rx_fifo_watermark(3);
+    /// let (tx, rx) = uart.split();
+    ///
+    /// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let mut rx: dma::Peripheral<_, u8, Circular<u8>> =
+    ///     receive_u8(rx, dma_channels[7].take().unwrap());
+    ///
+    /// let buffer = Circular::new(&RX_BUFFER.0).unwrap();
+    /// rx.receive_chunks(buffer, 4).unwrap();
+    ///
+    /// // In the DMA interrupt handler, or in a polling loop...
+    /// rx.receive_clear_interrupt();
+    /// while let Ok(Some(chunk)) = rx.take_chunk() {
+    ///     // chunk is a &[u8] of 4 elements
+    /// }
+    /// ```
+    pub fn receive_chunks(
+        &mut self,
+        mut buffer: Circular<E>,
+        chunk_elements: usize,
+    ) -> Result<(), (Circular<E>, Error)> {
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        if rx_channel.is_enabled() {
+            return Err((buffer, Error::ScheduledTransfer));
+        }
+        if chunk_elements == 0 || buffer.size() % chunk_elements != 0 {
+            return Err((buffer, Error::InvalidChunkSize));
+        }
+        let iterations = match channel::major_iterations(buffer.size() / chunk_elements) {
+            Ok(iterations) => iterations,
+            Err(_) => return Err((buffer, Error::InvalidChunkSize)),
+        };
+
+        buffer.prepare_continuous_destination();
+        self.peripheral.enable_source();
+        let dst = buffer::Destination::destination(&buffer);
+
+        unsafe {
+            rx_channel.set_destination_transfer(&dst);
+        }
+        rx_channel.set_minor_loop_elements::<E>(chunk_elements);
+        rx_channel.set_transfer_iterations(iterations);
+        rx_channel.set_disable_on_completion(false);
+        rx_channel.set_interrupt_on_completion(true);
+
+        compiler_fence(Ordering::Release);
+        unsafe {
+            rx_channel.enable();
+        }
+        if rx_channel.is_error() {
+            let es = rx_channel.error_status();
+            rx_channel.clear_error();
+            rx_channel.set_disable_on_completion(true);
+            Err((buffer, Error::Setup(es)))
+        } else {
+            self.chunks = Some(Chunks::new(
+                chunk_elements,
+                buffer.size(),
+                buffer.as_ptr() as usize,
+                core::mem::size_of::<E>(),
+            ));
+            self.destination_buffer = Some(buffer);
+            Ok(())
+        }
+    }
+
+    /// Returns the number of complete chunks that are ready to take
+    ///
+    /// Returns 0 if the peripheral is not receiving chunks, or
+    /// [`Error::Overrun`](enum.Error.html#variant.Overrun) if the DMA controller
+    /// overwrote chunks that weren't taken.
+    pub fn chunks_available(&mut self) -> Result<usize, Error> {
+        self.observe_chunks();
+        self.chunks.map_or(Ok(0), |chunks| chunks.available())
+    }
+
+    /// Take the next complete chunk
+    ///
+    /// Returns `Ok(None)` if there are no complete chunks, or if the peripheral is not
+    /// receiving chunks. Returns [`Error::Overrun`](enum.Error.html#variant.Overrun) if
+    /// the DMA controller overwrote chunks that weren't taken. The chunk's memory will be
+    /// overwritten once the DMA controller wraps around the buffer, so don't hold onto the
+    /// chunk for too long.
+    pub fn take_chunk(&mut self) -> Result<Option<&[E]>, Error> {
+        if self.chunks_available()? == 0 {
+            return Ok(None);
+        }
+        let chunks = match self.chunks.as_mut() {
+            Some(chunks) => chunks,
+            None => return Ok(None),
+        };
+        let buffer = match self.destination_buffer.as_mut() {
+            Some(buffer) => buffer,
+            None => return Ok(None),
+        };
+        chunks.take();
+        buffer.mark_written_until(chunks.write);
+        Ok(Some(buffer.take_contiguous(chunks.elements)))
+    }
+
+    /// Stop receiving chunks, and return the buffer
+    ///
+    /// Any chunks that you have not taken are readable from the returned buffer.
+    /// If the receive stops during a minor loop, the returned buffer also holds the
+    /// elements of that partial chunk. After an overrun, the buffer's
+    /// elements are unspecified. The receive channel is restored to disable itself on
+    /// completion. The receive channel still interrupts on completion.
+    ///
+    /// Returns `None` if the peripheral is not receiving chunks.
+    pub fn receive_chunks_stop(&mut self) -> Option<Circular<E>> {
+        self.chunks.take()?;
+        let mut buffer = self.receive_cancel()?;
+        let rx_channel = self.rx_channel.as_mut().unwrap();
+        rx_channel.set_disable_on_completion(true);
+        buffer.mark_written_until(buffer.index_of(channel::destination_address(rx_channel)));
+        Some(buffer)
+    }
 }
 
 impl<P, E, S, D> Peripheral<P, E, S, D>
@@ -304,6 +867,16 @@ where
         self.source_buffer.take()
     }
 
+    /// Returns the number of elements sent from the buffer so far
+    ///
+    /// Returns 0 if there's no transfer in progress, or if the transfer is complete.
+    pub fn transfer_progress(&self) -> usize {
+        if self.source_buffer.is_none() {
+            return 0;
+        }
+        channel::completed_iterations(self.tx_channel.as_ref().unwrap(), false)
+    }
+
     /// Release the peripheral and the channel
     ///
     /// Users should ensure that any started transfer has completed. If the
@@ -323,6 +896,22 @@ where
     pub fn is_transfer_active(&self) -> bool {
         self.rx_channel.as_ref().unwrap().is_active()
     }
+
+    /// Trigger the transfer channel periodically from the PIT channel of the same number
+    ///
+    /// Use a periodic trigger for destinations that don't have a DMA request signal, like a
+    /// [`gpio::PortWriter`](../gpio/struct.PortWriter.html). See [`ChannelExt::set_periodic_trigger()`](trait.ChannelExt.html#tymethod.set_periodic_trigger)
+    /// for details.
+    pub fn transfer_set_periodic_trigger(&mut self) -> Result<(), PeriodicTriggerError> {
+        self.tx_channel.as_mut().unwrap().set_periodic_trigger()
+    }
+
+    /// Returns a snapshot of the transfer channel's TCD
+    ///
+    /// See [`ChannelExt::tcd_snapshot()`](trait.ChannelExt.html#tymethod.tcd_snapshot) for details.
+    pub fn transfer_dump_tcd(&self) -> TcdSnapshot {
+        self.tx_channel.as_ref().unwrap().tcd_snapshot()
+    }
 }
 
 impl<P, E, D> Peripheral<P, E, Circular<E>, D>
@@ -341,6 +930,155 @@ where
     pub fn write_half(&mut self) -> Option<WriteHalf<E>> {
         self.source_buffer.as_mut().map(WriteHalf::new)
     }
+
+    /// Start streaming data from `buffer` to the peripheral
+    ///
+    /// While streaming, the `Peripheral` keeps `buffer`. Use [`write()`](struct.Peripheral.html#method.write)
+    /// to append data. Whenever the DMA controller is idle, and there's data in the buffer, the
+    /// `Peripheral` starts a DMA transfer for all of the buffered data. Elements that you append during
+    /// a transfer are sent by the next transfer.
+    ///
+    /// `write()` only starts transfers when you call it. To send data without waiting for the next
+    /// `write()`, enable the transfer channel's interrupt on completion, and call
+    /// [`poll_streaming_tx()`](struct.Peripheral.html#method.poll_streaming_tx) in the DMA interrupt
+    /// handler.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, transfer_u8, Buffer, Circular};
+    ///
+    /// #[repr(align(1024))]
+    /// struct Align(Buffer<[u8; 1024]>);
+    /// static LOG_BUFFER: Align = Align(Buffer::new([0; 1024]));
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    ///
+    /// let (tx, rx) = uart.split();
+    ///
+    /// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let mut tx: dma::Peripheral<_, u8, Circular<u8>> =
+    ///     transfer_u8(tx, dma_channels[7].take().unwrap());
+    ///
+    /// tx.start_streaming_tx(Circular::new(&LOG_BUFFER.0).unwrap()).unwrap();
+    /// let accepted = tx.write(b"Hello world\r\n");
+    /// assert_eq!(accepted, 13);
+    /// ```
+    pub fn start_streaming_tx(&mut self, buffer: Circular<E>) -> Result<(), (Circular<E>, Error)> {
+        if self.source_buffer.is_some() || self.tx_channel.as_ref().unwrap().is_enabled() {
+            return Err((buffer, Error::ScheduledTransfer));
+        }
+        self.source_buffer = Some(buffer);
+        self.streaming = true;
+        self.peripheral.enable_destination();
+        self.poll_streaming_tx().map_err(|err| {
+            self.peripheral.disable_destination();
+            self.streaming = false;
+            (self.source_buffer.take().unwrap(), err)
+        })
+    }
+
+    /// Append `data` to the stream, returning the number of elements accepted
+    ///
+    /// `write()` never blocks. If the buffer is full, `write()` accepts fewer elements than
+    /// `data`. If the DMA controller is idle, `write()` starts a transfer.
+    ///
+    /// Returns 0 if the peripheral is not streaming. If starting the transfer fails, the data
+    /// remains buffered, and the next [`poll_streaming_tx()`](struct.Peripheral.html#method.poll_streaming_tx)
+    /// returns the error.
+    pub fn write(&mut self, data: &[E]) -> usize
+    where
+        E: Copy,
+    {
+        if !self.streaming {
+            return 0;
+        }
+        let accepted = self
+            .source_buffer
+            .as_mut()
+            .unwrap()
+            .insert(data.iter().copied());
+        let _ = self.poll_streaming_tx();
+        accepted
+    }
+
+    /// Finish any complete transfer, and start a new transfer for any buffered data
+    ///
+    /// Call `poll_streaming_tx()` from the DMA interrupt handler to keep the stream moving between
+    /// calls to [`write()`](struct.Peripheral.html#method.write). It does nothing if the peripheral is
+    /// not streaming, or if a transfer is in progress.
+    pub fn poll_streaming_tx(&mut self) -> Result<(), Error> {
+        if !self.streaming {
+            return Ok(());
+        }
+        let tx_channel = self.tx_channel.as_mut().unwrap();
+        let buffer = self.source_buffer.as_mut().unwrap();
+        if tx_channel.is_enabled() {
+            return Ok(());
+        }
+        if tx_channel.is_complete() {
+            tx_channel.clear_complete();
+            buffer::Source::complete_source(buffer);
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let src = buffer::Source::source(buffer);
+        unsafe {
+            tx_channel.set_source_transfer(&src);
+        }
+        tx_channel.set_minor_loop_elements::<E>(1);
+        tx_channel.set_transfer_iterations(buffer.len() as u16);
+
+        buffer::Source::prepare_source(buffer);
+
+        compiler_fence(Ordering::Release);
+        unsafe {
+            tx_channel.enable();
+        }
+        if tx_channel.is_error() {
+            let es = tx_channel.error_status();
+            tx_channel.clear_error();
+            Err(Error::Setup(es))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stop streaming, and return the buffer
+    ///
+    /// If there's a transfer in progress, `stop_streaming_tx()` waits for the transfer to
+    /// complete, so that no data is sent twice. Any data that was not yet sent remains in
+    /// the returned buffer.
+    ///
+    /// Returns `None` if the peripheral is not streaming.
+    pub fn stop_streaming_tx(&mut self) -> Option<Circular<E>> {
+        if !self.streaming {
+            return None;
+        }
+        self.streaming = false;
+        let tx_channel = self.tx_channel.as_mut().unwrap();
+        while tx_channel.is_enabled() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.peripheral.disable_destination();
+        compiler_fence(Ordering::Acquire);
+        let mut buffer = self.source_buffer.take()?;
+        if tx_channel.is_complete() {
+            tx_channel.clear_complete();
+            buffer::Source::complete_source(&mut buffer);
+        }
+        Some(buffer)
+    }
 }
 
 impl<P, E, S, D> Peripheral<P, E, S, D>
@@ -373,6 +1111,20 @@ where
     }
 }
 
+/// The result of a [`receive_with_timeout()`](struct.Peripheral.html#method.receive_with_timeout)
+#[derive(Debug)]
+pub enum ReceiveTimeout<D> {
+    /// The receive completed before the deadline
+    Complete(D),
+    /// The deadline expired, and the receive was cancelled
+    Expired {
+        /// The destination buffer
+        buffer: D,
+        /// The number of elements received into the buffer before the cancel
+        received: usize,
+    },
+}
+
 /// Helper functions for constructing `Peripheral`s
 pub mod helpers {
     use super::{buffer, Channel, Destination, Peripheral, Source};
@@ -443,3 +1195,104 @@ pub mod helpers {
         Peripheral::new_bidirectional(peripheral, tx, rx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunks, Error};
+
+    /// 4 chunks of 4 `u16`s
+    fn sixteen() -> Chunks {
+        Chunks::new(4, 16, 0x2000_0000, 2)
+    }
+
+    #[test]
+    fn chunk_positions() {
+        let chunks = sixteen();
+        assert_eq!(chunks.index_of(0x2000_0000), 0);
+        assert_eq!(chunks.index_of(0x2000_000A), 5);
+        // The end of the buffer is its start
+        assert_eq!(chunks.index_of(0x2000_0020), 0);
+    }
+
+    #[test]
+    fn count_chunks() {
+        let mut chunks = sixteen();
+        chunks.observe(false, 0);
+        assert_eq!(chunks.available().unwrap(), 0);
+        chunks.observe(false, 4);
+        assert_eq!(chunks.available().unwrap(), 1);
+        chunks.take();
+        chunks.observe(false, 12);
+        // The major loop completes, and the next pass starts at the beginning
+        chunks.observe(true, 0);
+        assert_eq!(chunks.available().unwrap(), 3);
+        chunks.take();
+        chunks.take();
+        chunks.take();
+        chunks.observe(false, 6);
+        assert_eq!(chunks.available().unwrap(), 1);
+        chunks.take();
+        assert_eq!(chunks.available().unwrap(), 0);
+        assert_eq!(chunks.unread, 2);
+    }
+
+    #[test]
+    fn full_lap_is_an_overrun() {
+        // The position is where it was, but the major loop completed: it lapped
+        let mut chunks = sixteen();
+        chunks.observe(false, 4);
+        chunks.take();
+        chunks.observe(true, 4);
+        assert!(matches!(chunks.available(), Err(Error::Overrun)));
+        // The overrun is sticky
+        chunks.observe(false, 8);
+        assert!(matches!(chunks.available(), Err(Error::Overrun)));
+    }
+
+    #[test]
+    fn lap_without_crossing_is_an_overrun() {
+        // The major loop completed, but the position is after the last one, so
+        // the DMA controller went around the whole buffer
+        let mut chunks = sixteen();
+        chunks.observe(false, 4);
+        chunks.take();
+        chunks.observe(true, 8);
+        assert!(matches!(chunks.available(), Err(Error::Overrun)));
+    }
+
+    #[test]
+    fn filled_buffer_is_an_overrun() {
+        let mut chunks = sixteen();
+        chunks.observe(false, 12);
+        assert_eq!(chunks.available().unwrap(), 3);
+        // The last chunk fills the buffer, and the position is back at the start
+        chunks.observe(true, 0);
+        assert!(matches!(chunks.available(), Err(Error::Overrun)));
+
+        // Taking chunks makes room
+        let mut chunks = sixteen();
+        chunks.observe(false, 12);
+        chunks.take();
+        chunks.take();
+        chunks.observe(true, 4);
+        assert_eq!(chunks.available().unwrap(), 3);
+    }
+
+    #[test]
+    fn late_flag_is_not_a_lap() {
+        // The major loop completed after the flag was checked, but before the
+        // position was read. Its flag shows up in the next observation.
+        let mut chunks = sixteen();
+        chunks.observe(false, 12);
+        chunks.take();
+        chunks.take();
+        chunks.take();
+        chunks.observe(false, 0);
+        assert_eq!(chunks.available().unwrap(), 1);
+        chunks.observe(true, 0);
+        assert_eq!(chunks.available().unwrap(), 1);
+        // Once the owed flag is counted, the next flag without a crossing is a lap
+        chunks.observe(true, 0);
+        assert!(matches!(chunks.available(), Err(Error::Overrun)));
+    }
+}
@@ -0,0 +1,239 @@
+//! Scoped DMA transfers, for memory that's not `'static`
+//!
+//! See [`scope()`](fn.scope.html) for details.
+//!
+//! # Soundness
+//!
+//! A DMA transfer writes to memory without the compiler's knowledge. If the memory
+//! is freed, or borrowed by someone else, while the transfer is running, the transfer
+//! corrupts it. The `'static` [`Buffer`](struct.Buffer.html) API prevents that by never
+//! freeing the memory. The scope prevents it by never letting a borrow end while a
+//! transfer still runs:
+//!
+//! 1. Transfers borrow their buffers, and their channel, for `'scope`. `'scope` ends after
+//!    `scope()` returns, so the borrows outlive the closure.
+//! 2. `Scope` is `'scope`-invariant, and the closure must work for *any* `'scope`. The closure
+//!    can't pick a `'scope` that ends early, or borrow something that lives inside the closure
+//!    for `'scope`.
+//! 3. The closure only sees `&Scope`, so it can't move, drop, or forget the `Scope`. `scope()`
+//!    keeps track of every channel a transfer started on. It stops all of them before it
+//!    returns, whether the closure returns, or panics. The cleanup runs in a drop guard that's
+//!    owned by `scope()`, so it's not up to the user.
+//! 4. Forgetting a [`ScopedTransfer`](struct.ScopedTransfer.html) handle only skips the
+//!    handle's own cleanup. Step 3 still stops the channel.
+//!
+//! Once `scope()` returns, no channel that the scope used is moving data, and the borrows
+//! can safely end. The cleanup only writes the channel's registers, so it never creates
+//! a second reference to a `Channel` that's still borrowed by a handle.
+
+use super::{cache, channel, Channel, ChannelExt, Element, Error, Transfer, CHANNEL_COUNT};
+use core::{
+    cell::Cell,
+    marker::PhantomData,
+    mem,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+/// Run `f` with a [`Scope`](struct.Scope.html) for starting DMA transfers on memory that's not `'static`
+///
+/// Transfers that you start in the scope may use buffers that live on the stack. Before `scope()`
+/// returns, it cancels every transfer that's still running. Wait for a transfer with
+/// [`ScopedTransfer::wait()`](struct.ScopedTransfer.html#method.wait) to make sure it finishes.
+///
+/// The scope borrows each channel you use for the duration of the scope, so a channel
+/// runs at most one transfer per scope. Channels are reset when a transfer starts, like
+/// [`Memcpy`](struct.Memcpy.html).
+///
+/// ```no_run
+/// use imxrt1060_hal::dma;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+/// let mut channel = dma_channels[7].take().unwrap();
+///
+/// let source = [1u32, 2, 3, 4];
+/// let mut destination = [0u32; 4];
+///
+/// dma::scope(|scope| {
+///     let transfer = scope.memcpy(&mut channel, &source, &mut destination).unwrap();
+///     transfer.wait().unwrap();
+/// });
+/// assert_eq!(destination, source);
+/// ```
+///
+/// The buffers must outlive the scope. This doesn't compile:
+///
+/// ```compile_fail
+/// use imxrt1060_hal::dma;
+///
+/// # fn f(mut channel: dma::Channel) {
+/// dma::scope(|scope| {
+///     let source = [1u32, 2, 3, 4];
+///     let mut destination = [0u32; 4];
+///     scope.memcpy(&mut channel, &source, &mut destination).unwrap();
+/// });
+/// # }
+/// ```
+///
+/// Stack memory is usually in DTCM, which the DMA controller can access. If your stack is
+/// somewhere else, make sure that the DMA controller can reach it.
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+{
+    let scope = Scope {
+        started: Cell::new(0),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let guard = CancelOnDrop(&scope);
+    let result = f(&scope);
+    drop(guard);
+    result
+}
+
+/// Starts DMA transfers on memory that's not `'static`
+///
+/// Acquire a `Scope` with [`scope()`](fn.scope.html).
+pub struct Scope<'scope, 'env: 'scope> {
+    /// Bitmask of channels that may be running a transfer
+    started: Cell<u32>,
+    /// Invariant over `'scope`; see the module-level soundness notes
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Copy `source` into `destination` using `channel`
+    ///
+    /// The number of elements transferred is the minimum size of the two
+    /// slices. The transfer starts immediately; use the returned handle to wait
+    /// for it, or to cancel it. Returns
+    /// [`Error::TooManyElements`](enum.Error.html#variant.TooManyElements) if that's more
+    /// than 32,767 elements.
+    pub fn memcpy<E: Element>(
+        &'scope self,
+        channel: &'scope mut Channel,
+        source: &'scope [E],
+        destination: &'scope mut [E],
+    ) -> Result<ScopedTransfer<'scope, 'env>, Error> {
+        if channel.is_enabled() {
+            return Err(Error::ScheduledTransfer);
+        }
+        let len = source.len().min(destination.len());
+        let iterations = channel::major_iterations(len)?;
+        let bytes = len * mem::size_of::<E>();
+
+        channel.reset_tcd();
+        channel.set_always_on();
+        channel.set_disable_on_completion(true);
+        // Safety: the scope guarantees that the slices outlive the transfer. The
+        // pointers are valid for `len` elements.
+        unsafe {
+            channel.set_source_transfer(&Transfer::buffer_linear(source.as_ptr(), len));
+            channel
+                .set_destination_transfer(&Transfer::buffer_linear(destination.as_mut_ptr(), len));
+        }
+        channel.set_minor_loop_elements::<E>(1);
+        channel.set_transfer_iterations(iterations);
+
+        cache::clean(source.as_ptr() as usize, bytes);
+        cache::clean_invalidate(destination.as_ptr() as usize, bytes);
+
+        // Track the channel before it can move data
+        self.started
+            .set(self.started.get() | (1 << channel.channel()));
+        compiler_fence(Ordering::Release);
+        unsafe {
+            channel.enable();
+            channel.start();
+        }
+        if channel.is_error() {
+            let es = channel.error_status();
+            channel.clear_error();
+            channel.disable();
+            Err(Error::Setup(es))
+        } else {
+            Ok(ScopedTransfer {
+                channel,
+                destination: (destination.as_ptr() as usize, bytes),
+                scope: self,
+            })
+        }
+    }
+
+    /// Stop the channel, and forget about it
+    fn finish(&self, channel: &mut Channel) {
+        // Safety: the handle owns the channel
+        unsafe { channel::cancel(channel.channel()) };
+        channel.disable();
+        channel.clear_complete();
+        self.started
+            .set(self.started.get() & !(1 << channel.channel()));
+        compiler_fence(Ordering::Acquire);
+    }
+}
+
+/// Stops every channel that the scope started, even if the closure panics
+struct CancelOnDrop<'a, 'scope, 'env>(&'a Scope<'scope, 'env>);
+
+impl Drop for CancelOnDrop<'_, '_, '_> {
+    fn drop(&mut self) {
+        let started = self.0.started.get();
+        for idx in (0..CHANNEL_COUNT).filter(|idx| started & (1 << idx) != 0) {
+            // Safety: the scope borrows the channel until after this guard is dropped.
+            // Nothing else can use the channel.
+            unsafe { channel::cancel(idx) };
+        }
+        self.0.started.set(0);
+        compiler_fence(Ordering::Acquire);
+    }
+}
+
+/// A DMA transfer that was started in a [`Scope`](struct.Scope.html)
+///
+/// Dropping the handle cancels the transfer.
+pub struct ScopedTransfer<'scope, 'env> {
+    channel: &'scope mut Channel,
+    /// Address and size, in bytes, of the destination memory
+    destination: (usize, usize),
+    scope: &'scope Scope<'scope, 'env>,
+}
+
+impl ScopedTransfer<'_, '_> {
+    /// Returns `true` if the transfer is complete
+    pub fn is_complete(&self) -> bool {
+        self.channel.is_complete()
+    }
+
+    /// Wait for the transfer to complete
+    ///
+    /// Returns an error if the DMA controller reports an error while
+    /// moving data.
+    pub fn wait(self) -> Result<(), Error> {
+        while !self.channel.is_complete() && !self.channel.is_error() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        if self.channel.is_error() {
+            let es = self.channel.error_status();
+            self.channel.clear_error();
+            Err(Error::Setup(es))
+        } else {
+            let (addr, len) = self.destination;
+            cache::invalidate(addr, len);
+            Ok(())
+        }
+    }
+
+    /// Cancel the transfer
+    ///
+    /// The contents of the destination are unspecified.
+    pub fn cancel(self) {}
+}
+
+impl Drop for ScopedTransfer<'_, '_> {
+    fn drop(&mut self) {
+        self.scope.finish(self.channel);
+    }
+}
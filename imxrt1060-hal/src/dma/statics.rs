@@ -0,0 +1,176 @@
+//! Declare statically-allocated DMA buffers
+//!
+//! See [`buffers!`](macro.buffers.html) for details. The rest of this module supports
+//! the macro, and it's not part of the public API.
+
+use super::Buffer;
+
+/// Associates a size, in bytes, with a type that has the same alignment
+///
+/// Only implemented for powers of two.
+pub trait Alignment {
+    /// A zero-sized type with the alignment
+    type Align;
+}
+
+/// A size, in bytes
+pub struct Bytes<const N: usize>;
+
+macro_rules! alignments {
+    ($($bytes:literal => $align:ident),*) => {
+        $(
+            #[repr(align($bytes))]
+            pub struct $align;
+            impl Alignment for Bytes<$bytes> {
+                type Align = $align;
+            }
+        )*
+    };
+}
+
+alignments!(
+    1 => Align1, 2 => Align2, 4 => Align4, 8 => Align8,
+    16 => Align16, 32 => Align32, 64 => Align64, 128 => Align128,
+    256 => Align256, 512 => Align512, 1024 => Align1024, 2048 => Align2048,
+    4096 => Align4096, 8192 => Align8192, 16384 => Align16384, 32768 => Align32768,
+    65536 => Align65536
+);
+
+/// A `Buffer` that's aligned to `A`
+///
+/// The buffer is the first, and only sized, field, so it has the same address as
+/// the `Aligned` wrapper.
+#[repr(C)]
+pub struct Aligned<A, B> {
+    _align: [A; 0],
+    /// The aligned buffer
+    pub buffer: Buffer<B>,
+}
+
+impl<A, B> Aligned<A, B> {
+    /// Wrap `memory` in an aligned `Buffer`
+    pub const fn new(memory: B) -> Self {
+        Aligned {
+            _align: [],
+            buffer: Buffer::new(memory),
+        }
+    }
+}
+
+/// Declare statically-allocated DMA buffers
+///
+/// Each declaration has a name, and an array type. `buffers!` expands each name into a type
+/// that owns a static [`Buffer`](dma/struct.Buffer.html). Use the type's associated functions
+/// to access the buffer:
+///
+/// - `buffer()` returns the `&'static Buffer`.
+/// - `linear()` returns a [`Linear`](dma/struct.Linear.html) adapter over the buffer.
+/// - `circular()` returns a [`Circular`](dma/struct.Circular.html) adapter over the buffer.
+///   It's only available for buffers declared as `circular`.
+///
+/// Like the adapters' constructors, `linear()` and `circular()` return an error if the buffer
+/// is already taken.
+///
+/// Follow the type with `in "<section>"` to place the buffer in a link section. The
+/// section name depends on your linker script.
+///
+/// ```
+/// use imxrt1060_hal::dma;
+///
+/// dma::buffers! {
+///     /// Receives UART data
+///     pub UART_RX: [u8; 512],
+///     UART_TX: [u8; 512] in ".ocram",
+///     LOG: circular [u16; 256],
+/// }
+///
+/// let rx: dma::Linear<u8> = UART_RX::linear().unwrap();
+/// assert!(UART_RX::linear().is_err());
+///
+/// let log: dma::Circular<u16> = LOG::circular().unwrap();
+/// assert_eq!(LOG::buffer().addr_range().start % 512, 0);
+/// # let _ = UART_TX::buffer();
+/// ```
+///
+/// # Circular buffers
+///
+/// A `circular` buffer is aligned to its size, in bytes, as required by `Circular`. Its size
+/// must be a power of two, no larger than 64KiB. Otherwise, the declaration fails to compile.
+///
+/// ```compile_fail
+/// use imxrt1060_hal::dma;
+///
+/// dma::buffers! {
+///     NOT_A_POWER_OF_TWO: circular [u8; 500],
+/// }
+/// ```
+#[macro_export]
+macro_rules! buffers {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        $vis:vis $name:ident: circular [$elem:ty; $len:expr] $(in $section:literal)?
+        $(, $($rest:tt)*)?
+    ) => {
+        $(#[$attr])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name;
+
+        impl $name {
+            /// Returns the static DMA buffer
+            pub fn buffer() -> &'static $crate::dma::Buffer<[$elem; $len]> {
+                const BYTES: usize = core::mem::size_of::<[$elem; $len]>();
+                // Fails to evaluate if the buffer's size is not a power of two
+                const _: () = [()][!BYTES.is_power_of_two() as usize];
+
+                $(#[link_section = $section])?
+                static BUFFER: $crate::dma::__statics::Aligned<
+                    <$crate::dma::__statics::Bytes<{ BYTES }> as $crate::dma::__statics::Alignment>::Align,
+                    [$elem; $len],
+                > = $crate::dma::__statics::Aligned::new([0; $len]);
+                &BUFFER.buffer
+            }
+
+            /// Returns a linear adapter over the DMA buffer
+            #[allow(dead_code)]
+            pub fn linear() -> Result<$crate::dma::Linear<$elem>, $crate::dma::LinearError> {
+                $crate::dma::Linear::try_new(Self::buffer())
+            }
+
+            /// Returns a circular adapter over the DMA buffer
+            #[allow(dead_code)]
+            pub fn circular() -> Result<$crate::dma::Circular<$elem>, $crate::dma::CircularError> {
+                $crate::dma::Circular::new(Self::buffer())
+            }
+        }
+
+        $($crate::buffers!($($rest)*);)?
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis $name:ident: [$elem:ty; $len:expr] $(in $section:literal)?
+        $(, $($rest:tt)*)?
+    ) => {
+        $(#[$attr])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name;
+
+        impl $name {
+            /// Returns the static DMA buffer
+            pub fn buffer() -> &'static $crate::dma::Buffer<[$elem; $len]> {
+                $(#[link_section = $section])?
+                static BUFFER: $crate::dma::Buffer<[$elem; $len]> =
+                    $crate::dma::Buffer::new([0; $len]);
+                &BUFFER
+            }
+
+            /// Returns a linear adapter over the DMA buffer
+            #[allow(dead_code)]
+            pub fn linear() -> Result<$crate::dma::Linear<$elem>, $crate::dma::LinearError> {
+                $crate::dma::Linear::try_new(Self::buffer())
+            }
+        }
+
+        $($crate::buffers!($($rest)*);)?
+    };
+}
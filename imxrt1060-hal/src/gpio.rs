@@ -7,12 +7,55 @@
 //! High speed, or "fast," GPIOs are GPIOs that run on the AHB clock. Normal GPIOs
 //! run on the IPG clock.
 //!
+//! Pins on GPIO1 through GPIO4 may switch to the fast GPIO6 through GPIO9 with
+//! [`fast()`](struct.GPIO.html#method.fast), and back with
+//! [`normal()`](struct.GPIO.html#method.normal). The switch flips the pin's bit in
+//! IOMUXC_GPR GPR26 through GPR29, and changes the GPIO's `Normal` or `Fast` type. The
+//! type selects the port, so a `GPIO` always drives the port that owns the pin; there's
+//! no way to write the pin through the port that doesn't own it. GPIO5 pins don't have
+//! `fast()`.
+//!
+//! The snippet below counts the core cycles for 1000 toggles, first on the normal
+//! port, then on the fast port. Expect far fewer cycles on the fast port.
+//!
+//! ```no_run
+//! use cortex_m::peripheral::DWT;
+//! use imxrt1060_hal::{
+//!     self,
+//!     gpio::{Output, PortSpeed, GPIO},
+//!     iomuxc::imxrt1060::b0::B0_03,
+//! };
+//!
+//! fn cycles<S: PortSpeed>(led: &mut GPIO<B0_03, Output, S>) -> u32 {
+//!     let start = DWT::get_cycle_count();
+//!     for _ in 0..1000 {
+//!         led.toggle();
+//!     }
+//!     DWT::get_cycle_count().wrapping_sub(start)
+//! }
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+//! let mut core = cortex_m::Peripherals::take().unwrap();
+//! core.DCB.enable_trace();
+//! core.DWT.enable_cycle_counter();
+//!
+//! // Teensy 4 LED, GPIO2_3, or GPIO7_3 in fast mode
+//! let mut led = GPIO::new(peripherals.iomuxc.b0.p03).output();
+//! let normal = cycles(&mut led);
+//! let mut led = led.fast(&mut gpr);
+//! assert!(led.is_fast());
+//! let fast = cycles(&mut led);
+//! assert!(fast < normal);
+//! ```
+//!
 //! # Example
 //!
 //! ```no_run
 //! use imxrt1060_hal::{self, gpio::GPIO};
 //!
 //! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
 //! let input = GPIO::new(peripherals.iomuxc.ad_b0.p11);
 //!
 //! assert!(!input.is_set());
@@ -24,11 +67,70 @@
 //! output.toggle();
 //! assert!(!output.is_set());
 //!
-//! assert!(output.set_fast(true));
+//! let mut output = output.fast(&mut gpr);
 //! output.toggle();
 //! assert!(output.is_set());
 //! ```
 //!
+//! # Atomic outputs
+//!
+//! `set()`, `clear()`, and `toggle()` write the port's DR_SET, DR_CLEAR, and DR_TOGGLE
+//! registers. Each is a single store that only affects the pin's bit, so there's no
+//! read-modify-write of DR. An interrupt that drives another pin on the same port can't
+//! undo your write, and you can't undo its write. Only the transitions between input and
+//! output, and between fast and normal GPIOs, modify shared registers, and they run in
+//! critical sections.
+//!
+//! The snippet below stresses two pins on one port. Teensy 4 pins 12 and 13 are GPIO2_1
+//! and GPIO2_3. The main loop toggles pin 13 while a SysTick handler toggles pin 12, and
+//! each side checks that its pin holds the last value that it wrote. A lost update
+//! trips an assert.
+//!
+//! ```no_run
+//! use core::cell::RefCell;
+//! use cortex_m::interrupt::Mutex;
+//! use imxrt1060_hal::{
+//!     gpio::{Output, GPIO},
+//!     iomuxc::imxrt1060::b0::B0_01,
+//! };
+//!
+//! static PIN_12: Mutex<RefCell<Option<GPIO<B0_01, Output>>>> = Mutex::new(RefCell::new(None));
+//!
+//! #[cortex_m_rt::exception]
+//! fn SysTick() {
+//!     cortex_m::interrupt::free(|cs| {
+//!         if let Some(pin) = PIN_12.borrow(cs).borrow_mut().as_mut() {
+//!             let expected = !pin.is_set();
+//!             pin.toggle();
+//!             assert_eq!(pin.is_set(), expected);
+//!         }
+//!     });
+//! }
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+//! let mut core = cortex_m::Peripherals::take().unwrap();
+//! let pin_12 = GPIO::new(peripherals.iomuxc.b0.p01).output();
+//! let mut pin_13 = GPIO::new(peripherals.iomuxc.b0.p03).output();
+//! cortex_m::interrupt::free(|cs| *PIN_12.borrow(cs).borrow_mut() = Some(pin_12));
+//!
+//! core.SYST.set_reload(1_000);
+//! core.SYST.clear_current();
+//! core.SYST.enable_interrupt();
+//! core.SYST.enable_counter();
+//!
+//! loop {
+//!     // SysTick may fire between these calls. It only touches pin 12.
+//!     pin_13.set();
+//!     assert!(pin_13.is_set());
+//!     pin_13.clear();
+//!     assert!(!pin_13.is_set());
+//!     pin_13.toggle();
+//!     assert!(pin_13.is_set());
+//!     pin_13.toggle();
+//! }
+//! ```
+//!
 //! # Interrupts
 //!
 //! GPIO inputs can generate interrupts on edge or level triggers. See
@@ -84,8 +186,8 @@
 //!     fn GPIO2_Combined_0_15() {
 //!         cortex_m::interrupt::free(|cs| {
 //!             SHARED_STATE.borrow(cs).borrow_mut().as_mut().map(|state| {
-//!                 if state.pin.is_interrupt_status() {
-//!                     state.pin.clear_interrupt_status();
+//!                 if state.pin.is_interrupt_pending() {
+//!                     state.pin.clear_interrupt_pending();
 //!                     (state.callback)(cs);
 //!                 }
 //!             });
@@ -102,41 +204,136 @@
 //!     });
 //! }
 //! ```
+//!
+//! When many pins share a vector, read every pending interrupt in the port with
+//! [`interrupt_status()`](fn.interrupt_status.html), then dispatch on the bits.
+//!
+//! ## Teensy 4 button
+//!
+//! Teensy 4 pin 2 is pad EMC_04, which is GPIO4_4. Wire a button from pin 2 to ground,
+//! and the pin reads low while you hold the button. Enable the pull-up, then count
+//! presses on falling edges.
+//!
+//! ```no_run
+//! use core::sync::atomic::{AtomicU32, Ordering};
+//! use imxrt1060_hal::{
+//!     gpio::{self, InterruptConfiguration, GPIO},
+//!     iomuxc::{configure, Config, PullKeeper},
+//!     ral::interrupt,
+//! };
+//!
+//! static PRESSES: AtomicU32 = AtomicU32::new(0);
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn GPIO4_Combined_0_15() {
+//!     let pending = gpio::interrupt_status(4);
+//!     if pending & (1 << 4) != 0 {
+//!         PRESSES.fetch_add(1, Ordering::Relaxed);
+//!         // Safety: we only clear the button's flag
+//!         unsafe { gpio::clear_interrupt_status(4, 1 << 4) };
+//!     }
+//! }
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut pad = peripherals.iomuxc.emc.p04;
+//! configure(&mut pad, Config::zero().set_pull_keeper(Some(PullKeeper::Pullup100k)));
+//! let mut button = GPIO::new(pad);
+//! button.set_interrupt_configuration(InterruptConfiguration::FallingEdge);
+//! button.set_interrupt_enable(true);
+//! // Safety: the handler only touches an atomic, and the button's flag
+//! unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::GPIO4_Combined_0_15) };
+//! ```
 
-use crate::iomuxc::{consts::Unsigned, gpio::Pin};
+use crate::iomuxc::{
+    self,
+    consts::{Unsigned, U1, U2, U3, U4},
+    gpio::Pin,
+    Config, PullKeeper, GPR,
+};
 use crate::ral::{
     self,
     gpio::{self, RegisterBlock},
+    iomuxc_gpr,
 };
 use core::marker::PhantomData;
 
+pub use embedded_hal::digital::v2::PinState;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod debounce;
+pub mod dispatch;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
 /// Denotes that a pin is configured as an input
 pub enum Input {}
 /// Denotes that a pin is configured as an output
 pub enum Output {}
+/// Denotes that a pin is configured as an open-drain output
+///
+/// An open-drain output drives the line low, or releases it. A released line is pulled
+/// high by a pull-up, so you need an external pull-up, or the pad's internal pull-up.
+/// Other devices may also hold the line low, and an open-drain pin reads the line's
+/// actual level.
+pub enum OpenDrain {}
+
+/// Denotes that a pin uses its normal GPIO port, GPIO1 through GPIO5
+pub enum Normal {}
+/// Denotes that a pin uses its fast GPIO port, GPIO6 through GPIO9
+pub enum Fast {}
+
+/// A GPIO port type, [`Normal`](enum.Normal.html) or [`Fast`](enum.Fast.html)
+pub trait PortSpeed: private::Sealed {
+    /// `true` for the fast GPIO ports
+    const FAST: bool;
+}
+
+impl PortSpeed for Normal {
+    const FAST: bool = false;
+}
+
+impl PortSpeed for Fast {
+    const FAST: bool = true;
+}
 
-pub struct GPIO<P, D> {
+/// A normal GPIO module that has a fast GPIO port
+///
+/// Implemented for the GPIO1 through GPIO4 modules.
+pub trait FastPort: private::Sealed {}
+
+impl FastPort for U1 {}
+impl FastPort for U2 {}
+impl FastPort for U3 {}
+impl FastPort for U4 {}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for super::Normal {}
+    impl Sealed for super::Fast {}
+    impl Sealed for super::U1 {}
+    impl Sealed for super::U2 {}
+    impl Sealed for super::U3 {}
+    impl Sealed for super::U4 {}
+}
+
+/// A GPIO
+///
+/// `D` is the direction, `Input`, `Output`, or `OpenDrain`. `S` is the port,
+/// `Normal` or `Fast`. A new GPIO uses its normal port.
+pub struct GPIO<P, D, S = Normal> {
     pin: P,
     dir: PhantomData<D>,
+    speed: PhantomData<S>,
 }
 
-impl<P, D> GPIO<P, D>
+impl<P, D, S> GPIO<P, D, S>
 where
     P: Pin,
+    S: PortSpeed,
 {
     fn register_block(&self) -> *const RegisterBlock {
-        const REGISTER_BLOCKS: [*const RegisterBlock; 9] = [
-            gpio::GPIO1,
-            gpio::GPIO2,
-            gpio::GPIO3,
-            gpio::GPIO4,
-            gpio::GPIO5,
-            gpio::GPIO6,
-            gpio::GPIO7,
-            gpio::GPIO8,
-            gpio::GPIO9,
-        ];
-        REGISTER_BLOCKS[self.module().wrapping_sub(1)]
+        PORTS[self.module().wrapping_sub(1)]
     }
 
     /// Returns the bitmask for this GPIO
@@ -145,84 +342,49 @@ where
         1u32 << <P as Pin>::Offset::USIZE
     }
 
-    /// Returns the ICR field offset for this GPIO
+    /// Returns the ICR mask for this GPIO
     ///
     /// ICR is "Interrupt Configuration Register"
-    fn icr_offset(&self) -> usize {
-        (<P as Pin>::Offset::USIZE % 16) * 2
-    }
-
-    /// Returns the ICR mask for this GPIO
     fn icr_mask(&self) -> u32 {
-        0b11 << self.icr_offset()
+        IcrField::new(<P as Pin>::Offset::USIZE, InterruptConfiguration::LowLevel).mask
     }
 
     /// The return is a non-zero number, since the GPIO identifiers
     /// start with '1.'
     #[inline(always)]
     fn module(&self) -> usize {
-        let fast_offset = if self.is_fast() { 5 } else { 0 };
-        <P as Pin>::Module::USIZE + fast_offset
+        port(<P as Pin>::Module::USIZE, S::FAST)
     }
 
-    fn gpr(&self) -> Option<*mut u32> {
-        // GPR register for GPIO1
-        const GPR26: *mut u32 = 0x400A_C068 as *mut u32;
-        if <P as Pin>::Module::USIZE < 5 {
-            // Safety: GPR registers in range for GPIO1, 2, 3, and 4
-            Some(unsafe { GPR26.add(<P as Pin>::Module::USIZE.wrapping_sub(1)) })
-        } else {
-            None
-        }
-    }
-
-    /// Returns `true` if the GPIO is configured for fast mode
-    ///
-    /// If the GPIO cannot support fast mode, `is_fast()` always returns `false`
+    /// Returns `true` if the GPIO uses its fast port
     pub fn is_fast(&self) -> bool {
-        // Safety: MMIO valid per gpr() function.
-        // Read is atomic
-        self.gpr()
-            .map(|gpr| unsafe { core::ptr::read_volatile(gpr) & self.mask() != 0 })
-            .unwrap_or(false)
+        S::FAST
     }
 
-    /// Configures the GPIO to fast mode. `true` indicates "fast," and `false` indicates "normal."
-    ///
-    /// Returns `false` if this pin does not support fast mode. Otherwise, returns `true`, indicating
-    /// that the setting was respected.
+    /// Move the pin to the port of `T`, keeping its settings
     ///
-    /// If you transition an output pin into fast mode, the GPIO output state may *not* be maintained.
-    /// That is, if your GPIO pin was high, a transition into fast mode may set the pin low. Consider
-    /// setting fast mode before driving the GPIO output to avoid inconsistencies.
-    pub fn set_fast(&mut self, fast: bool) -> bool {
-        self.gpr()
-            .map(|gpr| {
-                cortex_m::interrupt::free(|_| unsafe {
-                    let v = core::ptr::read_volatile(gpr);
-
-                    let from = self.register_block();
-
-                    if fast {
-                        core::ptr::write_volatile(gpr, v | self.mask());
-                    } else {
-                        core::ptr::write_volatile(gpr, v & !self.mask());
-                    }
-
-                    let to = self.register_block();
-                    self.copy_settings(from, to);
-
-                    true
-                })
-            })
-            .unwrap_or(false)
+    /// The caller ensures that the pin's module has a fast port.
+    fn switch<T: PortSpeed>(self, gpr: &mut GPR) -> GPIO<P, D, T> {
+        let module = <P as Pin>::Module::USIZE;
+        let to = PORTS[port(module, T::FAST) - 1];
+        cortex_m::interrupt::free(|_| {
+            // Copy first, so that the pin drives the same level, in the same
+            // direction, once the GPR selects the other port.
+            unsafe { self.copy_settings(self.register_block(), to) };
+            select_fast(&gpr.0, module, self.mask(), T::FAST);
+        });
+        GPIO {
+            pin: self.pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        }
     }
 
     /// Copies the settings for one GPIO register block, `from`, to another register block, `to`.
     ///
     /// This method runs when changing from a normal to a fast GPIO, or a fast to a normal GPIO.
     /// The goal is to make the switch seamless for the end user. You must only copy the settings
-    /// for the current GPIO pin.
+    /// for the current GPIO pin. Copy the settings before the GPR selects the `to` port.
     ///
     /// # Safety
     ///
@@ -243,8 +405,9 @@ where
             }};
         }
 
-        // The input / output direction. When switching across fast / normal, keep the
-        // same direction.
+        // The output level and the input / output direction. When switching across
+        // fast / normal, keep the same level and direction.
+        copy_bits!(DR, self.mask());
         copy_bits!(GDIR, self.mask());
 
         // Interrupt configuration is preserved when switching fast / normal.
@@ -281,193 +444,1334 @@ where
     }
 }
 
+impl<P, D> GPIO<P, D, Normal>
+where
+    P: Pin,
+    <P as Pin>::Module: FastPort,
+{
+    /// Switch the pin to its fast port, GPIO6 through GPIO9
+    ///
+    /// The pin keeps its direction, output level, and interrupt settings. The switch
+    /// flips the pin's bit in the IOMUXC_GPR, so it needs the `GPR`.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::{self, gpio::{Fast, Output, GPIO}, iomuxc::imxrt1060::b0::B0_03};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+    /// let led = GPIO::new(peripherals.iomuxc.b0.p03).output();
+    /// // GPIO2_3 is now GPIO7_3
+    /// let mut led: GPIO<B0_03, Output, Fast> = led.fast(&mut gpr);
+    /// led.set();
+    /// let led = led.normal(&mut gpr);
+    /// assert!(led.is_set());
+    /// ```
+    pub fn fast(self, gpr: &mut GPR) -> GPIO<P, D, Fast> {
+        self.switch(gpr)
+    }
+}
+
+impl<P, D> GPIO<P, D, Fast>
+where
+    P: Pin,
+    <P as Pin>::Module: FastPort,
+{
+    /// Switch the pin back to its normal port, GPIO1 through GPIO4
+    ///
+    /// See [`fast()`](#method.fast) for more information.
+    pub fn normal(self, gpr: &mut GPR) -> GPIO<P, D, Normal> {
+        self.switch(gpr)
+    }
+}
+
 impl<P> GPIO<P, Input>
 where
     P: Pin,
 {
     /// Create a GPIO from a pad that supports a GPIO configuration
     ///
-    /// All pads may be used as a GPIO, so this should always work.
+    /// All pads may be used as a GPIO, so this should always work. The GPIO uses its
+    /// normal port.
     pub fn new(mut pin: P) -> Self {
         crate::iomuxc::gpio::prepare(&mut pin);
         Self {
             pin,
             dir: PhantomData,
+            speed: PhantomData,
         }
     }
+}
 
+impl<P, S> GPIO<P, Input, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
     /// Set the GPIO as an output.
     ///
     /// Any interrupt configuration will be cleared and needs redoing if the pin is transitioned
     /// back to an input.
-    pub fn output(self) -> GPIO<P, Output> {
+    ///
+    /// The output drives whatever level the port's data register (DR) holds for the pin,
+    /// which may not be the level that you want. To select the first level, use
+    /// [`output_with_state()`](#method.output_with_state).
+    pub fn output(self) -> GPIO<P, Output, S> {
         cortex_m::interrupt::free(|cs| self.set_output(cs));
         GPIO {
             pin: self.pin,
             dir: PhantomData,
+            speed: PhantomData,
         }
     }
 
+    /// Set the GPIO as an output, driving `state` from the start
+    ///
+    /// This writes the data register before it changes the pin's direction, so the pin
+    /// never drives the old level. See [`output()`](#method.output) for more information.
+    pub fn output_with_state(self, state: PinState) -> GPIO<P, Output, S> {
+        let mut gpio = GPIO {
+            pin: self.pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        };
+        run_output_steps(&mut gpio, state, output_steps(false));
+        gpio
+    }
+
     /// Returns `true` if this input pin is high
     pub fn is_set(&self) -> bool {
         // Safety: read is atomic
         unsafe { ral::read_reg!(ral::gpio, self.register_block(), PSR) & self.mask() != 0 }
     }
-}
 
-/// GPIO input interrupt configurations.
-///
-/// These configurations do not take effect until
-/// GPIO input interrupts are enabled.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u32)]
-pub enum InterruptConfiguration {
-    LowLevel = 0,
-    HighLevel = 1,
-    RisingEdge = 2,
-    FallingEdge = 3,
-    EitherEdge = 4,
+    /// Select the input's pull-up, pull-down, or keeper
+    ///
+    /// This changes the pad's PKE, PUE, and PUS fields, and keeps the rest of the pad
+    /// configuration. The pull is a pad setting, so it stays when you change the pin
+    /// to an output and back. Outputs don't have `with_pull()`:
+    ///
+    /// ```compile_fail,E0599
+    /// use imxrt1060_hal::{self, gpio::{GPIO, Pull}};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let output = GPIO::new(peripherals.iomuxc.b0.p03).output();
+    /// let output = output.with_pull(Pull::Up22k);
+    /// ```
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::{self, gpio::{GPIO, Pull}};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let mut input = GPIO::new(peripherals.iomuxc.emc.p04).with_pull(Pull::Up47k);
+    /// assert_eq!(input.pull(), Pull::Up47k);
+    /// ```
+    pub fn with_pull(mut self, pull: Pull) -> Self {
+        self.configure_pad(Config::modify().set_pull_keeper(pull.pull_keeper()));
+        self
+    }
 }
 
-impl<P> GPIO<P, Input>
+impl<P, D, S> GPIO<P, D, S>
 where
     P: Pin,
+    S: PortSpeed,
 {
-    /// Enable (`true`) or disable (`false`) interrupts for this GPIO input.
-    pub fn set_interrupt_enable(&mut self, enable: bool) {
-        cortex_m::interrupt::free(|_| unsafe {
-            ral::modify_reg!(ral::gpio, self.register_block(), IMR, |imr| if enable {
-                imr | self.mask()
-            } else {
-                imr & !self.mask()
-            })
-        });
+    /// Returns the pad's pull-up, pull-down, or keeper
+    pub fn pull(&self) -> Pull {
+        Pull::from_bits(self.read_pad())
     }
 
-    /// Indicates if interrupts are (`true`) or are not (`false`) enabled for this GPIO input.
-    pub fn is_interrupt_enabled(&self) -> bool {
-        unsafe { ral::read_reg!(ral::gpio, self.register_block(), IMR) & self.mask() != 0u32 }
-    }
-
-    /// Set the interrupt configuration for this GPIO input.
-    pub fn set_interrupt_configuration(&mut self, interrupt_configuration: InterruptConfiguration) {
-        // Safety: These modify_reg! must be completed as one unit, or we get an inconsistent state.
-        cortex_m::interrupt::free(|_| unsafe {
-            if InterruptConfiguration::EitherEdge == interrupt_configuration {
-                ral::modify_reg!(ral::gpio, self.register_block(), EDGE_SEL, |edge_sel| {
-                    edge_sel | self.mask()
-                });
-            } else {
-                ral::modify_reg!(ral::gpio, self.register_block(), EDGE_SEL, |edge_sel| {
-                    edge_sel & !self.mask()
-                });
-                let icr = interrupt_configuration as u32;
-                let icr_modify = |reg| reg & !self.icr_mask() | (icr << self.icr_offset());
-                if <P as Pin>::Offset::USIZE < 16 {
-                    ral::modify_reg!(ral::gpio, self.register_block(), ICR1, icr_modify);
-                } else {
-                    ral::modify_reg!(ral::gpio, self.register_block(), ICR2, icr_modify);
-                }
-            }
-        });
+    /// Returns the pad's configuration
+    pub fn pad_configuration(&self) -> PadConfiguration {
+        PadConfiguration::from_bits(self.read_pad())
     }
 
-    /// Indicates whether this GPIO input triggered an interrupt.
-    pub fn is_interrupt_status(&self) -> bool {
-        unsafe { ral::read_reg!(ral::gpio, self.register_block(), ISR) & self.mask() != 0u32 }
+    fn read_pad(&self) -> u32 {
+        // Safety: we own the pad; read is atomic
+        unsafe { core::ptr::read_volatile(self.pin.pad()) }
     }
 
-    /// Clear the interrupt status flag.
-    pub fn clear_interrupt_status(&mut self) {
-        unsafe { ral::write_reg!(ral::gpio, self.register_block(), ISR, self.mask()) }
+    /// Change the pad fields that `config` modifies
+    fn configure_pad(&mut self, config: Config) {
+        iomuxc::configure(&mut self.pin, config);
     }
 }
 
-impl<P> GPIO<P, Output>
+impl<P, S> GPIO<P, Output, S>
 where
     P: Pin,
+    S: PortSpeed,
 {
-    /// Transition the pin back to an input
-    pub fn input(self) -> GPIO<P, Input> {
-        cortex_m::interrupt::free(|cs| self.set_input(cs));
-        GPIO {
-            pin: self.pin,
-            dir: PhantomData,
-        }
+    /// Set the output's drive strength
+    ///
+    /// The change takes effect immediately. Stronger drive strengths help when driving
+    /// long cables, or level shifters. On short, lightly loaded traces, a strong drive
+    /// overshoots and rings; a weaker drive, with a slow slew rate, gives cleaner edges.
+    ///
+    /// # Example
+    ///
+    /// Tame the ringing on a short trace to a single load.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::{self, gpio::{DriveStrength, SlewRate, Speed, GPIO}};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let mut output = GPIO::new(peripherals.iomuxc.b0.p03).output();
+    /// output.set_drive_strength(DriveStrength::R0_2);
+    /// output.set_speed(Speed::Low50MHz);
+    /// output.set_slew_rate(SlewRate::Slow);
+    ///
+    /// let config = output.pad_configuration();
+    /// assert_eq!(config.drive_strength, Some(DriveStrength::R0_2));
+    /// ```
+    pub fn set_drive_strength(&mut self, drive_strength: DriveStrength) {
+        self.configure_pad(Config::modify().set_drive_strength(drive_strength.into()));
     }
 
-    /// Set the GPIO high
-    pub fn set(&mut self) {
-        // Safety: atomic write
-        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_SET, self.mask()) };
+    /// Set the output's speed
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.configure_pad(Config::modify().set_speed(speed.into()));
     }
 
-    /// Set the GPIO low
-    pub fn clear(&mut self) {
-        // Safety: atomic write
-        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_CLEAR, self.mask()) };
+    /// Set the output's slew rate
+    ///
+    /// A slow slew rate reduces ringing and EMI, at the cost of edge time.
+    pub fn set_slew_rate(&mut self, slew_rate: SlewRate) {
+        self.configure_pad(Config::modify().set_slew_rate(slew_rate.into()));
     }
+}
 
-    /// Returns `true` if the pin is high
-    pub fn is_set(&self) -> bool {
-        // Safety: atomic read
-        unsafe { ral::read_reg!(ral::gpio, self.register_block(), DR) & self.mask() != 0u32 }
+/// Output drive strength
+///
+/// `R0` is the weakest drive, with an output impedance of about 150Ohm at 3.3V. `R0_N`
+/// divides that impedance by `N`, so `R0_7` is the strongest drive. There's no setting
+/// that disables the output driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)] // Easier mapping if the names are consistent
+pub enum DriveStrength {
+    R0 = 1,
+    R0_2 = 2,
+    R0_3 = 3,
+    R0_4 = 4,
+    R0_5 = 5,
+    R0_6 = 6,
+    R0_7 = 7,
+}
+
+impl DriveStrength {
+    const SHIFT: u32 = 3;
+    const MASK: u32 = 0b111 << Self::SHIFT;
+
+    /// Returns `None` if the output driver is disabled
+    fn from_bits(pad: u32) -> Option<Self> {
+        use DriveStrength::*;
+        match (pad & Self::MASK) >> Self::SHIFT {
+            1 => Some(R0),
+            2 => Some(R0_2),
+            3 => Some(R0_3),
+            4 => Some(R0_4),
+            5 => Some(R0_5),
+            6 => Some(R0_6),
+            7 => Some(R0_7),
+            _ => None,
+        }
     }
+}
 
-    /// Alternate the state of the pin
-    pub fn toggle(&mut self) {
-        // Safety: atomic write
-        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_TOGGLE, self.mask()) }
+impl From<DriveStrength> for iomuxc::DriveStrength {
+    fn from(drive_strength: DriveStrength) -> Self {
+        match drive_strength {
+            DriveStrength::R0 => iomuxc::DriveStrength::R0,
+            DriveStrength::R0_2 => iomuxc::DriveStrength::R0_2,
+            DriveStrength::R0_3 => iomuxc::DriveStrength::R0_3,
+            DriveStrength::R0_4 => iomuxc::DriveStrength::R0_4,
+            DriveStrength::R0_5 => iomuxc::DriveStrength::R0_5,
+            DriveStrength::R0_6 => iomuxc::DriveStrength::R0_6,
+            DriveStrength::R0_7 => iomuxc::DriveStrength::R0_7,
+        }
     }
 }
 
-use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+/// Output speed
+///
+/// The speed limits the output's bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// 50MHz
+    Low50MHz,
+    /// 100MHz
+    Medium100MHz,
+    /// 200MHz
+    Max200MHz,
+}
 
-impl<P> OutputPin for GPIO<P, Output>
-where
-    P: Pin,
-{
-    type Error = core::convert::Infallible;
+impl Speed {
+    const SHIFT: u32 = 6;
+    const MASK: u32 = 0b11 << Self::SHIFT;
 
-    fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.set();
-        Ok(())
+    fn from_bits(pad: u32) -> Self {
+        match (pad & Self::MASK) >> Self::SHIFT {
+            0b00 => Speed::Low50MHz,
+            // 0b01 is also 100MHz
+            0b01 | 0b10 => Speed::Medium100MHz,
+            _ => Speed::Max200MHz,
+        }
     }
+}
 
-    fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.clear();
-        Ok(())
+/// The IOMUXC names the 100MHz setting `Medium`
+impl From<Speed> for iomuxc::Speed {
+    fn from(speed: Speed) -> Self {
+        match speed {
+            Speed::Low50MHz => iomuxc::Speed::Low,
+            Speed::Medium100MHz => iomuxc::Speed::Medium,
+            Speed::Max200MHz => iomuxc::Speed::Max,
+        }
     }
 }
 
-impl<P> StatefulOutputPin for GPIO<P, Output>
-where
-    P: Pin,
-{
-    fn is_set_high(&self) -> Result<bool, Self::Error> {
-        Ok(self.is_set())
+/// Output slew rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlewRate {
+    Slow,
+    Fast,
+}
+
+impl SlewRate {
+    const MASK: u32 = 1;
+
+    fn from_bits(pad: u32) -> Self {
+        if pad & Self::MASK != 0 {
+            SlewRate::Fast
+        } else {
+            SlewRate::Slow
+        }
     }
-    fn is_set_low(&self) -> Result<bool, Self::Error> {
-        self.is_set_high().map(|res| !res)
+}
+
+impl From<SlewRate> for iomuxc::SlewRate {
+    fn from(slew_rate: SlewRate) -> Self {
+        match slew_rate {
+            SlewRate::Slow => iomuxc::SlewRate::Slow,
+            SlewRate::Fast => iomuxc::SlewRate::Fast,
+        }
     }
 }
 
-impl<P> ToggleableOutputPin for GPIO<P, Output>
-where
-    P: Pin,
-{
-    type Error = core::convert::Infallible;
-    fn toggle(&mut self) -> Result<(), Self::Error> {
-        GPIO::<P, Output>::toggle(self);
-        Ok(())
+/// A decoded pad configuration
+///
+/// Use [`GPIO::pad_configuration()`](struct.GPIO.html#method.pad_configuration) to read
+/// a GPIO's pad configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PadConfiguration {
+    /// The output drive strength, or `None` if the output driver is disabled
+    pub drive_strength: Option<DriveStrength>,
+    pub speed: Speed,
+    pub slew_rate: SlewRate,
+    /// `true` if the output is open drain
+    pub open_drain: bool,
+    pub pull: Pull,
+    /// `true` if the input has a Schmitt trigger
+    pub hysteresis: bool,
+}
+
+impl PadConfiguration {
+    /// Open drain enable
+    const ODE: u32 = 1 << 11;
+    /// Hysteresis enable
+    const HYS: u32 = 1 << 16;
+
+    fn from_bits(pad: u32) -> Self {
+        PadConfiguration {
+            drive_strength: DriveStrength::from_bits(pad),
+            speed: Speed::from_bits(pad),
+            slew_rate: SlewRate::from_bits(pad),
+            open_drain: pad & Self::ODE != 0,
+            pull: Pull::from_bits(pad),
+            hysteresis: pad & Self::HYS != 0,
+        }
     }
 }
 
-impl<P> InputPin for GPIO<P, Input>
-where
-    P: Pin,
-{
-    type Error = core::convert::Infallible;
+/// A pad's pull-up, pull-down, or keeper
+///
+/// The resistances are nominal. Use [`GPIO::with_pull()`](struct.GPIO.html#method.with_pull)
+/// to change an input's pull.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    /// 22KOhm pull-up
+    Up22k,
+    /// 47KOhm pull-up
+    Up47k,
+    /// 100KOhm pull-up
+    Up100k,
+    /// 100KOhm pull-down
+    Down100k,
+    /// The keeper holds the last driven level
+    Keeper,
+    /// No pull, and no keeper; the input floats
+    None,
+}
+
+impl Pull {
+    /// Pull / keep enable
+    const PKE: u32 = 1 << 12;
+    /// Pull / keep select; set for pull
+    const PUE: u32 = 1 << 13;
+    /// Pull up / down config
+    const PUS_SHIFT: u32 = 14;
+
+    /// Returns the IOMUXC pull / keeper, or `None` for no pull, and no keeper
+    fn pull_keeper(self) -> Option<PullKeeper> {
+        match self {
+            Pull::Down100k => Some(PullKeeper::Pulldown100k),
+            Pull::Up47k => Some(PullKeeper::Pullup47k),
+            Pull::Up100k => Some(PullKeeper::Pullup100k),
+            Pull::Up22k => Some(PullKeeper::Pullup22k),
+            Pull::Keeper => Some(PullKeeper::Keeper),
+            Pull::None => None,
+        }
+    }
+
+    /// Decodes the pull from a pad register value
+    fn from_bits(pad: u32) -> Self {
+        if pad & Self::PKE == 0 {
+            Pull::None
+        } else if pad & Self::PUE == 0 {
+            Pull::Keeper
+        } else {
+            match (pad >> Self::PUS_SHIFT) & 0b11 {
+                0b00 => Pull::Down100k,
+                0b01 => Pull::Up47k,
+                0b10 => Pull::Up100k,
+                _ => Pull::Up22k,
+            }
+        }
+    }
+}
+
+/// GPIO input interrupt configurations.
+///
+/// These configurations do not take effect until
+/// GPIO input interrupts are enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum InterruptConfiguration {
+    /// Interrupt while the input is low
+    LowLevel = 0,
+    /// Interrupt while the input is high
+    HighLevel = 1,
+    /// Interrupt when the input rises
+    RisingEdge = 2,
+    /// Interrupt when the input falls
+    FallingEdge = 3,
+    /// Interrupt when the input rises, or falls
+    ///
+    /// This sets the pin's EDGE_SEL bit, which overrides the pin's ICR field.
+    EitherEdge = 4,
+}
+
+/// The interrupt configuration bits for one pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IcrField {
+    /// `true` for ICR2, which covers pins 16 through 31
+    icr2: bool,
+    /// The pin's ICR field
+    mask: u32,
+    /// The new ICR field value, in place
+    value: u32,
+    /// The new EDGE_SEL bit
+    edge_sel: bool,
+}
+
+impl IcrField {
+    /// Packs `config` for the pin at `offset` in its port
+    fn new(offset: usize, config: InterruptConfiguration) -> Self {
+        let shift = (offset % 16) * 2;
+        let edge_sel = InterruptConfiguration::EitherEdge == config;
+        // EDGE_SEL ignores ICR, so leave a rising edge in ICR
+        let icr = if edge_sel {
+            InterruptConfiguration::RisingEdge as u32
+        } else {
+            config as u32
+        };
+        IcrField {
+            icr2: offset >= 16,
+            mask: 0b11 << shift,
+            value: icr << shift,
+            edge_sel,
+        }
+    }
+}
+
+/// The first fast GPIO port, GPIO6, is the fast GPIO1
+const FAST_PORT_OFFSET: usize = 5;
+
+/// Returns the port that drives a pin of normal GPIO `module`
+///
+/// Only GPIO1 through GPIO4 have fast ports. `fast` is ignored for other modules.
+const fn port(module: usize, fast: bool) -> usize {
+    if fast && gpr_offset(module).is_some() {
+        module + FAST_PORT_OFFSET
+    } else {
+        module
+    }
+}
+
+/// Returns the offset from GPR26 of the GPR that selects fast GPIOs for `module`
+///
+/// Returns `None` if the module has no fast port.
+const fn gpr_offset(module: usize) -> Option<usize> {
+    if module >= 1 && module < FAST_PORT_OFFSET {
+        Some(module - 1)
+    } else {
+        None
+    }
+}
+
+/// Returns the GPR26 through GPR29 bits that select fast GPIOs for `module`
+///
+/// Returns `None` if the module has no fast port.
+fn fast_pins(gpr: &iomuxc_gpr::RegisterBlock, module: usize) -> Option<u32> {
+    Some(match gpr_offset(module)? {
+        0 => ral::read_reg!(ral::iomuxc_gpr, gpr, GPR26),
+        1 => ral::read_reg!(ral::iomuxc_gpr, gpr, GPR27),
+        2 => ral::read_reg!(ral::iomuxc_gpr, gpr, GPR28),
+        _ => ral::read_reg!(ral::iomuxc_gpr, gpr, GPR29),
+    })
+}
+
+/// Select the fast, or normal, port for the `mask` pins of `module`
+///
+/// Does nothing if the module has no fast port.
+fn select_fast(gpr: &iomuxc_gpr::RegisterBlock, module: usize, mask: u32, fast: bool) {
+    let select = |pins: u32| if fast { pins | mask } else { pins & !mask };
+    match gpr_offset(module) {
+        Some(0) => ral::modify_reg!(ral::iomuxc_gpr, gpr, GPR26, select),
+        Some(1) => ral::modify_reg!(ral::iomuxc_gpr, gpr, GPR27, select),
+        Some(2) => ral::modify_reg!(ral::iomuxc_gpr, gpr, GPR28, select),
+        Some(_) => ral::modify_reg!(ral::iomuxc_gpr, gpr, GPR29, select),
+        None => (),
+    }
+}
+
+/// GPIO register blocks, indexed by port number, less one
+const PORTS: [*const RegisterBlock; 9] = [
+    gpio::GPIO1,
+    gpio::GPIO2,
+    gpio::GPIO3,
+    gpio::GPIO4,
+    gpio::GPIO5,
+    gpio::GPIO6,
+    gpio::GPIO7,
+    gpio::GPIO8,
+    gpio::GPIO9,
+];
+
+/// Returns the pending, and enabled, interrupts for GPIO `port`
+///
+/// Bit `n` is set if pin `n` of the port has a pending interrupt, and the pin's interrupt
+/// is enabled. Use this in a `GPIO[X]_Combined` handler to find the pins that need service.
+/// Returns 0 if `port` is not between 1 and 9.
+pub fn interrupt_status(port: usize) -> u32 {
+    PORTS
+        .get(port.wrapping_sub(1))
+        // Safety: reads are atomic
+        .map(|&block| unsafe {
+            ral::read_reg!(ral::gpio, block, ISR) & ral::read_reg!(ral::gpio, block, IMR)
+        })
+        .unwrap_or(0)
+}
+
+/// Clear the pending interrupts for the pins in `mask` of GPIO `port`
+///
+/// Does nothing if `port` is not between 1 and 9.
+///
+/// # Safety
+///
+/// The write is atomic, but it clears the flags of every pin in `mask`. Caller must
+/// ensure that it doesn't clear flags for pins that are used elsewhere.
+pub unsafe fn clear_interrupt_status(port: usize, mask: u32) {
+    if let Some(&block) = PORTS.get(port.wrapping_sub(1)) {
+        ral::write_reg!(ral::gpio, block, ISR, mask);
+    }
+}
+
+impl<P, S> GPIO<P, Input, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Enable (`true`) or disable (`false`) interrupts for this GPIO input.
+    pub fn set_interrupt_enable(&mut self, enable: bool) {
+        cortex_m::interrupt::free(|_| unsafe {
+            ral::modify_reg!(ral::gpio, self.register_block(), IMR, |imr| if enable {
+                imr | self.mask()
+            } else {
+                imr & !self.mask()
+            })
+        });
+    }
+
+    /// Indicates if interrupts are (`true`) or are not (`false`) enabled for this GPIO input.
+    pub fn is_interrupt_enabled(&self) -> bool {
+        unsafe { ral::read_reg!(ral::gpio, self.register_block(), IMR) & self.mask() != 0u32 }
+    }
+
+    /// Set the interrupt configuration for this GPIO input.
+    ///
+    /// Changing the configuration may set the pin's interrupt flag, so this clears the
+    /// flag after the change.
+    pub fn set_interrupt_configuration(&mut self, interrupt_configuration: InterruptConfiguration) {
+        let field = IcrField::new(<P as Pin>::Offset::USIZE, interrupt_configuration);
+        // Safety: These modify_reg! must be completed as one unit, or we get an inconsistent state.
+        cortex_m::interrupt::free(|_| unsafe {
+            let icr_modify = |reg| reg & !field.mask | field.value;
+            if field.icr2 {
+                ral::modify_reg!(ral::gpio, self.register_block(), ICR2, icr_modify);
+            } else {
+                ral::modify_reg!(ral::gpio, self.register_block(), ICR1, icr_modify);
+            }
+            ral::modify_reg!(ral::gpio, self.register_block(), EDGE_SEL, |edge_sel| {
+                if field.edge_sel {
+                    edge_sel | self.mask()
+                } else {
+                    edge_sel & !self.mask()
+                }
+            });
+            ral::write_reg!(ral::gpio, self.register_block(), ISR, self.mask());
+        });
+    }
+
+    /// Returns `true` if this GPIO input has a pending interrupt.
+    ///
+    /// The flag is set even if the interrupt is disabled.
+    pub fn is_interrupt_pending(&self) -> bool {
+        unsafe { ral::read_reg!(ral::gpio, self.register_block(), ISR) & self.mask() != 0u32 }
+    }
+
+    /// Clear the pending interrupt flag.
+    pub fn clear_interrupt_pending(&mut self) {
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), ISR, self.mask()) }
+    }
+
+    /// Indicates whether this GPIO input triggered an interrupt.
+    ///
+    /// Same as [`is_interrupt_pending()`](#method.is_interrupt_pending).
+    pub fn is_interrupt_status(&self) -> bool {
+        self.is_interrupt_pending()
+    }
+
+    /// Clear the interrupt status flag.
+    ///
+    /// Same as [`clear_interrupt_pending()`](#method.clear_interrupt_pending).
+    pub fn clear_interrupt_status(&mut self) {
+        self.clear_interrupt_pending()
+    }
+}
+
+/// Steps that turn a pad into a GPIO output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputStep {
+    /// Write the pin's data register bit
+    Data,
+    /// Set the pin's direction to output
+    Direction,
+    /// Select the pad's GPIO function
+    Mux,
+}
+
+/// The order of the output steps
+///
+/// The data register holds the level before the pin drives, and the pad doesn't connect
+/// to the GPIO until the GPIO drives the level.
+const OUTPUT_STEPS: [OutputStep; 3] = [OutputStep::Data, OutputStep::Direction, OutputStep::Mux];
+
+/// Returns the output steps for a pad that needs its GPIO function, `mux`, or for a pad
+/// that's already a GPIO
+fn output_steps(mux: bool) -> &'static [OutputStep] {
+    if mux {
+        &OUTPUT_STEPS
+    } else {
+        &OUTPUT_STEPS[..2]
+    }
+}
+
+/// The register writes of each output step
+trait OutputRegisters {
+    /// Write DR_SET, or DR_CLEAR, for `state`
+    fn write_data(&mut self, state: PinState);
+    /// Set the pin's GDIR bit
+    fn set_direction(&mut self);
+    /// Select the pad's GPIO function
+    fn select_gpio(&mut self);
+}
+
+/// Run the output `steps`, in order, to drive `state`
+fn run_output_steps(regs: &mut impl OutputRegisters, state: PinState, steps: &[OutputStep]) {
+    for step in steps {
+        match step {
+            OutputStep::Data => regs.write_data(state),
+            OutputStep::Direction => regs.set_direction(),
+            OutputStep::Mux => regs.select_gpio(),
+        }
+    }
+}
+
+impl<P, S> OutputRegisters for GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    fn write_data(&mut self, state: PinState) {
+        match state {
+            PinState::High => self.set(),
+            PinState::Low => self.clear(),
+        }
+    }
+    fn set_direction(&mut self) {
+        cortex_m::interrupt::free(|cs| self.set_output(cs));
+    }
+    fn select_gpio(&mut self) {
+        crate::iomuxc::gpio::prepare(&mut self.pin);
+    }
+}
+
+impl<P> GPIO<P, Output>
+where
+    P: Pin,
+{
+    /// Create an output from a pad, driving `state` from the start
+    ///
+    /// Unlike `GPIO::new(pad).output()`, the pad never drives the wrong level. This sets the
+    /// data register, then the direction, and then selects the pad's GPIO function. Use this
+    /// for active-low enables and resets.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::{self, gpio::{GPIO, PinState}};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// // Active-low enable, held disabled
+    /// let enable = GPIO::new_with_state(peripherals.iomuxc.b0.p03, PinState::High);
+    /// assert_eq!(enable.state(), PinState::High);
+    /// ```
+    pub fn new_with_state(pin: P, state: PinState) -> Self {
+        let mut gpio = GPIO {
+            pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        };
+        run_output_steps(&mut gpio, state, output_steps(true));
+        gpio
+    }
+}
+
+impl<P, S> GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Returns the level that the output drives
+    ///
+    /// This reads the data register, not the pad, so it's the level that you last set.
+    pub fn state(&self) -> PinState {
+        PinState::from(self.is_set())
+    }
+
+    /// Transition the pin back to an input
+    pub fn input(self) -> GPIO<P, Input, S> {
+        cortex_m::interrupt::free(|cs| self.set_input(cs));
+        GPIO {
+            pin: self.pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        }
+    }
+
+    /// Set the GPIO high
+    ///
+    /// This writes DR_SET, so it's safe to call while an interrupt drives another
+    /// pin on the same port.
+    pub fn set(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_SET, self.mask()) };
+    }
+
+    /// Set the GPIO low
+    pub fn clear(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_CLEAR, self.mask()) };
+    }
+
+    /// Returns `true` if the pin is high
+    pub fn is_set(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.register_block(), DR) & self.mask() != 0u32 }
+    }
+
+    /// Alternate the state of the pin
+    pub fn toggle(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_TOGGLE, self.mask()) }
+    }
+}
+
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+impl<P, S> OutputPin for GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    type Error = core::convert::Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set();
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        Ok(())
+    }
+}
+
+impl<P, S> StatefulOutputPin for GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|res| !res)
+    }
+}
+
+impl<P, S> ToggleableOutputPin for GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    type Error = core::convert::Infallible;
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        GPIO::<P, Output, S>::toggle(self);
+        Ok(())
+    }
+}
+
+impl<P, S> InputPin for GPIO<P, Input, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    type Error = core::convert::Infallible;
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|res| !res)
+    }
+}
+
+/// A GPIO port data register that the DMA controller may write
+///
+/// Use a `PortWriter` as the destination of a DMA transfer to generate arbitrary digital patterns
+/// on a GPIO port. Each element of the DMA source is one `u32` word for the port. The DMA controller
+/// writes every word to the same register; the destination address never increments.
+///
+/// The DMA controller can only access the normal GPIO ports, GPIO1 through GPIO5. Make sure that the
+/// GPIO [outputs](struct.GPIO.html#method.output) that you're driving are `Normal`, not `Fast`.
+///
+/// # Pacing
+///
+/// By default, a `PortWriter` has no hardware request signal. Pace the transfer with a periodic trigger:
+/// DMA channels 0 through 3 may be triggered by the PIT channel of the same number. See
+/// [`Peripheral::transfer_set_periodic_trigger()`](../dma/struct.Peripheral.html#method.transfer_set_periodic_trigger).
+/// Alternatively, use [`with_request_signal()`](struct.PortWriter.html#method.with_request_signal) to pace
+/// the transfer with another peripheral's DMA request, like a PWM or FlexIO request.
+///
+/// Each DMA write to a GPIO port crosses the peripheral bus, which runs from the IPG clock. Expect
+/// update rates in the low MHz, well below the IPG clock. The time between the trigger and the port
+/// update depends on DMA channel arbitration and bus contention. To reduce jitter, give the channel
+/// a high priority, and avoid other bus traffic to the same port.
+///
+/// # Example
+///
+/// Generate a pattern from a circular buffer on GPIO2, paced by PIT channel 0.
+///
+/// ```no_run
+/// use imxrt1060_hal::{
+///     dma::{Buffer, Circular, Peripheral},
+///     gpio::{PortRegister, PortWriter, GPIO},
+/// };
+///
+/// #[repr(align(64))]
+/// struct Align(Buffer<[u32; 16]>);
+/// static PATTERN: Align = Align(Buffer::new([0; 16]));
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+///
+/// // B0_00 through B0_03 are GPIO2_0 through GPIO2_3
+/// let _pins = (
+///     GPIO::new(peripherals.iomuxc.b0.p00).output(),
+///     GPIO::new(peripherals.iomuxc.b0.p01).output(),
+///     GPIO::new(peripherals.iomuxc.b0.p02).output(),
+///     GPIO::new(peripherals.iomuxc.b0.p03).output(),
+/// );
+///
+/// // Safety: we own the GPIO2 pins that we toggle.
+/// let writer = unsafe { PortWriter::new(2, PortRegister::Toggle) }.unwrap();
+///
+/// let mut dma_channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+/// let mut peripheral: Peripheral<_, u32, Circular<u32>> =
+///     Peripheral::new_transfer(writer, dma_channels[0].take().unwrap());
+/// peripheral.transfer_set_periodic_trigger().unwrap();
+///
+/// let mut pattern = Circular::new(&PATTERN.0).unwrap();
+/// pattern.insert([0b0001, 0b0011, 0b0110, 0b1100, 0b1000].iter().copied());
+///
+/// // Start the PIT channel 0 timer, then...
+/// peripheral.start_transfer(pattern).unwrap();
+/// ```
+pub struct PortWriter {
+    register: *const u32,
+    signal: u32,
+}
+
+/// The GPIO port register written by a [`PortWriter`](struct.PortWriter.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortRegister {
+    /// The data register; each word sets the state of every pin in the port
+    Data,
+    /// Each `1` bit sets the pin high
+    Set,
+    /// Each `1` bit sets the pin low
+    Clear,
+    /// Each `1` bit toggles the pin
+    Toggle,
+}
+
+impl PortWriter {
+    /// Create a `PortWriter` for the register `register` of GPIO `port`
+    ///
+    /// Returns `None` if `port` is not between 1 and 5.
+    ///
+    /// # Safety
+    ///
+    /// The DMA controller may change the state of any pin in the port. The `Set`, `Clear`, and
+    /// `Toggle` registers only affect the pins that have a `1` bit. Caller must ensure that the
+    /// DMA source does not modify pins that are used elsewhere.
+    pub unsafe fn new(port: usize, register: PortRegister) -> Option<Self> {
+        const REGISTER_BLOCKS: [*const RegisterBlock; 5] = [
+            gpio::GPIO1,
+            gpio::GPIO2,
+            gpio::GPIO3,
+            gpio::GPIO4,
+            gpio::GPIO5,
+        ];
+        let block = *REGISTER_BLOCKS.get(port.wrapping_sub(1))?;
+        let register = match register {
+            PortRegister::Data => &(*block).DR as *const _ as *const u32,
+            PortRegister::Set => &(*block).DR_SET as *const _ as *const u32,
+            PortRegister::Clear => &(*block).DR_CLEAR as *const _ as *const u32,
+            PortRegister::Toggle => &(*block).DR_TOGGLE as *const _ as *const u32,
+        };
+        Some(PortWriter {
+            register,
+            signal: 0,
+        })
+    }
+
+    /// Pace DMA transfers with the DMA request `signal`
+    ///
+    /// Refer to the DMA multiplexer's request sources in the reference manual to find the signal.
+    pub fn with_request_signal(self, signal: u32) -> Self {
+        PortWriter { signal, ..self }
+    }
+}
+
+// Safety: the register pointer is static, and the DMA controller is the only writer.
+unsafe impl Send for PortWriter {}
+
+unsafe impl crate::dma::peripheral::Destination<u32> for PortWriter {
+    fn destination_signal(&self) -> u32 {
+        self.signal
+    }
+    fn destination(&self) -> *const u32 {
+        self.register
+    }
+    fn enable_destination(&self) {}
+    fn disable_destination(&self) {}
+}
+
+impl<P, S> GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Turn the output into an open-drain output
+    ///
+    /// This sets the pad's open drain enable (ODE) bit. The pin keeps the rest of its pad
+    /// configuration, including any pull-up. The pin keeps its state: a high output is
+    /// released, and a low output holds the line low.
+    pub fn into_open_drain(mut self) -> GPIO<P, OpenDrain, S> {
+        crate::iomuxc::configure(
+            &mut self.pin,
+            crate::iomuxc::Config::modify().set_open_drain(crate::iomuxc::OpenDrain::Enabled),
+        );
+        GPIO {
+            pin: self.pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        }
+    }
+}
+
+/// Open-drain outputs
+///
+/// # Example
+///
+/// A 1-Wire reset and presence detect on Teensy 4 pin 2, pad EMC_04. The bus needs a
+/// pull-up; 1-Wire buses usually have a 4.7KOhm resistor to 3.3V. The delays assume a
+/// 600MHz core.
+///
+/// ```no_run
+/// use imxrt1060_hal::{self, gpio::GPIO};
+///
+/// const CYCLES_PER_US: u32 = 600;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let mut bus = GPIO::new(peripherals.iomuxc.emc.p04).output();
+/// bus.set();
+/// let mut bus = bus.into_open_drain();
+///
+/// // Reset pulse
+/// bus.clear();
+/// cortex_m::asm::delay(480 * CYCLES_PER_US);
+/// bus.set();
+/// // A device answers by holding the bus low
+/// cortex_m::asm::delay(70 * CYCLES_PER_US);
+/// let present = !bus.is_set();
+/// cortex_m::asm::delay(410 * CYCLES_PER_US);
+/// ```
+impl<P, S> GPIO<P, OpenDrain, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Turn the open-drain output back into a push-pull output
+    pub fn into_push_pull(mut self) -> GPIO<P, Output, S> {
+        crate::iomuxc::configure(
+            &mut self.pin,
+            crate::iomuxc::Config::modify().set_open_drain(crate::iomuxc::OpenDrain::Disabled),
+        );
+        GPIO {
+            pin: self.pin,
+            dir: PhantomData,
+            speed: PhantomData,
+        }
+    }
+
+    /// Transition the pin back to an input
+    pub fn input(self) -> GPIO<P, Input, S> {
+        self.into_push_pull().input()
+    }
+
+    /// Release the line, so that the pull-up, or another device, sets the level
+    pub fn set(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_SET, self.mask()) };
+    }
+
+    /// Drive the line low
+    pub fn clear(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.register_block(), DR_CLEAR, self.mask()) };
+    }
+
+    /// Returns `true` if the line is high
+    ///
+    /// This reads the line, not the output. If the pin releases the line, but another device
+    /// holds the line low, this returns `false`.
+    pub fn is_set(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.register_block(), PSR) & self.mask() != 0u32 }
+    }
+
+    /// Returns `true` if the pin releases the line
+    pub fn is_released(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.register_block(), DR) & self.mask() != 0u32 }
+    }
+}
+
+impl<P, S> OutputPin for GPIO<P, OpenDrain, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    type Error = core::convert::Infallible;
+
+    /// Release the line
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set();
+        Ok(())
+    }
+
+    /// Drive the line low
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        Ok(())
+    }
+}
+
+impl<P, S> InputPin for GPIO<P, OpenDrain, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    type Error = core::convert::Infallible;
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|res| !res)
+    }
+}
+
+/// A GPIO's port and pin, known at runtime
+#[derive(Clone, Copy)]
+pub(crate) struct ErasedPin {
+    block: *const RegisterBlock,
+    mask: u32,
+}
+
+impl ErasedPin {
+    /// Erase the pin `P`, which uses its fast port if `fast` is `true`
+    fn new<P: Pin>(fast: bool) -> Self {
+        ErasedPin {
+            block: PORTS[port(<P as Pin>::Module::USIZE, fast).wrapping_sub(1)],
+            mask: 1 << <P as Pin>::Offset::USIZE,
+        }
+    }
+
+    /// Erase the pin `P`, on the port, fast or normal, that drives it now
+    pub(crate) fn driving<P: Pin>(gpr: &GPR) -> Self {
+        let mask = 1 << <P as Pin>::Offset::USIZE;
+        let fast =
+            fast_pins(&gpr.0, <P as Pin>::Module::USIZE).map_or(false, |pins| pins & mask != 0);
+        ErasedPin::new::<P>(fast)
+    }
+
+    /// Set the pin's output high
+    pub(crate) fn set(&self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.block, DR_SET, self.mask) };
+    }
+
+    /// Set the pin's output low
+    pub(crate) fn clear(&self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.block, DR_CLEAR, self.mask) };
+    }
+
+    /// Returns `true` if the pin's output is high
+    pub(crate) fn is_set(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.block, DR) & self.mask != 0 }
+    }
+
+    /// Returns `true` if the pad is high
+    pub(crate) fn is_high(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.block, PSR) & self.mask != 0 }
+    }
+
+    /// Make the pin an output if `output` is `true`, or an input, and returns `true`
+    /// if the pin was an output
+    pub(crate) fn set_output(&self, output: bool) -> bool {
+        cortex_m::interrupt::free(|_| {
+            // Safety: critical section ensures consistency
+            unsafe {
+                let gdir = ral::read_reg!(ral::gpio, self.block, GDIR);
+                let gdir_next = if output {
+                    gdir | self.mask
+                } else {
+                    gdir & !self.mask
+                };
+                ral::write_reg!(ral::gpio, self.block, GDIR, gdir_next);
+                gdir & self.mask != 0
+            }
+        })
+    }
+
+    fn port(&self) -> usize {
+        PORTS
+            .iter()
+            .position(|&block| block == self.block)
+            .map_or(0, |idx| idx + 1)
+    }
+
+    fn offset(&self) -> usize {
+        self.mask.trailing_zeros() as usize
+    }
+}
+
+impl<P, D, S> GPIO<P, D, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    fn erased(&self) -> ErasedPin {
+        ErasedPin::new::<P>(S::FAST)
+    }
+}
+
+/// An output that doesn't carry its pad type
+///
+/// Use `ErasedOutput` to store outputs from different pads in one array. Create one with
+/// [`GPIO::erase()`](struct.GPIO.html#method.erase). Erasing is one way, and the pad stays
+/// a GPIO output. An erased output stays on the port, fast or normal, that it used when
+/// it was erased.
+///
+/// ```no_run
+/// use imxrt1060_hal::{self, gpio::{ErasedOutput, GPIO}};
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let mut leds: [ErasedOutput; 3] = [
+///     GPIO::new(peripherals.iomuxc.b0.p03).output().erase(),
+///     GPIO::new(peripherals.iomuxc.emc.p04).output().erase(),
+///     GPIO::new(peripherals.iomuxc.ad_b0.p12).output().erase(),
+/// ];
+/// for led in leds.iter_mut() {
+///     led.set();
+/// }
+/// ```
+pub struct ErasedOutput {
+    pin: ErasedPin,
+}
+
+/// An input that doesn't carry its pad type
+///
+/// Create one with [`GPIO::erase()`](struct.GPIO.html#method.erase-1). See
+/// [`ErasedOutput`](struct.ErasedOutput.html) for more information.
+pub struct ErasedInput {
+    pin: ErasedPin,
+}
+
+impl<P, S> GPIO<P, Output, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Erase the output's pad type
+    pub fn erase(self) -> ErasedOutput {
+        ErasedOutput { pin: self.erased() }
+    }
+}
+
+impl<P, S> GPIO<P, Input, S>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Erase the input's pad type
+    pub fn erase(self) -> ErasedInput {
+        ErasedInput { pin: self.erased() }
+    }
+}
+
+impl ErasedOutput {
+    /// Returns the GPIO port number, 1 through 9
+    pub fn port(&self) -> usize {
+        self.pin.port()
+    }
+
+    /// Returns the pin's offset in its port
+    pub fn offset(&self) -> usize {
+        self.pin.offset()
+    }
+
+    /// Set the GPIO high
+    pub fn set(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.pin.block, DR_SET, self.pin.mask) };
+    }
+
+    /// Set the GPIO low
+    pub fn clear(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.pin.block, DR_CLEAR, self.pin.mask) };
+    }
+
+    /// Returns `true` if the pin is high
+    pub fn is_set(&self) -> bool {
+        // Safety: atomic read
+        unsafe { ral::read_reg!(ral::gpio, self.pin.block, DR) & self.pin.mask != 0u32 }
+    }
+
+    /// Alternate the state of the pin
+    pub fn toggle(&mut self) {
+        // Safety: atomic write
+        unsafe { ral::write_reg!(ral::gpio, self.pin.block, DR_TOGGLE, self.pin.mask) }
+    }
+}
+
+impl ErasedInput {
+    /// Returns the GPIO port number, 1 through 9
+    pub fn port(&self) -> usize {
+        self.pin.port()
+    }
+
+    /// Returns the pin's offset in its port
+    pub fn offset(&self) -> usize {
+        self.pin.offset()
+    }
+
+    /// Returns `true` if this input pin is high
+    pub fn is_set(&self) -> bool {
+        // Safety: read is atomic
+        unsafe { ral::read_reg!(ral::gpio, self.pin.block, PSR) & self.pin.mask != 0 }
+    }
+}
+
+impl OutputPin for ErasedOutput {
+    type Error = core::convert::Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set();
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for ErasedOutput {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|res| !res)
+    }
+}
+
+impl ToggleableOutputPin for ErasedOutput {
+    type Error = core::convert::Infallible;
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        ErasedOutput::toggle(self);
+        Ok(())
+    }
+}
+
+impl InputPin for ErasedInput {
+    type Error = core::convert::Infallible;
     fn is_high(&self) -> Result<bool, Self::Error> {
         Ok(self.is_set())
     }
@@ -475,3 +1779,478 @@ where
         self.is_high().map(|res| !res)
     }
 }
+
+/// A whole GPIO port
+///
+/// Use a `Port` to read, or write, many pins of one port in a single access. See
+/// [`PinGroup`](struct.PinGroup.html) for a safe way to own the pins that you drive.
+pub struct Port {
+    block: *const RegisterBlock,
+}
+
+impl Port {
+    /// Create a handle for GPIO `port`
+    ///
+    /// Returns `None` if `port` is not between 1 and 9. Ports 6 through 9 are the fast
+    /// GPIOs, and they only drive pins that are switched to fast mode.
+    ///
+    /// # Safety
+    ///
+    /// A `Port` may change the state of any output in the port. Caller must ensure that
+    /// writes do not modify pins that are used elsewhere.
+    pub unsafe fn new(port: usize) -> Option<Self> {
+        PORTS.get(port.wrapping_sub(1)).map(|&block| Port { block })
+    }
+
+    /// Returns the state of every pin in the port
+    ///
+    /// Bit `n` is the level of pin `n`.
+    pub fn read(&self) -> u32 {
+        // Safety: read is atomic
+        unsafe { ral::read_reg!(ral::gpio, self.block, PSR) }
+    }
+
+    /// Drive the outputs selected by `mask` to the levels in `value`
+    ///
+    /// Pins outside of `mask` are never written, so they don't glitch. The write is a
+    /// DR_SET store followed by a DR_CLEAR store, so the pins that go high change a few
+    /// cycles before the pins that go low.
+    pub fn write_masked(&mut self, mask: u32, value: u32) {
+        // Safety: atomic writes, and they only affect the pins in mask
+        unsafe {
+            ral::write_reg!(ral::gpio, self.block, DR_SET, value & mask);
+            ral::write_reg!(ral::gpio, self.block, DR_CLEAR, !value & mask);
+        }
+    }
+}
+
+/// The most pins in a [`PinGroup`](struct.PinGroup.html)
+const PIN_GROUP_MAX: usize = 16;
+
+/// Pins of one GPIO port, read and written as a bit field
+///
+/// Start a group with [`new()`](#method.new), then add pins with [`with()`](#method.with).
+/// The first pin is bit 0 of [`write_bits()`](#method.write_bits) and
+/// [`read_bits()`](#method.read_bits), the second pin is bit 1, and so on. The pins may
+/// be in any order in the port. The group owns its pins; use [`release()`](#method.release)
+/// to get them back, as nested tuples.
+///
+/// # Example
+///
+/// A 4-bit bus on B0_00 through B0_03, all on GPIO2.
+///
+/// ```no_run
+/// use imxrt1060_hal::{self, gpio::{GPIO, PinGroup}};
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let b0 = peripherals.iomuxc.b0;
+/// let mut bus = PinGroup::new(GPIO::new(b0.p00).output())
+///     .with(GPIO::new(b0.p01).output())
+///     .ok()
+///     .and_then(|bus| bus.with(GPIO::new(b0.p02).output()).ok())
+///     .and_then(|bus| bus.with(GPIO::new(b0.p03).output()).ok())
+///     .unwrap();
+///
+/// bus.write_bits(0b1010);
+/// assert_eq!(bus.read_bits(), 0b1010);
+/// ```
+pub struct PinGroup<D, Pins> {
+    pins: Pins,
+    block: *const RegisterBlock,
+    offsets: [u8; PIN_GROUP_MAX],
+    len: usize,
+    dir: PhantomData<D>,
+}
+
+/// Errors when adding a pin to a [`PinGroup`](struct.PinGroup.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinGroupError {
+    /// The pin is in a different port than the group
+    ///
+    /// Fast and normal GPIOs are different ports.
+    OtherPort,
+    /// The group already has 16 pins
+    Full,
+}
+
+impl<P, D, S> PinGroup<D, GPIO<P, D, S>>
+where
+    P: Pin,
+    S: PortSpeed,
+{
+    /// Start a group with `pin`, which becomes bit 0
+    pub fn new(pin: GPIO<P, D, S>) -> Self {
+        let mut offsets = [0; PIN_GROUP_MAX];
+        offsets[0] = <P as Pin>::Offset::USIZE as u8;
+        PinGroup {
+            block: pin.register_block(),
+            pins: pin,
+            offsets,
+            len: 1,
+            dir: PhantomData,
+        }
+    }
+}
+
+impl<D, Pins> PinGroup<D, Pins> {
+    /// Add `pin` to the group, as the next bit
+    ///
+    /// Returns the group, the pin, and the error if `pin` is in another port, or if the group
+    /// is full.
+    #[allow(clippy::type_complexity)] // The error hands back everything that we took
+    pub fn with<P, S>(
+        mut self,
+        pin: GPIO<P, D, S>,
+    ) -> Result<PinGroup<D, (Pins, GPIO<P, D, S>)>, (Self, GPIO<P, D, S>, PinGroupError)>
+    where
+        P: Pin,
+        S: PortSpeed,
+    {
+        if pin.register_block() != self.block {
+            return Err((self, pin, PinGroupError::OtherPort));
+        }
+        if self.len == PIN_GROUP_MAX {
+            return Err((self, pin, PinGroupError::Full));
+        }
+        self.offsets[self.len] = <P as Pin>::Offset::USIZE as u8;
+        Ok(PinGroup {
+            pins: (self.pins, pin),
+            block: self.block,
+            offsets: self.offsets,
+            len: self.len + 1,
+            dir: PhantomData,
+        })
+    }
+
+    /// Returns the port bits of every pin in the group
+    pub fn mask(&self) -> u32 {
+        spread_bits(self.offsets(), u16::max_value()).0
+    }
+
+    /// Release the pins
+    pub fn release(self) -> Pins {
+        self.pins
+    }
+
+    fn offsets(&self) -> &[u8] {
+        &self.offsets[..self.len]
+    }
+}
+
+impl<Pins> PinGroup<Input, Pins> {
+    /// Returns the inputs' levels, one bit per pin
+    pub fn read_bits(&self) -> u16 {
+        // Safety: read is atomic
+        let psr = unsafe { ral::read_reg!(ral::gpio, self.block, PSR) };
+        gather_bits(self.offsets(), psr)
+    }
+}
+
+impl<Pins> PinGroup<Output, Pins> {
+    /// Drive the outputs, one bit per pin
+    ///
+    /// Bits past the last pin are ignored. See [`Port::write_masked()`](struct.Port.html#method.write_masked)
+    /// for the timing across pins.
+    pub fn write_bits(&mut self, bits: u16) {
+        let (mask, value) = spread_bits(self.offsets(), bits);
+        Port { block: self.block }.write_masked(mask, value);
+    }
+
+    /// Returns the levels that the outputs drive, one bit per pin
+    pub fn read_bits(&self) -> u16 {
+        // Safety: read is atomic
+        let dr = unsafe { ral::read_reg!(ral::gpio, self.block, DR) };
+        gather_bits(self.offsets(), dr)
+    }
+}
+
+/// Moves bit `n` of `bits` to bit `offsets[n]` of the port
+///
+/// Returns the port mask for all of the offsets, and the port value.
+fn spread_bits(offsets: &[u8], bits: u16) -> (u32, u32) {
+    offsets
+        .iter()
+        .enumerate()
+        .fold((0, 0), |(mask, value), (bit, &offset)| {
+            let port_bit = 1 << offset;
+            let set = if bits & (1 << bit) != 0 { port_bit } else { 0 };
+            (mask | port_bit, value | set)
+        })
+}
+
+/// Moves bit `offsets[n]` of the port `word` to bit `n`
+fn gather_bits(offsets: &[u8], word: u32) -> u16 {
+    offsets.iter().enumerate().fold(0, |bits, (bit, &offset)| {
+        bits | ((((word >> offset) & 1) as u16) << bit)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        gather_bits, gpr_offset, output_steps, port, run_output_steps, spread_bits, IcrField,
+        InterruptConfiguration, OutputStep, PinState,
+    };
+    use crate::testing::EventLog;
+
+    #[test]
+    fn fast_ports() {
+        for module in 1..=4 {
+            assert_eq!(port(module, false), module);
+            assert_eq!(port(module, true), module + 5);
+            // GPR26 through GPR29
+            assert_eq!(gpr_offset(module), Some(module - 1));
+        }
+        // GPIO5 has no fast port
+        assert_eq!(port(5, true), 5);
+        assert_eq!(gpr_offset(5), None);
+        assert_eq!(gpr_offset(0), None);
+    }
+
+    #[test]
+    fn pull_fields() {
+        use super::Pull;
+        // PUS selects the pull
+        assert_eq!(Pull::from_bits(0x3000), Pull::Down100k);
+        assert_eq!(Pull::from_bits(0x7000), Pull::Up47k);
+        assert_eq!(Pull::from_bits(0xB000), Pull::Up100k);
+        assert_eq!(Pull::from_bits(0xF000), Pull::Up22k);
+        // Other pad fields don't matter
+        assert_eq!(Pull::from_bits(0xF000 | 0x1_0FFF), Pull::Up22k);
+        // The i.MX RT 1060 reset value for most pads: 100K pull-down, with the keeper
+        // selected
+        assert_eq!(Pull::from_bits(0x10B0), Pull::Keeper);
+        // Keeper ignores PUS
+        assert_eq!(Pull::from_bits(0x1000 | 0xC000), Pull::Keeper);
+        // Without PKE, PUE and PUS don't matter
+        assert_eq!(Pull::from_bits(0xE000), Pull::None);
+    }
+
+    /// A pad, and its pin's DR and GDIR bits, that records the register writes
+    struct FakePad {
+        dr: bool,
+        gdir: bool,
+        gpio_mux: bool,
+        writes: EventLog<Write>,
+        /// Every level that the pad drove, after each write
+        driven: EventLog<Option<bool>>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Write {
+        DrSet,
+        DrClear,
+        Gdir,
+        Mux,
+    }
+
+    impl FakePad {
+        /// A pad with `dr` in the data register, and the GPIO function if `gpio_mux`
+        fn new(dr: bool, gpio_mux: bool) -> Self {
+            FakePad {
+                dr,
+                gdir: false,
+                gpio_mux,
+                writes: EventLog::default(),
+                driven: EventLog::default(),
+            }
+        }
+
+        fn record(&mut self, write: Write) {
+            self.writes.push(write);
+            self.driven.push(if self.gpio_mux && self.gdir {
+                Some(self.dr)
+            } else {
+                None
+            });
+        }
+
+        /// Returns `true` if the pad only ever drove `level`
+        fn only_drove(&self, level: bool) -> bool {
+            self.driven
+                .events()
+                .all(|driven| driven.map_or(true, |driven| driven == level))
+        }
+
+        /// Returns the level that the pad drove after the last write
+        fn last_driven(&self) -> Option<bool> {
+            self.driven.events().last().flatten()
+        }
+    }
+
+    impl super::OutputRegisters for FakePad {
+        fn write_data(&mut self, state: PinState) {
+            self.dr = state == PinState::High;
+            self.record(if self.dr {
+                Write::DrSet
+            } else {
+                Write::DrClear
+            });
+        }
+        fn set_direction(&mut self) {
+            self.gdir = true;
+            self.record(Write::Gdir);
+        }
+        fn select_gpio(&mut self) {
+            self.gpio_mux = true;
+            self.record(Write::Mux);
+        }
+    }
+
+    #[test]
+    fn output_steps_write_order() {
+        // new_with_state(): DR, then GDIR, then the mux
+        for &(state, dr, write) in &[
+            (PinState::High, false, Write::DrSet),
+            (PinState::Low, true, Write::DrClear),
+        ] {
+            let mut pad = FakePad::new(dr, false);
+            run_output_steps(&mut pad, state, output_steps(true));
+            pad.writes.assert(&[write, Write::Gdir, Write::Mux]);
+            assert!(pad.only_drove(!dr));
+            assert_eq!(pad.last_driven(), Some(!dr));
+        }
+
+        // output_with_state(): the pad is already a GPIO input, so DR, then GDIR
+        let mut pad = FakePad::new(false, true);
+        run_output_steps(&mut pad, PinState::High, output_steps(false));
+        pad.writes.assert(&[Write::DrSet, Write::Gdir]);
+        assert!(pad.only_drove(true));
+        assert_eq!(pad.last_driven(), Some(true));
+    }
+
+    #[test]
+    fn output_steps_glitch() {
+        // The fake catches the glitch of setting GDIR before DR
+        let mut pad = FakePad::new(false, true);
+        run_output_steps(
+            &mut pad,
+            PinState::High,
+            &[OutputStep::Direction, OutputStep::Data],
+        );
+        assert!(!pad.only_drove(true));
+    }
+
+    #[test]
+    fn pad_fields() {
+        use super::{DriveStrength, PadConfiguration, Pull, SlewRate, Speed};
+        // DSE = R0/6, SPEED = 100MHz, SRE = slow, the reset value for most pads
+        assert_eq!(
+            PadConfiguration::from_bits(0x10B0),
+            PadConfiguration {
+                drive_strength: Some(DriveStrength::R0_6),
+                speed: Speed::Medium100MHz,
+                slew_rate: SlewRate::Slow,
+                open_drain: false,
+                pull: Pull::Keeper,
+                hysteresis: false,
+            }
+        );
+        let config = PadConfiguration::from_bits(0x1_F8F9);
+        assert_eq!(config.drive_strength, Some(DriveStrength::R0_7));
+        assert_eq!(config.speed, Speed::Max200MHz);
+        assert_eq!(config.slew_rate, SlewRate::Fast);
+        assert!(config.open_drain);
+        assert_eq!(config.pull, Pull::Up22k);
+        assert!(config.hysteresis);
+        assert_eq!(DriveStrength::from_bits(0), None);
+
+        for dse in 1..=7 {
+            assert!(DriveStrength::from_bits(dse << 3).is_some());
+        }
+        assert_eq!(Speed::from_bits(0b01 << 6), Speed::Medium100MHz);
+        assert_eq!(Speed::from_bits(0), Speed::Low50MHz);
+        assert_eq!(SlewRate::from_bits(0), SlewRate::Slow);
+    }
+
+    #[test]
+    fn erased_pins() {
+        use super::ErasedPin;
+        use crate::iomuxc::imxrt1060::{
+            ad_b0::AD_B0_12, b0::B0_03, b1::B1_00, emc::EMC_04, sd_b0::SD_B0_00,
+        };
+
+        fn parts(pin: ErasedPin) -> (usize, usize) {
+            (pin.port(), pin.offset())
+        }
+        assert_eq!(parts(ErasedPin::new::<AD_B0_12>(false)), (1, 12));
+        assert_eq!(parts(ErasedPin::new::<AD_B0_12>(true)), (6, 12));
+        assert_eq!(parts(ErasedPin::new::<B0_03>(false)), (2, 3));
+        assert_eq!(parts(ErasedPin::new::<B1_00>(true)), (7, 16));
+        assert_eq!(parts(ErasedPin::new::<SD_B0_00>(false)), (3, 12));
+        assert_eq!(parts(ErasedPin::new::<EMC_04>(true)), (9, 4));
+        assert_eq!(
+            core::mem::size_of::<super::ErasedOutput>(),
+            2 * core::mem::size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn pin_group_bits() {
+        // Out of order, with a gap
+        let offsets = [3, 1, 2, 16, 31];
+        assert_eq!(spread_bits(&offsets, 0), (0x8001_000E, 0));
+        assert_eq!(spread_bits(&offsets, 0b00001), (0x8001_000E, 1 << 3));
+        assert_eq!(
+            spread_bits(&offsets, 0b10010),
+            (0x8001_000E, 1 << 31 | 1 << 1)
+        );
+        // Bits past the last pin are ignored
+        assert_eq!(spread_bits(&offsets, 0xFFE0).1, 0);
+        for bits in 0..32 {
+            let (_, value) = spread_bits(&offsets, bits);
+            // Other port bits don't leak in
+            assert_eq!(gather_bits(&offsets, value | 0x7FFE_FFF1), bits);
+        }
+        assert_eq!(gather_bits(&[], u32::max_value()), 0);
+    }
+
+    #[test]
+    fn icr_fields() {
+        let field = IcrField::new(0, InterruptConfiguration::LowLevel);
+        assert_eq!(
+            field,
+            IcrField {
+                icr2: false,
+                mask: 0b11,
+                value: 0,
+                edge_sel: false
+            }
+        );
+        let field = IcrField::new(5, InterruptConfiguration::FallingEdge);
+        assert_eq!((field.icr2, field.mask, field.value), (false, 0xC00, 0xC00));
+        let field = IcrField::new(15, InterruptConfiguration::HighLevel);
+        assert_eq!(
+            (field.icr2, field.mask, field.value),
+            (false, 0xC000_0000, 0x4000_0000)
+        );
+        // ICR2 starts again at bit 0
+        let field = IcrField::new(16, InterruptConfiguration::RisingEdge);
+        assert_eq!((field.icr2, field.mask, field.value), (true, 0b11, 0b10));
+        let field = IcrField::new(31, InterruptConfiguration::FallingEdge);
+        assert_eq!(
+            (field.icr2, field.mask, field.value),
+            (true, 0xC000_0000, 0xC000_0000)
+        );
+    }
+
+    #[test]
+    fn either_edge_overrides_icr() {
+        for &offset in &[0, 7, 16, 31] {
+            let either = IcrField::new(offset, InterruptConfiguration::EitherEdge);
+            assert!(either.edge_sel);
+            assert_eq!(
+                either.value,
+                IcrField::new(offset, InterruptConfiguration::RisingEdge).value
+            );
+            for &config in &[
+                InterruptConfiguration::LowLevel,
+                InterruptConfiguration::HighLevel,
+                InterruptConfiguration::RisingEdge,
+                InterruptConfiguration::FallingEdge,
+            ] {
+                assert!(!IcrField::new(offset, config).edge_sel);
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Async GPIO inputs
+//!
+//! Enable this module with the `"async"` feature.
+//!
+//! A GPIO input can wait for a level, or an edge. The wait returns a future that resolves
+//! when the pin's interrupt fires. You service the interrupts: call
+//! [`on_interrupt()`](fn.on_interrupt.html) from each `GPIO[X]_Combined` handler that you
+//! use, and unmask the interrupt in the NVIC.
+//!
+//! The futures keep the wakers in a static table, with one slot for each pin of GPIO1
+//! through GPIO5. The table takes 1280 bytes of RAM when the module is enabled. A fast
+//! GPIO pin shares the slot of its normal GPIO pin.
+//!
+//! Dropping a future disables the pin's interrupt, and clears its waker.
+//!
+//! # Example
+//!
+//! A debounced button on Teensy 4 pin 2, pad EMC_04, which is GPIO4_4. The button connects
+//! the pin to ground, and the pad's pull-up is enabled. Spawn `button()` with your executor,
+//! and an async delay from your timer.
+//!
+//! ```no_run
+//! use imxrt1060_hal::{
+//!     gpio::{asynch, Input, GPIO},
+//!     iomuxc::imxrt1060::emc::EMC_04,
+//! };
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn GPIO4_Combined_0_15() {
+//!     asynch::on_interrupt(4);
+//! }
+//!
+//! async fn button(
+//!     mut pin: GPIO<EMC_04, Input>,
+//!     mut delay: impl embedded_hal_async::delay::DelayNs,
+//!     mut on_press: impl FnMut(),
+//! ) -> ! {
+//!     const DEBOUNCE_MS: u32 = 10;
+//!     loop {
+//!         pin.wait_for_falling_edge().await;
+//!         delay.delay_ms(DEBOUNCE_MS).await;
+//!         if !pin.is_set() {
+//!             on_press();
+//!         }
+//!         pin.wait_for_high().await;
+//!         delay.delay_ms(DEBOUNCE_MS).await;
+//!     }
+//! }
+//! ```
+
+use super::{interrupt_status, Input, InterruptConfiguration, FAST_PORT_OFFSET, GPIO, PORTS};
+use crate::iomuxc::{consts::Unsigned, gpio::Pin};
+use crate::ral;
+use core::{
+    cell::RefCell,
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+use cortex_m::interrupt::Mutex;
+
+/// Pins in a port
+const PINS: usize = 32;
+
+const NO_WAKER: Option<Waker> = None;
+const NO_WAKERS: [Option<Waker>; PINS] = [NO_WAKER; PINS];
+
+/// Wakers for GPIO1 through GPIO5, indexed by `row()` and the pin offset
+static WAKERS: Mutex<RefCell<[[Option<Waker>; PINS]; FAST_PORT_OFFSET]>> =
+    Mutex::new(RefCell::new([NO_WAKERS; FAST_PORT_OFFSET]));
+
+/// Returns the waker table row for GPIO `port`
+///
+/// Fast ports share the row of their normal port. Returns `None` if `port` is not
+/// between 1 and 9.
+fn row(port: usize) -> Option<usize> {
+    if (1..=PORTS.len()).contains(&port) {
+        Some((port - 1) % FAST_PORT_OFFSET)
+    } else {
+        None
+    }
+}
+
+/// Wake the futures that wait on GPIO `port`
+///
+/// Call this from the port's `GPIO[X]_Combined` interrupt handlers. Both the `0_15` and
+/// `16_31` handlers may call this with the same port. This disables the interrupt of
+/// every pending pin that a future waits on, and leaves the flags for the futures to
+/// clear. Pins without a future keep their interrupts and their flags. Does nothing if
+/// `port` is not between 1 and 9, or if nothing is pending.
+pub fn on_interrupt(port: usize) {
+    let pending = interrupt_status(port);
+    let row = match row(port) {
+        Some(row) if pending != 0 => row,
+        _ => return,
+    };
+    let block = PORTS[port - 1];
+    cortex_m::interrupt::free(|cs| {
+        let mut wakers = WAKERS.borrow(cs).borrow_mut();
+        let woken = wake(&mut wakers[row], pending);
+        // Safety: critical section ensures the read-modify-write is consistent
+        unsafe { ral::modify_reg!(ral::gpio, block, IMR, |imr| imr & !woken) };
+    });
+}
+
+/// Wake the futures of the `pending` pins, and return the pins that had futures
+fn wake(wakers: &mut [Option<Waker>; PINS], pending: u32) -> u32 {
+    let mut woken = 0;
+    for (offset, slot) in wakers.iter_mut().enumerate() {
+        if pending & (1 << offset) != 0 {
+            if let Some(waker) = slot.take() {
+                waker.wake();
+                woken |= 1 << offset;
+            }
+        }
+    }
+    woken
+}
+
+/// A future that waits for a GPIO input's level, or edge
+///
+/// Create a `Wait` with the `wait_for_*` methods on an input
+/// [`GPIO`](../struct.GPIO.html).
+pub struct Wait<'a, P>
+where
+    P: Pin,
+{
+    pin: &'a mut GPIO<P, Input>,
+    config: InterruptConfiguration,
+    armed: bool,
+}
+
+impl<'a, P> Wait<'a, P>
+where
+    P: Pin,
+{
+    fn new(pin: &'a mut GPIO<P, Input>, config: InterruptConfiguration) -> Self {
+        Wait {
+            pin,
+            config,
+            armed: false,
+        }
+    }
+
+    /// Returns `true` if a level wait is already satisfied
+    fn is_level(&self) -> bool {
+        match self.config {
+            InterruptConfiguration::HighLevel => self.pin.is_set(),
+            InterruptConfiguration::LowLevel => !self.pin.is_set(),
+            _ => false,
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        let row = <P as Pin>::Module::USIZE - 1;
+        let offset = <P as Pin>::Offset::USIZE;
+        cortex_m::interrupt::free(|cs| {
+            let mut wakers = WAKERS.borrow(cs).borrow_mut();
+            let slot = &mut wakers[row][offset];
+            if !slot.as_ref().map_or(false, |slot| slot.will_wake(waker)) {
+                *slot = Some(waker.clone());
+            }
+        });
+    }
+
+    fn disarm(&mut self) {
+        self.pin.set_interrupt_enable(false);
+        self.pin.clear_interrupt_pending();
+        let row = <P as Pin>::Module::USIZE - 1;
+        let offset = <P as Pin>::Offset::USIZE;
+        cortex_m::interrupt::free(|cs| {
+            WAKERS.borrow(cs).borrow_mut()[row][offset] = None;
+        });
+        self.armed = false;
+    }
+}
+
+impl<P> Future for Wait<'_, P>
+where
+    P: Pin,
+{
+    type Output = ();
+
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        if !this.armed {
+            if this.is_level() {
+                return Poll::Ready(());
+            }
+            // Clears any stale flag
+            this.pin.set_interrupt_configuration(this.config);
+            this.armed = true;
+        } else if this.pin.is_interrupt_pending() {
+            this.disarm();
+            return Poll::Ready(());
+        }
+        // If the flag set after the check, enabling the interrupt wakes us.
+        this.register(cx.waker());
+        this.pin.set_interrupt_enable(true);
+        Poll::Pending
+    }
+}
+
+impl<P> Drop for Wait<'_, P>
+where
+    P: Pin,
+{
+    fn drop(&mut self) {
+        if self.armed {
+            self.disarm();
+        }
+    }
+}
+
+impl<P> GPIO<P, Input>
+where
+    P: Pin,
+{
+    /// Wait for the input to be high
+    ///
+    /// Resolves immediately if the input is already high.
+    pub fn wait_for_high(&mut self) -> Wait<'_, P> {
+        Wait::new(self, InterruptConfiguration::HighLevel)
+    }
+
+    /// Wait for the input to be low
+    ///
+    /// Resolves immediately if the input is already low.
+    pub fn wait_for_low(&mut self) -> Wait<'_, P> {
+        Wait::new(self, InterruptConfiguration::LowLevel)
+    }
+
+    /// Wait for the input to rise
+    pub fn wait_for_rising_edge(&mut self) -> Wait<'_, P> {
+        Wait::new(self, InterruptConfiguration::RisingEdge)
+    }
+
+    /// Wait for the input to fall
+    pub fn wait_for_falling_edge(&mut self) -> Wait<'_, P> {
+        Wait::new(self, InterruptConfiguration::FallingEdge)
+    }
+
+    /// Wait for the input to rise, or fall
+    pub fn wait_for_any_edge(&mut self) -> Wait<'_, P> {
+        Wait::new(self, InterruptConfiguration::EitherEdge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{row, wake, NO_WAKERS};
+    use core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{RawWaker, RawWakerVTable, Waker},
+    };
+
+    /// Counts the wakes of every waker that it makes
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn wake(_: *const ()) {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        }
+        fn drop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        // Safety: the vtable ignores the data pointer
+        unsafe { Waker::from_raw(clone(core::ptr::null())) }
+    }
+
+    #[test]
+    fn wakes_awaited_pins() {
+        let mut wakers = NO_WAKERS;
+        wakers[3] = Some(counting_waker());
+        wakers[7] = Some(counting_waker());
+        // Pin 5 is pending, but nothing waits on it
+        assert_eq!(wake(&mut wakers, 1 << 3 | 1 << 5), 1 << 3);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+        assert!(wakers[3].is_none());
+        assert!(wakers[7].is_some());
+        // A second interrupt doesn't wake the same future again
+        assert_eq!(wake(&mut wakers, 1 << 3), 0);
+        assert_eq!(WAKES.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn waker_rows() {
+        assert_eq!(row(0), None);
+        assert_eq!(row(1), Some(0));
+        assert_eq!(row(5), Some(4));
+        // GPIO6 is the fast GPIO1
+        assert_eq!(row(6), Some(0));
+        assert_eq!(row(9), Some(3));
+        assert_eq!(row(10), None);
+    }
+}
@@ -0,0 +1,307 @@
+//! Debounced GPIO inputs
+//!
+//! A [`Debounced`](struct.Debounced.html) input reports a new level once the pin holds
+//! that level for a stable time. It needs a [`Now`](trait.Now.html) time source; the GPT
+//! implements `Now` when it runs in free-running mode, and a PIT timer implements `Now`
+//! once you [start it free running](../../pit/struct.PIT.html#method.start_free_running).
+//! Implement `Now` for other time sources.
+//!
+//! Call [`poll()`](struct.Debounced.html#method.poll) from a loop. Or, call `poll()` from
+//! the pin's interrupt, and, if the input is [`settling()`](struct.Debounced.html#method.settling),
+//! call `poll()` again from a timer once the stable time elapses.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use core::time::Duration;
+//! use imxrt1060_hal::{self, gpio::{debounce::{Debounced, Edge}, GPIO, Pull}, gpt};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let (_, ipg_hz) = peripherals.ccm.pll1.set_arm_clock(
+//!     imxrt1060_hal::ccm::PLL1::ARM_HZ,
+//!     &mut peripherals.ccm.handle,
+//!     &mut peripherals.dcdc,
+//! ).unwrap();
+//! let mut cfg = peripherals.ccm.perclk.configure(
+//!     &mut peripherals.ccm.handle,
+//!     imxrt1060_hal::ccm::perclk::PODF::DIVIDE_3,
+//!     imxrt1060_hal::ccm::perclk::CLKSEL::IPG(ipg_hz),
+//! );
+//! let mut timer = peripherals.gpt1.clock(&mut cfg);
+//! timer.set_mode(gpt::Mode::FreeRunning);
+//! timer.set_enable(true);
+//!
+//! let pin = GPIO::new(peripherals.iomuxc.emc.p04).with_pull(Pull::Up47k);
+//! let mut button = Debounced::new(pin, &timer, Duration::from_millis(10));
+//! loop {
+//!     if let Some(Edge::Falling) = button.poll() {
+//!         // Pressed
+//!     }
+//! }
+//! ```
+
+use core::{convert::Infallible, time::Duration};
+use embedded_hal::digital::v2::InputPin;
+
+/// A free-running tick counter
+pub trait Now {
+    /// Returns the tick count
+    ///
+    /// The count increments once per tick, and wraps from `u32::max_value()` to 0.
+    fn now(&self) -> u32;
+    /// Returns the duration of one tick
+    fn tick(&self) -> Duration;
+}
+
+impl<T: Now> Now for &T {
+    fn now(&self) -> u32 {
+        T::now(self)
+    }
+    fn tick(&self) -> Duration {
+        T::tick(self)
+    }
+}
+
+/// The GPT must be enabled, and in [free-running mode](../../gpt/enum.Mode.html).
+impl Now for crate::gpt::GPT {
+    fn now(&self) -> u32 {
+        self.count()
+    }
+    fn tick(&self) -> Duration {
+        self.clock_period()
+    }
+}
+
+/// The PIT timer must be [free running](../../pit/struct.PIT.html#method.start_free_running).
+impl<Chan: crate::pit::channel::Channel> Now for crate::pit::PIT<Chan> {
+    fn now(&self) -> u32 {
+        self.count()
+    }
+    fn tick(&self) -> Duration {
+        self.clock_period()
+    }
+}
+
+/// A debounced level change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// The input is now high
+    Rising,
+    /// The input is now low
+    Falling,
+}
+
+/// A debounced input
+///
+/// `P` is any input, like a [`GPIO`](../struct.GPIO.html) input, or an
+/// [`ErasedInput`](../struct.ErasedInput.html). `T` is the time source.
+pub struct Debounced<P, T> {
+    pin: P,
+    clock: T,
+    /// Ticks that a new level must hold
+    stable: u32,
+    /// The debounced level
+    level: bool,
+    /// The level that's settling, if it's not `level`
+    candidate: bool,
+    /// When `candidate` was first seen
+    since: u32,
+}
+
+impl<P, T> Debounced<P, T>
+where
+    P: InputPin<Error = Infallible>,
+    T: Now,
+{
+    /// Debounce `pin`, which must hold a new level for `stable` before it's reported
+    ///
+    /// The debounced level starts as the pin's current level. `stable` is rounded up to
+    /// the next tick, and saturates at `u32::max_value()` ticks.
+    pub fn new(pin: P, clock: T, stable: Duration) -> Self {
+        let stable = ticks(stable, clock.tick());
+        let level = is_high(&pin);
+        let since = clock.now();
+        Debounced {
+            pin,
+            clock,
+            stable,
+            level,
+            candidate: level,
+            since,
+        }
+    }
+
+    /// Sample the pin, and return the edge if the debounced level changed
+    pub fn poll(&mut self) -> Option<Edge> {
+        let sample = is_high(&self.pin);
+        let now = self.clock.now();
+        if sample == self.level {
+            // Bounced back, or never changed
+            self.candidate = sample;
+            None
+        } else if sample != self.candidate {
+            self.candidate = sample;
+            self.since = now;
+            None
+        } else if now.wrapping_sub(self.since) >= self.stable {
+            self.level = sample;
+            Some(if sample { Edge::Rising } else { Edge::Falling })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the debounced level is high
+    pub fn is_high(&self) -> bool {
+        self.level
+    }
+
+    /// Returns `true` if the debounced level is low
+    pub fn is_low(&self) -> bool {
+        !self.level
+    }
+
+    /// Returns `true` if the pin has a new level that's not yet stable
+    ///
+    /// Keep polling until this returns `false`.
+    pub fn settling(&self) -> bool {
+        self.candidate != self.level
+    }
+
+    /// Release the pin and the time source
+    pub fn release(self) -> (P, T) {
+        (self.pin, self.clock)
+    }
+}
+
+fn is_high<P: InputPin<Error = Infallible>>(pin: &P) -> bool {
+    match pin.is_high() {
+        Ok(high) => high,
+        Err(never) => match never {},
+    }
+}
+
+/// Returns the ticks in `duration`, rounded up
+pub(crate) fn ticks(duration: Duration, tick: Duration) -> u32 {
+    let tick = tick.as_nanos().max(1);
+    let ticks = (duration.as_nanos() + tick - 1) / tick;
+    if ticks > u32::max_value() as u128 {
+        u32::max_value()
+    } else {
+        ticks as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debounced, Edge, Now};
+    use crate::gpio::mock::Line;
+    use core::{cell::Cell, time::Duration};
+
+    /// Ticks every microsecond
+    struct FakeClock<'a>(&'a Cell<u32>);
+
+    impl Now for FakeClock<'_> {
+        fn now(&self) -> u32 {
+            self.0.get()
+        }
+        fn tick(&self) -> Duration {
+            Duration::from_micros(1)
+        }
+    }
+
+    /// Drive the pin through `(at, level)` samples, passing each poll result to `expect`
+    ///
+    /// Returns the final debounced level, and whether the input is still settling.
+    fn run(
+        start: u32,
+        samples: &[(u32, bool)],
+        mut expect: impl FnMut(u32, Option<Edge>),
+    ) -> (bool, bool) {
+        let line = Line::new(true);
+        let now = Cell::new(start);
+        let mut input = Debounced::new(line.input(), FakeClock(&now), Duration::from_micros(10));
+        for &(at, level) in samples {
+            now.set(start.wrapping_add(at));
+            line.set_level_externally(level);
+            expect(at, input.poll());
+        }
+        (input.is_high(), input.settling())
+    }
+
+    #[test]
+    fn bounces_are_filtered() {
+        // Falls, bounces twice, then holds low
+        let samples = [
+            (1, false),
+            (2, true),
+            (3, false),
+            (4, true),
+            (5, false),
+            (14, false),
+            (15, false),
+            (20, false),
+        ];
+        let (high, settling) = run(0, &samples, |at, edge| {
+            let expected = if at == 15 { Some(Edge::Falling) } else { None };
+            assert_eq!(edge, expected, "{}", at);
+        });
+        assert!(!high);
+        assert!(!settling);
+    }
+
+    #[test]
+    fn glitch_is_ignored() {
+        let samples = [(1, false), (9, false), (10, true), (30, true)];
+        let (high, settling) = run(0, &samples, |at, edge| assert_eq!(edge, None, "{}", at));
+        assert!(high);
+        assert!(!settling);
+    }
+
+    #[test]
+    fn settling_then_edges() {
+        let samples = [
+            (1, false),
+            (5, false),
+            (11, false),
+            (12, true),
+            (21, true),
+            (22, true),
+        ];
+        let (high, settling) = run(0, &samples, |at, edge| {
+            let expected = match at {
+                11 => Some(Edge::Falling),
+                22 => Some(Edge::Rising),
+                _ => None,
+            };
+            assert_eq!(edge, expected, "{}", at);
+        });
+        assert!(high);
+        assert!(!settling);
+
+        let (_, settling) = run(0, &[(1, false), (5, false)], |_, _| {});
+        assert!(settling);
+    }
+
+    #[test]
+    fn clock_wraps() {
+        let samples = [(1, false), (5, false), (11, false)];
+        run(u32::max_value() - 3, &samples, |at, edge| {
+            let expected = if at == 11 { Some(Edge::Falling) } else { None };
+            assert_eq!(edge, expected, "{}", at);
+        });
+    }
+
+    #[test]
+    fn stable_ticks() {
+        use super::ticks;
+        let us = Duration::from_micros(1);
+        assert_eq!(ticks(Duration::from_micros(10), us), 10);
+        assert_eq!(ticks(Duration::from_nanos(10_001), us), 11);
+        assert_eq!(ticks(Duration::from_secs(1 << 40), us), u32::max_value());
+        assert_eq!(
+            ticks(Duration::from_micros(3), Duration::from_secs(0)),
+            3000
+        );
+    }
+}
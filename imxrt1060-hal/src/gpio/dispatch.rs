@@ -0,0 +1,221 @@
+//! Dispatch GPIO port interrupts to pin handlers
+//!
+//! A [`PortDispatcher`](struct.PortDispatcher.html) maps pins of one port to handlers. Call
+//! [`on_interrupt()`](struct.PortDispatcher.html#method.on_interrupt) from the port's
+//! `GPIO[X]_Combined_0_15` and `GPIO[X]_Combined_16_31` handlers. The dispatcher clears the
+//! flags of the pending pins, then calls their handlers.
+//!
+//! # Example
+//!
+//! Two buttons on GPIO2. Teensy 4 pin 10, pad B0_00, is GPIO2_0, and it uses the `0_15`
+//! vector. Teensy 4 pin 8, pad B1_00, is GPIO2_16, and it uses the `16_31` vector.
+//!
+//! ```no_run
+//! use core::sync::atomic::{AtomicU32, Ordering};
+//! use imxrt1060_hal::{
+//!     gpio::{dispatch::PortDispatcher, InterruptConfiguration, Pull, GPIO},
+//!     ral::interrupt,
+//! };
+//!
+//! static PRESSES: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+//!
+//! fn pin_10(_: usize) {
+//!     PRESSES[0].fetch_add(1, Ordering::Relaxed);
+//! }
+//!
+//! fn pin_8(_: usize) {
+//!     PRESSES[1].fetch_add(1, Ordering::Relaxed);
+//! }
+//!
+//! static GPIO2: PortDispatcher<2> = PortDispatcher::new(2).with(0, pin_10).with(16, pin_8);
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn GPIO2_Combined_0_15() {
+//!     GPIO2.on_interrupt();
+//! }
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn GPIO2_Combined_16_31() {
+//!     GPIO2.on_interrupt();
+//! }
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut pin_10 = GPIO::new(peripherals.iomuxc.b0.p00).with_pull(Pull::Up47k);
+//! pin_10.set_interrupt_configuration(InterruptConfiguration::FallingEdge);
+//! pin_10.set_interrupt_enable(true);
+//! let mut pin_8 = GPIO::new(peripherals.iomuxc.b1.p00).with_pull(Pull::Up47k);
+//! pin_8.set_interrupt_configuration(InterruptConfiguration::FallingEdge);
+//! pin_8.set_interrupt_enable(true);
+//!
+//! // Safety: the handlers only touch atomics
+//! unsafe {
+//!     cortex_m::peripheral::NVIC::unmask(interrupt::GPIO2_Combined_0_15);
+//!     cortex_m::peripheral::NVIC::unmask(interrupt::GPIO2_Combined_16_31);
+//! }
+//! ```
+
+use super::{clear_interrupt_status, interrupt_status};
+
+/// A pin interrupt handler
+///
+/// The dispatcher calls the handler with the pin's offset in the port.
+pub type Handler = fn(usize);
+
+/// Errors when registering a [`Handler`](type.Handler.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The pin offset is not between 0 and 31
+    InvalidPin,
+    /// The pin already has a handler
+    Registered,
+    /// There's no space for another handler
+    Full,
+}
+
+/// Maps the pins of a GPIO port to handlers
+///
+/// A dispatcher holds up to `N` handlers, one per pin. Build it in a `static` with
+/// [`new()`](#method.new) and [`with()`](#method.with), or register handlers at runtime
+/// with [`register()`](#method.register).
+#[derive(Clone, Copy)]
+pub struct PortDispatcher<const N: usize> {
+    port: usize,
+    /// The handled pins
+    mask: u32,
+    handlers: [Option<(u8, Handler)>; N],
+}
+
+impl<const N: usize> PortDispatcher<N> {
+    /// Create a dispatcher for GPIO `port`, without any handlers
+    ///
+    /// A dispatcher for a port that's not between 1 and 9 never calls its handlers.
+    pub const fn new(port: usize) -> Self {
+        PortDispatcher {
+            port,
+            mask: 0,
+            handlers: [None; N],
+        }
+    }
+
+    /// Add a `handler` for pin `offset`
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`register()`](#method.register) would return an error. In a `static`,
+    /// that's a build error.
+    pub const fn with(self, offset: usize, handler: Handler) -> Self {
+        match self.add(offset, handler) {
+            Ok(dispatcher) => dispatcher,
+            Err(DispatchError::InvalidPin) => panic!("pin offset is not between 0 and 31"),
+            Err(DispatchError::Registered) => panic!("pin already has a handler"),
+            Err(DispatchError::Full) => panic!("dispatcher is full"),
+        }
+    }
+
+    /// Add a `handler` for pin `offset`
+    pub fn register(&mut self, offset: usize, handler: Handler) -> Result<(), DispatchError> {
+        *self = self.add(offset, handler)?;
+        Ok(())
+    }
+
+    /// Remove, and return, the handler for pin `offset`
+    pub fn unregister(&mut self, offset: usize) -> Option<Handler> {
+        let slot = self
+            .handlers
+            .iter_mut()
+            .find(|slot| matches!(slot, Some((pin, _)) if *pin as usize == offset))?;
+        let (_, handler) = slot.take()?;
+        self.mask &= !(1 << offset);
+        Some(handler)
+    }
+
+    /// Returns the dispatcher with a `handler` for pin `offset`
+    ///
+    /// The dispatcher is a copy, so that `with()` can be a `const fn`.
+    const fn add(mut self, offset: usize, handler: Handler) -> Result<Self, DispatchError> {
+        if offset >= 32 {
+            return Err(DispatchError::InvalidPin);
+        }
+        if self.mask & (1 << offset) != 0 {
+            return Err(DispatchError::Registered);
+        }
+        let mut idx = 0;
+        while idx < N {
+            if self.handlers[idx].is_none() {
+                self.handlers[idx] = Some((offset as u8, handler));
+                self.mask |= 1 << offset;
+                return Ok(self);
+            }
+            idx += 1;
+        }
+        Err(DispatchError::Full)
+    }
+
+    /// Dispatch the port's pending interrupts
+    ///
+    /// Clears the flags of the pending pins that have handlers, then calls the handlers.
+    /// A flag that sets again while a handler runs stays set for the next interrupt. Pins
+    /// without handlers keep their flags. Returns the pins that were dispatched.
+    ///
+    /// Call this from both of the port's combined interrupt handlers. A pin is dispatched
+    /// by the first call that sees its flag.
+    pub fn on_interrupt(&self) -> u32 {
+        let pending = interrupt_status(self.port) & self.mask;
+        if pending != 0 {
+            // Safety: we only clear the flags of pins that we handle
+            unsafe { clear_interrupt_status(self.port, pending) };
+            self.dispatch(pending);
+        }
+        pending
+    }
+
+    /// Call the handlers for the `pending` pins
+    fn dispatch(&self, pending: u32) {
+        self.handlers
+            .iter()
+            .flatten()
+            .filter(|(offset, _)| pending & (1 << offset) != 0)
+            .for_each(|&(offset, handler)| handler(offset as usize));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DispatchError, PortDispatcher};
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static CALLS: AtomicU32 = AtomicU32::new(0);
+
+    fn record(offset: usize) {
+        CALLS.fetch_or(1 << offset, Ordering::Relaxed);
+    }
+
+    fn other(_: usize) {}
+
+    #[test]
+    fn handlers() {
+        static DISPATCHER: PortDispatcher<3> =
+            PortDispatcher::new(2).with(0, record).with(16, record);
+        assert_eq!(DISPATCHER.mask, 1 | 1 << 16);
+
+        DISPATCHER.dispatch(1 << 16 | 1 << 5);
+        assert_eq!(CALLS.swap(0, Ordering::Relaxed), 1 << 16);
+
+        let mut dispatcher: PortDispatcher<2> = PortDispatcher::new(1);
+        assert_eq!(
+            dispatcher.register(32, record),
+            Err(DispatchError::InvalidPin)
+        );
+        assert_eq!(dispatcher.register(31, record), Ok(()));
+        assert_eq!(
+            dispatcher.register(31, other),
+            Err(DispatchError::Registered)
+        );
+        assert_eq!(dispatcher.register(7, other), Ok(()));
+        assert_eq!(dispatcher.register(8, other), Err(DispatchError::Full));
+        assert!(dispatcher.unregister(31).is_some());
+        assert!(dispatcher.unregister(31).is_none());
+        assert_eq!(dispatcher.mask, 1 << 7);
+        assert_eq!(dispatcher.register(8, other), Ok(()));
+    }
+}
@@ -0,0 +1,421 @@
+//! Simulated GPIOs for host-side tests
+//!
+//! Enable this module with the `"mock"` feature. It doesn't touch any registers, so it
+//! builds for any target.
+//!
+//! A [`Line`](struct.Line.html) is a simulated wire. Take a [`MockInput`](struct.MockInput.html),
+//! or a [`MockOutput`](struct.MockOutput.html), from a line, and hand it to code that's
+//! generic over the `embedded_hal` digital traits. The test drives the line with
+//! [`set_level_externally()`](struct.Line.html#method.set_level_externally), and inspects
+//! what outputs did with [`recorded_transitions()`](struct.Line.html#method.recorded_transitions).
+//!
+//! HAL utilities that are generic over the traits, like the
+//! [`Debounced`](../debounce/struct.Debounced.html) input, accept the mocks.
+//!
+//! To inspect a pin that isn't a mock, like a GPIO, or another driver's pin, wrap it in a
+//! [`Probe`](struct.Probe.html) with [`Line::probe()`](struct.Line.html#method.probe). A
+//! probe forwards to the pin, and its line records what the pin did. Probes work with any
+//! `InputPin`, `OutputPin`, `StatefulOutputPin`, or `ToggleableOutputPin`.
+//!
+//! # Example
+//!
+#![cfg_attr(feature = "mock", doc = "```")]
+#![cfg_attr(not(feature = "mock"), doc = "```ignore")]
+//! use core::convert::Infallible;
+//! use embedded_hal::digital::v2::{InputPin, OutputPin};
+//! use imxrt1060_hal::gpio::mock::Line;
+//!
+//! fn follow(
+//!     input: &impl InputPin<Error = Infallible>,
+//!     output: &mut impl OutputPin<Error = Infallible>,
+//! ) {
+//!     if input.is_high().unwrap() {
+//!         output.set_high().unwrap();
+//!     } else {
+//!         output.set_low().unwrap();
+//!     }
+//! }
+//!
+//! let button = Line::new(false);
+//! let led = Line::new(false);
+//! let (input, mut output) = (button.input(), led.output());
+//!
+//! follow(&input, &mut output);
+//! button.set_level_externally(true);
+//! follow(&input, &mut output);
+//! follow(&input, &mut output);
+//!
+//! assert!(led.level());
+//! assert_eq!(led.recorded_transitions().as_slice(), &[true]);
+//! ```
+
+use core::{cell::Cell, convert::Infallible};
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+/// Transitions that a [`Line`](struct.Line.html) records
+const CAPACITY: usize = 64;
+
+/// A simulated wire
+///
+/// A line has a level, and it records the level changes that its outputs make.
+pub struct Line {
+    level: Cell<bool>,
+    transitions: Cell<Transitions>,
+}
+
+/// The level changes that outputs made on a [`Line`](struct.Line.html)
+///
+/// Holds the first 64 changes. [`total()`](#method.total) counts every change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transitions {
+    levels: [bool; CAPACITY],
+    total: usize,
+}
+
+impl Transitions {
+    const fn new() -> Self {
+        Transitions {
+            levels: [false; CAPACITY],
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, level: bool) {
+        if let Some(slot) = self.levels.get_mut(self.total) {
+            *slot = level;
+        }
+        self.total += 1;
+    }
+
+    /// Returns the new level of each recorded change, in order
+    pub fn as_slice(&self) -> &[bool] {
+        &self.levels[..self.total.min(CAPACITY)]
+    }
+
+    /// Returns the number of changes, including the changes that weren't recorded
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl Line {
+    /// Create a line at `level`
+    pub const fn new(level: bool) -> Self {
+        Line {
+            level: Cell::new(level),
+            transitions: Cell::new(Transitions::new()),
+        }
+    }
+
+    /// Returns the line's level
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Drive the line from outside of the code under test
+    ///
+    /// This isn't a recorded transition.
+    pub fn set_level_externally(&self, level: bool) {
+        self.level.set(level);
+    }
+
+    /// Returns the level changes that outputs made
+    pub fn recorded_transitions(&self) -> Transitions {
+        self.transitions.get()
+    }
+
+    /// Forget the recorded transitions
+    pub fn clear_transitions(&self) {
+        self.transitions.set(Transitions::new());
+    }
+
+    /// Returns an input that reads the line
+    pub fn input(&self) -> MockInput<'_> {
+        MockInput { line: self }
+    }
+
+    /// Returns an output that drives the line
+    pub fn output(&self) -> MockOutput<'_> {
+        MockOutput { line: self }
+    }
+
+    /// Wrap `pin`, so that the line follows it
+    ///
+    /// Levels that a probed output sets are recorded transitions. Levels that a probed
+    /// input reads become the line's level, without a recorded transition.
+    pub fn probe<P>(&self, pin: P) -> Probe<'_, P> {
+        Probe { line: self, pin }
+    }
+
+    fn drive(&self, level: bool) {
+        if self.level.replace(level) != level {
+            let mut transitions = self.transitions.get();
+            transitions.push(level);
+            self.transitions.set(transitions);
+        }
+    }
+}
+
+/// A simulated input
+pub struct MockInput<'a> {
+    line: &'a Line,
+}
+
+/// A simulated output
+pub struct MockOutput<'a> {
+    line: &'a Line,
+}
+
+/// A pin that a [`Line`](struct.Line.html) follows
+///
+/// Create one with [`Line::probe()`](struct.Line.html#method.probe). A probe has the
+/// digital traits of the pin that it wraps. Each call goes to the pin, and the line
+/// follows the results. Errors pass through, and they don't change the line.
+///
+#[cfg_attr(feature = "mock", doc = "```")]
+#[cfg_attr(not(feature = "mock"), doc = "```ignore")]
+/// use embedded_hal::digital::v2::ToggleableOutputPin;
+/// use imxrt1060_hal::gpio::mock::Line;
+///
+/// fn blink<P: ToggleableOutputPin>(led: &mut P) -> Result<(), P::Error> {
+///     led.toggle()?;
+///     led.toggle()
+/// }
+///
+/// // Any ToggleableOutputPin; a mock output here, a GPIO on hardware
+/// let led = Line::new(false);
+/// let probe = Line::new(false);
+/// let mut pin = probe.probe(led.output());
+///
+/// blink(&mut pin).unwrap();
+/// assert_eq!(probe.recorded_transitions().as_slice(), &[true, false]);
+/// ```
+pub struct Probe<'a, P> {
+    line: &'a Line,
+    pin: P,
+}
+
+impl<P> Probe<'_, P> {
+    /// Returns the probed pin
+    pub fn release(self) -> P {
+        self.pin
+    }
+}
+
+impl MockInput<'_> {
+    /// Returns `true` if the line is high
+    pub fn is_set(&self) -> bool {
+        self.line.level()
+    }
+}
+
+impl MockOutput<'_> {
+    /// Set the line high
+    pub fn set(&mut self) {
+        self.line.drive(true);
+    }
+
+    /// Set the line low
+    pub fn clear(&mut self) {
+        self.line.drive(false);
+    }
+
+    /// Returns `true` if the line is high
+    pub fn is_set(&self) -> bool {
+        self.line.level()
+    }
+
+    /// Alternate the level of the line
+    pub fn toggle(&mut self) {
+        self.line.drive(!self.line.level());
+    }
+}
+
+impl InputPin for MockInput<'_> {
+    type Error = Infallible;
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|res| !res)
+    }
+}
+
+impl OutputPin for MockOutput<'_> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set();
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.clear();
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for MockOutput<'_> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set())
+    }
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.is_set_high().map(|res| !res)
+    }
+}
+
+impl ToggleableOutputPin for MockOutput<'_> {
+    type Error = Infallible;
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        MockOutput::toggle(self);
+        Ok(())
+    }
+}
+
+impl<P: InputPin> InputPin for Probe<'_, P> {
+    type Error = P::Error;
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let high = self.pin.is_high()?;
+        self.line.set_level_externally(high);
+        Ok(high)
+    }
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.is_high().map(|res| !res)
+    }
+}
+
+impl<P: OutputPin> OutputPin for Probe<'_, P> {
+    type Error = P::Error;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()?;
+        self.line.drive(true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()?;
+        self.line.drive(false);
+        Ok(())
+    }
+}
+
+impl<P: StatefulOutputPin> StatefulOutputPin for Probe<'_, P> {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_high()
+    }
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        self.pin.is_set_low()
+    }
+}
+
+impl<P: ToggleableOutputPin> ToggleableOutputPin for Probe<'_, P> {
+    type Error = P::Error;
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        self.pin.toggle()?;
+        self.line.drive(!self.line.level());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Line, CAPACITY};
+    use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+
+    /// An output that fails every other write
+    struct Flaky {
+        level: bool,
+        fail: bool,
+    }
+
+    impl OutputPin for Flaky {
+        type Error = ();
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.fail = !self.fail;
+            if self.fail {
+                return Err(());
+            }
+            self.level = true;
+            Ok(())
+        }
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.level = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transitions() {
+        let line = Line::new(false);
+        let mut output = line.output();
+        output.clear();
+        output.set();
+        output.set();
+        line.set_level_externally(false);
+        output.toggle();
+        output.clear();
+        assert_eq!(line.recorded_transitions().as_slice(), &[true, true, false]);
+
+        line.clear_transitions();
+        for _ in 0..CAPACITY + 3 {
+            output.toggle();
+        }
+        let transitions = line.recorded_transitions();
+        assert_eq!(transitions.total(), CAPACITY + 3);
+        assert_eq!(transitions.as_slice().len(), CAPACITY);
+        assert!(transitions.as_slice()[0]);
+        assert!(line.input().is_set());
+    }
+
+    #[test]
+    fn probe_outputs() {
+        let led = Line::new(false);
+        let probe = Line::new(false);
+        let mut pin = probe.probe(led.output());
+
+        pin.set_high().unwrap();
+        pin.set_high().unwrap();
+        ToggleableOutputPin::toggle(&mut pin).unwrap();
+        ToggleableOutputPin::toggle(&mut pin).unwrap();
+        pin.set_low().unwrap();
+        assert!(pin.is_set_low().unwrap());
+
+        assert_eq!(
+            probe.recorded_transitions().as_slice(),
+            &[true, false, true, false]
+        );
+        assert_eq!(led.recorded_transitions(), probe.recorded_transitions());
+        assert!(!pin.release().is_set());
+    }
+
+    #[test]
+    fn probe_inputs() {
+        let button = Line::new(false);
+        let probe = Line::new(true);
+        let pin = probe.probe(button.input());
+
+        assert!(pin.is_low().unwrap());
+        assert!(!probe.level());
+        button.set_level_externally(true);
+        assert!(pin.is_high().unwrap());
+        assert!(probe.level());
+        assert_eq!(probe.recorded_transitions().total(), 0);
+    }
+
+    #[test]
+    fn probe_errors() {
+        let probe = Line::new(false);
+        let mut pin = probe.probe(Flaky {
+            level: false,
+            fail: false,
+        });
+
+        assert!(pin.set_high().is_err());
+        assert!(!probe.level());
+        pin.set_high().unwrap();
+        assert!(probe.level());
+        assert!(pin.release().level);
+        assert_eq!(probe.recorded_transitions().as_slice(), &[true]);
+    }
+}
@@ -95,7 +95,7 @@
 //!     imxrt1060_hal::ccm::PLL1::ARM_HZ,
 //!     &mut peripherals.ccm.handle,
 //!     &mut peripherals.dcdc,
-//! );
+//! ).unwrap();
 //!
 //! let mut cfg = peripherals.ccm.perclk.configure(
 //!     &mut peripherals.ccm.handle,
@@ -118,6 +118,43 @@
 //! );
 //! ```
 //!
+//! # Clock sources
+//!
+//! [`clock()`](struct.Unclocked.html#method.clock) runs the GPT from PERCLK. To run the
+//! GPT from another source, use [`clock_source()`](struct.Unclocked.html#method.clock_source)
+//! with a [`ClockSource`](enum.ClockSource.html). The 32.768KHz and crystal sources don't
+//! depend on PERCLK, so a GPT that uses them keeps counting while the PLLs are gated.
+//!
+//! Wake from WAIT after 5 seconds, using GPT2 and the 32.768KHz clock:
+//!
+//! ```no_run
+//! use imxrt1060_hal::{ccm::low_power, gpt, ral::interrupt};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut gpr = imxrt1060_hal::iomuxc::GPR::take().unwrap();
+//! let mut core = cortex_m::Peripherals::take().unwrap();
+//!
+//! let mut gpt2 = peripherals
+//!     .gpt2
+//!     .clock_source(&mut peripherals.ccm.handle, gpt::ClockSource::LowFrequency32k)
+//!     .map_err(|(_, err)| err)
+//!     .unwrap();
+//! assert_eq!(gpt2.frequency().hz(), 32_768);
+//! gpt2.set_wait_mode_enable(true);
+//! gpt2.set_output_interrupt_on_compare(gpt::OutputCompareRegister::One, true);
+//! gpt2.set_output_compare_duration(
+//!     gpt::OutputCompareRegister::One,
+//!     core::time::Duration::from_secs(5),
+//! );
+//! gpt2.set_enable(true);
+//! unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::GPT2) };
+//!
+//! let reason = cortex_m::interrupt::free(|_| {
+//!     low_power::enter_wait(&mut peripherals.ccm.handle, &mut gpr, &mut core.SCB)
+//! });
+//! assert_eq!(reason, low_power::WakeReason::Interrupt(interrupt::GPT2 as u8));
+//! ```
+//!
 //! # TODO
 //!
 //! - Input capture. Each GPT can capture the value of the counter
@@ -128,7 +165,7 @@
 //!   pin.
 
 use crate::{
-    ccm::{perclk, ticks},
+    ccm::{self, perclk, ticks},
     ral,
 };
 
@@ -171,6 +208,9 @@ pub struct GPT {
     ///
     /// The value accounts for all prescalers and dividers.
     clock_hz: u32,
+    /// The GPT's clock source
+    source: ClockSource,
+    instance: Instance,
 }
 
 impl Unclocked {
@@ -238,6 +278,136 @@ impl Unclocked {
         GPT {
             registers: self.registers,
             clock_hz: (freq / div).0 / DEFAULT_PRESCALER,
+            source: ClockSource::Perclk,
+            instance: self.instance,
+        }
+    }
+
+    /// Enable the clocks to the GPT, and run the counter from `source`
+    ///
+    /// The PERCLK sources use the PERCLK frequency that's configured when you call
+    /// `clock_source()`. To change the source of a running GPT,
+    /// [`disable()`](struct.GPT.html#method.disable) the GPT, then clock it again.
+    ///
+    /// Returns the unclocked GPT, and an error, if `source` is invalid.
+    pub fn clock_source(
+        self,
+        handle: &mut ccm::Handle,
+        source: ClockSource,
+    ) -> Result<GPT, (Self, ClockSourceError)> {
+        let clock_hz = match source.frequency(handle) {
+            Ok(hz) => hz.0,
+            Err(err) => return Err((self, err)),
+        };
+        match self.instance {
+            Instance::One => handle.gate::<ccm::clock_gate::Gpt1>(ccm::GateSetting::RunAndWait),
+            Instance::Two => handle.gate::<ccm::clock_gate::Gpt2>(ccm::GateSetting::RunAndWait),
+        }
+
+        match source {
+            ClockSource::Perclk | ClockSource::HighFrequencyReference => {
+                let clksrc = match source {
+                    ClockSource::Perclk => 0b001,
+                    _ => 0b010,
+                };
+                ral::write_reg!(ral::gpt, self.registers, CR, EN_24M: 0, CLKSRC: clksrc);
+                // Same divide by two as clock(), so that both paths agree on the
+                // PERCLK tick frequency.
+                ral::write_reg!(ral::gpt, self.registers, PR, PRESCALER: (DEFAULT_PRESCALER - 1));
+            }
+            ClockSource::LowFrequency32k => {
+                ral::write_reg!(ral::gpt, self.registers, CR, EN_24M: 0, CLKSRC: 0b100);
+                ral::write_reg!(ral::gpt, self.registers, PR, PRESCALER: 0);
+            }
+            ClockSource::Crystal { divider } => {
+                ral::write_reg!(ral::gpt, self.registers, CR, EN_24M: 1, CLKSRC: 0b101);
+                ral::write_reg!(ral::gpt, self.registers, PR, PRESCALER24M: (divider as u32 - 1));
+            }
+        }
+        ral::write_reg!(ral::gpt, self.registers, SR, 0b11_1111);
+
+        Ok(GPT {
+            registers: self.registers,
+            clock_hz,
+            source,
+            instance: self.instance,
+        })
+    }
+
+    /// Enable the clocks to the GPT, and run the counter from the 32.768KHz
+    /// low-frequency clock
+    ///
+    /// The low-frequency clock keeps running in STOP mode. Combine it with
+    /// [`set_stop_mode_enable()`](struct.GPT.html#method.set_stop_mode_enable) to wake
+    /// the processor from STOP. See the [`low_power`](../ccm/low_power/index.html)
+    /// module for an example.
+    pub fn clock_low_frequency(self, handle: &mut ccm::Handle) -> GPT {
+        match self.clock_source(handle, ClockSource::LowFrequency32k) {
+            Ok(gpt) => gpt,
+            // The low-frequency source is always valid
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+/// The low-frequency reference clock
+const LOW_FREQUENCY_HZ: u32 = 32_768;
+
+/// Largest crystal oscillator divider
+pub const MAX_CRYSTAL_DIVIDER: u8 = 16;
+
+/// GPT clock sources
+///
+/// Use a clock source with [`clock_source()`](struct.Unclocked.html#method.clock_source).
+/// The low-frequency and crystal sources don't depend on PERCLK, so they keep counting
+/// when PERCLK's source is gated in WAIT mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// PERCLK, through the GPT's peripheral clock input, divided by 2
+    Perclk,
+    /// PERCLK, through the GPT's high-frequency reference input, divided by 2
+    HighFrequencyReference,
+    /// The 32.768KHz low-frequency clock
+    ///
+    /// The low-frequency clock keeps running in STOP mode.
+    LowFrequency32k,
+    /// The 24MHz crystal oscillator, divided by `divider`
+    ///
+    /// `divider` is from 2 to [`MAX_CRYSTAL_DIVIDER`](constant.MAX_CRYSTAL_DIVIDER.html).
+    /// The GPT doesn't count if its crystal prescaler is zero, so the divider can't be 1.
+    Crystal {
+        /// Crystal oscillator divider
+        divider: u8,
+    },
+}
+
+/// Errors when selecting a GPT clock source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSourceError {
+    /// The crystal oscillator divider is out of range
+    CrystalDivider,
+}
+
+impl ClockSource {
+    /// Returns the GPT tick frequency for this source
+    ///
+    /// Returns an error if the source is invalid.
+    pub fn frequency(&self, handle: &ccm::Handle) -> Result<ccm::Frequency, ClockSourceError> {
+        self.tick_hz(|| handle.frequencies().perclk.hz())
+            .map(ccm::Frequency)
+    }
+
+    /// Returns the tick frequency, given a way to read the PERCLK frequency
+    fn tick_hz(&self, perclk_hz: impl FnOnce() -> u32) -> Result<u32, ClockSourceError> {
+        match *self {
+            ClockSource::Perclk | ClockSource::HighFrequencyReference => {
+                Ok(perclk_hz() / DEFAULT_PRESCALER)
+            }
+            ClockSource::LowFrequency32k => Ok(LOW_FREQUENCY_HZ),
+            ClockSource::Crystal { divider } if (2..=MAX_CRYSTAL_DIVIDER).contains(&divider) => {
+                Ok(ccm::OSCILLATOR_FREQUENCY.hz() / divider as u32)
+            }
+            ClockSource::Crystal { .. } => Err(ClockSourceError::CrystalDivider),
         }
     }
 }
@@ -266,6 +436,87 @@ pub enum Mode {
 }
 
 impl GPT {
+    /// Disable the GPT, gate its clocks, and return the unclocked GPT
+    ///
+    /// Use `clock()` to clock the GPT again.
+    pub fn disable(self, handle: &mut ccm::Handle) -> Unclocked {
+        ral::modify_reg!(ral::gpt, self.registers, CR, EN: 0);
+        match self.instance {
+            Instance::One => handle.gate::<ccm::clock_gate::Gpt1>(ccm::GateSetting::Off),
+            Instance::Two => handle.gate::<ccm::clock_gate::Gpt2>(ccm::GateSetting::Off),
+        }
+        Unclocked {
+            registers: self.registers,
+            instance: self.instance,
+        }
+    }
+
+    /// Use the PERCLK frequency from a PERCLK change
+    ///
+    /// `reclock()` pauses the counter, selects the new clock source, and scales the
+    /// output compare registers so that they keep their timeouts. In free running mode,
+    /// `reclock()` scales the distance from the current count to each compare value.
+    /// `reclock()` does nothing if the GPT doesn't run from PERCLK. See
+    /// [`perclk::reconfigure()`](../ccm/perclk/fn.reconfigure.html) for more
+    /// information.
+    pub fn reclock(&mut self, changed: &perclk::PerclkChanged) {
+        match self.source {
+            ClockSource::Perclk | ClockSource::HighFrequencyReference => {}
+            ClockSource::LowFrequency32k | ClockSource::Crystal { .. } => return,
+        }
+        let enabled = self.enabled();
+        self.set_enable(false);
+
+        // The high-frequency reference input keeps its PERCLK selection
+        if ClockSource::Perclk == self.source {
+            match changed.clock_selection() {
+                perclk::CLKSEL::OSC => {
+                    ral::modify_reg!(ral::gpt, self.registers, CR, EN_24M: 1, CLKSRC: 0b101);
+                    ral::write_reg!(ral::gpt, self.registers, PR, PRESCALER24M: (DEFAULT_PRESCALER - 1));
+                }
+                perclk::CLKSEL::IPG(_) => {
+                    ral::modify_reg!(ral::gpt, self.registers, CR, EN_24M: 0, CLKSRC: 0b001);
+                    ral::write_reg!(ral::gpt, self.registers, PR, PRESCALER: (DEFAULT_PRESCALER - 1));
+                }
+            }
+        }
+
+        let count = self.count();
+        let mode = self.mode();
+        for &output in &[
+            OutputCompareRegister::One,
+            OutputCompareRegister::Two,
+            OutputCompareRegister::Three,
+        ] {
+            let compare = self.output_compare_count(output);
+            let compare = match mode {
+                Mode::Reset => changed.rescale(compare),
+                Mode::FreeRunning => {
+                    count.wrapping_add(changed.rescale(compare.wrapping_sub(count)))
+                }
+            };
+            self.set_output_compare_count(output, compare);
+        }
+
+        self.clock_hz = changed.new_hz() / DEFAULT_PRESCALER;
+        self.set_enable(enabled);
+    }
+
+    /// Returns the GPT's clock source
+    pub fn clock_source(&self) -> ClockSource {
+        self.source
+    }
+
+    /// Returns the GPT tick frequency
+    pub fn frequency(&self) -> ccm::Frequency {
+        ccm::Frequency(self.clock_hz)
+    }
+
+    /// Returns the duration of `counts` GPT ticks
+    fn counts_duration(&self, counts: u32) -> Duration {
+        Duration::from_nanos(counts as u64 * 1_000_000_000 / self.clock_hz as u64)
+    }
+
     /// Returns the current mode of the GPT
     pub fn mode(&self) -> Mode {
         if ral::read_reg!(ral::gpt, self.registers, CR, FRR == 0) {
@@ -318,6 +569,19 @@ impl GPT {
         ral::read_reg!(ral::gpt, self.registers, CR, WAITEN == 1)
     }
 
+    /// Allow the GPT to run in stop mode; or, prevent the GPT from running
+    /// in stop mode.
+    ///
+    /// The GPT only counts in stop mode if it runs from the low-frequency clock.
+    pub fn set_stop_mode_enable(&mut self, stop: bool) {
+        ral::modify_reg!(ral::gpt, self.registers, CR, STOPEN: (stop as u32));
+    }
+
+    /// Indicates if the GPT runs while in stop mode
+    pub fn stop_mode_enabled(&self) -> bool {
+        ral::read_reg!(ral::gpt, self.registers, CR, STOPEN == 1)
+    }
+
     /// Enable the GPT interrupt when the output compares
     pub fn set_output_interrupt_on_compare(&mut self, output: OutputCompareRegister, intr: bool) {
         let ir: u32 = ral::read_reg!(ral::gpt, self.registers, IR);
@@ -405,7 +669,7 @@ impl GPT {
         let result = act();
         let end = self.count();
         let counts = end.wrapping_sub(start);
-        (result, counts * self.clock_period())
+        (result, self.counts_duration(counts))
     }
 
     /// Time an operation, returning the result, and the amount of time the
@@ -455,7 +719,7 @@ impl GPT {
             (result, None)
         } else {
             let counts = end.wrapping_sub(start);
-            (result, Some(counts * self.clock_period()))
+            (result, Some(self.counts_duration(counts)))
         }
     }
 
@@ -625,6 +889,22 @@ impl<'a> embedded_hal::timer::CountDown for CountDown<'a> {
     }
 }
 
+/// The output compare register keeps its count after the timer expires,
+/// and it matches again when the counter wraps. Observe each expiry with
+/// `is_expired()`, or `cancel()` the timer.
+impl<'a> crate::timer::OneShot for CountDown<'a> {
+    fn start(&mut self, duration: Duration) {
+        self.0.gpt.output_compare_status(self.0.output).clear();
+        self.0.start(duration)
+    }
+    fn cancel(&mut self) {
+        self.0.gpt.output_compare_status(self.0.output).clear();
+    }
+    fn is_expired(&mut self) -> bool {
+        self.0.wait().is_ok()
+    }
+}
+
 /// Adapter that implements [ther `Periodic` trait][docs].
 ///
 /// It mutably borrows the GPT, and it uses the supplied output
@@ -648,3 +928,33 @@ impl<'a> embedded_hal::timer::CountDown for Periodic<'a> {
 }
 
 impl<'a> embedded_hal::timer::Periodic for Periodic<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClockSource, ClockSourceError};
+
+    #[test]
+    fn tick_frequencies() {
+        let perclk = || 75_000_000;
+        assert_eq!(ClockSource::Perclk.tick_hz(perclk), Ok(37_500_000));
+        assert_eq!(
+            ClockSource::HighFrequencyReference.tick_hz(perclk),
+            Ok(37_500_000)
+        );
+        assert_eq!(ClockSource::LowFrequency32k.tick_hz(perclk), Ok(32_768));
+        assert_eq!(
+            ClockSource::Crystal { divider: 2 }.tick_hz(perclk),
+            Ok(12_000_000)
+        );
+        assert_eq!(
+            ClockSource::Crystal { divider: 16 }.tick_hz(perclk),
+            Ok(1_500_000)
+        );
+        for &divider in &[0, 1, 17] {
+            assert_eq!(
+                ClockSource::Crystal { divider }.tick_hz(perclk),
+                Err(ClockSourceError::CrystalDivider)
+            );
+        }
+    }
+}
@@ -31,10 +31,33 @@
 //! i2c3.write_read(MY_SLAVE_ADDRESS, &output, &mut input).unwrap();
 //! ```
 
+#[cfg(feature = "async")]
+pub mod asynch;
+mod dma_transfer;
+#[cfg(feature = "embedded-hal-1")]
+mod eh1;
+mod probe;
+mod recovery;
+mod retry;
+mod stretching;
+mod target;
+mod timeout;
+mod timing;
+mod transaction;
+
+pub use dma_transfer::DmaError;
+pub use probe::{is_reserved, ProbeMethod, Scan};
+pub use recovery::{RecoveryError, RecoveryTiming};
+pub use retry::Retries;
+pub use stretching::Stretching;
+pub use target::{Event, Target, TargetAddressError};
+pub use timeout::Phase;
+pub use timing::ClockTiming;
+
 use crate::iomuxc::consts::{Unsigned, U1, U2, U3, U4};
 
 use crate::ccm;
-use crate::iomuxc::i2c;
+use crate::iomuxc::{gpio, i2c};
 use crate::ral;
 use core::marker::PhantomData;
 use embedded_hal::blocking;
@@ -77,6 +100,32 @@ impl Unclocked {
     }
 }
 
+/// An I2C with a gated clock
+///
+/// Get a `Disabled` I2C from [`I2C::disable()`](struct.I2C.html#method.disable). Use
+/// `enable()` to clock the I2C again.
+pub struct Disabled<M> {
+    _module: PhantomData<M>,
+    reg: ral::lpi2c::Instance,
+    source_clock: ccm::Frequency,
+}
+
+impl<M> Disabled<M>
+where
+    M: Unsigned,
+{
+    /// Ungate the I2C's clock, and return a builder
+    ///
+    /// The I2C keeps the root clock that it had before it was disabled.
+    pub fn enable(self, handle: &mut ccm::Handle) -> Builder<M> {
+        handle.set_gates(
+            ccm::clock_gate::LPI2C[M::USIZE - 1],
+            ccm::GateSetting::RunAndWait,
+        );
+        Builder::new(self.source_clock, self.reg)
+    }
+}
+
 /// An I2C builder that can build and I2C peripheral
 pub struct Builder<M> {
     _module: PhantomData<M>,
@@ -100,6 +149,9 @@ where
 
     /// Builds an I2C peripheral from the SCL and SDA pins. The return
     /// is a configured I2C master running at 100KHz.
+    ///
+    /// The I2C can't [recover the bus](struct.I2C.html#method.recover_bus). To recover
+    /// the bus, use [`build_recoverable()`](#method.build_recoverable).
     pub fn build<SCL, SDA>(self, mut scl: SCL, mut sda: SDA) -> I2C<M>
     where
         SCL: i2c::Pin<Module = M, Signal = i2c::SCL>,
@@ -107,19 +159,61 @@ where
     {
         crate::iomuxc::i2c::prepare(&mut scl);
         crate::iomuxc::i2c::prepare(&mut sda);
-        I2C::new(self.source_clock, self.reg)
+        I2C::new(self.source_clock, self.reg, None)
+    }
+
+    /// Builds an I2C peripheral that can recover its bus from the SCL and SDA pins
+    ///
+    /// Like [`build()`](#method.build), the I2C runs at 100KHz. The I2C keeps the pins,
+    /// so that it can [recover the bus](struct.I2C.html#method.recover_bus) by using them
+    /// as GPIOs.
+    pub fn build_recoverable<SCL, SDA>(self, mut scl: SCL, mut sda: SDA) -> I2C<M>
+    where
+        SCL: i2c::Pin<Module = M, Signal = i2c::SCL> + gpio::Pin,
+        SDA: i2c::Pin<Module = M, Signal = i2c::SDA> + gpio::Pin,
+    {
+        crate::iomuxc::i2c::prepare(&mut scl);
+        crate::iomuxc::i2c::prepare(&mut sda);
+        let pads = (
+            recovery::BusPad::new(&mut scl),
+            recovery::BusPad::new(&mut sda),
+        );
+        I2C::new(self.source_clock, self.reg, Some(pads))
+    }
+
+    /// Builds an I2C target from the SCL and SDA pins, answering the 7-bit `address`
+    ///
+    /// The target responds to a controller, instead of driving the bus. Returns an
+    /// error if `address` isn't a 7-bit address.
+    pub fn build_target<SCL, SDA>(
+        self,
+        mut scl: SCL,
+        mut sda: SDA,
+        address: u8,
+    ) -> Result<Target<M>, TargetAddressError>
+    where
+        SCL: i2c::Pin<Module = M, Signal = i2c::SCL>,
+        SDA: i2c::Pin<Module = M, Signal = i2c::SDA>,
+    {
+        crate::iomuxc::i2c::prepare(&mut scl);
+        crate::iomuxc::i2c::prepare(&mut sda);
+        Target::new(self.source_clock, self.reg, address)
     }
 }
 
 /// I2C Clock speed
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ClockSpeed {
-    /// 100 KHz
+    /// 100 KHz, Standard-mode
     KHz100,
-    /// 400 KHz
+    /// 400 KHz, Fast-mode
     KHz400,
-    /// 1 MHz
+    /// 1 MHz, Fast-mode Plus
     MHz1,
+    /// Any speed up to 1 MHz, in Hz
+    ///
+    /// The timing meets the specification of the slowest mode that supports the speed.
+    Custom(u32),
 }
 
 impl Default for ClockSpeed {
@@ -129,104 +223,14 @@ impl Default for ClockSpeed {
 }
 
 impl ClockSpeed {
-    /// Sets the clock speed parameters
-    ///
-    /// # Safety
-    ///
-    /// The function touches I2C registers that should only be touched
-    /// while the I2C master is disabled.
-    unsafe fn set(self, source_clock: ccm::Frequency, reg: &ral::lpi2c::Instance) {
-        // Baud rate = (source_clock/2^prescale)/(CLKLO+1+CLKHI+1 + FLOOR((2+FILTSCL)/2^prescale)
-        // Assume CLKLO = 2*CLKHI, SETHOLD = CLKHI, DATAVD = CLKHI/2, FILTSCL = FILTSDA = 0,
-        // and that risetime is negligible (less than 1 cycle).
-        use core::cmp;
-        use ral::lpi2c::MCFGR1::PRESCALE::RW::*;
-
-        log::debug!(
-            "I2C baud rate = {:?}, source clock = {:?}",
-            self,
-            source_clock
-        );
-        let source_clock = source_clock.0;
-        const PRESCALARS: [u32; 8] = [
-            PRESCALE_0, PRESCALE_1, PRESCALE_2, PRESCALE_3, PRESCALE_4, PRESCALE_5, PRESCALE_6,
-            PRESCALE_7,
-        ];
-
-        struct ByError {
-            prescalar: u32,
-            clkhi: u32,
-            error: u32,
-            computed_rate: u32,
+    /// Returns the clock speed, in Hz
+    pub fn hz(self) -> u32 {
+        match self {
+            ClockSpeed::KHz100 => 100_000,
+            ClockSpeed::KHz400 => 400_000,
+            ClockSpeed::MHz1 => 1_000_000,
+            ClockSpeed::Custom(hz) => hz,
         }
-
-        let baud_rate: u32 = match self {
-            Self::KHz100 => 100_000,
-            Self::KHz400 => 400_000,
-            Self::MHz1 => 1_000_000,
-        };
-
-        // prescale = 1, 2, 4, 8, ... 128
-        // divider = 2 ^ prescale
-        let dividers = PRESCALARS.iter().copied().map(|prescalar| 1 << prescalar);
-        let clkhis = 1u32..32u32;
-        // possibilities = every divider with every clkhi (8 * 30 == 240 possibilities)
-        let possibilities =
-            dividers.flat_map(|divider| core::iter::repeat(divider).zip(clkhis.clone()));
-        let errors = possibilities.map(|(divider, clkhi)| {
-            let computed_rate = if 1 == clkhi {
-                // See below for justification on magic numbers.
-                // In the 1 == clkhi case, the + 3 is the minimum allowable CLKLO value
-                // + 1 is CLKHI itself
-                (source_clock / divider) / ((1 + 3 + 2) + 2 / divider)
-            } else {
-                // CLKLO = 2 * CLKHI, allows us to do 3 * CLKHI
-                // + 2 accounts for the CLKLOW + 1 and CLKHI + 1
-                // + 2 accounts for the FLOOR((2 + FILTSCL)) factor
-                (source_clock / divider) / ((3 * clkhi + 2) + 2 / divider)
-            };
-            let error = cmp::max(computed_rate, baud_rate) - cmp::min(computed_rate, baud_rate);
-            ByError {
-                prescalar: divider.saturating_sub(1).count_ones(),
-                clkhi, /* (1..32) in u8 range */
-                error,
-                computed_rate,
-            }
-        });
-
-        let ByError {
-            prescalar,
-            clkhi,
-            error,
-            computed_rate,
-        } = errors.min_by(|lhs, rhs| lhs.error.cmp(&rhs.error)).unwrap();
-
-        let (clklo, sethold, datavd) = if clkhi < 2 {
-            (3, 2, 1)
-        } else {
-            (clkhi * 2, clkhi, clkhi / 2)
-        };
-
-        log::debug!(
-            "COMPUTED_RATE = {}, ERROR = {}, PRESCALAR = {}, CLKHI = {}, CLKLO = {}, SETHOLD = {}, DAVAVD = {}",
-            computed_rate,
-            error,
-            prescalar,
-            clkhi,
-            clklo,
-            sethold,
-            datavd
-        );
-        ral::write_reg!(
-            ral::lpi2c,
-            reg,
-            MCCR0,
-            CLKHI: clkhi,
-            CLKLO: clklo,
-            SETHOLD: sethold,
-            DATAVD: datavd
-        );
-        ral::write_reg!(ral::lpi2c, reg, MCFGR1, PRESCALE: prescalar);
     }
 }
 
@@ -239,6 +243,25 @@ pub struct I2C<M> {
     _module: PhantomData<M>,
     /// LPI2C effective input clock frequency
     source_clock: ccm::Frequency,
+    /// The I2C master clock speed
+    clock_speed: ClockSpeed,
+    /// The SCL and SDA pads, for bus recovery
+    pads: Option<(recovery::BusPad, recovery::BusPad)>,
+    recovery: RecoveryTiming,
+    /// Polls before a wait times out
+    timeout_polls: u32,
+    /// The core clock, for the stretching measurements
+    core_hz: u32,
+    /// The waits of the blocking transfers
+    stretching: stretching::Observer,
+    /// When a blocking transaction tries again
+    retries: Retries,
+    /// The attempts of the last blocking transaction
+    attempts: u16,
+    /// The glitch filter width, or `None` for the specification's width
+    glitch_filter: Option<core::time::Duration>,
+    /// The commands of the DMA transfer, if there's a DMA transfer
+    dma: Option<dma_transfer::DmaCommands>,
 }
 
 /// Indicates an error when computing the parameters that control
@@ -254,26 +277,58 @@ pub struct PinLowTimeoutError(());
 #[derive(Debug)]
 pub struct BusIdleTimeoutError(());
 
-const RETRIES: usize = 100_000;
-
 impl<M> I2C<M>
 where
     M: Unsigned,
 {
-    fn new(source_clock: ccm::Frequency, reg: ral::lpi2c::Instance) -> Self {
+    fn new(
+        source_clock: ccm::Frequency,
+        reg: ral::lpi2c::Instance,
+        pads: Option<(recovery::BusPad, recovery::BusPad)>,
+    ) -> Self {
         let mut i2c = I2C {
             reg,
             _module: PhantomData,
             source_clock,
+            clock_speed: ClockSpeed::KHz100,
+            pads,
+            recovery: RecoveryTiming::default(),
+            timeout_polls: timeout::DEFAULT_POLLS,
+            core_hz: stretching::DEFAULT_CORE_HZ,
+            stretching: stretching::Observer::default(),
+            retries: Retries::default(),
+            attempts: 0,
+            glitch_filter: None,
+            dma: None,
         };
         ral::write_reg!(ral::lpi2c, i2c.reg, MCR, RST: RST_1);
 
         // Enables I2C master
-        i2c.set_clock_speed(ClockSpeed::KHz100).unwrap();
+        if i2c.set_clock_speed(ClockSpeed::KHz100).is_err() {
+            log::warn!(
+                "I2C{} can't run at 100KHz from {:?}",
+                M::USIZE,
+                source_clock
+            );
+        }
         ral::write_reg!(ral::lpi2c, i2c.reg, MFCR, RXWATER: 0b01, TXWATER: 0b01);
         i2c
     }
 
+    /// Disable the I2C master, gate its clock, and return the disabled I2C
+    ///
+    /// Finish, or abort, the transactions first; a transaction that's interrupted
+    /// here may leave the bus busy.
+    pub fn disable(self, handle: &mut ccm::Handle) -> Disabled<M> {
+        ral::write_reg!(ral::lpi2c, self.reg, MCR, MEN: MEN_0);
+        handle.set_gates(ccm::clock_gate::LPI2C[M::USIZE - 1], ccm::GateSetting::Off);
+        Disabled {
+            _module: PhantomData,
+            reg: self.reg,
+            source_clock: self.source_clock,
+        }
+    }
+
     fn with_master_disabled<F: FnMut() -> R, R>(&self, mut act: F) -> R {
         // Note that we should really specify the 'instance module'. This approach
         // assumes that the reset values for all instance modules are the same, which
@@ -288,41 +343,82 @@ where
         res
     }
 
-    /// Set the I2C master clock speed
-    pub fn set_clock_speed(&mut self, clock_speed: ClockSpeed) -> Result<(), ClockSpeedError> {
+    /// Set the I2C master clock speed, and return the achieved SCL frequency, in Hz
+    ///
+    /// The SCL timing meets the I2C specification, and the achieved frequency doesn't
+    /// exceed `clock_speed`. See [`ClockTiming`](struct.ClockTiming.html) for the
+    /// details. If there's no such timing, returns an error, and the clock speed
+    /// doesn't change.
+    ///
+    /// The prescaler may change, which scales the pin low, and bus idle, timeouts. Set
+    /// them after the clock speed. A [glitch filter](#method.set_glitch_filter) stays
+    /// in effect.
+    pub fn set_clock_speed(&mut self, clock_speed: ClockSpeed) -> Result<u32, ClockSpeedError> {
+        let timing = match self.glitch_filter {
+            Some(width) => ClockTiming::with_glitch_filter(self.source_clock.0, clock_speed, width),
+            None => ClockTiming::new(self.source_clock.0, clock_speed),
+        }?;
+        log::debug!(
+            "I2C{} clock speed = {:?}, source clock = {:?}, {:?}",
+            M::USIZE,
+            clock_speed,
+            self.source_clock,
+            timing
+        );
+        self.clock_speed = clock_speed;
         self.with_master_disabled(|| unsafe {
             // Safety: master is disabled
-            clock_speed.set(self.source_clock, &self.reg);
-            Ok(())
-        })
+            timing.set(&self.reg);
+        });
+        Ok(timing.achieved_hz)
+    }
+
+    /// Set the SCL and SDA glitch filters, and return the achieved filter width
+    ///
+    /// The filters ignore pulses up to `width`, counted in LPI2C root clock cycles, and
+    /// rounded up to the next cycle. The achieved width is at most 15 cycles. Without a
+    /// glitch filter, the filter width follows the I2C specification for the clock
+    /// speed.
+    ///
+    /// The filters delay SCL, so this computes the clock speed's timing again. If
+    /// there's no timing for the filter, returns an error, and the filters don't
+    /// change.
+    pub fn set_glitch_filter(
+        &mut self,
+        width: core::time::Duration,
+    ) -> Result<core::time::Duration, ClockSpeedError> {
+        let previous = self.glitch_filter.replace(width);
+        if let Err(err) = self.set_clock_speed(self.clock_speed) {
+            self.glitch_filter = previous;
+            return Err(err);
+        }
+        let root_hz = self.source_clock.0;
+        Ok(timing::filter_width(
+            root_hz,
+            timing::filter_cycles(root_hz, width),
+        ))
     }
 
     /// Set the pin low timeout
     ///
     /// If SCL or, either SCL or SDA, is low for longer than the specified duration, then the
     /// I2C hardware indicates an error. If the timeout is `0`, then the detection is disabled.
+    /// A transfer that sees the error aborts, and returns
+    /// [`Error::PinLowTimeout`](enum.Error.html#variant.PinLowTimeout).
     ///
-    /// If the number of cycles required to represent the duration is too large, returns a
-    /// `PinLowTimeoutError`. Try using a smaller duration.
+    /// The hardware counts in steps of 256 prescaled cycles, so the timeout rounds up to
+    /// the next step. If the number of cycles required to represent the duration is too
+    /// large, returns a `PinLowTimeoutError`. Try using a smaller duration.
     pub fn set_pin_low_timeout(
         &mut self,
         timeout: core::time::Duration,
     ) -> Result<(), PinLowTimeoutError> {
-        let divider = 1 << ral::read_reg!(ral::lpi2c, self.reg, MCFGR1, PRESCALE);
-        let pin_low_ticks: u16 = ccm::ticks(timeout, self.source_clock.0, divider)
-            .map(|ticks: u16| ticks / 256)
-            .into_iter()
-            .next()
-            .filter(|ticks| *ticks <= 0x0FFFu16)
+        let prescaler = ral::read_reg!(ral::lpi2c, self.reg, MCFGR1, PRESCALE);
+        let pin_low_ticks = timeout::pin_low(timeout, self.source_clock.0, prescaler)
             .ok_or(PinLowTimeoutError(()))?;
         log::debug!("PINLOW = 0x{:X}", pin_low_ticks);
         self.with_master_disabled(|| {
-            ral::modify_reg!(
-                ral::lpi2c,
-                self.reg,
-                MCFGR3,
-                PINLOW: u32::from(pin_low_ticks)
-            );
+            ral::modify_reg!(ral::lpi2c, self.reg, MCFGR3, PINLOW: pin_low_ticks);
             Ok(())
         })
     }
@@ -357,19 +453,6 @@ where
         })
     }
 
-    #[inline(always)]
-    fn wait<F>(&mut self, on: F) -> Result<(), Error>
-    where
-        F: Fn(u32) -> bool,
-    {
-        for _ in 0..RETRIES {
-            if on(self.check_errors()?) {
-                return Ok(());
-            }
-        }
-        Err(Error::WaitTimeout)
-    }
-
     /// Clears all master status flags that are required to be
     /// low before acting as an I2C master.
     ///
@@ -390,44 +473,92 @@ where
         );
     }
 
+    /// Discard the words in the transmit and receive FIFOs
+    ///
+    /// A transfer that fails clears the FIFOs for you, whatever the error, so the next
+    /// transfer starts clean. Don't clear the FIFOs while a DMA transfer runs.
     #[inline(always)]
-    fn clear_fifo(&mut self) {
+    pub fn clear_fifo(&mut self) {
         ral::modify_reg!(ral::lpi2c, self.reg, MCR, RRF: RRF_1, RTF: RTF_1);
     }
-
-    /// Check master status flags for erroneous conditions
-    #[inline(always)]
-    fn check_errors(&mut self) -> Result<u32, Error> {
-        use ral::lpi2c::MSR::*;
-        let status = ral::read_reg!(ral::lpi2c, self.reg, MSR);
-        if (status & PLTF::mask) != 0 {
-            Err(Error::PinLowTimeout)
-        } else if (status & ALF::mask) != 0 {
-            Err(Error::LostBusArbitration)
-        } else if (status & NDF::mask) != 0 {
-            Err(Error::UnexpectedNACK)
-        } else if (status & FEF::mask) != 0 {
-            Err(Error::FIFO)
-        } else {
-            Ok(status)
-        }
-    }
 }
 
+/// An I2C master error
+///
+/// A NACK of the address usually means that there's no device, or that it's busy,
+/// and a NACK of data means that the device rejected a byte. A lost arbitration
+/// means that another master used the bus; try again, or set a
+/// [retry policy](struct.I2C.html#method.set_retry_policy).
+///
+/// # Example
+///
+/// Scan the bus. A write without bytes sends only the address.
+///
+/// ```no_run
+/// use embedded_hal::blocking::i2c::Write;
+/// use imxrt1060_hal::i2c::{Error, I2C};
+/// # use imxrt1060_hal::iomuxc::consts::U3;
+/// # fn scan(i2c: &mut I2C<U3>) {
+///
+/// for address in 0..128 {
+///     match i2c.write(address, &[]) {
+///         Ok(()) => log::info!("found a device at 0x{:02X}", address),
+///         Err(Error::NoAcknowledgeAddress) => (),
+///         Err(err) => log::warn!("0x{:02X}: {:?}", address, err),
+///     }
+/// }
+/// # }
+/// ```
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
-    /// Master has lost arbitration
-    LostBusArbitration,
+    /// Another master won the bus
+    ArbitrationLost,
     /// SCL and / or SDA went low for too long, despite our control
     PinLowTimeout,
-    /// Detected a NACK when sending an address or data
-    UnexpectedNACK,
+    /// The device didn't acknowledge its address
+    NoAcknowledgeAddress,
+    /// The device didn't acknowledge a byte that the master wrote
+    NoAcknowledgeData {
+        /// The byte's index, counting the transfer's written bytes from 0
+        index: usize,
+    },
     /// Sending or receiving data without a START
-    FIFO,
-    /// Requesting too much data in a receive; upper limit is `u8::MAX`
-    RequestTooMuchData,
+    FifoError,
+    /// A wait in the transfer outlasted the
+    /// [operation timeout](struct.I2C.html#method.set_operation_timeout)
+    ///
+    /// The transfer was aborted, and the master is idle.
+    Timeout(Phase),
+    /// The bus stayed busy, but this master wasn't using it
+    ///
+    /// Usually, a device holds SDA low after a reset. Try
+    /// [`recover_bus()`](struct.I2C.html#method.recover_bus).
+    BusStuck,
+}
+
+/// The names of the errors before the NACK classification, and the operation timeout
+///
+/// The constants work in patterns, so existing matches keep compiling.
+#[allow(non_upper_case_globals)]
+impl Error {
+    /// Another master won the bus
+    #[deprecated(since = "0.5.0", note = "use `Error::ArbitrationLost`")]
+    pub const LostBusArbitration: Error = Error::ArbitrationLost;
+    /// The device didn't acknowledge its address
+    ///
+    /// This only matches a NACK of the address. Match `NoAcknowledgeData` for a NACK
+    /// of data.
+    #[deprecated(since = "0.5.0", note = "use `Error::NoAcknowledgeAddress`")]
+    pub const UnexpectedNACK: Error = Error::NoAcknowledgeAddress;
+    /// Sending or receiving data without a START
+    #[deprecated(since = "0.5.0", note = "use `Error::FifoError`")]
+    pub const FIFO: Error = Error::FifoError;
     /// Busy-wait on an internal flag was too long
-    WaitTimeout,
+    ///
+    /// This only matches a timeout while sending or receiving data. Match
+    /// `Timeout(_)` for a timeout in any phase.
+    #[deprecated(since = "0.5.0", note = "use `Error::Timeout`")]
+    pub const WaitTimeout: Error = Error::Timeout(Phase::Data);
 }
 
 macro_rules! target_fn {
@@ -436,6 +567,29 @@ macro_rules! target_fn {
     };
 }
 
+/// Keeps the clock speed when the LPI2C root clock changes
+///
+/// The pin low, and bus idle, timeouts keep their tick counts, so they scale with the
+/// clock. Set them again after a clock change.
+impl<M: Unsigned> ccm::ClockListenerMut for I2C<M> {
+    fn clocks_changed(&mut self, change: &ccm::ClocksChanged) {
+        let clock = change.new.lpi2c;
+        if clock == self.source_clock || clock.0 == 0 {
+            return;
+        }
+        self.source_clock = clock;
+        let clock_speed = self.clock_speed;
+        if self.set_clock_speed(clock_speed).is_err() {
+            log::warn!(
+                "I2C{} can't run at {:?} from {:?}",
+                M::USIZE,
+                clock_speed,
+                clock
+            );
+        }
+    }
+}
+
 impl<M> blocking::i2c::Write for I2C<M>
 where
     M: Unsigned,
@@ -443,39 +597,12 @@ where
     type Error = Error;
 
     fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-        use ral::lpi2c::MSR::*;
-        self.clear_fifo();
-        self.clear_status();
-        log::trace!(target: target_fn!("write"), "WAIT MBF & TDF");
-        self.wait(|msr| (msr & MBF::mask) == 0 && (msr & TDF::mask) != 0)?;
-
-        log::trace!(target: target_fn!("write"), "START");
-        ral::write_reg!(
-            ral::lpi2c,
-            self.reg,
-            MTDR,
-            DATA: u32::from(addr) << 1,
-            CMD: CMD_4
-        );
-
         log::trace!(target: target_fn!("write"), "'{:?}' -> 0x{:X}", bytes, addr);
-        for byte in bytes {
-            self.wait(|msr| (msr & TDF::mask) != 0)?;
-            ral::write_reg!(ral::lpi2c, self.reg, MTDR, DATA: *byte as u32);
-        }
-
-        log::trace!(target: target_fn!("write"), "WAIT TDF");
-        self.wait(|msr| (msr & TDF::mask) != 0)?;
-        log::trace!(target: target_fn!("write"), "STOP");
-        ral::write_reg!(ral::lpi2c, self.reg, MTDR, CMD: CMD_2);
-
-        log::trace!(target: target_fn!("write"), "WAIT EPF");
-        self.wait(|msr| (msr & EPF::mask) != 0)?;
-
-        Ok(())
+        self.transaction(addr, &mut [blocking::i2c::Operation::Write(bytes)])
     }
 }
 
+/// The read follows the write with a repeated START, without a STOP
 impl<M> blocking::i2c::WriteRead for I2C<M>
 where
     M: Unsigned,
@@ -487,95 +614,21 @@ where
         output: &[u8],
         input: &mut [u8],
     ) -> Result<(), Self::Error> {
-        use ral::lpi2c::MSR::*;
-        if input.len() > 256 {
-            return Err(Error::RequestTooMuchData);
-        }
-
-        self.clear_fifo();
-        self.clear_status();
-        log::trace!(target: target_fn!("write_read"), "WAIT MBF & TDF");
-        self.wait(|msr| (msr & MBF::mask) == 0 && (msr & TDF::mask) != 0)?;
-
-        log::trace!(target: target_fn!("write_read"), "START");
-        ral::write_reg!(
-            ral::lpi2c,
-            self.reg,
-            MTDR,
-            DATA: u32::from(address) << 1,
-            CMD: CMD_4
-        );
-
         log::trace!(
             target: target_fn!("write_read"),
-            "'{:?}' -> 0x{:X}",
+            "'{:?}' -> 0x{:X}, '{}' <- 0x{:X}",
             output,
+            address,
+            input.len(),
             address
         );
-        for byte in output {
-            self.wait(|msr| (msr & TDF::mask) != 0)?;
-            ral::write_reg!(ral::lpi2c, self.reg, MTDR, DATA: *byte as u32);
-        }
-
-        log::trace!(target: target_fn!("write_read"), "REPEAT START");
-        ral::write_reg!(
-            ral::lpi2c,
-            self.reg,
-            MTDR,
-            DATA: (u32::from(address) << 1) | 1,
-            CMD: CMD_4
-        );
-
-        log::trace!(target: target_fn!("write_read"), "WAIT EPF");
-        self.wait(|msr| (msr & EPF::mask) != 0)?;
-        ral::write_reg!(ral::lpi2c, self.reg, MSR, EPF: EPF_1);
-
-        if !input.is_empty() {
-            log::trace!(target: target_fn!("write_read"), "WAIT TDF");
-            self.wait(|msr| (msr & TDF::mask) != 0)?;
-
-            log::trace!(
-                target: target_fn!("write_read"),
-                "'{}' -> 0x{:X}",
-                input.len() - 1,
-                address
-            );
-            ral::write_reg!(
-                ral::lpi2c,
-                self.reg,
-                MTDR,
-                DATA: (input.len() - 1) as u32,
-                CMD: CMD_1
-            );
-
-            log::trace!(target: target_fn!("write_read"), "WAIT DATA");
-            for slot in input.iter_mut() {
-                let mut j = 0;
-                loop {
-                    use ral::lpi2c::MRDR::*;
-                    self.check_errors()?;
-                    let mrdr = ral::read_reg!(ral::lpi2c, self.reg, MRDR);
-                    if mrdr & RXEMPTY::mask == 0 {
-                        *slot = ((mrdr & DATA::mask) >> DATA::offset) as u8;
-                        break;
-                    }
-                    j += 1;
-                    if j > RETRIES {
-                        return Err(Error::WaitTimeout);
-                    }
-                }
-            }
-        }
-
-        log::trace!(target: target_fn!("write_read"), "WAIT TDF");
-        self.wait(|msr| (msr & TDF::mask) != 0)?;
-        log::trace!(target: target_fn!("write_read"), "STOP");
-        ral::write_reg!(ral::lpi2c, self.reg, MTDR, CMD: CMD_2);
-
-        log::trace!(target: target_fn!("write_read"), "WAIT EPF");
-        self.wait(|msr| (msr & EPF::mask) != 0)?;
-
-        Ok(())
+        self.transaction(
+            address,
+            &mut [
+                blocking::i2c::Operation::Write(output),
+                blocking::i2c::Operation::Read(input),
+            ],
+        )
     }
 }
 
@@ -586,70 +639,12 @@ where
     type Error = Error;
 
     fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        use ral::lpi2c::MSR::*;
-        if buffer.len() > 256 {
-            return Err(Error::RequestTooMuchData);
-        }
-
-        if buffer.is_empty() {
-            return Ok(());
-        }
-
-        self.clear_fifo();
-        self.clear_status();
-        log::trace!(target: target_fn!("read"), "WAIT MBF & TDF");
-        self.wait(|msr| (msr & MBF::mask) == 0 && (msr & TDF::mask) != 0)?;
-
-        log::trace!(target: target_fn!("read"), "START");
-        ral::write_reg!(
-            ral::lpi2c,
-            self.reg,
-            MTDR,
-            DATA: (u32::from(address) << 1) | 1,
-            CMD: CMD_4
-        );
-
-        log::trace!(target: target_fn!("read"), "WAIT TDF");
-        self.wait(|msr| (msr & TDF::mask) != 0)?;
-
         log::trace!(
             target: target_fn!("read"),
-            "'{}' -> 0x{:X}",
-            buffer.len() - 1,
+            "'{}' <- 0x{:X}",
+            buffer.len(),
             address
         );
-        ral::write_reg!(
-            ral::lpi2c,
-            self.reg,
-            MTDR,
-            DATA: (buffer.len() - 1) as u32,
-            CMD: CMD_1
-        );
-
-        log::trace!(target: target_fn!("read"), "WAIT DATA");
-        for slot in buffer.iter_mut() {
-            let mut j = 0;
-            loop {
-                use ral::lpi2c::MRDR::*;
-                self.check_errors()?;
-                let mrdr = ral::read_reg!(ral::lpi2c, self.reg, MRDR);
-                if mrdr & RXEMPTY::mask == 0 {
-                    *slot = ((mrdr & DATA::mask) >> DATA::offset) as u8;
-                    break;
-                }
-                j += 1;
-                if j > RETRIES {
-                    return Err(Error::WaitTimeout);
-                }
-            }
-        }
-
-        log::trace!(target: target_fn!("read"), "STOP");
-        ral::write_reg!(ral::lpi2c, self.reg, MTDR, CMD: CMD_2);
-
-        log::trace!(target: target_fn!("read"), "WAIT EPF");
-        self.wait(|msr| (msr & EPF::mask) != 0)?;
-
-        Ok(())
+        self.transaction(address, &mut [blocking::i2c::Operation::Read(buffer)])
     }
 }
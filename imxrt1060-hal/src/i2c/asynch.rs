@@ -0,0 +1,591 @@
+//! Async I2C transactions
+//!
+//! Enable this module with the `"async"` feature. With the `"embedded-hal-1"` and
+//! `"embedded-hal-async"` features, the I2C also implements the `embedded-hal-async`
+//! `I2c` trait.
+//!
+//! An async transaction fills the command FIFO, then waits for the LPI2C interrupts
+//! instead of polling. You service the interrupts: call
+//! [`on_interrupt()`](fn.on_interrupt.html) from each `LPI2C[X]` handler that you use,
+//! and unmask the interrupt in the NVIC.
+//!
+//! A NACK, a lost arbitration, a FIFO error, or a pin low timeout resolves the
+//! transaction with the error, and leaves the master idle. The operation timeout doesn't
+//! apply; set a [pin low timeout](../struct.I2C.html#method.set_pin_low_timeout) to catch
+//! a device that holds the bus. Dropping an unfinished transaction sends a STOP, and
+//! discards the FIFOs.
+//!
+//! # Example
+//!
+//! Read a temperature sensor, with a minimal executor that sleeps until an interrupt.
+//!
+//! ```no_run
+//! use core::{
+//!     future::Future,
+//!     pin::Pin,
+//!     task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+//! };
+//! use embedded_hal::blocking::i2c::Operation;
+//! use imxrt1060_hal::i2c::{asynch, Error, I2C};
+//! # use imxrt1060_hal::iomuxc::consts::U3;
+//!
+//! #[cortex_m_rt::interrupt]
+//! fn LPI2C3() {
+//!     asynch::on_interrupt(3);
+//! }
+//!
+//! async fn temperature(i2c: &mut I2C<U3>) -> Result<i16, Error> {
+//!     const SENSOR: u8 = 0x48;
+//!     let mut raw = [0; 2];
+//!     i2c.exec_async(
+//!         SENSOR,
+//!         &mut [Operation::Write(&[0x00]), Operation::Read(&mut raw)],
+//!     )
+//!     .await?;
+//!     // 12 bits, in 1/16 degrees C
+//!     Ok(i16::from_be_bytes(raw) >> 4)
+//! }
+//!
+//! fn block_on<F: Future>(mut future: F) -> F::Output {
+//!     fn clone(_: *const ()) -> RawWaker {
+//!         RawWaker::new(core::ptr::null(), &VTABLE)
+//!     }
+//!     fn noop(_: *const ()) {}
+//!     static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+//!
+//!     // Safety: the waker does nothing, so it upholds the RawWaker contract
+//!     let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+//!     let mut cx = Context::from_waker(&waker);
+//!     // Safety: the future is never moved
+//!     let mut future = unsafe { Pin::new_unchecked(&mut future) };
+//!     loop {
+//!         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+//!             return output;
+//!         }
+//!         cortex_m::asm::wfi();
+//!     }
+//! }
+//!
+//! # fn run(mut i2c: I2C<U3>) {
+//! match block_on(temperature(&mut i2c)) {
+//!     Ok(sixteenths) => log::info!("{} C", sixteenths / 16),
+//!     Err(err) => log::warn!("{:?}", err),
+//! }
+//! # }
+//! ```
+
+use super::{
+    timeout::{self, Phase},
+    transaction::{Command, Commands, Op, Receiver},
+    Error, I2C,
+};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::{
+    cell::RefCell,
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+use cortex_m::interrupt::Mutex;
+use embedded_hal::blocking::i2c::Operation;
+
+const NO_WAKER: Option<Waker> = None;
+
+/// Wakers for LPI2C1 through LPI2C4
+static WAKERS: Mutex<RefCell<[Option<Waker>; 4]>> = Mutex::new(RefCell::new([NO_WAKER; 4]));
+
+const LPI2C: [*const ral::lpi2c::RegisterBlock; 4] = [
+    ral::lpi2c::LPI2C1,
+    ral::lpi2c::LPI2C2,
+    ral::lpi2c::LPI2C3,
+    ral::lpi2c::LPI2C4,
+];
+
+/// Wake the transaction that waits on LPI2C `module`
+///
+/// Call this from the `LPI2C[X]` interrupt handler. This disables the master interrupts,
+/// and leaves the flags for the transaction. Does nothing if `module` is not between 1
+/// and 4.
+pub fn on_interrupt(module: usize) {
+    let block = match LPI2C.get(module.wrapping_sub(1)) {
+        Some(&block) => block,
+        None => return,
+    };
+    cortex_m::interrupt::free(|cs| {
+        // Safety: writes are atomic, and the transaction only enables interrupts
+        unsafe { ral::write_reg!(ral::lpi2c, block, MIER, 0) };
+        if let Some(waker) = WAKERS.borrow(cs).borrow_mut()[module - 1].take() {
+            waker.wake();
+        }
+    });
+}
+
+/// The LPI2C command FIFO, as the async transaction sees it
+pub(super) trait Fifo: timeout::Master {
+    /// Returns the number of words in the TX FIFO
+    fn tx_count(&mut self) -> u32;
+    /// Returns the number of words that the TX FIFO holds
+    fn tx_depth(&mut self) -> u32;
+    /// Put a word in the TX FIFO
+    fn send(&mut self, word: u32);
+}
+
+/// The result of one step of a transaction
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Step {
+    /// Wait for the MIER interrupts
+    Wait(u32),
+    /// The transaction is over
+    Done(Result<(), Error>),
+}
+
+/// A transaction that moves forward each time that it's polled
+pub(super) struct Machine {
+    commands: Commands,
+    receiver: Receiver,
+    pending: Option<Command>,
+    started: bool,
+    finished: bool,
+}
+
+impl Machine {
+    pub(super) fn new<O: Op>(address: u8, ops: &[O]) -> Self {
+        let mut commands = Commands::new(address);
+        let pending = commands.next(ops);
+        Machine {
+            commands,
+            receiver: Receiver::default(),
+            pending,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Fill the TX FIFO, and drain the RX FIFO, then say what to wait for
+    ///
+    /// The first step clears the FIFOs and the status flags. An error aborts the
+    /// transaction.
+    pub(super) fn step<F: Fifo, O: Op>(&mut self, fifo: &mut F, ops: &mut [O]) -> Step {
+        use ral::lpi2c::{MIER, MSR};
+        if self.finished {
+            return Step::Done(Ok(()));
+        }
+        if !self.started {
+            if self.pending.is_none() {
+                self.finished = true;
+                return Step::Done(Ok(()));
+            }
+            fifo.clear_fifo();
+            fifo.clear_status();
+            self.started = true;
+        }
+
+        let msr = fifo.status();
+        if let Err(err) = timeout::check(msr, Phase::Data) {
+            let err = match err {
+                Error::NoAcknowledgeAddress | Error::NoAcknowledgeData { .. } => {
+                    self.commands.nacked(fifo.tx_count())
+                }
+                err => err,
+            };
+            self.cancel(fifo);
+            return Step::Done(Err(err));
+        }
+
+        let depth = fifo.tx_depth();
+        while let Some(command) = self.pending {
+            if fifo.tx_count() >= depth {
+                break;
+            }
+            fifo.send(command.word());
+            self.commands.sent(command);
+            self.pending = self.commands.next(ops);
+        }
+        while let Some(byte) = fifo.receive() {
+            if !self.receiver.receive(ops, byte) {
+                log::warn!("I2C received an unexpected byte");
+            }
+        }
+        let received = self.receiver.is_done(ops);
+        if self.pending.is_none() && received && msr & MSR::SDF::mask != 0 {
+            self.finished = true;
+            return Step::Done(Ok(()));
+        }
+
+        let mut interrupts =
+            MIER::NDIE::mask | MIER::ALIE::mask | MIER::FEIE::mask | MIER::PLTIE::mask;
+        if self.pending.is_some() {
+            interrupts |= MIER::TDIE::mask;
+        } else {
+            interrupts |= MIER::SDIE::mask;
+        }
+        if !received {
+            interrupts |= MIER::RDIE::mask;
+        }
+        Step::Wait(interrupts)
+    }
+
+    /// Abort an unfinished transaction, sending a STOP if the master holds the bus
+    pub(super) fn cancel<F: Fifo>(&mut self, fifo: &mut F) {
+        if self.started && !self.finished {
+            timeout::abort(fifo);
+        }
+        self.finished = true;
+    }
+}
+
+impl<M> Fifo for I2C<M>
+where
+    M: Unsigned,
+{
+    fn tx_count(&mut self) -> u32 {
+        I2C::tx_count(self)
+    }
+    fn tx_depth(&mut self) -> u32 {
+        I2C::tx_depth(self)
+    }
+    fn send(&mut self, word: u32) {
+        ral::write_reg!(ral::lpi2c, self.reg, MTDR, word);
+    }
+}
+
+/// The operations of an async transaction
+enum Operations<'a, 'b> {
+    /// The `embedded_hal` 0.2 operations
+    Eh02(&'a mut [Operation<'b>]),
+    /// The `embedded-hal` 1.0 operations
+    #[cfg(all(feature = "embedded-hal-1", feature = "embedded-hal-async"))]
+    Eh1(&'a mut [embedded_hal_1::i2c::Operation<'b>]),
+}
+
+impl Operations<'_, '_> {
+    fn start(&self, address: u8) -> Machine {
+        match self {
+            Operations::Eh02(ops) => Machine::new(address, &ops[..]),
+            #[cfg(all(feature = "embedded-hal-1", feature = "embedded-hal-async"))]
+            Operations::Eh1(ops) => Machine::new(address, &ops[..]),
+        }
+    }
+
+    fn step<F: Fifo>(&mut self, machine: &mut Machine, fifo: &mut F) -> Step {
+        match self {
+            Operations::Eh02(ops) => machine.step(fifo, &mut ops[..]),
+            #[cfg(all(feature = "embedded-hal-1", feature = "embedded-hal-async"))]
+            Operations::Eh1(ops) => machine.step(fifo, &mut ops[..]),
+        }
+    }
+}
+
+/// An async I2C transaction
+///
+/// Create a `Transaction` with [`I2C::exec_async()`](../struct.I2C.html#method.exec_async).
+/// The transaction joins adjacent operations that have the same direction, and sends a
+/// repeated START between the others, like the blocking `Transactional` implementation.
+pub struct Transaction<'a, 'b, M>
+where
+    M: Unsigned,
+{
+    i2c: &'a mut I2C<M>,
+    ops: Operations<'a, 'b>,
+    machine: Machine,
+    /// The RX watermark before the transaction
+    rxwater: u32,
+}
+
+impl<'a, 'b, M> Transaction<'a, 'b, M>
+where
+    M: Unsigned,
+{
+    fn new(i2c: &'a mut I2C<M>, address: u8, ops: Operations<'a, 'b>) -> Self {
+        let rxwater = ral::read_reg!(ral::lpi2c, i2c.reg, MFCR, RXWATER);
+        // Ask for each byte as it arrives
+        ral::modify_reg!(ral::lpi2c, i2c.reg, MFCR, RXWATER: 0);
+        let machine = ops.start(address);
+        Transaction {
+            i2c,
+            ops,
+            machine,
+            rxwater,
+        }
+    }
+
+    /// Stop waiting for interrupts, and restore the RX watermark
+    fn disarm(&mut self) {
+        ral::write_reg!(ral::lpi2c, self.i2c.reg, MIER, 0);
+        ral::modify_reg!(ral::lpi2c, self.i2c.reg, MFCR, RXWATER: self.rxwater);
+        cortex_m::interrupt::free(|cs| {
+            WAKERS.borrow(cs).borrow_mut()[M::USIZE - 1] = None;
+        });
+    }
+}
+
+impl<M> Future for Transaction<'_, '_, M>
+where
+    M: Unsigned,
+{
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        match this.ops.step(&mut this.machine, this.i2c) {
+            Step::Done(result) => {
+                this.disarm();
+                Poll::Ready(result)
+            }
+            Step::Wait(interrupts) => {
+                cortex_m::interrupt::free(|cs| {
+                    let mut wakers = WAKERS.borrow(cs).borrow_mut();
+                    let slot = &mut wakers[M::USIZE - 1];
+                    if !slot
+                        .as_ref()
+                        .map_or(false, |slot| slot.will_wake(cx.waker()))
+                    {
+                        *slot = Some(cx.waker().clone());
+                    }
+                });
+                // If a flag set after the step, enabling its interrupt wakes us.
+                ral::write_reg!(ral::lpi2c, this.i2c.reg, MIER, interrupts);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Dropping an unfinished transaction aborts it
+impl<M> Drop for Transaction<'_, '_, M>
+where
+    M: Unsigned,
+{
+    fn drop(&mut self) {
+        if !self.machine.finished {
+            self.disarm();
+            self.machine.cancel(self.i2c);
+        }
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Run the operations as one transaction with the device at `address`, without
+    /// blocking
+    ///
+    /// See the [module documentation](asynch/index.html) for an example.
+    pub fn exec_async<'a, 'b>(
+        &'a mut self,
+        address: u8,
+        operations: &'a mut [Operation<'b>],
+    ) -> Transaction<'a, 'b, M> {
+        Transaction::new(self, address, Operations::Eh02(operations))
+    }
+}
+
+#[cfg(all(feature = "embedded-hal-1", feature = "embedded-hal-async"))]
+impl<M: Unsigned> embedded_hal_async::i2c::I2c for I2C<M> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal_1::i2c::Operation<'_>],
+    ) -> Result<(), Error> {
+        Transaction::new(self, address, Operations::Eh1(operations)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{timeout::Master, Error, Fifo, Machine, Step};
+    use embedded_hal::blocking::i2c::Operation;
+
+    const TDF: u32 = 1 << 0;
+    const SDF: u32 = 1 << 9;
+    const NDF: u32 = 1 << 10;
+    const MBF: u32 = 1 << 24;
+
+    const TDIE: u32 = 1 << 0;
+    const RDIE: u32 = 1 << 1;
+    const SDIE: u32 = 1 << 9;
+    const ERRORS: u32 = 0b1111 << 10;
+
+    /// A master with a TX FIFO that the test drains, and bytes that the test receives
+    struct Mock {
+        msr: u32,
+        words: [u32; 8],
+        sent: usize,
+        /// The number of sent words that the master took
+        taken: usize,
+        rx: [Option<u8>; 4],
+        stopped: bool,
+        cleared: usize,
+    }
+
+    impl Mock {
+        fn new() -> Self {
+            Mock {
+                msr: TDF,
+                words: [0; 8],
+                sent: 0,
+                taken: 0,
+                rx: [None; 4],
+                stopped: false,
+                cleared: 0,
+            }
+        }
+        /// The master takes `words` words, and receives `bytes`
+        fn interrupt(&mut self, words: usize, bytes: &[u8], msr: u32) {
+            self.taken += words;
+            for (slot, &byte) in self.rx.iter_mut().filter(|slot| slot.is_none()).zip(bytes) {
+                *slot = Some(byte);
+            }
+            self.msr |= msr;
+        }
+    }
+
+    impl Master for Mock {
+        fn status(&mut self) -> u32 {
+            self.msr
+        }
+        fn receive(&mut self) -> Option<u8> {
+            let slot = self.rx.iter_mut().find(|slot| slot.is_some())?;
+            slot.take()
+        }
+        fn stop(&mut self) {
+            self.stopped = true;
+        }
+        fn clear_fifo(&mut self) {
+            self.taken = self.sent;
+        }
+        fn clear_status(&mut self) {
+            self.msr = TDF | (self.msr & MBF);
+            self.cleared += 1;
+        }
+        fn reset(&mut self) {}
+        fn delay(&mut self) {
+            if self.stopped {
+                self.msr &= !MBF;
+            }
+        }
+        fn timeout_polls(&self) -> u32 {
+            1_000
+        }
+    }
+
+    impl Fifo for Mock {
+        fn tx_count(&mut self) -> u32 {
+            (self.sent - self.taken) as u32
+        }
+        fn tx_depth(&mut self) -> u32 {
+            4
+        }
+        fn send(&mut self, word: u32) {
+            self.words[self.sent] = word;
+            self.sent += 1;
+        }
+    }
+
+    #[test]
+    fn register_read() {
+        let mut data = [0; 2];
+        let mut ops = [Operation::Write(&[0x10]), Operation::Read(&mut data)];
+        let mut machine = Machine::new(0x48, &ops);
+        let mut mock = Mock::new();
+
+        // START, register, repeated START, and receive fill the FIFO
+        assert_eq!(
+            machine.step(&mut mock, &mut ops),
+            Step::Wait(ERRORS | TDIE | RDIE)
+        );
+        assert_eq!(mock.words[..4], [0x490, 0x010, 0x491, 0x101]);
+        mock.interrupt(3, &[0xAA], MBF);
+        // The STOP goes in
+        assert_eq!(
+            machine.step(&mut mock, &mut ops),
+            Step::Wait(ERRORS | SDIE | RDIE)
+        );
+        assert_eq!(mock.words[4], 0x200);
+        mock.interrupt(2, &[0xBB], SDF);
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Done(Ok(())));
+        assert!(!mock.stopped);
+        assert_eq!(data, [0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn address_nack() {
+        let mut ops = [Operation::Write(&[1, 2])];
+        let mut machine = Machine::new(0x50, &ops);
+        let mut mock = Mock::new();
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Wait(ERRORS | SDIE));
+        // The master is sending the address
+        mock.interrupt(1, &[], MBF | NDF);
+        assert_eq!(
+            machine.step(&mut mock, &mut ops),
+            Step::Done(Err(Error::NoAcknowledgeAddress))
+        );
+        // The abort discards the FIFO, sends a STOP, and clears the flags
+        assert!(mock.stopped);
+        assert_eq!(mock.tx_count(), 0);
+        assert_eq!(mock.msr, TDF);
+        // Finished for good
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Done(Ok(())));
+    }
+
+    #[test]
+    fn long_write_address_nack() {
+        // A page write that's longer than the TX FIFO, to a busy EEPROM
+        let mut ops = [Operation::Write(&[0x01, 0x23, 0xAA, 0xBB, 0xCC])];
+        let mut machine = Machine::new(0x50, &ops);
+        let mut mock = Mock::new();
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Wait(ERRORS | TDIE));
+        assert_eq!(mock.words[..4], [0x4A0, 0x001, 0x023, 0x0AA]);
+        // The 0xBB byte waits for room, while the master sends the address
+        mock.interrupt(1, &[], MBF | NDF);
+        assert_eq!(
+            machine.step(&mut mock, &mut ops),
+            Step::Done(Err(Error::NoAcknowledgeAddress))
+        );
+        assert_eq!(mock.sent, 4);
+    }
+
+    #[test]
+    fn long_write_data_nack() {
+        let mut ops = [Operation::Write(&[0x01, 0x23, 0xAA, 0xBB, 0xCC])];
+        let mut machine = Machine::new(0x50, &ops);
+        let mut mock = Mock::new();
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Wait(ERRORS | TDIE));
+        // The master took the address and 0x01, then the device rejected 0x23
+        mock.interrupt(3, &[], MBF | NDF);
+        assert_eq!(
+            machine.step(&mut mock, &mut ops),
+            Step::Done(Err(Error::NoAcknowledgeData { index: 1 }))
+        );
+    }
+
+    #[test]
+    fn cancel_stops() {
+        let mut ops = [Operation::Write(&[1, 2, 3, 4, 5])];
+        let mut machine = Machine::new(0x50, &ops);
+        let mut mock = Mock::new();
+        machine.cancel(&mut mock);
+        // Not started, so there's no bus traffic
+        assert_eq!(mock.cleared, 0);
+
+        let mut machine = Machine::new(0x50, &ops);
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Wait(ERRORS | TDIE));
+        mock.interrupt(2, &[], MBF);
+        machine.cancel(&mut mock);
+        assert!(mock.stopped);
+        assert_eq!(mock.tx_count(), 0);
+        assert_eq!(mock.msr & MBF, 0);
+        machine.cancel(&mut mock);
+        assert_eq!(mock.cleared, 2);
+    }
+
+    #[test]
+    fn empty_transaction() {
+        let mut ops: [Operation; 0] = [];
+        let mut machine = Machine::new(0x50, &ops);
+        let mut mock = Mock::new();
+        assert_eq!(machine.step(&mut mock, &mut ops), Step::Done(Ok(())));
+        assert_eq!(mock.cleared, 0);
+    }
+}
@@ -0,0 +1,511 @@
+//! DMA transfers, with commands queued by the CPU
+//!
+//! The DMA controller moves the data bytes. The CPU queues the START, the receive
+//! commands, and the STOP, since each receive command covers at most 256 bytes. Each
+//! I2C has one DMA request for both directions, so a transfer uses one channel.
+
+use super::{
+    timeout::{self, Phase},
+    transaction::Command,
+    Error, I2C,
+};
+use crate::dma;
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+/// LPI2C DMA request signals
+///
+/// See table 4-3 of the iMXRT1060 Reference Manual (Rev 2)
+const DMA_REQUEST_LOOKUP: [u32; 4] = [17, 18, 19, 20];
+
+/// The most bytes that one receive command reads
+const MAX_RECEIVE: usize = 256;
+/// The most bytes that one DMA transfer moves
+const MAX_DMA_LEN: usize = 0x7FFF;
+
+/// An error from a DMA transfer
+#[derive(Debug)]
+pub enum DmaError {
+    /// The DMA transfer couldn't start
+    Dma(dma::Error),
+    /// The buffer is empty, or it's longer than 32767 bytes
+    InvalidLength,
+    /// The I2C failed, so the DMA transfer was cancelled
+    ///
+    /// The master is idle. `byte` is the index of the DMA buffer's byte that failed,
+    /// or `None` if the transfer failed before the DMA buffer. A NACK of a written
+    /// byte has the same index.
+    I2c {
+        /// The I2C error
+        error: Error,
+        /// The buffer's failed byte
+        byte: Option<usize>,
+    },
+}
+
+/// Returns the size of the next receive command, or `None` if every byte has one
+pub(super) fn next_receive(len: usize, commanded: usize) -> Option<u16> {
+    let remaining = len.saturating_sub(commanded);
+    if remaining == 0 {
+        None
+    } else {
+        Some(remaining.min(MAX_RECEIVE) as u16)
+    }
+}
+
+/// Returns the index of the data byte that the master was sending, or `None` for the
+/// address
+///
+/// `written` counts the words written to the TX FIFO, starting with the address, and
+/// `queued` the words still in the TX FIFO.
+pub(super) fn failed_byte(written: usize, queued: usize) -> Option<usize> {
+    written.saturating_sub(queued + 1).checked_sub(1)
+}
+
+/// The commands of a DMA transfer that the CPU hasn't queued
+pub(super) struct DmaCommands {
+    /// The number of bytes to receive, or zero for a write
+    len: usize,
+    /// The number of bytes that the CPU wrote before the read
+    output: usize,
+    /// The number of bytes that receive commands cover
+    commanded: usize,
+    /// The number of words that the CPU wrote, starting with the address
+    written: usize,
+    stopped: bool,
+}
+
+impl DmaCommands {
+    /// `output` bytes, and a repeated START, if there are any, follow the address
+    fn new(len: usize, output: usize) -> Self {
+        DmaCommands {
+            len,
+            output,
+            commanded: 0,
+            written: if output == 0 { 1 } else { output + 2 },
+            stopped: false,
+        }
+    }
+
+    /// Returns the next command, then the STOP, then `None`
+    fn next(&mut self) -> Option<Command> {
+        let command = if let Some(count) = next_receive(self.len, self.commanded) {
+            self.commanded += usize::from(count);
+            Command::Receive(count)
+        } else if !self.stopped {
+            self.stopped = true;
+            Command::Stop
+        } else {
+            return None;
+        };
+        self.written += 1;
+        Some(command)
+    }
+
+    /// Returns the NACK error of a read, given the `queued` words in the TX FIFO
+    ///
+    /// Only the addresses, and the output bytes, see a NACK.
+    fn nacked(&self, queued: usize) -> Error {
+        match failed_byte(self.written, queued) {
+            Some(index) if index < self.output => timeout::nack(Some(index)),
+            _ => timeout::nack(None),
+        }
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Wait for the bus, then send a START with `address`
+    fn dma_start(&mut self, address: u8, read: bool) -> Result<(), Error> {
+        use ral::lpi2c::MSR::{MBF, TDF};
+        self.clear_fifo();
+        self.clear_status();
+        self.wait_until(Phase::Address, |msr| {
+            (msr & MBF::mask) == 0 && (msr & TDF::mask) != 0
+        })?;
+        let start = Command::Start { address, read };
+        ral::write_reg!(ral::lpi2c, self.reg, MTDR, start.word());
+        Ok(())
+    }
+
+    /// Queue the DMA transfer's commands while the TX FIFO has room
+    fn dma_queue(&mut self) {
+        let depth = self.tx_depth();
+        while self.tx_count() < depth {
+            match self.dma.as_mut().and_then(DmaCommands::next) {
+                Some(command) => ral::write_reg!(ral::lpi2c, self.reg, MTDR, command.word()),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns `true` once the master sent the DMA transfer's STOP
+    fn dma_stopped(&self) -> bool {
+        use ral::lpi2c::MSR::SDF;
+        let stopped = self.dma.as_ref().map_or(false, |commands| commands.stopped);
+        stopped && ral::read_reg!(ral::lpi2c, self.reg, MSR) & SDF::mask != 0
+    }
+
+    /// Returns the I2C error, if there is one
+    fn dma_error(&mut self) -> Result<(), Error> {
+        let msr = timeout::Master::status(self);
+        timeout::check(msr, Phase::Data).map(|_| ())
+    }
+
+    /// Abort the DMA transfer, after its channel stops
+    fn dma_abort(&mut self) {
+        timeout::abort(self);
+        self.dma = None;
+    }
+
+    /// Abort a transfer that failed before the DMA transfer, returning its error
+    ///
+    /// `written` counts the words written to the TX FIFO, starting with the address.
+    fn dma_failed(&mut self, error: Error, written: usize) -> Error {
+        match error {
+            Error::Timeout(_) | Error::BusStuck => error,
+            Error::NoAcknowledgeAddress | Error::NoAcknowledgeData { .. } => {
+                let error = timeout::nack(failed_byte(written, self.tx_count() as usize));
+                self.dma_abort();
+                error
+            }
+            error => {
+                self.dma_abort();
+                error
+            }
+        }
+    }
+}
+
+impl<M, S, D> dma::Peripheral<I2C<M>, u8, S, D>
+where
+    M: Unsigned,
+    S: dma::buffer::Source<u8>,
+{
+    /// Write `buffer` to the device at `address`, over DMA
+    ///
+    /// The I2C sends the START and address, then the DMA controller moves the bytes.
+    /// Poll the write with [`dma_write_complete()`](#method.dma_write_complete), which
+    /// sends the STOP once the last byte is in the I2C. `buffer` holds between 1 and
+    /// 32767 bytes.
+    ///
+    /// The operation timeout bounds the wait for the bus. Set a
+    /// [pin low timeout](../i2c/struct.I2C.html#method.set_pin_low_timeout) to catch a
+    /// device that holds the bus during the transfer.
+    pub fn dma_write(&mut self, address: u8, buffer: S) -> Result<(), (S, DmaError)> {
+        if !(1..=MAX_DMA_LEN).contains(&buffer.source_len()) {
+            return Err((buffer, DmaError::InvalidLength));
+        }
+        if self.peripheral().dma.is_some() {
+            return Err((buffer, DmaError::Dma(dma::Error::ScheduledTransfer)));
+        }
+        let i2c = self.peripheral_mut();
+        if let Err(error) = i2c.dma_start(address, false) {
+            let error = i2c.dma_failed(error, 0);
+            return Err((buffer, DmaError::I2c { error, byte: None }));
+        }
+        i2c.dma = Some(DmaCommands::new(0, 0));
+        self.start_transfer(buffer).map_err(|(buffer, err)| {
+            self.peripheral_mut().dma_abort();
+            (buffer, DmaError::Dma(err))
+        })
+    }
+
+    /// Returns the buffer once the DMA write is complete, and the STOP is sent
+    ///
+    /// Returns `None` while the write is running. Call `dma_write_complete()` until it
+    /// returns the buffer; the call after the last byte queues the STOP. A NACK, or a
+    /// lost arbitration, cancels the DMA transfer, and returns the index of the byte
+    /// that failed.
+    pub fn dma_write_complete(&mut self) -> Option<Result<S, (S, DmaError)>> {
+        self.peripheral().dma.as_ref()?;
+        if let Err(error) = self.peripheral_mut().dma_error() {
+            // Stop the requests, so that the progress is final
+            dma::peripheral::Destination::<u8>::disable_destination(self.peripheral());
+            let complete = self.is_transfer_complete();
+            let progress = self.transfer_progress();
+            let buffer = if complete {
+                self.transfer_complete()
+            } else {
+                self.transfer_cancel()
+            };
+            let sent = match &buffer {
+                Some(buffer) if complete => buffer.source_len(),
+                _ => progress,
+            };
+            let byte = failed_byte(1 + sent, self.peripheral().tx_count() as usize);
+            let error = match error {
+                Error::NoAcknowledgeAddress | Error::NoAcknowledgeData { .. } => {
+                    timeout::nack(byte)
+                }
+                error => error,
+            };
+            self.peripheral_mut().dma_abort();
+            return buffer.map(|buffer| Err((buffer, DmaError::I2c { error, byte })));
+        }
+        if !self.is_transfer_complete() {
+            return None;
+        }
+        let i2c = self.peripheral_mut();
+        // Stop the requests, so that the STOP doesn't trigger another transfer
+        dma::peripheral::Destination::<u8>::disable_destination(i2c);
+        i2c.dma_queue();
+        if !i2c.dma_stopped() {
+            return None;
+        }
+        i2c.dma = None;
+        self.transfer_complete().map(Ok)
+    }
+}
+
+impl<M, S, D> dma::Peripheral<I2C<M>, u8, S, D>
+where
+    M: Unsigned,
+    D: dma::buffer::Destination<u8>,
+{
+    /// Read `buffer` from the device at `address`, over DMA
+    ///
+    /// The I2C sends the START and address, and queues receive commands, then the DMA
+    /// controller moves the bytes. `buffer` holds between 1 and 32767 bytes. Poll the
+    /// read with [`dma_read_complete()`](#method.dma_read_complete).
+    ///
+    /// Each receive command covers 256 bytes, and the TX FIFO holds four commands, so
+    /// a read of more than 768 bytes needs more commands while it runs. Poll often
+    /// enough to queue them; the clock stretches while the master waits.
+    ///
+    /// # Example
+    ///
+    /// Read a 2KiB calibration blob from an EEPROM, starting at address 0.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, Buffer, Linear};
+    /// use imxrt1060_hal::i2c::I2C;
+    /// # use imxrt1060_hal::iomuxc::consts::U3;
+    ///
+    /// static BLOB: Buffer<[u8; 2048]> = Buffer::new([0; 2048]);
+    /// const EEPROM: u8 = 0x50;
+    ///
+    /// # fn read_blob(i2c: I2C<U3>, channel: dma::Channel) {
+    /// let mut eeprom: dma::Peripheral<_, u8, Linear<u8>> = dma::receive_u8(i2c, channel);
+    /// let buffer = Linear::try_new(&BLOB).unwrap();
+    /// eeprom.dma_write_read(EEPROM, &[0x00, 0x00], buffer).unwrap();
+    /// let buffer = loop {
+    ///     match eeprom.dma_read_complete() {
+    ///         Some(Ok(buffer)) => break buffer,
+    ///         Some(Err((_buffer, err))) => panic!("{:?}", err),
+    ///         None => continue, // Other work...
+    ///     }
+    /// };
+    /// let blob = buffer.as_elements();
+    /// # }
+    /// ```
+    pub fn dma_read(&mut self, address: u8, buffer: D) -> Result<(), (D, DmaError)> {
+        self.dma_write_read(address, &[], buffer)
+    }
+
+    /// Write `output`, then read `buffer` after a repeated START, over DMA
+    ///
+    /// The CPU writes `output`, usually a register or memory address, then the DMA
+    /// controller moves the read bytes, like [`dma_read()`](#method.dma_read). If
+    /// `output` is empty, there's no write.
+    pub fn dma_write_read(
+        &mut self,
+        address: u8,
+        output: &[u8],
+        buffer: D,
+    ) -> Result<(), (D, DmaError)> {
+        let len = buffer.destination_len();
+        if !(1..=MAX_DMA_LEN).contains(&len) {
+            return Err((buffer, DmaError::InvalidLength));
+        }
+        if self.peripheral().dma.is_some() {
+            return Err((buffer, DmaError::Dma(dma::Error::ScheduledTransfer)));
+        }
+        let i2c = self.peripheral_mut();
+        if let Err(error) = i2c.dma_write_output(address, output) {
+            return Err((buffer, DmaError::I2c { error, byte: None }));
+        }
+        i2c.dma = Some(DmaCommands::new(len, output.len()));
+        i2c.dma_queue();
+        self.start_receive(buffer).map_err(|(buffer, err)| {
+            self.peripheral_mut().dma_abort();
+            (buffer, DmaError::Dma(err))
+        })
+    }
+
+    /// Returns the buffer once the DMA read is complete, and the STOP is sent
+    ///
+    /// Returns `None` while the read is running. Each call queues the receive commands
+    /// that fit in the TX FIFO. A NACK, or a lost arbitration, cancels the DMA transfer.
+    /// A NACK fails an address, or an output byte, and a lost arbitration returns the
+    /// index of the byte that the I2C was receiving.
+    pub fn dma_read_complete(&mut self) -> Option<Result<D, (D, DmaError)>> {
+        self.peripheral().dma.as_ref()?;
+        if let Err(error) = self.peripheral_mut().dma_error() {
+            let (error, byte) = match error {
+                Error::NoAcknowledgeAddress | Error::NoAcknowledgeData { .. } => {
+                    let i2c = self.peripheral();
+                    let queued = i2c.tx_count() as usize;
+                    let nacked = i2c.dma.as_ref().map(|commands| commands.nacked(queued));
+                    (nacked.unwrap_or(error), None)
+                }
+                _ if self.is_receive_complete() => (error, None),
+                _ => (error, Some(self.receive_progress())),
+            };
+            let buffer = if self.is_receive_complete() {
+                self.receive_complete()
+            } else {
+                self.receive_cancel()
+            };
+            self.peripheral_mut().dma_abort();
+            return buffer.map(|buffer| Err((buffer, DmaError::I2c { error, byte })));
+        }
+        let i2c = self.peripheral_mut();
+        i2c.dma_queue();
+        if !self.is_receive_complete() || !self.peripheral().dma_stopped() {
+            return None;
+        }
+        self.peripheral_mut().dma = None;
+        self.receive_complete().map(Ok)
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Send the START, and `output`, then a repeated START for the read
+    fn dma_write_output(&mut self, address: u8, output: &[u8]) -> Result<(), Error> {
+        use ral::lpi2c::MSR::TDF;
+        if output.is_empty() {
+            return self
+                .dma_start(address, true)
+                .map_err(|err| self.dma_failed(err, 0));
+        }
+        self.dma_start(address, false)
+            .map_err(|err| self.dma_failed(err, 0))?;
+        for (written, byte) in output.iter().enumerate() {
+            if let Err(err) = self.wait_until(Phase::Data, |msr| (msr & TDF::mask) != 0) {
+                return Err(self.dma_failed(err, 1 + written));
+            }
+            ral::write_reg!(ral::lpi2c, self.reg, MTDR, Command::Transmit(*byte).word());
+        }
+        if let Err(err) = self.wait_until(Phase::Data, |msr| (msr & TDF::mask) != 0) {
+            return Err(self.dma_failed(err, 1 + output.len()));
+        }
+        let start = Command::Start {
+            address,
+            read: true,
+        };
+        ral::write_reg!(ral::lpi2c, self.reg, MTDR, start.word());
+        Ok(())
+    }
+}
+
+unsafe impl<M> dma::peripheral::Source<u8> for I2C<M>
+where
+    M: Unsigned,
+{
+    fn source_signal(&self) -> u32 {
+        DMA_REQUEST_LOOKUP[M::USIZE - 1]
+    }
+    fn source(&self) -> *const u8 {
+        &self.reg.MRDR as *const _ as *const u8
+    }
+    fn enable_source(&self) {
+        cortex_m::interrupt::free(|_| {
+            // Request each byte as it arrives
+            ral::modify_reg!(ral::lpi2c, self.reg, MFCR, RXWATER: 0);
+            ral::modify_reg!(ral::lpi2c, self.reg, MDER, RDDE: 1);
+        });
+    }
+    fn disable_source(&self) {
+        cortex_m::interrupt::free(|_| {
+            ral::modify_reg!(ral::lpi2c, self.reg, MDER, RDDE: 0);
+        });
+    }
+}
+
+/// An 8-bit write to MTDR is a transmit command, so each DMA byte is sent as data
+unsafe impl<M> dma::peripheral::Destination<u8> for I2C<M>
+where
+    M: Unsigned,
+{
+    fn destination_signal(&self) -> u32 {
+        DMA_REQUEST_LOOKUP[M::USIZE - 1]
+    }
+    fn destination(&self) -> *const u8 {
+        &self.reg.MTDR as *const _ as *const u8
+    }
+    fn enable_destination(&self) {
+        cortex_m::interrupt::free(|_| {
+            ral::modify_reg!(ral::lpi2c, self.reg, MDER, TDDE: 1);
+        });
+    }
+    fn disable_destination(&self) {
+        cortex_m::interrupt::free(|_| {
+            ral::modify_reg!(ral::lpi2c, self.reg, MDER, TDDE: 0);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{failed_byte, next_receive, Command, DmaCommands, Error};
+
+    #[test]
+    fn receive_chunks() {
+        // A 2KiB blob takes eight commands
+        let mut commands = DmaCommands::new(2048, 0);
+        let mut receives = 0;
+        while let Some(Command::Receive(count)) = commands.next() {
+            assert_eq!(count, 256);
+            receives += 1;
+        }
+        assert_eq!(receives, 8);
+        assert!(commands.stopped);
+        assert_eq!(commands.next(), None);
+
+        let mut commands = DmaCommands::new(600, 2);
+        let words: [u32; 4] = [
+            commands.next().unwrap().word(),
+            commands.next().unwrap().word(),
+            commands.next().unwrap().word(),
+            commands.next().unwrap().word(),
+        ];
+        assert_eq!(words, [0x1FF, 0x1FF, 0x157, 0x200]);
+
+        assert_eq!(next_receive(1, 0), Some(1));
+        assert_eq!(next_receive(257, 256), Some(1));
+        assert_eq!(next_receive(256, 256), None);
+        assert_eq!(next_receive(0x7FFF, 0x7F00), Some(0xFF));
+    }
+
+    #[test]
+    fn write_is_only_stop() {
+        let mut commands = DmaCommands::new(0, 0);
+        assert_eq!(commands.next(), Some(Command::Stop));
+        assert_eq!(commands.next(), None);
+    }
+
+    #[test]
+    fn failed_bytes() {
+        // The address, with bytes queued behind it
+        assert_eq!(failed_byte(1, 0), None);
+        assert_eq!(failed_byte(3, 2), None);
+        // The master took the address, and two bytes
+        assert_eq!(failed_byte(5, 2), Some(1));
+        // Everything sent; the last byte failed
+        assert_eq!(failed_byte(17, 0), Some(15));
+
+        // A write-read: START, two output bytes, repeated START, receive, STOP
+        let mut commands = DmaCommands::new(1, 2);
+        while commands.next().is_some() {}
+        assert_eq!(commands.nacked(5), Error::NoAcknowledgeAddress);
+        assert_eq!(commands.nacked(4), Error::NoAcknowledgeData { index: 0 });
+        assert_eq!(commands.nacked(3), Error::NoAcknowledgeData { index: 1 });
+        assert_eq!(commands.nacked(2), Error::NoAcknowledgeAddress);
+    }
+}
@@ -0,0 +1,33 @@
+//! `embedded-hal` 1.0 traits
+//!
+//! Enable these implementations with the `"embedded-hal-1"` feature. Transactions use
+//! the same command FIFO as the `embedded_hal` 0.2 `Transactional` implementation.
+
+use super::{Error, I2C};
+use crate::iomuxc::consts::Unsigned;
+use embedded_hal_1::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+impl embedded_hal_1::i2c::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ArbitrationLost => ErrorKind::ArbitrationLoss,
+            Error::NoAcknowledgeAddress => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            Error::NoAcknowledgeData { .. } => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data),
+            Error::PinLowTimeout | Error::FifoError | Error::BusStuck => ErrorKind::Bus,
+            Error::Timeout(_) => ErrorKind::Other,
+        }
+    }
+}
+
+super::transaction::impl_op!(Operation);
+
+impl<M: Unsigned> ErrorType for I2C<M> {
+    type Error = Error;
+}
+
+/// A NACK reports whether the device rejected its address, or data
+impl<M: Unsigned> I2c for I2C<M> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        I2C::transaction(self, address, operations)
+    }
+}
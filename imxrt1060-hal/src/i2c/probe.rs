@@ -0,0 +1,188 @@
+//! Probing for devices, and scanning the bus
+
+use super::{Error, Retries, I2C};
+use crate::iomuxc::consts::Unsigned;
+use core::ops::Range;
+use embedded_hal::blocking::i2c::Operation;
+
+/// How a probe addresses a device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeMethod {
+    /// Write no bytes, sending only the address
+    ///
+    /// Most devices acknowledge their address, then see a STOP.
+    Write,
+    /// Read one byte
+    ///
+    /// Use a read for devices that don't acknowledge a write address.
+    Read,
+}
+
+impl Default for ProbeMethod {
+    fn default() -> Self {
+        ProbeMethod::Write
+    }
+}
+
+/// Returns `true` for the addresses that the I2C specification reserves
+///
+/// That's 0x00 through 0x07, for the general call, START byte, CBUS, other buses,
+/// and high-speed masters, and 0x78 through 0x7F, for 10-bit addressing and device
+/// IDs.
+pub fn is_reserved(address: u8) -> bool {
+    address < 0x08 || (0x78..=0x7F).contains(&address)
+}
+
+/// Returns `true` if a scan probes `address`
+fn is_candidate(address: u8, reserved: bool) -> bool {
+    address <= 0x7F && (reserved || !is_reserved(address))
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Returns `true` if a device acknowledges `address`
+    ///
+    /// A NACK of the address returns `false`. Any other error, like a lost arbitration,
+    /// or a bus that's stuck, returns the error, since it doesn't say whether there's a
+    /// device. The probe is bounded by the operation timeout. The probe follows the
+    /// retry policy for lost arbitration, but never retries a NACK.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::i2c::{ProbeMethod, I2C};
+    /// # use imxrt1060_hal::iomuxc::consts::U3;
+    /// # fn probe(i2c: &mut I2C<U3>) -> Result<(), imxrt1060_hal::i2c::Error> {
+    ///
+    /// const IMU: u8 = 0x68;
+    ///
+    /// if !i2c.probe(IMU, ProbeMethod::Write)? {
+    ///     log::warn!("no IMU; is it powered?");
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn probe(&mut self, address: u8, method: ProbeMethod) -> Result<bool, Error> {
+        let mut byte = [0];
+        let retries = Retries {
+            on_address_nack: 0,
+            ..self.retries
+        };
+        let result = self.with_retries(retries, |i2c| match method {
+            ProbeMethod::Write => i2c.attempt(address, &mut [Operation::Write(&[])]),
+            ProbeMethod::Read => i2c.attempt(address, &mut [Operation::Read(&mut byte)]),
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(Error::NoAcknowledgeAddress) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Probe each address in `addresses`, returning the devices that acknowledge
+    ///
+    /// The scan skips the [reserved addresses](fn.is_reserved.html) and the addresses
+    /// above 0x7F. Change the scan with the [`Scan`](struct.Scan.html) methods.
+    ///
+    /// # Example
+    ///
+    /// Print every device on the bus.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::i2c::I2C;
+    /// # use imxrt1060_hal::iomuxc::consts::U3;
+    /// # fn scan(i2c: &mut I2C<U3>) {
+    ///
+    /// for result in i2c.scan(0..128) {
+    ///     match result {
+    ///         Ok(address) => log::info!("found a device at 0x{:02X}", address),
+    ///         Err((address, err)) => log::warn!("0x{:02X}: {:?}", address, err),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn scan(&mut self, addresses: Range<u8>) -> Scan<'_, M> {
+        Scan {
+            i2c: self,
+            addresses,
+            reserved: false,
+            method: ProbeMethod::default(),
+        }
+    }
+}
+
+/// A bus scan, from [`I2C::scan()`](struct.I2C.html#method.scan)
+///
+/// The scan yields each address that a device acknowledges. An address that fails
+/// with an error other than a NACK yields the address and the error, then the scan
+/// continues.
+pub struct Scan<'a, M> {
+    i2c: &'a mut I2C<M>,
+    addresses: Range<u8>,
+    reserved: bool,
+    method: ProbeMethod,
+}
+
+impl<'a, M> Scan<'a, M> {
+    /// Probe the reserved addresses, too
+    pub fn with_reserved(mut self) -> Self {
+        self.reserved = true;
+        self
+    }
+
+    /// Probe with `method`, instead of a write
+    pub fn with_method(mut self, method: ProbeMethod) -> Self {
+        self.method = method;
+        self
+    }
+}
+
+impl<'a, M> Iterator for Scan<'a, M>
+where
+    M: Unsigned,
+{
+    type Item = Result<u8, (u8, Error)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reserved = self.reserved;
+        while let Some(address) = self
+            .addresses
+            .by_ref()
+            .find(|&address| is_candidate(address, reserved))
+        {
+            match self.i2c.probe(address, self.method) {
+                Ok(true) => return Some(Ok(address)),
+                Ok(false) => continue,
+                Err(err) => return Some(Err((address, err))),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_candidate, is_reserved};
+
+    #[test]
+    fn reserved_addresses() {
+        let reserved = (0..=0x7F).filter(|&address| is_reserved(address)).count();
+        assert_eq!(reserved, 16);
+        assert!(is_reserved(0x00));
+        assert!(is_reserved(0x07));
+        assert!(!is_reserved(0x08));
+        assert!(!is_reserved(0x77));
+        assert!(is_reserved(0x78));
+    }
+
+    #[test]
+    fn scan_candidates() {
+        let candidates = (0..=0xFF).filter(|&address| is_candidate(address, false));
+        assert_eq!(candidates.count(), 112);
+        assert!(is_candidate(0x00, true));
+        assert!(is_candidate(0x7F, true));
+        assert!(!is_candidate(0x80, true));
+        assert!(!is_candidate(0x50 | 0x80, false));
+    }
+}
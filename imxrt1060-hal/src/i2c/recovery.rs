@@ -0,0 +1,420 @@
+//! Recovering a bus that a device holds busy
+
+use super::I2C;
+use crate::gpio::ErasedPin;
+use crate::iomuxc::{consts::Unsigned, gpio, GPR};
+use crate::ral;
+
+/// Timing of the clock pulses that free a stuck bus
+///
+/// The delays count core clock cycles. The default pulses SCL at about 100KHz with a
+/// 600MHz core, and gives a device 1ms to stop stretching the clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryTiming {
+    /// Cycles that SCL spends low, then high, in each pulse
+    pub half_period: u32,
+    /// The most pulses before recovery gives up
+    ///
+    /// A device releases SDA within 9 pulses: the rest of its byte, and the ACK.
+    pub max_pulses: u8,
+    /// Cycles to wait for SCL to rise after the pulse releases it
+    pub stretch: u32,
+}
+
+impl Default for RecoveryTiming {
+    fn default() -> Self {
+        RecoveryTiming {
+            half_period: 3_000,
+            max_pulses: 9,
+            stretch: 600_000,
+        }
+    }
+}
+
+/// An error when recovering the bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryError {
+    /// SDA stayed low after every clock pulse, and the STOP
+    SdaStuck,
+    /// SCL stayed low after recovery released it
+    SclStuck,
+    /// The I2C doesn't have its pads
+    ///
+    /// Build the I2C with [`build_recoverable()`](struct.Builder.html#method.build_recoverable).
+    NoPads,
+}
+
+/// The two bus lines, driven as open-drain outputs
+trait Lines {
+    /// Release SCL if `high`, or pull it low
+    fn scl(&mut self, high: bool);
+    /// Release SDA if `high`, or pull it low
+    fn sda(&mut self, high: bool);
+    /// Returns `true` if SCL is high
+    fn is_scl_high(&self) -> bool;
+    /// Returns `true` if SDA is high
+    fn is_sda_high(&self) -> bool;
+    /// Wait for `cycles` core clock cycles
+    fn delay(&mut self, cycles: u32);
+}
+
+/// Returns `true` if the master status `msr` shows a bus that's busy without this master
+///
+/// Another device holds the bus, or a device holds SDA low after a reset, so the master
+/// can't send a START.
+pub(super) fn is_bus_stuck(msr: u32) -> bool {
+    use ral::lpi2c::MSR::{BBF, MBF};
+    msr & BBF::mask != 0 && msr & MBF::mask == 0
+}
+
+/// Release SCL, and wait for it to rise
+fn release_scl<L: Lines>(lines: &mut L, timing: &RecoveryTiming) -> Result<(), RecoveryError> {
+    lines.scl(true);
+    let step = timing.half_period.max(1);
+    let mut waited = 0;
+    while !lines.is_scl_high() {
+        if waited >= timing.stretch {
+            return Err(RecoveryError::SclStuck);
+        }
+        lines.delay(step);
+        waited = waited.saturating_add(step);
+    }
+    Ok(())
+}
+
+/// Clock SCL until the device releases SDA, then send a STOP
+///
+/// Returns the number of clock pulses. Each pulse lets the device shift out one more
+/// bit; once it's high, SDA is sampled while SCL is high, like the master would sample
+/// a bit. The STOP leaves the device waiting for a START.
+fn recover<L: Lines>(lines: &mut L, timing: &RecoveryTiming) -> Result<u8, RecoveryError> {
+    let half = timing.half_period;
+    lines.sda(true);
+    release_scl(lines, timing)?;
+    lines.delay(half);
+
+    let mut pulses = 0;
+    while !lines.is_sda_high() {
+        if pulses == timing.max_pulses {
+            return Err(RecoveryError::SdaStuck);
+        }
+        lines.scl(false);
+        lines.delay(half);
+        release_scl(lines, timing)?;
+        lines.delay(half);
+        pulses += 1;
+    }
+
+    // SDA falls while SCL is low, then rises while SCL is high
+    lines.scl(false);
+    lines.delay(half);
+    lines.sda(false);
+    lines.delay(half);
+    release_scl(lines, timing)?;
+    lines.delay(half);
+    lines.sda(true);
+    lines.delay(half);
+    if lines.is_sda_high() {
+        Ok(pulses)
+    } else {
+        Err(RecoveryError::SdaStuck)
+    }
+}
+
+/// The mux register's software input on bit, so that PSR reads the pad
+const SION: u32 = 1 << 4;
+/// The pad's open drain enable
+const ODE: u32 = 1 << 11;
+
+/// A bus pad, known at runtime
+///
+/// `BusPad` lets the I2C borrow its pads as GPIOs. The I2C doesn't keep the pad
+/// types, so the pad keeps its registers, and a function that finds its GPIO.
+pub(super) struct BusPad {
+    mux: *mut u32,
+    pad: *mut u32,
+    alt: u32,
+    gpio: fn(&GPR) -> ErasedPin,
+}
+
+// Safety: the pointers are the pad's static registers. The I2C owns the pad, and only
+// touches the registers through a `&mut I2C`.
+unsafe impl Send for BusPad {}
+unsafe impl Sync for BusPad {}
+
+impl BusPad {
+    /// Remember `pad`, which the I2C takes
+    pub(super) fn new<P: gpio::Pin>(pad: &mut P) -> Self {
+        // Safety: the register pointers are static.
+        let (mux, pad) = unsafe { (pad.mux(), pad.pad()) };
+        BusPad {
+            mux,
+            pad,
+            alt: <P as gpio::Pin>::ALT,
+            gpio: ErasedPin::driving::<P>,
+        }
+    }
+
+    /// Switch the pad to an open-drain GPIO that releases the line
+    ///
+    /// The `gpr` tells if the GPIO uses its fast port.
+    fn lend(&self, gpr: &GPR) -> LentPad<'_> {
+        // Safety: the I2C owns the pad, and the registers are static. Reads are atomic.
+        let (mux, pad) = unsafe {
+            (
+                core::ptr::read_volatile(self.mux),
+                core::ptr::read_volatile(self.pad),
+            )
+        };
+        let gpio = (self.gpio)(gpr);
+        let level = gpio.is_set();
+        gpio.set();
+        // Safety: as above. Writes are atomic.
+        unsafe {
+            core::ptr::write_volatile(self.pad, pad | ODE);
+            core::ptr::write_volatile(self.mux, self.alt | SION);
+        }
+        let output = gpio.set_output(true);
+        LentPad {
+            bus_pad: self,
+            gpio,
+            mux,
+            pad,
+            level,
+            output,
+        }
+    }
+}
+
+/// A bus pad in use as a GPIO
+///
+/// Dropping the pad restores its mux and pad registers, and the GPIO level and direction.
+struct LentPad<'a> {
+    bus_pad: &'a BusPad,
+    gpio: ErasedPin,
+    mux: u32,
+    pad: u32,
+    level: bool,
+    output: bool,
+}
+
+impl LentPad<'_> {
+    fn set(&mut self, high: bool) {
+        if high {
+            self.gpio.set();
+        } else {
+            self.gpio.clear();
+        }
+    }
+}
+
+impl Drop for LentPad<'_> {
+    fn drop(&mut self) {
+        self.set(self.level);
+        self.gpio.set_output(self.output);
+        // Safety: the I2C owns the pad. Writes are atomic.
+        unsafe {
+            core::ptr::write_volatile(self.bus_pad.mux, self.mux);
+            core::ptr::write_volatile(self.bus_pad.pad, self.pad);
+        }
+    }
+}
+
+/// The lent SCL and SDA pads
+struct GpioLines<'a> {
+    scl: LentPad<'a>,
+    sda: LentPad<'a>,
+}
+
+impl Lines for GpioLines<'_> {
+    fn scl(&mut self, high: bool) {
+        self.scl.set(high);
+    }
+    fn sda(&mut self, high: bool) {
+        self.sda.set(high);
+    }
+    fn is_scl_high(&self) -> bool {
+        self.scl.gpio.is_high()
+    }
+    fn is_sda_high(&self) -> bool {
+        self.sda.gpio.is_high()
+    }
+    fn delay(&mut self, cycles: u32) {
+        cortex_m::asm::delay(cycles);
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Set the timing of [`recover_bus()`](#method.recover_bus)
+    pub fn set_recovery_timing(&mut self, timing: RecoveryTiming) {
+        self.recovery = timing;
+    }
+
+    /// Free a bus that a device holds busy, usually by holding SDA low
+    ///
+    /// A device that resets, or loses power, in the middle of a transfer may hold
+    /// SDA low, waiting for clocks that never come. The I2C can't send a START, and
+    /// transfers return [`Error::BusStuck`](enum.Error.html#variant.BusStuck).
+    ///
+    /// `recover_bus()` disables the I2C, and switches its pads to open-drain GPIOs. The
+    /// `gpr` tells it which port, fast or normal, drives each pad. Returns
+    /// [`RecoveryError::NoPads`](enum.RecoveryError.html#variant.NoPads), without touching
+    /// the bus, if the I2C doesn't have its pads.
+    /// It clocks SCL until the device releases SDA, up to
+    /// [`max_pulses`](struct.RecoveryTiming.html#structfield.max_pulses) times, then
+    /// sends a STOP. Finally, it switches the pads back, and resets the I2C, keeping its
+    /// clock speed, timeouts, and watermarks. The pads always switch back, even if the
+    /// recovery fails.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use embedded_hal::blocking::i2c::Write;
+    /// use imxrt1060_hal::i2c::{Error, I2C};
+    /// # use imxrt1060_hal::iomuxc::{consts::U3, GPR};
+    /// # fn sensor(i2c: &mut I2C<U3>, gpr: &GPR) {
+    ///
+    /// match i2c.write(0x48, &[0x01, 0x60]) {
+    ///     Err(Error::BusStuck) => i2c.recover_bus(gpr).unwrap(),
+    ///     result => result.unwrap(),
+    /// }
+    /// # }
+    /// ```
+    pub fn recover_bus(&mut self, gpr: &GPR) -> Result<(), RecoveryError> {
+        let (scl, sda) = self.pads.as_ref().ok_or(RecoveryError::NoPads)?;
+        ral::modify_reg!(ral::lpi2c, self.reg, MCR, MEN: MEN_0);
+        let pulses = {
+            let mut lines = GpioLines {
+                scl: scl.lend(gpr),
+                sda: sda.lend(gpr),
+            };
+            recover(&mut lines, &self.recovery)
+        };
+        log::debug!("I2C{} bus recovery: {:?} pulses", M::USIZE, pulses);
+        self.reset();
+        pulses.map(|_| ())
+    }
+
+    /// Reset the I2C, then restore its configuration, and enable it
+    ///
+    /// The reset keeps the doze and debug enables.
+    pub(super) fn reset(&mut self) {
+        let (dozen, dbgen) = ral::read_reg!(ral::lpi2c, self.reg, MCR, DOZEN, DBGEN);
+        let mcfgr0 = ral::read_reg!(ral::lpi2c, self.reg, MCFGR0);
+        let mcfgr1 = ral::read_reg!(ral::lpi2c, self.reg, MCFGR1);
+        let mcfgr2 = ral::read_reg!(ral::lpi2c, self.reg, MCFGR2);
+        let mcfgr3 = ral::read_reg!(ral::lpi2c, self.reg, MCFGR3);
+        let mccr0 = ral::read_reg!(ral::lpi2c, self.reg, MCCR0);
+        let mccr1 = ral::read_reg!(ral::lpi2c, self.reg, MCCR1);
+        let mfcr = ral::read_reg!(ral::lpi2c, self.reg, MFCR);
+        let mier = ral::read_reg!(ral::lpi2c, self.reg, MIER);
+        let mder = ral::read_reg!(ral::lpi2c, self.reg, MDER);
+
+        ral::write_reg!(ral::lpi2c, self.reg, MCR, RST: RST_1);
+        ral::write_reg!(ral::lpi2c, self.reg, MCR, RST: RST_0, DOZEN: dozen, DBGEN: dbgen);
+        // The master is disabled, so the configuration registers are writable
+        ral::write_reg!(ral::lpi2c, self.reg, MCFGR0, mcfgr0);
+        ral::write_reg!(ral::lpi2c, self.reg, MCFGR1, mcfgr1);
+        ral::write_reg!(ral::lpi2c, self.reg, MCFGR2, mcfgr2);
+        ral::write_reg!(ral::lpi2c, self.reg, MCFGR3, mcfgr3);
+        ral::write_reg!(ral::lpi2c, self.reg, MCCR0, mccr0);
+        ral::write_reg!(ral::lpi2c, self.reg, MCCR1, mccr1);
+        ral::write_reg!(ral::lpi2c, self.reg, MFCR, mfcr);
+        ral::write_reg!(ral::lpi2c, self.reg, MIER, mier);
+        ral::write_reg!(ral::lpi2c, self.reg, MDER, mder);
+        ral::modify_reg!(ral::lpi2c, self.reg, MCR, MEN: MEN_1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_bus_stuck, recover, Lines, RecoveryError, RecoveryTiming};
+
+    /// A bus with a device that holds SDA low for some SCL pulses
+    #[derive(Default)]
+    struct Bus {
+        scl_released: bool,
+        sda_released: bool,
+        /// Falling SCL edges until the device releases SDA
+        held_bits: Option<u8>,
+        /// The device holds SCL low
+        stretching: bool,
+        pulses: u8,
+        stops: u8,
+    }
+
+    impl Bus {
+        fn holding(bits: Option<u8>) -> Self {
+            Bus {
+                held_bits: bits,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Lines for Bus {
+        fn scl(&mut self, high: bool) {
+            if self.scl_released && !high {
+                self.pulses += 1;
+                self.held_bits = self.held_bits.map(|bits| bits.saturating_sub(1));
+            }
+            self.scl_released = high;
+        }
+        fn sda(&mut self, high: bool) {
+            if !self.sda_released && high && self.is_scl_high() {
+                self.stops += 1;
+            }
+            self.sda_released = high;
+        }
+        fn is_scl_high(&self) -> bool {
+            self.scl_released && !self.stretching
+        }
+        fn is_sda_high(&self) -> bool {
+            self.sda_released && self.held_bits.map_or(false, |bits| bits == 0)
+        }
+        fn delay(&mut self, _: u32) {}
+    }
+
+    #[test]
+    fn stuck_bus_status() {
+        const MBF: u32 = 1 << 24;
+        const BBF: u32 = 1 << 25;
+        const TDF: u32 = 1;
+        assert!(is_bus_stuck(BBF | TDF));
+        // This master owns the bus
+        assert!(!is_bus_stuck(BBF | MBF));
+        assert!(!is_bus_stuck(TDF));
+    }
+
+    #[test]
+    fn clocks_until_sda_releases() {
+        let timing = RecoveryTiming::default();
+        let mut bus = Bus::holding(Some(3));
+        assert_eq!(recover(&mut bus, &timing), Ok(3));
+        // Three pulses, then the STOP's falling edge
+        assert_eq!(bus.pulses, 4);
+        assert_eq!(bus.stops, 1);
+        assert!(bus.scl_released && bus.sda_released);
+
+        // An idle bus still gets a STOP
+        let mut bus = Bus::holding(Some(0));
+        assert_eq!(recover(&mut bus, &timing), Ok(0));
+        assert_eq!(bus.stops, 1);
+    }
+
+    #[test]
+    fn gives_up_on_stuck_lines() {
+        let timing = RecoveryTiming::default();
+        let mut bus = Bus::holding(None);
+        assert_eq!(recover(&mut bus, &timing), Err(RecoveryError::SdaStuck));
+        assert_eq!(bus.pulses, 9);
+
+        let mut bus = Bus::holding(Some(1));
+        bus.stretching = true;
+        assert_eq!(recover(&mut bus, &timing), Err(RecoveryError::SclStuck));
+        assert_eq!(bus.pulses, 0);
+    }
+}
@@ -0,0 +1,266 @@
+//! Retrying the transactions that another master, or a busy device, interrupts
+
+use super::{timeout, Error, I2C};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::time::Duration;
+
+/// When a blocking transaction tries again
+///
+/// The default never retries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retries {
+    /// The retries after the master loses arbitration to another master
+    pub on_arbitration_lost: u8,
+    /// The retries after a device doesn't acknowledge its address
+    ///
+    /// A busy device, like an EEPROM that's writing its page, ignores its address.
+    pub on_address_nack: u8,
+    /// The wait before each retry
+    pub backoff: Duration,
+}
+
+/// The attempts of one transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Attempts {
+    retries: Retries,
+    arbitration: u8,
+    nacks: u8,
+    attempts: u16,
+}
+
+impl Attempts {
+    pub(super) fn new(retries: Retries) -> Self {
+        Attempts {
+            retries,
+            arbitration: 0,
+            nacks: 0,
+            attempts: 0,
+        }
+    }
+
+    /// Record the result of an attempt, and return `true` to try again
+    ///
+    /// Only a lost arbitration, and a NACK of the address, try again, each until its
+    /// retries run out. Every other result is final.
+    pub(super) fn retry(&mut self, result: &Result<(), Error>) -> bool {
+        self.attempts += 1;
+        match result {
+            Err(Error::ArbitrationLost) if self.arbitration < self.retries.on_arbitration_lost => {
+                self.arbitration += 1;
+                true
+            }
+            Err(Error::NoAcknowledgeAddress) if self.nacks < self.retries.on_address_nack => {
+                self.nacks += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the number of attempts
+    pub(super) fn attempts(&self) -> u16 {
+        self.attempts
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Retry the blocking transactions that lose arbitration, or that a device NACKs
+    ///
+    /// A read, write, or transaction that fails with
+    /// [`Error::ArbitrationLost`](enum.Error.html#variant.ArbitrationLost) or
+    /// [`Error::NoAcknowledgeAddress`](enum.Error.html#variant.NoAcknowledgeAddress)
+    /// waits for the backoff, then starts over, until its retries run out. The last
+    /// error returns, and [`attempts()`](#method.attempts) says how many attempts there
+    /// were. A [probe](#method.probe) never retries a NACK. DMA and async transfers
+    /// don't retry.
+    ///
+    /// Each attempt waits for the bus to be idle, within the operation timeout. A bus
+    /// that another master holds for longer returns
+    /// [`Error::BusStuck`](enum.Error.html#variant.BusStuck). Set a
+    /// [pin low timeout](#method.set_pin_low_timeout), too, so that a master that holds
+    /// SCL or SDA low ends the retries with
+    /// [`Error::PinLowTimeout`](enum.Error.html#variant.PinLowTimeout). The backoff
+    /// assumes a 600MHz core, until
+    /// [`set_operation_timeout()`](#method.set_operation_timeout) supplies the core
+    /// clock.
+    ///
+    /// # Example
+    ///
+    /// Share the bus with another master.
+    ///
+    /// ```no_run
+    /// use core::time::Duration;
+    /// use embedded_hal::blocking::i2c::Write;
+    /// use imxrt1060_hal::i2c::{Retries, I2C};
+    /// # use imxrt1060_hal::iomuxc::consts::U3;
+    /// # fn share(i2c: &mut I2C<U3>) {
+    ///
+    /// i2c.set_pin_low_timeout(Duration::from_millis(25)).unwrap();
+    /// i2c.set_retry_policy(Retries {
+    ///     on_arbitration_lost: 5,
+    ///     on_address_nack: 0,
+    ///     backoff: Duration::from_micros(100),
+    /// });
+    ///
+    /// if let Err(err) = i2c.write(0x20, &[0x01, 0xFF]) {
+    ///     log::warn!("{:?} after {} attempts", err, i2c.attempts());
+    /// }
+    /// # }
+    /// ```
+    pub fn set_retry_policy(&mut self, retries: Retries) {
+        let retrying = retries.on_arbitration_lost != 0 || retries.on_address_nack != 0;
+        if retrying && ral::read_reg!(ral::lpi2c, self.reg, MCFGR3, PINLOW) == 0 {
+            log::warn!("I2C{} retries without a pin low timeout", M::USIZE);
+        }
+        self.retries = retries;
+    }
+
+    /// Returns the number of attempts of the last blocking transaction
+    ///
+    /// That's one, unless the [retry policy](#method.set_retry_policy) tried again.
+    pub fn attempts(&self) -> u16 {
+        self.attempts
+    }
+
+    /// Run `attempt` until it succeeds, or until `retries` says to stop
+    pub(super) fn with_retries<F>(&mut self, retries: Retries, mut attempt: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self) -> Result<(), Error>,
+    {
+        let mut attempts = Attempts::new(retries);
+        loop {
+            let result = attempt(self);
+            let retry = attempts.retry(&result);
+            self.attempts = attempts.attempts();
+            if !retry {
+                return result;
+            }
+            // Without a backoff, retry right away. A zero-cycle delay isn't a
+            // no-op for every cortex-m version.
+            let backoff = timeout::cycles(retries.backoff, self.core_hz);
+            if backoff > 0 {
+                cortex_m::asm::delay(backoff);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::transaction::Commands, Attempts, Error, Retries};
+    use core::time::Duration;
+    use embedded_hal::blocking::i2c::Operation;
+
+    /// Run the attempts that return `results`, and return the final result, and the
+    /// number of attempts
+    fn run(retries: Retries, results: &[Result<(), Error>]) -> (Result<(), Error>, u16) {
+        let mut attempts = Attempts::new(retries);
+        for result in results {
+            if !attempts.retry(result) {
+                return (*result, attempts.attempts());
+            }
+        }
+        panic!("the attempts ran out of results");
+    }
+
+    const RETRIES: Retries = Retries {
+        on_arbitration_lost: 2,
+        on_address_nack: 1,
+        backoff: Duration::from_micros(10),
+    };
+
+    #[test]
+    fn no_retries_by_default() {
+        let retries = Retries::default();
+        assert_eq!(run(retries, &[Ok(())]), (Ok(()), 1));
+        assert_eq!(
+            run(retries, &[Err(Error::ArbitrationLost)]),
+            (Err(Error::ArbitrationLost), 1)
+        );
+        assert_eq!(
+            run(retries, &[Err(Error::NoAcknowledgeAddress)]),
+            (Err(Error::NoAcknowledgeAddress), 1)
+        );
+    }
+
+    #[test]
+    fn retry_until_success() {
+        let results = [
+            Err(Error::ArbitrationLost),
+            Err(Error::NoAcknowledgeAddress),
+            Err(Error::ArbitrationLost),
+            Ok(()),
+        ];
+        assert_eq!(run(RETRIES, &results), (Ok(()), 4));
+    }
+
+    #[test]
+    fn retries_run_out() {
+        let results = [Err(Error::ArbitrationLost); 3];
+        assert_eq!(run(RETRIES, &results), (Err(Error::ArbitrationLost), 3));
+
+        // The NACK retries count on their own
+        let results = [
+            Err(Error::NoAcknowledgeAddress),
+            Err(Error::ArbitrationLost),
+            Err(Error::NoAcknowledgeAddress),
+        ];
+        assert_eq!(
+            run(RETRIES, &results),
+            (Err(Error::NoAcknowledgeAddress), 3)
+        );
+    }
+
+    #[test]
+    fn other_errors_are_final() {
+        for &error in &[
+            Error::NoAcknowledgeData { index: 0 },
+            Error::PinLowTimeout,
+            Error::FifoError,
+            Error::BusStuck,
+            Error::Timeout(super::timeout::Phase::Address),
+        ] {
+            assert_eq!(run(RETRIES, &[Err(error)]), (Err(error), 1));
+        }
+    }
+
+    #[test]
+    fn busy_eeprom_page_write_retries() {
+        // A busy EEPROM NACKs the address of a page write that's longer than the
+        // four-word TX FIFO, while the rest of the page waits for room
+        let ops = [Operation::Write(&[0x01, 0x23, 0xAA, 0xBB, 0xCC])];
+        let mut commands = Commands::new(0x50);
+        for _ in 0..4 {
+            let command = commands.next(&ops).unwrap();
+            commands.sent(command);
+        }
+        assert!(commands.next(&ops).is_some());
+        // The master took the START, and three words are queued
+        let nack = Err(commands.nacked(3));
+        assert_eq!(nack, Err(Error::NoAcknowledgeAddress));
+
+        let mut attempts = Attempts::new(RETRIES);
+        assert!(attempts.retry(&nack));
+        assert!(!attempts.retry(&nack));
+    }
+
+    #[test]
+    fn attempts_count_both_retries() {
+        let retries = Retries {
+            on_arbitration_lost: u8::max_value(),
+            on_address_nack: u8::max_value(),
+            backoff: Duration::from_secs(0),
+        };
+        let mut attempts = Attempts::new(retries);
+        for _ in 0..u8::max_value() {
+            assert!(attempts.retry(&Err(Error::ArbitrationLost)));
+            assert!(attempts.retry(&Err(Error::NoAcknowledgeAddress)));
+        }
+        assert!(!attempts.retry(&Err(Error::ArbitrationLost)));
+        assert_eq!(attempts.attempts(), 511);
+    }
+}
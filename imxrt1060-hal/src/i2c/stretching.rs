@@ -0,0 +1,150 @@
+//! Measuring how long the devices stretch the clock
+
+use super::{timeout, I2C};
+use crate::iomuxc::consts::Unsigned;
+use core::time::Duration;
+
+/// The core clock that the measurements assume, until the I2C knows the core clock
+pub(super) const DEFAULT_CORE_HZ: u32 = 600_000_000;
+
+/// SCL cycles in a byte, with its acknowledge
+const BYTE_CLOCKS: u32 = 9;
+/// The bytes that a wait may take before it counts as a stretch
+///
+/// With the FIFOs kept full, a byte goes by in every wait. The second byte covers the
+/// START, the STOP, and the glitch filters.
+const BYTES: u32 = 2;
+
+/// The clock stretching that the blocking transfers observed
+///
+/// A wait for progress that outlasts two bytes at the clock speed counts as a stretch.
+/// Get it from [`I2C::stretching_observed()`](struct.I2C.html#method.stretching_observed).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stretching {
+    /// The waits that outlasted two bytes
+    pub stretches: u32,
+    /// The longest wait for progress, stretched or not
+    pub longest: Duration,
+}
+
+impl Stretching {
+    /// Returns `true` if a device stretched the clock
+    pub fn observed(&self) -> bool {
+        self.stretches != 0
+    }
+}
+
+/// The waits, in polls
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct Observer {
+    stretches: u32,
+    longest: u32,
+}
+
+impl Observer {
+    /// Record a wait of `polls` polls; a wait longer than `limit` polls is a stretch
+    pub(super) fn observe(&mut self, polls: u32, limit: u32) {
+        if polls > limit {
+            self.stretches = self.stretches.saturating_add(1);
+        }
+        self.longest = self.longest.max(polls);
+    }
+
+    /// Returns the waits, with a `core_hz` core clock
+    pub(super) fn stretching(&self, core_hz: u32) -> Stretching {
+        let cycles = u64::from(self.longest) * u64::from(timeout::POLL_CYCLES);
+        Stretching {
+            stretches: self.stretches,
+            longest: Duration::from_nanos(cycles * 1_000_000_000 / u64::from(core_hz.max(1))),
+        }
+    }
+}
+
+/// Returns the polls in two bytes at `scl_hz`, with a `core_hz` core clock
+pub(super) fn limit(core_hz: u32, scl_hz: u32) -> u32 {
+    let cycles = u64::from(core_hz) * u64::from(BYTES * BYTE_CLOCKS) / u64::from(scl_hz.max(1));
+    timeout::polls(cycles.min(u64::from(u32::max_value())) as u32)
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Returns the clock stretching that the blocking transfers observed
+    ///
+    /// The blocking reads, writes, and transactions time each wait for a byte, or for
+    /// the STOP. The DMA and async transfers don't. The measurements assume a 600MHz
+    /// core, until [`set_operation_timeout()`](#method.set_operation_timeout) supplies
+    /// the core clock. They're at least the true times; the status reads make them a
+    /// little longer.
+    ///
+    /// # Example
+    ///
+    /// Find out whether a sensor stretches the clock during its conversion.
+    ///
+    /// ```no_run
+    /// use embedded_hal::blocking::i2c::WriteRead;
+    /// use imxrt1060_hal::i2c::I2C;
+    /// # use imxrt1060_hal::iomuxc::consts::U3;
+    /// # fn measure(i2c: &mut I2C<U3>) {
+    ///
+    /// i2c.clear_stretching_observed();
+    /// let mut sample = [0; 2];
+    /// i2c.write_read(0x40, &[0xE3], &mut sample).unwrap();
+    ///
+    /// let stretching = i2c.stretching_observed();
+    /// if stretching.observed() {
+    ///     log::info!("the longest wait was {:?}", stretching.longest);
+    /// }
+    /// # }
+    /// ```
+    pub fn stretching_observed(&self) -> Stretching {
+        self.stretching.stretching(self.core_hz)
+    }
+
+    /// Forget the clock stretching that the transfers observed
+    pub fn clear_stretching_observed(&mut self) {
+        self.stretching = Observer::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{limit, Observer, Stretching};
+    use core::time::Duration;
+
+    #[test]
+    fn two_byte_limit() {
+        // 18 SCL cycles at 100KHz is 108,000 cycles at 600MHz, or 1,687.5 polls
+        assert_eq!(limit(600_000_000, 100_000), 1_688);
+        assert_eq!(limit(600_000_000, 1_000_000), 169);
+        // A slow core still gets one poll
+        assert_eq!(limit(24_000_000, 1_000_000), 7);
+        assert_eq!(limit(1_000, 1_000_000), 1);
+        assert_eq!(limit(600_000_000, 0), u32::max_value() / 64 + 1);
+    }
+
+    #[test]
+    fn observe_waits() {
+        let mut observer = Observer::default();
+        assert!(!observer.stretching(600_000_000).observed());
+
+        observer.observe(10, 100);
+        observer.observe(100, 100);
+        assert_eq!(
+            observer.stretching(600_000_000),
+            Stretching {
+                stretches: 0,
+                longest: Duration::from_nanos(100 * 64 * 1_000 / 600),
+            }
+        );
+
+        // A 20ms stretch at 600MHz
+        observer.observe(187_500, 100);
+        observer.observe(50, 100);
+        let stretching = observer.stretching(600_000_000);
+        assert!(stretching.observed());
+        assert_eq!(stretching.stretches, 1);
+        assert_eq!(stretching.longest, Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,455 @@
+//! I2C target (slave) mode
+//!
+//! The LPI2C slave logic answers one or two 7-bit addresses. It stretches the clock
+//! after the address, before each byte it sends, and while a received byte is
+//! unread, so the firmware answers at its own pace.
+
+use super::timing::cycles;
+use crate::ccm;
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::marker::PhantomData;
+
+/// The glitch filter width, in nanoseconds
+///
+/// The I2C specification's Fast-mode suppresses spikes up to 50ns.
+const FILTER_NS: u64 = 50;
+/// The SCL hold time after a clock stretch, in nanoseconds
+///
+/// That's the Standard-mode data setup time, tSU;DAT.
+const CLKHOLD_NS: u64 = 250;
+/// The SDA hold time after SCL falls, in nanoseconds
+const DATAVD_NS: u64 = 100;
+const MAX_FILTER: u32 = 15;
+const MAX_CLKHOLD: u32 = 15;
+const MAX_DATAVD: u32 = 63;
+
+/// An event on the I2C bus, from [`Target::poll_event()`](struct.Target.html#method.poll_event)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A controller addressed the target to write bytes
+    ///
+    /// `ByteReceived` events follow. `address` is the one that matched, or 0 for a
+    /// general call.
+    AddressedForWrite {
+        /// The target address that matched
+        address: u8,
+    },
+    /// The controller wrote a byte
+    ByteReceived(u8),
+    /// A controller addressed the target to read bytes
+    ///
+    /// `TransmitRequested` events follow.
+    AddressedForRead {
+        /// The target address that matched
+        address: u8,
+    },
+    /// The controller waits for the next byte
+    ///
+    /// The target stretches the clock until you call
+    /// [`transmit()`](struct.Target.html#method.transmit).
+    TransmitRequested,
+    /// The controller sent a STOP
+    StopDetected,
+    /// SDA didn't match the bit that the target sent
+    ///
+    /// Another device drove the bus. The controller usually ends the transfer.
+    BitError,
+    /// The target received a byte before the last byte was read, or had nothing
+    /// to send
+    FifoError,
+}
+
+/// Indicates that an address isn't a 7-bit address
+#[derive(Debug)]
+pub struct TargetAddressError(());
+
+/// The LPI2C slave, as the event decoding sees it
+pub(super) trait Slave {
+    /// Returns the slave status register, SSR
+    fn status(&mut self) -> u32;
+    /// Returns the slave address status register, SASR, which clears AVF
+    fn address(&mut self) -> u32;
+    /// Returns the slave receive data register, SRDR, which clears RDF
+    fn data(&mut self) -> u32;
+    /// Clear the `flags` in SSR
+    fn clear(&mut self, flags: u32);
+}
+
+/// Returns the next event, in bus order
+///
+/// A received byte comes before the STOP, or the repeated START, that follows it,
+/// and the STOP comes before the next transfer's address.
+pub(super) fn next_event<S: Slave>(slave: &mut S) -> Option<Event> {
+    use ral::lpi2c::{SASR, SRDR, SSR};
+    let ssr = slave.status();
+    if ssr & SSR::BEF::mask != 0 {
+        slave.clear(SSR::BEF::mask);
+        return Some(Event::BitError);
+    }
+    if ssr & SSR::FEF::mask != 0 {
+        slave.clear(SSR::FEF::mask);
+        return Some(Event::FifoError);
+    }
+    if ssr & SSR::RDF::mask != 0 {
+        let srdr = slave.data();
+        if srdr & SRDR::RXEMPTY::mask == 0 {
+            let byte = (srdr & SRDR::DATA::mask) >> SRDR::DATA::offset;
+            return Some(Event::ByteReceived(byte as u8));
+        }
+    }
+    if ssr & SSR::SDF::mask != 0 {
+        slave.clear(SSR::SDF::mask | SSR::RSF::mask);
+        return Some(Event::StopDetected);
+    }
+    if ssr & SSR::AVF::mask != 0 {
+        slave.clear(SSR::RSF::mask);
+        let sasr = slave.address();
+        if sasr & SASR::ANV::mask != 0 {
+            return None;
+        }
+        // RADDR holds the address byte: the address, then the R/W bit
+        let raddr = (sasr & SASR::RADDR::mask) >> SASR::RADDR::offset;
+        let address = ((raddr >> 1) & 0x7F) as u8;
+        return if raddr & 1 != 0 {
+            Some(Event::AddressedForRead { address })
+        } else {
+            Some(Event::AddressedForWrite { address })
+        };
+    }
+    if ssr & SSR::TDF::mask != 0 {
+        Some(Event::TransmitRequested)
+    } else {
+        None
+    }
+}
+
+fn check_address(address: u8) -> Result<u8, TargetAddressError> {
+    if address <= 0x7F {
+        Ok(address)
+    } else {
+        Err(TargetAddressError(()))
+    }
+}
+
+/// An I2C target
+///
+/// Build a target with [`Builder::build_target()`](struct.Builder.html#method.build_target).
+/// Call [`poll_event()`](#method.poll_event) in a loop, or from the LPI2C interrupt
+/// after [`set_interrupts()`](#method.set_interrupts), and answer each
+/// `TransmitRequested` with [`transmit()`](#method.transmit).
+///
+/// # Example
+///
+/// Emulate a sensor with 16 registers. A controller writes a register number, then
+/// optionally writes the registers that follow, or reads them after a repeated
+/// START.
+///
+/// ```no_run
+/// use imxrt1060_hal::i2c::Event;
+///
+/// const SENSOR: u8 = 0x48;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let (_, _, i2c3_builder, _) = peripherals.i2c.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::i2c::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::i2c::PrescalarSelect::DIVIDE_3,
+/// );
+/// let mut target = i2c3_builder
+///     .build_target(
+///         peripherals.iomuxc.ad_b1.p07,
+///         peripherals.iomuxc.ad_b1.p06,
+///         SENSOR,
+///     )
+///     .unwrap();
+///
+/// let mut registers = [0u8; 16];
+/// let mut pointer = 0;
+/// let mut pointer_written = false;
+/// loop {
+///     // Other work updates the registers...
+///     registers[0] = registers[0].wrapping_add(1);
+///     while let Some(event) = target.poll_event() {
+///         match event {
+///             Event::AddressedForWrite { .. } => pointer_written = false,
+///             Event::ByteReceived(byte) if !pointer_written => {
+///                 pointer = usize::from(byte) % registers.len();
+///                 pointer_written = true;
+///             }
+///             Event::ByteReceived(byte) => {
+///                 registers[pointer] = byte;
+///                 pointer = (pointer + 1) % registers.len();
+///             }
+///             Event::TransmitRequested => {
+///                 target.transmit(registers[pointer]);
+///                 pointer = (pointer + 1) % registers.len();
+///             }
+///             _ => (),
+///         }
+///     }
+/// }
+/// ```
+pub struct Target<M> {
+    reg: ral::lpi2c::Instance,
+    _module: PhantomData<M>,
+    /// LPI2C effective input clock frequency
+    source_clock: ccm::Frequency,
+    addresses: (u8, Option<u8>),
+    general_call: bool,
+}
+
+impl<M> Target<M>
+where
+    M: Unsigned,
+{
+    pub(super) fn new(
+        source_clock: ccm::Frequency,
+        reg: ral::lpi2c::Instance,
+        address: u8,
+    ) -> Result<Self, TargetAddressError> {
+        let mut target = Target {
+            reg,
+            _module: PhantomData,
+            source_clock,
+            addresses: (check_address(address)?, None),
+            general_call: false,
+        };
+        ral::write_reg!(ral::lpi2c, target.reg, MCR, RST: RST_1);
+        ral::write_reg!(ral::lpi2c, target.reg, MCR, 0);
+        target.configure();
+        Ok(target)
+    }
+
+    /// Reset the slave, then write the configuration, and enable it
+    fn configure(&mut self) {
+        let root_hz = self.source_clock.0;
+        let filter = cycles(root_hz, 0, FILTER_NS).min(MAX_FILTER);
+        let clkhold = cycles(root_hz, 0, CLKHOLD_NS).min(MAX_CLKHOLD);
+        let datavd = cycles(root_hz, 0, DATAVD_NS).min(MAX_DATAVD);
+        let (address0, address1) = self.addresses;
+        // 0b000: address 0; 0b010: address 0, or address 1
+        let addrcfg = if address1.is_some() { 0b010 } else { 0b000 };
+
+        ral::write_reg!(ral::lpi2c, self.reg, SCR, RST: RST_1);
+        ral::write_reg!(ral::lpi2c, self.reg, SCR, 0);
+        ral::write_reg!(
+            ral::lpi2c,
+            self.reg,
+            SAMR,
+            ADDR0: u32::from(address0),
+            ADDR1: u32::from(address1.unwrap_or(0))
+        );
+        ral::write_reg!(
+            ral::lpi2c,
+            self.reg,
+            SCFGR1,
+            ADDRCFG: addrcfg,
+            GCEN: self.general_call as u32,
+            TXDSTALL: 1,
+            RXSTALL: 1,
+            ADRSTALL: 1
+        );
+        ral::write_reg!(
+            ral::lpi2c,
+            self.reg,
+            SCFGR2,
+            FILTSDA: filter,
+            FILTSCL: filter,
+            DATAVD: datavd,
+            CLKHOLD: clkhold
+        );
+        ral::write_reg!(ral::lpi2c, self.reg, SSR, 0xFFFF_FFFF);
+        ral::write_reg!(ral::lpi2c, self.reg, SCR, FILTEN: 1, SEN: 1);
+    }
+
+    /// Set the target addresses
+    ///
+    /// The target answers `address`, and `second`, if it's `Some`. Returns an error
+    /// if an address isn't a 7-bit address; then, the addresses don't change. This
+    /// resets the target, abandoning a transfer.
+    pub fn set_addresses(
+        &mut self,
+        address: u8,
+        second: Option<u8>,
+    ) -> Result<(), TargetAddressError> {
+        let second = second.map(check_address).transpose()?;
+        self.addresses = (check_address(address)?, second);
+        self.configure();
+        Ok(())
+    }
+
+    /// Answer, or ignore, the general call address, 0
+    ///
+    /// A general call is an `AddressedForWrite` event with address 0. This resets the
+    /// target, abandoning a transfer.
+    pub fn set_general_call(&mut self, general_call: bool) {
+        self.general_call = general_call;
+        self.configure();
+    }
+
+    /// Returns the next bus event, or `None` if nothing happened
+    ///
+    /// Call `poll_event()` until it returns `None`; the hardware stretches the clock
+    /// until you handle the events that need an answer.
+    pub fn poll_event(&mut self) -> Option<Event> {
+        let event = next_event(self);
+        if let Some(event) = event {
+            log::trace!("I2C{} target event {:?}", M::USIZE, event);
+        }
+        event
+    }
+
+    /// Send `byte`, answering a `TransmitRequested` event
+    pub fn transmit(&mut self, byte: u8) {
+        ral::write_reg!(ral::lpi2c, self.reg, STDR, DATA: u32::from(byte));
+    }
+
+    /// Enable, or disable, the interrupts for every event
+    ///
+    /// In the LPI2C interrupt, call [`poll_event()`](#method.poll_event) until it
+    /// returns `None`. A `TransmitRequested` event interrupts until you call
+    /// [`transmit()`](#method.transmit).
+    pub fn set_interrupts(&mut self, enable: bool) {
+        let enable = enable as u32;
+        ral::write_reg!(
+            ral::lpi2c,
+            self.reg,
+            SIER,
+            TDIE: enable,
+            RDIE: enable,
+            AVIE: enable,
+            SDIE: enable,
+            BEIE: enable,
+            FEIE: enable
+        );
+    }
+}
+
+impl<M> Slave for Target<M>
+where
+    M: Unsigned,
+{
+    fn status(&mut self) -> u32 {
+        ral::read_reg!(ral::lpi2c, self.reg, SSR)
+    }
+    fn address(&mut self) -> u32 {
+        ral::read_reg!(ral::lpi2c, self.reg, SASR)
+    }
+    fn data(&mut self) -> u32 {
+        ral::read_reg!(ral::lpi2c, self.reg, SRDR)
+    }
+    fn clear(&mut self, flags: u32) {
+        ral::write_reg!(ral::lpi2c, self.reg, SSR, flags);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_address, next_event, Event, Slave};
+    use crate::testing::EventLog;
+
+    const TDF: u32 = 1 << 0;
+    const RDF: u32 = 1 << 1;
+    const AVF: u32 = 1 << 2;
+    const RSF: u32 = 1 << 8;
+    const SDF: u32 = 1 << 9;
+    const BEF: u32 = 1 << 10;
+    const RXEMPTY: u32 = 1 << 14;
+    const ANV: u32 = 1 << 14;
+
+    /// A slave with some flags, and one address byte and one data byte
+    struct Mock {
+        ssr: u32,
+        sasr: u32,
+        srdr: u32,
+    }
+
+    impl Slave for Mock {
+        fn status(&mut self) -> u32 {
+            self.ssr
+        }
+        fn address(&mut self) -> u32 {
+            self.ssr &= !AVF;
+            core::mem::replace(&mut self.sasr, ANV)
+        }
+        fn data(&mut self) -> u32 {
+            self.ssr &= !RDF;
+            core::mem::replace(&mut self.srdr, RXEMPTY)
+        }
+        fn clear(&mut self, flags: u32) {
+            self.ssr &= !flags;
+        }
+    }
+
+    /// Poll up to four events, stopping at the first `None`
+    fn events(mock: &mut Mock) -> EventLog<Event> {
+        let mut log = EventLog::default();
+        while let Some(event) = next_event(mock) {
+            log.push(event);
+            if log.events().count() == 4 {
+                break;
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn write_then_repeated_start_read() {
+        // A register number, then a repeated START read from 0x48, pending together
+        let mut mock = Mock {
+            ssr: RDF | RSF | AVF,
+            sasr: 0x48 << 1 | 1,
+            srdr: 0x07,
+        };
+        events(&mut mock).assert(&[
+            Event::ByteReceived(0x07),
+            Event::AddressedForRead { address: 0x48 },
+        ]);
+        // The hardware asks for the first byte
+        mock.ssr |= TDF;
+        assert_eq!(next_event(&mut mock), Some(Event::TransmitRequested));
+    }
+
+    #[test]
+    fn stop_before_next_address() {
+        let mut mock = Mock {
+            ssr: SDF | AVF | RDF,
+            sasr: 0x49 << 1,
+            srdr: 0xAB,
+        };
+        events(&mut mock).assert(&[
+            Event::ByteReceived(0xAB),
+            Event::StopDetected,
+            Event::AddressedForWrite { address: 0x49 },
+        ]);
+    }
+
+    #[test]
+    fn errors_first() {
+        let mut mock = Mock {
+            ssr: BEF | TDF,
+            sasr: ANV,
+            srdr: RXEMPTY,
+        };
+        events(&mut mock).assert(&[
+            Event::BitError,
+            Event::TransmitRequested,
+            Event::TransmitRequested,
+            Event::TransmitRequested,
+        ]);
+    }
+
+    #[test]
+    fn empty_reads() {
+        // A stale flag, but no byte, and no valid address
+        let mut mock = Mock {
+            ssr: RDF | AVF,
+            sasr: ANV,
+            srdr: RXEMPTY,
+        };
+        events(&mut mock).assert(&[]);
+        assert!(check_address(0x7F).is_ok());
+        assert!(check_address(0x80).is_err());
+    }
+}
@@ -0,0 +1,506 @@
+//! Bounded waits, and aborting the transfers that time out
+
+use super::{recovery::is_bus_stuck, Error, I2C};
+use crate::ccm;
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::time::Duration;
+
+/// Core clock cycles between two polls of the status flags
+pub(super) const POLL_CYCLES: u32 = 64;
+/// The default number of polls before a wait times out
+///
+/// That's about 10ms with a 600MHz core.
+pub(super) const DEFAULT_POLLS: u32 = 100_000;
+
+/// The part of a transfer that timed out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for the bus, or sending the address after a START or a repeated START
+    Address,
+    /// Sending or receiving data
+    ///
+    /// This includes the wait for the last written byte before a repeated START.
+    Data,
+    /// Waiting for the STOP
+    Stop,
+}
+
+/// The LPI2C master, as the bounded waits see it
+pub(super) trait Master {
+    /// Returns the master status register, MSR
+    fn status(&mut self) -> u32;
+    /// Take a byte from the RX FIFO, if there is one
+    fn receive(&mut self) -> Option<u8>;
+    /// Put a STOP command in the TX FIFO
+    fn stop(&mut self);
+    /// Discard the words in both FIFOs
+    fn clear_fifo(&mut self);
+    /// Clear the status flags
+    fn clear_status(&mut self);
+    /// Reset the I2C, keeping its configuration
+    fn reset(&mut self);
+    /// Wait between two polls
+    fn delay(&mut self);
+    /// Returns the polls before a wait times out
+    fn timeout_polls(&self) -> u32;
+}
+
+/// Returns the NACK error for the byte that the master was sending
+///
+/// `byte` is the index of a data byte, or `None` for the address.
+pub(super) fn nack(byte: Option<usize>) -> Error {
+    match byte {
+        Some(index) => Error::NoAcknowledgeData { index },
+        None => Error::NoAcknowledgeAddress,
+    }
+}
+
+/// Returns `msr`, or the error that it shows
+///
+/// A NACK in the `Address` phase is a NACK of the address. Otherwise, it's a NACK of
+/// data byte 0; callers that know which byte was on the bus replace the index.
+pub(super) fn check(msr: u32, phase: Phase) -> Result<u32, Error> {
+    use ral::lpi2c::MSR::*;
+    if (msr & PLTF::mask) != 0 {
+        Err(Error::PinLowTimeout)
+    } else if (msr & ALF::mask) != 0 {
+        Err(Error::ArbitrationLost)
+    } else if (msr & NDF::mask) != 0 {
+        Err(nack(Some(0).filter(|_| phase != Phase::Address)))
+    } else if (msr & FEF::mask) != 0 {
+        Err(Error::FifoError)
+    } else {
+        Ok(msr)
+    }
+}
+
+/// Poll `master` until `ready` returns a value, for at most `polls` polls
+///
+/// `ready` sees the status of each poll. Status errors return immediately, without
+/// aborting; pass them to [`fail()`](fn.fail.html) once you know which byte failed. If
+/// the polls run out, the transfer aborts, and the wait returns
+/// [`Error::BusStuck`](enum.Error.html#variant.BusStuck) if the bus is busy without
+/// the master, or a timeout in `phase`.
+pub(super) fn wait<B, T, F>(
+    master: &mut B,
+    polls: u32,
+    phase: Phase,
+    mut ready: F,
+) -> Result<T, Error>
+where
+    B: Master,
+    F: FnMut(&mut B, u32) -> Option<T>,
+{
+    for _ in 0..polls {
+        let msr = check(master.status(), phase)?;
+        if let Some(value) = ready(master, msr) {
+            return Ok(value);
+        }
+        master.delay();
+    }
+    let stuck = is_bus_stuck(master.status());
+    abort(master);
+    Err(if stuck {
+        Error::BusStuck
+    } else {
+        Error::Timeout(phase)
+    })
+}
+
+/// Abort the transfer that failed with `error`, and return the error
+///
+/// A timeout already aborted the transfer. Every other error aborts it here, so no error
+/// leaves words in the FIFOs for the next transfer.
+pub(super) fn fail<B: Master>(master: &mut B, error: Error) -> Error {
+    match error {
+        Error::Timeout(_) | Error::BusStuck => (),
+        _ => abort(master),
+    }
+    error
+}
+
+/// Abort the transfer, and leave the master idle
+///
+/// The FIFOs are discarded. If the master still holds the bus, it sends a STOP once the
+/// current byte finishes. A device may stretch the clock before then, so the STOP has the
+/// operation timeout. If the STOP doesn't finish, the I2C resets.
+pub(super) fn abort<B: Master>(master: &mut B) {
+    use ral::lpi2c::MSR::MBF;
+    master.clear_fifo();
+    if master.status() & MBF::mask != 0 {
+        master.stop();
+        let limit = master.timeout_polls();
+        let mut polls = 0;
+        while master.status() & MBF::mask != 0 {
+            if polls == limit {
+                master.reset();
+                break;
+            }
+            master.delay();
+            polls += 1;
+        }
+    }
+    master.clear_status();
+}
+
+/// Returns the number of polls that take at least `cycles` core clock cycles
+pub(super) fn polls(cycles: u32) -> u32 {
+    let polls = cycles / POLL_CYCLES + u32::from(cycles % POLL_CYCLES != 0);
+    polls.max(1)
+}
+
+/// The largest MCFGR3[PINLOW] value
+const MAX_PIN_LOW: u128 = 0xFFF;
+
+/// Returns the MCFGR3[PINLOW] value for a pin low timeout of at least `timeout`
+///
+/// The LPI2C counts PINLOW steps of 256 prescaled root clock cycles. Returns `None` if
+/// the timeout needs more than 0xFFF steps. A zero timeout disables the detection.
+pub(super) fn pin_low(timeout: Duration, root_hz: u32, prescaler: u32) -> Option<u32> {
+    let step = 1_000_000_000u128 << (prescaler + 8);
+    let steps = (timeout.as_nanos() * u128::from(root_hz) + step - 1) / step;
+    if steps <= MAX_PIN_LOW {
+        Some(steps as u32)
+    } else {
+        None
+    }
+}
+
+/// Returns the core clock cycles in `timeout`, saturating
+pub(super) fn cycles(timeout: Duration, arm_hz: u32) -> u32 {
+    let cycles = timeout.as_nanos() * u128::from(arm_hz) / 1_000_000_000;
+    cycles.min(u128::from(u32::max_value())) as u32
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Abort a transfer that waits longer than `cycles` core clock cycles
+    ///
+    /// Every wait in a transfer, like the wait for the bus, for a byte, or for the STOP,
+    /// is bounded by the timeout. When a wait times out, the transfer discards the FIFOs,
+    /// sends a STOP, if the I2C holds the bus, and returns
+    /// [`Error::Timeout`](enum.Error.html#variant.Timeout). The timeout is at least
+    /// `cycles`; the status reads make it a little longer. The default is the time of
+    /// 100,000 polls, about 10ms with a 600MHz core.
+    ///
+    /// The timeout restarts with each byte, and a device that stretches the clock holds
+    /// off the next byte, so the timeout also bounds the clock stretching. There's no
+    /// shorter wait in a transfer. If your device stretches the clock for longer than
+    /// 10ms, raise the timeout; see
+    /// [`stretching_observed()`](#method.stretching_observed) to measure it. A pin low
+    /// timeout, if you set one, must be longer than the stretching, too.
+    pub fn set_operation_timeout_cycles(&mut self, cycles: u32) {
+        self.timeout_polls = polls(cycles);
+    }
+
+    /// Abort a transfer that waits longer than `timeout`
+    ///
+    /// `handle` supplies the current core clock. The timeout counts cycles, so set it
+    /// again after you change the core clock. See
+    /// [`set_operation_timeout_cycles()`](#method.set_operation_timeout_cycles).
+    ///
+    /// # Example
+    ///
+    /// Probe for a device that might not be there, without hanging.
+    ///
+    /// ```no_run
+    /// use core::time::Duration;
+    /// use embedded_hal::blocking::i2c::Write;
+    /// use imxrt1060_hal::i2c::{Error, Phase, I2C};
+    /// # use imxrt1060_hal::{ccm, iomuxc::consts::U3};
+    /// # fn probe(i2c: &mut I2C<U3>, handle: &ccm::Handle) {
+    ///
+    /// i2c.set_operation_timeout(Duration::from_millis(2), handle);
+    /// match i2c.write(0x48, &[]) {
+    ///     Ok(()) => log::info!("found a device at 0x48"),
+    ///     Err(Error::NoAcknowledgeAddress) => log::info!("no device at 0x48"),
+    ///     Err(Error::Timeout(Phase::Address)) => log::warn!("no pull-ups?"),
+    ///     Err(err) => log::warn!("{:?}", err),
+    /// }
+    /// # }
+    /// ```
+    pub fn set_operation_timeout(&mut self, timeout: Duration, handle: &ccm::Handle) {
+        let arm_hz = handle.frequencies().arm.hz();
+        self.core_hz = arm_hz;
+        self.set_operation_timeout_cycles(cycles(timeout, arm_hz));
+    }
+
+    /// Wait for `ready` to accept the status, within the operation timeout
+    pub(super) fn wait_until<F>(&mut self, phase: Phase, ready: F) -> Result<(), Error>
+    where
+        F: Fn(u32) -> bool,
+    {
+        let polls = self.timeout_polls;
+        wait(
+            self,
+            polls,
+            phase,
+            |_, msr| if ready(msr) { Some(()) } else { None },
+        )
+    }
+}
+
+impl<M> Master for I2C<M>
+where
+    M: Unsigned,
+{
+    fn status(&mut self) -> u32 {
+        ral::read_reg!(ral::lpi2c, self.reg, MSR)
+    }
+    fn receive(&mut self) -> Option<u8> {
+        use ral::lpi2c::MRDR::*;
+        let mrdr = ral::read_reg!(ral::lpi2c, self.reg, MRDR);
+        if mrdr & RXEMPTY::mask == 0 {
+            Some(((mrdr & DATA::mask) >> DATA::offset) as u8)
+        } else {
+            None
+        }
+    }
+    fn stop(&mut self) {
+        ral::write_reg!(ral::lpi2c, self.reg, MTDR, CMD: CMD_2);
+    }
+    fn clear_fifo(&mut self) {
+        I2C::clear_fifo(self);
+    }
+    fn clear_status(&mut self) {
+        I2C::clear_status(self);
+    }
+    fn reset(&mut self) {
+        I2C::reset(self);
+    }
+    fn delay(&mut self) {
+        cortex_m::asm::delay(POLL_CYCLES);
+    }
+    fn timeout_polls(&self) -> u32 {
+        self.timeout_polls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{abort, check, cycles, fail, pin_low, polls, wait, Error, Master, Phase};
+    use crate::testing::EventLog;
+
+    const MBF: u32 = 1 << 24;
+    const BBF: u32 = 1 << 25;
+    const NDF: u32 = 1 << 10;
+    const ALF: u32 = 1 << 11;
+    const FEF: u32 = 1 << 12;
+    const PLTF: u32 = 1 << 13;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Op {
+        ClearFifo,
+        Stop,
+        Reset,
+        ClearStatus,
+    }
+
+    /// A master that stays busy for some polls, and records the cleanup
+    struct Mock {
+        msr: u32,
+        /// Polls until the master finishes a STOP, or `None` if it never does
+        stop_polls: Option<u32>,
+        stopping: bool,
+        polls: u32,
+        /// The operation timeout
+        timeout_polls: u32,
+        ops: EventLog<Op>,
+    }
+
+    impl Mock {
+        fn new(msr: u32, stop_polls: Option<u32>) -> Self {
+            Mock {
+                msr,
+                stop_polls,
+                stopping: false,
+                polls: 0,
+                timeout_polls: 1_000,
+                ops: EventLog::default(),
+            }
+        }
+        fn record(&mut self, op: Op) {
+            self.ops.push(op);
+        }
+    }
+
+    impl Master for Mock {
+        fn status(&mut self) -> u32 {
+            self.msr
+        }
+        fn receive(&mut self) -> Option<u8> {
+            None
+        }
+        fn stop(&mut self) {
+            self.stopping = true;
+            self.record(Op::Stop);
+        }
+        fn clear_fifo(&mut self) {
+            self.record(Op::ClearFifo);
+        }
+        fn clear_status(&mut self) {
+            self.record(Op::ClearStatus);
+        }
+        fn reset(&mut self) {
+            self.msr = 0;
+            self.record(Op::Reset);
+        }
+        fn delay(&mut self) {
+            self.polls += 1;
+            if self.stopping {
+                match self.stop_polls.as_mut() {
+                    Some(0) => self.msr &= !(MBF | BBF),
+                    Some(polls) => *polls -= 1,
+                    None => (),
+                }
+            }
+        }
+        fn timeout_polls(&self) -> u32 {
+            self.timeout_polls
+        }
+    }
+
+    #[test]
+    fn timeout_sends_stop() {
+        let mut master = Mock::new(MBF | BBF, Some(3));
+        let result = wait(&mut master, 10, Phase::Data, |_, _| None::<()>);
+        assert_eq!(result, Err(Error::Timeout(Phase::Data)));
+        master
+            .ops
+            .assert(&[Op::ClearFifo, Op::Stop, Op::ClearStatus]);
+        // The wait's polls, then the STOP's polls
+        assert_eq!(master.polls, 14);
+    }
+
+    #[test]
+    fn stuck_stop_resets() {
+        let mut master = Mock::new(MBF | BBF, None);
+        abort(&mut master);
+        master
+            .ops
+            .assert(&[Op::ClearFifo, Op::Stop, Op::Reset, Op::ClearStatus]);
+        assert_eq!(master.polls, 1_000);
+    }
+
+    #[test]
+    fn stretched_stop_finishes() {
+        // The device holds SCL for longer than 1,000 polls
+        let mut master = Mock::new(MBF | BBF, Some(4_000));
+        master.timeout_polls = 5_000;
+        abort(&mut master);
+        master
+            .ops
+            .assert(&[Op::ClearFifo, Op::Stop, Op::ClearStatus]);
+        assert_eq!(master.polls, 4_001);
+    }
+
+    #[test]
+    fn errors_abort_once() {
+        for &error in &[
+            Error::NoAcknowledgeAddress,
+            Error::NoAcknowledgeData { index: 2 },
+            Error::ArbitrationLost,
+            Error::FifoError,
+            Error::PinLowTimeout,
+        ] {
+            let mut master = Mock::new(MBF | BBF, Some(0));
+            assert_eq!(fail(&mut master, error), error);
+            assert_eq!(master.ops.events().next(), Some(Op::ClearFifo));
+            assert_eq!(master.ops.events().nth(2), Some(Op::ClearStatus));
+        }
+
+        // The wait aborted the timeouts
+        for &error in &[Error::Timeout(Phase::Data), Error::BusStuck] {
+            let mut master = Mock::new(MBF | BBF, Some(0));
+            assert_eq!(fail(&mut master, error), error);
+            master.ops.assert(&[]);
+        }
+    }
+
+    #[test]
+    fn idle_master_skips_stop() {
+        // Someone else holds the bus
+        let mut master = Mock::new(BBF, Some(0));
+        let result = wait(&mut master, 5, Phase::Address, |_, _| None::<()>);
+        assert_eq!(result, Err(Error::BusStuck));
+        master.ops.assert(&[Op::ClearFifo, Op::ClearStatus]);
+
+        let mut master = Mock::new(0, Some(0));
+        let result = wait(&mut master, 5, Phase::Stop, |_, _| None::<()>);
+        assert_eq!(result, Err(Error::Timeout(Phase::Stop)));
+    }
+
+    #[test]
+    fn ready_and_errors_return_early() {
+        let mut master = Mock::new(MBF, None);
+        let mut calls = 0;
+        let result = wait(&mut master, 10, Phase::Data, |_, msr| {
+            calls += 1;
+            if calls == 3 {
+                Some(msr)
+            } else {
+                None
+            }
+        });
+        assert_eq!(result, Ok(MBF));
+        assert_eq!(master.polls, 2);
+
+        // Errors don't abort; the caller fails them
+        let mut master = Mock::new(MBF | NDF, None);
+        let result = wait(&mut master, 10, Phase::Address, |_, _| None::<()>);
+        assert_eq!(result, Err(Error::NoAcknowledgeAddress));
+        master.ops.assert(&[]);
+    }
+
+    #[test]
+    fn classify_status() {
+        assert_eq!(check(MBF, Phase::Data), Ok(MBF));
+        assert_eq!(check(NDF, Phase::Address), Err(Error::NoAcknowledgeAddress));
+        assert_eq!(
+            check(NDF, Phase::Data),
+            Err(Error::NoAcknowledgeData { index: 0 })
+        );
+        assert_eq!(check(ALF | NDF, Phase::Data), Err(Error::ArbitrationLost));
+        assert_eq!(check(FEF, Phase::Address), Err(Error::FifoError));
+        assert_eq!(check(PLTF | ALF, Phase::Data), Err(Error::PinLowTimeout));
+    }
+
+    #[test]
+    fn pin_low_steps() {
+        use core::time::Duration;
+        assert_eq!(pin_low(Duration::from_secs(0), 8_000_000, 0), Some(0));
+        // 1ms is 8000 cycles, or 31.25 steps
+        assert_eq!(pin_low(Duration::from_millis(1), 8_000_000, 0), Some(32));
+        // Exactly four steps
+        assert_eq!(
+            pin_low(Duration::from_nanos(128_000), 8_000_000, 0),
+            Some(4)
+        );
+        // 200ms at 60MHz needs the prescaler
+        assert_eq!(pin_low(Duration::from_millis(200), 60_000_000, 0), None);
+        assert_eq!(
+            pin_low(Duration::from_millis(200), 60_000_000, 7),
+            Some(367)
+        );
+        assert_eq!(pin_low(Duration::from_secs(3600), 60_000_000, 7), None);
+    }
+
+    #[test]
+    fn timeout_polls() {
+        assert_eq!(polls(0), 1);
+        assert_eq!(polls(64), 1);
+        assert_eq!(polls(65), 2);
+        assert_eq!(polls(u32::max_value()), u32::max_value() / 64 + 1);
+        assert_eq!(
+            cycles(core::time::Duration::from_millis(2), 600_000_000),
+            1_200_000
+        );
+        assert_eq!(
+            cycles(core::time::Duration::from_secs(10), 600_000_000),
+            u32::max_value()
+        );
+    }
+}
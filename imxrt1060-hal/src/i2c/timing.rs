@@ -0,0 +1,413 @@
+//! SCL timing that meets the I2C specification
+
+use super::{ClockSpeed, ClockSpeedError};
+use crate::ral;
+use core::time::Duration;
+
+/// Largest MCFGR1[PRESCALE] value; the prescaler divides by 2^PRESCALE
+const MAX_PRESCALE: u32 = 7;
+/// Largest CLKLO, CLKHI, SETHOLD and DATAVD value
+const MAX_CYCLES: u32 = 63;
+/// Largest FILTSCL and FILTSDA value
+const MAX_FILTER: u32 = 15;
+/// The smallest CLKLO, CLKHI, SETHOLD and DATAVD values that the LPI2C supports
+const MIN_CLKLO: u32 = 3;
+const MIN_CLKHI: u32 = 1;
+const MIN_SETHOLD: u32 = 2;
+const MIN_DATAVD: u32 = 1;
+
+const NS_PER_S: u64 = 1_000_000_000;
+
+/// The I2C specification's timing limits for one mode, in nanoseconds
+///
+/// The values are from UM10204, table 10.
+struct Spec {
+    /// The fastest SCL, in Hz
+    max_hz: u32,
+    /// Minimum SCL low period, and minimum bus free time
+    low: u64,
+    /// Minimum SCL high period
+    high: u64,
+    /// Minimum (repeated) START setup and hold time, and STOP setup time
+    start: u64,
+    /// Minimum data setup time
+    setup: u64,
+    /// Maximum data valid time
+    valid: u64,
+    /// Width of the spikes that the inputs must suppress
+    spike: u64,
+}
+
+/// Standard-mode, Fast-mode, and Fast-mode Plus
+const SPECS: [Spec; 3] = [
+    Spec {
+        max_hz: 100_000,
+        low: 4_700,
+        high: 4_000,
+        start: 4_700,
+        setup: 250,
+        valid: 3_450,
+        spike: 0,
+    },
+    Spec {
+        max_hz: 400_000,
+        low: 1_300,
+        high: 600,
+        start: 600,
+        setup: 100,
+        valid: 900,
+        spike: 50,
+    },
+    Spec {
+        max_hz: 1_000_000,
+        low: 500,
+        high: 260,
+        start: 260,
+        setup: 50,
+        valid: 450,
+        spike: 50,
+    },
+];
+
+impl Spec {
+    /// Returns the slowest mode that supports `hz`
+    fn for_hz(hz: u32) -> Option<&'static Spec> {
+        if hz == 0 {
+            return None;
+        }
+        SPECS.iter().find(|spec| hz <= spec.max_hz)
+    }
+}
+
+/// Returns the number of prescaled clock cycles that last at least `ns`
+pub(super) fn cycles(root_hz: u32, prescaler: u32, ns: u64) -> u32 {
+    let cycle = NS_PER_S << prescaler;
+    ((ns * u64::from(root_hz) + cycle - 1) / cycle) as u32
+}
+
+/// Returns the FILTSCL and FILTSDA value that ignores pulses up to `width`
+///
+/// The filters count root clock cycles, and the fields hold at most 15.
+pub(super) fn filter_cycles(root_hz: u32, width: Duration) -> u32 {
+    // Longer than the longest filter, without overflowing
+    let ns = width.as_nanos().min(NS_PER_S.into()) as u64;
+    cycles(root_hz, 0, ns).min(MAX_FILTER)
+}
+
+/// Returns the width of the pulses that a filter of `filter` cycles ignores
+pub(super) fn filter_width(root_hz: u32, filter: u32) -> Duration {
+    let ns = u64::from(filter) * NS_PER_S;
+    Duration::from_nanos(ns.checked_div(u64::from(root_hz)).unwrap_or(0))
+}
+
+/// The LPI2C timing parameters, and the SCL frequency that they achieve
+///
+/// The LPI2C counts the SCL low and high periods, the START and STOP timing, and
+/// the data hold time in cycles of the prescaled LPI2C root clock. Compute the
+/// parameters with [`new()`](#method.new); the I2C computes them when you call
+/// [`set_clock_speed()`](struct.I2C.html#method.set_clock_speed).
+///
+/// The SCL period is `(CLKLO + 1 + CLKHI + 1 + SCL_LATENCY) * 2^PRESCALE` root clock
+/// cycles, where `SCL_LATENCY = (2 + FILTSCL) / 2^PRESCALE`, rounded down. The
+/// master doesn't count the high period until it sees SCL high, so slow rise times
+/// lengthen the period, and `achieved_hz` is an upper bound.
+///
+/// ```
+/// use imxrt1060_hal::i2c::{ClockSpeed, ClockTiming};
+///
+/// // 24MHz OSC, divided by 3
+/// let timing = ClockTiming::new(8_000_000, ClockSpeed::KHz400).unwrap();
+/// assert_eq!(timing.achieved_hz, 400_000);
+/// assert_eq!((timing.prescaler, timing.clklo, timing.clkhi), (0, 13, 2));
+/// assert_eq!((timing.sethold, timing.datavd, timing.filter), (4, 2, 1));
+///
+/// // Faster than Fast-mode Plus
+/// assert!(ClockTiming::new(60_000_000, ClockSpeed::Custom(3_400_000)).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockTiming {
+    /// The SCL frequency, in Hz
+    pub achieved_hz: u32,
+    /// The MCFGR1[PRESCALE] value; the prescaler divides by 2^`prescaler`
+    pub prescaler: u32,
+    /// The MCCR0[CLKLO] value
+    pub clklo: u32,
+    /// The MCCR0[CLKHI] value
+    pub clkhi: u32,
+    /// The MCCR0[SETHOLD] value
+    pub sethold: u32,
+    /// The MCCR0[DATAVD] value
+    pub datavd: u32,
+    /// The MCFGR2[FILTSCL] and MCFGR2[FILTSDA] value, in root clock cycles
+    pub filter: u32,
+}
+
+impl ClockTiming {
+    /// Computes the parameters that get closest to `clock_speed`, without exceeding it
+    ///
+    /// The parameters meet the I2C specification's minimum low, high, START, STOP and
+    /// data setup times, and its maximum data valid time, for the slowest mode that
+    /// supports `clock_speed`. In Fast-mode and Fast-mode Plus, the glitch filters
+    /// suppress 50ns spikes.
+    ///
+    /// Returns an error if `clock_speed` is faster than 1MHz, if it's zero, or if it's
+    /// slower than the slowest SCL from `root_hz`. Also returns an error if `root_hz` is
+    /// too slow to meet the specification; Fast-mode Plus needs a root clock of about
+    /// 4.5MHz for its data valid time.
+    pub fn new(root_hz: u32, clock_speed: ClockSpeed) -> Result<Self, ClockSpeedError> {
+        let spec = Spec::for_hz(clock_speed.hz()).ok_or(ClockSpeedError(()))?;
+        let filter = cycles(root_hz, 0, spec.spike).min(MAX_FILTER);
+        Self::with_filter(root_hz, clock_speed, filter)
+    }
+
+    /// Computes the parameters like [`new()`](#method.new), with glitch filters that
+    /// ignore pulses up to `width`
+    ///
+    /// The filters count root clock cycles, rounded up, and ignore at most 15 cycles.
+    /// A longer filter delays SCL more, which may lower the achieved frequency.
+    pub fn with_glitch_filter(
+        root_hz: u32,
+        clock_speed: ClockSpeed,
+        width: Duration,
+    ) -> Result<Self, ClockSpeedError> {
+        Self::with_filter(root_hz, clock_speed, filter_cycles(root_hz, width))
+    }
+
+    fn with_filter(
+        root_hz: u32,
+        clock_speed: ClockSpeed,
+        filter: u32,
+    ) -> Result<Self, ClockSpeedError> {
+        let target_hz = clock_speed.hz();
+        let spec = Spec::for_hz(target_hz).ok_or(ClockSpeedError(()))?;
+        let mut best: Option<ClockTiming> = None;
+        for prescaler in 0..=MAX_PRESCALE {
+            let candidate = match Self::with_prescaler(root_hz, target_hz, spec, filter, prescaler)
+            {
+                Some(candidate) => candidate,
+                None => continue,
+            };
+            // Strictly greater, so that we prefer the smallest prescaler
+            if best.map_or(true, |best| candidate.achieved_hz > best.achieved_hz) {
+                best = Some(candidate);
+            }
+        }
+        best.ok_or(ClockSpeedError(()))
+    }
+
+    fn with_prescaler(
+        root_hz: u32,
+        target_hz: u32,
+        spec: &Spec,
+        filter: u32,
+        prescaler: u32,
+    ) -> Option<Self> {
+        let cycles = |ns| cycles(root_hz, prescaler, ns);
+        let latency = (2 + filter) >> prescaler;
+
+        let mut clklo = cycles(spec.low).saturating_sub(1).max(MIN_CLKLO);
+        let mut clkhi = cycles(spec.high).saturating_sub(1 + latency).max(MIN_CLKHI);
+        // The shortest period that doesn't exceed the target
+        let divider = u64::from(target_hz) << prescaler;
+        let period = ((u64::from(root_hz) + divider - 1) / divider) as u32;
+        let shortest = clklo + clkhi + 2 + latency;
+        if period > shortest {
+            // Lengthen both halves, keeping their ratio
+            let extra = period - shortest;
+            let extra_low = extra * (clklo + 1) / (clklo + clkhi + 2);
+            clklo += extra_low;
+            clkhi += extra - extra_low;
+        }
+        if clklo > MAX_CYCLES || clkhi > MAX_CYCLES {
+            return None;
+        }
+
+        let sethold = cycles(spec.start).saturating_sub(1).max(MIN_SETHOLD);
+        // Change SDA about a quarter into the low period
+        let datavd = ((clklo + 1) / 4).saturating_sub(1).max(MIN_DATAVD);
+        let valid = u64::from(datavd + 1) * (NS_PER_S << prescaler);
+        if sethold > MAX_CYCLES
+            || datavd >= clklo
+            || clklo - datavd < cycles(spec.setup)
+            || valid > spec.valid * u64::from(root_hz)
+        {
+            return None;
+        }
+
+        Some(ClockTiming {
+            achieved_hz: (root_hz >> prescaler) / (clklo + clkhi + 2 + latency),
+            prescaler,
+            clklo,
+            clkhi,
+            sethold,
+            datavd,
+            filter,
+        })
+    }
+
+    /// Write the parameters to the I2C's registers
+    ///
+    /// # Safety
+    ///
+    /// The function touches I2C registers that should only be touched
+    /// while the I2C master is disabled.
+    pub(super) unsafe fn set(&self, reg: &ral::lpi2c::Instance) {
+        ral::write_reg!(
+            ral::lpi2c,
+            reg,
+            MCCR0,
+            CLKHI: self.clkhi,
+            CLKLO: self.clklo,
+            SETHOLD: self.sethold,
+            DATAVD: self.datavd
+        );
+        ral::modify_reg!(ral::lpi2c, reg, MCFGR1, PRESCALE: self.prescaler);
+        ral::modify_reg!(
+            ral::lpi2c,
+            reg,
+            MCFGR2,
+            FILTSCL: self.filter,
+            FILTSDA: self.filter
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_cycles, filter_width, ClockSpeed, ClockTiming, Spec, NS_PER_S};
+    use core::time::Duration;
+
+    /// The LPI2C root clocks, from OSC and PLL3, with a few dividers
+    const ROOTS: [u32; 6] = [
+        60_000_000, 30_000_000, 24_000_000, 12_000_000, 8_000_000, 3_000_000,
+    ];
+
+    /// Returns the duration of `cycles` prescaled cycles, in nanoseconds, rounded down
+    fn ns(timing: &ClockTiming, root_hz: u32, cycles: u32) -> u64 {
+        (u64::from(cycles) * (NS_PER_S << timing.prescaler)) / u64::from(root_hz)
+    }
+
+    #[test]
+    fn meets_specification() {
+        let speeds = [
+            ClockSpeed::KHz100,
+            ClockSpeed::KHz400,
+            ClockSpeed::MHz1,
+            ClockSpeed::Custom(250_000),
+        ];
+        for &root_hz in ROOTS.iter() {
+            for &speed in speeds.iter() {
+                let timing = match ClockTiming::new(root_hz, speed) {
+                    Ok(timing) => timing,
+                    Err(_) => {
+                        assert_eq!((root_hz, speed), (3_000_000, ClockSpeed::MHz1));
+                        continue;
+                    }
+                };
+                let spec = Spec::for_hz(speed.hz()).unwrap();
+                let latency = (2 + timing.filter) >> timing.prescaler;
+                let msg = (root_hz, speed, timing);
+
+                assert!(timing.achieved_hz <= speed.hz(), "{:?}", msg);
+                assert!(
+                    ns(&timing, root_hz, timing.clklo + 1) >= spec.low,
+                    "{:?}",
+                    msg
+                );
+                assert!(
+                    ns(&timing, root_hz, timing.clkhi + 1 + latency) >= spec.high,
+                    "{:?}",
+                    msg
+                );
+                assert!(
+                    ns(&timing, root_hz, timing.sethold + 1) >= spec.start,
+                    "{:?}",
+                    msg
+                );
+                assert!(
+                    ns(&timing, root_hz, timing.clklo - timing.datavd) >= spec.setup,
+                    "{:?}",
+                    msg
+                );
+                assert!(
+                    ns(&timing, root_hz, timing.datavd + 1) <= spec.valid,
+                    "{:?}",
+                    msg
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn close_to_target() {
+        for &root_hz in ROOTS[..5].iter() {
+            for &hz in [100_000, 400_000].iter() {
+                let timing = ClockTiming::new(root_hz, ClockSpeed::Custom(hz)).unwrap();
+                assert!(
+                    timing.achieved_hz * 100 >= hz * 95,
+                    "{}: {:?}",
+                    root_hz,
+                    timing
+                );
+            }
+        }
+        // Fast-mode Plus needs a fast root clock
+        let timing = ClockTiming::new(60_000_000, ClockSpeed::MHz1).unwrap();
+        assert_eq!(timing.achieved_hz, 1_000_000);
+        assert_eq!((timing.prescaler, timing.clklo, timing.clkhi), (0, 39, 14));
+        assert_eq!(timing.filter, 3);
+
+        let timing = ClockTiming::new(8_000_000, ClockSpeed::MHz1).unwrap();
+        assert_eq!(timing.achieved_hz, 888_888);
+        assert!(ClockTiming::new(3_000_000, ClockSpeed::MHz1).is_err());
+    }
+
+    #[test]
+    fn worked_example() {
+        // 8MHz root, 100KHz: 80 cycles per period. The 4.7us low period needs 38
+        // cycles, and the 4us high period needs 32, including 2 cycles of latency. The
+        // remaining 10 cycles split 5 / 5. SDA changes 10 cycles into the low period.
+        let timing = ClockTiming::new(8_000_000, ClockSpeed::KHz100).unwrap();
+        assert_eq!(
+            timing,
+            ClockTiming {
+                achieved_hz: 100_000,
+                prescaler: 0,
+                clklo: 42,
+                clkhi: 34,
+                sethold: 37,
+                datavd: 9,
+                filter: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(ClockTiming::new(24_000_000, ClockSpeed::Custom(0)).is_err());
+        assert!(ClockTiming::new(60_000_000, ClockSpeed::Custom(1_000_001)).is_err());
+        // Slower than 60MHz / 128 / (64 + 64)
+        assert!(ClockTiming::new(60_000_000, ClockSpeed::Custom(3_000)).is_err());
+    }
+
+    #[test]
+    fn glitch_filters() {
+        let ns = Duration::from_nanos;
+        // 100ns at 24MHz is 2.4 cycles, rounded up
+        assert_eq!(filter_cycles(24_000_000, ns(100)), 3);
+        assert_eq!(filter_width(24_000_000, 3), ns(125));
+        // Clamped to the 4-bit field
+        assert_eq!(filter_cycles(60_000_000, Duration::from_micros(1)), 15);
+        assert_eq!(filter_width(60_000_000, 15), ns(250));
+        assert_eq!(filter_cycles(60_000_000, Duration::from_secs(10)), 15);
+        assert_eq!(filter_cycles(8_000_000, ns(0)), 0);
+
+        // A longer filter can only slow the clock
+        let spec = ClockTiming::new(8_000_000, ClockSpeed::KHz100).unwrap();
+        let filtered =
+            ClockTiming::with_glitch_filter(8_000_000, ClockSpeed::KHz100, ns(1_000)).unwrap();
+        assert_eq!(filtered.filter, 8);
+        assert!(filtered.achieved_hz <= spec.achieved_hz);
+        assert!(filtered.achieved_hz <= 100_000);
+    }
+}
@@ -0,0 +1,576 @@
+//! Transactions of reads and writes, joined by repeated STARTs
+
+use super::{
+    stretching,
+    timeout::{self, Phase},
+    Error, I2C,
+};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use embedded_hal::blocking::i2c::{Operation, Transactional};
+
+/// The most bytes that one receive command reads
+const MAX_RECEIVE: usize = 256;
+
+/// One read or write in a transaction
+pub(super) trait Op {
+    /// Returns `true` for a read
+    fn is_read(&self) -> bool;
+    /// Returns the number of bytes to read or write
+    fn len(&self) -> usize;
+    /// Returns the byte to write at `index`
+    fn byte(&self, index: usize) -> u8;
+    /// Store the byte read at `index`
+    fn set_byte(&mut self, index: usize, byte: u8);
+}
+
+/// Implements [`Op`] for an `Operation` enum, which has a `Read(&mut [u8])` and a
+/// `Write(&[u8])` variant
+macro_rules! impl_op {
+    ($operation:ident) => {
+        impl $crate::i2c::transaction::Op for $operation<'_> {
+            fn is_read(&self) -> bool {
+                match self {
+                    $operation::Read(_) => true,
+                    $operation::Write(_) => false,
+                }
+            }
+            fn len(&self) -> usize {
+                match self {
+                    $operation::Read(buffer) => buffer.len(),
+                    $operation::Write(bytes) => bytes.len(),
+                }
+            }
+            fn byte(&self, index: usize) -> u8 {
+                match self {
+                    $operation::Write(bytes) => bytes[index],
+                    $operation::Read(_) => 0,
+                }
+            }
+            fn set_byte(&mut self, index: usize, byte: u8) {
+                if let $operation::Read(buffer) = self {
+                    buffer[index] = byte;
+                }
+            }
+        }
+    };
+}
+#[cfg(feature = "embedded-hal-1")]
+pub(super) use impl_op;
+
+impl_op!(Operation);
+
+/// A command for the master's transmit data register, MTDR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Command {
+    /// Send a (repeated) START, and the address
+    Start { address: u8, read: bool },
+    /// Send a byte
+    Transmit(u8),
+    /// Receive between 1 and 256 bytes
+    Receive(u16),
+    /// Send a STOP
+    Stop,
+}
+
+impl Command {
+    /// Returns the MTDR word for the command
+    pub(super) fn word(self) -> u32 {
+        match self {
+            Command::Start { address, read } => {
+                0b100 << 8 | u32::from(address & 0x7F) << 1 | read as u32
+            }
+            Command::Transmit(byte) => u32::from(byte),
+            Command::Receive(count) => 0b001 << 8 | u32::from(count - 1),
+            Command::Stop => 0b010 << 8,
+        }
+    }
+}
+
+/// Generates the commands for a transaction
+///
+/// Each change of direction sends a (repeated) START, so adjacent operations with the
+/// same direction join without a START. Reads of more than 256 bytes chain receive
+/// commands. Empty reads are skipped, and an empty write sends only the address. The
+/// last command is the STOP.
+pub(super) struct Commands {
+    address: u8,
+    op: usize,
+    offset: usize,
+    /// The direction of the last START, if there's been a START
+    direction: Option<bool>,
+    stopped: bool,
+    /// A bit for each sent command, set for STARTs; the newest is bit 0
+    starts: u32,
+    /// A bit for each sent command, set for transmits; the newest is bit 0
+    transmits: u32,
+    /// The number of sent transmits
+    transmitted: usize,
+}
+
+impl Commands {
+    pub(super) fn new(address: u8) -> Self {
+        Commands {
+            address,
+            op: 0,
+            offset: 0,
+            direction: None,
+            stopped: false,
+            starts: 0,
+            transmits: 0,
+            transmitted: 0,
+        }
+    }
+
+    /// Returns the next command, or `None` after the STOP
+    ///
+    /// If there's nothing to read or write, there are no commands. Call
+    /// [`sent()`](#method.sent) once the command is in the TX FIFO.
+    pub(super) fn next<O: Op>(&mut self, ops: &[O]) -> Option<Command> {
+        if self.stopped {
+            return None;
+        }
+        while let Some(op) = ops.get(self.op) {
+            let (read, len) = (op.is_read(), op.len());
+            if read && len == 0 {
+                self.op += 1;
+                continue;
+            }
+            if self.direction != Some(read) {
+                self.direction = Some(read);
+                return Some(Command::Start {
+                    address: self.address,
+                    read,
+                });
+            }
+            if self.offset < len {
+                let command = if read {
+                    let count = (len - self.offset).min(MAX_RECEIVE);
+                    self.offset += count;
+                    Command::Receive(count as u16)
+                } else {
+                    self.offset += 1;
+                    Command::Transmit(op.byte(self.offset - 1))
+                };
+                return Some(command);
+            }
+            self.op += 1;
+            self.offset = 0;
+        }
+        self.stopped = true;
+        self.direction.map(|_| Command::Stop)
+    }
+
+    /// Record that `command` is in the TX FIFO
+    ///
+    /// The NACK classification counts back from the newest sent command, so a command
+    /// that waits for room in the FIFO doesn't count.
+    pub(super) fn sent(&mut self, command: Command) {
+        let transmit = matches!(command, Command::Transmit(_));
+        self.starts = self.starts << 1 | matches!(command, Command::Start { .. }) as u32;
+        self.transmits = self.transmits << 1 | transmit as u32;
+        self.transmitted += transmit as usize;
+    }
+
+    /// Returns the phase of the command that the master is executing
+    ///
+    /// `queued` is the number of sent commands still in the TX FIFO; the master took
+    /// the command before them.
+    pub(super) fn phase(&self, queued: u32) -> Phase {
+        if queued < 32 && self.starts >> queued & 1 != 0 {
+            Phase::Address
+        } else {
+            Phase::Data
+        }
+    }
+
+    /// Returns the NACK error for the command that the master is executing
+    ///
+    /// `queued` is the number of sent commands still in the TX FIFO. The data byte's
+    /// index counts the transaction's transmits.
+    pub(super) fn nacked(&self, queued: u32) -> Error {
+        if self.phase(queued) == Phase::Address {
+            return timeout::nack(None);
+        }
+        let newer = if queued < 32 {
+            (self.transmits & ((1 << queued) - 1)).count_ones()
+        } else {
+            0
+        };
+        timeout::nack(self.transmitted.checked_sub(newer as usize + 1))
+    }
+}
+
+/// Stores received bytes in a transaction's reads
+#[derive(Default)]
+pub(super) struct Receiver {
+    op: usize,
+    offset: usize,
+}
+
+impl Receiver {
+    /// Move to the next byte to read
+    fn advance<O: Op>(&mut self, ops: &[O]) {
+        while let Some(op) = ops.get(self.op) {
+            if op.is_read() && self.offset < op.len() {
+                break;
+            }
+            self.op += 1;
+            self.offset = 0;
+        }
+    }
+
+    /// Store `byte` in the next byte to read
+    ///
+    /// Returns `false` if the reads are already full.
+    pub(super) fn receive<O: Op>(&mut self, ops: &mut [O], byte: u8) -> bool {
+        self.advance(ops);
+        match ops.get_mut(self.op) {
+            Some(op) => {
+                op.set_byte(self.offset, byte);
+                self.offset += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if every read is full
+    pub(super) fn is_done<O: Op>(&mut self, ops: &[O]) -> bool {
+        self.advance(ops);
+        self.op >= ops.len()
+    }
+}
+
+impl<M> I2C<M>
+where
+    M: Unsigned,
+{
+    /// Run the operations as one transaction with the device at `address`
+    ///
+    /// The retry policy may run the transaction more than once.
+    pub(super) fn transaction<O: Op>(&mut self, address: u8, ops: &mut [O]) -> Result<(), Error> {
+        let retries = self.retries;
+        self.with_retries(retries, |i2c| i2c.attempt(address, ops))
+    }
+
+    /// Run the operations once
+    ///
+    /// The attempt waits for an idle bus. Then the command FIFO stays full, and the RX
+    /// FIFO drains, until the STOP. Every wait for progress is bounded by the operation
+    /// timeout, and measured for the stretching diagnostics. Any error aborts the
+    /// transaction, clears the FIFOs, and leaves the master idle.
+    pub(super) fn attempt<O: Op>(&mut self, address: u8, ops: &mut [O]) -> Result<(), Error> {
+        use ral::lpi2c::MSR::{BBF, MBF, SDF, TDF};
+        let mut commands = Commands::new(address);
+        let mut receiver = Receiver::default();
+        let mut pending = commands.next(ops);
+        if pending.is_none() {
+            return Ok(());
+        }
+
+        self.clear_fifo();
+        self.clear_status();
+        self.wait_until(Phase::Address, |msr| {
+            (msr & (MBF::mask | BBF::mask)) == 0 && (msr & TDF::mask) != 0
+        })
+        .map_err(|err| timeout::fail(self, err))?;
+
+        let depth = self.tx_depth();
+        let limit = stretching::limit(self.core_hz, self.clock_speed.hz());
+        loop {
+            let phase = if pending.is_none() && receiver.is_done(ops) {
+                Phase::Stop
+            } else {
+                commands.phase(self.tx_count())
+            };
+            let polls = self.timeout_polls;
+            let mut checks = 0;
+            let finished = timeout::wait(self, polls, phase, |i2c, msr| {
+                checks += 1;
+                let mut progress = false;
+                while let Some(command) = pending {
+                    if i2c.tx_count() >= depth {
+                        break;
+                    }
+                    ral::write_reg!(ral::lpi2c, i2c.reg, MTDR, command.word());
+                    commands.sent(command);
+                    pending = commands.next(ops);
+                    progress = true;
+                }
+                while let Some(byte) = timeout::Master::receive(i2c) {
+                    if !receiver.receive(ops, byte) {
+                        log::warn!("I2C{} received an unexpected byte", M::USIZE);
+                    }
+                    progress = true;
+                }
+                if pending.is_none() && msr & SDF::mask != 0 && receiver.is_done(ops) {
+                    Some(true)
+                } else if progress {
+                    Some(false)
+                } else {
+                    None
+                }
+            });
+            // The first check doesn't wait
+            self.stretching.observe(checks.max(1) - 1, limit);
+            match finished {
+                Ok(true) => return Ok(()),
+                Ok(false) => continue,
+                Err(Error::NoAcknowledgeAddress) | Err(Error::NoAcknowledgeData { .. }) => {
+                    let err = commands.nacked(self.tx_count());
+                    return Err(timeout::fail(self, err));
+                }
+                Err(err) => return Err(timeout::fail(self, err)),
+            }
+        }
+    }
+
+    /// Returns the number of words in the TX FIFO
+    pub(super) fn tx_count(&self) -> u32 {
+        ral::read_reg!(ral::lpi2c, self.reg, MFSR, TXCOUNT)
+    }
+
+    /// Returns the number of words that the TX FIFO holds
+    pub(super) fn tx_depth(&self) -> u32 {
+        1 << ral::read_reg!(ral::lpi2c, self.reg, PARAM, MTXFIFO)
+    }
+}
+
+/// Adjacent operations with the same direction join, without a repeated START
+///
+/// # Example
+///
+/// Read four bytes from an EEPROM, starting at address 0x0123. The repeated START
+/// keeps the address pointer that the write set.
+///
+/// ```no_run
+/// use embedded_hal::blocking::i2c::{Operation, Transactional};
+/// use imxrt1060_hal::i2c::{Error, I2C};
+/// # use imxrt1060_hal::iomuxc::consts::U3;
+/// # fn eeprom(i2c: &mut I2C<U3>) -> Result<(), Error> {
+/// const EEPROM: u8 = 0x50;
+///
+/// let mut data = [0; 4];
+/// match i2c.exec(
+///     EEPROM,
+///     &mut [Operation::Write(&[0x01, 0x23]), Operation::Read(&mut data)],
+/// ) {
+///     Ok(()) => log::info!("{:?}", data),
+///     // The EEPROM doesn't acknowledge while it's writing
+///     Err(Error::NoAcknowledgeAddress) => log::info!("EEPROM busy"),
+///     Err(err) => return Err(err),
+/// }
+/// # Ok(()) }
+/// ```
+impl<M> Transactional for I2C<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn exec(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.transaction(address, operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Commands, Error, Phase, Receiver};
+
+    enum TestOp<'a> {
+        Read(&'a mut [u8]),
+        Write(&'a [u8]),
+    }
+
+    impl_op!(TestOp);
+
+    /// Returns the MTDR words for `ops`
+    fn mtdr(address: u8, ops: &[TestOp]) -> ([u32; 16], usize) {
+        let mut commands = Commands::new(address);
+        let mut words = [0; 16];
+        let mut len = 0;
+        while let Some(command) = commands.next(ops) {
+            commands.sent(command);
+            words[len] = command.word();
+            len += 1;
+        }
+        (words, len)
+    }
+
+    #[test]
+    fn eeprom_random_read() {
+        let mut data = [0; 4];
+        let ops = [TestOp::Write(&[0x01, 0x23]), TestOp::Read(&mut data)];
+        let (words, len) = mtdr(0x50, &ops);
+        assert_eq!(&words[..len], &[0x4A0, 0x001, 0x023, 0x4A1, 0x103, 0x200]);
+    }
+
+    #[test]
+    fn joins_and_chains() {
+        // Same-direction operations join; long reads chain receive commands
+        let mut first = [0; 2];
+        let mut second = [0; 600];
+        let ops = [
+            TestOp::Write(&[0xAA]),
+            TestOp::Write(&[]),
+            TestOp::Write(&[0xBB]),
+            TestOp::Read(&mut first),
+            TestOp::Read(&mut second),
+        ];
+        let (words, len) = mtdr(0x10, &ops);
+        assert_eq!(
+            &words[..len],
+            &[0x420, 0x0AA, 0x0BB, 0x421, 0x101, 0x1FF, 0x1FF, 0x157, 0x200]
+        );
+    }
+
+    #[test]
+    fn empty_operations() {
+        // A probe sends only the address
+        let (words, len) = mtdr(0x48, &[TestOp::Write(&[])]);
+        assert_eq!(&words[..len], &[0x490, 0x200]);
+
+        // Empty reads are skipped, so they don't change direction
+        let ops = [
+            TestOp::Write(&[1]),
+            TestOp::Read(&mut []),
+            TestOp::Write(&[2]),
+        ];
+        let (words, len) = mtdr(0x48, &ops);
+        assert_eq!(&words[..len], &[0x490, 0x001, 0x002, 0x200]);
+
+        assert_eq!(words_len(&[]), 0);
+        assert_eq!(words_len(&[TestOp::Read(&mut [])]), 0);
+    }
+
+    fn words_len(ops: &[TestOp]) -> usize {
+        mtdr(0x48, ops).1
+    }
+
+    /// Send every command for `ops`
+    fn send_all(commands: &mut Commands, ops: &[TestOp]) {
+        while let Some(command) = commands.next(ops) {
+            commands.sent(command);
+        }
+    }
+
+    #[test]
+    fn nack_phase() {
+        let ops = [TestOp::Write(&[1, 2, 3])];
+        let mut commands = Commands::new(0x48);
+        let start = commands.next(&ops).unwrap();
+        assert_eq!(
+            start,
+            Command::Start {
+                address: 0x48,
+                read: false
+            }
+        );
+        commands.sent(start);
+        for byte in 1..=2 {
+            let transmit = commands.next(&ops).unwrap();
+            assert_eq!(transmit, Command::Transmit(byte));
+            commands.sent(transmit);
+        }
+        // The START is behind two queued bytes
+        assert_eq!(commands.phase(2), Phase::Address);
+        assert_eq!(commands.phase(1), Phase::Data);
+        assert_eq!(commands.phase(0), Phase::Data);
+    }
+
+    #[test]
+    fn repeated_start_phase() {
+        let mut read = [0; 2];
+        let ops = [TestOp::Write(&[0x0F]), TestOp::Read(&mut read)];
+        let mut commands = Commands::new(0x48);
+        for _ in 0..3 {
+            let command = commands.next(&ops).unwrap();
+            commands.sent(command);
+        }
+        // START, the written byte, then the repeated START
+        assert_eq!(commands.phase(2), Phase::Address);
+        // The written byte drains before the repeated START
+        assert_eq!(commands.phase(1), Phase::Data);
+        assert_eq!(commands.phase(0), Phase::Address);
+    }
+
+    #[test]
+    fn nack_while_pending() {
+        // A page write that's longer than the four-word TX FIFO
+        let ops = [TestOp::Write(&[0x01, 0x23, 0xAA, 0xBB, 0xCC])];
+        let mut commands = Commands::new(0x50);
+        for _ in 0..4 {
+            let command = commands.next(&ops).unwrap();
+            commands.sent(command);
+        }
+        // The next byte waits for room in the FIFO
+        assert_eq!(commands.next(&ops), Some(Command::Transmit(0xBB)));
+
+        // The master took the START, and three words are queued
+        assert_eq!(commands.phase(3), Phase::Address);
+        assert_eq!(commands.nacked(3), Error::NoAcknowledgeAddress);
+        // The master took the first byte
+        assert_eq!(commands.phase(2), Phase::Data);
+        assert_eq!(commands.nacked(2), Error::NoAcknowledgeData { index: 0 });
+        assert_eq!(commands.nacked(0), Error::NoAcknowledgeData { index: 2 });
+    }
+
+    #[test]
+    fn nack_classification() {
+        let ops = [
+            TestOp::Write(&[1, 2]),
+            TestOp::Read(&mut []),
+            TestOp::Write(&[3]),
+        ];
+        let mut commands = Commands::new(0x48);
+        // START, 1, 2, 3, STOP
+        send_all(&mut commands, &ops);
+        assert_eq!(commands.nacked(4), Error::NoAcknowledgeAddress);
+        assert_eq!(commands.nacked(3), Error::NoAcknowledgeData { index: 0 });
+        assert_eq!(commands.nacked(2), Error::NoAcknowledgeData { index: 1 });
+        // The bytes of both writes count
+        assert_eq!(commands.nacked(1), Error::NoAcknowledgeData { index: 2 });
+        assert_eq!(commands.nacked(0), Error::NoAcknowledgeData { index: 2 });
+
+        // A repeated START for a read
+        let mut read = [0; 2];
+        let ops = [TestOp::Write(&[7]), TestOp::Read(&mut read)];
+        let mut commands = Commands::new(0x50);
+        // START, 7, START, receive, STOP
+        send_all(&mut commands, &ops);
+        assert_eq!(commands.nacked(3), Error::NoAcknowledgeData { index: 0 });
+        assert_eq!(commands.nacked(2), Error::NoAcknowledgeAddress);
+    }
+
+    #[test]
+    fn start_masks_the_address() {
+        // Only seven address bits fit in the START
+        let start = Command::Start {
+            address: 0xD0,
+            read: true,
+        };
+        assert_eq!(start.word(), 0x4A1);
+    }
+
+    #[test]
+    fn receive_into_reads() {
+        let mut first = [0; 2];
+        let mut second = [0; 1];
+        let mut ops = [
+            TestOp::Read(&mut first),
+            TestOp::Write(&[9]),
+            TestOp::Read(&mut []),
+            TestOp::Read(&mut second),
+        ];
+        let mut receiver = Receiver::default();
+        assert!(!receiver.is_done(&ops));
+        for byte in 1..=3 {
+            assert!(receiver.receive(&mut ops, byte));
+        }
+        assert!(receiver.is_done(&ops));
+        assert!(!receiver.receive(&mut ops, 4));
+        drop(ops);
+        assert_eq!((first, second), ([1, 2], [3]));
+    }
+}
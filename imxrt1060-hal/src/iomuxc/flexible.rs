@@ -0,0 +1,193 @@
+//! Pads that switch between roles at runtime
+
+use super::{consts::Unsigned, gpio::Pin};
+use crate::gpio::{Input, Output, GPIO};
+use core::ops::{Deref, DerefMut};
+
+/// A pad that switches between roles at runtime
+///
+/// The pad types move into drivers, and drivers don't give them back. A `Flexible` pad
+/// lends the pad to one role at a time, like a GPIO output, or a UART TX pin. Each role
+/// is a [`Role`](struct.Role.html) guard that borrows the `Flexible` pad, so there is at
+/// most one role at a time. When you drop the guard, the pad's mux (SW_MUX_CTL) and pad
+/// (SW_PAD_CTL) registers return to the values they had before the role.
+///
+/// Restoring only covers those two registers. Daisy chain (SELECT_INPUT) registers, and
+/// the state of the peripheral that used the pad, like a GPIO's direction, stay as the
+/// role left them.
+///
+/// # Example
+///
+/// Teensy 4 pin 1, pad AD_B0_02, is an LPUART6 TX pin. Ping-pong the pad between a
+/// console and a status LED, once a second, at 600MHz. LPUART6 initializes on its
+/// other TX pad, EMC_25, so that AD_B0_02 stays free.
+///
+/// ```no_run
+/// use embedded_hal::serial::Write;
+/// use imxrt1060_hal::{self, ccm, iomuxc::Flexible};
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     ccm::uart::ClockSelect::OSC,
+///     ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let mut uart = uarts
+///     .uart6
+///     .init(peripherals.iomuxc.emc.p25, peripherals.iomuxc.ad_b0.p03, 115_200)
+///     .unwrap();
+///
+/// let mut pad = Flexible::new(peripherals.iomuxc.ad_b0.p02);
+/// loop {
+///     {
+///         let mut led = pad.into_gpio_output();
+///         led.set();
+///         cortex_m::asm::delay(600_000_000);
+///     }
+///     {
+///         let _console = pad.into_uart_tx(&mut uart);
+///         for &byte in b"hello\r\n" {
+///             nb::block!(uart.write(byte)).unwrap();
+///         }
+///         nb::block!(uart.flush()).unwrap();
+///         cortex_m::asm::delay(600_000_000);
+///     }
+/// }
+/// ```
+pub struct Flexible<P> {
+    pad: P,
+}
+
+/// A role for a [`Flexible`](struct.Flexible.html) pad
+///
+/// `Role` dereferences to the driver that uses the pad, like a `GPIO`. Dropping the
+/// role restores the pad's mux and pad registers.
+pub struct Role<'a, P, R>
+where
+    P: Pin,
+{
+    flexible: &'a mut Flexible<P>,
+    driver: R,
+    mux: u32,
+    pad: u32,
+}
+
+impl<P> Flexible<P>
+where
+    P: Pin,
+{
+    /// Take the pad
+    pub fn new(pad: P) -> Self {
+        Flexible { pad }
+    }
+
+    /// Release the pad
+    pub fn release(self) -> P {
+        self.pad
+    }
+
+    /// Lend the pad to `f`, which configures the pad for a role
+    ///
+    /// `f` borrows the pad, so it can't keep it. The returned role holds what `f`
+    /// returns, and it restores the pad when it drops.
+    pub fn lend<R>(&mut self, f: impl FnOnce(&mut P) -> R) -> Role<'_, P, R> {
+        let (mux, pad) = self.registers();
+        let driver = f(&mut self.pad);
+        Role {
+            driver,
+            flexible: self,
+            mux,
+            pad,
+        }
+    }
+
+    /// Lend a copy of the pad to `f`, which turns the copy into a driver
+    ///
+    /// # Safety
+    ///
+    /// The driver must not give the pad back through a `&mut` reference, like an
+    /// `Option<P>` would. Otherwise, the pad's copy outlives the role, and there are two
+    /// owners of one pad.
+    unsafe fn lend_owned<R>(&mut self, f: impl FnOnce(P) -> R) -> Role<'_, P, R> {
+        let (mux, pad) = self.registers();
+        // Safety: pads are tags without drop glue. The role borrows this Flexible pad, and
+        // the caller keeps the copy in the role, so the copy is the only pad that's in use
+        // until the role drops.
+        let copy = core::ptr::read(&self.pad);
+        Role {
+            driver: f(copy),
+            flexible: self,
+            mux,
+            pad,
+        }
+    }
+
+    /// Returns the pad's mux and pad register values
+    fn registers(&self) -> (u32, u32) {
+        // Safety: pad registers are static, and we own the pad. Reads are atomic.
+        unsafe {
+            (
+                core::ptr::read_volatile(self.pad.mux()),
+                core::ptr::read_volatile(self.pad.pad()),
+            )
+        }
+    }
+
+    /// Use the pad as a GPIO output
+    pub fn into_gpio_output(&mut self) -> Role<'_, P, GPIO<P, Output>> {
+        // Safety: a GPIO only releases its pad by value, and there's no other GPIO of the
+        // same pad to swap with.
+        unsafe { self.lend_owned(|pad| GPIO::new(pad).output()) }
+    }
+
+    /// Use the pad as a GPIO input
+    pub fn into_gpio_input(&mut self) -> Role<'_, P, GPIO<P, Input>> {
+        // Safety: see into_gpio_output()
+        unsafe { self.lend_owned(GPIO::new) }
+    }
+
+    /// Use the pad as the TX pin of `uart`
+    ///
+    /// The UART starts transmitting on the pad, in addition to the TX pin that you used
+    /// to initialize the UART.
+    pub fn into_uart_tx<M>(&mut self, _: &mut crate::uart::UART<M>) -> Role<'_, P, ()>
+    where
+        M: Unsigned,
+        P: super::uart::Pin<Direction = super::uart::TX, Module = M>,
+    {
+        self.lend(|pad| super::uart::prepare(pad))
+    }
+}
+
+impl<P, R> Deref for Role<'_, P, R>
+where
+    P: Pin,
+{
+    type Target = R;
+    fn deref(&self) -> &R {
+        &self.driver
+    }
+}
+
+impl<P, R> DerefMut for Role<'_, P, R>
+where
+    P: Pin,
+{
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.driver
+    }
+}
+
+impl<P, R> Drop for Role<'_, P, R>
+where
+    P: Pin,
+{
+    fn drop(&mut self) {
+        let pad = &mut self.flexible.pad;
+        // Safety: we own the pad, and the driver is done with it. Writes are atomic.
+        unsafe {
+            core::ptr::write_volatile(pad.mux(), self.mux);
+            core::ptr::write_volatile(pad.pad(), self.pad);
+        }
+    }
+}
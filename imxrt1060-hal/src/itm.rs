@@ -0,0 +1,110 @@
+//! Instrumentation trace macrocell (ITM) output over SWO
+//!
+//! [`init()`](fn.init.html) configures the TPIU for SWO, with NRZ (UART) encoding, and
+//! enables ITM stimulus port 0. Configure the trace root clock with
+//! [`ccm::trace::trace_clock()`](../ccm/trace/fn.trace_clock.html) first, since the TPIU
+//! divides the trace clock to produce the SWO baud rate. The SWO signal shares a pad with
+//! JTAG_TDO, so use SWD, not JTAG, to debug.
+//!
+//! Once you've initialized the ITM, write to stimulus port 0 with
+//! `cortex_m::iprintln!`. Configure your SWO viewer for the baud rate that `init()`
+//! returns.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use imxrt1060_hal::{ccm::trace::{self, TraceClockSelect}, itm};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let mut core = cortex_m::Peripherals::take().unwrap();
+//! let hz = trace::trace_clock(&mut peripherals.ccm.handle, TraceClockSelect::Pll2, 4).unwrap();
+//! let baud = itm::init(
+//!     &mut core.DCB,
+//!     &mut core.TPIU,
+//!     &mut core.ITM,
+//!     hz,
+//!     2_000_000,
+//! )
+//! .unwrap();
+//! assert_eq!(baud, 2_000_000);
+//!
+//! cortex_m::iprintln!(&mut core.ITM.stim[0], "Hello from the i.MX RT1060");
+//! ```
+
+use crate::ccm::Frequency;
+use cortex_m::peripheral::{DCB, ITM, TPIU};
+
+/// Largest TPIU_ACPR prescaler
+const MAX_PRESCALER: u32 = 0x1FFF;
+/// Unlocks the ITM registers
+const ITM_UNLOCK: u32 = 0xC5AC_CE55;
+/// TPIU_SPPR: asynchronous NRZ encoding
+const SPPR_NRZ: u32 = 0b10;
+/// TPIU_FFCR: disable the formatter, since SWO carries one trace source
+const FFCR_NO_FORMATTER: u32 = 0x100;
+/// ITM_TCR: trace bus ID 1, sync packets, and ITM enable
+const TCR_ENABLE: u32 = (1 << 16) | (1 << 2) | 1;
+
+/// The trace clock can't produce the SWO baud rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaudError(());
+
+/// Configure SWO, and enable ITM stimulus port 0
+///
+/// `trace` is the trace root clock frequency. Returns the achieved baud rate, which is
+/// the closest rate to `baud` that the TPIU supports.
+///
+/// Returns an error, without touching any registers, if `baud` is zero, faster than the
+/// trace clock, or too slow for the TPIU prescaler.
+pub fn init(
+    dcb: &mut DCB,
+    tpiu: &mut TPIU,
+    itm: &mut ITM,
+    trace: Frequency,
+    baud: u32,
+) -> Result<u32, BaudError> {
+    let prescaler = prescaler(trace.hz(), baud).ok_or(BaudError(()))?;
+    dcb.enable_trace();
+    // Safety: we have exclusive access to the TPIU and ITM. These are the
+    // documented values for SWO with NRZ encoding.
+    unsafe {
+        tpiu.sppr.write(SPPR_NRZ);
+        tpiu.ffcr.write(FFCR_NO_FORMATTER);
+        tpiu.acpr.write(prescaler);
+        itm.lar.write(ITM_UNLOCK);
+        itm.tcr.write(TCR_ENABLE);
+        itm.tpr.write(0);
+        itm.ter[0].write(1);
+    }
+    Ok(trace.hz() / (prescaler + 1))
+}
+
+/// Returns the TPIU_ACPR prescaler that produces the closest baud rate to `baud`
+fn prescaler(trace_hz: u32, baud: u32) -> Option<u32> {
+    if baud == 0 || baud > trace_hz {
+        return None;
+    }
+    let divider = (trace_hz + baud / 2) / baud;
+    if divider - 1 > MAX_PRESCALER {
+        None
+    } else {
+        Some(divider - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prescaler;
+
+    #[test]
+    fn prescalers() {
+        assert_eq!(prescaler(132_000_000, 2_000_000), Some(65));
+        assert_eq!(prescaler(132_000_000, 132_000_000), Some(0));
+        // 132MHz / 7 = 18.857MHz; 132MHz / 6 = 22MHz
+        assert_eq!(prescaler(132_000_000, 20_000_000), Some(6));
+        assert_eq!(prescaler(132_000_000, 0), None);
+        assert_eq!(prescaler(132_000_000, 133_000_000), None);
+        assert_eq!(prescaler(24_576_000, 3_000), Some(0x1FFF));
+        assert_eq!(prescaler(24_576_000, 2_999), None);
+    }
+}
@@ -18,6 +18,38 @@ pub mod iomuxc {
     pub use imxrt_iomuxc::imxrt1060::*;
     pub use imxrt_iomuxc::*;
 
+    mod flexible;
+    pub use flexible::{Flexible, Role};
+
+    /// The IOMUXC general purpose registers (GPR)
+    ///
+    /// Functions that change GPR settings, like
+    /// [`GPIO::fast()`](../gpio/struct.GPIO.html#method.fast), take a `&mut GPR`. The
+    /// `Peripherals` don't include the GPR. [`take()`](#method.take) it when you need
+    /// it, or wrap the RAL instance that you already have with [`new()`](#method.new).
+    pub struct GPR(pub(crate) crate::ral::iomuxc_gpr::Instance);
+
+    impl GPR {
+        /// Take the GPR
+        ///
+        /// Returns `None` if the RAL's `IOMUXC_GPR` instance is already taken.
+        pub fn take() -> Option<Self> {
+            crate::ral::iomuxc_gpr::IOMUXC_GPR::take().map(GPR)
+        }
+
+        /// Use the RAL's `IOMUXC_GPR` instance as the GPR
+        pub fn new(gpr: crate::ral::iomuxc_gpr::Instance) -> Self {
+            GPR(gpr)
+        }
+
+        /// Returns the RAL's `IOMUXC_GPR` instance
+        ///
+        /// The HAL assumes that it owns the GPIO port, ENET clock, and GPR_IRQ fields.
+        pub fn raw(&mut self) -> &crate::ral::iomuxc_gpr::Instance {
+            &self.0
+        }
+    }
+
     /// Use this function to acquire the IOMUXC pads. It requires that you have an
     /// instance to the RAL's IOMUXC instance.
     pub(super) fn pads(_: crate::ral::iomuxc::Instance) -> Pads {
@@ -31,13 +63,18 @@ pub mod dma;
 pub mod gpio;
 pub mod gpt;
 pub mod i2c;
+pub mod itm;
 pub mod pit;
 pub mod pwm;
 pub mod spi;
 pub mod srtc;
+pub mod timer;
 pub mod trng;
 pub mod uart;
 
+#[cfg(test)]
+mod testing;
+
 pub mod dcdc {
     use imxrt_ral as ral;
     pub struct DCDC(pub(crate) ral::dcdc::Instance);
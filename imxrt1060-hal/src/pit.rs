@@ -12,7 +12,7 @@
 //!     imxrt1060_hal::ccm::PLL1::ARM_HZ,
 //!     &mut peripherals.ccm.handle,
 //!     &mut peripherals.dcdc,
-//! );
+//! ).unwrap();
 //!
 //! let mut cfg = peripherals.ccm.perclk.configure(
 //!     &mut peripherals.ccm.handle,
@@ -24,8 +24,11 @@
 //! timer.start(core::time::Duration::from_micros(200));
 //! ```
 
-use crate::ccm::{perclk, ticks, Divider, Frequency, TicksError};
+use crate::ccm::{
+    self, perclk, ticks, ClockListenerMut, ClocksChanged, Divider, Frequency, TicksError,
+};
 use crate::ral;
+use crate::timer::OneShot;
 use core::marker::PhantomData;
 use embedded_hal::timer::{CountDown, Periodic};
 
@@ -96,6 +99,7 @@ pub mod channel {
         fn set_interrupt_enable(interrupt: bool);
         fn interrupt_enable() -> bool;
         fn enable_chain();
+        fn disable_chain();
     }
 
     macro_rules! _impl_channel {
@@ -167,6 +171,13 @@ pub mod channel {
                         ral::modify_reg!(ral::pit, ral::pit::PIT, $tctrl, CHN: CHN_1);
                     }
                 }
+
+                #[inline(always)]
+                fn disable_chain() {
+                    unsafe {
+                        ral::modify_reg!(ral::pit, ral::pit::PIT, $tctrl, CHN: CHN_0);
+                    }
+                }
             }
         };
     }
@@ -209,6 +220,9 @@ pub mod channel {
         fn enable_chain() {
             unreachable!()
         }
+        fn disable_chain() {
+            unreachable!()
+        }
     }
 
     _impl_channel!(_0, _X, TCTRL0, LDVAL0, TFLG0, CVAL0);
@@ -256,6 +270,40 @@ impl<Chan: channel::Channel> PIT<Chan> {
         Chan::clear_tif();
     }
 
+    /// Stop the timer, and clear its interrupt
+    fn stop(self) {
+        Chan::set_enabled(false);
+        Chan::set_interrupt_enable(false);
+        self.clear_tif();
+    }
+
+    /// Use the PERCLK frequency from a PERCLK change
+    ///
+    /// `reclock()` scales this timer's load value, so that the timer keeps its
+    /// period. The change takes effect when the current period expires. If the timer
+    /// already uses the new frequency, `reclock()` does nothing, so it's safe to reclock a
+    /// timer that's also a [clock listener](../ccm/listener/index.html). See
+    /// [`perclk::reconfigure()`](../ccm/perclk/fn.reconfigure.html) for more
+    /// information.
+    pub fn reclock(&mut self, changed: &perclk::PerclkChanged) {
+        self.rescale_to(changed.new_hz());
+    }
+
+    /// Scale the load value from this timer's clock to a `new_hz` clock
+    fn rescale_to(&mut self, new_hz: u32) {
+        let old_hz = (self.clock_hz / self.divider).0;
+        if old_hz == new_hz {
+            return;
+        }
+        let ldval = Chan::ldval();
+        if ldval != 0 {
+            let ticks = perclk::rescale(ldval.saturating_add(1), old_hz, new_hz);
+            self.ldval(ticks.saturating_sub(1));
+        }
+        self.clock_hz = Frequency(new_hz);
+        self.divider = Divider(1);
+    }
+
     /// Returns the period of the clock ticks. This is the inverse
     /// of the clock frequency
     pub fn clock_period(&self) -> core::time::Duration {
@@ -298,6 +346,32 @@ impl<Chan: channel::Channel> PIT<Chan> {
         })
     }
 
+    /// Start the timer as a free-running tick counter
+    ///
+    /// The timer counts down from `u32::max_value()` to zero, then reloads, so
+    /// [`count()`](#method.count) increments every clock period, and wraps from
+    /// `u32::max_value()` to 0. A free-running timer is a
+    /// [`debounce::Now`](../gpio/debounce/trait.Now.html) time source. Once
+    /// the timer reloads, it sets its interrupt flag; disable the timer's interrupt,
+    /// or clear the flag in the interrupt handler.
+    ///
+    /// A [reclock](#method.reclock) changes the load value. Restart the counter
+    /// after a PERCLK change.
+    pub fn start_free_running(&mut self) {
+        Chan::set_enabled(false);
+        self.clear_tif();
+        self.ldval(u32::max_value());
+        Chan::set_enabled(true);
+    }
+
+    /// Returns the number of clock periods since the timer started
+    ///
+    /// The count is only meaningful while the timer is
+    /// [free running](#method.start_free_running).
+    pub fn count(&self) -> u32 {
+        u32::max_value() - Chan::cval()
+    }
+
     /// Enable the timer to trigger an interrupt when the timer expires
     pub fn set_interrupt_enable(&mut self, interrupt: bool) {
         Chan::set_interrupt_enable(interrupt);
@@ -319,6 +393,16 @@ impl<Chan: channel::Channel> PIT<Chan> {
     }
 }
 
+/// Keeps the timer's period when PERCLK changes
+impl<Chan: channel::Channel> ClockListenerMut for PIT<Chan> {
+    fn clocks_changed(&mut self, change: &ClocksChanged) {
+        let new_hz = change.new.perclk.hz();
+        if new_hz != 0 {
+            self.rescale_to(new_hz);
+        }
+    }
+}
+
 impl<Chan: channel::Channel> CountDown for PIT<Chan> {
     type Time = core::time::Duration;
     fn start<T: Into<Self::Time>>(&mut self, ms: T) {
@@ -347,6 +431,50 @@ impl<Chan: channel::Channel> CountDown for PIT<Chan> {
 
 impl<Chan: channel::Channel> Periodic for PIT<Chan> {}
 
+/// Stops when it expires, instead of reloading
+impl<Chan: channel::Channel> OneShot for PIT<Chan> {
+    fn start(&mut self, duration: core::time::Duration) {
+        CountDown::start(self, duration);
+    }
+    fn cancel(&mut self) {
+        Chan::set_enabled(false);
+        self.clear_tif();
+    }
+    fn is_expired(&mut self) -> bool {
+        if self.tif() {
+            self.cancel();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl PIT<channel::_0> {
+    /// Stop all four timers, gate the PIT's clock, and return the unclocked PIT
+    ///
+    /// `disable()` needs every timer, since they share the clock gate. It stops
+    /// each timer, and disables its interrupt. Use
+    /// [`ChainedPIT::unchain()`](struct.ChainedPIT.html#method.unchain) to get
+    /// back chained timers.
+    pub fn disable(
+        self,
+        (pit1, pit2, pit3): (PIT<channel::_1>, PIT<channel::_2>, PIT<channel::_3>),
+        handle: &mut ccm::Handle,
+    ) -> UnclockedPIT {
+        self.stop();
+        pit1.stop();
+        pit2.stop();
+        pit3.stop();
+        // Safety: we own all four timers, so nothing else uses the PIT. We
+        // dropped the instance in `clock()`, and we're returning it here.
+        let pit = unsafe { ral::pit::PIT::steal() };
+        ral::write_reg!(ral::pit, pit, MCR, MDIS: MDIS_1);
+        handle.gate::<ccm::clock_gate::Pit>(ccm::GateSetting::Off);
+        UnclockedPIT::new(pit)
+    }
+}
+
 /// Two PIT timers chained together
 pub struct ChainedPIT<C0, C1> {
     lower: PIT<C0>,
@@ -369,6 +497,20 @@ where
     }
 }
 
+impl<C0, C1> ChainedPIT<C0, C1>
+where
+    C1: channel::Channel,
+{
+    /// Stop the chained timer, and return the two timers
+    ///
+    /// The lower timer's interrupt stays disabled.
+    pub fn unchain(self) -> (PIT<C0>, PIT<C1>) {
+        C1::set_enabled(false);
+        C1::disable_chain();
+        (self.lower, self.upper)
+    }
+}
+
 /// Chain two timers together, returning a `ChainedPIT` timer that can
 /// count twice as many ticks.
 ///
@@ -25,7 +25,8 @@
 //! let (_, ipg_hz) =
 //!     p.ccm
 //!         .pll1
-//!         .set_arm_clock(imxrt1060_hal::ccm::PLL1::ARM_HZ, &mut p.ccm.handle, &mut p.dcdc);
+//!         .set_arm_clock(imxrt1060_hal::ccm::PLL1::ARM_HZ, &mut p.ccm.handle, &mut p.dcdc)
+//!         .unwrap();
 //!
 //! let mut pwm2 = p.pwm2.clock(&mut p.ccm.handle);
 //!
@@ -51,6 +52,26 @@
 //! ctrl.set_duty(Channel::A, duty1);
 //! ctrl.set_duty(Channel::B, duty2);
 //! ```
+//!
+//! To drive a half-bridge, turn a submodule into a complementary pair with
+//! `into_complementary()`. See [`ComplementaryController`](struct.ComplementaryController.html).
+//!
+//! Fault inputs disable the outputs in hardware. See
+//! [`Handle::configure_faults()`](struct.Handle.html#method.configure_faults).
+//!
+//! To run submodules in sync, with phase-shifted outputs, group them in a
+//! [`SyncGroup`](struct.SyncGroup.html).
+
+mod complementary;
+mod fault;
+mod sync;
+
+pub use complementary::{Complementary, ComplementaryController, ComplementaryError, Deadtime};
+pub use fault::{
+    FaultClearing, FaultConfig, FaultFilter, FaultFilterError, FaultPolarity, FaultResponse,
+    FaultStatus, Faults, SafeState,
+};
+pub use sync::SyncGroup;
 
 use crate::ccm;
 use crate::iomuxc::consts::{Unsigned, U0, U1, U2, U3, U4};
@@ -414,6 +435,23 @@ where
     }
 }
 
+impl<M> Handle<M>
+where
+    M: Unsigned,
+{
+    /// Stop every submodule, gate the PWM's clock, and return the unclocked PWM
+    ///
+    /// The PWM outputs stop toggling. Drop the PWM pins that use this
+    /// module before you call `disable()`; the pins keep their PWM
+    /// configuration, and they can't change their duty cycles once the
+    /// module's clock is off.
+    pub fn disable(self, handle: &mut ccm::Handle) -> Unclocked<M> {
+        ral::modify_reg!(ral::pwm, self.reg, MCTRL, RUN: 0);
+        handle.set_gates(ccm::clock_gate::PWM[M::USIZE - 1], ccm::GateSetting::Off);
+        Unclocked::new(self.reg)
+    }
+}
+
 macro_rules! clock_impl {
     ($module:path, $cg:ident) => {
         impl Unclocked<$module> {
@@ -0,0 +1,392 @@
+//! Complementary PWM outputs, with hardware deadtime
+
+use super::{while_reset, Handle, Submodule, Timing};
+use crate::ccm;
+use crate::iomuxc::consts::{Unsigned, U0, U1, U2, U3};
+use crate::iomuxc::pwm::{self, Pin};
+use crate::ral;
+use core::marker::PhantomData;
+use core::ops::DerefMut;
+
+use embedded_hal::PwmPin;
+
+/// The largest DTCNT0 and DTCNT1 value
+const MAX_DEADTIME: u32 = 0x7FF;
+/// A compare value that the counter never reaches
+///
+/// The switching period must end before it, so `VAL1` is always smaller.
+const NEVER: u16 = u16::max_value();
+
+/// The deadtime between the complementary outputs
+///
+/// The deadtime counters count the submodule's clock, before the prescaler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Deadtime {
+    /// Delay from PWM_B falling to PWM_A rising, in nanoseconds
+    pub rising_ns: u32,
+    /// Delay from PWM_A falling to PWM_B rising, in nanoseconds
+    pub falling_ns: u32,
+}
+
+/// An error when configuring complementary outputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComplementaryError {
+    /// The switching period doesn't fit the submodule counter
+    Period,
+    /// A deadtime doesn't fit the 11-bit deadtime counters
+    Deadtime {
+        /// The longest deadtime with this clock, in nanoseconds
+        max_ns: u32,
+    },
+}
+
+/// Returns the deadtime counts for at least `ns` nanoseconds of a `clock_hz` clock
+fn deadtime_counts(ns: u32, clock_hz: u32) -> Result<u16, ComplementaryError> {
+    let counts = (u64::from(ns) * u64::from(clock_hz) + 999_999_999) / 1_000_000_000;
+    if counts <= u64::from(MAX_DEADTIME) {
+        Ok(counts as u16)
+    } else {
+        Err(ComplementaryError::Deadtime {
+            max_ns: max_deadtime_ns(clock_hz),
+        })
+    }
+}
+
+/// Returns the longest deadtime of a `clock_hz` clock, in nanoseconds
+fn max_deadtime_ns(clock_hz: u32) -> u32 {
+    let ns = u64::from(MAX_DEADTIME) * 1_000_000_000 / u64::from(clock_hz.max(1));
+    ns.min(u64::from(u32::max_value())) as u32
+}
+
+/// Returns the counter ticks that span `counts` deadtime counts, with the prescaler's
+/// `divider`
+fn deadtime_ticks(counts: u16, divider: u32) -> u16 {
+    ((u32::from(counts) + divider - 1) / divider) as u16
+}
+
+/// Returns the PWM_A turn on and turn off compare values, VAL2 and VAL3
+///
+/// `modulo` is VAL1. A pulse, on either output, that's shorter than `min_pulse` ticks
+/// would vanish in the deadtime, so `duty` snaps to 0% or to 100%. 0% and 100% have no
+/// edges.
+fn edges(duty: u16, modulo: u16, min_pulse: u16) -> (u16, u16) {
+    let period = u32::from(modulo) + 1;
+    let on = if duty == u16::max_value() {
+        period
+    } else {
+        (u32::from(duty) * period) >> 16
+    };
+    let min_pulse = u32::from(min_pulse).max(1);
+    if on < min_pulse {
+        (NEVER, 0)
+    } else if period - on < min_pulse {
+        (0, NEVER)
+    } else {
+        (0, on as u16)
+    }
+}
+
+/// Returns the duty cycle of the PWM_A compare values
+fn duty(modulo: u16, val2: u16, val3: u16) -> u16 {
+    if val2 == NEVER {
+        0
+    } else if val3 == NEVER {
+        u16::max_value()
+    } else {
+        ((u32::from(val3) << 16) / (u32::from(modulo) + 1)) as u16
+    }
+}
+
+macro_rules! complementary_outputs {
+    ($SUBMODULE:path, $SMCTRL2:ident, $SMCTRL:ident, $SMOCTRL:ident, $SMDTCNT0:ident, $SMDTCNT1:ident, $SMINIT: ident, $SMVAL0:ident, $SMVAL1:ident, $SMVAL2:ident, $SMVAL3:ident, $SMVAL4:ident, $SMVAL5:ident) => {
+        impl<M> Submodule<M, $SUBMODULE>
+        where
+            M: Unsigned,
+        {
+            /// Converts two pins into a complementary pair of PWM outputs. Returns a
+            /// `Complementary` type that wraps the underlying pins.
+            ///
+            /// PWM_B is the complement of PWM_A, and the hardware inserts `deadtime`
+            /// between the two, so the outputs are never on together. The outputs start
+            /// disabled, at 0% duty. Returns an error if the period doesn't fit the
+            /// counter, or if a deadtime doesn't fit the deadtime counters; the error
+            /// has the longest deadtime.
+            ///
+            /// The pins have the same requirements as the pins of
+            /// [`outputs()`](struct.Submodule.html#method.outputs).
+            pub fn into_complementary<A, B>(
+                self,
+                handle: &mut Handle<M>,
+                mut pin_a: A,
+                mut pin_b: B,
+                timing: Timing,
+                deadtime: Deadtime,
+            ) -> Result<Complementary<A, B>, ComplementaryError>
+            where
+                A: Pin<Module = M, Submodule = $SUBMODULE, Output = pwm::A>,
+                B: Pin<Module = M, Submodule = $SUBMODULE, Output = pwm::B>,
+            {
+                let clock_hz = ccm::Frequency::from(timing.clock_select).0;
+                let divider = ccm::Divider::from(timing.prescalar).0;
+                let rising = deadtime_counts(deadtime.rising_ns, clock_hz)?;
+                let falling = deadtime_counts(deadtime.falling_ns, clock_hz)?;
+                let ticks: u16 = ccm::ticks(timing.switching_period, clock_hz, divider)
+                    .ok()
+                    .filter(|&ticks| ticks < NEVER)
+                    .ok_or(ComplementaryError::Period)?;
+
+                crate::iomuxc::pwm::prepare(&mut pin_a);
+                crate::iomuxc::pwm::prepare(&mut pin_b);
+                let clk_sel: u16 = match timing.clock_select {
+                    ccm::pwm::ClockSelect::IPG(_) => ral::pwm::SMCTRL20::CLK_SEL::RW::CLK_SEL_0 as u16,
+                };
+                while_reset::<M, $SUBMODULE, _, _>(handle, |handle| {
+                    ral::write_reg!(ral::pwm, handle.reg, $SMCTRL2,
+                        WAITEN: 1u16,
+                        DBGEN: 1u16,
+                        INDEP: INDEP_0,     // PWM_B complements PWM_A
+                        CLK_SEL: clk_sel);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMCTRL, FULL: FULL_1, PRSC: (timing.prescalar as u16));
+                    ral::write_reg!(ral::pwm, handle.reg, $SMOCTRL, 0);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMDTCNT0, rising);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMDTCNT1, falling);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMINIT, 0);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL0, 0);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL1, ticks);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL2, NEVER);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL3, 0);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL4, 0);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL5, 0);
+                });
+                ral::modify_reg!(ral::pwm, handle.reg, MCTRL, RUN: 1 << <$SUBMODULE as Unsigned>::USIZE);
+                let min_pulse = deadtime_ticks(rising.max(falling), divider);
+                Ok(Complementary {
+                    _pin_a: pin_a,
+                    _pin_b: pin_b,
+                    timing,
+                    min_pulse,
+                })
+            }
+        }
+    };
+}
+
+complementary_outputs!(
+    U0, SMCTRL20, SMCTRL0, SMOCTRL0, SMDTCNT00, SMDTCNT10, SMINIT0, SMVAL00, SMVAL10, SMVAL20,
+    SMVAL30, SMVAL40, SMVAL50
+);
+complementary_outputs!(
+    U1, SMCTRL21, SMCTRL1, SMOCTRL1, SMDTCNT01, SMDTCNT11, SMINIT1, SMVAL01, SMVAL11, SMVAL21,
+    SMVAL31, SMVAL41, SMVAL51
+);
+complementary_outputs!(
+    U2, SMCTRL22, SMCTRL2, SMOCTRL2, SMDTCNT02, SMDTCNT12, SMINIT2, SMVAL02, SMVAL12, SMVAL22,
+    SMVAL32, SMVAL42, SMVAL52
+);
+complementary_outputs!(
+    U3, SMCTRL23, SMCTRL3, SMOCTRL3, SMDTCNT03, SMDTCNT13, SMINIT3, SMVAL03, SMVAL13, SMVAL23,
+    SMVAL33, SMVAL43, SMVAL53
+);
+
+/// A complementary pair of submodule PWM pins
+///
+/// When taken in a `ComplementaryController`, you may set the pair's duty cycle
+pub struct Complementary<A, B> {
+    _pin_a: A,
+    _pin_b: B,
+    timing: Timing,
+    /// The shortest pulse that survives the deadtime, in counter ticks
+    min_pulse: u16,
+}
+
+impl<A, B> Complementary<A, B>
+where
+    A: Pin<Output = pwm::A>,
+    B: Pin<Output = pwm::B, Module = <A as Pin>::Module, Submodule = <A as Pin>::Submodule>,
+{
+    /// Provides control of the complementary pins
+    ///
+    /// Supply a type that provides mutable access to the PWM handle. The handle is required
+    /// to modify peripheral-wide registers for safe manipulation.
+    pub fn control<'a, D>(
+        &'a mut self,
+        handle: D,
+    ) -> ComplementaryController<A, B, D, <A as Pin>::Submodule>
+    where
+        D: 'a + DerefMut<Target = Handle<<A as Pin>::Module>>,
+    {
+        ComplementaryController {
+            pins: self,
+            handle,
+            _submodule: PhantomData,
+        }
+    }
+
+    /// Returns the switching period
+    pub fn period(&self) -> core::time::Duration {
+        self.timing.switching_period
+    }
+}
+
+/// A complementary PWM controller, which implements `embedded_hal::PwmPin`
+///
+/// The duty cycle is PWM_A's; PWM_B is its complement, less the deadtime. Enabling and
+/// disabling affect both outputs. A duty cycle that would make either output's pulse
+/// shorter than the deadtime snaps to 0% or to 100%, which have no edges.
+///
+/// # Example
+///
+/// Drive a half-bridge at 20KHz, with 500ns of deadtime.
+///
+/// ```no_run
+/// use embedded_hal::PwmPin;
+/// use imxrt1060_hal::pwm::{Deadtime, Timing};
+/// use imxrt1060_hal::ccm::pwm::{ClockSelect, Prescalar};
+///
+/// let mut p = imxrt1060_hal::Peripherals::take().unwrap();
+/// let (_, ipg_hz) =
+///     p.ccm
+///         .pll1
+///         .set_arm_clock(imxrt1060_hal::ccm::PLL1::ARM_HZ, &mut p.ccm.handle, &mut p.dcdc)
+///         .unwrap();
+///
+/// let mut pwm2 = p.pwm2.clock(&mut p.ccm.handle);
+/// let mut bridge = pwm2
+///     .sm2
+///     .into_complementary(
+///         &mut pwm2.handle,
+///         p.iomuxc.b0.p10,
+///         p.iomuxc.b0.p11,
+///         Timing {
+///             clock_select: ClockSelect::IPG(ipg_hz),
+///             prescalar: Prescalar::PRSC_0,
+///             switching_period: core::time::Duration::from_micros(50),
+///         },
+///         Deadtime {
+///             rising_ns: 500,
+///             falling_ns: 500,
+///         },
+///     )
+///     .unwrap();
+///
+/// let mut ctrl = bridge.control(&mut pwm2.handle);
+/// ctrl.set_duty(u16::max_value() / 3);
+/// ctrl.enable();
+/// ```
+pub struct ComplementaryController<'a, A, B, D, S> {
+    pins: &'a mut Complementary<A, B>,
+    pub(super) handle: D,
+    _submodule: PhantomData<S>,
+}
+
+macro_rules! complementary_controller {
+    ($SUBMODULE: path, $SMVAL1: ident, $SMVAL2: ident, $SMVAL3: ident) => {
+        impl<'a, A, B, D> PwmPin for ComplementaryController<'a, A, B, D, $SUBMODULE>
+        where
+            A: Pin<Output = pwm::A, Submodule = $SUBMODULE>,
+            B: Pin<Output = pwm::B, Module = <A as Pin>::Module, Submodule = <A as Pin>::Submodule>,
+            D: 'a + DerefMut<Target = Handle<<A as Pin>::Module>>,
+        {
+            type Duty = u16;
+
+            fn disable(&mut self) {
+                let mask = 0x110u16 << <$SUBMODULE as Unsigned>::USIZE;
+                let outen: u16 = ral::read_reg!(ral::pwm, self.handle.reg, OUTEN);
+                ral::write_reg!(ral::pwm, self.handle.reg, OUTEN, outen & !mask);
+            }
+
+            fn enable(&mut self) {
+                let mask = 0x110u16 << <$SUBMODULE as Unsigned>::USIZE;
+                let outen: u16 = ral::read_reg!(ral::pwm, self.handle.reg, OUTEN);
+                ral::write_reg!(ral::pwm, self.handle.reg, OUTEN, outen | mask);
+            }
+
+            fn get_duty(&self) -> Self::Duty {
+                let modulo = ral::read_reg!(ral::pwm, self.handle.reg, $SMVAL1);
+                let val2 = ral::read_reg!(ral::pwm, self.handle.reg, $SMVAL2);
+                let val3 = ral::read_reg!(ral::pwm, self.handle.reg, $SMVAL3);
+                duty(modulo, val2, val3)
+            }
+
+            fn get_max_duty(&self) -> Self::Duty {
+                u16::max_value()
+            }
+
+            fn set_duty(&mut self, duty: Self::Duty) {
+                let min_pulse = self.pins.min_pulse;
+                while_reset::<<A as Pin>::Module, $SUBMODULE, _, _>(&mut self.handle, |handle| {
+                    let modulo = ral::read_reg!(ral::pwm, handle.reg, $SMVAL1);
+                    let (on, off) = edges(duty, modulo, min_pulse);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL2, on);
+                    ral::write_reg!(ral::pwm, handle.reg, $SMVAL3, off);
+                });
+            }
+        }
+    };
+}
+
+complementary_controller!(U0, SMVAL10, SMVAL20, SMVAL30);
+complementary_controller!(U1, SMVAL11, SMVAL21, SMVAL31);
+complementary_controller!(U2, SMVAL12, SMVAL22, SMVAL32);
+complementary_controller!(U3, SMVAL13, SMVAL23, SMVAL33);
+
+#[cfg(test)]
+mod tests {
+    use super::{deadtime_counts, deadtime_ticks, duty, edges, ComplementaryError, NEVER};
+
+    #[test]
+    fn nanoseconds_to_counts() {
+        // 150MHz IPG clock
+        assert_eq!(deadtime_counts(0, 150_000_000), Ok(0));
+        assert_eq!(deadtime_counts(500, 150_000_000), Ok(75));
+        // Rounds up, so the deadtime is never shorter
+        assert_eq!(deadtime_counts(1, 150_000_000), Ok(1));
+        assert_eq!(deadtime_counts(501, 150_000_000), Ok(76));
+        // 2047 counts is 13,646.67ns
+        assert_eq!(deadtime_counts(13_646, 150_000_000), Ok(2047));
+        assert_eq!(
+            deadtime_counts(13_647, 150_000_000),
+            Err(ComplementaryError::Deadtime { max_ns: 13_646 })
+        );
+        assert_eq!(
+            deadtime_counts(u32::max_value(), 24_000_000),
+            Err(ComplementaryError::Deadtime { max_ns: 85_291 })
+        );
+    }
+
+    #[test]
+    fn deadtime_in_counter_ticks() {
+        assert_eq!(deadtime_ticks(75, 1), 75);
+        assert_eq!(deadtime_ticks(75, 4), 19);
+        assert_eq!(deadtime_ticks(0, 128), 0);
+        assert_eq!(deadtime_ticks(2047, 128), 16);
+    }
+
+    #[test]
+    fn duty_to_edges() {
+        // 100 ticks per period, and a 10 tick deadtime
+        assert_eq!(edges(0, 99, 10), (NEVER, 0));
+        assert_eq!(edges(u16::max_value(), 99, 10), (0, NEVER));
+        assert_eq!(edges(0x8000, 99, 10), (0, 50));
+        // Pulses shorter than the deadtime snap to 0% or 100%
+        assert_eq!(edges(0x1800, 99, 10), (NEVER, 0));
+        assert_eq!(edges(0x1A00, 99, 10), (0, 10));
+        assert_eq!(edges(0xE800, 99, 10), (0, 90));
+        assert_eq!(edges(0xF000, 99, 10), (0, NEVER));
+        // Without deadtime, only 0 is 0%
+        assert_eq!(edges(0x0290, 99, 0), (0, 1));
+        assert_eq!(edges(0xFFFE, 99, 0), (0, 99));
+    }
+
+    #[test]
+    fn edges_to_duty() {
+        assert_eq!(duty(99, NEVER, 0), 0);
+        assert_eq!(duty(99, 0, NEVER), u16::max_value());
+        assert_eq!(duty(99, 0, 50), 0x8000);
+        for &val in &[0x1A00u16, 0x4000, 0xC000] {
+            let (on, off) = edges(val, 999, 10);
+            let back = duty(999, on, off);
+            assert!(val - back < 0x0100, "{:X} came back as {:X}", val, back);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Fault inputs, which disable the PWM outputs in hardware
+//!
+//! The fault inputs of a PWM module come from XBAR1. Route a pad, like a gate driver's
+//! FAULT line, through XBAR1 to the module's FAULT0 through FAULT3 inputs, then
+//! configure the inputs with the [`Handle`](struct.Handle.html). Each submodule chooses
+//! the faults that disable its outputs, and the outputs' levels while disabled.
+
+use super::{Controller, Handle};
+use crate::iomuxc::consts::{Unsigned, U0, U1, U2, U3};
+use crate::iomuxc::pwm::{self, Pin};
+use crate::ral;
+use core::ops::DerefMut;
+
+use super::complementary::ComplementaryController;
+
+bitflags::bitflags! {
+    /// The fault inputs of a PWM module
+    ///
+    /// The flags have the same positions as FSTS[FFLAG].
+    pub struct Faults : u16 {
+        /// FAULT0
+        const FAULT0 = 1 << 0;
+        /// FAULT1
+        const FAULT1 = 1 << 1;
+        /// FAULT2
+        const FAULT2 = 1 << 2;
+        /// FAULT3
+        const FAULT3 = 1 << 3;
+    }
+}
+
+/// The level of a fault input that signals a fault
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultPolarity {
+    /// A low input is a fault
+    ActiveLow,
+    /// A high input is a fault
+    ActiveHigh,
+}
+
+/// How the outputs come back after a fault
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultClearing {
+    /// The outputs stay disabled until you [clear the fault](struct.Handle.html#method.clear_fault)
+    Manual,
+    /// The outputs come back at the next PWM cycle after the input clears
+    Automatic,
+}
+
+/// The configuration of a fault input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultConfig {
+    /// The level that signals a fault
+    pub polarity: FaultPolarity,
+    /// How the outputs come back
+    pub clearing: FaultClearing,
+    /// With manual clearing, also wait for the input to clear
+    ///
+    /// Otherwise, clearing the fault re-enables the outputs while the input still
+    /// signals the fault.
+    pub safe: bool,
+    /// Only the filtered input disables the outputs
+    ///
+    /// By default, the input also disables the outputs through a combinational path,
+    /// within nanoseconds, and without the filter.
+    pub filtered_only: bool,
+    /// Raise the PWM module's fault interrupt
+    pub interrupt: bool,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        FaultConfig {
+            polarity: FaultPolarity::ActiveHigh,
+            clearing: FaultClearing::Manual,
+            safe: true,
+            filtered_only: false,
+            interrupt: false,
+        }
+    }
+}
+
+/// The fault input filter, shared by all fault inputs of a PWM module
+///
+/// The filter samples the inputs, and passes a change once `samples` samples agree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultFilter {
+    /// IPG clock cycles between two samples, from 1
+    pub sample_period: u8,
+    /// The samples that must agree, from 3 through 10
+    pub samples: u8,
+}
+
+/// Indicates an error when computing the fault filter's parameters
+#[derive(Debug)]
+pub struct FaultFilterError(());
+
+/// The fault flags, and the fault inputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultStatus {
+    /// The faults that were detected, and not yet cleared
+    pub detected: Faults,
+    /// The inputs that signal a fault now, after the filter
+    pub active: Faults,
+}
+
+/// The level of an output while a fault disables it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafeState {
+    /// Drive the output low
+    Low,
+    /// Drive the output high
+    High,
+    /// Stop driving the output
+    Tristate,
+}
+
+impl Default for SafeState {
+    fn default() -> Self {
+        SafeState::Low
+    }
+}
+
+/// The faults that disable a submodule's outputs, and the outputs' safe states
+///
+/// The safe states apply before the output polarity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultResponse {
+    /// The faults that disable PWM_A
+    pub disable_a: Faults,
+    /// The faults that disable PWM_B
+    pub disable_b: Faults,
+    /// PWM_A's level while disabled
+    pub safe_a: SafeState,
+    /// PWM_B's level while disabled
+    pub safe_b: SafeState,
+}
+
+/// FCTRL field offsets; each field has one bit per fault input
+const FIE: u16 = 0;
+const FSAFE: u16 = 4;
+const FAUTO: u16 = 8;
+const FLVL: u16 = 12;
+/// FSTS field offsets
+const FFLAG: u16 = 0;
+const FFULL: u16 = 4;
+const FFPIN: u16 = 8;
+/// FFILT field masks
+const FILT_PER_MASK: u16 = 0xFF;
+const FILT_CNT_OFFSET: u16 = 8;
+/// DISMAP0 field offsets
+const DIS0A: u16 = 8;
+const DIS0B: u16 = 4;
+const DIS0X: u16 = 0;
+/// OCTRL field offsets, and the width of a fault state
+const PWMAFS: u16 = 4;
+const PWMBFS: u16 = 2;
+const FS_MASK: u16 = 0b11;
+
+/// Returns FCTRL, with `faults` set to `config`
+fn fctrl(fctrl: u16, faults: Faults, config: &FaultConfig) -> u16 {
+    let bits = faults.bits();
+    let set = |value: u16, offset: u16, on: bool| {
+        if on {
+            value | (bits << offset)
+        } else {
+            value & !(bits << offset)
+        }
+    };
+    let fctrl = set(fctrl, FIE, config.interrupt);
+    let fctrl = set(fctrl, FSAFE, config.safe);
+    let fctrl = set(fctrl, FAUTO, config.clearing == FaultClearing::Automatic);
+    set(fctrl, FLVL, config.polarity == FaultPolarity::ActiveHigh)
+}
+
+/// Returns FCTRL2, with `faults` set to `config`
+fn fctrl2(fctrl2: u16, faults: Faults, config: &FaultConfig) -> u16 {
+    if config.filtered_only {
+        fctrl2 | faults.bits()
+    } else {
+        fctrl2 & !faults.bits()
+    }
+}
+
+/// Returns the FSTS value that clears the flags of `faults`, leaving the other flags
+///
+/// The outputs of `full` faults come back at the start of a full cycle.
+fn fsts(fsts: u16, faults: Faults, full: Faults) -> u16 {
+    let flags = Faults::all().bits() << FFLAG;
+    let pins = Faults::all().bits() << FFPIN;
+    let fsts = fsts & !(flags | pins);
+    let fsts = (fsts & !(faults.bits() << FFULL)) | (full.bits() << FFULL);
+    fsts | (faults.bits() << FFLAG)
+}
+
+/// Returns the FFILT value for `filter`, or zero to disable the filter
+fn ffilt(filter: Option<FaultFilter>) -> Result<u16, FaultFilterError> {
+    match filter {
+        None => Ok(0),
+        Some(FaultFilter {
+            sample_period,
+            samples,
+        }) if sample_period != 0 && (3..=10).contains(&samples) => Ok((u16::from(sample_period)
+            & FILT_PER_MASK)
+            | (u16::from(samples - 3) << FILT_CNT_OFFSET)),
+        Some(_) => Err(FaultFilterError(())),
+    }
+}
+
+/// Returns the DISMAP0 value for `response`
+///
+/// The unused PWM_X output follows both outputs.
+fn dismap(response: &FaultResponse) -> u16 {
+    let a = response.disable_a.bits();
+    let b = response.disable_b.bits();
+    (a << DIS0A) | (b << DIS0B) | ((a | b) << DIS0X)
+}
+
+/// Returns OCTRL, with the fault states of `response`
+fn octrl(octrl: u16, response: &FaultResponse) -> u16 {
+    let state = |safe: SafeState| match safe {
+        SafeState::Low => 0b00,
+        SafeState::High => 0b01,
+        SafeState::Tristate => 0b10,
+    };
+    let octrl = octrl & !((FS_MASK << PWMAFS) | (FS_MASK << PWMBFS));
+    octrl | (state(response.safe_a) << PWMAFS) | (state(response.safe_b) << PWMBFS)
+}
+
+/// Returns the faults in the `offset` field of a fault register
+fn faults_in(reg: u16, offset: u16) -> Faults {
+    Faults::from_bits_truncate(reg >> offset)
+}
+
+impl<M> Handle<M>
+where
+    M: Unsigned,
+{
+    /// Configure the fault inputs `faults`
+    ///
+    /// A submodule's outputs respond to the faults that its
+    /// [fault response](struct.Controller.html#method.set_fault_response) selects. After
+    /// a reset, every fault disables every output, so configure the inputs before you
+    /// enable outputs. The configuration clears the inputs' fault flags, and their outputs
+    /// come back at the start of a full cycle.
+    ///
+    /// # Example
+    ///
+    /// A gate driver pulls its FAULT line low on an overcurrent. Route the line through
+    /// XBAR1 to PWM2's FAULT0, then disable the half-bridge with it.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::pwm::{
+    ///     FaultClearing, FaultConfig, FaultFilter, FaultPolarity, FaultResponse, Faults,
+    ///     SafeState,
+    /// };
+    /// # use imxrt1060_hal::pwm::{Complementary, Handle};
+    /// # use imxrt1060_hal::iomuxc::{consts::U2, pwm::Pin};
+    /// # fn protect<A, B>(handle: &mut Handle<U2>, bridge: &mut Complementary<A, B>)
+    /// # where
+    /// #     A: Pin<Module = U2, Submodule = U2, Output = imxrt1060_hal::iomuxc::pwm::A>,
+    /// #     B: Pin<Module = U2, Submodule = U2, Output = imxrt1060_hal::iomuxc::pwm::B>,
+    /// # {
+    ///
+    /// handle.configure_faults(
+    ///     Faults::FAULT0,
+    ///     FaultConfig {
+    ///         polarity: FaultPolarity::ActiveLow,
+    ///         clearing: FaultClearing::Manual,
+    ///         interrupt: true,
+    ///         ..FaultConfig::default()
+    ///     },
+    /// );
+    /// // Ignore glitches shorter than 4 samples, 32 IPG cycles apart
+    /// handle
+    ///     .set_fault_filter(Some(FaultFilter { sample_period: 32, samples: 4 }))
+    ///     .unwrap();
+    ///
+    /// let mut ctrl = bridge.control(&mut *handle);
+    /// ctrl.set_fault_response(FaultResponse {
+    ///     disable_a: Faults::FAULT0,
+    ///     disable_b: Faults::FAULT0,
+    ///     safe_a: SafeState::Low,
+    ///     safe_b: SafeState::Low,
+    /// });
+    /// drop(ctrl);
+    ///
+    /// // Later, in the PWM2_FAULT interrupt, or while polling...
+    /// if handle.fault_status().detected.contains(Faults::FAULT0) {
+    ///     log::error!("gate driver fault");
+    ///     // ...once the driver is healthy
+    ///     handle.clear_fault(Faults::FAULT0);
+    /// }
+    /// # }
+    /// ```
+    pub fn configure_faults(&mut self, faults: Faults, config: FaultConfig) {
+        let value = fctrl(ral::read_reg!(ral::pwm, self.reg, FCTRL0), faults, &config);
+        ral::write_reg!(ral::pwm, self.reg, FCTRL0, value);
+        let value = fctrl2(ral::read_reg!(ral::pwm, self.reg, FCTRL20), faults, &config);
+        ral::write_reg!(ral::pwm, self.reg, FCTRL20, value);
+        let value = fsts(ral::read_reg!(ral::pwm, self.reg, FSTS0), faults, faults);
+        ral::write_reg!(ral::pwm, self.reg, FSTS0, value);
+    }
+
+    /// Set the fault input filter, or disable it with `None`
+    ///
+    /// Returns an error if the sample period is zero, or if the samples aren't 3
+    /// through 10.
+    pub fn set_fault_filter(
+        &mut self,
+        filter: Option<FaultFilter>,
+    ) -> Result<(), FaultFilterError> {
+        let value = ffilt(filter)?;
+        ral::write_reg!(ral::pwm, self.reg, FFILT0, value);
+        Ok(())
+    }
+
+    /// Returns the fault flags, and the fault inputs
+    pub fn fault_status(&self) -> FaultStatus {
+        let status = ral::read_reg!(ral::pwm, self.reg, FSTS0);
+        FaultStatus {
+            detected: faults_in(status, FFLAG),
+            active: faults_in(status, FFPIN),
+        }
+    }
+
+    /// Clear the fault flags of `faults`
+    ///
+    /// With manual clearing, the outputs come back at the start of the next cycle, once
+    /// the flags clear, and, in safe mode, once the inputs clear.
+    pub fn clear_fault(&mut self, faults: Faults) {
+        let status = ral::read_reg!(ral::pwm, self.reg, FSTS0);
+        let full = faults_in(status, FFULL);
+        ral::write_reg!(ral::pwm, self.reg, FSTS0, fsts(status, faults, full));
+    }
+}
+
+macro_rules! fault_response {
+    ($SUBMODULE:path, $SMDISMAP0:ident, $SMOCTRL:ident) => {
+        impl<'a, A, B, D> Controller<'a, A, B, D, $SUBMODULE>
+        where
+            A: Pin<Output = pwm::A, Submodule = $SUBMODULE>,
+            B: Pin<Output = pwm::B, Module = <A as Pin>::Module, Submodule = <A as Pin>::Submodule>,
+            D: 'a + DerefMut<Target = Handle<<A as Pin>::Module>>,
+        {
+            /// Choose the faults that disable the outputs, and the outputs' safe states
+            ///
+            /// See [`Handle::configure_faults()`](struct.Handle.html#method.configure_faults).
+            pub fn set_fault_response(&mut self, response: FaultResponse) {
+                let value = octrl(
+                    ral::read_reg!(ral::pwm, self.handle.reg, $SMOCTRL),
+                    &response,
+                );
+                ral::write_reg!(ral::pwm, self.handle.reg, $SMOCTRL, value);
+                ral::write_reg!(ral::pwm, self.handle.reg, $SMDISMAP0, dismap(&response));
+            }
+        }
+
+        impl<'a, A, B, D> ComplementaryController<'a, A, B, D, $SUBMODULE>
+        where
+            A: Pin<Output = pwm::A, Submodule = $SUBMODULE>,
+            B: Pin<Output = pwm::B, Module = <A as Pin>::Module, Submodule = <A as Pin>::Submodule>,
+            D: 'a + DerefMut<Target = Handle<<A as Pin>::Module>>,
+        {
+            /// Choose the faults that disable the outputs, and the outputs' safe states
+            ///
+            /// Disable both outputs with the same faults, unless the bridge tolerates one
+            /// output running alone. See
+            /// [`Handle::configure_faults()`](struct.Handle.html#method.configure_faults).
+            pub fn set_fault_response(&mut self, response: FaultResponse) {
+                let value = octrl(
+                    ral::read_reg!(ral::pwm, self.handle.reg, $SMOCTRL),
+                    &response,
+                );
+                ral::write_reg!(ral::pwm, self.handle.reg, $SMOCTRL, value);
+                ral::write_reg!(ral::pwm, self.handle.reg, $SMDISMAP0, dismap(&response));
+            }
+        }
+    };
+}
+
+fault_response!(U0, SMDISMAP00, SMOCTRL0);
+fault_response!(U1, SMDISMAP01, SMOCTRL1);
+fault_response!(U2, SMDISMAP02, SMOCTRL2);
+fault_response!(U3, SMDISMAP03, SMOCTRL3);
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dismap, fctrl, fctrl2, ffilt, fsts, octrl, FaultClearing, FaultConfig, FaultFilter,
+        FaultPolarity, FaultResponse, Faults, SafeState,
+    };
+
+    #[test]
+    fn fault_control() {
+        // After PWM::new(), every input is active high
+        let reset = 0xF000;
+        let config = FaultConfig {
+            polarity: FaultPolarity::ActiveLow,
+            clearing: FaultClearing::Automatic,
+            safe: false,
+            filtered_only: true,
+            interrupt: true,
+        };
+        assert_eq!(fctrl(reset, Faults::FAULT1, &config), 0xD202);
+        assert_eq!(
+            fctrl(0xD202, Faults::FAULT1, &FaultConfig::default()),
+            0xF020
+        );
+        assert_eq!(fctrl(0, Faults::all(), &FaultConfig::default()), 0xF0F0);
+        assert_eq!(fctrl2(0, Faults::FAULT2, &config), 0b0100);
+        assert_eq!(
+            fctrl2(0b0110, Faults::FAULT2, &FaultConfig::default()),
+            0b0010
+        );
+    }
+
+    #[test]
+    fn fault_status_writes() {
+        // FAULT0 and FAULT3 flagged, FAULT3's pin active, FAULT3 re-enables at full cycles
+        let status = 0x0809 | 0x0080;
+        // Clearing FAULT0 leaves FAULT3's flag, and never writes the pins
+        assert_eq!(fsts(status, Faults::FAULT0, Faults::FAULT3), 0x0081);
+        // Configuring FAULT0 sets its full cycle, and keeps FAULT3's
+        assert_eq!(fsts(status, Faults::FAULT0, Faults::FAULT0), 0x0091);
+        assert_eq!(fsts(0x00F0, Faults::FAULT1, Faults::empty()), 0x00D2);
+    }
+
+    #[test]
+    fn fault_filter() {
+        assert_eq!(ffilt(None).unwrap(), 0);
+        let filter = FaultFilter {
+            sample_period: 32,
+            samples: 4,
+        };
+        assert_eq!(ffilt(Some(filter)).unwrap(), 0x0120);
+        let filter = FaultFilter {
+            sample_period: 0xFF,
+            samples: 10,
+        };
+        assert_eq!(ffilt(Some(filter)).unwrap(), 0x07FF);
+        for &(sample_period, samples) in &[(0, 3), (1, 2), (1, 11)] {
+            let filter = FaultFilter {
+                sample_period,
+                samples,
+            };
+            assert!(ffilt(Some(filter)).is_err());
+        }
+    }
+
+    #[test]
+    fn fault_response() {
+        let response = FaultResponse {
+            disable_a: Faults::FAULT0,
+            disable_b: Faults::FAULT0 | Faults::FAULT2,
+            safe_a: SafeState::Low,
+            safe_b: SafeState::Tristate,
+        };
+        assert_eq!(dismap(&response), 0x0155);
+        // Keeps the polarity bits
+        assert_eq!(octrl(0x0730, &response), 0x0708);
+        let response = FaultResponse {
+            safe_a: SafeState::High,
+            safe_b: SafeState::High,
+            ..response
+        };
+        assert_eq!(octrl(0, &response), 0x0014);
+    }
+}
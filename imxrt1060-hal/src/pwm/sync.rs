@@ -0,0 +1,397 @@
+//! Submodules that run in sync, with phase-shifted outputs
+
+use super::{Channel, Handle, Submodule, Timing};
+use crate::ccm;
+use crate::iomuxc::consts::{Unsigned, U0};
+use crate::iomuxc::pwm::{self, Pin};
+use crate::ral::{self, pwm::Instance};
+use core::marker::PhantomData;
+
+/// A compare value that the counter never reaches
+const NEVER: u16 = u16::max_value();
+/// CTRL2[INIT_SEL]: submodule 0 initializes its counter when it reaches VAL1
+const LOCAL_SYNC: u16 = 0b00;
+/// CTRL2[INIT_SEL]: the other members initialize their counters with submodule 0's
+const MASTER_SYNC: u16 = 0b10;
+
+/// Submodules that share submodule 0's counter, with a phase shift each
+///
+/// Submodule 0 is the master. The other members initialize their counters with the
+/// master's, so they all run at the master's period. Each member shifts its outputs'
+/// edges by its phase. The group starts with one MCTRL write, and changes duty cycles
+/// through the buffered compare registers, loaded together at the next period.
+///
+/// # Example
+///
+/// Three phases of an interleaved converter, 120° apart, at 100KHz.
+///
+/// ```no_run
+/// use imxrt1060_hal::pwm::{Channel, SyncGroup, Timing};
+/// use imxrt1060_hal::ccm::pwm::{ClockSelect, Prescalar};
+///
+/// let mut p = imxrt1060_hal::Peripherals::take().unwrap();
+/// let (_, ipg_hz) =
+///     p.ccm
+///         .pll1
+///         .set_arm_clock(imxrt1060_hal::ccm::PLL1::ARM_HZ, &mut p.ccm.handle, &mut p.dcdc)
+///         .unwrap();
+///
+/// let mut pwm2 = p.pwm2.clock(&mut p.ccm.handle);
+/// let timing = Timing {
+///     clock_select: ClockSelect::IPG(ipg_hz),
+///     prescalar: Prescalar::PRSC_0,
+///     switching_period: core::time::Duration::from_micros(10),
+/// };
+///
+/// let mut group = SyncGroup::new(
+///     &mut pwm2.handle,
+///     pwm2.sm0,
+///     p.iomuxc.b0.p06,
+///     p.iomuxc.b0.p07,
+///     timing,
+/// )
+/// .unwrap();
+/// group.add(&mut pwm2.handle, pwm2.sm1, p.iomuxc.b0.p08, p.iomuxc.b0.p09, 120);
+/// group.add(&mut pwm2.handle, pwm2.sm2, p.iomuxc.b0.p10, p.iomuxc.b0.p11, 240);
+///
+/// let duty = u16::max_value() / 3;
+/// group.set_duties(
+///     &mut pwm2.handle,
+///     &[(0, Channel::A, duty), (1, Channel::A, duty), (2, Channel::A, duty)],
+/// );
+/// group.enable(&mut pwm2.handle);
+/// group.start(&mut pwm2.handle);
+/// ```
+pub struct SyncGroup<M> {
+    /// One bit for each member submodule
+    members: u8,
+    /// Each member's phase shift, in counter ticks
+    phases: [u16; 4],
+    /// VAL1, the last tick of the period
+    modulo: u16,
+    timing: Timing,
+    _module: PhantomData<M>,
+}
+
+/// Returns the ticks of a `degrees` phase shift, of a period with `modulo` as VAL1
+fn phase_ticks(degrees: u16, modulo: u16) -> u16 {
+    let period = u32::from(modulo) + 1;
+    let ticks = (u32::from(degrees % 360) * period + 180) / 360;
+    (ticks % period) as u16
+}
+
+/// Returns a channel's turn on and turn off compare values
+///
+/// The output turns on at `phase`, and stays on for the duty cycle, wrapping past the
+/// end of the period. 0% and 100% have no edges.
+fn edges(phase: u16, duty: u16, modulo: u16) -> (u16, u16) {
+    let period = u32::from(modulo) + 1;
+    let on = if duty == u16::max_value() {
+        period
+    } else {
+        (u32::from(duty) * period) >> 16
+    };
+    if on == 0 {
+        (NEVER, phase)
+    } else if on == period {
+        (phase, NEVER)
+    } else {
+        (phase, ((u32::from(phase) + on) % period) as u16)
+    }
+}
+
+/// Returns the MCTRL value that sets the `members` bits of the LDOK, CLDOK, or RUN field
+fn mctrl(current: u16, offset: u16, members: u8) -> u16 {
+    // LDOK reads back the pending loads, and writing zero to LDOK leaves them be. Don't
+    // write the ones back, or a CLDOK write would set LDOK in the same write.
+    (current & !0x000F) | (u16::from(members) << offset)
+}
+
+/// Returns the OUTEN bits of the members' A and B outputs
+fn outputs(members: u8) -> u16 {
+    let members = u16::from(members);
+    (members << 8) | (members << 4)
+}
+
+/// MCTRL field offsets
+const LDOK: u16 = 0;
+const CLDOK: u16 = 4;
+const RUN: u16 = 8;
+
+/// A member's configuration
+struct Setup {
+    clk_sel: u16,
+    init_sel: u16,
+    prescalar: u16,
+    modulo: u16,
+}
+
+/// The registers of one submodule, for the group to pick at run time
+struct Registers {
+    configure: fn(&Instance, &Setup),
+    compare: fn(&Instance, Channel, (u16, u16)),
+}
+
+macro_rules! registers {
+    ($SMCTRL2:ident, $SMCTRL:ident, $SMOCTRL:ident, $SMDTCNT0:ident, $SMINIT:ident, $SMVAL0:ident, $SMVAL1:ident, $SMVAL2:ident, $SMVAL3:ident, $SMVAL4:ident, $SMVAL5:ident) => {
+        Registers {
+            configure: |reg, setup| {
+                ral::write_reg!(ral::pwm, reg, $SMCTRL2,
+                    WAITEN: 1u16,
+                    DBGEN: 1u16,
+                    INDEP: INDEP_1,
+                    INIT_SEL: setup.init_sel,
+                    CLK_SEL: setup.clk_sel);
+                ral::write_reg!(ral::pwm, reg, $SMCTRL, FULL: FULL_1, PRSC: setup.prescalar);
+                ral::write_reg!(ral::pwm, reg, $SMOCTRL, 0);
+                ral::write_reg!(ral::pwm, reg, $SMDTCNT0, 0);
+                ral::write_reg!(ral::pwm, reg, $SMINIT, 0);
+                ral::write_reg!(ral::pwm, reg, $SMVAL0, 0);
+                ral::write_reg!(ral::pwm, reg, $SMVAL1, setup.modulo);
+                ral::write_reg!(ral::pwm, reg, $SMVAL2, NEVER);
+                ral::write_reg!(ral::pwm, reg, $SMVAL3, 0);
+                ral::write_reg!(ral::pwm, reg, $SMVAL4, NEVER);
+                ral::write_reg!(ral::pwm, reg, $SMVAL5, 0);
+            },
+            compare: |reg, channel, (on, off)| match channel {
+                Channel::A => {
+                    ral::write_reg!(ral::pwm, reg, $SMVAL2, on);
+                    ral::write_reg!(ral::pwm, reg, $SMVAL3, off);
+                }
+                Channel::B => {
+                    ral::write_reg!(ral::pwm, reg, $SMVAL4, on);
+                    ral::write_reg!(ral::pwm, reg, $SMVAL5, off);
+                }
+            },
+        }
+    };
+}
+
+static REGISTERS: [Registers; 4] = [
+    registers!(
+        SMCTRL20, SMCTRL0, SMOCTRL0, SMDTCNT00, SMINIT0, SMVAL00, SMVAL10, SMVAL20, SMVAL30,
+        SMVAL40, SMVAL50
+    ),
+    registers!(
+        SMCTRL21, SMCTRL1, SMOCTRL1, SMDTCNT01, SMINIT1, SMVAL01, SMVAL11, SMVAL21, SMVAL31,
+        SMVAL41, SMVAL51
+    ),
+    registers!(
+        SMCTRL22, SMCTRL2, SMOCTRL2, SMDTCNT02, SMINIT2, SMVAL02, SMVAL12, SMVAL22, SMVAL32,
+        SMVAL42, SMVAL52
+    ),
+    registers!(
+        SMCTRL23, SMCTRL3, SMOCTRL3, SMDTCNT03, SMINIT3, SMVAL03, SMVAL13, SMVAL23, SMVAL33,
+        SMVAL43, SMVAL53
+    ),
+];
+
+impl<M> SyncGroup<M>
+where
+    M: Unsigned,
+{
+    /// Make submodule 0, and its two pins, the master of a group
+    ///
+    /// All members share `timing`. The outputs start disabled, at 0% duty, and the group
+    /// doesn't run until you [`start()`](#method.start) it. Returns `None` if the period
+    /// doesn't fit the counter.
+    pub fn new<A, B>(
+        handle: &mut Handle<M>,
+        sm0: Submodule<M, U0>,
+        pin_a: A,
+        pin_b: B,
+        timing: Timing,
+    ) -> Option<Self>
+    where
+        A: Pin<Module = M, Submodule = U0, Output = pwm::A>,
+        B: Pin<Module = M, Submodule = U0, Output = pwm::B>,
+    {
+        let modulo: u16 = ccm::ticks(
+            timing.switching_period,
+            ccm::Frequency::from(timing.clock_select).0,
+            ccm::Divider::from(timing.prescalar).0,
+        )
+        .ok()
+        .filter(|&ticks| ticks < NEVER)?;
+        let mut group = SyncGroup {
+            members: 0,
+            phases: [0; 4],
+            modulo,
+            timing,
+            _module: PhantomData,
+        };
+        group.join(handle, sm0, pin_a, pin_b, 0, LOCAL_SYNC);
+        Some(group)
+    }
+
+    /// Add a submodule, and its two pins, with a phase shift of `phase_degrees`
+    ///
+    /// The member's outputs turn on `phase_degrees` after the master's. Add the members
+    /// before you start the group; a member added later runs after the next `start()`.
+    pub fn add<S, A, B>(
+        &mut self,
+        handle: &mut Handle<M>,
+        submodule: Submodule<M, S>,
+        pin_a: A,
+        pin_b: B,
+        phase_degrees: u16,
+    ) where
+        S: Unsigned,
+        A: Pin<Module = M, Submodule = S, Output = pwm::A>,
+        B: Pin<Module = M, Submodule = S, Output = pwm::B>,
+    {
+        let phase = phase_ticks(phase_degrees, self.modulo);
+        self.join(handle, submodule, pin_a, pin_b, phase, MASTER_SYNC);
+    }
+
+    fn join<S, A, B>(
+        &mut self,
+        handle: &mut Handle<M>,
+        _: Submodule<M, S>,
+        mut pin_a: A,
+        mut pin_b: B,
+        phase: u16,
+        init_sel: u16,
+    ) where
+        S: Unsigned,
+        A: Pin<Module = M, Submodule = S, Output = pwm::A>,
+        B: Pin<Module = M, Submodule = S, Output = pwm::B>,
+    {
+        crate::iomuxc::pwm::prepare(&mut pin_a);
+        crate::iomuxc::pwm::prepare(&mut pin_b);
+        let clk_sel: u16 = match self.timing.clock_select {
+            ccm::pwm::ClockSelect::IPG(_) => ral::pwm::SMCTRL20::CLK_SEL::RW::CLK_SEL_0 as u16,
+        };
+        let setup = Setup {
+            clk_sel,
+            init_sel,
+            prescalar: self.timing.prescalar as u16,
+            modulo: self.modulo,
+        };
+        let index = S::USIZE;
+        let bit = 1 << index;
+        self.loading(handle, bit, |reg| (REGISTERS[index].configure)(reg, &setup));
+        self.members |= bit;
+        self.phases[index] = phase;
+    }
+
+    /// Clear the `members` LDOK bits, run `act`, then set the LDOK bits with one write
+    fn loading<F: FnOnce(&Instance)>(&self, handle: &mut Handle<M>, members: u8, act: F) {
+        let value = mctrl(ral::read_reg!(ral::pwm, handle.reg, MCTRL), CLDOK, members);
+        ral::write_reg!(ral::pwm, handle.reg, MCTRL, value);
+        act(&handle.reg);
+        let value = mctrl(ral::read_reg!(ral::pwm, handle.reg, MCTRL), LDOK, members);
+        ral::write_reg!(ral::pwm, handle.reg, MCTRL, value);
+    }
+
+    /// Start every member, with one MCTRL write
+    ///
+    /// The counters start together, so the phase shifts hold from the first period.
+    pub fn start(&mut self, handle: &mut Handle<M>) {
+        let value = mctrl(
+            ral::read_reg!(ral::pwm, handle.reg, MCTRL),
+            RUN,
+            self.members,
+        );
+        ral::write_reg!(ral::pwm, handle.reg, MCTRL, value);
+    }
+
+    /// Stop every member, with one MCTRL write
+    pub fn stop(&mut self, handle: &mut Handle<M>) {
+        let value = ral::read_reg!(ral::pwm, handle.reg, MCTRL) & !(u16::from(self.members) << RUN);
+        ral::write_reg!(ral::pwm, handle.reg, MCTRL, value);
+    }
+
+    /// Enable the outputs of every member
+    pub fn enable(&mut self, handle: &mut Handle<M>) {
+        let outen: u16 = ral::read_reg!(ral::pwm, handle.reg, OUTEN);
+        ral::write_reg!(ral::pwm, handle.reg, OUTEN, outen | outputs(self.members));
+    }
+
+    /// Disable the outputs of every member
+    pub fn disable(&mut self, handle: &mut Handle<M>) {
+        let outen: u16 = ral::read_reg!(ral::pwm, handle.reg, OUTEN);
+        ral::write_reg!(ral::pwm, handle.reg, OUTEN, outen & !outputs(self.members));
+    }
+
+    /// Set the duty cycle of one member's channel
+    ///
+    /// See [`set_duties()`](#method.set_duties).
+    pub fn set_duty(
+        &mut self,
+        handle: &mut Handle<M>,
+        submodule: usize,
+        channel: Channel,
+        duty: u16,
+    ) {
+        self.set_duties(handle, &[(submodule, channel, duty)]);
+    }
+
+    /// Set the duty cycles of members' channels, as `(submodule, channel, duty)`
+    ///
+    /// Every duty cycle is out of `u16::max_value()`, and keeps its member's phase
+    /// shift. The new duty cycles load together, at the start of the next period.
+    /// Submodules that aren't members are skipped.
+    pub fn set_duties(&mut self, handle: &mut Handle<M>, duties: &[(usize, Channel, u16)]) {
+        let members = self.members;
+        let (phases, modulo) = (self.phases, self.modulo);
+        self.loading(handle, members, |reg| {
+            for &(submodule, channel, duty) in duties {
+                if submodule < 4 && members & (1 << submodule) != 0 {
+                    let compare = edges(phases[submodule], duty, modulo);
+                    (REGISTERS[submodule].compare)(reg, channel, compare);
+                }
+            }
+        });
+    }
+
+    /// Returns the switching period
+    pub fn period(&self) -> core::time::Duration {
+        self.timing.switching_period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edges, mctrl, outputs, phase_ticks, CLDOK, LDOK, NEVER, RUN};
+
+    #[test]
+    fn degrees_to_ticks() {
+        // 1000 ticks per period
+        assert_eq!(phase_ticks(0, 999), 0);
+        assert_eq!(phase_ticks(90, 999), 250);
+        assert_eq!(phase_ticks(120, 999), 333);
+        assert_eq!(phase_ticks(240, 999), 667);
+        assert_eq!(phase_ticks(360, 999), 0);
+        assert_eq!(phase_ticks(480, 999), 333);
+        // Rounds to the nearest tick, and stays in the period
+        assert_eq!(phase_ticks(359, 0), 0);
+        assert_eq!(phase_ticks(180, 2), 2);
+        assert_eq!(phase_ticks(359, 0xFFFE), 65_353);
+    }
+
+    #[test]
+    fn shifted_edges() {
+        // 100 ticks per period, 25% duty
+        assert_eq!(edges(0, 0x4000, 99), (0, 25));
+        assert_eq!(edges(33, 0x4000, 99), (33, 58));
+        // Turns off in the next period
+        assert_eq!(edges(90, 0x4000, 99), (90, 15));
+        assert_eq!(edges(67, 0x8000, 99), (67, 17));
+        assert_eq!(edges(67, 0, 99), (NEVER, 67));
+        assert_eq!(edges(67, u16::max_value(), 99), (67, NEVER));
+    }
+
+    #[test]
+    fn grouped_mctrl() {
+        // Submodules 0, 1, and 2; submodule 3 runs on its own, with a pending load
+        let members = 0b0111;
+        assert_eq!(mctrl(0x0808, CLDOK, members), 0x0870);
+        assert_eq!(mctrl(0x0808, LDOK, members), 0x0807);
+        assert_eq!(mctrl(0x0808, RUN, members), 0x0F00);
+        // Members with pending loads
+        assert_eq!(mctrl(0x0F07, CLDOK, members), 0x0F70);
+        assert_eq!(outputs(members), 0x0770);
+        assert_eq!(outputs(0b1000), 0x0880);
+    }
+}
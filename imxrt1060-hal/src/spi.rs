@@ -1,8 +1,16 @@
 //! SPI support
 //!
 //! The module provides an implementation of the `embedded_hal::spi::FullDuplex` trait.
-//! All blocking implementations are provided by the default implementations from
-//! `embedded_hal`.
+//! The blocking `Transfer` and `Write` implementations keep the FIFOs full, and they
+//! drain each received word as soon as it arrives. The `WriteIter` implementation is the
+//! default implementation from `embedded_hal`.
+//!
+//! Keeping the FIFOs full lets the SCK set the throughput. At a 30 MHz SCK, an 8-bit
+//! frame takes about 267ns, so a `u8` transfer moves up to 3.75 MB/s, less the
+//! [delay between frames](struct.SPI.html#method.set_between_transfer_delay). The CPU
+//! polls the status once for every FIFO's worth of words, instead of once for every
+//! word. Sending one word at a time through `FullDuplex` waits for each word's flags,
+//! so each frame also pays for the status polls and the register accesses.
 //!
 //! # Chip selects (CS) for SPI peripherals
 //!
@@ -13,6 +21,41 @@
 //! accomodate this selection. If you do not want to use the peripheral-controlled CS, you may
 //! select your own GPIO.
 //!
+//! Devices on separate chip selects share one SPI bus. Choose the device with
+//! [`set_active_cs()`](struct.SPI.html#method.set_active_cs), and its select level with
+//! [`set_cs_polarity()`](struct.SPI.html#method.set_cs_polarity). A
+//! [`transaction()`](struct.SPI.html#method.transaction) holds the chip select asserted
+//! across a command and its data. To give each device its own mode and bit order,
+//! describe it with a [`Device`](struct.Device.html), and use
+//! [`device_transaction()`](struct.SPI.html#method.device_transaction).
+//!
+//! # Frame sizes
+//!
+//! `u8` and `u16` transfers send 8- and 16-bit frames. `u32` transfers send 32-bit
+//! frames, or odd-sized frames from [`set_frame_size()`](struct.SPI.html#method.set_frame_size).
+//! The `embedded_hal` traits, and the DMA transfers, support all three word types.
+//!
+//! For DMA transfers that send and receive at the same time, see
+//! [`FullDuplexDma`](struct.FullDuplexDma.html). To read a device's register over and
+//! over without the CPU, see [`HardwarePoll`](struct.HardwarePoll.html). To receive a
+//! device's samples without end, see [`StreamingRead`](struct.StreamingRead.html).
+//!
+//! # FIFO errors
+//!
+//! By default, the SPI pauses the clock while it waits for software. With
+//! [`set_stall(false)`](struct.SPI.html#method.set_stall), the FIFOs underrun or
+//! overflow instead, and transfers return an [`Error::Transfer`](enum.Error.html#variant.Transfer)
+//! with a [`TransferError`](enum.TransferError.html). Inspect the flags with
+//! [`status()`](struct.SPI.html#method.status), and recover with
+//! [`reset_fifos()`](struct.SPI.html#method.reset_fifos).
+//!
+//! # `embedded-hal` 1.0
+//!
+//! With the `"embedded-hal-1"` feature, a SPI implements `embedded_hal` 1.0's `SpiBus`
+//! for `u8`, `u16`, and `u32` words. To share one SPI among drivers that need a
+//! `SpiDevice`, put it in a `SharedBus`, and give each driver a `PcsDevice` or a
+//! `GpioDevice`.
+//!
 //! # Example
 //!
 //! ```no_run
@@ -21,11 +64,12 @@
 //!
 //! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
 //!
-//! let (_, _, _, spi4_builder) = peripherals.spi.clock(
+//! let lpspi_clock = imxrt1060_hal::ccm::spi::lpspi_clock(
 //!     &mut peripherals.ccm.handle,
 //!     imxrt1060_hal::ccm::spi::ClockSelect::Pll2,
 //!     imxrt1060_hal::ccm::spi::PrescalarSelect::LPSPI_PODF_5,
 //! );
+//! let (_, _, _, spi4_builder) = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
 //!
 //! let mut spi4 = spi4_builder.build(
 //!     peripherals.iomuxc.b0.p02,
@@ -35,12 +79,38 @@
 //!
 //! spi4.enable_chip_select_0(peripherals.iomuxc.b0.p00);
 //!
-//! spi4.set_clock_speed(imxrt1060_hal::spi::ClockSpeed(1_000_000)).unwrap();
+//! let achieved = spi4.set_clock_speed(imxrt1060_hal::spi::ClockSpeed(1_000_000)).unwrap();
+//! assert_eq!(achieved, imxrt1060_hal::spi::ClockSpeed(1_000_000));
 //!
 //! let mut buffer: [u8; 3] = [1, 2, 3];
 //! spi4.transfer(&mut buffer).unwrap();
 //! ```
 
+mod chip_select;
+mod delay;
+#[cfg(feature = "embedded-hal-1")]
+mod eh1;
+mod fifo;
+mod frame;
+mod full_duplex;
+mod mode;
+mod poll;
+mod status;
+mod stream;
+mod transaction;
+
+pub use chip_select::{Pcs, PcsPolarity};
+pub use delay::Timings;
+#[cfg(feature = "embedded-hal-1")]
+pub use eh1::{DeviceError, GpioDevice, PcsDevice, SharedBus};
+pub use fifo::FifoStatus;
+pub use full_duplex::{DuplexError, FullDuplexDma};
+pub use mode::BitOrder;
+pub use poll::{HardwarePoll, PollError, PollTrigger};
+pub use status::Status;
+pub use stream::{StreamError, StreamingRead};
+pub use transaction::{Device, Transaction};
+
 use crate::iomuxc::consts::{Unsigned, U1, U2, U3, U4};
 
 use crate::ccm;
@@ -62,49 +132,21 @@ pub struct Unclocked {
 
 impl Unclocked {
     /// Enable clocks to all SPI modules, returning a builder for the four SPI modules.
+    ///
+    /// Acquire `clock` from [`ccm::spi::lpspi_clock()`](../ccm/spi/fn.lpspi_clock.html).
+    /// The SPI peripherals compute their clock dividers from its frequency.
     pub fn clock(
         self,
         handle: &mut ccm::Handle,
-        clock_select: ccm::spi::ClockSelect,
-        divider: ccm::spi::PrescalarSelect,
+        clock: ccm::spi::LpspiClock,
     ) -> (Builder<U1>, Builder<U2>, Builder<U3>, Builder<U4>) {
-        let (ccm, _) = handle.raw();
-        // First, disable clocks
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR1,
-            CG0: 0,
-            CG1: 0,
-            CG2: 0,
-            CG3: 0
-        );
-
-        let clk_sel = match clock_select {
-            ccm::spi::ClockSelect::Pll2 => ral::ccm::CBCMR::LPSPI_CLK_SEL::RW::LPSPI_CLK_SEL_2,
-        };
-
-        // Select clock, and commit prescalar
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CBCMR,
-            LPSPI_PODF: (divider as u32),
-            LPSPI_CLK_SEL: clk_sel
-        );
+        use ccm::clock_gate::{Lpspi1, Lpspi2, Lpspi3, Lpspi4};
+        handle.gate::<Lpspi1>(ccm::GateSetting::RunAndWait);
+        handle.gate::<Lpspi2>(ccm::GateSetting::RunAndWait);
+        handle.gate::<Lpspi3>(ccm::GateSetting::RunAndWait);
+        handle.gate::<Lpspi4>(ccm::GateSetting::RunAndWait);
 
-        // Enable clocks
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR1,
-            CG0: 0b11,
-            CG1: 0b11,
-            CG2: 0b11,
-            CG3: 0b11
-        );
-
-        let source_clock = ccm::Frequency::from(clock_select) / ccm::Divider::from(divider);
+        let source_clock = clock.frequency();
         (
             Builder::new(source_clock, self.spi1),
             Builder::new(source_clock, self.spi2),
@@ -114,6 +156,32 @@ impl Unclocked {
     }
 }
 
+/// A SPI with a gated clock
+///
+/// Get a `Disabled` SPI from [`SPI::disable()`](struct.SPI.html#method.disable). Use
+/// `enable()` to clock the SPI again.
+pub struct Disabled<M> {
+    _module: PhantomData<M>,
+    reg: ral::lpspi::Instance,
+    source_clock: ccm::Frequency,
+}
+
+impl<M> Disabled<M>
+where
+    M: Unsigned,
+{
+    /// Ungate the SPI's clock, and return a builder
+    ///
+    /// The SPI keeps the root clock that it had before it was disabled.
+    pub fn enable(self, handle: &mut ccm::Handle) -> Builder<M> {
+        handle.set_gates(
+            ccm::clock_gate::LPSPI[M::USIZE - 1],
+            ccm::GateSetting::RunAndWait,
+        );
+        Builder::new(self.source_clock, self.reg)
+    }
+}
+
 /// A SPI builder that can build a SPI peripheral
 pub struct Builder<M> {
     _module: PhantomData<M>,
@@ -149,6 +217,28 @@ where
 
         SPI::new(self.source_clock, self.reg)
     }
+
+    /// Builds a SPI peripheral from the SDO, SDI, and SCK pins, and the PCS0 pin
+    ///
+    /// This is the same as [`build()`](#method.build), followed by
+    /// [`enable_chip_select_0()`](struct.SPI.html#method.enable_chip_select_0).
+    pub fn build_with_pcs0<SDO, SDI, SCK, PCS0>(
+        self,
+        sdo: SDO,
+        sdi: SDI,
+        sck: SCK,
+        pcs0: PCS0,
+    ) -> SPI<M>
+    where
+        SDO: spi::Pin<Module = M, Signal = spi::SDO>,
+        SDI: spi::Pin<Module = M, Signal = spi::SDI>,
+        SCK: spi::Pin<Module = M, Signal = spi::SCK>,
+        PCS0: spi::Pin<Module = M, Signal = spi::PCS0>,
+    {
+        let mut spi = self.build(sdo, sdi, sck);
+        spi.enable_chip_select_0(pcs0);
+        spi
+    }
 }
 
 /// SPI Clock speed, in Hz
@@ -162,28 +252,105 @@ impl Default for ClockSpeed {
     }
 }
 
+/// Largest TCR[PRESCALE] value; the prescaler divides by 2^PRESCALE
+const MAX_PRESCALE: u32 = 7;
+/// Largest CCR[SCKDIV] value
+const MAX_SCKDIV: u32 = 255;
+
+/// The SCK dividers, and the SCK frequency that they achieve
+///
+/// `SCK = root / 2^prescaler / (sckdiv + 2)`, where `root` is the LPSPI root clock.
+/// Compute the dividers with [`new()`](#method.new), or read the SPI's dividers with
+/// [`SPI::clock()`](struct.SPI.html#method.clock).
+///
+/// ```
+/// use imxrt1060_hal::spi::SpiClock;
+///
+/// // 88MHz can't divide down to 7MHz
+/// let clock = SpiClock::new(88_000_000, 7_000_000).unwrap();
+/// assert_eq!(clock.achieved_hz, 6_769_230);
+/// assert_eq!((clock.prescaler, clock.sckdiv), (0, 11));
+///
+/// // Slower than 88MHz / 128 / 257
+/// assert!(SpiClock::new(88_000_000, 2_000).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiClock {
+    /// The SCK frequency, in Hz
+    pub achieved_hz: u32,
+    /// The CCR[SCKDIV] value
+    pub sckdiv: u32,
+    /// The TCR[PRESCALE] value; the prescaler divides by 2^`prescaler`
+    pub prescaler: u32,
+}
+
+impl SpiClock {
+    /// Computes the dividers that get closest to `requested_hz`, without exceeding it
+    ///
+    /// Returns an error if `requested_hz` is slower than the slowest SCK, or if it's zero.
+    pub fn new(root_hz: u32, requested_hz: u32) -> Result<Self, ClockSpeedError> {
+        if requested_hz == 0 {
+            return Err(ClockSpeedError(()));
+        }
+        let mut best: Option<SpiClock> = None;
+        for prescaler in 0..=MAX_PRESCALE {
+            // The smallest divider with root / 2^prescaler / div < requested + 1
+            let div = (root_hz as u64 / ((requested_hz as u64 + 1) << prescaler) + 1).max(2);
+            if div - 2 > MAX_SCKDIV as u64 {
+                continue;
+            }
+            let candidate = SpiClock::from_dividers(root_hz, prescaler, div as u32 - 2);
+            // Strictly greater, so that we prefer the smallest prescaler
+            if best.map_or(true, |best| candidate.achieved_hz > best.achieved_hz) {
+                best = Some(candidate);
+            }
+        }
+        best.ok_or(ClockSpeedError(()))
+    }
+
+    fn from_dividers(root_hz: u32, prescaler: u32, sckdiv: u32) -> Self {
+        SpiClock {
+            achieved_hz: root_hz / (1 << prescaler) / (sckdiv + 2),
+            sckdiv,
+            prescaler,
+        }
+    }
+}
+
 impl ClockSpeed {
-    /// Sets the clock speed parameters
+    /// Sets the clock speed parameters, and returns the achieved clock speed
     ///
     /// # Safety
     ///
     /// The function touches SPI registers that should only be touched
     /// while the SPI master is disabled.
-    unsafe fn set(self, source_clock: ccm::Frequency, reg: &ral::lpspi::Instance) {
+    unsafe fn set(
+        self,
+        source_clock: ccm::Frequency,
+        reg: &ral::lpspi::Instance,
+    ) -> Result<ClockSpeed, ClockSpeedError> {
         log::debug!(
             "SPI baud rate = {:?}, source clock = {:?}",
             self,
             source_clock
         );
 
-        let mut div = source_clock.0 / self.0;
-
-        if source_clock.0 / div > self.0 {
-            div += 1;
-        }
+        let clock = SpiClock::new(source_clock.0, self.0)?;
+        clock.set(reg);
+        Ok(ClockSpeed(clock.achieved_hz))
+    }
+}
 
-        // 0 <= div <= 255, and the true coefficient is really div + 2
-        let div = div.saturating_sub(2).min(255).max(0);
+impl SpiClock {
+    /// Sets the dividers, and resets the delays
+    ///
+    /// # Safety
+    ///
+    /// The function touches SPI registers that should only be touched
+    /// while the SPI master is disabled.
+    unsafe fn set(&self, reg: &ral::lpspi::Instance) {
+        let div = self.sckdiv;
+        ral::modify_reg!(ral::lpspi, reg, TCR, PRESCALE: self.prescaler);
         ral::write_reg!(
             ral::lpspi,
             reg,
@@ -207,15 +374,39 @@ pub struct SPI<M> {
     _module: PhantomData<M>,
     /// LPSPI effective input clock frequency
     source_clock: ccm::Frequency,
+    /// The number of bits in each frame of a `u32` transfer
+    u32_frame_bits: u32,
 }
 
 /// Indicates an error when computing the parameters that control
 /// the clock speed.
+///
+/// The requested clock speed is slower than the slowest clock speed.
 #[derive(Debug)]
 pub struct ClockSpeedError(());
 
-/// Indicates an error when computing the parameters that control
-/// the mode.
+/// Indicates a delay that's too long for the prescaled clock
+///
+/// Use a larger prescaler, which means a slower clock speed, for longer delays.
+#[derive(Debug)]
+pub struct DelayError(());
+
+/// Indicates a FIFO watermark that's too large
+///
+/// Each watermark must be smaller than its FIFO's depth.
+#[derive(Debug)]
+pub struct WatermarkError(());
+
+/// Indicates an unsupported frame size
+///
+/// Frames have 8 to 32 bits. The LPSPI supports wider frames, but the driver
+/// doesn't, since each frame is one `u32`.
+#[derive(Debug)]
+pub struct FrameSizeError(());
+
+/// Indicates an error when changing the mode, the bit order, or the timings
+///
+/// The SPI was still sending frames.
 #[derive(Debug)]
 pub struct ModeError(());
 
@@ -243,6 +434,7 @@ where
             reg,
             _module: PhantomData,
             source_clock,
+            u32_frame_bits: 32,
         };
         ral::write_reg!(ral::lpspi, spi.reg, CR, RST: RST_1);
         ral::write_reg!(ral::lpspi, spi.reg, CR, RST: RST_0);
@@ -260,6 +452,19 @@ where
         spi
     }
 
+    /// Disable the SPI, gate its clock, and return the disabled SPI
+    ///
+    /// Finish the transfers first, since disabling the SPI drops the words in its FIFOs.
+    pub fn disable(self, handle: &mut ccm::Handle) -> Disabled<M> {
+        ral::write_reg!(ral::lpspi, self.reg, CR, MEN: MEN_0);
+        handle.set_gates(ccm::clock_gate::LPSPI[M::USIZE - 1], ccm::GateSetting::Off);
+        Disabled {
+            _module: PhantomData,
+            reg: self.reg,
+            source_clock: self.source_clock,
+        }
+    }
+
     fn with_master_disabled<F: FnMut() -> R, R>(&self, mut act: F) -> R {
         let men = ral::read_reg!(ral::lpspi, self.reg, CR, MEN == MEN_1);
         ral::modify_reg!(ral::lpspi, self.reg, CR, MEN: MEN_0);
@@ -271,7 +476,8 @@ where
     /// Enables the peripheral-controlled chip select 0 (PCS0)
     ///
     /// Using the peripheral-controlled chip select is typically more efficient,
-    /// and it means that software doesn't need to cooridnate its control.
+    /// and it means that software doesn't need to cooridnate its control. See
+    /// [`set_active_cs()`](#method.set_active_cs) to use more than one chip select.
     pub fn enable_chip_select_0<PCS>(&mut self, mut pcs: PCS)
     where
         PCS: spi::Pin<Module = M, Signal = spi::PCS0>,
@@ -279,27 +485,33 @@ where
         crate::iomuxc::spi::prepare(&mut pcs);
     }
 
-    /// Set the SPI mode for the peripheral
-    pub fn set_mode(&mut self, mode: embedded_hal::spi::Mode) -> Result<(), ModeError> {
-        ral::modify_reg!(
-            ral::lpspi,
-            self.reg,
-            TCR,
-            CPOL: ((mode.polarity == embedded_hal::spi::Polarity::IdleHigh) as u32),
-            CPHA: ((mode.phase == embedded_hal::spi::Phase::CaptureOnSecondTransition) as u32)
-        );
-        Ok(())
-    }
-
     /// Set the SPI master clock speed
-    pub fn set_clock_speed(&mut self, clock_speed: ClockSpeed) -> Result<(), ClockSpeedError> {
+    ///
+    /// Returns the achieved clock speed, which is the fastest speed that doesn't exceed
+    /// `clock_speed`. Returns an error, without changing the clock speed, if `clock_speed`
+    /// is slower than the slowest clock speed. See [`clock()`](#method.clock) for the
+    /// dividers behind the achieved speed.
+    ///
+    /// The delays around each transfer count prescaled clock cycles, so
+    /// `set_clock_speed()` resets them to their defaults. Set the delays, like
+    /// [`set_pcs_to_sck_delay()`](#method.set_pcs_to_sck_delay), after the clock speed.
+    pub fn set_clock_speed(
+        &mut self,
+        clock_speed: ClockSpeed,
+    ) -> Result<ClockSpeed, ClockSpeedError> {
         self.with_master_disabled(|| unsafe {
             // Safety: master is disabled
-            clock_speed.set(self.source_clock, &self.reg);
-            Ok(())
+            clock_speed.set(self.source_clock, &self.reg)
         })
     }
 
+    /// Returns the SCK dividers, and the SCK frequency that they achieve
+    pub fn clock(&self) -> SpiClock {
+        let prescaler = ral::read_reg!(ral::lpspi, self.reg, TCR, PRESCALE);
+        let sckdiv = ral::read_reg!(ral::lpspi, self.reg, CCR, SCKDIV);
+        SpiClock::from_dividers(self.source_clock.0, prescaler, sckdiv)
+    }
+
     #[inline(always)]
     fn wait<F>(&mut self, mut on: F) -> Result<(), Error>
     where
@@ -313,24 +525,6 @@ where
         Err(Error::WaitTimeout)
     }
 
-    /// Clears all master status flags that are required to be
-    /// low before acting as an SPI master.
-    ///
-    /// All flags are W1C.
-    fn clear_status(&mut self) {
-        ral::write_reg!(
-            ral::lpspi,
-            self.reg,
-            SR,
-            WCF: WCF_1,
-            FCF: FCF_1,
-            TCF: TCF_1,
-            TEF: TEF_1,
-            REF: REF_1,
-            DMF: DMF_1
-        );
-    }
-
     /// Clear any existing data in the SPI receive or transfer FIFOs
     // TODO: for now I believe this is required to be public for the cases where an user wishes
     // to clear the FIFO.  It would be a bit cleaner if we had SPI transaction methods
@@ -341,16 +535,10 @@ where
     /// Check master status flags for erroneous conditions
     #[inline(always)]
     fn check_errors(&mut self) -> Result<u32, Error> {
-        use ral::lpspi::SR::*;
         let status = ral::read_reg!(ral::lpspi, self.reg, SR);
-        if status & TEF::mask != 0 {
-            Err(Error::Transmit)
-        } else if status & REF::mask != 0 {
-            Err(Error::Receive)
-        } else if status & DMF::mask != 0 {
-            Err(Error::DataMismatch) // TODO: is this an error?
-        } else {
-            Ok(status)
+        match Status::from_bits_truncate(status).error() {
+            Some(error) => Err(Error::Transfer(error)),
+            None => Ok(status),
         }
     }
 
@@ -358,8 +546,14 @@ where
     ///
     /// Interior mutability must be atomic
     #[inline(always)]
-    unsafe fn set_frame_size<Word>(&self) {
-        ral::modify_reg!(ral::lpspi, self.reg, TCR, FRAMESZ: ((core::mem::size_of::<Word>() * 8 - 1) as u32));
+    unsafe fn apply_frame_size<Word>(&self) {
+        // Each TCR write is a new command, so skip the write if the frame size is
+        // the same. That keeps the chip select of a transaction asserted.
+        let tcr = ral::read_reg!(ral::lpspi, self.reg, TCR);
+        let bits = frame::word_frame_bits::<Word>(self.u32_frame_bits);
+        if let Some(tcr) = transaction::tcr_frame_size(tcr, bits) {
+            ral::write_reg!(ral::lpspi, self.reg, TCR, tcr);
+        }
     }
 
     #[inline(always)]
@@ -370,7 +564,7 @@ where
         self.clear_status();
         // Safety: user provided mutable reference to SPI, so they are ensuring that
         // we can safely change this.
-        unsafe { self.set_frame_size::<Word>() };
+        unsafe { self.apply_frame_size::<Word>() };
 
         if (sr & MBF::mask != 0) || (sr & TDF::mask == 0) {
             return Err(nb::Error::WouldBlock);
@@ -404,7 +598,7 @@ where
     /// Interior mutability must be atomic
     #[inline(always)]
     unsafe fn enable_dma_source<W>(&self) {
-        self.set_frame_size::<W>();
+        self.apply_frame_size::<W>();
         ral::modify_reg!(ral::lpspi, self.reg, FCR, RXWATER: 0); // No watermarks; affects DMA signaling
         ral::modify_reg!(ral::lpspi, self.reg, DER, RDDE: 1);
     }
@@ -426,7 +620,7 @@ where
     /// Performs writes behind an immutable receiver. Interior mutability must be atomic.
     #[inline(always)]
     unsafe fn enable_dma_destination<W>(&self) {
-        self.set_frame_size::<W>();
+        self.apply_frame_size::<W>();
         ral::modify_reg!(ral::lpspi, self.reg, FCR, TXWATER: 0); // No watermarks; affects DMA signaling
         ral::modify_reg!(ral::lpspi, self.reg, DER, TDDE: 1);
     }
@@ -446,13 +640,41 @@ where
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
     /// A generic transmit error
+    #[deprecated(
+        since = "0.5.0",
+        note = "use `Error::Transfer(TransferError::TransmitUnderrun)`"
+    )]
     Transmit,
     /// A generic receive error
+    #[deprecated(
+        since = "0.5.0",
+        note = "use `Error::Transfer(TransferError::ReceiveOverflow)`"
+    )]
     Receive,
     /// Data mismatch error
+    #[deprecated(
+        since = "0.5.0",
+        note = "use `Error::Transfer(TransferError::DataMismatch)`"
+    )]
     DataMismatch,
     /// Busy-wait on an internal flag was too long
     WaitTimeout,
+    /// The status flags failed the transfer
+    Transfer(TransferError),
+}
+
+/// An error from the SPI status flags
+///
+/// The flags stay set, and every transfer fails, until
+/// [`reset_fifos()`](struct.SPI.html#method.reset_fifos) clears them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    /// The TX FIFO underran, so the SPI sent a stale word
+    TransmitUnderrun,
+    /// The RX FIFO overflowed, so received words were lost
+    ReceiveOverflow,
+    /// A received word matched the data match configuration
+    DataMismatch,
 }
 
 impl<M> embedded_hal::spi::FullDuplex<u8> for SPI<M>
@@ -470,8 +692,6 @@ where
     }
 }
 
-impl<M> embedded_hal::blocking::spi::write::Default<u8> for SPI<M> where M: Unsigned {}
-impl<M> embedded_hal::blocking::spi::transfer::Default<u8> for SPI<M> where M: Unsigned {}
 impl<M> embedded_hal::blocking::spi::write_iter::Default<u8> for SPI<M> where M: Unsigned {}
 
 impl<M> embedded_hal::spi::FullDuplex<u16> for SPI<M>
@@ -489,8 +709,6 @@ where
     }
 }
 
-impl<M> embedded_hal::blocking::spi::write::Default<u16> for SPI<M> where M: Unsigned {}
-impl<M> embedded_hal::blocking::spi::transfer::Default<u16> for SPI<M> where M: Unsigned {}
 impl<M> embedded_hal::blocking::spi::write_iter::Default<u16> for SPI<M> where M: Unsigned {}
 
 //
@@ -604,3 +822,113 @@ where
         });
     }
 }
+
+unsafe impl<M> dma::peripheral::Source<u32> for SPI<M>
+where
+    M: Unsigned,
+{
+    fn source_signal(&self) -> u32 {
+        Self::DMA_SOURCE_REQUEST_SIGNAL
+    }
+    fn source(&self) -> *const u32 {
+        &self.reg.RDR as *const _ as *const u32
+    }
+    fn enable_source(&self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            // Safety: atomic operation
+            self.enable_dma_source::<u32>();
+        });
+    }
+    fn disable_source(&self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            // Safety: atomic operation
+            self.disable_dma_source();
+        });
+    }
+}
+
+unsafe impl<M> dma::peripheral::Destination<u32> for SPI<M>
+where
+    M: Unsigned,
+{
+    fn destination_signal(&self) -> u32 {
+        Self::DMA_DESTINATION_REQUEST_SIGNAL
+    }
+    fn destination(&self) -> *const u32 {
+        &self.reg.TDR as *const _ as *const u32
+    }
+    fn enable_destination(&self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            // Safety: atomic operation
+            self.enable_dma_destination::<u32>();
+        });
+    }
+    fn disable_destination(&self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            // Safety: atomic operation
+            self.disable_dma_destination();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpiClock;
+
+    #[test]
+    fn spi_clocks() {
+        for &(root_hz, requested_hz, achieved_hz) in &[
+            // Exact
+            (88_000_000, 1_000_000, 1_000_000),
+            (88_000_000, 8_000_000, 8_000_000),
+            (88_000_000, 44_000_000, 44_000_000),
+            (66_000_000, 1_000_000, 1_000_000),
+            (132_000_000, 33_000_000, 33_000_000),
+            // Nearest lower
+            (88_000_000, 7_000_000, 6_769_230),
+            (66_000_000, 20_000_000, 16_500_000),
+            (132_000_000, 5_000_000, 4_888_888),
+            // Faster than root / 2
+            (88_000_000, 88_000_000, 44_000_000),
+            // Needs the prescaler
+            (88_000_000, 100_000, 100_000),
+            (88_000_000, 10_000, 9_963),
+            (24_000_000, 1_000, 997),
+        ] {
+            let clock = SpiClock::new(root_hz, requested_hz).unwrap();
+            assert_eq!(
+                clock.achieved_hz, achieved_hz,
+                "{} {} {:?}",
+                root_hz, requested_hz, clock
+            );
+            assert_eq!(
+                clock,
+                SpiClock::from_dividers(root_hz, clock.prescaler, clock.sckdiv)
+            );
+        }
+    }
+
+    #[test]
+    fn spi_clocks_never_exceed_request() {
+        for requested_hz in (3_000..=60_000_000).step_by(9_973) {
+            let clock = SpiClock::new(88_000_000, requested_hz).unwrap();
+            assert!(clock.achieved_hz <= requested_hz, "{:?}", clock);
+            assert!(clock.sckdiv <= 255 && clock.prescaler <= 7);
+        }
+    }
+
+    #[test]
+    fn spi_clocks_too_slow() {
+        for &(root_hz, requested_hz) in &[
+            // Slowest: 88MHz / 128 / 257
+            (88_000_000, 2_674),
+            (24_000_000, 728),
+            (132_000_000, 1_000),
+            (88_000_000, 0),
+        ] {
+            assert!(SpiClock::new(root_hz, requested_hz).is_err());
+        }
+        assert!(SpiClock::new(88_000_000, 2_675).is_ok());
+        assert!(SpiClock::new(24_000_000, 729).is_ok());
+    }
+}
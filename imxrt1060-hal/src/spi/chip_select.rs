@@ -0,0 +1,161 @@
+//! Peripheral-controlled chip selects
+
+use super::SPI;
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+/// A peripheral-controlled chip select (PCS)
+///
+/// Each LPSPI has four PCS outputs. The SPI asserts one of them for each frame; see
+/// [`set_active_cs()`](struct.SPI.html#method.set_active_cs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Pcs {
+    /// PCS0
+    Pcs0 = 0,
+    /// PCS1
+    Pcs1 = 1,
+    /// PCS2
+    Pcs2 = 2,
+    /// PCS3
+    Pcs3 = 3,
+}
+
+impl Pcs {
+    fn from_field(pcs: u32) -> Self {
+        match pcs & 0b11 {
+            0 => Pcs::Pcs0,
+            1 => Pcs::Pcs1,
+            2 => Pcs::Pcs2,
+            _ => Pcs::Pcs3,
+        }
+    }
+}
+
+/// The level that asserts a chip select
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcsPolarity {
+    /// The chip select is low while asserted. This is the default.
+    ActiveLow,
+    /// The chip select is high while asserted
+    ActiveHigh,
+}
+
+/// Returns the TCR value that asserts `pcs` for the next frames
+pub(super) fn tcr_pcs(tcr: u32, pcs: Pcs) -> u32 {
+    use ral::lpspi::TCR::PCS;
+    (tcr & !PCS::mask) | ((pcs as u32) << PCS::offset)
+}
+
+/// Returns the CFGR1 value that asserts `pcs` at `polarity`
+fn cfgr1_pcspol(cfgr1: u32, pcs: Pcs, polarity: PcsPolarity) -> u32 {
+    use ral::lpspi::CFGR1::PCSPOL;
+    let bit = 1 << (PCSPOL::offset + pcs as u32);
+    match polarity {
+        PcsPolarity::ActiveLow => cfgr1 & !bit,
+        PcsPolarity::ActiveHigh => cfgr1 | bit,
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Choose the chip select that the next frames assert
+    ///
+    /// The choice takes effect at the next frame, so it never changes the chip select of
+    /// a frame that's on the wire. The default is [`Pcs::Pcs0`](enum.Pcs.html#variant.Pcs0).
+    /// Prepare PCS0's pad with [`enable_chip_select_0()`](#method.enable_chip_select_0). The
+    /// `iomuxc` pad tables only describe PCS0 pads, so mux the pads for PCS1 through PCS3
+    /// yourself.
+    ///
+    /// # Example
+    ///
+    /// A flash on PCS0, and a sensor on PCS1, share LPSPI4.
+    ///
+    /// ```no_run
+    /// use embedded_hal::blocking::spi::{Transfer, Write};
+    /// use imxrt1060_hal::spi::{Pcs, PcsPolarity};
+    /// # fn mux_pcs1_pad() {}
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let lpspi_clock = imxrt1060_hal::ccm::spi::lpspi_clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::spi::ClockSelect::Pll2,
+    ///     imxrt1060_hal::ccm::spi::PrescalarSelect::LPSPI_PODF_5,
+    /// );
+    /// let (_, _, _, builder) = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
+    /// let mut spi = builder.build(
+    ///     peripherals.iomuxc.b0.p02,
+    ///     peripherals.iomuxc.b0.p01,
+    ///     peripherals.iomuxc.b0.p03,
+    /// );
+    /// spi.enable_chip_select_0(peripherals.iomuxc.b0.p00);
+    /// // Your board's LPSPI4_PCS1 pad
+    /// mux_pcs1_pad();
+    /// // The sensor selects on a high level
+    /// spi.set_cs_polarity(Pcs::Pcs1, PcsPolarity::ActiveHigh);
+    ///
+    /// spi.set_active_cs(Pcs::Pcs0);
+    /// spi.write(&[0x06u8]).unwrap(); // Flash write enable
+    ///
+    /// spi.set_active_cs(Pcs::Pcs1);
+    /// let mut reading = [0x80u8 | 0x0F, 0];
+    /// spi.transfer(&mut reading).unwrap(); // Read the sensor's ID register
+    /// ```
+    pub fn set_active_cs(&mut self, pcs: Pcs) {
+        let tcr = ral::read_reg!(ral::lpspi, self.reg, TCR);
+        ral::write_reg!(ral::lpspi, self.reg, TCR, tcr_pcs(tcr, pcs));
+    }
+
+    /// Returns the chip select that the next frames assert
+    pub fn active_cs(&self) -> Pcs {
+        Pcs::from_field(ral::read_reg!(ral::lpspi, self.reg, TCR, PCS))
+    }
+
+    /// Set the level that asserts `pcs`
+    ///
+    /// Calling this method temporarily disables the SPI master. Call it between
+    /// transfers.
+    pub fn set_cs_polarity(&mut self, pcs: Pcs, polarity: PcsPolarity) {
+        self.with_master_disabled(|| {
+            let cfgr1 = ral::read_reg!(ral::lpspi, self.reg, CFGR1);
+            ral::write_reg!(
+                ral::lpspi,
+                self.reg,
+                CFGR1,
+                cfgr1_pcspol(cfgr1, pcs, polarity)
+            );
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cfgr1_pcspol, tcr_pcs, Pcs, PcsPolarity};
+
+    #[test]
+    fn tcr_composition() {
+        // TCR[PCS] is bits 25:24
+        assert_eq!(tcr_pcs(0, Pcs::Pcs0), 0);
+        assert_eq!(tcr_pcs(0, Pcs::Pcs1), 1 << 24);
+        assert_eq!(tcr_pcs(0, Pcs::Pcs3), 3 << 24);
+        // Other fields stay
+        let tcr = 1 << 31 | 7 << 27 | 3 << 24 | 7;
+        assert_eq!(tcr_pcs(tcr, Pcs::Pcs2), 1 << 31 | 7 << 27 | 2 << 24 | 7);
+        for &pcs in &[Pcs::Pcs0, Pcs::Pcs1, Pcs::Pcs2, Pcs::Pcs3] {
+            assert_eq!(Pcs::from_field(tcr_pcs(!0, pcs) >> 24), pcs);
+        }
+    }
+
+    #[test]
+    fn pcs_polarity() {
+        // CFGR1[PCSPOL] is bits 11:8, one for each PCS
+        assert_eq!(cfgr1_pcspol(0, Pcs::Pcs1, PcsPolarity::ActiveHigh), 1 << 9);
+        assert_eq!(
+            cfgr1_pcspol(0x1 | 0xF << 8, Pcs::Pcs3, PcsPolarity::ActiveLow),
+            0x1 | 0x7 << 8
+        );
+        assert_eq!(cfgr1_pcspol(0, Pcs::Pcs0, PcsPolarity::ActiveLow), 0);
+    }
+}
@@ -0,0 +1,331 @@
+//! Delays around each transfer
+
+use super::{DelayError, ModeError, SPI};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+const NS_PER_S: u64 = 1_000_000_000;
+/// Largest value of a CCR delay field
+const MAX_DELAY: u32 = 255;
+
+/// A CCR delay
+///
+/// Each delay lasts `field + offset` cycles of the prescaled clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delay {
+    /// CCR[PCSSCK]
+    PcsToSck,
+    /// CCR[SCKPCS]
+    SckToPcs,
+    /// CCR[DBT]
+    BetweenTransfers,
+}
+
+impl Delay {
+    fn offset(self) -> u32 {
+        match self {
+            Delay::PcsToSck | Delay::SckToPcs => 1,
+            Delay::BetweenTransfers => 2,
+        }
+    }
+}
+
+/// Returns the field value for the shortest `delay` that lasts at least `ns`,
+/// which may not fit in the field
+fn unbounded_field(delay: Delay, ns: u32, tick_hz: u32) -> u64 {
+    let tick_hz = u64::from(tick_hz);
+    let ticks = (u64::from(ns) * tick_hz + NS_PER_S - 1) / NS_PER_S;
+    let offset = u64::from(delay.offset());
+    ticks.max(offset) - offset
+}
+
+/// Returns the length of `delay`, in nanoseconds, when its field is `field`
+fn achieved_ns(delay: Delay, field: u32, tick_hz: u32) -> u32 {
+    let tick_hz = u64::from(tick_hz);
+    let cycles = u64::from(field + delay.offset());
+    ((cycles * NS_PER_S + tick_hz - 1) / tick_hz) as u32
+}
+
+/// Returns the field value for the shortest `delay` that lasts at least `ns`,
+/// and the length of that delay in nanoseconds
+fn delay_field(delay: Delay, ns: u32, tick_hz: u32) -> Result<(u32, u32), DelayError> {
+    let field = unbounded_field(delay, ns, tick_hz);
+    if field > u64::from(MAX_DELAY) {
+        return Err(DelayError(()));
+    }
+    let field = field as u32;
+    Ok((field, achieved_ns(delay, field, tick_hz)))
+}
+
+/// Chip select and word timings, in nanoseconds
+///
+/// See [`SPI::set_timings()`](struct.SPI.html#method.set_timings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timings {
+    /// From the chip select asserting to the first SCK edge
+    pub cs_setup: u32,
+    /// From the last SCK edge to the chip select deasserting
+    pub cs_hold: u32,
+    /// Between words, while the chip select is deasserted
+    pub inter_word: u32,
+}
+
+/// The CCR fields for a set of timings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimingFields {
+    pcssck: u32,
+    sckpcs: u32,
+    dbt: u32,
+}
+
+/// Returns the fields for the shortest delays that last at least `timings`, and
+/// the timings that they achieve
+///
+/// Each delay saturates at its longest value, so an achieved timing is shorter
+/// than the requested timing if the request is out of range.
+fn timing_fields(timings: &Timings, tick_hz: u32) -> (TimingFields, Timings) {
+    let field = |delay, ns| unbounded_field(delay, ns, tick_hz).min(u64::from(MAX_DELAY)) as u32;
+    let fields = TimingFields {
+        pcssck: field(Delay::PcsToSck, timings.cs_setup),
+        sckpcs: field(Delay::SckToPcs, timings.cs_hold),
+        dbt: field(Delay::BetweenTransfers, timings.inter_word),
+    };
+    (fields, achieved_timings(&fields, tick_hz))
+}
+
+/// Returns the timings of `fields`
+fn achieved_timings(fields: &TimingFields, tick_hz: u32) -> Timings {
+    Timings {
+        cs_setup: achieved_ns(Delay::PcsToSck, fields.pcssck, tick_hz),
+        cs_hold: achieved_ns(Delay::SckToPcs, fields.sckpcs, tick_hz),
+        inter_word: achieved_ns(Delay::BetweenTransfers, fields.dbt, tick_hz),
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Returns the frequency of the clock that counts the delays
+    fn prescaled_hz(&self) -> u32 {
+        self.source_clock.0 >> ral::read_reg!(ral::lpspi, self.reg, TCR, PRESCALE)
+    }
+
+    fn set_delay(&mut self, delay: Delay, ns: u32) -> Result<u32, DelayError> {
+        let (field, achieved_ns) = delay_field(delay, ns, self.prescaled_hz())?;
+        self.with_master_disabled(|| match delay {
+            Delay::PcsToSck => ral::modify_reg!(ral::lpspi, self.reg, CCR, PCSSCK: field),
+            Delay::SckToPcs => ral::modify_reg!(ral::lpspi, self.reg, CCR, SCKPCS: field),
+            Delay::BetweenTransfers => ral::modify_reg!(ral::lpspi, self.reg, CCR, DBT: field),
+        });
+        Ok(achieved_ns)
+    }
+
+    /// Set the delay from the chip select asserting to the first SCK edge
+    ///
+    /// The delay is at least `ns` nanoseconds. It's a whole number of prescaled clock
+    /// cycles, so `set_pcs_to_sck_delay()` returns the actual delay in nanoseconds. Returns
+    /// an error, without changing the delay, if the delay is longer than 256 cycles.
+    ///
+    /// Calling this method temporarily disables the SPI master. Set delays after
+    /// [`set_clock_speed()`](#method.set_clock_speed), since it resets them.
+    ///
+    /// # Example
+    ///
+    /// An ADC needs 100ns between the chip select and the clock, and 50ns to release
+    /// its output before the next conversion starts 1us later.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::spi::ClockSpeed;
+    /// # fn adc<M: imxrt1060_hal::iomuxc::consts::Unsigned>(spi: &mut imxrt1060_hal::spi::SPI<M>) {
+    ///
+    /// spi.set_clock_speed(ClockSpeed(10_000_000)).unwrap();
+    /// let pcs_to_sck = spi.set_pcs_to_sck_delay(100).unwrap();
+    /// let sck_to_pcs = spi.set_sck_to_pcs_delay(50).unwrap();
+    /// let between = spi.set_between_transfer_delay(1_000).unwrap();
+    /// log::info!("{}ns, {}ns, {}ns", pcs_to_sck, sck_to_pcs, between);
+    /// # }
+    /// ```
+    pub fn set_pcs_to_sck_delay(&mut self, ns: u32) -> Result<u32, DelayError> {
+        self.set_delay(Delay::PcsToSck, ns)
+    }
+
+    /// Set the delay from the last SCK edge to the chip select deasserting
+    ///
+    /// The delay is at least `ns` nanoseconds, and at most 256 cycles. Returns the actual
+    /// delay; see [`set_pcs_to_sck_delay()`](#method.set_pcs_to_sck_delay).
+    pub fn set_sck_to_pcs_delay(&mut self, ns: u32) -> Result<u32, DelayError> {
+        self.set_delay(Delay::SckToPcs, ns)
+    }
+
+    /// Set the delay between transfers, while the chip select is deasserted
+    ///
+    /// The delay is at least `ns` nanoseconds, and at least two cycles, up to 257 cycles.
+    /// Returns the actual delay; see [`set_pcs_to_sck_delay()`](#method.set_pcs_to_sck_delay).
+    pub fn set_between_transfer_delay(&mut self, ns: u32) -> Result<u32, DelayError> {
+        self.set_delay(Delay::BetweenTransfers, ns)
+    }
+
+    /// Set all three delays, and return the timings that the SPI achieves
+    ///
+    /// Each achieved timing is the shortest whole number of prescaled clock cycles that
+    /// lasts at least the requested timing. A timing that's longer than the longest delay
+    /// saturates, so compare the achieved timings to your device's limits. A chip select
+    /// setup or hold is at most 256 cycles, and the time between words is at most 257
+    /// cycles.
+    ///
+    /// `set_timings()` waits for the SPI to send its queued frames, so the new timings
+    /// start with the next transfer. It returns an error, without changing the timings,
+    /// if the SPI doesn't go idle. Like the other delays, set the timings after
+    /// [`set_clock_speed()`](#method.set_clock_speed).
+    ///
+    /// # Example
+    ///
+    /// A level-shifted bus needs 200ns of chip select setup, and 1us between words.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::spi::Timings;
+    /// # fn bus<M: imxrt1060_hal::iomuxc::consts::Unsigned>(spi: &mut imxrt1060_hal::spi::SPI<M>) {
+    ///
+    /// let requested = Timings {
+    ///     cs_setup: 200,
+    ///     cs_hold: 0,
+    ///     inter_word: 1_000,
+    /// };
+    /// let achieved = spi.set_timings(requested).unwrap();
+    /// assert!(achieved.cs_setup >= 200 && achieved.inter_word >= 1_000);
+    /// # }
+    /// ```
+    pub fn set_timings(&mut self, timings: Timings) -> Result<Timings, ModeError> {
+        let (fields, achieved) = timing_fields(&timings, self.prescaled_hz());
+        self.quiesce()?;
+        self.with_master_disabled(|| {
+            ral::modify_reg!(
+                ral::lpspi,
+                self.reg,
+                CCR,
+                PCSSCK: fields.pcssck,
+                SCKPCS: fields.sckpcs,
+                DBT: fields.dbt
+            )
+        });
+        Ok(achieved)
+    }
+
+    /// Returns the timings of the SPI's current delays
+    pub fn timings(&self) -> Timings {
+        let (pcssck, sckpcs, dbt) = ral::read_reg!(ral::lpspi, self.reg, CCR, PCSSCK, SCKPCS, DBT);
+        achieved_timings(
+            &TimingFields {
+                pcssck,
+                sckpcs,
+                dbt,
+            },
+            self.prescaled_hz(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delay_field, timing_fields, Delay, TimingFields, Timings};
+
+    #[test]
+    fn delay_fields() {
+        for &(delay, ns, tick_hz, field, achieved_ns) in &[
+            // 10ns cycles
+            (Delay::PcsToSck, 100, 100_000_000, 9, 100),
+            (Delay::SckToPcs, 95, 100_000_000, 9, 100),
+            (Delay::BetweenTransfers, 100, 100_000_000, 8, 100),
+            // Shorter than the offset
+            (Delay::PcsToSck, 0, 100_000_000, 0, 10),
+            (Delay::BetweenTransfers, 5, 100_000_000, 0, 20),
+            // 15.15..ns cycles; rounds up
+            (Delay::PcsToSck, 50, 66_000_000, 3, 61),
+            // The longest delays
+            (Delay::SckToPcs, 2_560, 100_000_000, 255, 2_560),
+            (Delay::BetweenTransfers, 2_570, 100_000_000, 255, 2_570),
+            // 128 prescaler from 88MHz
+            (Delay::PcsToSck, 100_000, 687_500, 68, 100_364),
+        ] {
+            assert_eq!(
+                delay_field(delay, ns, tick_hz).unwrap(),
+                (field, achieved_ns),
+                "{:?} {} {}",
+                delay,
+                ns,
+                tick_hz
+            );
+        }
+    }
+
+    #[test]
+    fn delays_too_long() {
+        assert!(delay_field(Delay::SckToPcs, 2_561, 100_000_000).is_err());
+        assert!(delay_field(Delay::BetweenTransfers, 2_571, 100_000_000).is_err());
+        assert!(delay_field(Delay::PcsToSck, u32::max_value(), 100_000_000).is_err());
+    }
+
+    #[test]
+    fn timings_across_clocks() {
+        let requested = Timings {
+            cs_setup: 200,
+            cs_hold: 50,
+            inter_word: 1_000,
+        };
+        for &(tick_hz, pcssck, sckpcs, dbt, achieved) in &[
+            // 10ns cycles
+            (100_000_000, 19, 4, 98, (200, 50, 1_000)),
+            // 7.57..ns cycles, from 132MHz; rounds up
+            (132_000_000, 26, 6, 130, (205, 54, 1_000)),
+            // 15.15..ns cycles
+            (66_000_000, 13, 3, 64, (213, 61, 1_000)),
+            // 1us cycles; every delay is at least its offset
+            (1_000_000, 0, 0, 0, (1_000, 1_000, 2_000)),
+        ] {
+            let (fields, timings) = timing_fields(&requested, tick_hz);
+            assert_eq!(
+                fields,
+                TimingFields {
+                    pcssck,
+                    sckpcs,
+                    dbt
+                },
+                "{}",
+                tick_hz
+            );
+            assert_eq!(
+                (timings.cs_setup, timings.cs_hold, timings.inter_word),
+                achieved,
+                "{}",
+                tick_hz
+            );
+        }
+    }
+
+    #[test]
+    fn timings_saturate() {
+        let requested = Timings {
+            cs_setup: 10_000,
+            cs_hold: u32::max_value(),
+            inter_word: 2_570,
+        };
+        let (fields, timings) = timing_fields(&requested, 100_000_000);
+        assert_eq!(
+            fields,
+            TimingFields {
+                pcssck: 255,
+                sckpcs: 255,
+                dbt: 255
+            }
+        );
+        assert_eq!(
+            timings,
+            Timings {
+                cs_setup: 2_560,
+                cs_hold: 2_560,
+                inter_word: 2_570,
+            }
+        );
+    }
+}
@@ -0,0 +1,823 @@
+//! `embedded-hal` 1.0 traits, and a bus that devices share
+//!
+//! Enable these implementations with the `"embedded-hal-1"` feature. The `SpiBus`
+//! methods use the same FIFO bursts as the blocking `embedded_hal` 0.2 traits.
+
+use super::{
+    transaction, ClockSpeed, ClockSpeedError, Device, Error, SpiClock, TransferError, SPI,
+};
+use crate::iomuxc::consts::Unsigned;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::spi::{ErrorKind, ErrorType, Operation, SpiBus, SpiDevice};
+
+/// Receive overflows lost data; the other errors don't have a matching kind
+impl embedded_hal_1::spi::Error for Error {
+    #[allow(deprecated)]
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Receive | Error::Transfer(TransferError::ReceiveOverflow) => ErrorKind::Overrun,
+            Error::Transmit
+            | Error::DataMismatch
+            | Error::WaitTimeout
+            | Error::Transfer(TransferError::TransmitUnderrun)
+            | Error::Transfer(TransferError::DataMismatch) => ErrorKind::Other,
+        }
+    }
+}
+
+impl<M: Unsigned> ErrorType for SPI<M> {
+    type Error = Error;
+}
+
+macro_rules! spi_bus {
+    ($($word:ty),*) => {
+        $(
+            /// `read()` sends zeros. A `transfer()` lasts for the longer buffer; it sends
+            /// zeros after `write`, and discards the words after `read`.
+            impl<M: Unsigned> SpiBus<$word> for SPI<M> {
+                fn read(&mut self, words: &mut [$word]) -> Result<(), Error> {
+                    self.transfer_split(words, &[])
+                }
+                fn write(&mut self, words: &[$word]) -> Result<(), Error> {
+                    self.write_words(words)
+                }
+                fn transfer(&mut self, read: &mut [$word], write: &[$word]) -> Result<(), Error> {
+                    self.transfer_split(read, write)
+                }
+                fn transfer_in_place(&mut self, words: &mut [$word]) -> Result<(), Error> {
+                    self.transfer_words(words).map(|_| ())
+                }
+                fn flush(&mut self) -> Result<(), Error> {
+                    Bus::wait_idle(self)
+                }
+            }
+        )*
+    };
+}
+
+spi_bus!(u8, u16, u32);
+
+/// The settings that a device applies to the bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Config {
+    device: Device,
+    clock: SpiClock,
+}
+
+/// The bus control around a device's transaction
+trait Bus {
+    /// Apply a device's settings
+    fn configure(&mut self, config: &Config) -> Result<(), Error>;
+    /// Hold the peripheral-controlled chip select asserted across frames
+    fn begin(&mut self);
+    /// Release the peripheral-controlled chip select after the last frame
+    fn end(&mut self);
+    /// Wait for the last frame to finish
+    fn wait_idle(&mut self) -> Result<(), Error>;
+}
+
+impl<M> Bus for SPI<M>
+where
+    M: Unsigned,
+{
+    fn configure(&mut self, config: &Config) -> Result<(), Error> {
+        self.update_tcr(|tcr| transaction::tcr_device(tcr, &config.device))
+            .map_err(|_| Error::WaitTimeout)?;
+        // Setting the clock resets the delays, so keep them if the clock is the same
+        if self.clock() != config.clock {
+            self.with_master_disabled(|| unsafe {
+                // Safety: master is disabled
+                config.clock.set(&self.reg)
+            });
+        }
+        Ok(())
+    }
+    fn begin(&mut self) {
+        transaction::begin(self);
+    }
+    fn end(&mut self) {
+        transaction::end(self);
+    }
+    fn wait_idle(&mut self) -> Result<(), Error> {
+        self.quiesce().map_err(|_| Error::WaitTimeout)
+    }
+}
+
+/// A bus, and the settings of the device that used it last
+struct State<B> {
+    bus: B,
+    config: Option<Config>,
+}
+
+impl<B: Bus> State<B> {
+    /// Apply `config`, unless the bus already has it
+    fn acquire(&mut self, config: &Config) -> Result<&mut B, Error> {
+        if self.config.as_ref() != Some(config) {
+            // If configuring fails part way, the next device configures the bus again
+            self.config = None;
+            self.bus.configure(config)?;
+            self.config = Some(*config);
+        }
+        Ok(&mut self.bus)
+    }
+}
+
+/// Run `operations` on `bus`
+///
+/// Each operation returns once it receives its last frame, so a delay starts after the
+/// frames before it.
+fn operate<B, W, D>(
+    bus: &mut B,
+    operations: &mut [Operation<'_, W>],
+    delay: &mut D,
+) -> Result<(), Error>
+where
+    B: SpiBus<W, Error = Error>,
+    W: Copy + 'static,
+    D: DelayNs,
+{
+    for operation in operations {
+        match operation {
+            Operation::Read(words) => bus.read(words)?,
+            Operation::Write(words) => bus.write(words)?,
+            Operation::Transfer(read, write) => bus.transfer(read, write)?,
+            Operation::TransferInPlace(words) => bus.transfer_in_place(words)?,
+            Operation::DelayNs(ns) => delay.delay_ns(*ns),
+        }
+    }
+    Ok(())
+}
+
+/// A transaction of a device on a peripheral-controlled chip select
+///
+/// The chip select deasserts after the last frame, even if an operation fails.
+fn pcs_transaction<B, W, D>(
+    state: &mut State<B>,
+    config: &Config,
+    operations: &mut [Operation<'_, W>],
+    delay: &mut D,
+) -> Result<(), Error>
+where
+    B: Bus + SpiBus<W, Error = Error>,
+    W: Copy + 'static,
+    D: DelayNs,
+{
+    let bus = state.acquire(config)?;
+    bus.begin();
+    let result = operate(bus, operations, delay);
+    bus.end();
+    result.and(bus.wait_idle())
+}
+
+/// A transaction of a device on a GPIO chip select
+///
+/// The chip select deasserts after the last frame, even if an operation fails.
+fn gpio_transaction<B, W, D, CS>(
+    state: &mut State<B>,
+    config: &Config,
+    cs: &mut CS,
+    operations: &mut [Operation<'_, W>],
+    delay: &mut D,
+) -> Result<(), DeviceError<CS::Error>>
+where
+    B: Bus + SpiBus<W, Error = Error>,
+    W: Copy + 'static,
+    D: DelayNs,
+    CS: OutputPin,
+{
+    let bus = state.acquire(config).map_err(DeviceError::Spi)?;
+    cs.set_low().map_err(DeviceError::ChipSelect)?;
+    let result = operate(bus, operations, delay).and_then(|()| bus.wait_idle());
+    let deselected = cs.set_high();
+    result.map_err(DeviceError::Spi)?;
+    deselected.map_err(DeviceError::ChipSelect)
+}
+
+/// A SPI bus that devices share
+///
+/// Each device on the bus, a [`PcsDevice`](struct.PcsDevice.html) or a
+/// [`GpioDevice`](struct.GpioDevice.html), implements `embedded_hal` 1.0's `SpiDevice`.
+/// A device takes the bus for its transaction, and interrupts stay enabled. A
+/// transaction that interrupts another device's transaction can't take the bus, and
+/// returns [`DeviceError::Busy`](enum.DeviceError.html#variant.Busy) without touching
+/// the bus.
+///
+/// Before its transaction, a device applies its chip select, mode, bit order, and clock
+/// speed, unless it was the last device on the bus. Changing the clock speed resets the
+/// delays around each transfer, so devices that need their own delays should share a
+/// clock speed, or set their delays in [`lock()`](#method.lock).
+///
+/// # Example
+///
+/// A flash on PCS0, and a sensor on a GPIO chip select, share LPSPI4. The devices only
+/// need a `&SharedBus`, so each may move to the task that uses it.
+///
+/// ```no_run
+/// use embedded_hal::spi::{MODE_0, MODE_3};
+/// use embedded_hal_1::spi::{Operation, SpiDevice};
+/// use imxrt1060_hal::gpio::GPIO;
+/// use imxrt1060_hal::iomuxc::consts::U4;
+/// use imxrt1060_hal::spi::{
+///     BitOrder, ClockSpeed, Device, GpioDevice, Pcs, PcsDevice, SharedBus,
+/// };
+///
+/// /// Busy-waits, assuming a 600MHz core
+/// struct Delay;
+/// impl embedded_hal_1::delay::DelayNs for Delay {
+///     fn delay_ns(&mut self, ns: u32) {
+///         cortex_m::asm::delay(ns.saturating_mul(3) / 5 + 1);
+///     }
+/// }
+///
+/// /// A driver that only needs a `SpiDevice`
+/// fn read_id<S: SpiDevice>(device: &mut S, command: u8) -> Result<[u8; 3], S::Error> {
+///     let mut id = [0; 3];
+///     device.transaction(&mut [Operation::Write(&[command]), Operation::Read(&mut id)])?;
+///     Ok(id)
+/// }
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let lpspi_clock = imxrt1060_hal::ccm::spi::lpspi_clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::spi::ClockSelect::Pll2,
+///     imxrt1060_hal::ccm::spi::PrescalarSelect::LPSPI_PODF_5,
+/// );
+/// let (_, _, _, builder) = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
+/// let spi = builder.build_with_pcs0(
+///     peripherals.iomuxc.b0.p02,
+///     peripherals.iomuxc.b0.p01,
+///     peripherals.iomuxc.b0.p03,
+///     peripherals.iomuxc.b0.p00,
+/// );
+/// let bus: &'static SharedBus<U4> =
+///     cortex_m::singleton!(: SharedBus<U4> = SharedBus::new(spi)).unwrap();
+///
+/// let flash = Device { pcs: Pcs::Pcs0, mode: MODE_0, bit_order: BitOrder::MsbFirst };
+/// let mut flash = PcsDevice::new(bus, flash, ClockSpeed(20_000_000), Delay).unwrap();
+///
+/// // The sensor's frames assert PCS3, which has no pad
+/// let sensor = Device { pcs: Pcs::Pcs3, mode: MODE_3, bit_order: BitOrder::MsbFirst };
+/// let mut cs = GPIO::new(peripherals.iomuxc.b0.p10).output();
+/// cs.set();
+/// let mut sensor = GpioDevice::new(bus, sensor, ClockSpeed(1_000_000), cs, Delay).unwrap();
+///
+/// let jedec = read_id(&mut flash, 0x9F).unwrap();
+/// let whoami = read_id(&mut sensor, 0x80 | 0x0F).unwrap();
+/// log::info!("{:?} {:?}", jedec, whoami);
+/// ```
+pub struct SharedBus<M> {
+    state: Lock<State<SPI<M>>>,
+    source_hz: u32,
+}
+
+/// A value that one context at a time may use
+///
+/// A context that finds the value in use can't wait for it, since it may have interrupted
+/// the user.
+struct Lock<T> {
+    value: UnsafeCell<T>,
+    /// Set while someone uses the value
+    busy: AtomicBool,
+}
+
+// Safety: only the holder of the busy flag touches the value.
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+impl<T> Lock<T> {
+    fn new(value: T) -> Self {
+        Lock {
+            value: UnsafeCell::new(value),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    /// Run `f` with the value, or return `None` if someone else uses the value
+    fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        // Safety: the busy flag gives us the only reference to the value.
+        let result = f(unsafe { &mut *self.value.get() });
+        self.busy.store(false, Ordering::Release);
+        Some(result)
+    }
+}
+
+impl<M> SharedBus<M>
+where
+    M: Unsigned,
+{
+    /// Share `spi` among devices
+    pub fn new(spi: SPI<M>) -> Self {
+        SharedBus {
+            source_hz: spi.source_clock.0,
+            state: Lock::new(State {
+                bus: spi,
+                config: None,
+            }),
+        }
+    }
+
+    /// Use the SPI between device transactions
+    ///
+    /// `f` may change any setting, since the next device applies its settings again.
+    /// Returns `None`, without calling `f`, if `lock()` interrupted a device's
+    /// transaction.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut SPI<M>) -> R) -> Option<R> {
+        self.transaction(|state| {
+            state.config = None;
+            f(&mut state.bus)
+        })
+    }
+
+    /// Run `f` with the bus, or return `None` if someone else has the bus
+    fn transaction<R>(&self, f: impl FnOnce(&mut State<SPI<M>>) -> R) -> Option<R> {
+        self.state.try_with(f)
+    }
+
+    fn config(&self, device: Device, clock_speed: ClockSpeed) -> Result<Config, ClockSpeedError> {
+        Ok(Config {
+            device,
+            clock: SpiClock::new(self.source_hz, clock_speed.0)?,
+        })
+    }
+}
+
+/// An error from a device on a [`SharedBus`](struct.SharedBus.html)
+///
+/// A [`PcsDevice`](struct.PcsDevice.html) has no chip select pin, so its `CS` error is
+/// `Infallible`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceError<CS> {
+    /// The SPI failed
+    Spi(Error),
+    /// The chip select failed
+    ChipSelect(CS),
+    /// The transaction interrupted another device's transaction
+    ///
+    /// The transaction didn't touch the bus, or its chip select. Try again once the
+    /// other transaction finishes.
+    Busy,
+}
+
+impl<CS: core::fmt::Debug> embedded_hal_1::spi::Error for DeviceError<CS> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            DeviceError::Spi(err) => err.kind(),
+            DeviceError::ChipSelect(_) => ErrorKind::ChipSelectFault,
+            DeviceError::Busy => ErrorKind::Other,
+        }
+    }
+}
+
+/// A device on a peripheral-controlled chip select of a [`SharedBus`](struct.SharedBus.html)
+///
+/// The chip select stays asserted for the whole transaction.
+pub struct PcsDevice<'a, M, D> {
+    bus: &'a SharedBus<M>,
+    config: Config,
+    delay: D,
+}
+
+impl<'a, M, D> PcsDevice<'a, M, D>
+where
+    M: Unsigned,
+    D: DelayNs,
+{
+    /// Create a device that talks to `device` at, or below, `clock_speed`
+    ///
+    /// `delay` runs the `Operation::DelayNs` in the device's transactions. Returns an
+    /// error if the SPI can't run as slowly as `clock_speed`.
+    pub fn new(
+        bus: &'a SharedBus<M>,
+        device: Device,
+        clock_speed: ClockSpeed,
+        delay: D,
+    ) -> Result<Self, ClockSpeedError> {
+        Ok(PcsDevice {
+            bus,
+            config: bus.config(device, clock_speed)?,
+            delay,
+        })
+    }
+}
+
+impl<'a, M, D> PcsDevice<'a, M, D> {
+    /// Returns the device's clock speed
+    pub fn clock_speed(&self) -> ClockSpeed {
+        ClockSpeed(self.config.clock.achieved_hz)
+    }
+}
+
+impl<M, D> ErrorType for PcsDevice<'_, M, D>
+where
+    M: Unsigned,
+{
+    type Error = DeviceError<core::convert::Infallible>;
+}
+
+impl<M, D, W> SpiDevice<W> for PcsDevice<'_, M, D>
+where
+    M: Unsigned,
+    D: DelayNs,
+    W: Copy + 'static,
+    SPI<M>: SpiBus<W, Error = Error>,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        let (config, delay) = (&self.config, &mut self.delay);
+        self.bus
+            .transaction(|state| pcs_transaction(state, config, operations, delay))
+            .ok_or(DeviceError::Busy)?
+            .map_err(DeviceError::Spi)
+    }
+}
+
+/// A device on a GPIO chip select of a [`SharedBus`](struct.SharedBus.html)
+///
+/// `cs` is an active-low `embedded_hal` 0.2 `OutputPin`, like a GPIO output. The SPI still
+/// asserts the device's peripheral-controlled chip select for each frame, so choose a PCS
+/// without a pad, or without another device.
+pub struct GpioDevice<'a, M, CS, D> {
+    bus: &'a SharedBus<M>,
+    config: Config,
+    cs: CS,
+    delay: D,
+}
+
+impl<'a, M, CS, D> GpioDevice<'a, M, CS, D>
+where
+    M: Unsigned,
+    CS: OutputPin,
+    D: DelayNs,
+{
+    /// Create a device that talks to `device` at, or below, `clock_speed`, and selects
+    /// it with `cs`
+    ///
+    /// Set `cs` high before the first transaction. `delay` runs the `Operation::DelayNs`
+    /// in the device's transactions. Returns an error if the SPI can't run as slowly as
+    /// `clock_speed`.
+    pub fn new(
+        bus: &'a SharedBus<M>,
+        device: Device,
+        clock_speed: ClockSpeed,
+        cs: CS,
+        delay: D,
+    ) -> Result<Self, ClockSpeedError> {
+        Ok(GpioDevice {
+            bus,
+            config: bus.config(device, clock_speed)?,
+            cs,
+            delay,
+        })
+    }
+}
+
+impl<'a, M, CS, D> GpioDevice<'a, M, CS, D> {
+    /// Returns the device's clock speed
+    pub fn clock_speed(&self) -> ClockSpeed {
+        ClockSpeed(self.config.clock.achieved_hz)
+    }
+
+    /// Release the chip select
+    pub fn release(self) -> CS {
+        self.cs
+    }
+}
+
+impl<M, CS, D> ErrorType for GpioDevice<'_, M, CS, D>
+where
+    M: Unsigned,
+    CS: OutputPin,
+    CS::Error: core::fmt::Debug,
+{
+    type Error = DeviceError<CS::Error>;
+}
+
+impl<M, CS, D, W> SpiDevice<W> for GpioDevice<'_, M, CS, D>
+where
+    M: Unsigned,
+    CS: OutputPin,
+    CS::Error: core::fmt::Debug,
+    D: DelayNs,
+    W: Copy + 'static,
+    SPI<M>: SpiBus<W, Error = Error>,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        let (config, cs, delay) = (&self.config, &mut self.cs, &mut self.delay);
+        self.bus
+            .transaction(|state| gpio_transaction(state, config, cs, operations, delay))
+            .ok_or(DeviceError::Busy)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        gpio_transaction, pcs_transaction, Bus, Config, DeviceError, Error, ErrorType, Lock, State,
+        TransferError,
+    };
+    use crate::spi::{BitOrder, Device, Pcs, SpiClock};
+    use crate::testing::EventLog;
+    use core::cell::RefCell;
+    use embedded_hal::spi::{MODE_0, MODE_3};
+    use embedded_hal_1::delay::DelayNs;
+    use embedded_hal_1::spi::{Operation, SpiBus};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Event {
+        Configure(Pcs, u32),
+        Begin,
+        End,
+        WaitIdle,
+        Write(usize),
+        Read(usize),
+        Delay(u32),
+        CsLow,
+        CsHigh,
+    }
+
+    type Log = EventLog<Event>;
+
+    /// Check, and forget, the events so far
+    fn expect(log: &RefCell<Log>, expected: &[Event]) {
+        let mut log = log.borrow_mut();
+        log.assert(expected);
+        log.clear();
+    }
+
+    struct MockBus<'a> {
+        log: &'a RefCell<Log>,
+        fail_configure: bool,
+        fail_write: bool,
+    }
+
+    impl Bus for MockBus<'_> {
+        fn configure(&mut self, config: &Config) -> Result<(), Error> {
+            if self.fail_configure {
+                return Err(Error::WaitTimeout);
+            }
+            self.log.borrow_mut().push(Event::Configure(
+                config.device.pcs,
+                config.clock.achieved_hz,
+            ));
+            Ok(())
+        }
+        fn begin(&mut self) {
+            self.log.borrow_mut().push(Event::Begin);
+        }
+        fn end(&mut self) {
+            self.log.borrow_mut().push(Event::End);
+        }
+        fn wait_idle(&mut self) -> Result<(), Error> {
+            self.log.borrow_mut().push(Event::WaitIdle);
+            Ok(())
+        }
+    }
+
+    impl ErrorType for MockBus<'_> {
+        type Error = Error;
+    }
+
+    impl SpiBus<u8> for MockBus<'_> {
+        fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+            self.log.borrow_mut().push(Event::Read(words.len()));
+            Ok(())
+        }
+        fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+            if self.fail_write {
+                return Err(Error::Transfer(TransferError::TransmitUnderrun));
+            }
+            self.log.borrow_mut().push(Event::Write(words.len()));
+            Ok(())
+        }
+        fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+            self.write(write)?;
+            self.read(read)
+        }
+        fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+            self.write(words)?;
+            self.read(words)
+        }
+        fn flush(&mut self) -> Result<(), Error> {
+            self.wait_idle()
+        }
+    }
+
+    struct MockCs<'a>(&'a RefCell<Log>);
+
+    impl embedded_hal::digital::v2::OutputPin for MockCs<'_> {
+        type Error = core::convert::Infallible;
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push(Event::CsLow);
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push(Event::CsHigh);
+            Ok(())
+        }
+    }
+
+    struct MockDelay<'a>(&'a RefCell<Log>);
+
+    impl DelayNs for MockDelay<'_> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0.borrow_mut().push(Event::Delay(ns));
+        }
+    }
+
+    fn config(pcs: Pcs, mode: embedded_hal::spi::Mode, hz: u32) -> Config {
+        Config {
+            device: Device {
+                pcs,
+                mode,
+                bit_order: BitOrder::MsbFirst,
+            },
+            clock: SpiClock::new(88_000_000, hz).unwrap(),
+        }
+    }
+
+    fn state(log: &RefCell<Log>) -> State<MockBus<'_>> {
+        State {
+            bus: MockBus {
+                log,
+                fail_configure: false,
+                fail_write: false,
+            },
+            config: None,
+        }
+    }
+
+    #[test]
+    fn devices_switch_settings() {
+        let log = RefCell::new(Log::default());
+        let mut state = state(&log);
+        let mut delay = MockDelay(&log);
+        let flash = config(Pcs::Pcs0, MODE_0, 22_000_000);
+        let sensor = config(Pcs::Pcs1, MODE_3, 1_000_000);
+
+        for _ in 0..2 {
+            pcs_transaction(
+                &mut state,
+                &flash,
+                &mut [Operation::Write(&[0x9F])],
+                &mut delay,
+            )
+            .unwrap();
+        }
+        // The flash configures the bus once
+        expect(
+            &log,
+            &[
+                Event::Configure(Pcs::Pcs0, 22_000_000),
+                Event::Begin,
+                Event::Write(1),
+                Event::End,
+                Event::WaitIdle,
+                Event::Begin,
+                Event::Write(1),
+                Event::End,
+                Event::WaitIdle,
+            ],
+        );
+
+        pcs_transaction(&mut state, &sensor, &mut [], &mut delay).unwrap();
+        pcs_transaction(&mut state, &flash, &mut [], &mut delay).unwrap();
+        expect(
+            &log,
+            &[
+                Event::Configure(Pcs::Pcs1, 1_000_000),
+                Event::Begin,
+                Event::End,
+                Event::WaitIdle,
+                Event::Configure(Pcs::Pcs0, 22_000_000),
+                Event::Begin,
+                Event::End,
+                Event::WaitIdle,
+            ],
+        );
+    }
+
+    #[test]
+    fn failed_configuration_retries() {
+        let log = RefCell::new(Log::default());
+        let mut state = state(&log);
+        let mut delay = MockDelay(&log);
+        let flash = config(Pcs::Pcs0, MODE_0, 22_000_000);
+
+        state.bus.fail_configure = true;
+        assert_eq!(
+            pcs_transaction(&mut state, &flash, &mut [], &mut delay),
+            Err(Error::WaitTimeout)
+        );
+        // Nothing touched the chip select
+        expect(&log, &[]);
+
+        state.bus.fail_configure = false;
+        pcs_transaction(&mut state, &flash, &mut [], &mut delay).unwrap();
+        expect(
+            &log,
+            &[
+                Event::Configure(Pcs::Pcs0, 22_000_000),
+                Event::Begin,
+                Event::End,
+                Event::WaitIdle,
+            ],
+        );
+    }
+
+    #[test]
+    fn gpio_chip_select() {
+        let log = RefCell::new(Log::default());
+        let mut state = state(&log);
+        let mut delay = MockDelay(&log);
+        let mut cs = MockCs(&log);
+        let sensor = config(Pcs::Pcs3, MODE_3, 1_000_000);
+
+        let mut whoami = [0; 1];
+        gpio_transaction(
+            &mut state,
+            &sensor,
+            &mut cs,
+            &mut [
+                Operation::Write(&[0x8F]),
+                Operation::DelayNs(500),
+                Operation::Read(&mut whoami),
+            ],
+            &mut delay,
+        )
+        .unwrap();
+        // The chip select deasserts once the frames finish
+        expect(
+            &log,
+            &[
+                Event::Configure(Pcs::Pcs3, 1_000_000),
+                Event::CsLow,
+                Event::Write(1),
+                Event::Delay(500),
+                Event::Read(1),
+                Event::WaitIdle,
+                Event::CsHigh,
+            ],
+        );
+
+        // A failure still deasserts the chip select
+        state.bus.fail_write = true;
+        assert_eq!(
+            gpio_transaction(
+                &mut state,
+                &sensor,
+                &mut cs,
+                &mut [Operation::Write(&[0x8F]), Operation::Read(&mut whoami)],
+                &mut delay,
+            ),
+            Err(DeviceError::Spi(Error::Transfer(
+                TransferError::TransmitUnderrun
+            )))
+        );
+        expect(&log, &[Event::CsLow, Event::CsHigh]);
+    }
+
+    #[test]
+    fn pcs_released_after_failure() {
+        let log = RefCell::new(Log::default());
+        let mut state = state(&log);
+        let mut delay = MockDelay(&log);
+        let flash = config(Pcs::Pcs0, MODE_0, 22_000_000);
+
+        state.bus.fail_write = true;
+        assert_eq!(
+            pcs_transaction(
+                &mut state,
+                &flash,
+                &mut [Operation::Write(&[0x06]), Operation::Read(&mut [0; 4])],
+                &mut delay,
+            ),
+            Err(Error::Transfer(TransferError::TransmitUnderrun))
+        );
+        expect(
+            &log,
+            &[
+                Event::Configure(Pcs::Pcs0, 22_000_000),
+                Event::Begin,
+                Event::End,
+                Event::WaitIdle,
+            ],
+        );
+    }
+
+    #[test]
+    fn interrupting_transaction_is_busy() {
+        let lock = Lock::new(0);
+        let nested = lock.try_with(|value| {
+            *value += 1;
+            // An interrupt handler's transaction, during this one
+            lock.try_with(|value| *value += 1)
+        });
+        assert_eq!(nested, Some(None));
+        // The bus is free again
+        assert_eq!(lock.try_with(|value| *value), Some(1));
+    }
+}
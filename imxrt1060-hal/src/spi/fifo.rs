@@ -0,0 +1,669 @@
+//! FIFO watermarks, and blocking transfers that fill the FIFOs
+
+use super::{frame::word_mask, Error, WatermarkError, RETRIES, SPI};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use embedded_hal::blocking::spi::{Transfer, Write};
+
+/// The LPSPI FIFOs, as the blocking transfers see them
+trait Fifo {
+    /// Returns the number of words that the TX FIFO can take, or an error status
+    fn tx_space(&mut self) -> Result<usize, Error>;
+    /// Put a word in the TX FIFO
+    fn push(&mut self, word: u32);
+    /// Take a word from the RX FIFO, if there is one
+    fn pop(&mut self) -> Option<u32>;
+    /// The number of words that the RX FIFO holds
+    fn rx_depth(&self) -> usize;
+    /// Returns the error from the status flags, if there is one
+    fn check(&mut self) -> Result<(), Error>;
+}
+
+/// The words of a blocking transfer
+trait Words {
+    fn len(&self) -> usize;
+    /// Returns the FIFO word that sends frame `idx`
+    fn send(&self, idx: usize) -> u32;
+    /// Handle the FIFO word that was received for frame `idx`
+    fn receive(&mut self, idx: usize, word: u32);
+}
+
+/// A word type for blocking transfers
+pub(super) trait Word: Copy + Into<u32> {
+    fn from_fifo(word: u32) -> Self;
+}
+
+impl Word for u8 {
+    fn from_fifo(word: u32) -> Self {
+        word as u8
+    }
+}
+
+impl Word for u16 {
+    fn from_fifo(word: u32) -> Self {
+        word as u16
+    }
+}
+
+impl Word for u32 {
+    fn from_fifo(word: u32) -> Self {
+        word
+    }
+}
+
+/// Words that are replaced by the received words
+struct Exchange<'a, W> {
+    words: &'a mut [W],
+    mask: u32,
+}
+
+impl<W: Word> Words for Exchange<'_, W> {
+    fn len(&self) -> usize {
+        self.words.len()
+    }
+    fn send(&self, idx: usize) -> u32 {
+        self.words[idx].into() & self.mask
+    }
+    fn receive(&mut self, idx: usize, word: u32) {
+        self.words[idx] = W::from_fifo(word & self.mask);
+    }
+}
+
+/// Words that are sent, with the received words discarded
+struct Outgoing<'a, W> {
+    words: &'a [W],
+    mask: u32,
+}
+
+impl<W: Word> Words for Outgoing<'_, W> {
+    fn len(&self) -> usize {
+        self.words.len()
+    }
+    fn send(&self, idx: usize) -> u32 {
+        self.words[idx].into() & self.mask
+    }
+    fn receive(&mut self, _: usize, _: u32) {}
+}
+
+/// Words that are sent from one buffer, and received into another
+///
+/// The transfer is as long as the longer buffer. Once `write` runs out, the transfer
+/// sends zeros; once `read` runs out, it discards the received words.
+struct Split<'a, W> {
+    read: &'a mut [W],
+    write: &'a [W],
+    mask: u32,
+}
+
+impl<W: Word> Words for Split<'_, W> {
+    fn len(&self) -> usize {
+        self.read.len().max(self.write.len())
+    }
+    fn send(&self, idx: usize) -> u32 {
+        self.write
+            .get(idx)
+            .map_or(0, |&word| word.into() & self.mask)
+    }
+    fn receive(&mut self, idx: usize, word: u32) {
+        if let Some(slot) = self.read.get_mut(idx) {
+            *slot = W::from_fifo(word & self.mask);
+        }
+    }
+}
+
+/// Returns the number of words to push into the TX FIFO
+///
+/// Words that are in flight are in the TX FIFO, in the shifter, or in the RX FIFO.
+/// Keeping at most `rx_depth` words in flight means that the RX FIFO always has room
+/// for every word that the shifter receives, so the LPSPI never stalls.
+fn push_count(remaining: usize, in_flight: usize, tx_space: usize, rx_depth: usize) -> usize {
+    remaining
+        .min(tx_space)
+        .min(rx_depth.saturating_sub(in_flight))
+}
+
+/// Send and receive all `words`
+///
+/// Each poll drains the RX FIFO, then fills the TX FIFO. Returns `WaitTimeout` if
+/// `RETRIES` polls in a row don't move a word. The status flags are checked once more
+/// after the last word, since an overflow can happen while it's received.
+fn exchange<F: Fifo, B: Words>(fifo: &mut F, words: &mut B) -> Result<(), Error> {
+    let len = words.len();
+    let rx_depth = fifo.rx_depth();
+    let (mut sent, mut received) = (0, 0);
+    let mut idle = 0;
+    while received < len {
+        let tx_space = fifo.tx_space()?;
+        let before = received;
+        while received < sent {
+            match fifo.pop() {
+                Some(word) => {
+                    words.receive(received, word);
+                    received += 1;
+                }
+                None => break,
+            }
+        }
+
+        let count = push_count(len - sent, sent - received, tx_space, rx_depth);
+        for idx in sent..sent + count {
+            fifo.push(words.send(idx));
+        }
+        sent += count;
+
+        if count == 0 && received == before {
+            idle += 1;
+            if idle > RETRIES {
+                return Err(Error::WaitTimeout);
+            }
+        } else {
+            idle = 0;
+        }
+    }
+    fifo.check()
+}
+
+/// The counts and depths of the LPSPI FIFOs
+///
+/// See [`SPI::fifo_status()`](struct.SPI.html#method.fifo_status).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FifoStatus {
+    /// The number of words in the TX FIFO
+    pub tx_count: usize,
+    /// The number of words in the RX FIFO
+    pub rx_count: usize,
+    /// The number of words that the TX FIFO holds
+    pub tx_depth: usize,
+    /// The number of words that the RX FIFO holds
+    pub rx_depth: usize,
+}
+
+/// Check watermarks against the FIFO depths
+fn validate_watermarks(
+    tx: u8,
+    rx: u8,
+    tx_depth: usize,
+    rx_depth: usize,
+) -> Result<(), WatermarkError> {
+    if usize::from(tx) < tx_depth && usize::from(rx) < rx_depth {
+        Ok(())
+    } else {
+        Err(WatermarkError(()))
+    }
+}
+
+impl<M> Fifo for SPI<M>
+where
+    M: Unsigned,
+{
+    fn tx_space(&mut self) -> Result<usize, Error> {
+        self.check_errors()?;
+        let count = ral::read_reg!(ral::lpspi, self.reg, FSR, TXCOUNT) as usize;
+        Ok(self.tx_depth().saturating_sub(count))
+    }
+    fn push(&mut self, word: u32) {
+        ral::write_reg!(ral::lpspi, self.reg, TDR, word);
+    }
+    fn pop(&mut self) -> Option<u32> {
+        if ral::read_reg!(ral::lpspi, self.reg, RSR, RXEMPTY == RXEMPTY_0) {
+            Some(ral::read_reg!(ral::lpspi, self.reg, RDR))
+        } else {
+            None
+        }
+    }
+    fn rx_depth(&self) -> usize {
+        1 << ral::read_reg!(ral::lpspi, self.reg, PARAM, RXFIFO)
+    }
+    fn check(&mut self) -> Result<(), Error> {
+        self.check_errors().map(|_| ())
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    pub(super) fn tx_depth(&self) -> usize {
+        1 << ral::read_reg!(ral::lpspi, self.reg, PARAM, TXFIFO)
+    }
+
+    /// Set the TX and RX FIFO watermarks
+    ///
+    /// The TX data flag is set while the TX FIFO holds `tx` words or fewer, and the RX data
+    /// flag is set while the RX FIFO holds more than `rx` words. Returns an error, without
+    /// changing the watermarks, if either watermark isn't smaller than its FIFO's depth; see
+    /// [`fifo_status()`](#method.fifo_status).
+    ///
+    /// The blocking transfers don't use the watermarks. Starting a DMA transfer sets the
+    /// watermark of its direction to 0.
+    pub fn set_watermarks(&mut self, tx: u8, rx: u8) -> Result<(), WatermarkError> {
+        validate_watermarks(tx, rx, self.tx_depth(), Fifo::rx_depth(self))?;
+        ral::modify_reg!(ral::lpspi, self.reg, FCR, TXWATER: u32::from(tx), RXWATER: u32::from(rx));
+        Ok(())
+    }
+
+    /// Returns the number of words in each FIFO, and the depth of each FIFO
+    pub fn fifo_status(&self) -> FifoStatus {
+        let (tx_count, rx_count) = ral::read_reg!(ral::lpspi, self.reg, FSR, TXCOUNT, RXCOUNT);
+        FifoStatus {
+            tx_count: tx_count as usize,
+            rx_count: rx_count as usize,
+            tx_depth: self.tx_depth(),
+            rx_depth: Fifo::rx_depth(self),
+        }
+    }
+
+    /// Run a blocking transfer, and drop any words that it leaves in the FIFOs if
+    /// it fails
+    fn burst<W, B: Words>(&mut self, words: &mut B) -> Result<(), Error> {
+        self.check_errors()?;
+        self.clear_status();
+        // Safety: we have a mutable reference to the SPI
+        unsafe { self.apply_frame_size::<W>() };
+        let result = exchange(self, words);
+        if result.is_err() {
+            self.clear_fifo();
+        }
+        result
+    }
+
+    pub(super) fn transfer_words<'w, W: Word>(
+        &mut self,
+        words: &'w mut [W],
+    ) -> Result<&'w [W], Error> {
+        let mask = word_mask::<W>(self.u32_frame_bits);
+        self.burst::<W, _>(&mut Exchange {
+            words: &mut *words,
+            mask,
+        })?;
+        Ok(words)
+    }
+
+    pub(super) fn write_words<W: Word>(&mut self, words: &[W]) -> Result<(), Error> {
+        let mask = word_mask::<W>(self.u32_frame_bits);
+        self.burst::<W, _>(&mut Outgoing { words, mask })
+    }
+
+    /// Send `write`, and receive into `read`, for as many words as the longer buffer
+    pub(super) fn transfer_split<W: Word>(
+        &mut self,
+        read: &mut [W],
+        write: &[W],
+    ) -> Result<(), Error> {
+        let mask = word_mask::<W>(self.u32_frame_bits);
+        self.burst::<W, _>(&mut Split { read, write, mask })
+    }
+}
+
+impl<M> Transfer<u8> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        self.transfer_words(words)
+    }
+}
+
+impl<M> Transfer<u16> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Error> {
+        self.transfer_words(words)
+    }
+}
+
+impl<M> Transfer<u32> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u32]) -> Result<&'w [u32], Error> {
+        self.transfer_words(words)
+    }
+}
+
+impl<M> Write<u8> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        self.write_words(words)
+    }
+}
+
+impl<M> Write<u16> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn write(&mut self, words: &[u16]) -> Result<(), Error> {
+        self.write_words(words)
+    }
+}
+
+impl<M> Write<u32> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn write(&mut self, words: &[u32]) -> Result<(), Error> {
+        self.write_words(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        exchange, push_count, validate_watermarks, word_mask, Error, Exchange, Fifo, Outgoing,
+        Split, Words,
+    };
+    use crate::spi::TransferError;
+
+    const DEPTH: usize = 4;
+
+    /// A device that answers each word with the word plus one, after a delay
+    struct Loopback {
+        tx: [u32; DEPTH],
+        tx_count: usize,
+        rx: [u32; DEPTH],
+        rx_count: usize,
+        /// Words that the shifter moves each poll
+        speed: usize,
+        polls: usize,
+        stalled: bool,
+        /// The number of words that the shifter moved
+        shifted: usize,
+        /// Signal an overflow once this many words have moved
+        overflow_after: Option<usize>,
+    }
+
+    impl Loopback {
+        fn new(speed: usize) -> Self {
+            Loopback {
+                tx: [0; DEPTH],
+                tx_count: 0,
+                rx: [0; DEPTH],
+                rx_count: 0,
+                speed,
+                polls: 0,
+                stalled: false,
+                shifted: 0,
+                overflow_after: None,
+            }
+        }
+    }
+
+    impl Fifo for Loopback {
+        fn tx_space(&mut self) -> Result<usize, Error> {
+            self.check()?;
+            self.polls += 1;
+            for _ in 0..self.speed.min(self.tx_count) {
+                if self.rx_count == DEPTH {
+                    self.stalled = true;
+                    break;
+                }
+                self.rx[self.rx_count] = self.tx[0] + 1;
+                self.rx_count += 1;
+                self.tx.rotate_left(1);
+                self.tx_count -= 1;
+                self.shifted += 1;
+            }
+            Ok(DEPTH - self.tx_count)
+        }
+        fn push(&mut self, word: u32) {
+            assert!(self.tx_count < DEPTH, "TX FIFO overflow");
+            self.tx[self.tx_count] = word;
+            self.tx_count += 1;
+        }
+        fn pop(&mut self) -> Option<u32> {
+            if self.rx_count == 0 {
+                return None;
+            }
+            let word = self.rx[0];
+            self.rx.rotate_left(1);
+            self.rx_count -= 1;
+            Some(word)
+        }
+        fn rx_depth(&self) -> usize {
+            DEPTH
+        }
+        fn check(&mut self) -> Result<(), Error> {
+            match self.overflow_after {
+                Some(words) if self.shifted >= words => {
+                    Err(Error::Transfer(TransferError::ReceiveOverflow))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn batches() {
+        // Fill the TX FIFO
+        assert_eq!(push_count(10, 0, 4, 4), 4);
+        // Only the rest of the transfer
+        assert_eq!(push_count(2, 0, 4, 4), 2);
+        // Don't overfill the RX FIFO
+        assert_eq!(push_count(10, 3, 4, 4), 1);
+        assert_eq!(push_count(10, 4, 4, 4), 0);
+        assert_eq!(push_count(10, 0, 0, 4), 0);
+    }
+
+    #[test]
+    fn transfers_in_order() {
+        for &speed in &[1, 2, DEPTH, 2 * DEPTH] {
+            let mut fifo = Loopback::new(speed);
+            let mut words = [0u16; 37];
+            for (idx, word) in words.iter_mut().enumerate() {
+                *word = idx as u16 * 3;
+            }
+            exchange(
+                &mut fifo,
+                &mut Exchange {
+                    words: &mut words,
+                    mask: 0xFFFF,
+                },
+            )
+            .unwrap();
+            for (idx, word) in words.iter().enumerate() {
+                assert_eq!(*word, idx as u16 * 3 + 1);
+            }
+            assert!(!fifo.stalled);
+            assert_eq!((fifo.tx_count, fifo.rx_count), (0, 0));
+        }
+    }
+
+    #[test]
+    fn uneven_buffers() {
+        // Reading more than writing sends zeros
+        let mut fifo = Loopback::new(2);
+        let mut read = [0u8; 6];
+        exchange(
+            &mut fifo,
+            &mut Split {
+                read: &mut read,
+                write: &[10, 20],
+                mask: 0xFF,
+            },
+        )
+        .unwrap();
+        assert_eq!(read, [11, 21, 1, 1, 1, 1]);
+
+        // Writing more than reading discards the rest
+        let mut read = [0u8; 2];
+        exchange(
+            &mut fifo,
+            &mut Split {
+                read: &mut read,
+                write: &[10, 20, 30, 40, 50],
+                mask: 0xFF,
+            },
+        )
+        .unwrap();
+        assert_eq!(read, [11, 21]);
+        assert_eq!((fifo.tx_count, fifo.rx_count), (0, 0));
+    }
+
+    #[test]
+    fn fewer_polls_than_words() {
+        let mut fifo = Loopback::new(DEPTH);
+        let words = [0xA5u8; 64];
+        exchange(
+            &mut fifo,
+            &mut Outgoing {
+                words: &words,
+                mask: 0xFF,
+            },
+        )
+        .unwrap();
+        // A word-at-a-time transfer polls at least once for each word
+        assert!(fifo.polls <= words.len() / DEPTH + 1, "{}", fifo.polls);
+        assert_eq!((fifo.tx_count, fifo.rx_count), (0, 0));
+    }
+
+    #[test]
+    fn masks_odd_frames() {
+        let mut fifo = Loopback::new(1);
+        let mut words = [0xFF12_3456u32, 0x00FF_FFFF];
+        exchange(
+            &mut fifo,
+            &mut Exchange {
+                words: &mut words,
+                mask: 0x00FF_FFFF,
+            },
+        )
+        .unwrap();
+        // The second word overflows the 24 bits
+        assert_eq!(words, [0x12_3457, 0]);
+    }
+
+    #[test]
+    fn frames_narrower_than_words() {
+        // A 24-bit DAC frame drops the word's high byte when sending...
+        let mut words = [0xFF30_8000u32];
+        let mut dac = Exchange {
+            words: &mut words,
+            mask: word_mask::<u32>(24),
+        };
+        assert_eq!(dac.send(0), 0x30_8000);
+        // ...and the received word never shows what's left in the high byte
+        dac.receive(0, 0xA5AB_CDEF);
+        assert_eq!(words, [0xAB_CDEF]);
+
+        let twelve = Outgoing {
+            words: &[0x0001_2345u32],
+            mask: word_mask::<u32>(12),
+        };
+        assert_eq!(twelve.send(0), 0x345);
+
+        // Every value that fits in the frame makes it through unchanged
+        for bits in 8..=32 {
+            let largest = u32::max_value() >> (32 - bits);
+            for &word in &[0, 1, largest >> 1, largest] {
+                let mut words = [word];
+                let mut exchange = Exchange {
+                    words: &mut words,
+                    mask: word_mask::<u32>(bits),
+                };
+                let fifo = exchange.send(0);
+                exchange.receive(0, fifo);
+                assert_eq!(words, [word], "{} bits", bits);
+            }
+            // The first value that doesn't fit wraps around
+            if bits < 32 {
+                let outgoing = Outgoing {
+                    words: &[largest + 1],
+                    mask: word_mask::<u32>(bits),
+                };
+                assert_eq!(outgoing.send(0), 0, "{} bits", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_as_wide_as_words() {
+        let mut words = [0xDEAD_BEEFu32];
+        let mut exchange = Exchange {
+            words: &mut words,
+            mask: word_mask::<u32>(32),
+        };
+        assert_eq!(exchange.send(0), 0xDEAD_BEEF);
+        exchange.receive(0, 0xFEED_F00D);
+        assert_eq!(words, [0xFEED_F00D]);
+
+        // A 24-bit u32 frame size doesn't widen u8 or u16 frames, so the receive
+        // side keeps only the word's own bits
+        let (mut bytes, mut halves) = ([0u8; 2], [0u16; 2]);
+        let mut split = Split {
+            read: &mut bytes,
+            write: &[0xAB],
+            mask: word_mask::<u8>(24),
+        };
+        assert_eq!((split.send(0), split.send(1)), (0xAB, 0));
+        split.receive(0, 0x00AB_CD12);
+        let mut split = Split {
+            read: &mut halves,
+            write: &[0xABCD],
+            mask: word_mask::<u16>(24),
+        };
+        assert_eq!(split.send(0), 0xABCD);
+        split.receive(0, 0x00AB_CD12);
+        assert_eq!((bytes[0], halves[0]), (0x12, 0xCD12));
+    }
+
+    #[test]
+    fn times_out_without_progress() {
+        let mut fifo = Loopback::new(0);
+        let mut words = [0u8; 8];
+        let result = exchange(
+            &mut fifo,
+            &mut Exchange {
+                words: &mut words,
+                mask: 0xFF,
+            },
+        );
+        assert_eq!(result, Err(Error::WaitTimeout));
+    }
+
+    #[test]
+    fn errors_fail_the_transfer() {
+        // The overflow arrives with the last word
+        let mut fifo = Loopback::new(1);
+        fifo.overflow_after = Some(8);
+        let mut words = [0u8; 8];
+        let mut exchange_words = Exchange {
+            words: &mut words,
+            mask: 0xFF,
+        };
+        assert_eq!(
+            exchange(&mut fifo, &mut exchange_words),
+            Err(Error::Transfer(TransferError::ReceiveOverflow))
+        );
+
+        // The overflow stops the transfer early
+        let mut fifo = Loopback::new(1);
+        fifo.overflow_after = Some(3);
+        let result = exchange(&mut fifo, &mut exchange_words);
+        assert_eq!(result, Err(Error::Transfer(TransferError::ReceiveOverflow)));
+        assert!(fifo.shifted < 8);
+    }
+
+    #[test]
+    fn watermarks() {
+        assert!(validate_watermarks(0, 0, 16, 16).is_ok());
+        assert!(validate_watermarks(15, 15, 16, 16).is_ok());
+        assert!(validate_watermarks(16, 0, 16, 16).is_err());
+        assert!(validate_watermarks(0, 16, 16, 16).is_err());
+        assert!(validate_watermarks(4, 1, 4, 16).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! 16-bit, 32-bit, and odd-sized frames
+
+use super::{fifo::Word, Error, FrameSizeError, SPI};
+use crate::iomuxc::consts::Unsigned;
+use embedded_hal::blocking::spi::Transfer;
+
+/// The smallest frame, in bits
+const MIN_FRAME_BITS: u8 = 8;
+/// The largest frame that fits in a `u32`
+///
+/// The LPSPI sends wider frames, up to 4096 bits, as several FIFO words. The driver
+/// sends each word as its own frame, so it doesn't support them.
+const MAX_FRAME_BITS: u8 = 32;
+
+/// Returns the number of bits in a `u32` frame, if the LPSPI supports `bits`
+fn validate_frame_bits(bits: u8) -> Result<u32, FrameSizeError> {
+    if (MIN_FRAME_BITS..=MAX_FRAME_BITS).contains(&bits) {
+        Ok(u32::from(bits))
+    } else {
+        Err(FrameSizeError(()))
+    }
+}
+
+/// Returns the bits of a FIFO word that hold a frame of `frame_bits`
+///
+/// The frame is in the least significant bits, so a word's value is the frame's value.
+pub(super) fn frame_mask(frame_bits: u32) -> u32 {
+    u32::max_value() >> (32 - frame_bits)
+}
+
+/// Returns the number of bits in each frame of a `W` transfer
+///
+/// Only `u32` words use the configurable frame size; smaller words are always one
+/// frame of their own width.
+pub(super) fn word_frame_bits<W>(u32_frame_bits: u32) -> u32 {
+    match core::mem::size_of::<W>() {
+        4 => u32_frame_bits,
+        bytes => (bytes * 8) as u32,
+    }
+}
+
+/// Returns the bits of a FIFO word that hold a frame of a `W` transfer
+pub(super) fn word_mask<W: Word>(u32_frame_bits: u32) -> u32 {
+    frame_mask(word_frame_bits::<W>(u32_frame_bits))
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Set the number of bits in each frame of a `u32` transfer
+    ///
+    /// Each `u32` holds one frame in its least significant bits, so a 24-bit frame
+    /// of `0x00AB_CDEF` sends `0xAB` first (in MSB-first order). The unused bits are
+    /// ignored when sending, and they're zero when receiving. `u8` and `u16` transfers
+    /// always send 8- and 16-bit frames. The default is 32 bits.
+    ///
+    /// Returns an error, without changing the frame size, if `bits` is less than 8,
+    /// or greater than 32. The LPSPI can send frames wider than 32 bits, but the
+    /// driver doesn't support them, since each `u32` is one frame.
+    ///
+    /// The size applies to [`transfer_u32()`](#method.transfer_u32), to the `u32`
+    /// `embedded_hal` traits, and to `u32` DMA transfers.
+    ///
+    /// # Example
+    ///
+    /// Set a 24-bit DAC's output.
+    ///
+    /// ```no_run
+    /// # fn dac<M: imxrt1060_hal::iomuxc::consts::Unsigned>(spi: &mut imxrt1060_hal::spi::SPI<M>) {
+    /// spi.set_frame_size(24).unwrap();
+    /// // Command 0x3, channel A, code 0x8000
+    /// let mut frame = [0x30_8000u32];
+    /// spi.transfer_u32(&mut frame).unwrap();
+    /// # }
+    /// ```
+    pub fn set_frame_size(&mut self, bits: u8) -> Result<(), FrameSizeError> {
+        self.u32_frame_bits = validate_frame_bits(bits)?;
+        Ok(())
+    }
+
+    /// Returns the number of bits in each frame of a `u32` transfer
+    pub fn frame_size(&self) -> u8 {
+        self.u32_frame_bits as u8
+    }
+
+    /// Send `words` as 16-bit frames, and replace each with the frame that the device
+    /// sends back
+    pub fn transfer_u16<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Error> {
+        Transfer::transfer(self, words).map(|words| &*words)
+    }
+
+    /// Send `words` as frames of [`frame_size()`](#method.frame_size) bits, and replace
+    /// each with the frame that the device sends back
+    pub fn transfer_u32<'w>(&mut self, words: &'w mut [u32]) -> Result<&'w [u32], Error> {
+        Transfer::transfer(self, words).map(|words| &*words)
+    }
+}
+
+impl<M> embedded_hal::spi::FullDuplex<u32> for SPI<M>
+where
+    M: Unsigned,
+{
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u32, Self::Error> {
+        let mask = word_mask::<u32>(self.u32_frame_bits);
+        Self::read(self).map(|w| w & mask)
+    }
+
+    fn send(&mut self, word: u32) -> nb::Result<(), Self::Error> {
+        let mask = word_mask::<u32>(self.u32_frame_bits);
+        Self::send::<u32>(self, word & mask)
+    }
+}
+
+impl<M> embedded_hal::blocking::spi::write_iter::Default<u32> for SPI<M> where M: Unsigned {}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame_mask, validate_frame_bits, word_frame_bits, word_mask};
+
+    #[test]
+    fn frame_sizes() {
+        assert!(validate_frame_bits(7).is_err());
+        assert_eq!(validate_frame_bits(8).unwrap(), 8);
+        assert_eq!(validate_frame_bits(24).unwrap(), 24);
+        assert_eq!(validate_frame_bits(32).unwrap(), 32);
+    }
+
+    #[test]
+    fn frames_wider_than_words() {
+        // The LPSPI takes these, but they'd span more than one u32
+        for bits in 33..=u8::max_value() {
+            assert!(validate_frame_bits(bits).is_err(), "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn masks() {
+        assert_eq!(frame_mask(8), 0xFF);
+        assert_eq!(frame_mask(12), 0xFFF);
+        assert_eq!(frame_mask(24), 0xFF_FFFF);
+        assert_eq!(frame_mask(32), 0xFFFF_FFFF);
+
+        // u8 and u16 words fill their frames, whatever the u32 frame size
+        for &bits in &[8, 12, 24, 32] {
+            assert_eq!(word_frame_bits::<u8>(bits), 8);
+            assert_eq!(word_frame_bits::<u16>(bits), 16);
+            assert_eq!(word_frame_bits::<u32>(bits), bits);
+            assert_eq!(word_mask::<u8>(bits), 0xFF);
+            assert_eq!(word_mask::<u16>(bits), 0xFFFF);
+            assert_eq!(word_mask::<u32>(bits), frame_mask(bits));
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Full-duplex DMA transfers on two channels
+
+use super::{Error, SPI};
+use crate::dma::{
+    self, buffer,
+    peripheral::{Destination, Source},
+    Channel, ChannelExt, ChannelState, Element,
+};
+use crate::iomuxc::consts::Unsigned;
+use core::{
+    marker::PhantomData,
+    sync::atomic::{compiler_fence, AtomicU32, Ordering},
+};
+use imxrt_dma::Transfer;
+
+/// The fill word of each LPSPI's reads
+///
+/// The transmit channel reads the same address for every frame. The DMA
+/// controller reads the least significant bytes of the word.
+static FILL: [AtomicU32; 4] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// An error from a full-duplex DMA transfer
+#[derive(Debug)]
+pub enum DuplexError {
+    /// The transmit channel has an error
+    ///
+    /// The transmit channel stops, so the receive channel never completes.
+    Transmit(dma::ErrorStatus),
+    /// The receive channel has an error
+    Receive(dma::ErrorStatus),
+    /// The transfer wasn't complete, so it was cancelled
+    ///
+    /// The contents of the destination buffer are unspecified.
+    Incomplete,
+    /// Both channels finished, but a FIFO underran or overflowed
+    ///
+    /// The destination buffer holds stale or missing frames. Only happens if transfers
+    /// [don't stall](struct.SPI.html#method.set_stall).
+    Spi(Error),
+}
+
+/// The progress of a full-duplex transfer, from the state of its two channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    /// Frames are still moving
+    Running,
+    /// The last frame landed in the destination buffer
+    Complete,
+    /// The transmit channel failed
+    TransmitError,
+    /// The receive channel failed
+    ReceiveError,
+}
+
+/// Decode the progress of a transfer
+///
+/// The receive channel takes the last frame after the transmit channel sends it, so
+/// the transfer is only complete once the receive is done. A transmit error is reported
+/// first, since it stalls the receive.
+fn progress(tx: ChannelState, rx: ChannelState) -> Progress {
+    match (tx, rx) {
+        (ChannelState::Error, _) => Progress::TransmitError,
+        (_, ChannelState::Error) => Progress::ReceiveError,
+        (ChannelState::Done, ChannelState::Done) => Progress::Complete,
+        _ => Progress::Running,
+    }
+}
+
+/// A SPI master that sends and receives with DMA
+///
+/// `FullDuplexDma` owns a SPI, and two DMA channels. The transmit channel moves frames
+/// from a source buffer into the TX FIFO, and the receive channel moves frames from the
+/// RX FIFO into a destination buffer. The LPSPI only clocks a frame once the transmit
+/// channel supplies it, so the transmit requests pace the transfer, and the receive
+/// channel follows. To read without a source buffer, use [`read()`](#method.read); it
+/// sends a fill word for every frame.
+///
+/// A transfer is complete once the receive channel takes the last frame. Wait for
+/// [`is_complete()`](#method.is_complete), possibly by enabling the receive channel's
+/// interrupt on completion, then take the buffers with [`complete()`](#method.complete).
+/// `is_complete()` also returns `true` if either channel has an error; `complete()`
+/// reports the error.
+///
+/// Like a [`Peripheral`](../dma/struct.Peripheral.html), `FullDuplexDma` configures each
+/// channel's hardware trigger and peripheral address, and each transfer sets the buffer
+/// side of the channel. The frame size is the size of `E`.
+///
+/// # Example
+///
+/// Read 1MiB from an SD card in 512 byte blocks, and measure the throughput. The card
+/// is already in SPI mode, and each block's read command is already sent.
+///
+/// ```no_run
+/// use imxrt1060_hal::dma::{Buffer, Linear};
+/// use imxrt1060_hal::spi::FullDuplexDma;
+/// # use imxrt1060_hal::{dma::Channel, gpt::GPT, iomuxc::consts::U4, spi::SPI};
+/// # fn read_card(spi: SPI<U4>, tx_channel: Channel, rx_channel: Channel, gpt: &GPT) {
+///
+/// static BLOCK: Buffer<[u8; 512]> = Buffer::new([0; 512]);
+///
+/// let mut card = FullDuplexDma::<_, u8, Linear<u8>>::new(spi, tx_channel, rx_channel);
+/// let mut block = Linear::try_new(&BLOCK).unwrap();
+///
+/// let start = gpt.count();
+/// for _ in 0..2048 {
+///     block.set_transfer_len(512);
+///     // SD cards expect 0xFF while they send data
+///     card.read(block, 0xFF).unwrap();
+///     while !card.is_complete() {}
+///     let (_, filled) = card.complete().unwrap().unwrap();
+///     // Check the block's CRC, then read the next block into the same buffer
+///     block = filled;
+/// }
+/// let ticks = gpt.count().wrapping_sub(start);
+/// log::info!("1MiB in {} GPT ticks", ticks);
+/// # }
+/// ```
+pub struct FullDuplexDma<M, E, S, D = S> {
+    spi: SPI<M>,
+    tx_channel: Channel,
+    rx_channel: Channel,
+    /// The source buffer, if there is one, and the destination buffer
+    buffers: Option<(Option<S>, D)>,
+    _element: PhantomData<E>,
+}
+
+impl<M, E, S, D> FullDuplexDma<M, E, S, D>
+where
+    M: Unsigned,
+    E: Element + Into<u32>,
+    SPI<M>: Source<E> + Destination<E>,
+    S: buffer::Source<E>,
+    D: buffer::Destination<E>,
+{
+    /// Prepare `spi` for full-duplex DMA transfers on `tx_channel` and `rx_channel`
+    ///
+    /// Configure each channel's interrupts and priority before calling `new()`. Keep
+    /// the two channels in the same group, so that the transmit channel can't starve
+    /// the receive channel.
+    pub fn new(spi: SPI<M>, mut tx_channel: Channel, mut rx_channel: Channel) -> Self {
+        crate::ccm::clock_gate::debug_assert_clocked!(crate::ccm::clock_gate::Dma);
+        tx_channel.set_trigger_from_hardware(Some(Destination::<E>::destination_signal(&spi)));
+        rx_channel.set_trigger_from_hardware(Some(Source::<E>::source_signal(&spi)));
+        // Safety: the SPI's Source and Destination implementations point at its
+        // data registers.
+        unsafe {
+            tx_channel
+                .set_destination_transfer(&Transfer::hardware(Destination::<E>::destination(&spi)));
+            rx_channel.set_source_transfer(&Transfer::hardware(Source::<E>::source(&spi)));
+        }
+        tx_channel.set_disable_on_completion(true);
+        rx_channel.set_disable_on_completion(true);
+        FullDuplexDma {
+            spi,
+            tx_channel,
+            rx_channel,
+            buffers: None,
+            _element: PhantomData,
+        }
+    }
+
+    /// Send `source` while receiving into `destination`
+    ///
+    /// The number of frames is the shorter of the two buffers. Returns the buffers if
+    /// there's already a transfer, if there are more than 32,767 frames, or if either
+    /// channel fails to start.
+    pub fn transfer(&mut self, mut source: S, destination: D) -> Result<(), (S, D, dma::Error)> {
+        let len = source.source_len().min(destination.destination_len());
+        source.prepare_source();
+        match self.start(Some(source.source()), len, destination) {
+            Ok(()) => {
+                self.buffers.as_mut().unwrap().0 = Some(source);
+                Ok(())
+            }
+            Err((destination, err)) => Err((source, destination, err)),
+        }
+    }
+
+    /// Receive into `destination` while sending `fill` for every frame
+    pub fn read(&mut self, destination: D, fill: E) -> Result<(), (D, dma::Error)> {
+        if self.buffers.is_some() {
+            return Err((destination, dma::Error::ScheduledTransfer));
+        }
+        let word = &FILL[M::USIZE - 1];
+        word.store(fill.into(), Ordering::Relaxed);
+        dma::cache::clean(word as *const _ as usize, core::mem::size_of::<AtomicU32>());
+        let len = destination.destination_len();
+        self.start(None, len, destination)
+    }
+
+    /// Arm the receive, then the transmit
+    ///
+    /// The transmit sends from `src`, or sends the fill word if `src` is `None`. The
+    /// receive is ready before the first frame arrives in the RX FIFO.
+    fn start(
+        &mut self,
+        src: Option<Transfer<E>>,
+        len: usize,
+        mut destination: D,
+    ) -> Result<(), (D, dma::Error)> {
+        if self.buffers.is_some() || self.tx_channel.is_enabled() || self.rx_channel.is_enabled() {
+            return Err((destination, dma::Error::ScheduledTransfer));
+        }
+        for channel in [&self.tx_channel, &self.rx_channel].iter() {
+            if channel.is_error() {
+                let es = channel.error_status();
+                return Err((destination, dma::Error::PreexistingError(es)));
+            }
+        }
+        let iterations = match dma::channel::major_iterations(len) {
+            Ok(iterations) => iterations,
+            Err(err) => return Err((destination, err)),
+        };
+        self.tx_channel.clear_complete();
+        self.rx_channel.clear_complete();
+
+        let dst = destination.destination();
+        // Safety: the buffers stay in place until the transfer is done. The fill
+        // word is static, and the channel only reads it.
+        unsafe {
+            self.rx_channel.set_destination_transfer(&dst);
+            match src {
+                Some(src) => self.tx_channel.set_source_transfer(&src),
+                None => {
+                    let fill = &FILL[M::USIZE - 1] as *const AtomicU32 as *const E;
+                    self.tx_channel
+                        .set_source_transfer(&Transfer::hardware(fill))
+                }
+            }
+        }
+        self.rx_channel.set_minor_loop_elements::<E>(1);
+        self.rx_channel.set_transfer_iterations(iterations);
+        self.tx_channel.set_minor_loop_elements::<E>(1);
+        self.tx_channel.set_transfer_iterations(iterations);
+        destination.prepare_destination();
+
+        if let Err(es) = self.arm() {
+            return Err((destination, dma::Error::Setup(es)));
+        }
+        self.buffers = Some((None, destination));
+        Ok(())
+    }
+
+    fn progress(&self) -> Progress {
+        progress(self.tx_channel.state(), self.rx_channel.state())
+    }
+
+    /// Returns `true` if the transfer is complete, or if it stopped with an error
+    ///
+    /// Once `is_complete()` returns `true`, call [`complete()`](#method.complete).
+    pub fn is_complete(&self) -> bool {
+        self.buffers.is_some() && self.progress() != Progress::Running
+    }
+
+    /// Finish the transfer, and return the buffers
+    ///
+    /// The first buffer is the source buffer, or `None` for a [`read()`](#method.read).
+    /// If either channel has an error, if a FIFO underran or overflowed, or if the transfer
+    /// isn't complete, `complete()` cancels the transfer, and returns the buffers with the
+    /// error. It clears the channel errors, the SPI FIFOs, and the SPI error flags, so that
+    /// the SPI is ready for the next transfer. Returns
+    /// `None` if there's no transfer.
+    #[allow(clippy::type_complexity)]
+    pub fn complete(&mut self) -> Option<Result<(Option<S>, D), (Option<S>, D, DuplexError)>> {
+        self.buffers.as_ref()?;
+        let progress = self.progress();
+        let error = match progress {
+            Progress::Complete => self
+                .spi
+                .status()
+                .error()
+                .map(|error| DuplexError::Spi(Error::Transfer(error))),
+            Progress::TransmitError => Some(DuplexError::Transmit(self.tx_channel.error_status())),
+            Progress::ReceiveError => Some(DuplexError::Receive(self.rx_channel.error_status())),
+            Progress::Running => Some(DuplexError::Incomplete),
+        };
+        let (mut source, mut destination) = self.teardown();
+        if error.is_some() {
+            self.spi.abort();
+        }
+        match error {
+            None => {
+                if let Some(source) = source.as_mut() {
+                    source.complete_source();
+                }
+                destination.complete_destination();
+                Some(Ok((source, destination)))
+            }
+            Some(error) => Some(Err((source, destination, error))),
+        }
+    }
+
+    /// Cancel the transfer, and return the buffers
+    ///
+    /// The contents of the destination buffer are unspecified. The SPI
+    /// [aborts](struct.SPI.html#method.abort) its last frame, so it's idle, with
+    /// its chip select deasserted. Returns `None` if there's no transfer.
+    pub fn cancel(&mut self) -> Option<(Option<S>, D)> {
+        self.buffers.as_ref()?;
+        let buffers = self.teardown();
+        self.spi.abort();
+        Some(buffers)
+    }
+
+    /// Stop the transfer, and take its buffers
+    fn teardown(&mut self) -> (Option<S>, D) {
+        self.disarm();
+        // Unwrap OK: callers check for a transfer
+        self.buffers.take().unwrap()
+    }
+
+    /// Returns `true` if the receive channel has generated an interrupt
+    pub fn is_receive_interrupt(&self) -> bool {
+        self.rx_channel.is_interrupt()
+    }
+
+    /// Clears the interrupt flag on the receive channel
+    pub fn receive_clear_interrupt(&mut self) {
+        self.rx_channel.clear_interrupt();
+    }
+
+    /// Returns `true` if the transmit channel has generated an interrupt
+    pub fn is_transfer_interrupt(&self) -> bool {
+        self.tx_channel.is_interrupt()
+    }
+
+    /// Clears the interrupt flag on the transmit channel
+    pub fn transfer_clear_interrupt(&mut self) {
+        self.tx_channel.clear_interrupt();
+    }
+
+    /// Release the SPI, and the (transmit, receive) channels
+    ///
+    /// Any transfer is cancelled, and its buffers are dropped.
+    pub fn release(mut self) -> (SPI<M>, (Channel, Channel)) {
+        if self.buffers.is_some() {
+            self.teardown();
+        }
+        (self.spi, (self.tx_channel, self.rx_channel))
+    }
+}
+
+/// The steps that start, and stop, a transfer
+impl<M, E, S, D> FullDuplexDma<M, E, S, D>
+where
+    M: Unsigned,
+    E: Element,
+    SPI<M>: Source<E> + Destination<E>,
+{
+    /// Arm the receive, then the transmit
+    ///
+    /// The receive is ready before the first frame arrives in the RX FIFO. If a channel
+    /// doesn't start, `arm()` stops the channels that it started.
+    fn arm(&mut self) -> Result<(), dma::ErrorStatus> {
+        if let Err(err) = self.arm_receive() {
+            self.stop_receive();
+            return Err(err);
+        }
+        if let Err(err) = self.arm_transmit() {
+            self.stop_transmit();
+            self.stop_receive();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Stop the transmit, then the receive, and leave the SPI and channels ready for
+    /// the next transfer
+    ///
+    /// The transmit stops first, so that the receive takes every frame that was sent.
+    fn disarm(&mut self) {
+        self.stop_transmit();
+        self.stop_receive();
+        self.spi.reset_fifos();
+        self.clear_channels();
+    }
+
+    /// Enable the SPI's receive requests, and the receive channel
+    ///
+    /// Returns the receive channel's error, and clears it, if the channel doesn't start.
+    fn arm_receive(&mut self) -> Result<(), dma::ErrorStatus> {
+        Source::<E>::enable_source(&self.spi);
+        compiler_fence(Ordering::Release);
+        // Safety: start() set up the receive channel for its destination buffer
+        unsafe {
+            self.rx_channel.enable();
+        }
+        if self.rx_channel.is_error() {
+            let es = self.rx_channel.error_status();
+            self.rx_channel.clear_error();
+            return Err(es);
+        }
+        Ok(())
+    }
+
+    /// Enable the SPI's transmit requests, and the transmit channel
+    ///
+    /// Returns the transmit channel's error, and clears it, if the channel doesn't start.
+    fn arm_transmit(&mut self) -> Result<(), dma::ErrorStatus> {
+        Destination::<E>::enable_destination(&self.spi);
+        // Safety: start() set up the transmit channel for its source
+        unsafe {
+            self.tx_channel.enable();
+        }
+        if self.tx_channel.is_error() {
+            let es = self.tx_channel.error_status();
+            self.tx_channel.clear_error();
+            return Err(es);
+        }
+        Ok(())
+    }
+
+    /// Stop the transmit channel, so that no more frames enter the TX FIFO
+    fn stop_transmit(&mut self) {
+        Destination::<E>::disable_destination(&self.spi);
+        while self.tx_channel.is_hardware_signaling() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.tx_channel.disable();
+    }
+
+    /// Stop the receive channel
+    fn stop_receive(&mut self) {
+        Source::<E>::disable_source(&self.spi);
+        while self.rx_channel.is_hardware_signaling() || self.rx_channel.is_active() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.rx_channel.disable();
+        compiler_fence(Ordering::Acquire);
+    }
+
+    /// Clear both channels' error and completion flags
+    fn clear_channels(&mut self) {
+        for channel in [&mut self.tx_channel, &mut self.rx_channel].iter_mut() {
+            channel.clear_error();
+            channel.clear_complete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{progress, ChannelState, Progress};
+
+    #[test]
+    fn receive_completes_last() {
+        use ChannelState::*;
+        // The receive runs behind the transmit
+        assert_eq!(progress(Armed, Armed), Progress::Running);
+        assert_eq!(progress(Active, Suspended), Progress::Running);
+        assert_eq!(progress(Done, Suspended), Progress::Running);
+        assert_eq!(progress(Done, Active), Progress::Running);
+        assert_eq!(progress(Done, Done), Progress::Complete);
+    }
+
+    #[test]
+    fn errors_from_either_channel() {
+        use ChannelState::*;
+        assert_eq!(progress(Error, Suspended), Progress::TransmitError);
+        assert_eq!(progress(Done, Error), Progress::ReceiveError);
+        // The receive stalls after a transmit error
+        assert_eq!(progress(Error, Error), Progress::TransmitError);
+    }
+}
@@ -0,0 +1,118 @@
+//! Clock mode and bit order
+
+use super::{ModeError, RETRIES, SPI};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use embedded_hal::spi::{Mode, Phase, Polarity};
+
+/// The order of the bits in each frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The most significant bit goes first. This is the default.
+    MsbFirst,
+    /// The least significant bit goes first
+    LsbFirst,
+}
+
+/// Returns the TCR value that clocks frames in `mode`
+pub(super) fn tcr_mode(tcr: u32, mode: Mode) -> u32 {
+    use ral::lpspi::TCR::{CPHA, CPOL};
+    let cpol = (mode.polarity == Polarity::IdleHigh) as u32;
+    let cpha = (mode.phase == Phase::CaptureOnSecondTransition) as u32;
+    (tcr & !(CPOL::mask | CPHA::mask)) | cpol << CPOL::offset | cpha << CPHA::offset
+}
+
+/// Returns the TCR value that shifts frames in `bit_order`
+pub(super) fn tcr_bit_order(tcr: u32, bit_order: BitOrder) -> u32 {
+    use ral::lpspi::TCR::LSBF;
+    match bit_order {
+        BitOrder::MsbFirst => tcr & !LSBF::mask,
+        BitOrder::LsbFirst => tcr | LSBF::mask,
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Wait for the TX FIFO to drain, and for the last frame to finish
+    pub(super) fn quiesce(&mut self) -> Result<(), ModeError> {
+        for _ in 0..RETRIES {
+            let txcount = ral::read_reg!(ral::lpspi, self.reg, FSR, TXCOUNT);
+            let busy = ral::read_reg!(ral::lpspi, self.reg, SR, MBF == MBF_1);
+            if txcount == 0 && !busy {
+                return Ok(());
+            }
+        }
+        Err(ModeError(()))
+    }
+
+    /// Change the TCR once the SPI is idle
+    pub(super) fn update_tcr(&mut self, f: impl FnOnce(u32) -> u32) -> Result<(), ModeError> {
+        self.quiesce()?;
+        let tcr = ral::read_reg!(ral::lpspi, self.reg, TCR);
+        ral::write_reg!(ral::lpspi, self.reg, TCR, f(tcr));
+        Ok(())
+    }
+
+    /// Set the SPI mode for the peripheral
+    ///
+    /// `set_mode()` waits for the SPI to send its queued frames, so the new mode never
+    /// applies to a frame that was sent in the old mode. Returns an error, without changing
+    /// the mode, if the SPI doesn't go idle.
+    ///
+    /// There's no way to change the mode during a [`transaction()`](#method.transaction).
+    /// To give each device on a shared bus its own mode, see [`Device`](struct.Device.html).
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), ModeError> {
+        self.update_tcr(|tcr| tcr_mode(tcr, mode))
+    }
+
+    /// Set the order of the bits in each frame
+    ///
+    /// Like [`set_mode()`](#method.set_mode), `set_bit_order()` waits for the SPI to send its
+    /// queued frames.
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) -> Result<(), ModeError> {
+        self.update_tcr(|tcr| tcr_bit_order(tcr, bit_order))
+    }
+
+    /// Returns the order of the bits in each frame
+    pub fn bit_order(&self) -> BitOrder {
+        if ral::read_reg!(ral::lpspi, self.reg, TCR, LSBF == LSBF_1) {
+            BitOrder::LsbFirst
+        } else {
+            BitOrder::MsbFirst
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tcr_bit_order, tcr_mode, BitOrder};
+    use embedded_hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+
+    #[test]
+    fn modes_and_bit_orders() {
+        const CPOL: u32 = 1 << 31;
+        const CPHA: u32 = 1 << 30;
+        const LSBF: u32 = 1 << 23;
+        // PRESCALE, PCS1, and 8-bit frames stay
+        let others = 0b101 << 27 | 1 << 24 | 7;
+
+        for &(mode, bits) in &[
+            (MODE_0, 0),
+            (MODE_1, CPHA),
+            (MODE_2, CPOL),
+            (MODE_3, CPOL | CPHA),
+        ] {
+            for &(bit_order, lsbf) in &[(BitOrder::MsbFirst, 0), (BitOrder::LsbFirst, LSBF)] {
+                // From every starting mode and order
+                for &before in &[others, others | CPOL | CPHA | LSBF] {
+                    let tcr = tcr_bit_order(tcr_mode(before, mode), bit_order);
+                    assert_eq!(tcr, others | bits | lsbf);
+                    // The order doesn't matter
+                    assert_eq!(tcr_mode(tcr_bit_order(before, bit_order), mode), tcr);
+                }
+            }
+        }
+    }
+}
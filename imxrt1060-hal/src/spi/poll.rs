@@ -0,0 +1,493 @@
+//! Repeated register reads, driven by DMA
+
+use super::{
+    transaction::{tcr_begin, tcr_end},
+    RETRIES, SPI,
+};
+use crate::dma::{
+    self, buffer,
+    channel::{self, Attributes, ControlFlags, ControlStatus, TcdSnapshot},
+    peripheral::Source,
+    Channel, ChannelExt, Circular,
+};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::{
+    mem, ptr,
+    sync::atomic::{compiler_fence, AtomicU32, Ordering},
+};
+use imxrt_dma::Transfer;
+
+/// The most frames, command and response, in one poll
+///
+/// Each frame takes a TCR word and a TDR word in the TX FIFO, and the end of the
+/// poll takes one more TCR word. The DMA controller writes the whole poll at once,
+/// so it must fit in the 16 word TX FIFO.
+const MAX_FRAMES: usize = 7;
+/// The most words in one poll's stream
+const STREAM_WORDS: usize = 2 * MAX_FRAMES + 1;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_WORD: AtomicU32 = AtomicU32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_STREAM: [AtomicU32; STREAM_WORDS] = [EMPTY_WORD; STREAM_WORDS];
+
+/// The TCR and TDR words of each LPSPI's poll
+///
+/// The transmit channel copies the whole stream for every poll.
+static STREAMS: [[AtomicU32; STREAM_WORDS]; 4] = [EMPTY_STREAM; 4];
+
+/// How each poll starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollTrigger {
+    /// The PIT channel with the transmit channel's number starts each poll
+    ///
+    /// Only DMA channels 0 through 3 support periodic triggers.
+    Periodic,
+    /// [`HardwarePoll::trigger()`](struct.HardwarePoll.html#method.trigger) starts each poll
+    Software,
+}
+
+/// An error when starting a [`HardwarePoll`](struct.HardwarePoll.html)
+#[derive(Debug)]
+pub enum PollError {
+    /// The response is empty, or the command and response are longer than 7 bytes
+    Length,
+    /// The transmit channel doesn't support periodic triggers
+    Trigger(dma::PeriodicTriggerError),
+    /// The circular buffer holds fewer than two responses
+    Buffer,
+    /// There's already a poll, or a channel has an error
+    Dma(dma::Error),
+}
+
+/// Fill `stream` with the words of one poll, and return the number of words
+///
+/// `tcr` is the SPI's TCR, which supplies the clock mode, bit order, and chip select.
+/// Every frame has its own TCR word, since the transmit channel alternates between TCR
+/// and TDR. The command frames mask the receiver, so only the response reaches the RX
+/// FIFO. The last TCR word deasserts the chip select.
+fn poll_stream(
+    tcr: u32,
+    command: &[u8],
+    response_len: usize,
+    stream: &mut [u32],
+) -> Result<usize, PollError> {
+    use ral::lpspi::TCR::{BYSW, CONTC, RXMSK, TXMSK};
+    let frames = command.len() + response_len;
+    let len = 2 * frames + 1;
+    if response_len == 0 || len > stream.len() {
+        return Err(PollError::Length);
+    }
+    let begin = tcr_begin(tcr & !(BYSW::mask | RXMSK::mask | TXMSK::mask), 8);
+    for frame in 0..frames {
+        let contc = if frame > 0 { CONTC::mask } else { 0 };
+        let rxmsk = if frame < command.len() {
+            RXMSK::mask
+        } else {
+            0
+        };
+        stream[2 * frame] = begin | contc | rxmsk;
+        stream[2 * frame + 1] = command.get(frame).copied().map_or(0, u32::from);
+    }
+    stream[len - 1] = tcr_end(begin);
+    Ok(len)
+}
+
+/// Returns the index of the first byte of the latest complete response
+///
+/// `next` is the index that the receive channel writes next, and `remaining` is the
+/// number of bytes that the current response still needs. `capacity` is a power of two.
+fn latest_start(next: usize, remaining: usize, response_len: usize, capacity: usize) -> usize {
+    let received = response_len - remaining.min(response_len);
+    next.wrapping_sub(received + response_len) & (capacity - 1)
+}
+
+/// A SPI that reads a device's register over and over, without the CPU
+///
+/// `HardwarePoll` owns a SPI, and two DMA channels. For every poll, the transmit channel
+/// writes the command, and the fill bytes of the response, into the TX FIFO. The TCR words
+/// between the bytes hold the chip select asserted for the whole poll. The receive channel
+/// moves each response from the RX FIFO into a [`Circular`](../dma/struct.Circular.html)
+/// buffer, and it wraps around the buffer forever. Read the newest response with
+/// [`latest_response()`](#method.latest_response).
+///
+/// A poll uses the SPI's current clock, mode, bit order, and [active chip
+/// select](struct.SPI.html#method.set_active_cs). The command and the response are at most 7
+/// bytes together, since the transmit channel writes the whole poll into the TX FIFO at
+/// once. For the same reason, a poll must finish before the next one starts; choose a
+/// trigger period that's longer than the poll's frames and delays.
+///
+/// # Example
+///
+/// Read a radio's two byte status register, command `0x07`, every 100us. The transmit
+/// channel is DMA channel 2, so PIT channel 2 paces the polls.
+///
+/// ```no_run
+/// use imxrt1060_hal::dma::{Buffer, Circular};
+/// use imxrt1060_hal::spi::{HardwarePoll, PollTrigger};
+/// # use imxrt1060_hal::{dma::Channel, iomuxc::consts::U4, spi::SPI};
+/// # fn radio(spi: SPI<U4>, tx_channel: Channel, rx_channel: Channel) {
+///
+/// #[repr(align(16))]
+/// struct Align(Buffer<[u8; 16]>);
+/// static STATUS: Align = Align(Buffer::new([0; 16]));
+///
+/// let mut radio = HardwarePoll::new(spi, tx_channel, rx_channel);
+/// let responses = Circular::new(&STATUS.0).unwrap();
+/// radio
+///     .start(&[0x07], responses, 2, PollTrigger::Periodic)
+///     .map_err(|(_, err)| err)
+///     .unwrap();
+///
+/// // Start PIT channel 2 with a 100us period, then...
+/// if let Some(status) = radio.latest_response() {
+///     log::info!("status {:02X} {:02X}", status[0], status[1]);
+/// }
+/// # }
+/// ```
+pub struct HardwarePoll<M> {
+    spi: SPI<M>,
+    tx_channel: Channel,
+    rx_channel: Channel,
+    responses: Option<Circular<u8>>,
+    response_len: usize,
+    trigger: PollTrigger,
+    /// A copy of the latest response, so that the receive channel can't change it
+    latest: [u8; MAX_FRAMES],
+}
+
+impl<M> HardwarePoll<M>
+where
+    M: Unsigned,
+{
+    /// Prepare `spi` to poll with `tx_channel` and `rx_channel`
+    ///
+    /// `new()` resets both channels' TCDs. Configure each channel's priority before starting
+    /// a poll.
+    pub fn new(spi: SPI<M>, mut tx_channel: Channel, mut rx_channel: Channel) -> Self {
+        crate::ccm::clock_gate::debug_assert_clocked!(crate::ccm::clock_gate::Dma);
+        tx_channel.reset_tcd();
+        rx_channel.reset_tcd();
+        rx_channel.set_trigger_from_hardware(Some(Source::<u8>::source_signal(&spi)));
+        // Safety: the SPI's Source implementation points at its receive data register.
+        unsafe {
+            rx_channel.set_source_transfer(&Transfer::hardware(Source::<u8>::source(&spi)));
+        }
+        rx_channel.set_disable_on_completion(false);
+        HardwarePoll {
+            spi,
+            tx_channel,
+            rx_channel,
+            responses: None,
+            response_len: 0,
+            trigger: PollTrigger::Periodic,
+            latest: [0; MAX_FRAMES],
+        }
+    }
+
+    /// Poll with `command`, and receive `response_len` byte responses into `responses`
+    ///
+    /// The previous contents of `responses` are discarded. Returns the buffer if there's
+    /// already a poll, if the poll doesn't fit in the TX FIFO, if `responses` can't hold
+    /// two responses, or if either channel fails to start.
+    ///
+    /// With a [`Periodic`](enum.PollTrigger.html#variant.Periodic) trigger, start the PIT
+    /// channel after `start()`.
+    pub fn start(
+        &mut self,
+        command: &[u8],
+        mut responses: Circular<u8>,
+        response_len: usize,
+        trigger: PollTrigger,
+    ) -> Result<(), (Circular<u8>, PollError)> {
+        if self.responses.is_some() || self.tx_channel.is_enabled() || self.rx_channel.is_enabled()
+        {
+            return Err((responses, PollError::Dma(dma::Error::ScheduledTransfer)));
+        }
+        for channel in [&self.tx_channel, &self.rx_channel].iter() {
+            if channel.is_error() {
+                let es = channel.error_status();
+                return Err((responses, PollError::Dma(dma::Error::PreexistingError(es))));
+            }
+        }
+        if responses.size() < 2 * response_len {
+            return Err((responses, PollError::Buffer));
+        }
+        let tcr = ral::read_reg!(ral::lpspi, self.spi.reg, TCR);
+        let mut words = [0; STREAM_WORDS];
+        let depth = self.spi.tx_depth().min(STREAM_WORDS);
+        let len = match poll_stream(tcr, command, response_len, &mut words[..depth]) {
+            Ok(len) => len,
+            Err(err) => return Err((responses, err)),
+        };
+        let trigger_result = match trigger {
+            PollTrigger::Periodic => self.tx_channel.set_periodic_trigger(),
+            PollTrigger::Software => {
+                self.tx_channel.set_trigger_from_hardware(None);
+                Ok(())
+            }
+        };
+        if let Err(err) = trigger_result {
+            return Err((responses, PollError::Trigger(err)));
+        }
+
+        let stream = &STREAMS[M::USIZE - 1];
+        for (slot, word) in stream.iter().zip(words[..len].iter()) {
+            slot.store(*word, Ordering::Relaxed);
+        }
+        dma::cache::clean(stream.as_ptr() as usize, len * mem::size_of::<AtomicU32>());
+        // The destination wraps around the 8 bytes that hold TCR and TDR. The stream
+        // has an odd number of words, so it ends with the TCR word that ends the poll,
+        // and leaves the destination on TDR; the last adjustment brings the destination
+        // back to TCR for the next poll.
+        let bytes = (len * mem::size_of::<u32>()) as u32;
+        let tcd = TcdSnapshot {
+            saddr: stream.as_ptr() as u32,
+            soff: 4,
+            attr: Attributes {
+                ssize: Some(4),
+                dsize: Some(4),
+                smod: 0,
+                dmod: 3,
+            },
+            nbytes: bytes,
+            slast: -(bytes as i32),
+            daddr: &self.spi.reg.TCR as *const _ as u32,
+            doff: 4,
+            citer: 1,
+            dlast_sga: -4,
+            csr: ControlStatus {
+                bwc: 0,
+                major_link_channel: 0,
+                flags: ControlFlags::empty(),
+            },
+            biter: 1,
+        };
+
+        responses.prepare_continuous_destination();
+        self.tx_channel.clear_complete();
+        self.rx_channel.clear_complete();
+        // Safety: the buffer stays in place until the poll stops. The static stream
+        // always holds this SPI's poll, and the TCD only writes TCR and TDR.
+        unsafe {
+            self.rx_channel
+                .set_destination_transfer(&buffer::Destination::destination(&responses));
+            channel::write_tcd(&mut self.tx_channel, &tcd);
+        }
+        self.rx_channel.set_minor_loop_elements::<u8>(1);
+        self.rx_channel.set_transfer_iterations(response_len as u16);
+
+        Source::<u8>::enable_source(&self.spi);
+        compiler_fence(Ordering::Release);
+        unsafe {
+            self.rx_channel.enable();
+        }
+        if self.rx_channel.is_error() {
+            let es = self.rx_channel.error_status();
+            self.rx_channel.clear_error();
+            self.stop_receive();
+            return Err((responses, PollError::Dma(dma::Error::Setup(es))));
+        }
+        if trigger == PollTrigger::Periodic {
+            unsafe {
+                self.tx_channel.enable();
+            }
+        }
+        if self.tx_channel.is_error() {
+            let es = self.tx_channel.error_status();
+            self.tx_channel.clear_error();
+            self.stop_transmit();
+            self.stop_receive();
+            return Err((responses, PollError::Dma(dma::Error::Setup(es))));
+        }
+        self.responses = Some(responses);
+        self.response_len = response_len;
+        self.trigger = trigger;
+        Ok(())
+    }
+
+    /// Start one poll
+    ///
+    /// Returns `false`, without starting a poll, if there's no poll with a
+    /// [`Software`](enum.PollTrigger.html#variant.Software) trigger, or if the previous
+    /// poll is still in the TX FIFO.
+    pub fn trigger(&mut self) -> bool {
+        if self.responses.is_none()
+            || self.trigger != PollTrigger::Software
+            || ral::read_reg!(ral::lpspi, self.spi.reg, FSR, TXCOUNT) != 0
+        {
+            return false;
+        }
+        // Safety: start() configured the TCD for this poll.
+        unsafe {
+            self.tx_channel.start();
+        }
+        true
+    }
+
+    /// Returns the latest complete response
+    ///
+    /// Returns `None` if there's no poll, or if the first response hasn't arrived. The
+    /// response is a copy, so the next poll doesn't change it.
+    pub fn latest_response(&mut self) -> Option<&[u8]> {
+        let responses = self.responses.as_ref()?;
+        if !self.rx_channel.is_complete() {
+            return None;
+        }
+        let capacity = responses.size();
+        let base = responses.as_ptr();
+        let position = |rx_channel: &Channel| {
+            (
+                responses.index_of(channel::destination_address(rx_channel)),
+                usize::from(channel::remaining_iterations(rx_channel)),
+            )
+        };
+        for _ in 0..RETRIES {
+            let (next, remaining) = position(&self.rx_channel);
+            let start = latest_start(next, remaining, self.response_len, capacity);
+            dma::cache::invalidate(base as usize, capacity);
+            for (idx, byte) in self.latest[..self.response_len].iter_mut().enumerate() {
+                // Safety: the index is within the backing buffer.
+                *byte = unsafe { ptr::read_volatile(base.add((start + idx) & (capacity - 1))) };
+            }
+            // If the receive channel moved, the copy may hold part of the next response
+            if position(&self.rx_channel) == (next, remaining) {
+                return Some(&self.latest[..self.response_len]);
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if either channel has an error, or if a FIFO underran or overflowed
+    ///
+    /// Polls stop after a channel error. Use [`stop()`](#method.stop) to clear the error.
+    pub fn is_error(&self) -> bool {
+        self.tx_channel.is_error()
+            || self.rx_channel.is_error()
+            || self.spi.status().error().is_some()
+    }
+
+    /// Stop polling, and return the buffer
+    ///
+    /// A poll that's already in the TX FIFO finishes, so the chip select always deasserts.
+    /// `stop()` clears the channel errors, and [aborts](struct.SPI.html#method.abort) the
+    /// SPI, if it's stuck. Returns `None` if there's no poll.
+    pub fn stop(&mut self) -> Option<Circular<u8>> {
+        let responses = self.responses.take()?;
+        self.stop_transmit();
+        self.stop_receive();
+        self.spi.abort();
+        for channel in [&mut self.tx_channel, &mut self.rx_channel].iter_mut() {
+            channel.clear_error();
+            channel.clear_complete();
+        }
+        Some(responses)
+    }
+
+    /// Stop the transmit channel between polls, and let the last poll finish
+    fn stop_transmit(&mut self) {
+        // Safety: we own the transmit channel, and nothing else uses its CSR.
+        unsafe {
+            channel::cancel(self.tx_channel.channel());
+        }
+        self.tx_channel.disable();
+        // The stream always ends with the TCR word that deasserts the chip select.
+        // If the SPI is stuck, clear_fifo() discards the rest of the poll.
+        let _ = self.spi.quiesce();
+    }
+
+    /// Stop the receive channel
+    fn stop_receive(&mut self) {
+        Source::<u8>::disable_source(&self.spi);
+        while self.rx_channel.is_hardware_signaling() || self.rx_channel.is_active() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.rx_channel.disable();
+        compiler_fence(Ordering::Acquire);
+    }
+
+    /// Release the SPI, and the (transmit, receive) channels
+    ///
+    /// Any poll stops, and its buffer is dropped. Reset the channels' TCDs before
+    /// using them for other transfers.
+    pub fn release(mut self) -> (SPI<M>, (Channel, Channel)) {
+        self.stop();
+        (self.spi, (self.tx_channel, self.rx_channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{latest_start, poll_stream, PollError, STREAM_WORDS};
+    use crate::ral::lpspi::TCR::{BYSW, CONT, CONTC, CPOL, FRAMESZ, PCS, RXMSK};
+
+    /// Mode 2, PCS1, 16-bit frames, with a leftover byte swap
+    const TCR: u32 = CPOL::mask | 1 << PCS::offset | 15 << FRAMESZ::offset | BYSW::mask;
+    /// The TCR that starts a poll
+    const BEGIN: u32 = CPOL::mask | 1 << PCS::offset | 7 << FRAMESZ::offset | CONT::mask;
+
+    #[test]
+    fn command_then_response() {
+        let mut stream = [0; STREAM_WORDS];
+        let len = poll_stream(TCR, &[0x07], 2, &mut stream).unwrap();
+        assert_eq!(
+            &stream[..len],
+            &[
+                BEGIN | RXMSK::mask,
+                0x07,
+                BEGIN | CONTC::mask,
+                0,
+                BEGIN | CONTC::mask,
+                0,
+                CPOL::mask | 1 << PCS::offset | 7 << FRAMESZ::offset,
+            ]
+        );
+    }
+
+    #[test]
+    fn longer_command() {
+        let mut stream = [0; STREAM_WORDS];
+        let len = poll_stream(TCR, &[0x80, 0x12], 1, &mut stream).unwrap();
+        assert_eq!(len, 7);
+        assert_eq!(stream[0], BEGIN | RXMSK::mask);
+        assert_eq!(stream[2], BEGIN | CONTC::mask | RXMSK::mask);
+        assert_eq!(stream[4], BEGIN | CONTC::mask);
+        assert_eq!((stream[1], stream[3], stream[5]), (0x80, 0x12, 0));
+
+        // Without a command, the first response frame starts the transfer
+        let len = poll_stream(TCR, &[], 1, &mut stream).unwrap();
+        assert_eq!(&stream[..len], &[BEGIN, 0, BEGIN & !CONT::mask]);
+    }
+
+    #[test]
+    fn stream_fits_fifo() {
+        let mut stream = [0; STREAM_WORDS];
+        assert_eq!(poll_stream(TCR, &[1, 2, 3], 4, &mut stream).unwrap(), 15);
+        assert!(matches!(
+            poll_stream(TCR, &[1, 2, 3, 4], 4, &mut stream),
+            Err(PollError::Length)
+        ));
+        assert!(matches!(
+            poll_stream(TCR, &[1], 0, &mut stream),
+            Err(PollError::Length)
+        ));
+        // A smaller FIFO
+        assert!(matches!(
+            poll_stream(TCR, &[1], 2, &mut stream[..4]),
+            Err(PollError::Length)
+        ));
+    }
+
+    #[test]
+    fn latest_response_position() {
+        // Between polls; the last response ends just before `next`
+        assert_eq!(latest_start(6, 2, 2, 16), 4);
+        // One byte into the next poll
+        assert_eq!(latest_start(7, 1, 2, 16), 4);
+        // The latest response wraps around the end of the buffer
+        assert_eq!(latest_start(1, 3, 3, 8), 6);
+        assert_eq!(latest_start(0, 1, 3, 8), 3);
+    }
+}
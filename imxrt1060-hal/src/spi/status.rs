@@ -0,0 +1,419 @@
+//! Status flags, and recovering from errors and cancelled transfers
+
+use super::{transaction::tcr_end, TransferError, SPI};
+use crate::dma::{
+    self,
+    peripheral::{Destination, Source},
+};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+bitflags::bitflags! {
+    /// SPI status flags
+    ///
+    /// The flags have the same positions as they have in the SR register.
+    pub struct Status : u32 {
+        /// The SPI is busy with a transfer
+        const BUSY = 1 << 24;
+        /// A received word matched the data match configuration
+        const DATA_MATCH = 1 << 13;
+        /// The RX FIFO overflowed, and received words were lost
+        ///
+        /// Only sets if transfers [don't stall](struct.SPI.html#method.set_stall).
+        const RECEIVE_OVERFLOW = 1 << 12;
+        /// The TX FIFO underran, and the SPI sent a stale word
+        ///
+        /// Only sets if transfers [don't stall](struct.SPI.html#method.set_stall).
+        const TRANSMIT_UNDERRUN = 1 << 11;
+        /// The chip select deasserted after the last frame
+        const TRANSFER_COMPLETE = 1 << 10;
+        /// A frame finished
+        const FRAME_COMPLETE = 1 << 9;
+        /// A word finished
+        const WORD_COMPLETE = 1 << 8;
+        /// The RX FIFO holds more words than its watermark
+        const RECEIVE_DATA = 1 << 1;
+        /// The TX FIFO holds its watermark, or fewer words
+        const TRANSMIT_DATA = 1 << 0;
+    }
+}
+
+impl Status {
+    /// The flags that software can clear
+    const CLEARABLE: Status = Status::from_bits_truncate(
+        Status::DATA_MATCH.bits()
+            | Status::RECEIVE_OVERFLOW.bits()
+            | Status::TRANSMIT_UNDERRUN.bits()
+            | Status::TRANSFER_COMPLETE.bits()
+            | Status::FRAME_COMPLETE.bits()
+            | Status::WORD_COMPLETE.bits(),
+    );
+
+    /// The flags that fail a transfer
+    const ERRORS: Status = Status::from_bits_truncate(
+        Status::DATA_MATCH.bits()
+            | Status::RECEIVE_OVERFLOW.bits()
+            | Status::TRANSMIT_UNDERRUN.bits(),
+    );
+
+    /// Returns the error that fails a transfer, if there is one
+    ///
+    /// An underrun is reported first, since it usually causes the overflow.
+    pub(super) fn error(self) -> Option<TransferError> {
+        if self.contains(Status::TRANSMIT_UNDERRUN) {
+            Some(TransferError::TransmitUnderrun)
+        } else if self.contains(Status::RECEIVE_OVERFLOW) {
+            Some(TransferError::ReceiveOverflow)
+        } else if self.contains(Status::DATA_MATCH) {
+            Some(TransferError::DataMismatch)
+        } else {
+            None
+        }
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Returns the SPI status flags
+    pub fn status(&self) -> Status {
+        Status::from_bits_truncate(ral::read_reg!(ral::lpspi, self.reg, SR))
+    }
+
+    /// Clear all of the error and completion flags
+    pub fn clear_status(&mut self) {
+        self.clear_status_flags(Status::all());
+    }
+
+    /// Clear the `status` flags
+    ///
+    /// Only the error and completion flags clear. The busy flag, and the FIFO data
+    /// flags, follow the SPI, so they're ignored.
+    pub fn clear_status_flags(&mut self, status: Status) {
+        // SR flags clear when written with 1, and the other bits are read-only
+        ral::write_reg!(
+            ral::lpspi,
+            self.reg,
+            SR,
+            (status & Status::CLEARABLE).bits()
+        );
+    }
+
+    /// Discard the words in both FIFOs, and clear the error flags
+    ///
+    /// The error flags stay set after a transfer returns a
+    /// [`TransferError`](enum.TransferError.html), and every transfer fails until they're
+    /// cleared. After `reset_fifos()`, the next transfer starts from
+    /// empty FIFOs.
+    pub fn reset_fifos(&mut self) {
+        self.clear_fifo();
+        self.clear_status_flags(Status::ERRORS);
+    }
+
+    /// Returns `true` if the SPI is busy with a transfer
+    ///
+    /// The SPI is busy while its chip select is asserted, and while it has frames to
+    /// send. Check `is_busy()` before changing the SPI's configuration.
+    pub fn is_busy(&self) -> bool {
+        self.status().contains(Status::BUSY)
+    }
+
+    /// Stop the current transfer, and leave the SPI idle, with its chip select deasserted
+    ///
+    /// `abort()` discards the words in both FIFOs, and ends any continuous transfer, so
+    /// the chip select deasserts after the frame in the shifter. If the SPI is still busy,
+    /// `abort()` resets it, and restores its configuration: the clock, delays, mode, chip
+    /// select, watermarks, and interrupt and DMA enables. The status flags are cleared.
+    ///
+    /// Stop any DMA channels that serve the SPI before calling `abort()`.
+    /// [`FullDuplexDma::cancel()`](struct.FullDuplexDma.html#method.cancel), and a SPI
+    /// `dma::Peripheral`'s `*_cancel_and_abort()` methods, abort for you.
+    pub fn abort(&mut self) {
+        abort(self);
+    }
+
+    /// Reset the SPI, then restore its configuration, and `tcr`
+    fn reset(&mut self, tcr: u32) {
+        use ral::lpspi::CR::{RRF, RST, RTF};
+        let cr = ral::read_reg!(ral::lpspi, self.reg, CR);
+        let cfgr1 = ral::read_reg!(ral::lpspi, self.reg, CFGR1);
+        let ccr = ral::read_reg!(ral::lpspi, self.reg, CCR);
+        let fcr = ral::read_reg!(ral::lpspi, self.reg, FCR);
+        let ier = ral::read_reg!(ral::lpspi, self.reg, IER);
+        let der = ral::read_reg!(ral::lpspi, self.reg, DER);
+
+        ral::write_reg!(ral::lpspi, self.reg, CR, RST: RST_1);
+        ral::write_reg!(ral::lpspi, self.reg, CR, RST: RST_0);
+        // The master is disabled, so the configuration registers are writable
+        ral::write_reg!(ral::lpspi, self.reg, CFGR1, cfgr1);
+        ral::write_reg!(ral::lpspi, self.reg, CCR, ccr);
+        ral::write_reg!(ral::lpspi, self.reg, FCR, fcr);
+        ral::write_reg!(ral::lpspi, self.reg, TCR, tcr);
+        ral::write_reg!(ral::lpspi, self.reg, IER, ier);
+        ral::write_reg!(ral::lpspi, self.reg, DER, der);
+        ral::write_reg!(
+            ral::lpspi,
+            self.reg,
+            CR,
+            cr & !(RST::mask | RRF::mask | RTF::mask)
+        );
+    }
+
+    /// Stall transfers, instead of losing data, when the FIFOs can't keep up
+    ///
+    /// When `stall` is `true`, the default, the SPI pauses the clock while the TX FIFO
+    /// is empty, or while the RX FIFO is full. When `stall` is `false`, the clock keeps
+    /// running: the TX FIFO underruns, or the RX FIFO overflows, and transfers return
+    /// an error. Disable the stall for devices that can't tolerate a paused clock, and
+    /// keep the FIFOs serviced, usually with DMA.
+    ///
+    /// Calling this method temporarily disables the SPI master.
+    pub fn set_stall(&mut self, stall: bool) {
+        self.with_master_disabled(
+            || ral::modify_reg!(ral::lpspi, self.reg, CFGR1, NOSTALL: (!stall) as u32),
+        );
+    }
+}
+
+/// The steps of an [`abort()`](struct.SPI.html#method.abort)
+trait Abort {
+    /// Discard the words in both FIFOs
+    fn flush(&mut self);
+    /// Write a TCR that ends any continuous transfer, and return it
+    fn end_transfer(&mut self) -> u32;
+    /// Wait for the SPI to go idle; returns `false` if it stays busy
+    fn wait_idle(&mut self) -> bool;
+    /// Reset the SPI, and restore its configuration with `tcr`
+    fn reset_module(&mut self, tcr: u32);
+    /// Clear every status flag
+    fn clear_flags(&mut self);
+}
+
+impl<M> Abort for SPI<M>
+where
+    M: Unsigned,
+{
+    fn flush(&mut self) {
+        self.clear_fifo();
+    }
+    fn end_transfer(&mut self) -> u32 {
+        let tcr = tcr_end(ral::read_reg!(ral::lpspi, self.reg, TCR));
+        ral::write_reg!(ral::lpspi, self.reg, TCR, tcr);
+        tcr
+    }
+    fn wait_idle(&mut self) -> bool {
+        self.quiesce().is_ok()
+    }
+    fn reset_module(&mut self, tcr: u32) {
+        self.reset(tcr);
+    }
+    fn clear_flags(&mut self) {
+        self.clear_status();
+    }
+}
+
+/// Flush the FIFOs, so that the ending TCR isn't queued behind stale words, then
+/// end the transfer, and reset the SPI if it doesn't go idle
+///
+/// The FIFOs are flushed again, since the last frame can land in the RX FIFO while
+/// the SPI stops.
+fn abort<T: Abort>(spi: &mut T) {
+    spi.flush();
+    let tcr = spi.end_transfer();
+    if !spi.wait_idle() {
+        spi.reset_module(tcr);
+    }
+    spi.flush();
+    spi.clear_flags();
+}
+
+impl<M, E, S, D> dma::Peripheral<SPI<M>, E, S, D>
+where
+    M: Unsigned,
+{
+    /// Stop the SPI's current transfer, and leave it idle
+    ///
+    /// Cancel the DMA transfers first, or use one of the `*_cancel_and_abort()` methods.
+    /// See [`SPI::abort()`](../spi/struct.SPI.html#method.abort).
+    pub fn abort(&mut self) {
+        self.peripheral_mut().abort();
+    }
+}
+
+impl<M, E, S, D> dma::Peripheral<SPI<M>, E, S, D>
+where
+    M: Unsigned,
+    SPI<M>: Source<E>,
+    E: dma::Element,
+    D: dma::buffer::Destination<E>,
+{
+    /// Cancel a receive, then [`abort()`](#method.abort)
+    ///
+    /// The SPI's chip select deasserts. Use this for a SPI that only receives with DMA.
+    /// If the SPI also transmits with DMA, use
+    /// [`bidirectional_cancel_and_abort()`](#method.bidirectional_cancel_and_abort).
+    pub fn receive_cancel_and_abort(&mut self) -> Option<D> {
+        let buffer = self.receive_cancel();
+        self.abort();
+        buffer
+    }
+}
+
+impl<M, E, S, D> dma::Peripheral<SPI<M>, E, S, D>
+where
+    M: Unsigned,
+    SPI<M>: Destination<E>,
+    E: dma::Element,
+    S: dma::buffer::Source<E>,
+{
+    /// Cancel a transfer, then [`abort()`](#method.abort)
+    ///
+    /// The SPI's chip select deasserts. Use this for a SPI that only transmits with DMA.
+    /// If the SPI also receives with DMA, use
+    /// [`bidirectional_cancel_and_abort()`](#method.bidirectional_cancel_and_abort).
+    pub fn transfer_cancel_and_abort(&mut self) -> Option<S> {
+        let buffer = self.transfer_cancel();
+        self.abort();
+        buffer
+    }
+}
+
+impl<M, E, S, D> dma::Peripheral<SPI<M>, E, S, D>
+where
+    M: Unsigned,
+    SPI<M>: Source<E> + Destination<E>,
+    E: dma::Element,
+    S: dma::buffer::Source<E>,
+    D: dma::buffer::Destination<E>,
+{
+    /// Cancel the transfer, then the receive, then [`abort()`](#method.abort)
+    ///
+    /// The transfer stops first, so that no more frames enter the TX FIFO. The SPI's chip
+    /// select deasserts. Returns the (transfer, receive) buffers.
+    pub fn bidirectional_cancel_and_abort(&mut self) -> (Option<S>, Option<D>) {
+        let source = self.transfer_cancel();
+        let destination = self.receive_cancel();
+        self.abort();
+        (source, destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{abort, Abort, Status, TransferError};
+    use crate::testing::EventLog;
+
+    #[test]
+    fn decode_errors() {
+        // Busy, with an empty TX FIFO
+        assert_eq!(Status::from_bits_truncate(0x0100_0001).error(), None);
+        assert_eq!(
+            Status::from_bits_truncate(1 << 12).error(),
+            Some(TransferError::ReceiveOverflow)
+        );
+        assert_eq!(
+            Status::from_bits_truncate(1 << 11 | 1 << 12).error(),
+            Some(TransferError::TransmitUnderrun)
+        );
+        assert_eq!(
+            Status::from_bits_truncate(1 << 13 | 1 << 10).error(),
+            Some(TransferError::DataMismatch)
+        );
+    }
+
+    #[test]
+    fn clearable_flags() {
+        let sr = Status::from_bits_truncate(0x0100_3F03);
+        assert_eq!(sr, Status::all());
+        assert_eq!((sr & Status::CLEARABLE).bits(), 0x3F00);
+        assert_eq!((sr & Status::ERRORS).bits(), 0x3800);
+    }
+
+    #[test]
+    fn busy_flag() {
+        // MBF, and nothing else, is busy
+        assert!(Status::from_bits_truncate(1 << 24).contains(Status::BUSY));
+        assert!(!Status::from_bits_truncate(0x0000_3F03).contains(Status::BUSY));
+        // Software can't clear it; only an abort can make the SPI idle
+        assert!(!Status::CLEARABLE.contains(Status::BUSY));
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        Flush,
+        EndTransfer,
+        Reset(u32),
+        ClearFlags,
+    }
+
+    /// Records the abort steps. A stuck SPI never goes idle on its own.
+    struct MockSpi {
+        log: EventLog<Event>,
+        stuck: bool,
+        busy: bool,
+    }
+
+    impl MockSpi {
+        fn new(stuck: bool) -> Self {
+            MockSpi {
+                log: EventLog::default(),
+                stuck,
+                busy: true,
+            }
+        }
+        fn push(&mut self, event: Event) {
+            self.log.push(event);
+        }
+    }
+
+    impl Abort for MockSpi {
+        fn flush(&mut self) {
+            self.push(Event::Flush);
+        }
+        fn end_transfer(&mut self) -> u32 {
+            self.push(Event::EndTransfer);
+            // CONT cleared, CONTC set
+            1 << 20
+        }
+        fn wait_idle(&mut self) -> bool {
+            self.busy = self.stuck;
+            !self.busy
+        }
+        fn reset_module(&mut self, tcr: u32) {
+            self.push(Event::Reset(tcr));
+            self.busy = false;
+        }
+        fn clear_flags(&mut self) {
+            self.push(Event::ClearFlags);
+        }
+    }
+
+    #[test]
+    fn abort_idle_spi() {
+        let mut spi = MockSpi::new(false);
+        abort(&mut spi);
+        assert!(!spi.busy);
+        spi.log.assert(&[
+            Event::Flush,
+            Event::EndTransfer,
+            Event::Flush,
+            Event::ClearFlags,
+        ]);
+    }
+
+    #[test]
+    fn abort_resets_stuck_spi() {
+        let mut spi = MockSpi::new(true);
+        abort(&mut spi);
+        assert!(!spi.busy);
+        // The reset keeps the TCR that ended the transfer
+        spi.log.assert(&[
+            Event::Flush,
+            Event::EndTransfer,
+            Event::Reset(1 << 20),
+            Event::Flush,
+            Event::ClearFlags,
+        ]);
+    }
+}
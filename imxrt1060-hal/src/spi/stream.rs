@@ -0,0 +1,554 @@
+//! Continuous DMA receives into a circular buffer
+
+use super::{transaction::tcr_begin, Error, SPI};
+use crate::dma::{
+    self, buffer, channel,
+    peripheral::{Destination, Source},
+    Channel, ChannelExt, Circular,
+};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::sync::atomic::{compiler_fence, AtomicU32, Ordering};
+use imxrt_dma::Transfer;
+
+/// The dummy word that each LPSPI sends while it streams
+static DUMMY: [AtomicU32; 4] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// The most samples in a stream's buffer
+///
+/// One pass around the buffer is one major loop of the receive channel, and CITER
+/// holds 15 bits.
+const MAX_SAMPLES: usize = 0x7FFF;
+
+/// An error while streaming
+#[derive(Debug)]
+pub enum StreamError {
+    /// The buffer filled before the samples were read, so the receive channel
+    /// overwrote unread samples
+    ///
+    /// The unread samples are dropped, and the stream continues with the next sample.
+    Overrun,
+    /// The RX FIFO overflowed, and the SPI lost samples
+    ///
+    /// Only happens if transfers [don't stall](struct.SPI.html#method.set_stall).
+    Spi(Error),
+    /// The transmit channel has an error, so the clock stopped
+    Transmit(dma::ErrorStatus),
+    /// The receive channel has an error, so the stream stopped
+    Receive(dma::ErrorStatus),
+}
+
+/// Returns the number of samples that the receive channel wrote since it was at `last`,
+/// or `None` if it overwrote unread samples
+///
+/// The receive channel is now at `next`, and `lapped` is `true` if it finished a pass
+/// around the buffer since `last`. The `unread` samples end at `last`. `size` is a power
+/// of two. A buffer with `size` unread samples counts as overwritten, since the next
+/// sample lands on the oldest one.
+fn written_since(
+    last: usize,
+    next: usize,
+    lapped: bool,
+    unread: usize,
+    size: usize,
+) -> Option<usize> {
+    let written = if next < last {
+        next + size - last
+    } else if lapped {
+        // At least one full pass
+        return None;
+    } else {
+        next - last
+    };
+    if unread + written >= size {
+        None
+    } else {
+        Some(written)
+    }
+}
+
+/// The receive channel of a stream
+trait Receive {
+    /// Returns `true` if the channel finished a pass around the buffer since the flag
+    /// was cleared
+    fn is_lapped(&self) -> bool;
+    /// Clear the lap flag
+    fn clear_lapped(&mut self);
+    /// Returns the index of the sample that the channel writes next
+    fn position(&self) -> usize;
+}
+
+/// Returns the receive channel's position, and `true` if it finished a pass since it
+/// was at `last`
+///
+/// The lap flag is sampled before the position. If the channel finishes a pass in
+/// between, the position is behind `last`, so the pass is counted, and the flag is
+/// cleared with it.
+fn observe<R: Receive>(rx: &mut R, last: usize) -> (usize, bool) {
+    let lapped = rx.is_lapped();
+    let next = rx.position();
+    if lapped || next < last {
+        rx.clear_lapped();
+    }
+    (next, lapped)
+}
+
+/// A SPI master that receives a continuous stream of `u32` samples with DMA
+///
+/// `StreamingRead` owns a SPI, and two DMA channels. The transmit channel sends a
+/// dummy word to the TX FIFO for every sample, so the clock never stops, and the receive
+/// channel moves the samples into a [`Circular`](../dma/struct.Circular.html) buffer.
+/// Both channels run until you call [`stop_streaming()`](#method.stop_streaming). The
+/// chip select stays asserted for the whole stream.
+///
+/// Read the samples in place with [`read_slices()`](#method.read_slices). Each pass
+/// around the buffer is one major loop of the receive channel, and `read_slices()` uses
+/// the channel's interrupt flag to count the passes. If the receive channel overwrites
+/// samples that you haven't read, `read_slices()` reports an overrun. Call it at least
+/// once per pass, and before the buffer fills, to see every overrun.
+///
+/// The receive channel interrupts at the end of each pass. You may leave the interrupt
+/// masked in the NVIC. If you handle it, call `read_slices()` from the handler, and don't
+/// clear the flag yourself; `read_slices()` clears it.
+///
+/// A stream uses the SPI's current clock, mode, bit order, and [active chip
+/// select](struct.SPI.html#method.set_active_cs). Each sample is one frame of the
+/// [`u32` frame size](struct.SPI.html#method.set_frame_size). The backing buffer holds
+/// at most 16384 samples.
+///
+/// # Example
+///
+/// Stream 24-bit samples from a sigma-delta ADC with a 10MHz clock. That's about 416k
+/// samples per second, so the buffer holds about 10ms of samples.
+///
+/// ```no_run
+/// use imxrt1060_hal::dma::{Buffer, Circular};
+/// use imxrt1060_hal::spi::{ClockSpeed, StreamingRead};
+/// # use imxrt1060_hal::{dma::Channel, iomuxc::consts::U4, spi::SPI};
+/// # fn stream(mut spi: SPI<U4>, tx_channel: Channel, rx_channel: Channel) {
+///
+/// #[repr(align(16384))]
+/// struct Align(Buffer<[u32; 4096]>);
+/// static SAMPLES: Align = Align(Buffer::new([0; 4096]));
+///
+/// spi.set_clock_speed(ClockSpeed(10_000_000)).unwrap();
+/// spi.set_frame_size(24).unwrap();
+/// let mut adc = StreamingRead::new(spi, tx_channel, rx_channel);
+/// let samples = Circular::new(&SAMPLES.0).unwrap();
+/// adc.start_streaming_read(samples, 0)
+///     .map_err(|(_, err)| err)
+///     .unwrap();
+///
+/// loop {
+///     match adc.read_slices() {
+///         Ok((first, second)) => {
+///             for sample in first.iter().chain(second) {
+///                 // Filter the sample...
+///             }
+///         }
+///         Err(err) => log::warn!("{:?}", err),
+///     }
+/// }
+/// # }
+/// ```
+pub struct StreamingRead<M> {
+    spi: SPI<M>,
+    tx_channel: Channel,
+    rx_channel: Channel,
+    samples: Option<Circular<u32>>,
+    /// The receive channel's position at the last `read_slices()`
+    last: usize,
+    /// The number of samples that the last `read_slices()` returned
+    ///
+    /// They stay unread, so that overruns account for them, until the next call.
+    taken: usize,
+}
+
+impl<M> StreamingRead<M>
+where
+    M: Unsigned,
+{
+    /// Prepare `spi` to stream with `tx_channel` and `rx_channel`
+    ///
+    /// `new()` resets both channels' TCDs. Configure each channel's priority before
+    /// starting a stream. Give the receive channel the higher priority, so that it keeps
+    /// up with the transmit channel.
+    pub fn new(spi: SPI<M>, mut tx_channel: Channel, mut rx_channel: Channel) -> Self {
+        crate::ccm::clock_gate::debug_assert_clocked!(crate::ccm::clock_gate::Dma);
+        tx_channel.reset_tcd();
+        rx_channel.reset_tcd();
+        tx_channel.set_trigger_from_hardware(Some(Destination::<u32>::destination_signal(&spi)));
+        rx_channel.set_trigger_from_hardware(Some(Source::<u32>::source_signal(&spi)));
+        // Safety: the SPI's Source and Destination implementations point at its
+        // data registers. The dummy word is static, and the channel only reads it.
+        unsafe {
+            let dummy = &DUMMY[M::USIZE - 1] as *const AtomicU32 as *const u32;
+            tx_channel.set_source_transfer(&Transfer::hardware(dummy));
+            tx_channel.set_destination_transfer(&Transfer::hardware(
+                Destination::<u32>::destination(&spi),
+            ));
+            rx_channel.set_source_transfer(&Transfer::hardware(Source::<u32>::source(&spi)));
+        }
+        tx_channel.set_disable_on_completion(false);
+        rx_channel.set_disable_on_completion(false);
+        StreamingRead {
+            spi,
+            tx_channel,
+            rx_channel,
+            samples: None,
+            last: 0,
+            taken: 0,
+        }
+    }
+
+    /// Start streaming samples into `samples`, while sending `dummy` for every sample
+    ///
+    /// The previous contents of `samples` are discarded. Returns the buffer if there's
+    /// already a stream, or if either channel fails to start. Returns
+    /// [`Error::InvalidChunkSize`](../dma/enum.Error.html#variant.InvalidChunkSize) if
+    /// the backing buffer holds more than 16384 samples.
+    pub fn start_streaming_read(
+        &mut self,
+        mut samples: Circular<u32>,
+        dummy: u32,
+    ) -> Result<(), (Circular<u32>, dma::Error)> {
+        if self.samples.is_some() || self.tx_channel.is_enabled() || self.rx_channel.is_enabled() {
+            return Err((samples, dma::Error::ScheduledTransfer));
+        }
+        for channel in [&self.tx_channel, &self.rx_channel].iter() {
+            if channel.is_error() {
+                let es = channel.error_status();
+                return Err((samples, dma::Error::PreexistingError(es)));
+            }
+        }
+        if samples.size() > MAX_SAMPLES {
+            return Err((samples, dma::Error::InvalidChunkSize));
+        }
+        let word = &DUMMY[M::USIZE - 1];
+        word.store(dummy, Ordering::Relaxed);
+        dma::cache::clean(word as *const _ as usize, core::mem::size_of::<AtomicU32>());
+
+        samples.prepare_continuous_destination();
+        self.tx_channel.clear_complete();
+        self.rx_channel.clear_complete();
+        self.rx_channel.clear_interrupt();
+        // Safety: the buffer stays in place until the stream stops.
+        unsafe {
+            self.rx_channel
+                .set_destination_transfer(&buffer::Destination::destination(&samples));
+        }
+        // The receive channel's major loop is one pass around the buffer, so its
+        // interrupt flag marks each pass. The completion flag can't: the channel stays
+        // enabled, and the next sample clears it.
+        self.rx_channel.set_interrupt_on_completion(true);
+        self.rx_channel.set_minor_loop_elements::<u32>(1);
+        self.rx_channel
+            .set_transfer_iterations(samples.size() as u16);
+        self.tx_channel.set_minor_loop_elements::<u32>(1);
+        self.tx_channel
+            .set_transfer_iterations(samples.size() as u16);
+
+        Source::<u32>::enable_source(&self.spi);
+        compiler_fence(Ordering::Release);
+        unsafe {
+            self.rx_channel.enable();
+        }
+        if self.rx_channel.is_error() {
+            let es = self.rx_channel.error_status();
+            self.rx_channel.clear_error();
+            self.stop_receive();
+            return Err((samples, dma::Error::Setup(es)));
+        }
+
+        // Hold the chip select for the whole stream
+        let tcr = ral::read_reg!(ral::lpspi, self.spi.reg, TCR);
+        ral::write_reg!(
+            ral::lpspi,
+            self.spi.reg,
+            TCR,
+            tcr_begin(tcr, self.spi.u32_frame_bits)
+        );
+        Destination::<u32>::enable_destination(&self.spi);
+        unsafe {
+            self.tx_channel.enable();
+        }
+        if self.tx_channel.is_error() {
+            let es = self.tx_channel.error_status();
+            self.tx_channel.clear_error();
+            self.stop_transmit();
+            self.stop_receive();
+            self.spi.abort();
+            return Err((samples, dma::Error::Setup(es)));
+        }
+        self.samples = Some(samples);
+        self.last = 0;
+        self.taken = 0;
+        Ok(())
+    }
+
+    /// Returns the number of samples that [`read_slices()`](#method.read_slices)
+    /// would return
+    ///
+    /// Returns 0 if there's no stream. The count doesn't account for overruns; the
+    /// next `read_slices()` reports them.
+    pub fn samples_available(&self) -> usize {
+        match self.samples.as_ref() {
+            Some(samples) => {
+                let next = samples.index_of(channel::destination_address(&self.rx_channel));
+                samples.readable_until(next).saturating_sub(self.taken)
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns the samples that arrived since the last call
+    ///
+    /// The first slice runs up to the end of the buffer, and the second slice holds the
+    /// samples that wrap around to its start. The samples stay in the buffer until the
+    /// next `read_slices()`; if the receive channel overwrites them before then, the next
+    /// call returns [`StreamError::Overrun`](enum.StreamError.html#variant.Overrun).
+    /// After an overrun, the following call returns the samples that arrived after the
+    /// overrun. Returns two empty slices if there's no stream.
+    ///
+    /// Returns an error if either channel has an error, or if the RX FIFO overflowed.
+    /// The errors stay until you stop the stream.
+    pub fn read_slices(&mut self) -> Result<(&[u32], &[u32]), StreamError> {
+        if self.samples.is_none() {
+            return Ok((&[], &[]));
+        }
+        if self.tx_channel.is_error() {
+            return Err(StreamError::Transmit(self.tx_channel.error_status()));
+        }
+        if self.rx_channel.is_error() {
+            return Err(StreamError::Receive(self.rx_channel.error_status()));
+        }
+        if let Some(error) = self.spi.status().error() {
+            return Err(StreamError::Spi(Error::Transfer(error)));
+        }
+
+        let last = self.last;
+        let (next, lapped) = observe(self, last);
+        // Unwrap OK: checked above
+        let samples = self.samples.as_mut().unwrap();
+        let written = written_since(last, next, lapped, samples.len(), samples.size());
+        self.last = next;
+        samples.mark_written_until(next);
+        if written.is_none() {
+            samples.clear();
+            self.taken = 0;
+            return Err(StreamError::Overrun);
+        }
+        samples.mark_read(self.taken);
+        let (first, second) = samples.as_slices();
+        self.taken = first.len() + second.len();
+        Ok((first, second))
+    }
+
+    /// Stop streaming, and return the buffer
+    ///
+    /// The samples that [`read_slices()`](#method.read_slices) hasn't returned are
+    /// readable from the buffer, unless the receive channel overwrote them; then, the
+    /// buffer is empty. The SPI [aborts](struct.SPI.html#method.abort) its last frame, so
+    /// its chip select deasserts. `stop_streaming()` clears the channel errors. Returns
+    /// `None` if there's no stream.
+    pub fn stop_streaming(&mut self) -> Option<Circular<u32>> {
+        self.samples.as_ref()?;
+        self.stop_transmit();
+        self.stop_receive();
+        self.spi.abort();
+        let last = self.last;
+        let (next, lapped) = observe(self, last);
+        for channel in [&mut self.tx_channel, &mut self.rx_channel].iter_mut() {
+            channel.clear_error();
+            channel.clear_complete();
+        }
+        self.rx_channel.clear_interrupt();
+        self.rx_channel.set_interrupt_on_completion(false);
+        let mut samples = self.samples.take()?;
+        let written = written_since(last, next, lapped, samples.len(), samples.size());
+        samples.mark_written_until(next);
+        if written.is_some() {
+            samples.mark_read(self.taken);
+        } else {
+            samples.clear();
+        }
+        Some(samples)
+    }
+
+    /// Stop the transmit channel, so that no more dummy words enter the TX FIFO
+    fn stop_transmit(&mut self) {
+        Destination::<u32>::disable_destination(&self.spi);
+        while self.tx_channel.is_hardware_signaling() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.tx_channel.disable();
+    }
+
+    /// Stop the receive channel
+    fn stop_receive(&mut self) {
+        Source::<u32>::disable_source(&self.spi);
+        while self.rx_channel.is_hardware_signaling() || self.rx_channel.is_active() {
+            #[allow(deprecated)]
+            core::sync::atomic::spin_loop_hint();
+        }
+        self.rx_channel.disable();
+        compiler_fence(Ordering::Acquire);
+    }
+
+    /// Release the SPI, and the (transmit, receive) channels
+    ///
+    /// Any stream stops, and its buffer is dropped. Reset the channels' TCDs before
+    /// using them for other transfers.
+    pub fn release(mut self) -> (SPI<M>, (Channel, Channel)) {
+        self.stop_streaming();
+        (self.spi, (self.tx_channel, self.rx_channel))
+    }
+}
+
+impl<M> Receive for StreamingRead<M> {
+    fn is_lapped(&self) -> bool {
+        self.rx_channel.is_interrupt()
+    }
+    fn clear_lapped(&mut self) {
+        self.rx_channel.clear_interrupt();
+    }
+    fn position(&self) -> usize {
+        // Unwrap OK: only called during a stream
+        let samples = self.samples.as_ref().unwrap();
+        samples.index_of(channel::destination_address(&self.rx_channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{observe, written_since, Receive};
+    use core::cell::Cell;
+
+    #[test]
+    fn samples_since_last_read() {
+        assert_eq!(written_since(0, 0, false, 0, 16), Some(0));
+        assert_eq!(written_since(4, 10, false, 2, 16), Some(6));
+        // The receive channel wrapped around the end of the buffer
+        assert_eq!(written_since(12, 3, true, 0, 16), Some(7));
+        // ...and the lap flag set after it was sampled
+        assert_eq!(written_since(12, 3, false, 0, 16), Some(7));
+    }
+
+    #[test]
+    fn overwritten_samples() {
+        // The unread samples and the new samples fill the buffer
+        assert_eq!(written_since(4, 14, false, 5, 16), Some(10));
+        assert_eq!(written_since(4, 15, false, 5, 16), None);
+        assert_eq!(written_since(12, 6, true, 6, 16), None);
+        // A full pass, or more, since the last read
+        assert_eq!(written_since(4, 4, true, 0, 16), None);
+        assert_eq!(written_since(4, 9, true, 0, 16), None);
+    }
+
+    const SIZE: usize = 16;
+
+    /// A receive channel that stays enabled, and writes one sample per minor loop
+    ///
+    /// Like the eDMA, the end of a pass sets the sticky interrupt flag, and the
+    /// completion flag, which the next minor loop clears.
+    #[derive(Default)]
+    struct MockReceive {
+        position: Cell<usize>,
+        interrupt: Cell<bool>,
+        complete: Cell<bool>,
+        /// Samples that arrive right after the lap flag is sampled
+        between: Cell<usize>,
+    }
+
+    impl MockReceive {
+        fn write(&self, samples: usize) {
+            for _ in 0..samples {
+                self.complete.set(false);
+                let position = (self.position.get() + 1) % SIZE;
+                self.position.set(position);
+                if position == 0 {
+                    self.interrupt.set(true);
+                    self.complete.set(true);
+                }
+            }
+        }
+    }
+
+    impl Receive for MockReceive {
+        fn is_lapped(&self) -> bool {
+            let lapped = self.interrupt.get();
+            self.write(self.between.replace(0));
+            lapped
+        }
+        fn clear_lapped(&mut self) {
+            self.interrupt.set(false);
+        }
+        fn position(&self) -> usize {
+            self.position.get()
+        }
+    }
+
+    #[test]
+    fn no_lap() {
+        let mut rx = MockReceive::default();
+        rx.write(4);
+        assert_eq!(observe(&mut rx, 0), (4, false));
+        rx.write(6);
+        assert_eq!(observe(&mut rx, 4), (10, false));
+        assert_eq!(written_since(4, 10, false, 4, SIZE), Some(6));
+    }
+
+    #[test]
+    fn wrap_is_one_lap() {
+        let mut rx = MockReceive::default();
+        rx.write(12);
+        assert_eq!(observe(&mut rx, 0), (12, false));
+        rx.write(7);
+        assert_eq!(observe(&mut rx, 12), (3, true));
+        assert!(!rx.interrupt.get());
+        assert_eq!(written_since(12, 3, true, 0, SIZE), Some(7));
+        // The lap was counted once
+        rx.write(2);
+        assert_eq!(observe(&mut rx, 3), (5, false));
+        assert_eq!(written_since(3, 5, false, 0, SIZE), Some(2));
+    }
+
+    #[test]
+    fn full_pass_outlives_completion_flag() {
+        let mut rx = MockReceive::default();
+        rx.write(4);
+        assert_eq!(observe(&mut rx, 0), (4, false));
+        // A whole pass, back to the last position. The next sample after the end of
+        // the pass cleared the completion flag, but the interrupt flag stays.
+        rx.write(SIZE);
+        assert!(!rx.complete.get());
+        assert_eq!(observe(&mut rx, 4), (4, true));
+        assert_eq!(written_since(4, 4, true, 0, SIZE), None);
+        // ...and more than a whole pass
+        rx.write(SIZE + 5);
+        assert_eq!(observe(&mut rx, 4), (9, true));
+        assert_eq!(written_since(4, 9, true, 0, SIZE), None);
+    }
+
+    #[test]
+    fn lap_after_flag_sampled() {
+        let mut rx = MockReceive::default();
+        rx.write(14);
+        assert_eq!(observe(&mut rx, 0), (14, false));
+        // The pass ends between sampling the flag and the position
+        rx.between.set(5);
+        assert_eq!(observe(&mut rx, 14), (3, false));
+        assert_eq!(written_since(14, 3, false, 0, SIZE), Some(5));
+        // The flag was cleared with the lap, so it doesn't count again
+        assert!(!rx.interrupt.get());
+        rx.write(1);
+        assert_eq!(observe(&mut rx, 3), (4, false));
+        assert_eq!(written_since(3, 4, false, 0, SIZE), Some(1));
+    }
+}
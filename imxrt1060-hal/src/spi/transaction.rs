@@ -0,0 +1,291 @@
+//! Transactions that hold the chip select across frames
+
+use super::{chip_select::tcr_pcs, mode, BitOrder, Error, Pcs, RETRIES, SPI};
+use crate::dma;
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use embedded_hal::spi::Mode;
+
+/// Returns the TCR value that starts a continuous transfer of `frame_bits` frames
+///
+/// The chip select asserts for the first frame, and stays asserted.
+pub(super) fn tcr_begin(tcr: u32, frame_bits: u32) -> u32 {
+    use ral::lpspi::TCR::{CONT, CONTC, FRAMESZ};
+    (tcr & !(CONTC::mask | FRAMESZ::mask)) | CONT::mask | (frame_bits - 1) << FRAMESZ::offset
+}
+
+/// Returns the TCR value that ends a continuous transfer
+///
+/// The chip select deasserts after the last frame.
+pub(super) fn tcr_end(tcr: u32) -> u32 {
+    use ral::lpspi::TCR::{CONT, CONTC};
+    tcr & !(CONT::mask | CONTC::mask)
+}
+
+/// Returns the TCR value for `frame_bits` frames, or `None` if the frame size is
+/// already `frame_bits`
+///
+/// Every TCR write is a new command. During a continuous transfer, the new command
+/// continues the transfer, so the chip select stays asserted.
+///
+/// The driver never swaps bytes, so each FIFO word holds the frame in its least
+/// significant bits.
+pub(super) fn tcr_frame_size(tcr: u32, frame_bits: u32) -> Option<u32> {
+    use ral::lpspi::TCR::{BYSW, CONT, CONTC, FRAMESZ};
+    let framesz = (frame_bits - 1) << FRAMESZ::offset;
+    if tcr & (FRAMESZ::mask | BYSW::mask) == framesz {
+        return None;
+    }
+    let contc = if tcr & CONT::mask != 0 {
+        CONTC::mask
+    } else {
+        0
+    };
+    Some((tcr & !(CONTC::mask | FRAMESZ::mask | BYSW::mask)) | framesz | contc)
+}
+
+/// Begin a continuous transfer of bytes on `spi`
+pub(super) fn begin<M: Unsigned>(spi: &SPI<M>) {
+    let tcr = ral::read_reg!(ral::lpspi, spi.reg, TCR);
+    ral::write_reg!(ral::lpspi, spi.reg, TCR, tcr_begin(tcr, 8));
+}
+
+/// End the continuous transfer on `spi`
+pub(super) fn end<M: Unsigned>(spi: &SPI<M>) {
+    let tcr = ral::read_reg!(ral::lpspi, spi.reg, TCR);
+    ral::write_reg!(ral::lpspi, spi.reg, TCR, tcr_end(tcr));
+}
+
+/// The settings of one device on a shared SPI bus
+///
+/// See [`SPI::device_transaction()`](struct.SPI.html#method.device_transaction).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    /// The device's chip select
+    pub pcs: Pcs,
+    /// The device's clock mode
+    pub mode: Mode,
+    /// The device's bit order
+    pub bit_order: BitOrder,
+}
+
+/// Returns the TCR value that talks to `device`
+pub(super) fn tcr_device(tcr: u32, device: &Device) -> u32 {
+    mode::tcr_bit_order(
+        mode::tcr_mode(tcr_pcs(tcr, device.pcs), device.mode),
+        device.bit_order,
+    )
+}
+
+/// A SPI bus with its chip select asserted
+///
+/// See [`SPI::transaction()`](struct.SPI.html#method.transaction).
+pub struct Transaction<'a, M> {
+    spi: &'a mut SPI<M>,
+}
+
+impl<'a, M> Transaction<'a, M>
+where
+    M: Unsigned,
+{
+    /// Send `word`, and return the word that the device sent back
+    fn exchange(&mut self, word: u8) -> Result<u8, Error> {
+        use ral::lpspi::SR::*;
+        self.spi.wait(|sr| sr & TDF::mask != 0)?;
+        ral::write_reg!(ral::lpspi, self.spi.reg, TDR, u32::from(word));
+        for _ in 0..RETRIES {
+            self.spi.check_errors()?;
+            if ral::read_reg!(ral::lpspi, self.spi.reg, RSR, RXEMPTY == RXEMPTY_0) {
+                return Ok(ral::read_reg!(ral::lpspi, self.spi.reg, RDR) as u8);
+            }
+        }
+        Err(Error::WaitTimeout)
+    }
+
+    /// Send `words`, and discard the words that the device sends back
+    pub fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        for &word in words {
+            self.exchange(word)?;
+        }
+        Ok(())
+    }
+
+    /// Send `words`, and replace each with the word that the device sends back
+    pub fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(*word)?;
+        }
+        Ok(words)
+    }
+
+    /// Fill `words` with the words that the device sends, while sending zeros
+    pub fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        for word in words.iter_mut() {
+            *word = self.exchange(0)?;
+        }
+        Ok(())
+    }
+}
+
+impl<M> SPI<M>
+where
+    M: Unsigned,
+{
+    /// Hold the chip select asserted while `f` transfers bytes
+    ///
+    /// `f` sends and receives with the [`Transaction`](struct.Transaction.html). The chip
+    /// select asserts for the first byte, and stays asserted between bytes. Once `f`
+    /// returns, the chip select deasserts after the last byte, even if `f` returns an error.
+    /// The transaction uses the [active chip select](#method.set_active_cs).
+    ///
+    /// For a DMA transfer in a transaction, see the `transaction()` method on a SPI
+    /// [`Peripheral`](../dma/struct.Peripheral.html#method.transaction).
+    ///
+    /// # Example
+    ///
+    /// Read the JEDEC ID of a W25Q flash on PCS0. The flash needs its chip select held
+    /// across the command, and the three ID bytes.
+    ///
+    /// ```no_run
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let lpspi_clock = imxrt1060_hal::ccm::spi::lpspi_clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::spi::ClockSelect::Pll2,
+    ///     imxrt1060_hal::ccm::spi::PrescalarSelect::LPSPI_PODF_5,
+    /// );
+    /// let (_, _, _, builder) = peripherals.spi.clock(&mut peripherals.ccm.handle, lpspi_clock);
+    /// let mut flash = builder.build_with_pcs0(
+    ///     peripherals.iomuxc.b0.p02,
+    ///     peripherals.iomuxc.b0.p01,
+    ///     peripherals.iomuxc.b0.p03,
+    ///     peripherals.iomuxc.b0.p00,
+    /// );
+    ///
+    /// let mut id = [0; 3];
+    /// flash
+    ///     .transaction(|bus| {
+    ///         bus.write(&[0x9F])?;
+    ///         bus.read(&mut id)
+    ///     })
+    ///     .unwrap();
+    /// // Winbond, then the memory type and capacity
+    /// assert_eq!(id[0], 0xEF);
+    /// ```
+    pub fn transaction<F, R>(&mut self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Transaction<'_, M>) -> Result<R, Error>,
+    {
+        begin(self);
+        let result = f(&mut Transaction { spi: self });
+        end(self);
+        result
+    }
+
+    /// Switch to `device`, then hold its chip select asserted while `f` transfers bytes
+    ///
+    /// Before the transaction, `device_transaction()` waits for the SPI to go idle, and
+    /// applies the device's chip select, mode, and bit order. Keep a `Device` for each device
+    /// on the bus, and the SPI switches between them. Returns
+    /// [`Error::WaitTimeout`](enum.Error.html#variant.WaitTimeout) if the SPI doesn't go idle.
+    /// Otherwise, it's the same as [`transaction()`](#method.transaction).
+    ///
+    /// # Example
+    ///
+    /// A mode 0 flash, a mode 3 sensor, and an LSB-first shift register share one bus.
+    ///
+    /// ```no_run
+    /// use embedded_hal::spi::{MODE_0, MODE_3};
+    /// use imxrt1060_hal::spi::{BitOrder, Device, Pcs};
+    /// # fn devices<M: imxrt1060_hal::iomuxc::consts::Unsigned>(spi: &mut imxrt1060_hal::spi::SPI<M>) {
+    ///
+    /// const FLASH: Device = Device { pcs: Pcs::Pcs0, mode: MODE_0, bit_order: BitOrder::MsbFirst };
+    /// const SENSOR: Device = Device { pcs: Pcs::Pcs1, mode: MODE_3, bit_order: BitOrder::MsbFirst };
+    /// const LEDS: Device = Device { pcs: Pcs::Pcs2, mode: MODE_0, bit_order: BitOrder::LsbFirst };
+    ///
+    /// let mut status = [0x05, 0];
+    /// spi.device_transaction(&FLASH, |bus| bus.transfer(&mut status).map(|_| ())).unwrap();
+    ///
+    /// let mut whoami = [0x8F, 0];
+    /// spi.device_transaction(&SENSOR, |bus| bus.transfer(&mut whoami).map(|_| ())).unwrap();
+    ///
+    /// spi.device_transaction(&LEDS, |bus| bus.write(&[0b0000_0101])).unwrap();
+    /// # }
+    /// ```
+    pub fn device_transaction<F, R>(&mut self, device: &Device, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut Transaction<'_, M>) -> Result<R, Error>,
+    {
+        self.update_tcr(|tcr| tcr_device(tcr, device))
+            .map_err(|_| Error::WaitTimeout)?;
+        self.transaction(f)
+    }
+}
+
+impl<M, E, S, D> dma::Peripheral<SPI<M>, E, S, D>
+where
+    M: Unsigned,
+{
+    /// Hold the chip select asserted while `f` runs DMA transfers
+    ///
+    /// This is the DMA version of [`SPI::transaction()`](../spi/struct.SPI.html#method.transaction).
+    /// The chip select deasserts after the last frame that `f` sent, so wait for each transfer
+    /// to complete before `f` returns.
+    pub fn transaction<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        begin(self.peripheral());
+        let result = f(self);
+        end(self.peripheral());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tcr_begin, tcr_device, tcr_end, tcr_frame_size, BitOrder, Device, Pcs};
+
+    const CONT: u32 = 1 << 21;
+    const CONTC: u32 = 1 << 20;
+
+    #[test]
+    fn tcr_sequence() {
+        // Mode 3 on PCS1, with 16-bit frames from a previous transfer
+        let idle = 0b11 << 30 | 1 << 24 | 15;
+
+        let tcr = tcr_begin(idle, 8);
+        assert_eq!(tcr, 0b11 << 30 | 1 << 24 | CONT | 7);
+        // Bytes don't write TCR again
+        assert_eq!(tcr_frame_size(tcr, 8), None);
+        // A DMA transfer of u16s continues the transfer
+        let wide = tcr_frame_size(tcr, 16).unwrap();
+        assert_eq!(wide, 0b11 << 30 | 1 << 24 | CONT | CONTC | 15);
+        assert_eq!(tcr_frame_size(wide, 16), None);
+
+        let ended = tcr_end(wide);
+        assert_eq!(ended, 0b11 << 30 | 1 << 24 | 15);
+        // A new transaction starts a new command
+        assert_eq!(tcr_begin(ended | CONTC, 8) & (CONT | CONTC), CONT);
+    }
+
+    #[test]
+    fn device_settings() {
+        let sensor = Device {
+            pcs: Pcs::Pcs1,
+            mode: embedded_hal::spi::MODE_3,
+            bit_order: BitOrder::LsbFirst,
+        };
+        // From the default mode 0, on PCS0, with 8-bit frames
+        assert_eq!(tcr_device(7, &sensor), 0b11 << 30 | 1 << 24 | 1 << 23 | 7);
+    }
+
+    #[test]
+    fn frame_size_outside_transactions() {
+        assert_eq!(tcr_frame_size(7, 16), Some(15));
+        assert_eq!(tcr_frame_size(15, 8), Some(7));
+        assert_eq!(tcr_frame_size(1 << 31 | 7, 32), Some(1 << 31 | 31));
+        assert_eq!(tcr_frame_size(23, 24), None);
+        // Byte swaps are off
+        assert_eq!(tcr_frame_size(1 << 22 | 23, 24), Some(23));
+    }
+}
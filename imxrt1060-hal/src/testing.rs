@@ -0,0 +1,50 @@
+//! Helpers for the host-side unit tests
+
+use core::fmt::Debug;
+
+/// The most events that an [`EventLog`] holds
+const CAPACITY: usize = 32;
+
+/// The events that a fake peripheral saw, in order
+///
+/// The crate is `no_std`, so the log is a fixed array, not a `Vec`.
+pub(crate) struct EventLog<E> {
+    events: [Option<E>; CAPACITY],
+    len: usize,
+}
+
+impl<E: Copy> Default for EventLog<E> {
+    fn default() -> Self {
+        EventLog {
+            events: [None; CAPACITY],
+            len: 0,
+        }
+    }
+}
+
+impl<E: Copy + PartialEq + Debug> EventLog<E> {
+    /// Record `event`
+    pub(crate) fn push(&mut self, event: E) {
+        assert!(self.len < CAPACITY, "too many events");
+        self.events[self.len] = Some(event);
+        self.len += 1;
+    }
+
+    /// Returns the recorded events
+    pub(crate) fn events(&self) -> impl Iterator<Item = E> + '_ {
+        self.events[..self.len].iter().flatten().copied()
+    }
+
+    /// Assert that the log holds `expected`, and nothing else
+    pub(crate) fn assert(&self, expected: &[E]) {
+        for (idx, (event, expected)) in self.events().zip(expected).enumerate() {
+            assert_eq!(event, *expected, "event {}", idx);
+        }
+        assert_eq!(self.len, expected.len(), "{:?}", &self.events[..self.len]);
+    }
+
+    /// Forget the recorded events
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+}
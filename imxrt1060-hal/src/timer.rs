@@ -0,0 +1,37 @@
+//! Timer traits that the timer drivers share
+//!
+//! The [`pit`](../pit/index.html) channels and the [`gpt`](../gpt/index.html) timers
+//! implement these traits, so that drivers can take any of them.
+
+use core::time::Duration;
+
+/// A one-shot timer
+///
+/// A [`ReceiveTimer`](../uart/struct.ReceiveTimer.html) uses a one-shot timer to measure
+/// the silence after the last received byte. The PIT channels, and a GPT
+/// [`count_down()`](../gpt/struct.GPT.html#method.count_down) adapter, implement
+/// `OneShot`.
+pub trait OneShot {
+    /// Start the timer, so that it expires after `duration`
+    ///
+    /// If the timer is running, it restarts.
+    fn start(&mut self, duration: Duration);
+    /// Stop the timer, and discard an expiry that hasn't been observed
+    fn cancel(&mut self);
+    /// Returns `true` if the timer expired, and clears the expiry
+    ///
+    /// The timer doesn't restart on its own.
+    fn is_expired(&mut self) -> bool;
+}
+
+impl<T: OneShot + ?Sized> OneShot for &mut T {
+    fn start(&mut self, duration: Duration) {
+        T::start(self, duration)
+    }
+    fn cancel(&mut self) {
+        T::cancel(self)
+    }
+    fn is_expired(&mut self) -> bool {
+        T::is_expired(self)
+    }
+}
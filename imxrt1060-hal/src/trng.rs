@@ -270,7 +270,6 @@ impl TRNG {
     ///
     /// This should preserve any previously set retry count and sample mode.
     pub fn disable(self, ccm: &mut ccm::Handle) -> Unclocked {
-        let (ccm, _) = ccm.raw();
         modify_reg!(trng, self.reg, MCTL, PRGM: 1);
         while read_reg!(trng, self.reg, MCTL, TSTOP_OK) == 0 {
             #[allow(deprecated)]
@@ -282,7 +281,7 @@ impl TRNG {
         let mut unclocked = Unclocked::new(self.reg);
         unclocked.sample_mode = SampleMode::try_from_reg(sample_mode).unwrap_or_default();
         unclocked.retry_count = retry_count.max(1); // RTY_CT is 4 bits, 0 is invalid
-        modify_reg!(ral::ccm, ccm, CCGR6, CG6: 0); // disable trng clock
+        ccm.gate::<ccm::clock_gate::Trng>(ccm::GateSetting::Off);
         unclocked
     }
 
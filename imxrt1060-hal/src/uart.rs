@@ -2,7 +2,9 @@
 //!
 //! The UART module provides a serial peripheral that implements
 //! the `embedded_hal::serial` traits. The peripheral is sufficient
-//! for implementing basic serial communications.
+//! for implementing basic serial communications. With the `"embedded-io"`
+//! feature, a UART and its halves also implement the blocking `embedded_io`
+//! traits.
 //!
 //! UARTs may also be used in bi-directional DMA transfers.
 //!
@@ -32,8 +34,8 @@
 //! uart.set_tx_fifo(core::num::NonZeroU8::new(3));
 //! uart.set_rx_fifo(true);
 //! uart.set_parity(Some(imxrt1060_hal::uart::Parity::Even));
-//! uart.set_rx_inversion(true);
-//! uart.set_tx_inversion(false);
+//! uart.set_rx_inverted(true);
+//! uart.set_tx_inverted(false);
 //!
 //! uart.write(0xDE).unwrap();
 //! let byte = uart.read().unwrap();
@@ -41,30 +43,220 @@
 //! // Split the peripheral into transfer and receive halves
 //! let (tx, rx) = uart.split();
 //! ```
+//!
+//! # Split UARTs
+//!
+//! A logging task may own the TX half, while a command parser owns the RX
+//! half. Each half implements its `embedded_hal::serial` trait, and is a DMA
+//! destination, or source. This UART streams logs over DMA, and reads commands
+//! with the CPU.
+//!
+//! ```no_run
+//! use embedded_hal::serial::Read;
+//! use imxrt1060_hal::dma::{self, Buffer, Circular};
+//!
+//! #[repr(align(512))]
+//! struct Align(Buffer<[u8; 512]>);
+//! static LOG: Align = Align(Buffer::new([0; 512]));
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let uarts = peripherals.uart.clock(
+//!     &mut peripherals.ccm.handle,
+//!     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+//!     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+//! );
+//! let uart = uarts
+//!     .uart2
+//!     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+//!     .unwrap();
+//! let (tx, mut rx) = uart.split();
+//!
+//! let mut channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+//! let mut log: dma::Peripheral<_, u8, Circular<u8>> =
+//!     dma::transfer_u8(tx, channels[7].take().unwrap());
+//! log.start_streaming_tx(Circular::new(&LOG.0).unwrap()).unwrap();
+//!
+//! // In the logging task...
+//! log.write(b"ready\r\n");
+//!
+//! // In the parser task...
+//! if let Ok(command) = rx.read() {
+//!     // ...
+//! }
+//! ```
+//!
+//! # High baud rates
+//!
+//! The 24MHz oscillator limits the baud rate, and its accuracy. For faster rates,
+//! enable PLL3, and clock the UARTs from its 80MHz output. 4Mbaud divides
+//! the 80MHz clock without error. A UART runs at most a quarter of its root clock;
+//! [`UartClock`](../ccm/uart/struct.UartClock.html) lists the limits.
+//!
+//! ```no_run
+//! use imxrt1060_hal::ccm;
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//!
+//! peripherals
+//!     .ccm
+//!     .pll3
+//!     .enable(&mut peripherals.ccm.handle)
+//!     .unwrap();
+//!
+//! let uarts = peripherals.uart.clock(
+//!     &mut peripherals.ccm.handle,
+//!     ccm::uart::ClockSelect::PLL3,
+//!     ccm::uart::PrescalarSelect::DIVIDE_1,
+//! );
+//!
+//! let uart = uarts
+//!     .uart2
+//!     .init(
+//!         peripherals.iomuxc.ad_b1.p02,
+//!         peripherals.iomuxc.ad_b1.p03,
+//!         4_000_000,
+//!     )
+//!     .unwrap();
+//! ```
+//!
+//! Other rates don't divide the clock. [`set_baud()`](struct.UART.html#method.set_baud)
+//! returns the rate that it achieved, so you can reject a rate that's too far off.
+//! Most receivers tolerate about 2% of error.
+//!
+//! ```no_run
+//! # use imxrt1060_hal::ccm;
+//! # let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! # let uarts = peripherals.uart.clock(
+//! #     &mut peripherals.ccm.handle,
+//! #     ccm::uart::ClockSelect::PLL3,
+//! #     ccm::uart::PrescalarSelect::DIVIDE_1,
+//! # );
+//! # let mut uart = uarts
+//! #     .uart2
+//! #     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 4_000_000)
+//! #     .unwrap();
+//! // 80MHz / (27 * 2) = 1_481_481 baud
+//! let baud = uart.set_baud(1_500_000).unwrap();
+//! assert_eq!(baud.achieved_hz, 1_481_481);
+//! if baud.error_ppm.abs() > 20_000 {
+//!     // More than 2% off; pick another rate, or another clock
+//! }
+//! ```
+//!
+//! # Frame formats
+//!
+//! UARTs start as 8N1. Select the parity, data bits, and stop bits for other
+//! formats. This 7E1 loopback expects a wire between AD_B1_02 (TX) and AD_B1_03
+//! (RX).
+//!
+//! ```no_run
+//! use embedded_hal::serial::{Read, Write};
+//! use imxrt1060_hal::uart::{DataBits, Parity, StopBits};
+//!
+//! let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+//! let uarts = peripherals.uart.clock(
+//!     &mut peripherals.ccm.handle,
+//!     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+//!     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+//! );
+//! let mut uart = uarts
+//!     .uart2
+//!     .init(
+//!         peripherals.iomuxc.ad_b1.p02,
+//!         peripherals.iomuxc.ad_b1.p03,
+//!         9600,
+//!     )
+//!     .unwrap();
+//! uart.set_data_bits(DataBits::Seven);
+//! uart.set_parity(Some(Parity::Even));
+//! uart.set_stop_bits(StopBits::One);
+//!
+//! nb::block!(uart.write(b'Z')).unwrap();
+//! assert_eq!(nb::block!(uart.read()).unwrap(), b'Z');
+//! ```
+//!
+//! Nine data bits move through [`UART::write_word9()`](struct.UART.html#method.write_word9)
+//! and [`UART::read_word9()`](struct.UART.html#method.read_word9). On a multidrop
+//! bus, the ninth bit marks an address.
+
+mod blocking;
+mod buffered;
+mod dma_tx;
+#[cfg(feature = "embedded-io")]
+mod io;
+mod loopback;
+mod multidrop;
+mod overrun;
+mod rs485;
+mod timeout;
+
+pub use blocking::BlockingError;
+pub use buffered::Buffered;
+pub use dma_tx::{DmaWriteError, DMA_STAGING_LEN};
+pub use loopback::{Direction, SelfTestError, SingleWire, WrongDirection};
+pub use multidrop::{MatchConfig, MatchMode, Matched};
+pub use overrun::{OverrunConfig, OverrunRecovery, ReceiveEvent};
+pub use rs485::{Rs485, Rs485Config};
+pub use timeout::ReceiveTimer;
+
+/// Moved to [`timer::OneShot`](../timer/trait.OneShot.html)
+pub use crate::timer::OneShot;
 
 use crate::ccm;
 use crate::iomuxc::consts::{Unsigned, U1, U2, U3, U4, U5, U6, U7, U8};
 use crate::iomuxc::uart;
 use crate::ral;
 use core::marker::PhantomData;
+use core::sync::atomic::AtomicUsize;
 
 /// An uninitialized UART peripheral
 ///
 /// Call `init()` to initialize the peripheral
 pub struct Uninit<M: Unsigned> {
-    effective_clock: ccm::Frequency,
+    clock: ccm::uart::UartClock,
     _module: PhantomData<M>,
     reg: ral::lpuart::Instance,
 }
 
 impl<M: Unsigned> Uninit<M> {
-    fn new(effective_clock: ccm::Frequency, reg: ral::lpuart::Instance) -> Self {
+    fn new(clock: ccm::uart::UartClock, reg: ral::lpuart::Instance) -> Self {
         Uninit {
-            effective_clock,
+            clock,
             _module: PhantomData,
             reg,
         }
     }
+
+    /// Returns the root clock that the UART will use
+    ///
+    /// Check [`max_baud()`](../ccm/uart/struct.UartClock.html#method.max_baud) before
+    /// you choose a baud rate.
+    pub fn clock(&self) -> &ccm::uart::UartClock {
+        &self.clock
+    }
+}
+
+/// A UART with a gated clock
+///
+/// Get a `Disabled` UART from [`UART::disable()`](struct.UART.html#method.disable). Use
+/// `enable()` to clock the UART again.
+pub struct Disabled<M: Unsigned> {
+    clock: ccm::uart::UartClock,
+    _module: PhantomData<M>,
+    reg: ral::lpuart::Instance,
+}
+
+impl<M: Unsigned> Disabled<M> {
+    /// Ungate the UART's clock, and return the uninitialized UART
+    ///
+    /// The UART keeps the root clock that it had before it was disabled.
+    pub fn enable(self, handle: &mut ccm::Handle) -> Uninit<M> {
+        handle.set_gates(
+            ccm::clock_gate::LPUART[M::USIZE - 1],
+            ccm::GateSetting::RunAndWait,
+        );
+        Uninit::new(self.clock, self.reg)
+    }
 }
 
 /// All available UARTs
@@ -80,6 +272,22 @@ pub struct UARTs {
     pub uart6: Uninit<U6>,
     pub uart7: Uninit<U7>,
     pub uart8: Uninit<U8>,
+    clock: Option<ccm::uart::UartClock>,
+}
+
+impl UARTs {
+    /// Returns the UART root clock, or `None` if it was taken
+    pub fn clock(&self) -> Option<&ccm::uart::UartClock> {
+        self.clock.as_ref()
+    }
+
+    /// Take the UART root clock
+    ///
+    /// Keep the clock to [`reconfigure()`](../ccm/uart/struct.UartClock.html#method.reconfigure)
+    /// it later. Returns `None` if the clock was already taken.
+    pub fn take_clock(&mut self) -> Option<ccm::uart::UartClock> {
+        self.clock.take()
+    }
 }
 
 /// Unclocked UART peripherals
@@ -100,115 +308,27 @@ pub struct Unclocked {
 }
 impl Unclocked {
     /// Enable all clocks for the UART peripherals. Returns a collection
-    /// of UART peripherals.
+    /// of UART peripherals, and the root clock that they share.
     pub fn clock(
         self,
         ccm: &mut ccm::Handle,
         clock_select: ccm::uart::ClockSelect,
         prescalar: ccm::uart::PrescalarSelect,
     ) -> UARTs {
-        let (ccm, _) = ccm.raw();
-
-        //
-        // See table 13-4 for clock gating registers
-        //
-
-        // -----------------------------------------
-        // Disable clocks before modifying selection
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR5,
-            CG12: 0,    // UART1
-            CG13: 0     // UART7
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR0,
-            CG14: 0,    // UART2
-            CG6: 0      // UART3
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR1,
-            CG12: 0     // UART4
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR3,
-            CG1: 0,     // UART5
-            CG3: 0      // UART6
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR6,
-            CG7: 0      // UART8
-        );
-        // -----------------------------------------
-
-        // -------------------------
-        // Select clocks & prescalar
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CSCDR1,
-            UART_CLK_SEL: (clock_select as u32),
-            UART_CLK_PODF: (prescalar as u32)
-        );
-        // -------------------------
-
-        // -------------
-        // Enable clocks
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR5,
-            CG12: 0b11,    // UART1
-            CG13: 0b11     // UART7
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR0,
-            CG14: 0b11,    // UART2
-            CG6: 0b11      // UART3
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR1,
-            CG12: 0b11     // UART4
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR3,
-            CG1: 0b11,     // UART5
-            CG3: 0b11      // UART6
-        );
-        ral::modify_reg!(
-            ral::ccm,
-            ccm,
-            CCGR6,
-            CG7: 0b11      // UART8
-        );
-
-        // -------------
-
-        let effective_clock = ccm::Frequency::from(clock_select) / ccm::Divider::from(prescalar);
+        // Unclocked is a singleton, and clock() consumes it, so this counts the
+        // clones of one root clock.
+        static USERS: AtomicUsize = AtomicUsize::new(0);
+        let clock = ccm::uart::UartClock::configure(ccm, &USERS, clock_select, prescalar);
         UARTs {
-            uart1: Uninit::new(effective_clock, self.uart1),
-            uart2: Uninit::new(effective_clock, self.uart2),
-            uart3: Uninit::new(effective_clock, self.uart3),
-            uart4: Uninit::new(effective_clock, self.uart4),
-            uart5: Uninit::new(effective_clock, self.uart5),
-            uart6: Uninit::new(effective_clock, self.uart6),
-            uart7: Uninit::new(effective_clock, self.uart7),
-            uart8: Uninit::new(effective_clock, self.uart8),
+            uart1: Uninit::new(clock.clone(), self.uart1),
+            uart2: Uninit::new(clock.clone(), self.uart2),
+            uart3: Uninit::new(clock.clone(), self.uart3),
+            uart4: Uninit::new(clock.clone(), self.uart4),
+            uart5: Uninit::new(clock.clone(), self.uart5),
+            uart6: Uninit::new(clock.clone(), self.uart6),
+            uart7: Uninit::new(clock.clone(), self.uart7),
+            uart8: Uninit::new(clock.clone(), self.uart8),
+            clock: Some(clock),
         }
     }
 }
@@ -256,7 +376,83 @@ where
     {
         crate::iomuxc::uart::prepare(&mut tx);
         crate::iomuxc::uart::prepare(&mut rx);
-        UART::start(self.reg, self.effective_clock, baud)
+        UART::start(self.reg, self.clock, baud)
+    }
+
+    /// Initializes a UART on the `tx` and `rx` pins, with optional `cts` and
+    /// `rts` flow control pins. Specify the initial baud rate of the bus with
+    /// `baud`.
+    ///
+    /// The UART enables the flow control of each pin that you supply. See
+    /// [`set_flow_control()`](struct.UART.html#method.set_flow_control) for
+    /// the behaviors. A `None` pin still needs a pad type, like
+    /// `None::<AD_B1_01>`.
+    ///
+    /// # Example
+    ///
+    /// LPUART2 at 921600 baud, with flow control, against a USB-serial adapter.
+    /// Connect the adapter's RTS to AD_B1_00 (CTS), and its CTS to AD_B1_01 (RTS).
+    ///
+    /// ```no_run
+    /// use embedded_hal::serial::{Read, Write};
+    /// use imxrt1060_hal::ccm;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// peripherals
+    ///     .ccm
+    ///     .pll3
+    ///     .enable(&mut peripherals.ccm.handle)
+    ///     .unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     ccm::uart::ClockSelect::PLL3,
+    ///     ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init_with_flow_control(
+    ///         peripherals.iomuxc.ad_b1.p02,
+    ///         peripherals.iomuxc.ad_b1.p03,
+    ///         Some(peripherals.iomuxc.ad_b1.p00),
+    ///         Some(peripherals.iomuxc.ad_b1.p01),
+    ///         921_600,
+    ///     )
+    ///     .unwrap();
+    /// uart.set_rx_fifo(true);
+    /// uart.set_tx_fifo(core::num::NonZeroU8::new(4));
+    /// uart.set_rts_watermark(2);
+    ///
+    /// // Echo. While the adapter deasserts CTS, writes return WouldBlock.
+    /// loop {
+    ///     if let Ok(byte) = uart.read() {
+    ///         nb::block!(uart.write(byte)).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn init_with_flow_control<TX, RX, CTS, RTS>(
+        self,
+        tx: TX,
+        rx: RX,
+        cts: Option<CTS>,
+        rts: Option<RTS>,
+        baud: u32,
+    ) -> Result<UART<M>, ccm::uart::TimingsError>
+    where
+        TX: uart::Pin<Direction = uart::TX, Module = M>,
+        RX: uart::Pin<Direction = uart::RX, Module = M>,
+        CTS: uart::Pin<Direction = uart::CTS, Module = M>,
+        RTS: uart::Pin<Direction = uart::RTS, Module = M>,
+    {
+        let flow_control = FlowControl::from_pins(cts.is_some(), rts.is_some());
+        if let Some(mut cts) = cts {
+            crate::iomuxc::uart::prepare(&mut cts);
+        }
+        if let Some(mut rts) = rts {
+            crate::iomuxc::uart::prepare(&mut rts);
+        }
+        let mut uart = self.init(tx, rx, baud)?;
+        uart.set_flow_control(flow_control);
+        Ok(uart)
     }
 }
 
@@ -265,8 +461,34 @@ where
 /// Call `read()` or `write()` to transmit bytes.
 pub struct UART<M: Unsigned> {
     reg: ral::lpuart::Instance,
-    effective_clock: ccm::Frequency,
+    clock: ccm::uart::UartClock,
     _module: PhantomData<M>,
+    /// When a DMA transmit is complete
+    dma_completion: TransmitCompletion,
+    /// The number of data bits in a frame, so that receives don't read CTRL and BAUD
+    data_bits: DataBits,
+}
+
+/// Keeps the baud rate when the UART root clock changes
+impl<M: Unsigned> ccm::ClockListenerMut for UART<M> {
+    fn clocks_changed(&mut self, change: &ccm::ClocksChanged) {
+        let clock = change.new.uart;
+        if clock == self.clock.frequency || clock.0 == 0 {
+            return;
+        }
+        let baud = self.achieved_baud();
+        self.clock.frequency = clock;
+        if let Some(baud) = baud {
+            if self.set_baud(baud).is_err() {
+                log::warn!(
+                    "UART{} can't run at {} baud from {:?}",
+                    M::USIZE,
+                    baud,
+                    clock
+                );
+            }
+        }
+    }
 }
 
 /// A UART transfer half
@@ -283,6 +505,18 @@ pub struct Tx<M: Unsigned>(UART<M>);
 /// before calling [`split()`](struct.UART.html#method.split).
 pub struct Rx<M: Unsigned>(UART<M>);
 
+/// The baud rate that [`set_baud()`](struct.UART.html#method.set_baud) achieved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Baud {
+    /// The achieved baud rate, in bits per second
+    pub achieved_hz: u32,
+    /// The difference between the achieved and requested baud rates, in parts
+    /// per million of the requested rate
+    ///
+    /// Negative when the achieved rate is slower than the requested rate.
+    pub error_ppm: i32,
+}
+
 /// Parity selection
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Parity {
@@ -298,6 +532,165 @@ impl Parity {
     }
 }
 
+/// Hardware flow control selection
+///
+/// Flow control needs the UART's CTS and RTS pins. Supply the pins with
+/// [`init_with_flow_control()`](struct.Uninit.html#method.init_with_flow_control).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No flow control
+    None,
+    /// The transmitter only starts a character while CTS is asserted
+    Cts,
+    /// The receiver deasserts RTS when the RX FIFO fills to the
+    /// [RTS watermark](struct.UART.html#method.set_rts_watermark)
+    Rts,
+    /// Both CTS and RTS flow control
+    RtsCts,
+}
+
+impl FlowControl {
+    fn from_pins(cts: bool, rts: bool) -> Self {
+        match (cts, rts) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::Cts,
+            (false, true) => FlowControl::Rts,
+            (true, true) => FlowControl::RtsCts,
+        }
+    }
+
+    /// Returns MODIR's TXCTSE and RXRTSE fields
+    fn fields(self) -> (u32, u32) {
+        match self {
+            FlowControl::None => (0, 0),
+            FlowControl::Cts => (1, 0),
+            FlowControl::Rts => (0, 1),
+            FlowControl::RtsCts => (1, 1),
+        }
+    }
+}
+
+/// The number of idle characters that marks an idle line
+///
+/// See [`set_idle_detection()`](struct.UART.html#method.set_idle_detection).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)] // Easier mapping if the names are consistent
+#[repr(u32)]
+pub enum IdleLength {
+    CHARS_1 = 0,
+    CHARS_2 = 1,
+    CHARS_4 = 2,
+    CHARS_8 = 3,
+    CHARS_16 = 4,
+    CHARS_32 = 5,
+    CHARS_64 = 6,
+    CHARS_128 = 7,
+}
+
+/// The length of a transmitted break character
+///
+/// The lengths are for frames with eight data bits, and one stop bit. Each extra
+/// data, parity, or stop bit lengthens the break by one bit time. See
+/// [`send_break()`](struct.UART.html#method.send_break).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)] // Easier mapping if the names are consistent
+#[repr(u32)]
+pub enum BreakLength {
+    BITS_10 = 0,
+    BITS_13 = 1,
+}
+
+/// The number of data bits in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    /// Seven data bits (the '7' in 7E1, for example)
+    Seven,
+    /// Eight data bits
+    Eight,
+    /// Nine data bits, for address-mark multidrop buses
+    Nine,
+}
+
+impl DataBits {
+    fn count(self) -> u32 {
+        match self {
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+            DataBits::Nine => 9,
+        }
+    }
+}
+
+/// The number of stop bits in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit
+    One,
+    /// Two stop bits
+    Two,
+}
+
+/// When a DMA transmit is complete
+///
+/// See [`set_dma_completion()`](struct.UART.html#method.set_dma_completion).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransmitCompletion {
+    /// Once the DMA controller writes the last byte into the TX FIFO. The UART
+    /// is still sending the bytes in the FIFO. This is the default.
+    FifoWritten,
+    /// Once the UART sends the last byte's stop bit, and the line is idle
+    Sent,
+}
+
+/// CTRL[PE, PT, M, M7] and BAUD[M10], which describe a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameFields {
+    pe: u32,
+    pt: u32,
+    m: u32,
+    m7: u32,
+    m10: u32,
+}
+
+impl FrameFields {
+    fn new(parity: Option<Parity>, data_bits: DataBits) -> Self {
+        // Frame bits between the start and stop bits, including the parity bit
+        let bits = data_bits.count() + u32::from(parity.is_some());
+        FrameFields {
+            pe: u32::from(parity.is_some()),
+            pt: u32::from(parity.map(|p| p.bit()).unwrap_or(false)),
+            m: u32::from(bits >= 9),
+            m7: u32::from(bits == 7),
+            m10: u32::from(bits == 10),
+        }
+    }
+
+    fn parity(self) -> Option<Parity> {
+        match (self.pe, self.pt) {
+            (0, _) => None,
+            (_, 0) => Some(Parity::Even),
+            _ => Some(Parity::Odd),
+        }
+    }
+
+    fn data_bits(self) -> DataBits {
+        let bits = if self.m10 != 0 {
+            10
+        } else if self.m != 0 {
+            9
+        } else if self.m7 != 0 {
+            7
+        } else {
+            8
+        };
+        match bits - u32::from(self.pe != 0) {
+            7 => DataBits::Seven,
+            8 => DataBits::Eight,
+            _ => DataBits::Nine,
+        }
+    }
+}
+
 impl<M> UART<M>
 where
     M: Unsigned,
@@ -305,16 +698,34 @@ where
     const DMA_SOURCE_REQUEST_SIGNAL: u32 = DMA_RX_REQUEST_LOOKUP[M::USIZE - 1];
     const DMA_DESTINATION_REQUEST_SIGNAL: u32 = DMA_TX_REQUEST_LOOKUP[M::USIZE - 1];
 
+    /// Disable the UART, gate its clock, and return the disabled UART
+    ///
+    /// Words that are still in the transmit FIFO are lost, so `flush()` the UART
+    /// first. To use the UART again, `enable()` it, and `init()` it with a baud rate.
+    pub fn disable(self, handle: &mut ccm::Handle) -> Disabled<M> {
+        ral::modify_reg!(ral::lpuart, self.reg, CTRL, TE: TE_0, RE: RE_0);
+        handle.set_gates(ccm::clock_gate::LPUART[M::USIZE - 1], ccm::GateSetting::Off);
+        Disabled {
+            clock: self.clock,
+            _module: PhantomData,
+            reg: self.reg,
+        }
+    }
+
     fn start(
         reg: ral::lpuart::Instance,
-        effective_clock: ccm::Frequency,
+        clock: ccm::uart::UartClock,
         baud: u32,
     ) -> Result<Self, ccm::uart::TimingsError> {
+        ccm::clock_gate::debug_assert_clocked!(LPUART[M::USIZE - 1]);
         let mut uart = UART {
             reg,
-            effective_clock,
+            clock,
             _module: PhantomData,
+            dma_completion: TransmitCompletion::FifoWritten,
+            data_bits: DataBits::Eight,
         };
+        uart.data_bits = uart.frame().data_bits();
         uart.set_baud(baud)?;
         ral::modify_reg!(ral::lpuart, uart.reg, CTRL, TE: TE_1, RE: RE_1);
         Ok(uart)
@@ -323,12 +734,21 @@ where
     /// Split the UART peripheral into its transfer and receive half
     ///
     /// Ensure your UART peripheral is configured before calling
-    /// `split()`.
+    /// `split()`. The halves can't change the baud rate, or the frame format;
+    /// [`join()`](#method.join) them to reconfigure the UART. Each half is
+    /// `Send`, so separate tasks may own them, and each half may move into a
+    /// DMA [`Peripheral`](../dma/struct.Peripheral.html).
     pub fn split(self) -> (Tx<M>, Rx<M>) {
         let rx_half = UART {
+            // Safety: the halves share the registers. The TX half only
+            // writes DATA, and reads STAT. The RX half reads DATA, and
+            // clears STAT flags with an exclusive receiver. Both halves
+            // toggle their DMA enables in BAUD within critical sections.
             reg: unsafe { M::steal() },
-            effective_clock: self.effective_clock,
+            clock: self.clock.clone(),
             _module: self._module,
+            dma_completion: self.dma_completion,
+            data_bits: self.data_bits,
         };
         (Tx(self), Rx(rx_half))
     }
@@ -338,65 +758,213 @@ where
     /// `join()` will let you re-configure a UART peripheral if theres a need to change
     /// settings.
     pub fn join(tx: Tx<M>, _rx: Rx<M>) -> Self {
-        UART {
-            reg: tx.0.reg,
-            effective_clock: tx.0.effective_clock,
-            _module: tx.0._module,
-        }
+        tx.0
     }
 
     /// Specify parity bit settings. If there is no parity, use `None`.
     ///
+    /// The parity bit is an extra bit in the frame, so the number of
+    /// [data bits](#method.set_data_bits) stays the same. Received parity
+    /// errors show up as [`ReadErrorFlags::PARITY`](struct.ReadErrorFlags.html).
+    ///
     /// Calling this method will temporarily disable the peripheral,
     /// flusing all data from all FIFOs.
     pub fn set_parity(&mut self, parity: Option<Parity>) {
+        let data_bits = self.data_bits();
+        self.set_frame(FrameFields::new(parity, data_bits));
+    }
+
+    /// Returns the parity bit settings
+    pub fn parity(&self) -> Option<Parity> {
+        self.frame().parity()
+    }
+
+    /// Specify the number of data bits in a frame. The default is eight.
+    ///
+    /// Use [`read_word9()`](#method.read_word9) and
+    /// [`write_word9()`](#method.write_word9) to move nine-bit data. The
+    /// `embedded_hal` traits move the low eight bits.
+    ///
+    /// Calling this method will temporarily disable the peripheral,
+    /// flusing all data from all FIFOs.
+    pub fn set_data_bits(&mut self, data_bits: DataBits) {
+        let parity = self.parity();
+        self.set_frame(FrameFields::new(parity, data_bits));
+    }
+
+    /// Returns the number of data bits in a frame
+    pub fn data_bits(&self) -> DataBits {
+        self.data_bits
+    }
+
+    /// Specify the number of stop bits. The default is one.
+    ///
+    /// Calling this method will temporarily disable the peripheral,
+    /// flusing all data from all FIFOs.
+    pub fn set_stop_bits(&mut self, stop_bits: StopBits) {
+        self.while_disabled(|this| {
+            ral::modify_reg!(
+                ral::lpuart,
+                this.reg,
+                BAUD,
+                SBNS: u32::from(stop_bits == StopBits::Two)
+            );
+        });
+    }
+
+    /// Returns the number of stop bits
+    pub fn stop_bits(&self) -> StopBits {
+        if ral::read_reg!(ral::lpuart, self.reg, BAUD, SBNS == 1) {
+            StopBits::Two
+        } else {
+            StopBits::One
+        }
+    }
+
+    fn frame(&self) -> FrameFields {
+        let (pe, pt, m, m7) = ral::read_reg!(ral::lpuart, self.reg, CTRL, PE, PT, M, M7);
+        let m10 = ral::read_reg!(ral::lpuart, self.reg, BAUD, M10);
+        FrameFields { pe, pt, m, m7, m10 }
+    }
+
+    fn set_frame(&mut self, frame: FrameFields) {
         self.while_disabled(|this| {
             ral::modify_reg!(
                 ral::lpuart,
                 this.reg,
                 CTRL,
-                PE: u32::from(parity.is_some()),
-                M: u32::from(parity.is_some()),
-                PT: u32::from(parity.map(|p| p.bit()).unwrap_or(false))
+                PE: frame.pe,
+                PT: frame.pt,
+                M: frame.m,
+                M7: frame.m7
             );
+            ral::modify_reg!(ral::lpuart, this.reg, BAUD, M10: frame.m10);
         });
+        self.data_bits = frame.data_bits();
     }
 
     /// Reverse the polarity of received data, affecting all data bits, start
     /// and stop bits, and polarity bits.
     ///
-    /// The default inversion state is `false`. Note that calling this method
-    /// will temporarily disable the peripheral, flusing all data from all FIFOs.
-    pub fn set_rx_inversion(&mut self, inverted: bool) {
-        self.while_disabled(|this| {
-            ral::modify_reg!(ral::lpuart, this.reg, STAT, RXINV: u32::from(inverted));
+    /// Use an inverted receiver with an inverting transceiver, or an optocoupler,
+    /// in place of an external inverter. The default inversion state is `false`.
+    ///
+    /// The method waits until the UART sends the bytes that are already in the TX
+    /// FIFO. It then temporarily disables the UART, flushing any unread data from the
+    /// RX FIFO.
+    pub fn set_rx_inverted(&mut self, inverted: bool) {
+        let rxinv = u32::from(inverted);
+        self.while_drained(|this| {
+            ral::modify_reg!(ral::lpuart, this.reg, STAT, |stat| {
+                stat_field(stat, ral::lpuart::STAT::RXINV::mask, rxinv)
+            });
         });
     }
 
+    /// Reverse the polarity of received data
+    #[deprecated(since = "0.5.0", note = "use `set_rx_inverted()`")]
+    pub fn set_rx_inversion(&mut self, inverted: bool) {
+        self.set_rx_inverted(inverted);
+    }
+
+    /// Returns `true` if the receiver inverts received data
+    pub fn is_rx_inverted(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, STAT, RXINV == 1)
+    }
+
     /// Reverse the polarity of transferred data, affecting all data bits,
     /// start and stop bits, and polarity bits.
     ///
-    /// The default inversion state is `false`. Note that calling this method
-    /// will temporarily disable the peripheral, flusing all data from all FIFOs.
-    pub fn set_tx_inversion(&mut self, inverted: bool) {
-        self.while_disabled(|this| {
+    /// An inverted transmitter idles low. The default inversion state is `false`.
+    ///
+    /// The method waits until the UART sends the bytes that are already in the TX
+    /// FIFO, so the change never corrupts a frame. It then temporarily disables the
+    /// UART, flushing any unread data from the RX FIFO.
+    ///
+    /// # Example
+    ///
+    /// Check an inverted UART with a [loopback self-test](#method.self_test). The
+    /// inverted transmitter drives the inverted receiver, so the test passes when both
+    /// inversions apply to the whole frame.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::uart::Parity;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    ///
+    /// uart.set_parity(Some(Parity::Odd));
+    /// uart.set_tx_inverted(true);
+    /// uart.set_rx_inverted(true);
+    /// uart.set_msb_first(true);
+    /// uart.self_test().unwrap();
+    /// ```
+    pub fn set_tx_inverted(&mut self, inverted: bool) {
+        self.while_drained(|this| {
             ral::modify_reg!(ral::lpuart, this.reg, CTRL, TXINV: u32::from(inverted));
         });
     }
 
-    /// Controls the TX FIFO.
+    /// Reverse the polarity of transferred data
+    #[deprecated(since = "0.5.0", note = "use `set_tx_inverted()`")]
+    pub fn set_tx_inversion(&mut self, inverted: bool) {
+        self.set_tx_inverted(inverted);
+    }
+
+    /// Returns `true` if the transmitter inverts transferred data
+    pub fn is_tx_inverted(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, CTRL, TXINV == 1)
+    }
+
+    /// Send and receive the most significant data bit first. The default is
+    /// `false`, least significant bit first.
     ///
-    /// If size is `Some(n)`, where `n > 0`, the method will enable the TX
-    /// FIFO with a size `n`. The method returns size of the FIFO that was
-    /// set, which is based on the hardware. On an iMXRT1062, the max size
-    /// is 4.
+    /// The bit order applies to the whole data word, so it depends on the
+    /// [data bits](#method.set_data_bits): with nine data bits, bit 8 follows the
+    /// start bit. The [parity bit](#method.set_parity) is the word's most
+    /// significant bit, so it moves to the front of the frame, too. The MSB first
+    /// order applies to both the transmitter and the receiver.
     ///
-    /// If size is `None`, the method disables the TX FIFO. The return is 0.
+    /// The method waits until the UART sends the bytes that are already in the TX
+    /// FIFO. It then temporarily disables the UART, flushing any unread data from the
+    /// RX FIFO.
+    pub fn set_msb_first(&mut self, msb_first: bool) {
+        let msbf = u32::from(msb_first);
+        self.while_drained(|this| {
+            ral::modify_reg!(ral::lpuart, this.reg, STAT, |stat| {
+                stat_field(stat, ral::lpuart::STAT::MSBF::mask, msbf)
+            });
+        });
+    }
+
+    /// Returns `true` if the UART sends and receives the most significant data
+    /// bit first
+    pub fn is_msb_first(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, STAT, MSBF == 1)
+    }
+
+    /// Controls the TX FIFO.
+    ///
+    /// If size is `Some(n)`, where `n > 0`, the method will enable the TX
+    /// FIFO with a size `n`. The method returns size of the FIFO that was
+    /// set, which is based on the hardware. On an iMXRT1062, the max size
+    /// is 4.
     ///
-    /// The method temporarily disables the UART bus, flushing any data in
-    /// the *both* TX and RX FIFOs.
+    /// If size is `None`, the method disables the TX FIFO. The return is 0.
+    ///
+    /// The method waits until the UART sends the bytes that are already in the TX
+    /// FIFO. It then temporarily disables the UART bus, flushing any unread data
+    /// from the RX FIFO.
     pub fn set_tx_fifo(&mut self, size: Option<core::num::NonZeroU8>) -> u8 {
-        self.while_disabled(|this| {
+        self.while_drained(|this| {
             if let Some(requested_size) = size {
                 // Maximum TX FIFO size supported by this device
                 let max_size = 1 << ral::read_reg!(ral::lpuart, this.reg, PARAM, TXFIFO);
@@ -426,11 +994,161 @@ where
     /// Enable or disable the RX FIFO. The maximum size of the FIFO is based on
     /// the underlying hardware. An iMXRT1062's RX FIFO is 4 bytes.
     ///
+    /// Calling this method waits until the UART sends the bytes in the TX FIFO,
+    /// then temporarily disables the peripheral. Unread bytes in the RX FIFO are
+    /// flushed; read [`rx_fifo_count()`](#method.rx_fifo_count) bytes before
+    /// calling this method to keep them.
+    pub fn set_rx_fifo(&mut self, enable: bool) {
+        self.while_drained(|this| {
+            ral::modify_reg!(ral::lpuart, this.reg, FIFO, RXFE: u32::from(enable));
+        })
+    }
+
+    /// Set the TX FIFO watermark
+    ///
+    /// The transmitter asks for more data once the TX FIFO holds `watermark` bytes,
+    /// or fewer. That's when `write()` stops returning `WouldBlock`, when the TX
+    /// interrupt fires, and when the transmitter requests DMA. A higher watermark
+    /// refills the FIFO before it runs dry. The watermark is at most one less than
+    /// the TX FIFO depth; on an iMXRT1062, that's 3. Returns the watermark that
+    /// was set.
+    ///
+    /// Unlike the other configuration methods, this doesn't disable the UART.
+    pub fn set_tx_fifo_watermark(&mut self, watermark: u8) -> u8 {
+        let watermark = clamp_watermark(watermark, self.tx_fifo_depth());
+        ral::modify_reg!(ral::lpuart, self.reg, WATER, TXWATER: u32::from(watermark));
+        watermark
+    }
+
+    /// Set the RX FIFO watermark
+    ///
+    /// The receiver signals data once the RX FIFO holds more than `watermark`
+    /// bytes. That's when the receiver interrupt fires, and when the receiver
+    /// requests DMA. A higher watermark means fewer interrupts, each of which
+    /// reads more bytes. The watermark is at most one less than the RX FIFO depth;
+    /// on an iMXRT1062, that's 3. Returns the watermark that was set. Enable the
+    /// [RX FIFO](#method.set_rx_fifo) to use a non-zero watermark.
+    ///
+    /// Unlike the other configuration methods, this doesn't disable the UART.
+    ///
+    /// # Example
+    ///
+    /// Receive at 4Mbaud without overruns. Each interrupt reads three or four bytes,
+    /// instead of one. Use [idle detection](#method.set_idle_detection) to read the
+    /// bytes that are left below the watermark at the end of a message.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::ccm;
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// peripherals.ccm.pll3.enable(&mut peripherals.ccm.handle).unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     ccm::uart::ClockSelect::PLL3,
+    ///     ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 4_000_000)
+    ///     .unwrap();
+    /// uart.set_rx_fifo(true);
+    /// uart.set_rx_fifo_watermark(2);
+    ///
+    /// let mut buffer = [0; 512];
+    /// let mut len = 0;
+    /// while len < buffer.len() {
+    ///     // Each call drains the FIFO
+    ///     match uart.read_fifo(&mut buffer[len..]) {
+    ///         Ok(count) => len += count,
+    ///         Err((count, err)) => panic!("{:?} after {} bytes", err, len + count),
+    ///     }
+    /// }
+    /// ```
+    pub fn set_rx_fifo_watermark(&mut self, watermark: u8) -> u8 {
+        let watermark = clamp_watermark(watermark, self.rx_fifo_depth());
+        ral::modify_reg!(ral::lpuart, self.reg, WATER, RXWATER: u32::from(watermark));
+        watermark
+    }
+
+    /// Returns the number of bytes that the TX FIFO can take right now
+    ///
+    /// Without the TX FIFO, the transmitter holds one byte.
+    pub fn tx_fifo_space(&self) -> usize {
+        let (enabled, count) = (
+            ral::read_reg!(ral::lpuart, self.reg, FIFO, TXFE == TXFE_1),
+            ral::read_reg!(ral::lpuart, self.reg, WATER, TXCOUNT),
+        );
+        fifo_space(self.tx_fifo_depth(), enabled, count)
+    }
+
+    /// Returns the number of bytes in the RX FIFO
+    pub fn rx_fifo_count(&self) -> usize {
+        ral::read_reg!(ral::lpuart, self.reg, WATER, RXCOUNT) as usize
+    }
+
+    /// Returns the number of bytes that the TX FIFO holds
+    fn tx_fifo_depth(&self) -> u8 {
+        fifo_depth(ral::read_reg!(ral::lpuart, self.reg, PARAM, TXFIFO))
+    }
+
+    /// Returns the number of bytes that the RX FIFO holds
+    fn rx_fifo_depth(&self) -> u8 {
+        fifo_depth(ral::read_reg!(ral::lpuart, self.reg, PARAM, RXFIFO))
+    }
+
+    /// Like `while_disabled`, but first waits until the transmitter sends what it holds
+    ///
+    /// Only waits if the transmitter is enabled. If CTS flow control is holding
+    /// the transmitter, this waits until the peer asserts CTS.
+    fn while_drained<R>(&mut self, act: impl FnOnce(&mut Self) -> R) -> R {
+        if ral::read_reg!(ral::lpuart, self.reg, CTRL, TE == TE_1) {
+            while ral::read_reg!(ral::lpuart, self.reg, STAT, TC == TC_0) {}
+        }
+        self.while_disabled(act)
+    }
+
+    /// Select the hardware flow control
+    ///
+    /// With CTS flow control, the transmitter holds characters while CTS is
+    /// deasserted. The TX FIFO stays full, so `write()` returns `WouldBlock`,
+    /// and DMA transfers pause, until the peer asserts CTS. With RTS flow
+    /// control, the receiver deasserts RTS once the RX FIFO holds the
+    /// [RTS watermark](#method.set_rts_watermark) number of bytes.
+    ///
+    /// The pins must be muxed to the UART; see
+    /// [`init_with_flow_control()`](struct.Uninit.html#method.init_with_flow_control).
     /// Calling this method temporarily disables the peripheral, flusing all data
     /// from *both* TX and RX FIFOs.
-    pub fn set_rx_fifo(&mut self, enable: bool) {
+    pub fn set_flow_control(&mut self, flow_control: FlowControl) {
+        let (txctse, rxrtse) = flow_control.fields();
         self.while_disabled(|this| {
-            ral::modify_reg!(ral::lpuart, this.reg, FIFO, RXFE: u32::from(enable));
+            ral::modify_reg!(ral::lpuart, this.reg, MODIR, TXCTSE: txctse, RXRTSE: rxrtse);
+        });
+    }
+
+    /// Returns the hardware flow control selection
+    pub fn flow_control(&self) -> FlowControl {
+        let (txctse, rxrtse) = ral::read_reg!(ral::lpuart, self.reg, MODIR, TXCTSE, RXRTSE);
+        FlowControl::from_pins(txctse != 0, rxrtse != 0)
+    }
+
+    /// Set the number of bytes in the RX FIFO that deasserts RTS
+    ///
+    /// The watermark is at most one less than the RX FIFO size; on an iMXRT1062,
+    /// that's 3. Returns the watermark that was set. Keep the watermark above the
+    /// [receiver interrupt](#method.set_receiver_interrupt) watermark, so that the
+    /// interrupt fires before the receiver throttles the peer. Without the RX FIFO,
+    /// the receiver deasserts RTS when its data register is full.
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    pub fn set_rts_watermark(&mut self, watermark: u8) -> u8 {
+        self.while_disabled(|this| {
+            let max_size: u8 = 1 << ral::read_reg!(ral::lpuart, this.reg, PARAM, RXFIFO);
+            let watermark = watermark.min(max_size.saturating_sub(1));
+            // Safety: see justification in set_tx_fifo
+            ral::modify_reg!(ral::lpuart, this.reg, MODIR, RTSWATER: watermark as u32);
+            watermark
         })
     }
 
@@ -449,13 +1167,23 @@ where
         res
     }
 
-    /// Set the baud rate for the UART bus. Returns a `TimingsError` if there was
-    /// an error computing the values that describe the baud rate.
+    /// Set the baud rate for the UART bus. Returns the baud rate that the UART
+    /// achieved, or a `TimingsError` if there are no OSR and SBR values that
+    /// produce the baud rate.
+    ///
+    /// The search tries each oversampling ratio, OSR, from 4 through 32, with
+    /// the nearest divider, SBR. It picks the pair with the smallest error,
+    /// preferring the larger OSR of equal errors. Check the returned
+    /// [`Baud`](struct.Baud.html) before trusting a fast rate; see the
+    /// [high baud rate example](index.html#high-baud-rates).
+    ///
+    /// The fastest rate is a quarter of the root clock; see
+    /// [`UartClock::max_baud()`](../ccm/uart/struct.UartClock.html#method.max_baud).
     ///
     /// Calling this method temporarily disables the peripheral, flusing all data
     /// from *both* TX and RX FIFOs.
-    pub fn set_baud(&mut self, baud: u32) -> Result<(), ccm::uart::TimingsError> {
-        let timings = ccm::uart::timings(self.effective_clock, baud)?;
+    pub fn set_baud(&mut self, baud: u32) -> Result<Baud, ccm::uart::TimingsError> {
+        let timings = ccm::uart::timings(self.clock.frequency, baud)?;
         self.while_disabled(|this| {
             ral::modify_reg!(
                 ral::lpuart,
@@ -466,26 +1194,53 @@ where
                 BOTHEDGE: u32::from(timings.both_edge)
             );
         });
-        Ok(())
+        Ok(Baud {
+            achieved_hz: timings.achieved,
+            error_ppm: timings.error_ppm,
+        })
     }
 
-    /// Clear the UART status flags
+    /// Returns the UART root clock
+    ///
+    /// See [`UartClock`](../ccm/uart/struct.UartClock.html) for the maximum baud rates.
+    pub fn clock(&self) -> &ccm::uart::UartClock {
+        &self.clock
+    }
+
+    /// Returns the baud rate that the BAUD register produces from the UART clock
+    ///
+    /// Returns `None` if the baud rate generator is off.
+    fn achieved_baud(&self) -> Option<u32> {
+        let (osr, sbr) = ral::read_reg!(ral::lpuart, self.reg, BAUD, OSR, SBR);
+        if sbr == 0 {
+            None
+        } else {
+            Some(self.clock.frequency.0 / ((osr + 1) * sbr))
+        }
+    }
+
+    /// Returns the UART status flags
+    pub fn status(&self) -> Status {
+        Status::from_bits_truncate(ral::read_reg!(ral::lpuart, self.reg, STAT))
+    }
+
+    /// Clear the `status` flags
+    ///
+    /// Only the idle and receive error flags clear. The transmit and receive
+    /// data flags follow the data, so they're ignored.
+    pub fn clear_status(&mut self, status: Status) {
+        // Safety: called with mutable receiver
+        unsafe { self.clear_flags(status) };
+    }
+
+    /// Clear the `status` flags
     ///
     /// # Safety
     ///
     /// Performs writes behind an immutable receiver. Caller must ensure
     /// that the operation is atomic.
-    unsafe fn clear_status(&self) {
-        ral::modify_reg!(
-            ral::lpuart,
-            self.reg,
-            STAT,
-            IDLE: IDLE_1,
-            OR: OR_1,
-            NF: NF_1,
-            FE: FE_1,
-            PF: PF_1
-        );
+    unsafe fn clear_flags(&self, status: Status) {
+        ral::modify_reg!(ral::lpuart, self.reg, STAT, |stat| clear_bits(stat, status));
     }
 
     /// Enable the receiver interrupt associated with this UART
@@ -507,11 +1262,7 @@ where
                     && watermark > 0
                 {
                     // Use the FIFO watermark to define interrupt frequency.
-                    let max_size = 1 << ral::read_reg!(ral::lpuart, this.reg, PARAM, RXFIFO);
-                    let fifo_size = max_size.min(watermark);
-                    // Safety: see justification in set_tx_fifo
-                    ral::modify_reg!(ral::lpuart, this.reg, WATER, RXWATER: fifo_size as u32);
-                    fifo_size
+                    this.set_rx_fifo_watermark(watermark)
                 } else {
                     // User has not enable the RX FIFO, or the watermark is zero.
                     0
@@ -525,6 +1276,135 @@ where
             }
         })
     }
+
+    /// Detect an idle receive line, and interrupt when the line goes idle
+    ///
+    /// The line is idle once it stays high for `length` characters after a
+    /// stop bit. The flag only sets after the receiver has received at least one
+    /// character since the flag was cleared, so one gap interrupts once. Disable
+    /// idle detection with `None`.
+    ///
+    /// Use the interrupt to end a variable-length DMA receive; see
+    /// [`receive_on_idle()`](../dma/struct.Peripheral.html#method.receive_on_idle).
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    pub fn set_idle_detection(&mut self, length: Option<IdleLength>) {
+        self.while_disabled(|this| {
+            ral::modify_reg!(
+                ral::lpuart,
+                this.reg,
+                CTRL,
+                IDLECFG: length.map(|length| length as u32).unwrap_or(0),
+                ILT: ILT_1,
+                ILIE: u32::from(length.is_some())
+            );
+        });
+        self.clear_idle();
+    }
+
+    /// Returns `true` if the receive line went idle
+    pub fn is_idle(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, STAT, IDLE == IDLE_1)
+    }
+
+    /// Clear the idle flag
+    ///
+    /// Other status flags, like receive errors, stay set.
+    pub fn clear_idle(&self) {
+        cortex_m::interrupt::free(|_| {
+            // Safety: the read-modify-write is atomic
+            unsafe { self.clear_flags(Status::IDLE) };
+        });
+    }
+
+    /// Queue a break character after the bytes in the TX FIFO
+    ///
+    /// A break holds the line low for longer than a frame. Returns `WouldBlock` if
+    /// the TX FIFO is full. The length applies to the breaks that are still queued,
+    /// so wait for [`flush_completely()`](#method.flush_completely) before queuing a
+    /// break of another length.
+    pub fn send_break(&mut self, length: BreakLength) -> nb::Result<(), core::convert::Infallible> {
+        serial::Write::flush(self)?;
+        ral::modify_reg!(ral::lpuart, self.reg, STAT, |stat| {
+            stat_field(stat, ral::lpuart::STAT::BRK13::mask, length as u32)
+        });
+        // A data word of zeros, with FRETSC set, is a break
+        ral::write_reg!(ral::lpuart, self.reg, DATA, FRETSC: 1);
+        Ok(())
+    }
+
+    /// Detect breaks on the receive line, and interrupt when a break arrives
+    ///
+    /// With break detection, a line that stays low for 11 bit times or more (12 with
+    /// nine data bits) sets [`Status::BREAK_DETECTED`](struct.Status.html#associatedconstant.BREAK_DETECTED),
+    /// instead of receiving a zero byte with a framing error. Reads also drop the
+    /// zero byte with a framing error, if one arrives, so the break doesn't
+    /// appear in the received data. Clear the flag with [`clear_status()`](#method.clear_status).
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    ///
+    /// # Example
+    ///
+    /// Toggle an LED each time the host sends a break.
+    ///
+    /// ```no_run
+    /// use core::cell::RefCell;
+    /// use cortex_m::interrupt::Mutex;
+    /// use imxrt1060_hal::{
+    ///     gpio::{Output, GPIO},
+    ///     iomuxc::{consts::U2, imxrt1060::b0::B0_03},
+    ///     ral::interrupt,
+    ///     uart::{Status, UART},
+    /// };
+    ///
+    /// static STATE: Mutex<RefCell<Option<(UART<U2>, GPIO<B0_03, Output>)>>> =
+    ///     Mutex::new(RefCell::new(None));
+    ///
+    /// #[cortex_m_rt::interrupt]
+    /// fn LPUART2() {
+    ///     cortex_m::interrupt::free(|cs| {
+    ///         if let Some((uart, led)) = STATE.borrow(cs).borrow_mut().as_mut() {
+    ///             if uart.status().contains(Status::BREAK_DETECTED) {
+    ///                 uart.clear_status(Status::BREAK_DETECTED);
+    ///                 led.toggle();
+    ///             }
+    ///         }
+    ///     });
+    /// }
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    /// uart.set_break_detection(true);
+    /// let led = GPIO::new(peripherals.iomuxc.b0.p03).output();
+    ///
+    /// cortex_m::interrupt::free(|cs| *STATE.borrow(cs).borrow_mut() = Some((uart, led)));
+    /// unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::LPUART2) };
+    /// ```
+    pub fn set_break_detection(&mut self, enable: bool) {
+        let lbkde = u32::from(enable);
+        self.while_disabled(|this| {
+            ral::modify_reg!(ral::lpuart, this.reg, STAT, |stat| {
+                stat_field(stat, ral::lpuart::STAT::LBKDE::mask, lbkde)
+            });
+            ral::modify_reg!(ral::lpuart, this.reg, BAUD, LBKDIE: u32::from(enable));
+        });
+        self.clear_status(Status::BREAK_DETECTED);
+    }
+
+    /// Returns `true` if break detection is enabled
+    pub fn is_break_detection(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, STAT, LBKDE == LBKDE_1)
+    }
 }
 
 use embedded_hal::serial;
@@ -541,11 +1421,13 @@ where
         Ok(())
     }
 
+    /// Returns once the TX FIFO can take another byte. The UART may still be
+    /// sending; see [`poll_transmit_complete()`](struct.UART.html#method.poll_transmit_complete).
     fn flush(&mut self) -> nb::Result<(), Self::Error> {
-        if ral::read_reg!(ral::lpuart, self.reg, STAT, TDRE == TDRE_0) {
-            Err(nb::Error::WouldBlock)
-        } else {
+        if is_fifo_ready(self.status()) {
             Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
         }
     }
 }
@@ -576,55 +1458,307 @@ bitflags::bitflags! {
         const FRAME_ERROR = 1 << 5;
         /// Overrun occured, and we lost data in the shift register
         const OVERRUN = 1 << 4;
+        /// The word has a ninth data bit, which doesn't fit in a byte
+        ///
+        /// Only the `embedded_hal` `Read` implementations report this flag. Use
+        /// `read_word9()` to read all nine data bits.
+        const NINTH_BIT = 1 << 3;
+    }
+}
+
+impl ReadErrorFlags {
+    fn from_status(status: Status) -> Self {
+        let mut flags = ReadErrorFlags::empty();
+        flags.set(ReadErrorFlags::NOISY, status.contains(Status::NOISY));
+        flags.set(ReadErrorFlags::PARITY, status.contains(Status::PARITY));
+        flags.set(
+            ReadErrorFlags::FRAME_ERROR,
+            status.contains(Status::FRAME_ERROR),
+        );
+        flags.set(ReadErrorFlags::OVERRUN, status.contains(Status::OVERRUN));
+        flags
     }
 }
 
 /// Type that describes a read error
+///
+/// A read that returns an error also clears the error, so the receiver keeps
+/// running.
+///
+/// # Example
+///
+/// Configure the UART for 115200 baud, and connect a peer that sends at 9600
+/// baud. The reads return framing errors.
+///
+/// ```no_run
+/// use embedded_hal::serial::Read;
+/// use imxrt1060_hal::uart::ReadErrorFlags;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let mut uart = uarts
+///     .uart2
+///     .init(
+///         peripherals.iomuxc.ad_b1.p02,
+///         peripherals.iomuxc.ad_b1.p03,
+///         115_200,
+///     )
+///     .unwrap();
+///
+/// let mut frame_errors = 0u32;
+/// loop {
+///     match uart.read() {
+///         Ok(_) | Err(nb::Error::WouldBlock) => {}
+///         Err(nb::Error::Other(err)) => {
+///             if err.flags.contains(ReadErrorFlags::FRAME_ERROR) {
+///                 frame_errors += 1;
+///             }
+///         }
+///     }
+/// }
+/// ```
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ReadError {
     /// Decribes the reason for the error
     pub flags: ReadErrorFlags,
-    /// The raw value read, if you'd like to consider it
-    pub raw: u8,
+    /// The byte that arrived with the error
+    ///
+    /// `None` if the receiver overran, and there was no byte to read.
+    pub byte: Option<u8>,
 }
 
-impl<M> serial::Read<u8> for UART<M>
+bitflags::bitflags! {
+    /// UART status flags
+    ///
+    /// The flags have the same positions as they have in the STAT register.
+    pub struct Status : u32 {
+        /// The receiver detected a break
+        ///
+        /// Only sets with [break detection](struct.UART.html#method.set_break_detection).
+        const BREAK_DETECTED = 1 << 31;
+        /// The transmitter can take more data
+        const TRANSMIT_EMPTY = 1 << 23;
+        /// The transmitter is idle
+        const TRANSMIT_COMPLETE = 1 << 22;
+        /// The receiver has data at, or above, the RX FIFO watermark
+        const RECEIVE_FULL = 1 << 21;
+        /// The receive line went idle
+        const IDLE = 1 << 20;
+        /// Overrun occured, and we lost data in the shift register
+        const OVERRUN = 1 << 19;
+        /// Data was received with noise
+        const NOISY = 1 << 18;
+        /// Framing error when receiving data
+        const FRAME_ERROR = 1 << 17;
+        /// Parity error when receiving data
+        const PARITY = 1 << 16;
+        /// The received word matched `addr1`
+        ///
+        /// See [`set_address_match()`](struct.UART.html#method.set_address_match).
+        const MATCH_1 = 1 << 15;
+        /// The received word matched `addr2`
+        const MATCH_2 = 1 << 14;
+    }
+}
+
+impl Status {
+    /// The flags that software can clear
+    const CLEARABLE: Status = Status::from_bits_truncate(
+        Status::BREAK_DETECTED.bits()
+            | Status::IDLE.bits()
+            | Status::OVERRUN.bits()
+            | Status::NOISY.bits()
+            | Status::FRAME_ERROR.bits()
+            | Status::PARITY.bits()
+            | Status::MATCH_1.bits()
+            | Status::MATCH_2.bits(),
+    );
+
+    /// The flags that mark a receive error
+    const RECEIVE_ERRORS: Status = Status::from_bits_truncate(
+        Status::OVERRUN.bits()
+            | Status::NOISY.bits()
+            | Status::FRAME_ERROR.bits()
+            | Status::PARITY.bits(),
+    );
+}
+
+/// STAT flags that clear when written with 1: LBKDIF, RXEDGIF, IDLE, OR, NF,
+/// FE, PF, MA1F, and MA2F
+const STAT_W1C: u32 = 0b11 << 30 | 0b1_1111 << 16 | 0b11 << 14;
+
+/// Returns the number of bytes in a FIFO, given its PARAM field
+fn fifo_depth(param: u32) -> u8 {
+    1 << param.min(7)
+}
+
+/// Returns the largest watermark, less than the FIFO depth, that's at most `watermark`
+fn clamp_watermark(watermark: u8, depth: u8) -> u8 {
+    watermark.min(depth.saturating_sub(1))
+}
+
+/// Returns the free space in a FIFO that holds `count` bytes
+///
+/// A disabled FIFO holds one byte.
+fn fifo_space(depth: u8, enabled: bool, count: u32) -> usize {
+    let depth = if enabled { depth } else { 1 };
+    (depth as usize).saturating_sub(count as usize)
+}
+
+/// Narrows a received word to a byte, flagging a ninth data bit that doesn't fit
+fn narrow(received: nb::Result<u16, (ReadErrorFlags, Option<u16>)>) -> nb::Result<u8, ReadError> {
+    let (mut flags, word) = match received {
+        Ok(word) => (ReadErrorFlags::empty(), Some(word)),
+        Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+        Err(nb::Error::Other(err)) => err,
+    };
+    flags.set(
+        ReadErrorFlags::NINTH_BIT,
+        word.map_or(false, |word| word > 0xFF),
+    );
+    match word {
+        Some(word) if flags.is_empty() => Ok(word as u8),
+        _ => Err(nb::Error::Other(ReadError {
+            flags,
+            byte: word.map(|word| word as u8),
+        })),
+    }
+}
+
+/// Returns `true` if a received word is a break character, instead of data
+fn is_break(flags: ReadErrorFlags, word: u16) -> bool {
+    word == 0 && flags.contains(ReadErrorFlags::FRAME_ERROR)
+}
+
+/// Returns `true` if the TX FIFO is at or below its watermark, so `flush()` is done
+fn is_fifo_ready(status: Status) -> bool {
+    status.contains(Status::TRANSMIT_EMPTY)
+}
+
+/// Returns `true` if the UART sent the last stop bit, and the line is idle
+fn is_sent(status: Status) -> bool {
+    status.contains(Status::TRANSMIT_COMPLETE)
+}
+
+/// Returns the STAT value that sets the one-bit field in `mask` to `value`, and
+/// keeps the other fields, without clearing flags
+fn stat_field(stat: u32, mask: u32, value: u32) -> u32 {
+    let field = if value != 0 { mask } else { 0 };
+    (stat & !STAT_W1C & !mask) | field
+}
+
+/// Returns the STAT value that clears `status`, and keeps the other flags
+fn clear_bits(stat: u32, status: Status) -> u32 {
+    (stat & !STAT_W1C) | (status & Status::CLEARABLE).bits()
+}
+
+impl<M> UART<M>
 where
     M: Unsigned,
 {
-    type Error = ReadError;
+    /// Write a nine-bit word
+    ///
+    /// Use this when the UART has [nine data bits](#method.set_data_bits).
+    /// Returns `WouldBlock` if the transmitter is busy.
+    pub fn write_word9(&mut self, word: u16) -> nb::Result<(), core::convert::Infallible> {
+        use embedded_hal::serial::Write;
+        self.flush()?;
+        ral::write_reg!(ral::lpuart, self.reg, DATA, u32::from(word & 0x1FF));
+        Ok(())
+    }
 
-    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+    /// Read a nine-bit word
+    ///
+    /// Use this when the UART has [nine data bits](#method.set_data_bits).
+    /// If there's an error, the error's `byte` holds the low eight bits.
+    pub fn read_word9(&mut self) -> nb::Result<u16, ReadError> {
+        self.receive().map_err(|err| {
+            err.map(|(flags, word)| ReadError {
+                flags,
+                byte: word.map(|word| word as u8),
+            })
+        })
+    }
+
+    /// Receive a word, masked to the data bits
+    fn receive(&mut self) -> nb::Result<u16, (ReadErrorFlags, Option<u16>)> {
         use ral::lpuart::DATA::*;
         let data = ral::read_reg!(ral::lpuart, self.reg, DATA);
+        let overrun = self.status() & Status::OVERRUN;
         if data & RXEMPT::mask != 0 {
-            Err(nb::Error::WouldBlock)
+            if overrun.is_empty() {
+                Err(nb::Error::WouldBlock)
+            } else {
+                // The receiver stops while the flag is set
+                self.clear_status(overrun);
+                Err(nb::Error::Other((ReadErrorFlags::OVERRUN, None)))
+            }
         } else {
-            let mut flags = ReadErrorFlags::empty();
-            flags.set(
-                ReadErrorFlags::OVERRUN,
-                ral::read_reg!(ral::lpuart, self.reg, STAT, OR == OR_1),
-            );
+            let mut flags = ReadErrorFlags::from_status(overrun);
             flags.set(ReadErrorFlags::PARITY, data & PARITYE::mask != 0);
             flags.set(ReadErrorFlags::FRAME_ERROR, data & FRETSC::mask != 0);
             flags.set(ReadErrorFlags::NOISY, data & NOISY::mask != 0);
 
-            let raw = (data & 0xFF) as u8;
-            // Safety: called with mutable receiver; caller is ensuring that this
-            // entire read() operation occurs atomically.
-            unsafe {
-                self.clear_status();
-            }
+            // Drops a parity bit that follows seven data bits
+            let mask = (1u32 << self.data_bits.count()) - 1;
+            let word = (data & mask) as u16;
+            self.clear_status(Status::RECEIVE_ERRORS);
 
-            if flags.is_empty() {
-                Ok(raw)
+            if is_break(flags, word) && self.is_break_detection() {
+                Err(nb::Error::WouldBlock)
+            } else if flags.is_empty() {
+                Ok(word)
             } else {
-                Err(nb::Error::Other(ReadError { flags, raw }))
+                Err(nb::Error::Other((flags, Some(word))))
             }
         }
     }
 }
 
+impl<M> serial::Read<u8> for UART<M>
+where
+    M: Unsigned,
+{
+    type Error = ReadError;
+
+    /// Reads a byte
+    ///
+    /// With [nine data bits](struct.UART.html#method.set_data_bits), a word that has its ninth
+    /// bit set is an error with [`ReadErrorFlags::NINTH_BIT`](struct.ReadErrorFlags.html),
+    /// and the error's `byte` holds the low eight bits.
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        narrow(self.receive())
+    }
+}
+
+impl<M> Tx<M>
+where
+    M: Unsigned,
+{
+    /// Write a nine-bit word
+    ///
+    /// See [`UART::write_word9()`](struct.UART.html#method.write_word9).
+    pub fn write_word9(&mut self, word: u16) -> nb::Result<(), core::convert::Infallible> {
+        self.0.write_word9(word)
+    }
+}
+
+impl<M> Rx<M>
+where
+    M: Unsigned,
+{
+    /// Read a nine-bit word
+    ///
+    /// See [`UART::read_word9()`](struct.UART.html#method.read_word9).
+    pub fn read_word9(&mut self) -> nb::Result<u16, ReadError> {
+        self.0.read_word9()
+    }
+}
+
 impl<M> serial::Read<u8> for Rx<M>
 where
     M: Unsigned,
@@ -648,6 +1782,259 @@ const DMA_TX_REQUEST_LOOKUP: [u32; 8] = [2, 66, 4, 68, 6, 70, 8, 72];
 /// See table 4-3 of the iMXRT1060 Reference Manual (Rev 2)
 const DMA_RX_REQUEST_LOOKUP: [u32; 8] = [3, 67, 5, 69, 7, 71, 9, 73];
 
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Returns a UART that aliases the registers of this UART module
+    ///
+    /// A DMA peripheral owns its UART. The alias reaches the UART's status
+    /// flags during the transfer.
+    ///
+    /// # Safety
+    ///
+    /// The alias may only perform atomic register accesses.
+    unsafe fn alias() -> Self {
+        let mut uart = UART {
+            reg: M::steal(),
+            clock: ccm::uart::UartClock::detached(),
+            _module: PhantomData,
+            dma_completion: TransmitCompletion::FifoWritten,
+            data_bits: DataBits::Eight,
+        };
+        uart.data_bits = uart.frame().data_bits();
+        uart
+    }
+
+    /// Choose when a DMA transmit is complete
+    ///
+    /// By default, a DMA transmit completes once the DMA controller writes the last
+    /// byte into the TX FIFO, while the UART is still sending. With
+    /// [`TransmitCompletion::Sent`](enum.TransmitCompletion.html#variant.Sent), the
+    /// transmit completes once the UART also sends the last stop bit. Then, it's safe to
+    /// power down the transceiver after [`transmit_complete()`](../dma/struct.Peripheral.html#method.transmit_complete).
+    ///
+    /// The DMA interrupt still fires when the DMA controller writes the last byte.
+    /// [`split()`](#method.split) keeps the setting for the TX half.
+    pub fn set_dma_completion(&mut self, completion: TransmitCompletion) {
+        self.dma_completion = completion;
+    }
+
+    /// Returns when a DMA transmit is complete
+    pub fn dma_completion(&self) -> TransmitCompletion {
+        self.dma_completion
+    }
+
+    /// Returns the receive errors, and clears them
+    fn take_receive_errors(&self) -> ReadErrorFlags {
+        let errors = self.status() & Status::RECEIVE_ERRORS;
+        cortex_m::interrupt::free(|_| {
+            // Safety: the read-modify-write is atomic
+            unsafe { self.clear_flags(errors) };
+        });
+        ReadErrorFlags::from_status(errors)
+    }
+}
+
+/// Returns `true` if a DMA transmit is complete
+///
+/// `transferred` is `true` once the DMA controller wrote the last byte.
+fn transmit_done(completion: TransmitCompletion, transferred: bool, status: Status) -> bool {
+    transferred && (completion == TransmitCompletion::FifoWritten || is_sent(status))
+}
+
+impl<M, S> dma::Peripheral<UART<M>, u8, S>
+where
+    M: Unsigned,
+    S: dma::buffer::Source<u8>,
+{
+    /// Returns `true` if the DMA transmit is complete
+    ///
+    /// By default, this is the same as `is_transfer_complete()`: the UART may still be
+    /// sending the last bytes. See [`set_dma_completion()`](../uart/struct.UART.html#method.set_dma_completion)
+    /// to wait until the last stop bit is sent.
+    pub fn is_transmit_complete(&self) -> bool {
+        let uart = self.peripheral();
+        transmit_done(
+            uart.dma_completion,
+            self.is_transfer_complete(),
+            uart.status(),
+        )
+    }
+
+    /// Returns the buffer once the DMA transmit is complete
+    ///
+    /// Returns `None` until [`is_transmit_complete()`](#method.is_transmit_complete). Once
+    /// it's complete, this is the same as `transfer_complete()`.
+    pub fn transmit_complete(&mut self) -> Option<S> {
+        if self.is_transmit_complete() {
+            self.transfer_complete()
+        } else {
+            None
+        }
+    }
+}
+
+impl<M, S> dma::Peripheral<Tx<M>, u8, S>
+where
+    M: Unsigned,
+    S: dma::buffer::Source<u8>,
+{
+    /// Returns `true` if the DMA transmit is complete
+    ///
+    /// This is the same as the `is_transmit_complete()` of a whole `UART`, for the
+    /// transfer half of a split UART.
+    pub fn is_transmit_complete(&self) -> bool {
+        let uart = &self.peripheral().0;
+        transmit_done(
+            uart.dma_completion,
+            self.is_transfer_complete(),
+            uart.status(),
+        )
+    }
+
+    /// Returns the buffer once the DMA transmit is complete
+    ///
+    /// This is the same as the `transmit_complete()` of a whole `UART`, for the
+    /// transfer half of a split UART.
+    pub fn transmit_complete(&mut self) -> Option<S> {
+        if self.is_transmit_complete() {
+            self.transfer_complete()
+        } else {
+            None
+        }
+    }
+}
+
+impl<M, D> dma::Peripheral<UART<M>, u8, D>
+where
+    M: Unsigned,
+{
+    /// Returns the UART's receive errors since the last call, and clears them
+    ///
+    /// Check the errors once a DMA receive completes. The DMA controller doesn't
+    /// see the errors of each byte, so the flags cover the whole transfer. An
+    /// overrun stops the receiver until the flag is cleared, so a receive that
+    /// never completes may have overrun; see
+    /// [`poll_events()`](struct.Peripheral.html#method.poll_events) to recover.
+    pub fn receive_errors(&mut self) -> ReadErrorFlags {
+        // Safety: only touches the status flags, with atomic accesses
+        unsafe { UART::<M>::alias() }.take_receive_errors()
+    }
+}
+
+impl<M, D> dma::Peripheral<Rx<M>, u8, D>
+where
+    M: Unsigned,
+{
+    /// Returns the UART's receive errors since the last call, and clears them
+    ///
+    /// This is the same as the `receive_errors()` of a whole `UART`, for the receive
+    /// half of a split UART.
+    pub fn receive_errors(&mut self) -> ReadErrorFlags {
+        // Safety: only touches the status flags, with atomic accesses
+        unsafe { UART::<M>::alias() }.take_receive_errors()
+    }
+}
+
+/// Handle the idle interrupt of a UART that's receiving over DMA
+fn receive_on_idle<P, M>(
+    rx: &mut dma::Peripheral<P, u8, dma::Linear<u8>>,
+) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>>
+where
+    P: dma::peripheral::Source<u8>,
+    M: Unsigned,
+{
+    // Safety: we only touch the IDLE flag, with an atomic write
+    let uart = unsafe { UART::<M>::alias() };
+    if !uart.is_idle() {
+        return None;
+    }
+    uart.clear_idle();
+    if rx.is_receive_complete() {
+        // The buffer filled before the line went idle
+        rx.receive_complete().map(dma::ReceiveTimeout::Complete)
+    } else if rx.receive_progress() == 0 {
+        // The gap after a receive that already completed. Keep the next
+        // receive running.
+        None
+    } else {
+        rx.receive_stop()
+    }
+}
+
+impl<M> dma::Peripheral<UART<M>, u8, dma::Linear<u8>>
+where
+    M: Unsigned,
+{
+    /// End a DMA receive when the UART's receive line goes idle
+    ///
+    /// Call this from the UART's interrupt handler, after enabling
+    /// [idle detection](../uart/struct.UART.html#method.set_idle_detection). If the line
+    /// went idle, this clears the idle flag, and stops the receive. The result has the
+    /// buffer, and the number of bytes received. Returns `None` if the line isn't idle, or
+    /// if no bytes arrived since the last receive.
+    ///
+    /// You may also complete the receive in the DMA interrupt. If the buffer fills at the
+    /// same time as the line goes idle, only one of the two handlers gets the buffer: this
+    /// method returns `Complete`, or the DMA handler completes the receive, and then this
+    /// method returns `None`, leaving the next receive running.
+    ///
+    /// Keep the RX FIFO watermark at zero, so that the DMA controller moves each byte when
+    /// it arrives. Bytes that are waiting in the FIFO when the receive stops stay in the FIFO.
+    ///
+    /// # Example
+    ///
+    /// Modbus RTU frames are separated by 3.5 characters of silence. End each receive after
+    /// two idle characters.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, Buffer, Linear, ReceiveTimeout};
+    /// use imxrt1060_hal::uart::{IdleLength, UART};
+    /// # use imxrt1060_hal::iomuxc::consts::U2;
+    ///
+    /// static FRAME: Buffer<[u8; 256]> = Buffer::new([0; 256]);
+    ///
+    /// # fn setup(mut uart: UART<U2>, channel: dma::Channel) -> dma::Peripheral<UART<U2>, u8, Linear<u8>> {
+    /// uart.set_baud(19_200).unwrap();
+    /// uart.set_idle_detection(Some(IdleLength::CHARS_2));
+    /// let mut rx = dma::receive_u8(uart, channel);
+    /// let buffer = Linear::try_new(&FRAME).unwrap();
+    /// rx.start_receive(buffer).unwrap();
+    /// rx
+    /// # }
+    ///
+    /// // In the LPUART2 interrupt handler...
+    /// # fn on_lpuart2(rx: &mut dma::Peripheral<UART<U2>, u8, Linear<u8>>) {
+    /// let (mut buffer, received) = match rx.receive_on_idle() {
+    ///     Some(ReceiveTimeout::Expired { buffer, received }) => (buffer, received),
+    ///     Some(ReceiveTimeout::Complete(buffer)) => (buffer, 256),
+    ///     None => return,
+    /// };
+    /// let frame = &buffer.as_elements()[..received];
+    /// // Check the CRC, and handle the request...
+    /// buffer.set_transfer_len(256);
+    /// rx.start_receive(buffer).unwrap();
+    /// # }
+    /// ```
+    pub fn receive_on_idle(&mut self) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>> {
+        receive_on_idle::<_, M>(self)
+    }
+}
+
+impl<M> dma::Peripheral<Rx<M>, u8, dma::Linear<u8>>
+where
+    M: Unsigned,
+{
+    /// End a DMA receive when the UART's receive line goes idle
+    ///
+    /// This is the same as the `receive_on_idle()` of a whole `UART`, for the receive half of a
+    /// split UART.
+    pub fn receive_on_idle(&mut self) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>> {
+        receive_on_idle::<_, M>(self)
+    }
+}
+
 unsafe impl<M> dma::peripheral::Source<u8> for UART<M>
 where
     M: Unsigned,
@@ -661,7 +2048,7 @@ where
     fn enable_source(&self) {
         cortex_m::interrupt::free(|_| unsafe {
             // Safety: mutability is atomic
-            self.clear_status();
+            self.clear_flags(Status::CLEARABLE);
             ral::modify_reg!(ral::lpuart, self.reg, BAUD, RDMAE: 1);
         });
     }
@@ -738,3 +2125,198 @@ use embedded_hal::blocking::serial::write::Default as BlockingWrite;
 
 impl<M> BlockingWrite<u8> for UART<M> where M: Unsigned {}
 impl<M> BlockingWrite<u8> for Tx<M> where M: Unsigned {}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clamp_watermark, clear_bits, fifo_depth, fifo_space, is_break, is_fifo_ready, is_sent,
+        narrow, stat_field, transmit_done, DataBits, FlowControl, FrameFields, Parity, ReadError,
+        ReadErrorFlags, Status, TransmitCompletion,
+    };
+
+    #[test]
+    fn flow_control_fields() {
+        for &flow_control in &[
+            FlowControl::None,
+            FlowControl::Cts,
+            FlowControl::Rts,
+            FlowControl::RtsCts,
+        ] {
+            let (txctse, rxrtse) = flow_control.fields();
+            assert_eq!(
+                FlowControl::from_pins(txctse != 0, rxrtse != 0),
+                flow_control
+            );
+        }
+        assert_eq!(FlowControl::Cts.fields(), (1, 0));
+        assert_eq!(FlowControl::Rts.fields(), (0, 1));
+    }
+
+    #[test]
+    fn frame_fields() {
+        let fields = |parity, data_bits| {
+            let f = FrameFields::new(parity, data_bits);
+            (f.pe, f.pt, f.m, f.m7, f.m10)
+        };
+        // 8N1 is the reset state
+        assert_eq!(fields(None, DataBits::Eight), (0, 0, 0, 0, 0));
+        assert_eq!(fields(None, DataBits::Seven), (0, 0, 0, 1, 0));
+        assert_eq!(fields(Some(Parity::Even), DataBits::Seven), (1, 0, 0, 0, 0));
+        assert_eq!(fields(Some(Parity::Odd), DataBits::Eight), (1, 1, 1, 0, 0));
+        assert_eq!(fields(None, DataBits::Nine), (0, 0, 1, 0, 0));
+        assert_eq!(fields(Some(Parity::Even), DataBits::Nine), (1, 0, 1, 0, 1));
+
+        for &parity in &[None, Some(Parity::Even), Some(Parity::Odd)] {
+            for &data_bits in &[DataBits::Seven, DataBits::Eight, DataBits::Nine] {
+                let f = FrameFields::new(parity, data_bits);
+                assert_eq!((f.parity(), f.data_bits()), (parity, data_bits));
+            }
+        }
+    }
+
+    #[test]
+    fn narrow_words() {
+        let error = |received| match narrow(received) {
+            Err(nb::Error::Other(err)) => Some(err),
+            _ => None,
+        };
+        assert!(matches!(narrow(Ok(0x5A)), Ok(0x5A)));
+        assert!(matches!(
+            narrow(Err(nb::Error::WouldBlock)),
+            Err(nb::Error::WouldBlock)
+        ));
+        // A nine-bit word doesn't fit
+        assert_eq!(
+            error(Ok(0x15A)),
+            Some(ReadError {
+                flags: ReadErrorFlags::NINTH_BIT,
+                byte: Some(0x5A)
+            })
+        );
+        assert_eq!(
+            error(Err(nb::Error::Other((ReadErrorFlags::PARITY, Some(0x1FF))))),
+            Some(ReadError {
+                flags: ReadErrorFlags::PARITY | ReadErrorFlags::NINTH_BIT,
+                byte: Some(0xFF)
+            })
+        );
+        assert_eq!(
+            error(Err(nb::Error::Other((ReadErrorFlags::OVERRUN, None)))),
+            Some(ReadError {
+                flags: ReadErrorFlags::OVERRUN,
+                byte: None
+            })
+        );
+    }
+
+    #[test]
+    fn status_flags() {
+        // RXINV, the idle transmitter, a framing error, and an address match
+        let stat = 1 << 28 | 0b11 << 22 | 1 << 17 | 1 << 15;
+        let status = Status::from_bits_truncate(stat);
+        assert_eq!(
+            status,
+            Status::TRANSMIT_EMPTY
+                | Status::TRANSMIT_COMPLETE
+                | Status::FRAME_ERROR
+                | Status::MATCH_1
+        );
+        assert_eq!(
+            ReadErrorFlags::from_status(status),
+            ReadErrorFlags::FRAME_ERROR
+        );
+        assert_eq!(
+            ReadErrorFlags::from_status(Status::all()),
+            ReadErrorFlags::all() - ReadErrorFlags::NINTH_BIT
+        );
+        // Keeps the configuration, and only writes 1 to the cleared flags
+        assert_eq!(
+            clear_bits(stat, Status::FRAME_ERROR),
+            1 << 28 | 0b11 << 22 | 1 << 17
+        );
+        assert_eq!(
+            clear_bits(stat, Status::TRANSMIT_EMPTY),
+            1 << 28 | 0b11 << 22
+        );
+
+        // LBKDIF and LBKDE
+        let stat = 1 << 31 | 1 << 25 | 1 << 20;
+        let status = Status::from_bits_truncate(stat);
+        assert_eq!(status, Status::BREAK_DETECTED | Status::IDLE);
+        assert!(ReadErrorFlags::from_status(status).is_empty());
+        assert_eq!(clear_bits(stat, Status::BREAK_DETECTED), 1 << 31 | 1 << 25);
+        assert_eq!(clear_bits(stat, Status::IDLE), 1 << 25 | 1 << 20);
+    }
+
+    #[test]
+    fn stat_fields() {
+        const MSBF: u32 = 1 << 29;
+        const RXINV: u32 = 1 << 28;
+        const BRK13: u32 = 1 << 26;
+        // A pending break, an overrun, and an address match, with LBKDE and the idle transmitter
+        let flags = 1 << 31 | 1 << 19 | 1 << 15;
+        let stat = flags | 1 << 25 | 0b11 << 22;
+
+        // Sets the field, and never writes 1 to a flag
+        assert_eq!(stat_field(stat, RXINV, 1), RXINV | 1 << 25 | 0b11 << 22);
+        assert_eq!(stat_field(stat, MSBF, 1), MSBF | 1 << 25 | 0b11 << 22);
+        assert_eq!(stat_field(stat | MSBF, MSBF, 0), 1 << 25 | 0b11 << 22);
+        assert_eq!(stat_field(stat, BRK13, 1) & flags, 0);
+        // Fields compose
+        let stat = stat_field(stat_field(stat, RXINV, 1), MSBF, 1);
+        assert_eq!(stat_field(stat, RXINV, 0), MSBF | 1 << 25 | 0b11 << 22);
+    }
+
+    #[test]
+    fn transmit_states() {
+        // TDRE and TC, from STAT
+        let stat = |tdre: u32, tc: u32| Status::from_bits_truncate(tdre << 23 | tc << 22);
+        let sending = stat(0, 0);
+        // The FIFO drained to its watermark, and the last bytes are shifting out
+        let shifting = stat(1, 0);
+        let sent = stat(1, 1);
+
+        assert!(!is_fifo_ready(sending) && !is_sent(sending));
+        assert!(is_fifo_ready(shifting) && !is_sent(shifting));
+        assert!(is_fifo_ready(sent) && is_sent(sent));
+
+        use TransmitCompletion::{FifoWritten, Sent};
+        assert!(!transmit_done(FifoWritten, false, sent));
+        assert!(transmit_done(FifoWritten, true, shifting));
+        assert!(!transmit_done(Sent, true, shifting));
+        assert!(!transmit_done(Sent, false, sent));
+        assert!(transmit_done(Sent, true, sent));
+    }
+
+    #[test]
+    fn break_characters() {
+        assert!(is_break(ReadErrorFlags::FRAME_ERROR, 0));
+        assert!(is_break(
+            ReadErrorFlags::FRAME_ERROR | ReadErrorFlags::PARITY,
+            0
+        ));
+        assert!(!is_break(ReadErrorFlags::FRAME_ERROR, 0x80));
+        assert!(!is_break(ReadErrorFlags::PARITY, 0));
+        assert!(!is_break(ReadErrorFlags::empty(), 0));
+    }
+
+    #[test]
+    fn fifo_math() {
+        // iMXRT1062: PARAM[TXFIFO] = PARAM[RXFIFO] = 2
+        assert_eq!(fifo_depth(2), 4);
+        assert_eq!(fifo_depth(0), 1);
+        assert_eq!(fifo_depth(31), 128);
+
+        assert_eq!(clamp_watermark(2, 4), 2);
+        assert_eq!(clamp_watermark(4, 4), 3);
+        assert_eq!(clamp_watermark(u8::max_value(), 4), 3);
+        assert_eq!(clamp_watermark(1, 1), 0);
+        assert_eq!(clamp_watermark(1, 0), 0);
+
+        assert_eq!(fifo_space(4, true, 0), 4);
+        assert_eq!(fifo_space(4, true, 3), 1);
+        assert_eq!(fifo_space(4, true, 4), 0);
+        assert_eq!(fifo_space(4, false, 0), 1);
+        assert_eq!(fifo_space(4, false, 1), 0);
+    }
+}
@@ -0,0 +1,342 @@
+//! Blocking UART helpers, and `core::fmt::Write`
+
+use super::buffered::{fill, Fifo};
+use super::{is_sent, ReadError, Rx, Tx, UART};
+use crate::gpio::debounce::{self, Now};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::convert::Infallible;
+use core::time::Duration;
+use embedded_hal::serial::Write;
+
+/// An error from a blocking UART helper that has a timeout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockingError {
+    /// The timeout elapsed after transferring this many bytes
+    Timeout(usize),
+    /// A byte was received with an error
+    Read(ReadError),
+}
+
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Returns `WouldBlock` until the UART has sent its last stop bit
+    ///
+    /// **This is not the `embedded_hal` `flush()`.** `flush()` returns once the
+    /// TX FIFO can take another byte. Then, up to a FIFO's worth of bytes, and the
+    /// byte in the shift register, are still on the wire; that's about 10 bit times
+    /// for each byte. This waits for the transmit complete flag, STAT\[TC\], which
+    /// sets once the FIFO and the shift register are empty, and the line is idle.
+    /// Wait for this before powering down a transceiver, turning an RS-485
+    /// transceiver around, or changing the direction of the TX pad.
+    pub fn poll_transmit_complete(&mut self) -> nb::Result<(), Infallible> {
+        if is_sent(self.status()) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Block until the UART has sent its last stop bit
+    ///
+    /// See [`poll_transmit_complete()`](#method.poll_transmit_complete) for how this
+    /// differs from `flush()`.
+    pub fn flush_completely(&mut self) {
+        while self.poll_transmit_complete().is_err() {}
+    }
+
+    /// Write all of `data`, blocking until the TX FIFO takes each byte
+    ///
+    /// Returns once the last byte is in the FIFO. Follow with
+    /// [`flush_completely()`](#method.flush_completely) to wait until it's sent.
+    /// Stops at the first write error.
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Infallible> {
+        for &byte in data {
+            nb::block!(Write::write(self, byte))?;
+        }
+        Ok(())
+    }
+
+    /// Write all of `data`, giving up if it takes longer than `timeout`
+    ///
+    /// `clock` measures the timeout. It could be a free-running GPT.
+    pub fn write_all_timeout<T: Now>(
+        &mut self,
+        data: &[u8],
+        clock: &T,
+        timeout: Duration,
+    ) -> Result<(), BlockingError> {
+        let expired = deadline(clock, timeout);
+        write_until(self, data, expired).map_err(BlockingError::Timeout)
+    }
+
+    /// Read bytes until `data` is full
+    ///
+    /// Stops at the first byte that has an error.
+    pub fn read_exact(&mut self, data: &mut [u8]) -> Result<(), ReadError> {
+        read_until(self, data, || false).map_err(|err| match err {
+            BlockingError::Read(err) => err,
+            BlockingError::Timeout(_) => unreachable!("never expires"),
+        })
+    }
+
+    /// Read bytes until `data` is full, giving up if it takes longer than `timeout`
+    ///
+    /// `clock` measures the timeout. Stops at the first byte that has an error.
+    pub fn read_exact_timeout<T: Now>(
+        &mut self,
+        data: &mut [u8],
+        clock: &T,
+        timeout: Duration,
+    ) -> Result<(), BlockingError> {
+        let expired = deadline(clock, timeout);
+        read_until(self, data, expired)
+    }
+}
+
+/// Write all of `data`
+///
+/// Returns the number of bytes written if `expired` returns `true` first.
+fn write_until<F: Fifo>(
+    fifo: &mut F,
+    data: &[u8],
+    mut expired: impl FnMut() -> bool,
+) -> Result<(), usize> {
+    let mut written = 0;
+    while written < data.len() {
+        written += fill(fifo, data[written..].iter().copied());
+        if written < data.len() && expired() {
+            return Err(written);
+        }
+    }
+    Ok(())
+}
+
+/// Read bytes until `data` is full, or until `expired` returns `true`
+fn read_until<F: Fifo>(
+    fifo: &mut F,
+    data: &mut [u8],
+    mut expired: impl FnMut() -> bool,
+) -> Result<(), BlockingError> {
+    for (read, slot) in data.iter_mut().enumerate() {
+        *slot = loop {
+            match fifo.read() {
+                Ok(byte) => break byte,
+                Err(nb::Error::WouldBlock) if expired() => {
+                    return Err(BlockingError::Timeout(read))
+                }
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(err)) => return Err(BlockingError::Read(err)),
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Returns a function that's `true` once `timeout` elapses on `clock`
+fn deadline<T: Now>(clock: &T, timeout: Duration) -> impl FnMut() -> bool + '_ {
+    let ticks = debounce::ticks(timeout, clock.tick());
+    let start = clock.now();
+    move || clock.now().wrapping_sub(start) >= ticks
+}
+
+impl<M> Tx<M>
+where
+    M: Unsigned,
+{
+    /// Returns `WouldBlock` until the UART has sent its last stop bit
+    ///
+    /// See [`UART::poll_transmit_complete()`](struct.UART.html#method.poll_transmit_complete).
+    pub fn poll_transmit_complete(&mut self) -> nb::Result<(), Infallible> {
+        self.0.poll_transmit_complete()
+    }
+
+    /// Block until the UART has sent its last stop bit
+    ///
+    /// See [`UART::flush_completely()`](struct.UART.html#method.flush_completely).
+    pub fn flush_completely(&mut self) {
+        self.0.flush_completely()
+    }
+
+    /// Write all of `data`, blocking until the TX FIFO takes each byte
+    ///
+    /// See [`UART::write_all()`](struct.UART.html#method.write_all).
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Infallible> {
+        self.0.write_all(data)
+    }
+
+    /// Write all of `data`, giving up if it takes longer than `timeout`
+    ///
+    /// See [`UART::write_all_timeout()`](struct.UART.html#method.write_all_timeout).
+    pub fn write_all_timeout<T: Now>(
+        &mut self,
+        data: &[u8],
+        clock: &T,
+        timeout: Duration,
+    ) -> Result<(), BlockingError> {
+        self.0.write_all_timeout(data, clock, timeout)
+    }
+}
+
+impl<M> Rx<M>
+where
+    M: Unsigned,
+{
+    /// Read bytes until `data` is full
+    ///
+    /// See [`UART::read_exact()`](struct.UART.html#method.read_exact).
+    pub fn read_exact(&mut self, data: &mut [u8]) -> Result<(), ReadError> {
+        self.0.read_exact(data)
+    }
+
+    /// Read bytes until `data` is full, giving up if it takes longer than `timeout`
+    ///
+    /// See [`UART::read_exact_timeout()`](struct.UART.html#method.read_exact_timeout).
+    pub fn read_exact_timeout<T: Now>(
+        &mut self,
+        data: &mut [u8],
+        clock: &T,
+        timeout: Duration,
+    ) -> Result<(), BlockingError> {
+        self.0.read_exact_timeout(data, clock, timeout)
+    }
+}
+
+/// Blocks until the TX FIFO takes each byte
+///
+/// ```no_run
+/// use core::fmt::Write;
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let uart = uarts
+///     .uart2
+///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+///     .unwrap();
+/// let (mut tx, _) = uart.split();
+///
+/// writeln!(tx, "booted in {} ms", 42).unwrap();
+/// tx.flush_completely();
+/// ```
+impl<M> core::fmt::Write for Tx<M>
+where
+    M: Unsigned,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Blocks until the TX FIFO takes each byte
+impl<M> core::fmt::Write for UART<M>
+where
+    M: Unsigned,
+{
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{buffered::mock::MockFifo, ReadErrorFlags};
+    use super::{deadline, read_until, write_until, BlockingError, ReadError};
+    use crate::gpio::debounce::Now;
+    use core::cell::Cell;
+    use core::time::Duration;
+
+    /// A clock that advances one microsecond each time it's read
+    struct Clock(Cell<u32>);
+
+    impl Now for Clock {
+        fn now(&self) -> u32 {
+            let now = self.0.get();
+            self.0.set(now.wrapping_add(1));
+            now
+        }
+        fn tick(&self) -> Duration {
+            Duration::from_micros(1)
+        }
+    }
+
+    #[test]
+    fn deadline_wraps() {
+        let clock = Clock(Cell::new(u32::max_value() - 1));
+        let mut expired = deadline(&clock, Duration::from_micros(3));
+        assert!(!expired());
+        assert!(!expired());
+        assert!(expired());
+    }
+
+    #[test]
+    fn write_times_out() {
+        let mut fifo = MockFifo {
+            tx_space: 2,
+            ..Default::default()
+        };
+        assert_eq!(write_until(&mut fifo, b"abcd", || true), Err(2));
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"ab");
+    }
+
+    #[test]
+    fn write_polls_until_expired() {
+        let mut fifo = MockFifo {
+            tx_space: 1,
+            ..Default::default()
+        };
+        let mut checks = 0;
+        let expired = || {
+            checks += 1;
+            checks == 3
+        };
+        assert_eq!(write_until(&mut fifo, b"abc", expired), Err(1));
+        assert_eq!(checks, 3);
+
+        fifo.tx_space = 8;
+        assert_eq!(write_until(&mut fifo, b"bc", || true), Ok(()));
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"abc");
+    }
+
+    #[test]
+    fn read_stops_at_error() {
+        let mut fifo = MockFifo {
+            received: [Some(Ok(1)), Some(Err(ReadErrorFlags::PARITY)), None, None],
+            ..Default::default()
+        };
+        let mut data = [0; 3];
+        assert_eq!(
+            read_until(&mut fifo, &mut data, || true),
+            Err(BlockingError::Read(ReadError {
+                flags: ReadErrorFlags::PARITY,
+                byte: None,
+            }))
+        );
+        assert_eq!(data, [1, 0, 0]);
+    }
+
+    #[test]
+    fn read_times_out() {
+        let mut fifo = MockFifo {
+            received: [Some(Ok(1)), Some(Ok(2)), None, None],
+            ..Default::default()
+        };
+        let mut data = [0; 3];
+        assert_eq!(
+            read_until(&mut fifo, &mut data, || true),
+            Err(BlockingError::Timeout(2))
+        );
+        assert_eq!(data, [1, 2, 0]);
+
+        fifo.received = [Some(Ok(3)), None, None, None];
+        fifo.received_idx = 0;
+        let mut data = [0; 1];
+        assert_eq!(read_until(&mut fifo, &mut data, || false), Ok(()));
+        assert_eq!(data, [3]);
+    }
+}
@@ -0,0 +1,469 @@
+//! Interrupt-driven UART with software buffers
+
+use super::{ReadError, ReadErrorFlags, Rx, Tx, UART};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use embedded_hal::serial;
+
+/// An interrupt-driven UART, with software TX and RX buffers
+///
+/// `Buffered` sits between the raw `embedded_hal` reads and writes, and DMA.
+/// [`write()`](#method.write) copies bytes into the TX buffer, and
+/// [`read()`](#method.read) copies bytes out of the RX buffer. Neither blocks.
+/// The UART's interrupt moves bytes between the buffers and the hardware FIFOs;
+/// call [`on_interrupt()`](#method.on_interrupt) from the `LPUART[N]` handler.
+///
+/// The methods take `&mut self`, so share the `Buffered` UART with its interrupt
+/// handler through a critical section, or an RTIC resource. Each `Buffered` UART
+/// only touches its own UART, so other UARTs may use DMA at the same time.
+///
+/// Bytes that arrive when the RX buffer is full are dropped, and counted by
+/// [`dropped()`](#method.dropped). Receive errors accumulate in
+/// [`take_errors()`](#method.take_errors), and their bytes are dropped.
+///
+/// # Example
+///
+/// An echo console on LPUART2.
+///
+/// ```no_run
+/// use core::cell::RefCell;
+/// use cortex_m::interrupt::Mutex;
+/// use imxrt1060_hal::{iomuxc::consts::U2, ral::interrupt, uart::Buffered};
+///
+/// static CONSOLE: Mutex<RefCell<Option<Buffered<U2, 256, 64>>>> =
+///     Mutex::new(RefCell::new(None));
+///
+/// #[cortex_m_rt::interrupt]
+/// fn LPUART2() {
+///     cortex_m::interrupt::free(|cs| {
+///         if let Some(console) = CONSOLE.borrow(cs).borrow_mut().as_mut() {
+///             console.on_interrupt();
+///         }
+///     });
+/// }
+///
+/// static mut TX_BUFFER: [u8; 256] = [0; 256];
+/// static mut RX_BUFFER: [u8; 64] = [0; 64];
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let uart = uarts
+///     .uart2
+///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+///     .unwrap();
+///
+/// // Safety: the buffers are only used here
+/// let console = unsafe { Buffered::new(uart, &mut TX_BUFFER, &mut RX_BUFFER) };
+/// cortex_m::interrupt::free(|cs| *CONSOLE.borrow(cs).borrow_mut() = Some(console));
+/// unsafe { cortex_m::peripheral::NVIC::unmask(interrupt::LPUART2) };
+///
+/// let mut line = [0; 64];
+/// loop {
+///     cortex_m::interrupt::free(|cs| {
+///         if let Some(console) = CONSOLE.borrow(cs).borrow_mut().as_mut() {
+///             let len = console.read(&mut line);
+///             console.write(&line[..len]);
+///         }
+///     });
+///     cortex_m::asm::wfi();
+/// }
+/// ```
+pub struct Buffered<M: Unsigned, const TX: usize, const RX: usize> {
+    uart: UART<M>,
+    tx: Ring<'static, TX>,
+    rx: Ring<'static, RX>,
+    dropped: usize,
+    errors: ReadErrorFlags,
+    /// Running count of received bytes, including dropped bytes
+    received: usize,
+}
+
+impl<M: Unsigned, const TX: usize, const RX: usize> Buffered<M, TX, RX> {
+    /// Buffer the `uart` with the `tx` and `rx` buffers
+    ///
+    /// Enables the UART's receive interrupt. You're responsible for unmasking the
+    /// UART's interrupt in the NVIC.
+    pub fn new(mut uart: UART<M>, tx: &'static mut [u8; TX], rx: &'static mut [u8; RX]) -> Self {
+        uart.set_receiver_interrupt(Some(0));
+        Buffered {
+            uart,
+            tx: Ring::new(tx),
+            rx: Ring::new(rx),
+            dropped: 0,
+            errors: ReadErrorFlags::empty(),
+            received: 0,
+        }
+    }
+
+    /// Copy `data` into the TX buffer, returning the number of bytes copied
+    ///
+    /// Copies fewer bytes than `data` when the TX buffer fills.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let written = self.tx.write(data);
+        if written > 0 {
+            // The interrupt fires as soon as the TX FIFO has space
+            self.uart.set_tx_interrupt(true);
+        }
+        written
+    }
+
+    /// Copy received bytes into `data`, returning the number of bytes copied
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        self.rx.read(data)
+    }
+
+    /// Returns the number of bytes in the RX buffer
+    pub fn rx_len(&self) -> usize {
+        self.rx.len
+    }
+
+    /// Returns `WouldBlock` until the TX buffer is empty, and the UART has sent
+    /// its last byte
+    pub fn flush(&mut self) -> nb::Result<(), core::convert::Infallible> {
+        let complete = ral::read_reg!(ral::lpuart, self.uart.reg, STAT, TC == TC_1);
+        if self.tx.is_empty() && complete {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Move bytes between the hardware FIFOs and the software buffers
+    ///
+    /// Call this from the UART's interrupt handler.
+    pub fn on_interrupt(&mut self) {
+        let buffered = self.rx.len;
+        let (dropped, errors) = service(&mut self.uart, &mut self.tx, &mut self.rx);
+        self.dropped = self.dropped.saturating_add(dropped);
+        self.errors |= errors;
+        self.received = self.received.wrapping_add(self.rx.len - buffered + dropped);
+    }
+
+    /// Returns the running count of received bytes, which wraps
+    pub(super) fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Returns the number of received bytes that were dropped, because the RX
+    /// buffer was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns the receive errors since the last call, and clears them
+    pub fn take_errors(&mut self) -> ReadErrorFlags {
+        core::mem::replace(&mut self.errors, ReadErrorFlags::empty())
+    }
+
+    /// Disable the UART's interrupts, and release the UART and the buffers
+    ///
+    /// Bytes that are still in the buffers are lost.
+    pub fn release(mut self) -> (UART<M>, &'static mut [u8; TX], &'static mut [u8; RX]) {
+        self.uart.set_tx_interrupt(false);
+        self.uart.set_receiver_interrupt(None);
+        (self.uart, self.tx.buffer, self.rx.buffer)
+    }
+}
+
+impl<M: Unsigned> UART<M> {
+    /// Enable or disable the interrupt when the TX FIFO has space
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        ral::modify_reg!(ral::lpuart, self.reg, CTRL, TIE: u32::from(enable));
+    }
+
+    /// Write as many bytes of `data` as the TX FIFO can take, and return the
+    /// number of bytes written
+    ///
+    /// `write()` checks for space before each byte, and it only finds space once
+    /// the FIFO drains to its [watermark](#method.set_tx_fifo_watermark). This
+    /// fills the FIFO's [free space](#method.tx_fifo_space) in one call.
+    pub fn write_fifo(&mut self, data: &[u8]) -> usize {
+        fill(self, data.iter().copied())
+    }
+
+    /// Read the received bytes into `data`, and return the number of bytes read
+    ///
+    /// Reads until `data` is full, or the RX FIFO is empty. If a byte has an error,
+    /// the read stops, and the error includes the number of bytes read before it.
+    pub fn read_fifo(&mut self, data: &mut [u8]) -> Result<usize, (usize, ReadError)> {
+        drain(self, data)
+    }
+}
+
+impl<M: Unsigned> Tx<M> {
+    /// Write as many bytes of `data` as the TX FIFO can take
+    ///
+    /// See [`UART::write_fifo()`](struct.UART.html#method.write_fifo).
+    pub fn write_fifo(&mut self, data: &[u8]) -> usize {
+        self.0.write_fifo(data)
+    }
+}
+
+impl<M: Unsigned> Rx<M> {
+    /// Read the received bytes into `data`
+    ///
+    /// See [`UART::read_fifo()`](struct.UART.html#method.read_fifo).
+    pub fn read_fifo(&mut self, data: &mut [u8]) -> Result<usize, (usize, ReadError)> {
+        self.0.read_fifo(data)
+    }
+}
+
+/// The hardware side of a buffered UART
+pub(super) trait Fifo {
+    /// Returns the number of bytes that the transmitter can take
+    fn tx_space(&self) -> usize;
+    /// Give the transmitter a byte
+    fn write(&mut self, byte: u8);
+    /// Returns the next received byte
+    fn read(&mut self) -> nb::Result<u8, ReadError>;
+    /// Enable or disable the interrupt when the transmitter has space
+    fn set_tx_interrupt(&mut self, enable: bool);
+}
+
+impl<M: Unsigned> Fifo for UART<M> {
+    fn tx_space(&self) -> usize {
+        self.tx_fifo_space()
+    }
+    fn write(&mut self, byte: u8) {
+        ral::write_reg!(ral::lpuart, self.reg, DATA, u32::from(byte));
+    }
+    fn read(&mut self) -> nb::Result<u8, ReadError> {
+        serial::Read::read(self)
+    }
+    fn set_tx_interrupt(&mut self, enable: bool) {
+        UART::set_tx_interrupt(self, enable);
+    }
+}
+
+/// Move bytes between the FIFOs and the rings
+///
+/// Returns the number of received bytes that didn't fit in `rx`, and the
+/// receive errors.
+fn service<F: Fifo, const TX: usize, const RX: usize>(
+    fifo: &mut F,
+    tx: &mut Ring<'_, TX>,
+    rx: &mut Ring<'_, RX>,
+) -> (usize, ReadErrorFlags) {
+    let mut dropped = 0;
+    let mut errors = ReadErrorFlags::empty();
+    loop {
+        match fifo.read() {
+            Ok(byte) => {
+                if !rx.push(byte) {
+                    dropped += 1;
+                }
+            }
+            Err(nb::Error::Other(err)) => errors |= err.flags,
+            Err(nb::Error::WouldBlock) => break,
+        }
+    }
+    fill(fifo, core::iter::from_fn(|| tx.pop()));
+    if tx.is_empty() {
+        fifo.set_tx_interrupt(false);
+    }
+    (dropped, errors)
+}
+
+/// Write bytes until the transmitter is full, and return the number of bytes written
+///
+/// Only takes the bytes that fit from `bytes`.
+pub(super) fn fill<F: Fifo>(fifo: &mut F, bytes: impl Iterator<Item = u8>) -> usize {
+    let space = fifo.tx_space();
+    bytes.take(space).map(|byte| fifo.write(byte)).count()
+}
+
+/// Read bytes until `data` is full, or there's nothing to read
+fn drain<F: Fifo>(fifo: &mut F, data: &mut [u8]) -> Result<usize, (usize, ReadError)> {
+    for (read, slot) in data.iter_mut().enumerate() {
+        match fifo.read() {
+            Ok(byte) => *slot = byte,
+            Err(nb::Error::WouldBlock) => return Ok(read),
+            Err(nb::Error::Other(err)) => return Err((read, err)),
+        }
+    }
+    Ok(data.len())
+}
+
+/// A byte ring over a borrowed buffer
+struct Ring<'a, const N: usize> {
+    buffer: &'a mut [u8; N],
+    /// Index of the oldest byte
+    read: usize,
+    len: usize,
+}
+
+impl<'a, const N: usize> Ring<'a, N> {
+    fn new(buffer: &'a mut [u8; N]) -> Self {
+        Ring {
+            buffer,
+            read: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `false` if the ring is full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buffer[(self.read + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buffer[self.read];
+        self.read = (self.read + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn write(&mut self, data: &[u8]) -> usize {
+        data.iter().take_while(|&&byte| self.push(byte)).count()
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> usize {
+        data.iter_mut()
+            .zip(core::iter::from_fn(|| self.pop()))
+            .map(|(slot, byte)| *slot = byte)
+            .count()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod mock {
+    use super::{Fifo, ReadError, ReadErrorFlags};
+
+    /// Records what the UART sends, and receives canned bytes
+    #[derive(Default)]
+    pub(crate) struct MockFifo {
+        pub(crate) sent: [u8; 8],
+        pub(crate) sent_len: usize,
+        /// Bytes the transmitter can take before it's full
+        pub(crate) tx_space: usize,
+        pub(crate) tie: bool,
+        pub(crate) received: [Option<Result<u8, ReadErrorFlags>>; 4],
+        pub(crate) received_idx: usize,
+    }
+
+    impl Fifo for MockFifo {
+        fn tx_space(&self) -> usize {
+            self.tx_space
+        }
+        fn write(&mut self, byte: u8) {
+            self.sent[self.sent_len] = byte;
+            self.sent_len += 1;
+            self.tx_space -= 1;
+        }
+        fn read(&mut self) -> nb::Result<u8, ReadError> {
+            let next = self
+                .received
+                .get_mut(self.received_idx)
+                .and_then(Option::take)
+                .ok_or(nb::Error::WouldBlock)?;
+            self.received_idx += 1;
+            next.map_err(|flags| nb::Error::Other(ReadError { flags, byte: None }))
+        }
+        fn set_tx_interrupt(&mut self, enable: bool) {
+            self.tie = enable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{drain, fill, mock::MockFifo, service, ReadErrorFlags, Ring};
+
+    #[test]
+    fn ring() {
+        let mut buffer = [0; 4];
+        let mut ring = Ring::new(&mut buffer);
+        assert_eq!(ring.write(b"abc"), 3);
+        let mut out = [0; 2];
+        assert_eq!(ring.read(&mut out), 2);
+        assert_eq!(&out, b"ab");
+        // Wraps around the end of the buffer
+        assert_eq!(ring.write(b"defg"), 3);
+        let mut out = [0; 8];
+        assert_eq!(ring.read(&mut out), 4);
+        assert_eq!(&out[..4], b"cdef");
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn service_moves_bytes() {
+        let (mut tx_buffer, mut rx_buffer) = ([0; 4], [0; 2]);
+        let mut tx = Ring::new(&mut tx_buffer);
+        let mut rx = Ring::new(&mut rx_buffer);
+        let mut fifo = MockFifo {
+            tx_space: 2,
+            tie: true,
+            received: [
+                Some(Ok(1)),
+                Some(Err(ReadErrorFlags::FRAME_ERROR)),
+                Some(Ok(2)),
+                Some(Ok(3)),
+            ],
+            ..Default::default()
+        };
+        tx.write(b"xyz");
+
+        let (dropped, errors) = service(&mut fifo, &mut tx, &mut rx);
+        assert_eq!((dropped, errors), (1, ReadErrorFlags::FRAME_ERROR));
+        let mut out = [0; 4];
+        assert_eq!(rx.read(&mut out), 2);
+        assert_eq!(&out[..2], &[1, 2]);
+        // The FIFO filled, so the interrupt stays on
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"xy");
+        assert!(fifo.tie);
+
+        fifo.tx_space = 2;
+        assert_eq!(
+            service(&mut fifo, &mut tx, &mut rx),
+            (0, ReadErrorFlags::empty())
+        );
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"xyz");
+        assert!(!fifo.tie);
+    }
+
+    #[test]
+    fn batches() {
+        let mut fifo = MockFifo {
+            tx_space: 3,
+            received: [
+                Some(Ok(1)),
+                Some(Ok(2)),
+                Some(Err(ReadErrorFlags::PARITY)),
+                Some(Ok(3)),
+            ],
+            ..Default::default()
+        };
+        // Fills the free space in one call, and leaves the rest
+        let mut data = b"abcd".iter().copied();
+        assert_eq!(fill(&mut fifo, &mut data), 3);
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"abc");
+        assert_eq!(data.next(), Some(b'd'));
+        assert_eq!(fill(&mut fifo, b"d".iter().copied()), 0);
+
+        let mut out = [0; 4];
+        let (read, err) = drain(&mut fifo, &mut out).unwrap_err();
+        assert_eq!((read, err.flags), (2, ReadErrorFlags::PARITY));
+        assert_eq!(&out[..2], &[1, 2]);
+        assert_eq!(drain(&mut fifo, &mut out), Ok(1));
+        assert_eq!(out[0], 3);
+        assert_eq!(drain(&mut fifo, &mut out), Ok(0));
+        assert_eq!(drain(&mut fifo, &mut []), Ok(0));
+    }
+}
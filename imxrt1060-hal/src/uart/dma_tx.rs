@@ -0,0 +1,223 @@
+//! DMA transmits of static data, and of formatted text
+
+use super::{Tx, UART};
+use crate::dma::{self, peripheral::Destination};
+use crate::iomuxc::consts::Unsigned;
+use core::fmt::{self, Write};
+
+/// The size of each UART's staging buffer for formatted DMA writes
+///
+/// A [`dma_write_fmt()`](../dma/struct.Peripheral.html#method.dma_write_fmt) sends
+/// at most this many bytes.
+pub const DMA_STAGING_LEN: usize = 256;
+
+/// The staging buffers for formatted DMA writes, one for each UART
+static STAGING: [dma::Buffer<[u8; DMA_STAGING_LEN]>; 8] = [
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+    dma::Buffer::new([0; DMA_STAGING_LEN]),
+];
+
+/// An error from a UART DMA write
+#[derive(Debug)]
+pub enum DmaWriteError {
+    /// The formatted text is longer than [`DMA_STAGING_LEN`](constant.DMA_STAGING_LEN.html),
+    /// or a formatting trait returned an error. Nothing was sent.
+    Format,
+    /// The DMA controller cannot read the data
+    InaccessibleMemory,
+    /// The DMA channel didn't start the transfer
+    Dma(dma::Error),
+}
+
+/// Formats into a byte slice, and fails once the slice is full
+struct Cursor<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let dst = self.buffer.get_mut(self.len..end).ok_or(fmt::Error)?;
+        dst.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Format `args` into `buffer`, and return the number of bytes written
+///
+/// Returns `None` if the text doesn't fit, or if formatting fails.
+fn format_into(buffer: &mut [u8], args: fmt::Arguments) -> Option<usize> {
+    let mut cursor = Cursor { buffer, len: 0 };
+    cursor.write_fmt(args).ok().map(|()| cursor.len)
+}
+
+/// Complete a finished transfer, so the channel may start the next one
+fn complete_finished<P>(tx: &mut dma::Peripheral<P, u8, dma::ReadOnly<u8>>)
+where
+    P: Destination<u8>,
+{
+    if tx.is_transfer_complete() {
+        // Any staged data is released; static data is still static
+        let _ = tx.transfer_complete();
+    }
+}
+
+fn start<P>(
+    tx: &mut dma::Peripheral<P, u8, dma::ReadOnly<u8>>,
+    source: dma::ReadOnly<u8>,
+) -> nb::Result<(), DmaWriteError>
+where
+    P: Destination<u8>,
+{
+    if source.as_elements().is_empty() {
+        return Ok(());
+    }
+    tx.start_transfer(source).map_err(|(_, err)| match err {
+        dma::Error::ScheduledTransfer => nb::Error::WouldBlock,
+        err => nb::Error::Other(DmaWriteError::Dma(err)),
+    })
+}
+
+fn dma_write<P>(
+    tx: &mut dma::Peripheral<P, u8, dma::ReadOnly<u8>>,
+    data: &'static [u8],
+) -> nb::Result<(), DmaWriteError>
+where
+    P: Destination<u8>,
+{
+    complete_finished(tx);
+    let source = dma::ReadOnly::new(data)
+        .map_err(|_| nb::Error::Other(DmaWriteError::InaccessibleMemory))?;
+    start(tx, source)
+}
+
+fn dma_write_fmt<P, M>(
+    tx: &mut dma::Peripheral<P, u8, dma::ReadOnly<u8>>,
+    args: fmt::Arguments,
+) -> nb::Result<(), DmaWriteError>
+where
+    P: Destination<u8>,
+    M: Unsigned,
+{
+    complete_finished(tx);
+    let mut formatted = true;
+    let source = dma::ReadOnly::stage(&STAGING[M::USIZE - 1], |buffer| {
+        format_into(buffer, args).unwrap_or_else(|| {
+            formatted = false;
+            0
+        })
+    })
+    .map_err(|err| match err {
+        // The last formatted write is still sending
+        dma::LinearError::BufferTaken => nb::Error::WouldBlock,
+        dma::LinearError::InaccessibleMemory => nb::Error::Other(DmaWriteError::InaccessibleMemory),
+    })?;
+    if !formatted {
+        return Err(nb::Error::Other(DmaWriteError::Format));
+    }
+    start(tx, source)
+}
+
+impl<M> dma::Peripheral<UART<M>, u8, dma::ReadOnly<u8>>
+where
+    M: Unsigned,
+{
+    /// Send `data` over DMA, without copying it
+    ///
+    /// `data` may be a string constant in flash. Returns `WouldBlock` while an earlier
+    /// write is still sending. If the earlier write finished, this completes it, so
+    /// [`transfer_complete()`](struct.Peripheral.html#method.transfer_complete) won't
+    /// return its source.
+    pub fn dma_write(&mut self, data: &'static [u8]) -> nb::Result<(), DmaWriteError> {
+        dma_write(self, data)
+    }
+
+    /// Format `args` into the UART's staging buffer, and send the text over DMA
+    ///
+    /// The staging buffer holds [`DMA_STAGING_LEN`](../uart/constant.DMA_STAGING_LEN.html)
+    /// bytes. If the text is longer, this sends nothing, and returns
+    /// [`DmaWriteError::Format`](../uart/enum.DmaWriteError.html#variant.Format). Returns
+    /// `WouldBlock` while an earlier write is still sending, so the staging buffer is never
+    /// overwritten during a transfer. Use the `format_args!` macro to create `args`.
+    ///
+    /// # Example
+    ///
+    /// Send a fixed banner, then formatted readings, over LPUART2's TX DMA.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, ReadOnly};
+    /// # fn read_sensor() -> i32 { 0 }
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    ///
+    /// let mut channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let mut log: dma::Peripheral<_, u8, ReadOnly<u8>> =
+    ///     dma::transfer_u8(uart, channels[7].take().unwrap());
+    ///
+    /// nb::block!(log.dma_write(b"sensor log\r\n")).unwrap();
+    /// for sample in 0.. {
+    ///     let reading = read_sensor();
+    ///     nb::block!(log.dma_write_fmt(format_args!("{}: {}\r\n", sample, reading))).unwrap();
+    /// }
+    /// ```
+    pub fn dma_write_fmt(&mut self, args: fmt::Arguments) -> nb::Result<(), DmaWriteError> {
+        dma_write_fmt::<_, M>(self, args)
+    }
+}
+
+impl<M> dma::Peripheral<Tx<M>, u8, dma::ReadOnly<u8>>
+where
+    M: Unsigned,
+{
+    /// Send `data` over DMA, without copying it
+    ///
+    /// See the `dma_write()` of a whole `UART`.
+    pub fn dma_write(&mut self, data: &'static [u8]) -> nb::Result<(), DmaWriteError> {
+        dma_write(self, data)
+    }
+
+    /// Format `args` into the UART's staging buffer, and send the text over DMA
+    ///
+    /// See the `dma_write_fmt()` of a whole `UART`.
+    pub fn dma_write_fmt(&mut self, args: fmt::Arguments) -> nb::Result<(), DmaWriteError> {
+        dma_write_fmt::<_, M>(self, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_into;
+
+    #[test]
+    fn staging_format() {
+        let mut buffer = [0; 8];
+        assert_eq!(
+            format_into(&mut buffer, format_args!("{}-{}", 12, "ab")),
+            Some(5)
+        );
+        assert_eq!(&buffer[..5], b"12-ab");
+        // Exactly full
+        assert_eq!(format_into(&mut buffer, format_args!("{:08}", 7)), Some(8));
+        assert_eq!(&buffer, b"00000007");
+        // Too long
+        assert_eq!(format_into(&mut buffer, format_args!("{:09}", 7)), None);
+        assert_eq!(format_into(&mut buffer, format_args!("")), Some(0));
+    }
+}
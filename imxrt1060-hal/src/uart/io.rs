@@ -0,0 +1,193 @@
+//! `embedded-io` traits
+//!
+//! Enable these implementations with the `"embedded-io"` feature. The reads and
+//! writes block, like the helpers in `blocking`.
+
+use super::{
+    buffered::{fill, Fifo},
+    is_fifo_ready, ReadError, ReadErrorFlags, Rx, Status, Tx, UART,
+};
+use crate::iomuxc::consts::Unsigned;
+use core::convert::Infallible;
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+
+/// Overruns lost data; the other errors arrived with a corrupt byte
+impl embedded_io::Error for ReadError {
+    fn kind(&self) -> ErrorKind {
+        kind(self.flags)
+    }
+}
+
+fn kind(flags: ReadErrorFlags) -> ErrorKind {
+    if flags.contains(ReadErrorFlags::OVERRUN) {
+        ErrorKind::Other
+    } else {
+        ErrorKind::InvalidData
+    }
+}
+
+/// Block until the TX FIFO has space, then write as many bytes as fit
+///
+/// Returns the number of bytes written, which is only zero if `buf` is empty.
+fn write<F: Fifo>(fifo: &mut F, buf: &[u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    loop {
+        let written = fill(fifo, buf.iter().copied());
+        if written > 0 {
+            return written;
+        }
+    }
+}
+
+/// Block until one byte arrives
+///
+/// Reading one byte at a time means that a receive error never discards the
+/// good bytes that arrived before it.
+fn read<M: Unsigned>(uart: &mut UART<M>, buf: &mut [u8]) -> Result<usize, ReadError> {
+    match buf.first_mut() {
+        Some(first) => {
+            *first = nb::block!(embedded_hal::serial::Read::read(uart))?;
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+/// The next read won't block, since there's a byte, or an overrun to report
+fn read_ready<M: Unsigned>(uart: &UART<M>) -> bool {
+    uart.rx_fifo_count() > 0 || uart.status().contains(Status::OVERRUN)
+}
+
+impl<M: Unsigned> ErrorType for UART<M> {
+    type Error = ReadError;
+}
+
+/// # Example
+///
+/// A driver that only needs an `embedded_io::Write`.
+///
+/// ```no_run
+/// use embedded_io::Write;
+///
+/// fn greet<W: Write>(out: &mut W) -> Result<(), W::Error> {
+///     out.write_all(b"hello\r\n")?;
+///     out.flush()
+/// }
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let mut uart = uarts
+///     .uart2
+///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+///     .unwrap();
+/// greet(&mut uart).unwrap();
+/// ```
+impl<M: Unsigned> Write for UART<M> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(write(self, buf))
+    }
+
+    /// Blocks until the UART sends the last stop bit
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_completely();
+        Ok(())
+    }
+}
+
+impl<M: Unsigned> WriteReady for UART<M> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(is_fifo_ready(self.status()))
+    }
+}
+
+impl<M: Unsigned> Read for UART<M> {
+    /// Blocks until a byte arrives, then reads that one byte
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        read(self, buf)
+    }
+}
+
+impl<M: Unsigned> ReadReady for UART<M> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(read_ready(self))
+    }
+}
+
+impl<M: Unsigned> ErrorType for Tx<M> {
+    type Error = Infallible;
+}
+
+impl<M: Unsigned> Write for Tx<M> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(write(&mut self.0, buf))
+    }
+
+    /// Blocks until the UART sends the last stop bit
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_completely();
+        Ok(())
+    }
+}
+
+impl<M: Unsigned> WriteReady for Tx<M> {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(is_fifo_ready(self.0.status()))
+    }
+}
+
+impl<M: Unsigned> ErrorType for Rx<M> {
+    type Error = ReadError;
+}
+
+impl<M: Unsigned> Read for Rx<M> {
+    /// Blocks until a byte arrives, then reads that one byte
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        read(&mut self.0, buf)
+    }
+}
+
+impl<M: Unsigned> ReadReady for Rx<M> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(read_ready(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::buffered::mock::MockFifo, kind, write, ErrorKind, ReadErrorFlags};
+
+    #[test]
+    fn short_write() {
+        // The FIFO takes three of the five bytes
+        let mut fifo = MockFifo {
+            tx_space: 3,
+            ..Default::default()
+        };
+        assert_eq!(write(&mut fifo, b"hello"), 3);
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"hel");
+
+        fifo.tx_space = 8;
+        assert_eq!(write(&mut fifo, b"lo"), 2);
+        assert_eq!(&fifo.sent[..fifo.sent_len], b"hello");
+        assert_eq!(write(&mut fifo, b""), 0);
+        assert_eq!(fifo.sent_len, 5);
+    }
+
+    #[test]
+    fn error_kinds() {
+        assert_eq!(kind(ReadErrorFlags::OVERRUN), ErrorKind::Other);
+        assert_eq!(
+            kind(ReadErrorFlags::OVERRUN | ReadErrorFlags::PARITY),
+            ErrorKind::Other
+        );
+        assert_eq!(kind(ReadErrorFlags::PARITY), ErrorKind::InvalidData);
+        assert_eq!(kind(ReadErrorFlags::FRAME_ERROR), ErrorKind::InvalidData);
+        assert_eq!(kind(ReadErrorFlags::NOISY), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,371 @@
+//! Loopback and single-wire modes
+
+use super::{DataBits, FlowControl, ReadError, Status, Uninit, UART};
+use crate::ccm;
+use crate::iomuxc::{consts::Unsigned, uart};
+use crate::ral;
+use core::convert::Infallible;
+use embedded_hal::serial;
+
+/// The bytes that [`self_test()`](struct.UART.html#method.self_test) sends
+const PATTERN: [u8; 6] = [0x55, 0xAA, 0x00, 0xFF, 0x0F, 0xF0];
+
+/// An error from the UART [`self_test()`](struct.UART.html#method.self_test)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SelfTestError {
+    /// The receiver didn't receive `sent`
+    Missing {
+        /// The byte that was sent
+        sent: u8,
+    },
+    /// The receiver received a different byte
+    Mismatch {
+        /// The byte that was sent
+        sent: u8,
+        /// The byte that was received
+        received: u8,
+    },
+    /// The receiver received a byte with an error
+    Read(ReadError),
+}
+
+/// The direction of a [`SingleWire`](struct.SingleWire.html) UART
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The UART drives the wire
+    Transmit,
+    /// The UART listens to the wire
+    Receive,
+}
+
+/// Returned when a [`SingleWire`](struct.SingleWire.html) UART writes while it's
+/// receiving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongDirection;
+
+/// How the receiver is connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// The receiver listens to the RX pin
+    Normal,
+    /// The receiver listens to the transmitter, inside the UART
+    Loopback,
+    /// The receiver and transmitter share the TX pin
+    SingleWire(Direction),
+}
+
+impl Mode {
+    /// Returns the CTRL fields (LOOPS, RSRC, TXDIR) that select this mode
+    fn fields(self) -> (u32, u32, u32) {
+        match self {
+            Mode::Normal => (0, 0, 0),
+            Mode::Loopback => (1, 0, 0),
+            Mode::SingleWire(Direction::Receive) => (1, 1, 0),
+            Mode::SingleWire(Direction::Transmit) => (1, 1, 1),
+        }
+    }
+
+    fn from_fields(loops: u32, rsrc: u32, txdir: u32) -> Self {
+        match (loops != 0, rsrc != 0, txdir != 0) {
+            (false, _, _) => Mode::Normal,
+            (true, false, _) => Mode::Loopback,
+            (true, true, false) => Mode::SingleWire(Direction::Receive),
+            (true, true, true) => Mode::SingleWire(Direction::Transmit),
+        }
+    }
+}
+
+/// Compare a received byte to the byte that was sent
+fn check(sent: u8, mask: u8, received: nb::Result<u8, ReadError>) -> Result<(), SelfTestError> {
+    match received {
+        Ok(received) if received == sent & mask => Ok(()),
+        Ok(received) => Err(SelfTestError::Mismatch { sent, received }),
+        Err(nb::Error::WouldBlock) => Err(SelfTestError::Missing { sent }),
+        Err(nb::Error::Other(err)) => Err(SelfTestError::Read(err)),
+    }
+}
+
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Connect the receiver to the transmitter, inside the UART
+    ///
+    /// In loopback mode, the receiver receives every byte that the transmitter
+    /// sends. The TX pin still sends the bytes, and the RX pin is ignored.
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    pub fn set_loopback(&mut self, loopback: bool) {
+        self.set_mode(if loopback {
+            Mode::Loopback
+        } else {
+            Mode::Normal
+        });
+    }
+
+    /// Returns `true` if the UART is in loopback mode
+    pub fn is_loopback(&self) -> bool {
+        self.mode() == Mode::Loopback
+    }
+
+    /// Send a pattern in loopback mode, and check that the receiver receives it
+    ///
+    /// The self-test checks the UART's clock, baud rate generator, transmitter,
+    /// and receiver, without any external wiring. It disables flow control for the
+    /// test. When it returns, the UART has the mode and flow control that it had
+    /// before the test. The test blocks for about 60 bit times.
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    ///
+    /// match uart.self_test() {
+    ///     Ok(()) => log::info!("UART2 passed"),
+    ///     Err(err) => log::error!("UART2 failed: {:?}", err),
+    /// }
+    /// ```
+    pub fn self_test(&mut self) -> Result<(), SelfTestError> {
+        let (mode, flow_control) = (self.mode(), self.flow_control());
+        self.set_flow_control(FlowControl::None);
+        self.set_mode(Mode::Loopback);
+
+        let mask = match self.data_bits() {
+            DataBits::Seven => 0x7F,
+            _ => 0xFF,
+        };
+        let result = PATTERN.iter().try_for_each(|&sent| {
+            self.write_all(&[sent]).map_err(|never| match never {})?;
+            // The receiver samples the stop bit before the transmitter finishes it
+            self.flush_completely();
+            check(sent, mask, serial::Read::read(self))
+        });
+
+        self.set_mode(mode);
+        self.set_flow_control(flow_control);
+        result
+    }
+
+    fn mode(&self) -> Mode {
+        let (loops, rsrc, txdir) = ral::read_reg!(ral::lpuart, self.reg, CTRL, LOOPS, RSRC, TXDIR);
+        Mode::from_fields(loops, rsrc, txdir)
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        let (loops, rsrc, txdir) = mode.fields();
+        self.while_disabled(|this| {
+            ral::modify_reg!(
+                ral::lpuart,
+                this.reg,
+                CTRL,
+                LOOPS: loops,
+                RSRC: rsrc,
+                TXDIR: txdir
+            );
+        });
+    }
+}
+
+impl<M> Uninit<M>
+where
+    M: Unsigned,
+{
+    /// Initializes a half-duplex UART on the `tx` pin, which both sends and receives
+    ///
+    /// Specify the initial baud rate of the bus with `baud`. The UART starts out
+    /// receiving. The RX pin is unused, and stays available.
+    pub fn init_single_wire<TX>(
+        self,
+        mut tx: TX,
+        baud: u32,
+    ) -> Result<SingleWire<M>, ccm::uart::TimingsError>
+    where
+        TX: uart::Pin<Direction = uart::TX, Module = M>,
+    {
+        crate::iomuxc::uart::prepare(&mut tx);
+        let mut uart = UART::start(self.reg, self.clock, baud)?;
+        uart.set_mode(Mode::SingleWire(Direction::Receive));
+        Ok(SingleWire { uart })
+    }
+}
+
+/// A half-duplex UART that sends and receives on its TX pin
+///
+/// The UART either drives the wire, or listens to it. Select the direction before
+/// writing, or reading:
+///
+/// - after [`set_transmit_direction()`](#method.set_transmit_direction), `write()`
+///   sends bytes, and `read()` returns `WouldBlock`.
+/// - after [`set_receive_direction()`](#method.set_receive_direction), `read()`
+///   receives bytes, and `write()` returns [`WrongDirection`](struct.WrongDirection.html).
+///
+/// Create a `SingleWire` UART with [`init_single_wire()`](struct.Uninit.html#method.init_single_wire).
+///
+/// # Example
+///
+/// Send a command, then wait for the response, on LPUART2's TX pin.
+///
+/// ```no_run
+/// use embedded_hal::serial::{Read, Write};
+///
+/// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+/// let uarts = peripherals.uart.clock(
+///     &mut peripherals.ccm.handle,
+///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+/// );
+/// let mut wire = uarts
+///     .uart2
+///     .init_single_wire(peripherals.iomuxc.ad_b1.p02, 115_200)
+///     .unwrap();
+///
+/// wire.set_transmit_direction();
+/// nb::block!(wire.write(0x42)).unwrap();
+/// nb::block!(wire.set_receive_direction()).unwrap();
+/// let response = nb::block!(wire.read()).unwrap();
+/// ```
+pub struct SingleWire<M: Unsigned> {
+    uart: UART<M>,
+}
+
+impl<M> SingleWire<M>
+where
+    M: Unsigned,
+{
+    /// Drive the wire
+    pub fn set_transmit_direction(&mut self) {
+        ral::modify_reg!(ral::lpuart, self.uart.reg, CTRL, TXDIR: 1);
+    }
+
+    /// Listen to the wire, once the UART sends its last stop bit
+    ///
+    /// Returns `WouldBlock` until the transmitter is idle. Then, discards the bytes
+    /// that the UART received from its own transmission, and releases the wire.
+    pub fn set_receive_direction(&mut self) -> nb::Result<(), Infallible> {
+        if self.direction() == Direction::Receive {
+            return Ok(());
+        }
+        self.uart.poll_transmit_complete()?;
+        ral::modify_reg!(ral::lpuart, self.uart.reg, CTRL, TXDIR: 0);
+        ral::modify_reg!(ral::lpuart, self.uart.reg, FIFO, RXFLUSH: RXFLUSH_1);
+        // Safety: we have a mutable receiver
+        unsafe { self.uart.clear_flags(Status::CLEARABLE) };
+        Ok(())
+    }
+
+    /// Returns the direction of the wire
+    pub fn direction(&self) -> Direction {
+        match self.uart.mode() {
+            Mode::SingleWire(direction) => direction,
+            // init_single_wire() selects single-wire mode, and it's never changed
+            _ => unreachable!("single-wire UART is in another mode"),
+        }
+    }
+
+    /// Returns the UART, in normal mode
+    pub fn release(mut self) -> UART<M> {
+        self.uart.set_mode(Mode::Normal);
+        self.uart
+    }
+}
+
+impl<M> serial::Write<u8> for SingleWire<M>
+where
+    M: Unsigned,
+{
+    type Error = WrongDirection;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), WrongDirection> {
+        if self.direction() == Direction::Receive {
+            return Err(nb::Error::Other(WrongDirection));
+        }
+        serial::Write::write(&mut self.uart, word).map_err(would_block)
+    }
+
+    /// Returns `WouldBlock` until the UART sends its last stop bit
+    fn flush(&mut self) -> nb::Result<(), WrongDirection> {
+        self.uart.poll_transmit_complete().map_err(would_block)
+    }
+}
+
+/// Changes the error type of an `nb` error that can only be `WouldBlock`
+fn would_block<E>(err: nb::Error<Infallible>) -> nb::Error<E> {
+    match err {
+        nb::Error::WouldBlock => nb::Error::WouldBlock,
+        nb::Error::Other(never) => match never {},
+    }
+}
+
+impl<M> serial::Read<u8> for SingleWire<M>
+where
+    M: Unsigned,
+{
+    type Error = ReadError;
+
+    fn read(&mut self) -> nb::Result<u8, ReadError> {
+        if self.direction() == Direction::Transmit {
+            return Err(nb::Error::WouldBlock);
+        }
+        serial::Read::read(&mut self.uart)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Direction, Mode, ReadError, SelfTestError};
+    use crate::uart::ReadErrorFlags;
+
+    #[test]
+    fn mode_fields() {
+        for &mode in &[
+            Mode::Normal,
+            Mode::Loopback,
+            Mode::SingleWire(Direction::Receive),
+            Mode::SingleWire(Direction::Transmit),
+        ] {
+            let (loops, rsrc, txdir) = mode.fields();
+            assert_eq!(Mode::from_fields(loops, rsrc, txdir), mode);
+        }
+        // RSRC and TXDIR don't matter outside of single-wire mode
+        assert_eq!(Mode::from_fields(0, 1, 1), Mode::Normal);
+        assert_eq!(Mode::from_fields(1, 0, 1), Mode::Loopback);
+    }
+
+    #[test]
+    fn self_test_check() {
+        assert_eq!(check(0xAA, 0xFF, Ok(0xAA)), Ok(()));
+        assert_eq!(check(0xFF, 0x7F, Ok(0x7F)), Ok(()));
+        assert_eq!(
+            check(0x55, 0xFF, Ok(0x54)),
+            Err(SelfTestError::Mismatch {
+                sent: 0x55,
+                received: 0x54
+            })
+        );
+        assert_eq!(
+            check(0x55, 0xFF, Err(nb::Error::WouldBlock)),
+            Err(SelfTestError::Missing { sent: 0x55 })
+        );
+        let err = ReadError {
+            flags: ReadErrorFlags::FRAME_ERROR,
+            byte: Some(0x55),
+        };
+        assert_eq!(
+            check(0x55, 0xFF, Err(nb::Error::Other(err.clone()))),
+            Err(SelfTestError::Read(err))
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Address matching for multidrop buses
+
+use super::{Status, UART};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+/// The ninth bit, which marks an address word
+const ADDRESS_MARK: u16 = 0x100;
+
+/// How the UART uses its match addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Receive the address words that equal a match address, and the data that
+    /// follows them. Discard the address words that don't match.
+    Address,
+    /// Like `Address`, but the receiver only compares the first word after an
+    /// idle line
+    Idle,
+    /// Receive from a word that equals `addr1`, until a word that equals `addr2`.
+    /// Needs both addresses.
+    OnOff,
+}
+
+/// Address match configuration
+///
+/// See [`set_address_match()`](struct.UART.html#method.set_address_match).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchConfig {
+    /// The first match address, or `None` to disable it
+    pub addr1: Option<u16>,
+    /// The second match address, or `None` to disable it
+    pub addr2: Option<u16>,
+    /// How the receiver uses the addresses
+    pub mode: MatchMode,
+}
+
+impl MatchConfig {
+    /// Returns the nine-bit address word for `address`
+    ///
+    /// The address word has the ninth bit set, so it's distinct from every data word.
+    pub const fn address(address: u8) -> u16 {
+        ADDRESS_MARK | address as u16
+    }
+}
+
+/// The match address that a received word matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matched {
+    /// `addr1`
+    Addr1,
+    /// `addr2`
+    Addr2,
+}
+
+/// Register fields for an address match configuration
+#[derive(Debug, PartialEq, Eq)]
+struct MatchFields {
+    /// MATCH register
+    matches: u32,
+    /// BAUD[MAEN1]
+    maen1: u32,
+    /// BAUD[MAEN2]
+    maen2: u32,
+    /// BAUD[MATCFG]
+    matcfg: u32,
+    /// CTRL[WAKE]; 1 wakes the receiver on an address mark
+    wake: u32,
+}
+
+impl MatchFields {
+    fn new(config: &MatchConfig) -> Self {
+        // MA1 and MA2 are ten bits
+        let addr = |addr: Option<u16>| u32::from(addr.unwrap_or(0) & 0x3FF);
+        MatchFields {
+            matches: addr(config.addr1) | addr(config.addr2) << 16,
+            maen1: u32::from(config.addr1.is_some()),
+            maen2: u32::from(config.addr2.is_some()),
+            matcfg: match config.mode {
+                MatchMode::Address => 0b00,
+                MatchMode::Idle => 0b01,
+                MatchMode::OnOff => 0b10,
+            },
+            wake: u32::from(config.mode == MatchMode::Address),
+        }
+    }
+}
+
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Compare received words to match addresses
+    ///
+    /// Use nine [data bits](#method.set_data_bits), and address words that have the
+    /// ninth bit set; see [`MatchConfig::address()`](struct.MatchConfig.html#method.address).
+    /// Disable address matching with `None` for both addresses. Once the addresses are
+    /// set, put the receiver in [`standby()`](#method.standby) to ignore the bus
+    /// until a matching address arrives.
+    ///
+    /// Calling this method temporarily disables the peripheral, flusing all data
+    /// from *both* TX and RX FIFOs.
+    ///
+    /// # Example
+    ///
+    /// Two nodes share an RS-485 bus. Each node runs a UART with nine data bits, and
+    /// the node at address 0x21 only wakes for its frames. The other node addresses it
+    /// with [`write_address()`](#method.write_address), and follows
+    /// with the frame's data.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::uart::{DataBits, MatchConfig, MatchMode};
+    /// # fn end_of_frame(_: u16) -> bool { true }
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 115_200)
+    ///     .unwrap();
+    /// uart.set_data_bits(DataBits::Nine);
+    /// uart.set_address_match(MatchConfig {
+    ///     addr1: Some(MatchConfig::address(0x21)),
+    ///     addr2: None,
+    ///     mode: MatchMode::Address,
+    /// });
+    /// uart.standby();
+    ///
+    /// // Only the frames that start with 0x21 arrive here
+    /// loop {
+    ///     let word = nb::block!(uart.read_word9()).unwrap();
+    ///     if uart.take_address_match().is_some() {
+    ///         // A new frame for us; `word` is the address, 0x121
+    ///     } else if end_of_frame(word) {
+    ///         // Ignore the bus until the next frame for us
+    ///         uart.standby();
+    ///     }
+    /// }
+    /// ```
+    pub fn set_address_match(&mut self, config: MatchConfig) {
+        let fields = MatchFields::new(&config);
+        self.while_disabled(|this| {
+            ral::write_reg!(ral::lpuart, this.reg, MATCH, fields.matches);
+            ral::modify_reg!(
+                ral::lpuart,
+                this.reg,
+                BAUD,
+                MAEN1: fields.maen1,
+                MAEN2: fields.maen2,
+                MATCFG: fields.matcfg
+            );
+            ral::modify_reg!(ral::lpuart, this.reg, CTRL, WAKE: fields.wake, RWU: 0);
+        });
+        self.clear_status(Status::MATCH_1 | Status::MATCH_2);
+    }
+
+    /// Ignore received data until a matching address word arrives
+    ///
+    /// The receiver leaves standby when it receives a word that matches an
+    /// [address](#method.set_address_match). Call this once a frame ends.
+    pub fn standby(&mut self) {
+        ral::modify_reg!(ral::lpuart, self.reg, CTRL, RWU: 1);
+    }
+
+    /// Returns `true` if the receiver is ignoring the bus
+    pub fn is_standby(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, CTRL, RWU == 1)
+    }
+
+    /// Returns the address that a received word matched, and clears the match
+    ///
+    /// Call this after each read. It returns `Some` when the word that was
+    /// just read is a matching address word, the start of a frame for this node.
+    pub fn take_address_match(&mut self) -> Option<Matched> {
+        let status = self.status();
+        let matched = if status.contains(Status::MATCH_1) {
+            Matched::Addr1
+        } else if status.contains(Status::MATCH_2) {
+            Matched::Addr2
+        } else {
+            return None;
+        };
+        self.clear_status(Status::MATCH_1 | Status::MATCH_2);
+        Some(matched)
+    }
+
+    /// Send an address word for `address`
+    ///
+    /// Use this when the UART has nine data bits. Returns `WouldBlock` if the
+    /// transmitter is busy.
+    pub fn write_address(&mut self, address: u8) -> nb::Result<(), core::convert::Infallible> {
+        self.write_word9(MatchConfig::address(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchConfig, MatchFields, MatchMode};
+
+    #[test]
+    fn match_fields() {
+        let fields = MatchFields::new(&MatchConfig {
+            addr1: Some(MatchConfig::address(0x21)),
+            addr2: None,
+            mode: MatchMode::Address,
+        });
+        assert_eq!(
+            fields,
+            MatchFields {
+                matches: 0x121,
+                maen1: 1,
+                maen2: 0,
+                matcfg: 0,
+                wake: 1,
+            }
+        );
+
+        let fields = MatchFields::new(&MatchConfig {
+            addr1: Some(0x3A),
+            addr2: Some(0x3FF | 0x400),
+            mode: MatchMode::OnOff,
+        });
+        assert_eq!(
+            fields,
+            MatchFields {
+                matches: 0x3A | 0x3FF << 16,
+                maen1: 1,
+                maen2: 1,
+                matcfg: 0b10,
+                wake: 0,
+            }
+        );
+
+        let fields = MatchFields::new(&MatchConfig {
+            addr1: None,
+            addr2: Some(MatchConfig::address(0xFF)),
+            mode: MatchMode::Idle,
+        });
+        assert_eq!(
+            (fields.matches, fields.maen1, fields.maen2),
+            (0x1FF << 16, 0, 1)
+        );
+        assert_eq!((fields.matcfg, fields.wake), (0b01, 0));
+    }
+
+    #[test]
+    fn address_words() {
+        assert_eq!(MatchConfig::address(0), 0x100);
+        assert_eq!(MatchConfig::address(0xFF), 0x1FF);
+    }
+}
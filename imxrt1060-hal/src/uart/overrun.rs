@@ -0,0 +1,239 @@
+//! Overrun recovery for DMA receives
+
+use super::{Rx, Status, UART};
+use crate::dma::{self, peripheral::Source};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+
+/// What a DMA receive does after the receiver overruns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrunRecovery {
+    /// Clear the overrun, and keep receiving into the same buffer
+    ///
+    /// The buffer has a gap where the lost bytes would have been.
+    Resume,
+    /// Stop the receive, and return the bytes received before the overrun
+    Complete,
+}
+
+/// Overrun recovery configuration
+///
+/// See [`poll_events()`](../dma/struct.Peripheral.html#method.poll_events).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverrunConfig {
+    /// Resume, or complete, the receive
+    pub recovery: OverrunRecovery,
+    /// Drop the bytes that wait in the RX FIFO
+    ///
+    /// The bytes arrived before the overrun, but the DMA controller didn't move
+    /// them. Flush them to restart from an empty FIFO. Otherwise, a resumed receive
+    /// moves them into the buffer, and a completed receive leaves them in the FIFO.
+    pub flush_fifo: bool,
+}
+
+/// An event from a UART DMA receive
+#[derive(Debug)]
+pub enum ReceiveEvent<D> {
+    /// The receive is complete
+    Complete(D),
+    /// The receiver overran, and the receive continues after the gap
+    Resumed {
+        /// The number of bytes received before the overrun
+        received: usize,
+    },
+    /// The receiver overran, and the receive stopped early
+    Overrun {
+        /// The destination buffer
+        buffer: D,
+        /// The number of good bytes in the buffer
+        received: usize,
+    },
+}
+
+/// Returns `true` if an overrun stopped a receive that's still waiting for bytes
+fn is_stalled(status: Status, scheduled: bool, complete: bool) -> bool {
+    status.contains(Status::OVERRUN) && scheduled && !complete
+}
+
+/// Returns `true` if the overrun belongs to a scheduled receive, which may clear it
+///
+/// Without a receive, the overrun is left for the code that reads the UART.
+fn is_receive_overrun(status: Status, scheduled: bool) -> bool {
+    status.contains(Status::OVERRUN) && scheduled
+}
+
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Interrupt when the receiver overruns
+    ///
+    /// Use the interrupt to recover a DMA receive; see
+    /// [`poll_events()`](../dma/struct.Peripheral.html#method.poll_events).
+    pub fn set_overrun_interrupt(&mut self, enable: bool) {
+        ral::modify_reg!(ral::lpuart, self.reg, CTRL, ORIE: u32::from(enable));
+    }
+}
+
+fn poll_events<P, D, M>(
+    rx: &mut dma::Peripheral<P, u8, D>,
+    config: OverrunConfig,
+) -> Option<ReceiveEvent<D>>
+where
+    P: Source<u8>,
+    D: dma::buffer::Destination<u8>,
+    M: Unsigned,
+{
+    // Safety: the status reads are atomic, and the writes happen in critical sections
+    let uart = unsafe { UART::<M>::alias() };
+    let status = uart.status();
+    let (scheduled, complete) = (rx.is_receive_scheduled(), rx.is_receive_complete());
+    let stalled = is_stalled(status, scheduled, complete);
+    let clear = is_receive_overrun(status, scheduled);
+
+    let event = if complete {
+        // An overrun only lost bytes after the buffer filled
+        rx.receive_complete().map(ReceiveEvent::Complete)
+    } else if !stalled {
+        None
+    } else {
+        match config.recovery {
+            OverrunRecovery::Resume => Some(ReceiveEvent::Resumed {
+                received: rx.receive_progress(),
+            }),
+            OverrunRecovery::Complete => rx.receive_stop().map(|stopped| match stopped {
+                dma::ReceiveTimeout::Complete(buffer) => ReceiveEvent::Complete(buffer),
+                dma::ReceiveTimeout::Expired { buffer, received } => {
+                    ReceiveEvent::Overrun { buffer, received }
+                }
+            }),
+        }
+    };
+
+    if clear {
+        cortex_m::interrupt::free(|_| {
+            if config.flush_fifo {
+                ral::modify_reg!(ral::lpuart, uart.reg, FIFO, RXFLUSH: RXFLUSH_1);
+            }
+            // Safety: the read-modify-write is atomic. The receiver restarts.
+            unsafe { uart.clear_flags(Status::OVERRUN) };
+        });
+    }
+    event
+}
+
+impl<M, D> dma::Peripheral<UART<M>, u8, D>
+where
+    M: Unsigned,
+    D: dma::buffer::Destination<u8>,
+{
+    /// Complete the receive, or recover it from an overrun
+    ///
+    /// If the DMA controller falls behind, the RX FIFO fills, and the receiver overruns.
+    /// The receiver stops until software clears the overrun flag, so the receive stalls.
+    /// `poll_events()` finds the stall, and follows `config`: it resumes the receive, or
+    /// stops the receive and returns the good bytes. Either way, it clears the overrun, so
+    /// the receiver restarts. Without a scheduled receive, `poll_events()` leaves the
+    /// overrun flag alone.
+    ///
+    /// Call this in a loop, or from the UART interrupt handler after
+    /// [`set_overrun_interrupt()`](../uart/struct.UART.html#method.set_overrun_interrupt).
+    /// It also returns the buffer of a complete receive, so you may call it from the DMA
+    /// interrupt handler. Returns `None` if the receive is still running.
+    ///
+    /// The DMA controller doesn't see the lost bytes, so a resumed receive completes after
+    /// the bytes that follow the gap fill the buffer.
+    ///
+    /// # Example
+    ///
+    /// Receive 3Mbaud frames without the RX FIFO, so that the receive overruns as soon
+    /// as other DMA traffic delays it by a byte. Count the frames that overran.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::dma::{self, Buffer, Linear};
+    /// use imxrt1060_hal::uart::{OverrunConfig, OverrunRecovery, ReceiveEvent};
+    ///
+    /// static FRAME: Buffer<[u8; 512]> = Buffer::new([0; 512]);
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let mut uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 3_000_000)
+    ///     .unwrap();
+    /// uart.set_rx_fifo(false);
+    ///
+    /// let mut channels = peripherals.dma.clock(&mut peripherals.ccm.handle);
+    /// let mut rx = dma::receive_u8(uart, channels[7].take().unwrap());
+    /// let config = OverrunConfig {
+    ///     recovery: OverrunRecovery::Complete,
+    ///     flush_fifo: true,
+    /// };
+    ///
+    /// let (mut overruns, mut frames) = (0u32, 0u32);
+    /// rx.start_receive(Linear::try_new(&FRAME).unwrap()).unwrap();
+    /// loop {
+    ///     // Other work, and other DMA transfers, happen here
+    ///     let mut buffer = match rx.poll_events(config) {
+    ///         Some(ReceiveEvent::Complete(buffer)) => {
+    ///             frames += 1;
+    ///             buffer
+    ///         }
+    ///         Some(ReceiveEvent::Overrun { buffer, received }) => {
+    ///             log::warn!("overrun after {} bytes", received);
+    ///             overruns += 1;
+    ///             buffer
+    ///         }
+    ///         _ => continue,
+    ///     };
+    ///     buffer.set_transfer_len(512);
+    ///     rx.start_receive(buffer).unwrap();
+    ///     log::info!("{} frames, {} overruns", frames, overruns);
+    /// }
+    /// ```
+    pub fn poll_events(&mut self, config: OverrunConfig) -> Option<ReceiveEvent<D>> {
+        poll_events::<_, _, M>(self, config)
+    }
+}
+
+impl<M, D> dma::Peripheral<Rx<M>, u8, D>
+where
+    M: Unsigned,
+    D: dma::buffer::Destination<u8>,
+{
+    /// Complete the receive, or recover it from an overrun
+    ///
+    /// This is the same as the `poll_events()` of a whole `UART`, for the receive half
+    /// of a split UART.
+    pub fn poll_events(&mut self, config: OverrunConfig) -> Option<ReceiveEvent<D>> {
+        poll_events::<_, _, M>(self, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_receive_overrun, is_stalled, Status};
+
+    #[test]
+    fn stalls() {
+        assert!(is_stalled(Status::OVERRUN, true, false));
+        assert!(is_stalled(Status::OVERRUN | Status::IDLE, true, false));
+        // The buffer filled first
+        assert!(!is_stalled(Status::OVERRUN, true, true));
+        // No receive to recover
+        assert!(!is_stalled(Status::OVERRUN, false, false));
+        assert!(!is_stalled(Status::IDLE, true, false));
+    }
+
+    #[test]
+    fn clears_only_during_receive() {
+        assert!(is_receive_overrun(Status::OVERRUN, true));
+        // No receive, so a blocking reader still sees the overrun
+        assert!(!is_receive_overrun(Status::OVERRUN, false));
+        assert!(!is_receive_overrun(Status::IDLE, true));
+    }
+}
@@ -0,0 +1,502 @@
+//! RS-485 direction control
+
+use super::{ReadError, UART};
+use crate::dma::{self, peripheral::Destination};
+use crate::iomuxc::consts::Unsigned;
+use crate::ral;
+use core::convert::Infallible;
+use embedded_hal::{digital::v2::OutputPin, serial};
+
+/// RS-485 driver-enable settings
+///
+/// The delays are in bit times at the UART's baud rate. They busy-wait on the
+/// core, so `cpu_hz` must match the ARM clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rs485Config {
+    /// Bit times between asserting DE, and loading the first byte
+    pub pre_delay_bits: u16,
+    /// Bit times between the last stop bit, and releasing DE
+    pub post_delay_bits: u16,
+    /// `true` if the transceiver drives the bus while DE is high
+    pub active_high: bool,
+    /// The ARM core clock, in Hz
+    pub cpu_hz: u32,
+}
+
+impl Default for Rs485Config {
+    /// No delays, an active-high DE, and the default ARM clock
+    fn default() -> Self {
+        Rs485Config {
+            pre_delay_bits: 0,
+            post_delay_bits: 0,
+            active_high: true,
+            cpu_hz: crate::ccm::PLL1::ARM_HZ,
+        }
+    }
+}
+
+/// A half-duplex RS-485 UART
+///
+/// `Rs485` asserts the transceiver's driver-enable (DE) pin before it loads the
+/// first byte of a message, and releases DE once the UART sends the message's last
+/// stop bit. It waits for the transmit complete flag, not an empty TX FIFO, so the
+/// transceiver never cuts off the final byte. Create an `Rs485` with
+/// [`UART::into_rs485()`](struct.UART.html#method.into_rs485).
+///
+/// There are three ways to send a message:
+///
+/// - [`write_all()`](#method.write_all) blocks until DE is released.
+/// - the `embedded_hal` `write()` asserts DE, and `flush()` returns `WouldBlock`
+///   until DE is released. Release DE by blocking on `flush()`.
+/// - write the message, then call [`finish()`](#method.finish). The UART
+///   interrupts once the message is sent. Call [`on_interrupt()`](#method.on_interrupt)
+///   from the `LPUART[N]` handler to release DE.
+///
+/// To send a message with DMA, see
+/// [`start_rs485_transfer()`](../dma/struct.Peripheral.html#method.start_rs485_transfer).
+/// Reads are the same as the UART's reads.
+pub struct Rs485<M: Unsigned, P> {
+    uart: UART<M>,
+    direction: Direction<P>,
+}
+
+impl<M> UART<M>
+where
+    M: Unsigned,
+{
+    /// Drive an RS-485 transceiver, using `de` as the driver-enable pin
+    ///
+    /// Releases `de`, so the transceiver starts out receiving.
+    ///
+    /// # Example
+    ///
+    /// A Modbus RTU request on LPUART2, with the transceiver's DE pin on a GPIO.
+    ///
+    /// ```no_run
+    /// use imxrt1060_hal::{gpio::GPIO, uart::Rs485Config};
+    ///
+    /// let mut peripherals = imxrt1060_hal::Peripherals::take().unwrap();
+    /// let uarts = peripherals.uart.clock(
+    ///     &mut peripherals.ccm.handle,
+    ///     imxrt1060_hal::ccm::uart::ClockSelect::OSC,
+    ///     imxrt1060_hal::ccm::uart::PrescalarSelect::DIVIDE_1,
+    /// );
+    /// let uart = uarts
+    ///     .uart2
+    ///     .init(peripherals.iomuxc.ad_b1.p02, peripherals.iomuxc.ad_b1.p03, 19_200)
+    ///     .unwrap();
+    /// let de = GPIO::new(peripherals.iomuxc.ad_b1.p01).output();
+    ///
+    /// let mut bus = uart.into_rs485(
+    ///     de,
+    ///     Rs485Config {
+    ///         pre_delay_bits: 1,
+    ///         post_delay_bits: 1,
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///
+    /// // Read two holding registers from device 0x11. DE is released when this returns.
+    /// bus.write_all(&[0x11, 0x03, 0x00, 0x6B, 0x00, 0x02, 0xB6, 0x87]).unwrap();
+    /// let mut response = [0; 9];
+    /// bus.read_exact(&mut response).unwrap();
+    /// ```
+    pub fn into_rs485<P: OutputPin>(self, de: P, config: Rs485Config) -> Rs485<M, P> {
+        let mut direction = Direction::new(de, config);
+        direction.release();
+        Rs485 {
+            uart: self,
+            direction,
+        }
+    }
+}
+
+impl<M, P> Rs485<M, P>
+where
+    M: Unsigned,
+    P: OutputPin,
+{
+    /// Send all of `data`, keeping DE asserted until the last stop bit is sent
+    ///
+    /// Blocks until DE is released.
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Infallible> {
+        self.direction.begin(&mut self.uart);
+        let result = self.uart.write_all(data);
+        while !self.direction.poll(&mut self.uart) {}
+        result
+    }
+
+    /// Read bytes until `data` is full
+    ///
+    /// See [`UART::read_exact()`](struct.UART.html#method.read_exact).
+    pub fn read_exact(&mut self, data: &mut [u8]) -> Result<(), ReadError> {
+        self.uart.read_exact(data)
+    }
+
+    /// Release DE once the UART sends everything that's been written
+    ///
+    /// Enables the UART's transmit complete interrupt. Call
+    /// [`on_interrupt()`](#method.on_interrupt) from the UART's interrupt handler.
+    /// Don't write more bytes until DE is released.
+    pub fn finish(&mut self) {
+        self.direction.finish(&mut self.uart);
+    }
+
+    /// Release DE if the message is sent
+    ///
+    /// Call this from the UART's interrupt handler after [`finish()`](#method.finish).
+    /// Returns `true` if DE was released.
+    pub fn on_interrupt(&mut self) -> bool {
+        self.direction.poll(&mut self.uart)
+    }
+
+    /// Returns `true` if DE is asserted
+    pub fn is_transmitting(&self) -> bool {
+        self.direction.state != State::Receiving
+    }
+
+    /// Release DE and the UART
+    ///
+    /// DE is released even if a message is still being sent.
+    pub fn release(mut self) -> (UART<M>, P) {
+        self.uart.set_tc_interrupt(false);
+        self.direction.release();
+        (self.uart, self.direction.de)
+    }
+}
+
+impl<M, P> serial::Write<u8> for Rs485<M, P>
+where
+    M: Unsigned,
+    P: OutputPin,
+{
+    type Error = Infallible;
+
+    /// Asserts DE, if it's not asserted, then writes the byte
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        self.direction.begin(&mut self.uart);
+        serial::Write::write(&mut self.uart, word)
+    }
+
+    /// Returns `WouldBlock` until the last stop bit is sent, and DE is released
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        if self.direction.state == State::Receiving || self.direction.poll(&mut self.uart) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<M, P> serial::Read<u8> for Rs485<M, P>
+where
+    M: Unsigned,
+{
+    type Error = ReadError;
+
+    fn read(&mut self) -> nb::Result<u8, ReadError> {
+        serial::Read::read(&mut self.uart)
+    }
+}
+
+unsafe impl<M, P> Destination<u8> for Rs485<M, P>
+where
+    M: Unsigned,
+{
+    fn destination_signal(&self) -> u32 {
+        UART::<M>::DMA_DESTINATION_REQUEST_SIGNAL
+    }
+    fn destination(&self) -> *const u8 {
+        self.uart.destination()
+    }
+    fn enable_destination(&self) {
+        self.uart.enable_destination()
+    }
+    fn disable_destination(&self) {
+        self.uart.disable_destination()
+    }
+}
+
+impl<M, P> dma::Peripheral<Rs485<M, P>, u8, dma::Linear<u8>>
+where
+    M: Unsigned,
+    P: OutputPin,
+{
+    /// Assert DE, then start a DMA transfer from `buffer`
+    ///
+    /// Once the transfer completes, call [`rs485_transfer_complete()`](#method.rs485_transfer_complete).
+    /// DE is released if the transfer doesn't start.
+    pub fn start_rs485_transfer(
+        &mut self,
+        buffer: dma::Linear<u8>,
+    ) -> Result<(), (dma::Linear<u8>, dma::Error)> {
+        let rs485 = self.peripheral_mut();
+        rs485.direction.begin(&mut rs485.uart);
+        self.start_transfer(buffer).map_err(|err| {
+            let rs485 = self.peripheral_mut();
+            rs485.direction.release();
+            err
+        })
+    }
+
+    /// Returns the buffer once the DMA transfer completes, and prepares to release DE
+    ///
+    /// Call this instead of `transfer_complete()`. The UART's last bytes may still be
+    /// in the FIFO. DE is released once they're sent: the UART interrupts, and
+    /// [`rs485_on_interrupt()`](#method.rs485_on_interrupt) releases DE. Returns `None`
+    /// if the transfer isn't complete.
+    pub fn rs485_transfer_complete(&mut self) -> Option<dma::Linear<u8>> {
+        if !self.is_transfer_complete() {
+            return None;
+        }
+        let buffer = self.transfer_complete();
+        let rs485 = self.peripheral_mut();
+        rs485.direction.finish(&mut rs485.uart);
+        buffer
+    }
+
+    /// Release DE if the DMA transfer's last stop bit is sent
+    ///
+    /// Call this from the UART's interrupt handler. Returns `true` if DE was released.
+    pub fn rs485_on_interrupt(&mut self) -> bool {
+        let rs485 = self.peripheral_mut();
+        rs485.direction.poll(&mut rs485.uart)
+    }
+}
+
+/// The UART side of an RS-485 transceiver
+trait Wire {
+    /// Returns `true` once the last stop bit is sent
+    fn transmit_complete(&self) -> bool;
+    /// Enable or disable the transmit complete interrupt
+    fn set_tc_interrupt(&mut self, enable: bool);
+    /// Busy-wait for `bits` bit times, given the core clock
+    fn delay_bits(&mut self, bits: u16, cpu_hz: u32);
+}
+
+impl<M: Unsigned> UART<M> {
+    /// Enable or disable the interrupt when the transmitter is idle
+    fn set_tc_interrupt(&mut self, enable: bool) {
+        ral::modify_reg!(ral::lpuart, self.reg, CTRL, TCIE: u32::from(enable));
+    }
+}
+
+impl<M: Unsigned> Wire for UART<M> {
+    fn transmit_complete(&self) -> bool {
+        ral::read_reg!(ral::lpuart, self.reg, STAT, TC == TC_1)
+    }
+    fn set_tc_interrupt(&mut self, enable: bool) {
+        UART::set_tc_interrupt(self, enable);
+    }
+    fn delay_bits(&mut self, bits: u16, cpu_hz: u32) {
+        if let Some(baud) = self.achieved_baud() {
+            cortex_m::asm::delay(delay_cycles(bits, cpu_hz, baud));
+        }
+    }
+}
+
+/// Returns the core cycles in `bits` bit times, rounded up
+fn delay_cycles(bits: u16, cpu_hz: u32, baud: u32) -> u32 {
+    let baud = u64::from(baud.max(1));
+    let cycles = (u64::from(bits) * u64::from(cpu_hz) + baud - 1) / baud;
+    cycles.min(u64::from(u32::max_value())) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// DE is released
+    Receiving,
+    /// DE is asserted, and there may be more bytes to come
+    Transmitting,
+    /// DE is asserted, and it's released once the transmitter is idle
+    Draining,
+}
+
+/// Drives DE around messages
+struct Direction<P> {
+    de: P,
+    config: Rs485Config,
+    state: State,
+}
+
+impl<P: OutputPin> Direction<P> {
+    fn new(de: P, config: Rs485Config) -> Self {
+        Direction {
+            de,
+            config,
+            state: State::Receiving,
+        }
+    }
+
+    /// Assert DE before the next byte
+    fn begin<W: Wire>(&mut self, wire: &mut W) {
+        match self.state {
+            State::Receiving => {
+                self.drive(true);
+                if self.config.pre_delay_bits > 0 {
+                    wire.delay_bits(self.config.pre_delay_bits, self.config.cpu_hz);
+                }
+            }
+            // A byte that's written while draining is part of the message
+            State::Draining => wire.set_tc_interrupt(false),
+            State::Transmitting => {}
+        }
+        self.state = State::Transmitting;
+    }
+
+    /// Release DE, by interrupt, once the transmitter is idle
+    fn finish<W: Wire>(&mut self, wire: &mut W) {
+        if self.state == State::Transmitting {
+            self.state = State::Draining;
+            wire.set_tc_interrupt(true);
+        }
+    }
+
+    /// Release DE if the transmitter is idle. Returns `true` if DE was released.
+    fn poll<W: Wire>(&mut self, wire: &mut W) -> bool {
+        if self.state == State::Receiving || !wire.transmit_complete() {
+            return false;
+        }
+        if self.state == State::Draining {
+            wire.set_tc_interrupt(false);
+        }
+        if self.config.post_delay_bits > 0 {
+            wire.delay_bits(self.config.post_delay_bits, self.config.cpu_hz);
+        }
+        self.release();
+        true
+    }
+
+    /// Release DE now
+    fn release(&mut self) {
+        self.drive(false);
+        self.state = State::Receiving;
+    }
+
+    fn drive(&mut self, assert: bool) {
+        // The HAL's GPIOs are infallible, and there's no way to report
+        // an error from the interrupt handler
+        let _ = if assert == self.config.active_high {
+            self.de.set_high()
+        } else {
+            self.de.set_low()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delay_cycles, Direction, Rs485Config, State, Wire};
+    use crate::gpio::mock::Line;
+
+    #[derive(Default)]
+    struct MockWire {
+        complete: bool,
+        interrupt: bool,
+        delayed: u32,
+    }
+
+    impl Wire for MockWire {
+        fn transmit_complete(&self) -> bool {
+            self.complete
+        }
+        fn set_tc_interrupt(&mut self, enable: bool) {
+            self.interrupt = enable;
+        }
+        fn delay_bits(&mut self, bits: u16, _: u32) {
+            self.delayed += u32::from(bits);
+        }
+    }
+
+    fn config() -> Rs485Config {
+        Rs485Config {
+            pre_delay_bits: 2,
+            post_delay_bits: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn polled_message() {
+        let de = Line::new(false);
+        let mut wire = MockWire::default();
+        let mut direction = Direction::new(de.output(), config());
+
+        direction.begin(&mut wire);
+        assert!(de.level());
+        assert_eq!(wire.delayed, 2);
+        direction.begin(&mut wire);
+        assert_eq!(wire.delayed, 2, "DE is already asserted");
+
+        // Bytes are still in the FIFO
+        assert!(!direction.poll(&mut wire));
+        assert!(de.level());
+
+        wire.complete = true;
+        assert!(direction.poll(&mut wire));
+        assert!(!de.level());
+        assert_eq!(wire.delayed, 5);
+        assert!(!wire.interrupt);
+        assert!(!direction.poll(&mut wire));
+        assert_eq!(de.recorded_transitions().as_slice(), &[true, false]);
+    }
+
+    #[test]
+    fn interrupt_message() {
+        let de = Line::new(false);
+        let mut wire = MockWire::default();
+        let mut direction = Direction::new(de.output(), Rs485Config::default());
+
+        direction.begin(&mut wire);
+        direction.finish(&mut wire);
+        assert_eq!(direction.state, State::Draining);
+        assert!(wire.interrupt);
+
+        // More bytes join the message
+        direction.begin(&mut wire);
+        assert_eq!(direction.state, State::Transmitting);
+        assert!(!wire.interrupt);
+        direction.finish(&mut wire);
+
+        wire.complete = true;
+        assert!(direction.poll(&mut wire));
+        assert!(!wire.interrupt);
+        assert_eq!(wire.delayed, 0);
+        assert_eq!(de.recorded_transitions().as_slice(), &[true, false]);
+
+        // Nothing to finish
+        direction.finish(&mut wire);
+        assert!(!wire.interrupt);
+    }
+
+    #[test]
+    fn active_low() {
+        let de = Line::new(true);
+        let mut wire = MockWire::default();
+        let mut direction = Direction::new(
+            de.output(),
+            Rs485Config {
+                active_high: false,
+                ..Default::default()
+            },
+        );
+        direction.release();
+        assert!(de.level());
+        direction.begin(&mut wire);
+        assert!(!de.level());
+        direction.release();
+        assert!(de.level());
+    }
+
+    #[test]
+    fn cycles() {
+        // One bit at 115200 baud is 5208.3 cycles at 600MHz
+        assert_eq!(delay_cycles(1, 600_000_000, 115_200), 5209);
+        assert_eq!(delay_cycles(2, 600_000_000, 1_000_000), 1200);
+        assert_eq!(delay_cycles(0, 600_000_000, 9600), 0);
+        assert_eq!(
+            delay_cycles(u16::max_value(), u32::max_value(), 1),
+            u32::max_value()
+        );
+    }
+}
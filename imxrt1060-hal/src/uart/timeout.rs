@@ -0,0 +1,350 @@
+//! Software receive timeouts, measured with a one-shot timer
+
+use super::{Buffered, Rx, UART};
+use crate::dma;
+use crate::iomuxc::consts::Unsigned;
+use crate::timer::OneShot;
+use core::time::Duration;
+
+/// A receive timeout that's longer than the UART's idle detection
+///
+/// The UART's [idle detection](struct.UART.html#method.set_idle_detection) waits
+/// for at most 128 idle characters. A `ReceiveTimer` waits for any duration that
+/// its [`OneShot`](../timer/trait.OneShot.html) timer can measure. Each received byte restarts the
+/// timer, and the receive times out when the timer expires before the next byte.
+///
+/// Use a `ReceiveTimer` with a [`Buffered`](struct.Buffered.html) UART, or a DMA
+/// receive. Enable the timer's interrupt, and call `on_timeout()` from the timer's
+/// interrupt handler:
+///
+/// - [`Buffered::on_timeout()`](struct.Buffered.html#method.on_timeout)
+/// - [`Peripheral::receive_on_timeout()`](../dma/struct.Peripheral.html#method.receive_on_timeout)
+///
+/// # Example
+///
+/// A frame ends after 40 bit times of silence; that's four characters at 10 bits per
+/// character. PIT channel 3 measures the silence.
+///
+/// ```no_run
+/// use imxrt1060_hal::{iomuxc::consts::U2, pit::{channel::_3, PIT}};
+/// use imxrt1060_hal::uart::{Buffered, ReceiveTimer};
+///
+/// # fn setup(mut pit: PIT<_3>) -> ReceiveTimer<PIT<_3>> {
+/// pit.set_interrupt_enable(true);
+/// let timeout = ReceiveTimer::bit_times(40, 115_200);
+/// let timer = ReceiveTimer::new(pit, timeout);
+/// # timer
+/// # }
+///
+/// // In the LPUART2 interrupt handler...
+/// # fn on_lpuart2(uart: &mut Buffered<U2, 64, 256>, timer: &mut ReceiveTimer<PIT<_3>>) {
+/// uart.on_interrupt_with_timeout(timer);
+/// # }
+///
+/// // In the PIT interrupt handler...
+/// # fn on_pit(uart: &mut Buffered<U2, 64, 256>, timer: &mut ReceiveTimer<PIT<_3>>) {
+/// if let Some(len) = uart.on_timeout(timer) {
+///     let mut frame = [0; 256];
+///     let len = uart.read(&mut frame[..len]);
+///     // Handle the frame...
+/// }
+/// # }
+/// ```
+pub struct ReceiveTimer<T> {
+    timer: T,
+    timeout: Duration,
+    /// The byte count when the timer started
+    seen: usize,
+    armed: bool,
+}
+
+impl<T: OneShot> ReceiveTimer<T> {
+    /// Time out a receive after `timeout` of silence, measured by `timer`
+    pub fn new(mut timer: T, timeout: Duration) -> Self {
+        timer.cancel();
+        ReceiveTimer {
+            timer,
+            timeout,
+            seen: 0,
+            armed: false,
+        }
+    }
+
+    /// Returns the duration of `bits` bit times at `baud`
+    ///
+    /// Rounds up to the next nanosecond. Use the achieved baud rate from
+    /// [`set_baud()`](struct.UART.html#method.set_baud) for the best estimate.
+    pub fn bit_times(bits: u32, baud: u32) -> Duration {
+        let baud = u64::from(baud.max(1));
+        let nanos = (u64::from(bits) * 1_000_000_000 + baud - 1) / baud;
+        Duration::from_nanos(nanos)
+    }
+
+    /// Returns the timeout
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the timeout
+    ///
+    /// The new timeout is used the next time that the timer starts.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Start timing a DMA receive
+    ///
+    /// Call this after starting the receive. The DMA receive doesn't signal each byte,
+    /// see [`receive_on_timeout()`](../dma/struct.Peripheral.html#method.receive_on_timeout).
+    pub fn start(&mut self) {
+        self.arm(0);
+    }
+
+    /// Stop the timer
+    pub fn stop(&mut self) {
+        self.armed = false;
+        self.timer.cancel();
+    }
+
+    /// Release the timer
+    pub fn release(mut self) -> T {
+        self.timer.cancel();
+        self.timer
+    }
+
+    fn arm(&mut self, count: usize) {
+        self.seen = count;
+        self.armed = true;
+        self.timer.start(self.timeout);
+    }
+
+    /// Restart the timer if `count` moved since the timer started
+    ///
+    /// `count` is a running count of received bytes.
+    fn receive(&mut self, count: usize) {
+        if count != self.seen {
+            self.arm(count);
+        }
+    }
+
+    /// Returns `true` if the timer expired, and there are still `count` bytes
+    ///
+    /// If bytes arrived after the timer started, the bytes won the race, and the
+    /// timer restarts.
+    fn expire(&mut self, count: usize) -> bool {
+        if !self.timer.is_expired() || !self.armed {
+            return false;
+        }
+        if count != self.seen {
+            self.arm(count);
+            return false;
+        }
+        self.armed = false;
+        true
+    }
+}
+
+impl<M: Unsigned, const TX: usize, const RX: usize> Buffered<M, TX, RX> {
+    /// Move bytes between the FIFOs and the buffers, and restart `timer` when
+    /// bytes arrive
+    ///
+    /// Call this from the UART's interrupt handler, instead of
+    /// [`on_interrupt()`](#method.on_interrupt).
+    pub fn on_interrupt_with_timeout<T: OneShot>(&mut self, timer: &mut ReceiveTimer<T>) {
+        self.on_interrupt();
+        timer.receive(self.received());
+    }
+
+    /// Returns the number of bytes in the RX buffer, if the receive timed out
+    ///
+    /// Call this from the timer's interrupt handler. The timeout starts at the first
+    /// byte after the last timeout, so a quiet line doesn't time out again. Bytes that
+    /// are still in the RX FIFO when the timer expires count as new bytes, and restart
+    /// the timer; the result includes every byte that arrived before the timeout.
+    pub fn on_timeout<T: OneShot>(&mut self, timer: &mut ReceiveTimer<T>) -> Option<usize> {
+        self.on_interrupt();
+        if timer.expire(self.received()) {
+            Some(self.rx_len())
+        } else {
+            None
+        }
+    }
+}
+
+/// Handle the timer expiry of a DMA receive
+fn receive_on_timeout<P, T>(
+    rx: &mut dma::Peripheral<P, u8, dma::Linear<u8>>,
+    timer: &mut ReceiveTimer<T>,
+) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>>
+where
+    P: dma::peripheral::Source<u8>,
+    T: OneShot,
+{
+    if rx.is_receive_complete() {
+        timer.stop();
+        return rx.receive_complete().map(dma::ReceiveTimeout::Complete);
+    }
+    let received = rx.receive_progress();
+    if !timer.expire(received) {
+        return None;
+    }
+    if received == 0 {
+        // Nothing to time out yet; wait for the first byte
+        timer.arm(0);
+        return None;
+    }
+    // Bytes that land while the receive stops are in the result
+    rx.receive_stop()
+}
+
+impl<M> dma::Peripheral<UART<M>, u8, dma::Linear<u8>>
+where
+    M: Unsigned,
+{
+    /// End a DMA receive when `timer` expires without new bytes
+    ///
+    /// Call this from the timer's interrupt handler, after starting the receive, and
+    /// the [`ReceiveTimer`](../uart/struct.ReceiveTimer.html). The DMA controller doesn't
+    /// signal each byte. Instead, each time the timer expires, this checks the receive's
+    /// progress. If bytes arrived, the timer restarts. Otherwise, this stops the receive,
+    /// and returns the buffer with the bytes received so far. The receive stops between one
+    /// and two timeouts after the last byte; use half of the silence that ends a frame.
+    ///
+    /// The DMA controller may finish a byte while the receive stops. The result includes
+    /// that byte. If the receive completes first, the result is `Complete`, and the timer
+    /// stops.
+    pub fn receive_on_timeout<T: OneShot>(
+        &mut self,
+        timer: &mut ReceiveTimer<T>,
+    ) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>> {
+        receive_on_timeout(self, timer)
+    }
+}
+
+impl<M> dma::Peripheral<Rx<M>, u8, dma::Linear<u8>>
+where
+    M: Unsigned,
+{
+    /// End a DMA receive when `timer` expires without new bytes
+    ///
+    /// This is the same as the `receive_on_timeout()` of a whole `UART`, for the receive
+    /// half of a split UART.
+    pub fn receive_on_timeout<T: OneShot>(
+        &mut self,
+        timer: &mut ReceiveTimer<T>,
+    ) -> Option<dma::ReceiveTimeout<dma::Linear<u8>>> {
+        receive_on_timeout(self, timer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OneShot, ReceiveTimer};
+    use core::time::Duration;
+
+    /// Expires when the test says so
+    #[derive(Default)]
+    struct FakeTimer {
+        running: bool,
+        expired: bool,
+        starts: usize,
+    }
+
+    impl FakeTimer {
+        fn elapse(&mut self) {
+            if self.running {
+                self.running = false;
+                self.expired = true;
+            }
+        }
+    }
+
+    impl OneShot for FakeTimer {
+        fn start(&mut self, _: Duration) {
+            self.running = true;
+            self.expired = false;
+            self.starts += 1;
+        }
+        fn cancel(&mut self) {
+            self.running = false;
+            self.expired = false;
+        }
+        fn is_expired(&mut self) -> bool {
+            core::mem::replace(&mut self.expired, false)
+        }
+    }
+
+    #[test]
+    fn bytes_restart_the_timer() {
+        let mut timer = ReceiveTimer::new(FakeTimer::default(), Duration::from_millis(1));
+        // Quiet line, after the last timeout
+        timer.timer.elapse();
+        assert!(!timer.expire(0));
+
+        timer.receive(1);
+        timer.receive(2);
+        assert_eq!(timer.timer.starts, 2);
+        // No new bytes
+        timer.receive(2);
+        assert_eq!(timer.timer.starts, 2);
+        assert!(!timer.expire(2));
+
+        timer.timer.elapse();
+        assert!(timer.expire(2));
+        // Only once
+        timer.timer.elapse();
+        assert!(!timer.expire(2));
+        assert!(!timer.timer.running);
+    }
+
+    #[test]
+    fn last_byte_races_the_timer() {
+        let mut timer = ReceiveTimer::new(FakeTimer::default(), Duration::from_millis(1));
+        timer.receive(5);
+        // A byte lands, but the timer expires before the byte is counted
+        timer.timer.elapse();
+        assert!(!timer.expire(6));
+        assert!(timer.timer.running);
+        // The byte's interrupt sees the new count, which is already timed
+        timer.receive(6);
+        assert_eq!(timer.timer.starts, 2);
+
+        timer.timer.elapse();
+        assert!(timer.expire(6));
+    }
+
+    #[test]
+    fn dma_receive() {
+        let mut timer = ReceiveTimer::new(FakeTimer::default(), Duration::from_millis(1));
+        timer.start();
+        timer.timer.elapse();
+        // Waiting for the first byte; the caller restarts the timer
+        assert!(timer.expire(0));
+        timer.start();
+        timer.timer.elapse();
+        assert!(!timer.expire(3));
+        timer.timer.elapse();
+        assert!(timer.expire(3));
+
+        timer.start();
+        timer.stop();
+        timer.timer.elapse();
+        assert!(!timer.expire(0));
+    }
+
+    #[test]
+    fn bit_times() {
+        assert_eq!(
+            ReceiveTimer::<FakeTimer>::bit_times(40, 115_200),
+            Duration::from_nanos(347_223)
+        );
+        assert_eq!(
+            ReceiveTimer::<FakeTimer>::bit_times(10, 1_000_000),
+            Duration::from_micros(10)
+        );
+        assert_eq!(
+            ReceiveTimer::<FakeTimer>::bit_times(64, 0),
+            Duration::from_secs(64)
+        );
+    }
+}